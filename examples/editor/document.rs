@@ -0,0 +1,280 @@
+//
+// Editor document
+//
+// A map is a stack of layers of the same size, drawn bottom to top.  A cell
+// with the transparent paper colour shows the layers below it, with its
+// glyph, if any, drawn over them.
+//
+// The native format stores each layer as three engine Grids (glyphs, ink and
+// paper), with the colours as indices into a palette saved with the map:
+//
+//      "MMAP", a version byte, the width and height as little-endian u32s
+//      the palette: a u16 count and then each colour as a u32
+//      a layer count byte, and for each layer a visible byte, its name as a
+//      length byte and UTF-8, then the three grids, each as a u32 length and
+//      the bytes from Grid::encode()
+//
+
+use md_mage::*;
+use std::{collections::HashMap, convert::TryInto};
+
+pub const TRANSPARENT: u32 = 0xffff_00ff;
+pub const BLANK: Cell = Cell {
+    glyph: b' ',
+    ink: 0xffff_ffff,
+    paper: 0xff00_0000,
+};
+pub const CLEAR: Cell = Cell {
+    glyph: b' ',
+    ink: 0xffff_ffff,
+    paper: TRANSPARENT,
+};
+
+const NATIVE_MAGIC: &[u8; 4] = b"MMAP";
+const NATIVE_VERSION: u8 = 1;
+const MAX_LAYERS: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub glyph: u8,
+    pub ink: u32,
+    pub paper: u32,
+}
+
+#[derive(Clone)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    pub cells: Grid<Cell>,
+}
+
+pub struct Document {
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<Layer>,
+}
+
+impl Document {
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut document = Document {
+            width,
+            height,
+            layers: Vec::new(),
+        };
+        document.add_layer();
+        document
+    }
+
+    // The first layer is opaque, and the others start transparent.
+    pub fn add_layer(&mut self) -> bool {
+        if self.layers.len() >= MAX_LAYERS {
+            return false;
+        }
+        let fill = if self.layers.is_empty() { BLANK } else { CLEAR };
+        self.layers.push(Layer {
+            name: format!("Layer {}", self.layers.len() + 1),
+            visible: true,
+            cells: Grid::new(self.width, self.height, fill),
+        });
+        true
+    }
+
+    // The cell seen at a point, from the visible layers
+    pub fn composite(&self, p: Point) -> Cell {
+        let mut result = BLANK;
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            let cell = match layer.cells.get(p) {
+                Some(cell) => *cell,
+                None => continue,
+            };
+            if cell.paper != TRANSPARENT {
+                result = cell;
+            } else if cell.glyph != b' ' && cell.glyph != 0 {
+                result.glyph = cell.glyph;
+                result.ink = cell.ink;
+            }
+        }
+        result
+    }
+
+    //
+    // Native format
+    //
+
+    pub fn to_native(&self) -> RogueResult<Vec<u8>> {
+        let mut palette = Vec::<u32>::new();
+        let mut indices = HashMap::<u32, u8>::new();
+        for cell in self.layers.iter().flat_map(|layer| layer.cells.cells()) {
+            for colour in [cell.ink, cell.paper] {
+                if indices.contains_key(&colour) {
+                    continue;
+                }
+                if palette.len() == 256 {
+                    return Err(RogueError::BadMapData(String::from(
+                        "the map uses more than 256 colours",
+                    )));
+                }
+                indices.insert(colour, palette.len() as u8);
+                palette.push(colour);
+            }
+        }
+
+        let mut data = Vec::new();
+        data.extend_from_slice(NATIVE_MAGIC);
+        data.push(NATIVE_VERSION);
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+        for colour in &palette {
+            data.extend_from_slice(&colour.to_le_bytes());
+        }
+        data.push(self.layers.len() as u8);
+        for layer in &self.layers {
+            data.push(layer.visible as u8);
+            let name = &layer.name.as_bytes()[..layer.name.len().min(255)];
+            data.push(name.len() as u8);
+            data.extend_from_slice(name);
+            let grids = [
+                layer.cells.encode(|cell| cell.glyph),
+                layer.cells.encode(|cell| indices[&cell.ink]),
+                layer.cells.encode(|cell| indices[&cell.paper]),
+            ];
+            for grid in &grids {
+                data.extend_from_slice(&(grid.len() as u32).to_le_bytes());
+                data.extend_from_slice(grid);
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn from_native(data: &[u8]) -> RogueResult<Self> {
+        let mut reader = Reader { data, at: 0 };
+        if reader.bytes(4)? != NATIVE_MAGIC {
+            return Err(bad("not a map"));
+        }
+        if reader.u8()? != NATIVE_VERSION {
+            return Err(bad("unknown version"));
+        }
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let palette = (0..reader.u16()?)
+            .map(|_| reader.u32())
+            .collect::<RogueResult<Vec<_>>>()?;
+        let colour = |index: u8| palette.get(index as usize).copied().unwrap_or(TRANSPARENT);
+
+        let mut layers = Vec::new();
+        for _ in 0..reader.u8()? {
+            let visible = reader.u8()? != 0;
+            let name_len = reader.u8()? as usize;
+            let name = String::from_utf8_lossy(reader.bytes(name_len)?).into_owned();
+            let mut grid = || -> RogueResult<Grid<u8>> {
+                let len = reader.u32()? as usize;
+                Grid::decode(reader.bytes(len)?, |b| b)
+            };
+            let (glyphs, inks, papers) = (grid()?, grid()?, grid()?);
+            let size_ok = [&glyphs, &inks, &papers]
+                .iter()
+                .all(|grid| (grid.width(), grid.height()) == (width, height));
+            if !size_ok {
+                return Err(bad("a layer is the wrong size"));
+            }
+            let at = |grid: &Grid<u8>, p| grid.get(p).copied().unwrap_or(0);
+            let cells = Grid::from_fn(width, height, |p| Cell {
+                glyph: at(&glyphs, p),
+                ink: colour(at(&inks, p)),
+                paper: colour(at(&papers, p)),
+            });
+            layers.push(Layer {
+                name,
+                visible,
+                cells,
+            });
+        }
+        if layers.is_empty() {
+            return Err(bad("there are no layers"));
+        }
+        Ok(Document {
+            width,
+            height,
+            layers,
+        })
+    }
+}
+
+//
+// Drawing on a layer
+//
+
+// Replaces the area of cells that match the one at the start, through their
+// edges.
+pub fn flood_fill(cells: &mut Grid<Cell>, start: Point, cell: Cell) {
+    let target = match cells.get(start) {
+        Some(&target) if target != cell => target,
+        _ => return,
+    };
+    let mut stack = vec![start];
+    while let Some(p) = stack.pop() {
+        if cells.get(p) != Some(&target) {
+            continue;
+        }
+        cells.set(p, cell);
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            stack.push(p.offset(dx, dy));
+        }
+    }
+}
+
+// The outline of the rectangle with the points at opposite corners
+pub fn rect_outline(a: Point, b: Point) -> Vec<Point> {
+    let (x0, x1) = (a.x.min(b.x), a.x.max(b.x));
+    let (y0, y1) = (a.y.min(b.y), a.y.max(b.y));
+    let mut points = Vec::new();
+    for x in x0..=x1 {
+        points.push(Point::new(x, y0));
+        if y1 != y0 {
+            points.push(Point::new(x, y1));
+        }
+    }
+    for y in y0 + 1..y1 {
+        points.push(Point::new(x0, y));
+        if x1 != x0 {
+            points.push(Point::new(x1, y));
+        }
+    }
+    points
+}
+
+pub fn bad(message: &str) -> RogueError {
+    RogueError::BadMapData(String::from(message))
+}
+
+// Reads little-endian values, failing at the end of the data
+pub struct Reader<'a> {
+    pub data: &'a [u8],
+    pub at: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&mut self, len: usize) -> RogueResult<&'a [u8]> {
+        let end = self
+            .at
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len());
+        let end = end.ok_or_else(|| bad("truncated"))?;
+        let bytes = &self.data[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> RogueResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> RogueResult<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> RogueResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
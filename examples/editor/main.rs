@@ -0,0 +1,641 @@
+//
+// Map editor
+//
+// A layered tile editor for REXPaint .xp files and the editor's own .map
+// format, picked by the file's extension:
+//
+//      cargo run --example editor -- dungeon.xp
+//
+// Left button paints with the current tool, right button erases and middle
+// button picks up the cell under the mouse.  Keys:
+//
+//      B, R, F         brush, rectangle and fill tools
+//      T               toggle transparent paper
+//      [ ]             previous and next glyph
+//      1-9             select a layer, V toggles its visibility
+//      A               add a layer
+//      arrow keys      scroll the map
+//      Ctrl+S          save
+//      Ctrl+O          reload the file
+//      Ctrl+Z          undo
+//
+
+#![cfg_attr(windows, windows_subsystem = "windows")]
+
+mod document;
+mod xp;
+
+use document::{flood_fill, rect_outline, Cell, Document, BLANK, CLEAR, TRANSPARENT};
+use md_mage::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const NEW_WIDTH: u32 = 80;
+const NEW_HEIGHT: u32 = 50;
+const SIDEBAR_WIDTH: u32 = 20;
+const MAX_UNDO: usize = 50;
+
+const TEXT: u32 = 0xffc0_c0c0;
+const HIGHLIGHT: u32 = 0xff00_ffff;
+const BACKGROUND: u32 = 0xff00_0000;
+const OFF_MAP: u32 = 0xff20_2020;
+
+const PALETTE: [u32; 16] = [
+    0xff00_0000,
+    0xff80_0000,
+    0xff00_8000,
+    0xff80_8000,
+    0xff00_0080,
+    0xff80_0080,
+    0xff00_8080,
+    0xffc0_c0c0,
+    0xff80_8080,
+    0xffff_0000,
+    0xff00_ff00,
+    0xffff_ff00,
+    0xff00_00ff,
+    0xffff_00ff,
+    0xff00_ffff,
+    0xffff_ffff,
+];
+
+fn main() -> RogueResult<()> {
+    let path = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("untitled.map"));
+    let document = if path.exists() {
+        load(&path)?
+    } else {
+        Document::new(NEW_WIDTH, NEW_HEIGHT)
+    };
+
+    let rogue = RogueBuilder::new()
+        .with_inner_size(1280, 720)
+        .with_title("Map Editor")
+        .with_min_grid_size(80, 42)
+        .build();
+
+    run(rogue, Box::new(Editor::new(path, document)))
+}
+
+fn load(path: &Path) -> RogueResult<Document> {
+    let data = fs::read(path)?;
+    if is_xp(path) {
+        xp::load_xp(&data)
+    } else {
+        Document::from_native(&data)
+    }
+}
+
+fn is_xp(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xp"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Brush,
+    Rect,
+    Fill,
+}
+
+const TOOLS: [(Tool, &str); 3] = [
+    (Tool::Brush, "B Brush"),
+    (Tool::Rect, "R Rectangle"),
+    (Tool::Fill, "F Fill"),
+];
+
+struct Editor {
+    path: PathBuf,
+    document: Document,
+    layer: usize,
+    tool: Tool,
+    brush: Cell,
+    // The map cell at the top-left of the canvas
+    view: Point,
+    width: u32,
+    height: u32,
+    hits: HitRegions,
+    // The map cell under the mouse, and where a rectangle was started
+    hover: Option<Point>,
+    drag_start: Option<Point>,
+    undo: Vec<(usize, Grid<Cell>)>,
+    stroke: bool,
+}
+
+impl Editor {
+    fn new(path: PathBuf, document: Document) -> Self {
+        Editor {
+            path,
+            document,
+            layer: 0,
+            tool: Tool::Brush,
+            brush: Cell {
+                glyph: b'#',
+                ink: 0xffff_ffff,
+                paper: BACKGROUND,
+            },
+            view: Point::new(0, 0),
+            width: 0,
+            height: 0,
+            hits: HitRegions::new(),
+            hover: None,
+            drag_start: None,
+            undo: Vec::new(),
+            stroke: false,
+        }
+    }
+
+    fn canvas_width(&self) -> u32 {
+        self.width.saturating_sub(SIDEBAR_WIDTH)
+    }
+
+    fn canvas_height(&self) -> u32 {
+        self.height.saturating_sub(1)
+    }
+
+    fn sidebar_x(&self) -> i32 {
+        self.canvas_width() as i32
+    }
+
+    fn to_map(&self, screen: Point) -> Point {
+        Point::new(screen.x + self.view.x, screen.y + self.view.y)
+    }
+
+    fn erase_cell(&self) -> Cell {
+        if self.layer == 0 {
+            BLANK
+        } else {
+            CLEAR
+        }
+    }
+
+    //
+    // Layout
+    // The sidebar holds four panels: tools, palette, glyphs and layers.
+    //
+
+    fn tools_y(&self) -> i32 {
+        0
+    }
+
+    fn palette_y(&self) -> i32 {
+        self.tools_y() + TOOLS.len() as i32 + 2
+    }
+
+    fn glyphs_y(&self) -> i32 {
+        self.palette_y() + 5
+    }
+
+    fn layers_y(&self) -> i32 {
+        self.glyphs_y() + 18
+    }
+
+    fn layout(&mut self) {
+        let x = self.sidebar_x();
+        let mut hits = HitRegions::new();
+        hits.add(
+            "canvas",
+            Rect::new(0, 0, self.canvas_width(), self.canvas_height()),
+        );
+        for i in 0..TOOLS.len() {
+            let y = self.tools_y() + 1 + i as i32;
+            hits.add(&format!("tool:{}", i), Rect::new(x + 1, y, 18, 1));
+        }
+        for i in 0..PALETTE.len() {
+            let (col, row) = (i as i32 % 8, i as i32 / 8);
+            let p = Point::new(x + 2 + col * 2, self.palette_y() + 1 + row);
+            hits.add(&format!("colour:{}", i), Rect::new(p.x, p.y, 2, 1));
+        }
+        hits.add("transparent", Rect::new(x + 2, self.palette_y() + 3, 16, 1));
+        for glyph in 0..256 {
+            let p = Point::new(x + 2 + glyph % 16, self.glyphs_y() + 1 + glyph / 16);
+            hits.add(&format!("glyph:{}", glyph), Rect::new(p.x, p.y, 1, 1));
+        }
+        for i in 0..self.document.layers.len() {
+            let y = self.layers_y() + 1 + i as i32;
+            hits.add(&format!("layer:{}", i), Rect::new(x + 1, y, 18, 1));
+            hits.add(&format!("visible:{}", i), Rect::new(x + 1, y, 3, 1));
+        }
+        let y = self.layers_y() + 1 + self.document.layers.len() as i32;
+        hits.add("add-layer", Rect::new(x + 1, y, 18, 1));
+        self.hits = hits;
+    }
+
+    //
+    // Editing
+    //
+
+    fn snapshot(&mut self) {
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        let cells = self.document.layers[self.layer].cells.clone();
+        self.undo.push((self.layer, cells));
+    }
+
+    fn undo(&mut self, ctx: &mut Context) {
+        match self.undo.pop() {
+            Some((layer, cells)) => {
+                if let Some(layer) = self.document.layers.get_mut(layer) {
+                    layer.cells = cells;
+                }
+            }
+            None => ctx.notify("Nothing to undo", NotifyStyle::Warning),
+        }
+    }
+
+    fn paint(&mut self, p: Point, cell: Cell) {
+        self.document.layers[self.layer].cells.set(p, cell);
+    }
+
+    fn select_layer(&mut self, layer: usize) {
+        if layer < self.document.layers.len() {
+            self.layer = layer;
+            self.drag_start = None;
+        }
+    }
+
+    fn add_layer(&mut self, ctx: &mut Context) {
+        if self.document.add_layer() {
+            self.select_layer(self.document.layers.len() - 1);
+        } else {
+            ctx.notify("There can't be any more layers", NotifyStyle::Warning);
+        }
+    }
+
+    fn toggle_visible(&mut self, layer: usize) {
+        if let Some(layer) = self.document.layers.get_mut(layer) {
+            layer.visible = !layer.visible;
+        }
+    }
+
+    fn save(&self, ctx: &mut Context) {
+        let data = if is_xp(&self.path) {
+            Ok(xp::save_xp(&self.document))
+        } else {
+            self.document.to_native()
+        };
+        let result = data.and_then(|data| Ok(fs::write(&self.path, data)?));
+        match result {
+            Ok(()) => ctx.notify(
+                &format!("Saved {}", self.path.display()),
+                NotifyStyle::Success,
+            ),
+            Err(e) => ctx.notify(&format!("Unable to save: {}", e), NotifyStyle::Error),
+        }
+    }
+
+    fn reload(&mut self, ctx: &mut Context) {
+        match load(&self.path) {
+            Ok(document) => {
+                self.document = document;
+                self.layer = 0;
+                self.undo.clear();
+                self.drag_start = None;
+                ctx.notify(
+                    &format!("Loaded {}", self.path.display()),
+                    NotifyStyle::Success,
+                );
+            }
+            Err(e) => ctx.notify(&format!("Unable to load: {}", e), NotifyStyle::Error),
+        }
+    }
+
+    //
+    // Input
+    //
+
+    fn handle_keys(&mut self, key: &KeyState, ctx: &mut Context) {
+        if !key.pressed {
+            return;
+        }
+        if key.ctrl_pressed() {
+            match key.vkey {
+                Some(Key::S) => self.save(ctx),
+                Some(Key::O) => self.reload(ctx),
+                Some(Key::Z) => self.undo(ctx),
+                _ => {}
+            }
+            return;
+        }
+
+        const LAYER_KEYS: [Key; 9] = [
+            Key::Key1,
+            Key::Key2,
+            Key::Key3,
+            Key::Key4,
+            Key::Key5,
+            Key::Key6,
+            Key::Key7,
+            Key::Key8,
+            Key::Key9,
+        ];
+        let step = if key.shift { 10 } else { 1 };
+        match key.vkey {
+            Some(Key::B) => self.tool = Tool::Brush,
+            Some(Key::R) => self.tool = Tool::Rect,
+            Some(Key::F) => self.tool = Tool::Fill,
+            Some(Key::T) => self.toggle_transparent(),
+            Some(Key::A) => self.add_layer(ctx),
+            Some(Key::V) => self.toggle_visible(self.layer),
+            Some(Key::LBracket) => self.brush.glyph = self.brush.glyph.wrapping_sub(1),
+            Some(Key::RBracket) => self.brush.glyph = self.brush.glyph.wrapping_add(1),
+            Some(Key::Escape) => self.drag_start = None,
+            Some(Key::Left) => self.scroll(-step, 0),
+            Some(Key::Right) => self.scroll(step, 0),
+            Some(Key::Up) => self.scroll(0, -step),
+            Some(Key::Down) => self.scroll(0, step),
+            Some(vkey) => {
+                if let Some(layer) = LAYER_KEYS.iter().position(|&k| k == vkey) {
+                    self.select_layer(layer);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn toggle_transparent(&mut self) {
+        self.brush.paper = if self.brush.paper == TRANSPARENT {
+            BACKGROUND
+        } else {
+            TRANSPARENT
+        };
+    }
+
+    // The view can scroll until the edge of the map reaches the middle of
+    // the canvas.
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let limit = |size: u32, view: u32| (size as i32 - view as i32 / 2).max(0);
+        let max_x = limit(self.document.width, self.canvas_width());
+        let max_y = limit(self.document.height, self.canvas_height());
+        self.view = Point::new(
+            (self.view.x + dx).clamp(-(self.canvas_width() as i32) / 2, max_x),
+            (self.view.y + dy).clamp(-(self.canvas_height() as i32) / 2, max_y),
+        );
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseState, screen: Option<Point>, ctx: &mut Context) {
+        let hit = screen.and_then(|p| self.hits.hit(p)).map(String::from);
+        self.hover = match (hit.as_deref(), screen) {
+            (Some("canvas"), Some(p)) => Some(self.to_map(p)),
+            _ => None,
+        };
+        if mouse.wheel_y != 0.0 {
+            self.scroll(0, -(mouse.wheel_y.round() as i32) * 3);
+        }
+
+        // A rectangle is drawn when the button is let go, wherever it is.
+        if let Some(start) = self.drag_start {
+            if !mouse.left_pressed {
+                self.drag_start = None;
+                if let Some(end) = self.hover {
+                    self.snapshot();
+                    for p in rect_outline(start, end) {
+                        self.paint(p, self.brush);
+                    }
+                }
+            }
+            return;
+        }
+        if !mouse.left_pressed && !mouse.right_pressed {
+            self.stroke = false;
+        }
+
+        let clicked = mouse.left_clicked || mouse.right_clicked || mouse.middle_clicked;
+        match hit.as_deref().and_then(|hit| hit.split_once(':')) {
+            Some((region, index)) if clicked => {
+                let index = index.parse::<usize>().unwrap_or(0);
+                self.click_sidebar(region, index, mouse.right_clicked);
+                return;
+            }
+            _ => {}
+        }
+        match hit.as_deref() {
+            Some("transparent") if clicked => self.toggle_transparent(),
+            Some("add-layer") if mouse.left_clicked => self.add_layer(ctx),
+            Some("canvas") => self.handle_canvas(mouse),
+            _ => {}
+        }
+    }
+
+    fn click_sidebar(&mut self, region: &str, index: usize, right: bool) {
+        match region {
+            "tool" => self.tool = TOOLS[index].0,
+            "colour" if right => self.brush.paper = PALETTE[index],
+            "colour" => self.brush.ink = PALETTE[index],
+            "glyph" => self.brush.glyph = index as u8,
+            "visible" => self.toggle_visible(index),
+            "layer" => self.select_layer(index),
+            _ => {}
+        }
+    }
+
+    fn handle_canvas(&mut self, mouse: MouseState) {
+        let p = match self.hover {
+            Some(p) => p,
+            None => return,
+        };
+        let in_map = self.document.layers[self.layer].cells.in_bounds(p);
+
+        if mouse.middle_clicked {
+            if let Some(&cell) = self.document.layers[self.layer].cells.get(p) {
+                self.brush = cell;
+            }
+            return;
+        }
+        if mouse.right_pressed {
+            if in_map {
+                if !self.stroke {
+                    self.snapshot();
+                    self.stroke = true;
+                }
+                self.paint(p, self.erase_cell());
+            }
+            return;
+        }
+
+        match self.tool {
+            Tool::Brush if mouse.left_pressed && in_map => {
+                if !self.stroke {
+                    self.snapshot();
+                    self.stroke = true;
+                }
+                self.paint(p, self.brush);
+            }
+            Tool::Rect if mouse.left_clicked => self.drag_start = Some(p),
+            Tool::Fill if mouse.left_clicked && in_map => {
+                self.snapshot();
+                flood_fill(&mut self.document.layers[self.layer].cells, p, self.brush);
+            }
+            _ => {}
+        }
+    }
+
+    //
+    // Drawing
+    //
+
+    fn draw_canvas(&self, image: &mut Image) {
+        let preview = match (self.drag_start, self.hover) {
+            (Some(start), Some(end)) => rect_outline(start, end),
+            _ => Vec::new(),
+        };
+        for y in 0..self.canvas_height() as i32 {
+            for x in 0..self.canvas_width() as i32 {
+                let screen = Point::new(x, y);
+                let p = self.to_map(screen);
+                if p.x < 0
+                    || p.y < 0
+                    || p.x >= self.document.width as i32
+                    || p.y >= self.document.height as i32
+                {
+                    image.draw_glyph(screen, b' ' as u32, OFF_MAP, OFF_MAP);
+                    continue;
+                }
+                let mut cell = self.document.composite(p);
+                if preview.contains(&p) || (self.hover == Some(p) && self.tool == Tool::Brush) {
+                    cell = self.brush;
+                    if cell.paper == TRANSPARENT {
+                        cell.paper = self.document.composite(p).paper;
+                    }
+                }
+                image.draw_glyph(screen, cell.glyph as u32, cell.ink, cell.paper);
+            }
+        }
+    }
+
+    fn draw_sidebar(&self, image: &mut Image) {
+        let x = self.sidebar_x();
+        let frame = NinePatch::single(TEXT, BACKGROUND);
+        image.draw_rect_filled(
+            Point::new(x, 0),
+            SIDEBAR_WIDTH,
+            self.canvas_height(),
+            Char::new(b' ', TEXT, BACKGROUND),
+        );
+
+        // Tools
+        let y = self.tools_y();
+        image.draw_panel(
+            Point::new(x, y),
+            SIDEBAR_WIDTH,
+            TOOLS.len() as u32 + 2,
+            &frame,
+        );
+        image.draw_string(Point::new(x + 2, y), "Tools", HIGHLIGHT, BACKGROUND);
+        for (i, (tool, name)) in TOOLS.iter().enumerate() {
+            let ink = if *tool == self.tool { HIGHLIGHT } else { TEXT };
+            image.draw_string(Point::new(x + 2, y + 1 + i as i32), name, ink, BACKGROUND);
+        }
+
+        // Palette, with the brush's ink and paper below
+        let y = self.palette_y();
+        image.draw_panel(Point::new(x, y), SIDEBAR_WIDTH, 5, &frame);
+        image.draw_string(Point::new(x + 2, y), "Colours", HIGHLIGHT, BACKGROUND);
+        for (i, &colour) in PALETTE.iter().enumerate() {
+            let (col, row) = (i as i32 % 8, i as i32 / 8);
+            let p = Point::new(x + 2 + col * 2, y + 1 + row);
+            image.draw_string(p, "  ", colour, colour);
+        }
+        let p = Point::new(x + 2, y + 3);
+        image.draw_string(p, "Ink", TEXT, BACKGROUND);
+        image.draw_string(p.offset(4, 0), "  ", self.brush.ink, self.brush.ink);
+        image.draw_string(p.offset(7, 0), "Paper", TEXT, BACKGROUND);
+        if self.brush.paper == TRANSPARENT {
+            image.draw_string(p.offset(13, 0), "\u{2591}\u{2591}", TEXT, BACKGROUND);
+        } else {
+            let paper = self.brush.paper;
+            image.draw_string(p.offset(13, 0), "  ", paper, paper);
+        }
+
+        // Glyphs
+        let y = self.glyphs_y();
+        image.draw_panel(Point::new(x, y), SIDEBAR_WIDTH, 18, &frame);
+        image.draw_string(Point::new(x + 2, y), "Glyphs", HIGHLIGHT, BACKGROUND);
+        for glyph in 0..256u32 {
+            let p = Point::new(x + 2 + (glyph % 16) as i32, y + 1 + (glyph / 16) as i32);
+            if glyph == self.brush.glyph as u32 {
+                image.draw_glyph(p, glyph, BACKGROUND, HIGHLIGHT);
+            } else {
+                image.draw_glyph(p, glyph, TEXT, BACKGROUND);
+            }
+        }
+
+        // Layers
+        let y = self.layers_y();
+        let count = self.document.layers.len();
+        image.draw_panel(Point::new(x, y), SIDEBAR_WIDTH, count as u32 + 3, &frame);
+        image.draw_string(Point::new(x + 2, y), "Layers", HIGHLIGHT, BACKGROUND);
+        for (i, layer) in self.document.layers.iter().enumerate() {
+            let ink = if i == self.layer { HIGHLIGHT } else { TEXT };
+            let mark = if layer.visible { "[x]" } else { "[ ]" };
+            let text = format!("{} {}", mark, layer.name);
+            let text: String = text.chars().take(SIDEBAR_WIDTH as usize - 2).collect();
+            image.draw_string(Point::new(x + 1, y + 1 + i as i32), &text, ink, BACKGROUND);
+        }
+        let p = Point::new(x + 1, y + 1 + count as i32);
+        image.draw_string(p, " +  Add layer", TEXT, BACKGROUND);
+    }
+
+    fn draw_status(&self, image: &mut Image) {
+        let y = self.canvas_height() as i32;
+        let position = match self.hover {
+            Some(p) => format!("{},{}", p.x, p.y),
+            None => String::from("-"),
+        };
+        let name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let text = format!(
+            " {}  {}x{}  layer {}/{}  {}  |  Ctrl+S save  Ctrl+O reload  Ctrl+Z undo",
+            name,
+            self.document.width,
+            self.document.height,
+            self.layer + 1,
+            self.document.layers.len(),
+            position,
+        );
+        let text: String = text.chars().take(self.width as usize).collect();
+        image.draw_rect_filled(
+            Point::new(0, y),
+            self.width,
+            1,
+            Char::new(b' ', BACKGROUND, TEXT),
+        );
+        image.draw_string(Point::new(0, y), &text, BACKGROUND, TEXT);
+    }
+}
+
+impl Game for Editor {
+    fn start(&mut self) {}
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.scroll(0, 0);
+    }
+
+    fn tick(&mut self, sim_input: SimInput) -> TickResult {
+        self.layout();
+        self.handle_keys(sim_input.key, sim_input.ctx);
+        if let Some(mouse) = sim_input.mouse {
+            let screen = sim_input.mouse_cell();
+            self.handle_mouse(mouse, screen, sim_input.ctx);
+        }
+        TickResult::Continue
+    }
+
+    fn present(&self, present_input: PresentInput) {
+        let image = present_input.image;
+        image.clear(TEXT, BACKGROUND);
+        self.draw_canvas(image);
+        self.draw_sidebar(image);
+        self.draw_status(image);
+    }
+}
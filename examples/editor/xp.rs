@@ -0,0 +1,241 @@
+//
+// REXPaint files
+//
+// A .xp file is gzipped, and inside holds:
+//
+//      the format version (-1) and the number of layers, as i32s
+//      for each layer, its width and height as i32s and then its cells in
+//      column-major order, each as a u32 glyph, the ink as r, g, b bytes and
+//      the paper as r, g, b bytes
+//
+// All numbers are little-endian.  Paper of (255, 0, 255) is transparent.  The
+// gzip wrapper is written here around miniz_oxide's raw deflate.
+//
+
+use crate::document::{bad, Cell, Document, Layer, Reader, CLEAR};
+use crc32fast::hash as crc32;
+use md_mage::*;
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+
+const XP_VERSION: i32 = -1;
+
+const GZIP_ID: [u8; 2] = [0x1f, 0x8b];
+const GZIP_DEFLATE: u8 = 8;
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+pub fn load_xp(data: &[u8]) -> RogueResult<Document> {
+    let data = gunzip(data)?;
+    let mut reader = Reader { data: &data, at: 0 };
+    if reader.u32()? as i32 != XP_VERSION {
+        return Err(bad("unknown REXPaint version"));
+    }
+    let count = reader.u32()?;
+    if count == 0 {
+        return Err(bad("there are no layers"));
+    }
+
+    let mut layers = Vec::new();
+    let (mut width, mut height) = (0, 0);
+    for i in 0..count {
+        let (w, h) = (reader.u32()?, reader.u32()?);
+        if w == 0 || h == 0 {
+            return Err(bad("a layer is empty"));
+        }
+        // Each cell takes 10 bytes, so checking they are all there first
+        // stops a bad size from allocating more than the file could fill
+        let size = w as u64 * h as u64;
+        if size > (reader.data.len() - reader.at) as u64 / 10 {
+            return Err(bad("truncated"));
+        }
+        if i == 0 {
+            width = w;
+            height = h;
+        } else if (w, h) != (width, height) {
+            return Err(bad("the layers are different sizes"));
+        }
+        let mut cells = Vec::with_capacity(size as usize);
+        for _ in 0..size {
+            let glyph = reader.u32()?;
+            let rgb = reader.bytes(6)?;
+            cells.push(Cell {
+                glyph: glyph.min(255) as u8,
+                ink: new_colour(rgb[0], rgb[1], rgb[2]),
+                paper: new_colour(rgb[3], rgb[4], rgb[5]),
+            });
+        }
+        let index = |p: Point| p.x as usize * h as usize + p.y as usize;
+        layers.push(Layer {
+            name: format!("Layer {}", i + 1),
+            visible: true,
+            cells: Grid::from_fn(w, h, |p| cells[index(p)]),
+        });
+    }
+
+    Ok(Document {
+        width,
+        height,
+        layers,
+    })
+}
+
+pub fn save_xp(document: &Document) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&XP_VERSION.to_le_bytes());
+    data.extend_from_slice(&(document.layers.len() as u32).to_le_bytes());
+    for layer in &document.layers {
+        data.extend_from_slice(&document.width.to_le_bytes());
+        data.extend_from_slice(&document.height.to_le_bytes());
+        for x in 0..document.width as i32 {
+            for y in 0..document.height as i32 {
+                let cell = layer.cells.get(Point::new(x, y)).copied().unwrap_or(CLEAR);
+                data.extend_from_slice(&(cell.glyph as u32).to_le_bytes());
+                data.extend_from_slice(&rgb(cell.ink));
+                data.extend_from_slice(&rgb(cell.paper));
+            }
+        }
+    }
+    gzip(&data)
+}
+
+fn rgb(colour: u32) -> [u8; 3] {
+    [colour as u8, (colour >> 8) as u8, (colour >> 16) as u8]
+}
+
+//
+// gzip
+//
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![GZIP_ID[0], GZIP_ID[1], GZIP_DEFLATE, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend_from_slice(&compress_to_vec(data, 6));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn gunzip(data: &[u8]) -> RogueResult<Vec<u8>> {
+    let mut reader = Reader { data, at: 0 };
+    let header = reader.bytes(10)?;
+    if header[..2] != GZIP_ID || header[2] != GZIP_DEFLATE {
+        return Err(bad("not a gzip file"));
+    }
+    let flags = header[3];
+    if flags & FLAG_EXTRA != 0 {
+        let len = reader.u16()? as usize;
+        reader.bytes(len)?;
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            while reader.u8()? != 0 {}
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        reader.u16()?;
+    }
+
+    let body = &data[reader.at..];
+    if body.len() < 8 {
+        return Err(bad("truncated"));
+    }
+    let (deflated, trailer) = body.split_at(body.len() - 8);
+    let mut trailer = Reader {
+        data: trailer,
+        at: 0,
+    };
+    let (crc, size) = (trailer.u32()?, trailer.u32()?);
+    // Inflating stops not far past the size the trailer gives, so a small
+    // file can't claim an enormous map.  The buffer grows by doubling, and
+    // the limit must be twice the size for the last doubling to be allowed.
+    let limit = 2 * (size as usize + 1);
+    let out =
+        decompress_to_vec_with_limit(deflated, limit).map_err(|_| bad("the data is corrupt"))?;
+    if crc != crc32(&out) || size != out.len() as u32 {
+        return Err(bad("the data is corrupt"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::TRANSPARENT;
+
+    fn is_bad(result: RogueResult<Document>) -> bool {
+        matches!(result, Err(RogueError::BadMapData(_)))
+    }
+
+    fn map() -> Document {
+        let mut document = Document::new(3, 2);
+        document.add_layer();
+        let cell = Cell {
+            glyph: b'@',
+            ink: new_colour(1, 2, 3),
+            paper: TRANSPARENT,
+        };
+        document.layers[1].cells.set(Point::new(2, 1), cell);
+        document
+    }
+
+    #[test]
+    fn saved_maps_load_again() {
+        let document = load_xp(&save_xp(&map())).unwrap();
+        assert_eq!((document.width, document.height), (3, 2));
+        assert_eq!(document.layers.len(), 2);
+        assert_eq!(
+            document.layers[1]
+                .cells
+                .get(Point::new(2, 1))
+                .unwrap()
+                .glyph,
+            b'@'
+        );
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let data = save_xp(&map());
+        for len in 0..data.len() {
+            assert!(is_bad(load_xp(&data[..len])), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        let mut data = save_xp(&map());
+        assert!(is_bad(load_xp(b"not a map at all")));
+        // A flipped bit in the deflated data or trailer
+        for i in 10..data.len() {
+            data[i] ^= 0x10;
+            assert!(load_xp(&data).is_err(), "byte {}", i);
+            data[i] ^= 0x10;
+        }
+    }
+
+    #[test]
+    fn odd_sizes_are_rejected() {
+        let layer = |w: u32, h: u32, cells: usize| {
+            let mut data = XP_VERSION.to_le_bytes().to_vec();
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.extend_from_slice(&w.to_le_bytes());
+            data.extend_from_slice(&h.to_le_bytes());
+            data.extend_from_slice(&vec![0; cells * 10]);
+            gzip(&data)
+        };
+        assert!(load_xp(&layer(2, 2, 4)).is_ok());
+        assert!(is_bad(load_xp(&layer(2, 2, 3))));
+        assert!(is_bad(load_xp(&layer(0, 5, 0))));
+        assert!(is_bad(load_xp(&layer(u32::MAX, u32::MAX, 1))));
+        assert!(is_bad(load_xp(&layer(u32::MAX, 1, 4))));
+    }
+
+    #[test]
+    fn a_wrong_trailer_size_is_rejected() {
+        let mut data = gzip(&[0; 4096]);
+        let len = data.len();
+        data[len - 4..].copy_from_slice(&16u32.to_le_bytes());
+        assert!(is_bad(load_xp(&data)));
+    }
+}
@@ -0,0 +1,289 @@
+//
+// Font sheet generator
+//
+// Rasterises a TrueType font into the 16x16 sheet of glyphs that
+// load_font_image() and RogueBuilder::with_font() take:
+//
+//      cargo run --example fontgen -- MyFont.ttf my_font.png --cell 10x20 --preview
+//
+// Options:
+//
+//      --cell WxH          the size of each glyph in pixels (8x16)
+//      --size PX           the font size in pixels, instead of the largest that
+//                          fits the cell
+//      --codepage NAME     which character goes in each of the 256 slots:
+//                          cp437 (the engine's own layout) or latin1
+//      --smooth            keep the anti-aliasing instead of making each pixel
+//                          on or off
+//      --preview           opens a window showing the sheet as the engine
+//                          draws it
+//
+// Glyphs are white on black.  Box-drawing and block characters are stretched
+// to fill their cells so they join up; the others are centred at the font's
+// size.
+//
+
+use anyhow::{anyhow, bail, Context as _};
+use md_mage::*;
+use rusttype::{point, Font, Scale};
+use std::{fs, path::PathBuf};
+
+const USAGE: &str = "Usage: fontgen <font.ttf> [output.png] [--cell WxH] [--size PX] \
+                     [--codepage cp437|latin1] [--smooth] [--preview]";
+
+const UNLIT: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codepage {
+    Cp437,
+    Latin1,
+}
+
+impl Codepage {
+    fn char(self, code: u8) -> char {
+        match self {
+            Codepage::Cp437 => cp437_to_char(code),
+            Codepage::Latin1 if code < 32 || (127..160).contains(&code) => ' ',
+            Codepage::Latin1 => code as char,
+        }
+    }
+}
+
+struct Options {
+    font: PathBuf,
+    output: PathBuf,
+    cell_width: u32,
+    cell_height: u32,
+    size: Option<f32>,
+    codepage: Codepage,
+    smooth: bool,
+    preview: bool,
+}
+
+impl Options {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = std::env::args().skip(1);
+        let mut paths = Vec::new();
+        let mut options = Options {
+            font: PathBuf::new(),
+            output: PathBuf::from("font.png"),
+            cell_width: 8,
+            cell_height: 16,
+            size: None,
+            codepage: Codepage::Cp437,
+            smooth: false,
+            preview: false,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--cell" => {
+                    let cell = value()?;
+                    let (w, h) = cell
+                        .split_once('x')
+                        .ok_or_else(|| anyhow!("The cell size should be WxH, not {}", cell))?;
+                    options.cell_width = w.parse()?;
+                    options.cell_height = h.parse()?;
+                }
+                "--size" => options.size = Some(value()?.parse()?),
+                "--codepage" => {
+                    options.codepage = match value()?.as_str() {
+                        "cp437" => Codepage::Cp437,
+                        "latin1" => Codepage::Latin1,
+                        name => bail!("Unknown code page {}", name),
+                    }
+                }
+                "--smooth" => options.smooth = true,
+                "--preview" => options.preview = true,
+                "--help" | "-h" => bail!(USAGE),
+                _ if arg.starts_with("--") => bail!("Unknown option {}\n{}", arg, USAGE),
+                _ => paths.push(PathBuf::from(arg)),
+            }
+        }
+
+        let mut paths = paths.into_iter();
+        options.font = paths.next().ok_or_else(|| anyhow!(USAGE))?;
+        if let Some(output) = paths.next() {
+            options.output = output;
+        }
+        if options.cell_width == 0 || options.cell_height == 0 {
+            bail!("The cells must be at least 1x1");
+        }
+        Ok(options)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let options = Options::parse()?;
+    let data = fs::read(&options.font)
+        .with_context(|| format!("Unable to read {}", options.font.display()))?;
+    let font = Font::try_from_vec(data).ok_or_else(|| anyhow!("Not a TrueType font"))?;
+
+    let (pixels, missing) = render_sheet(&font, &options);
+    let (width, height) = (16 * options.cell_width, 16 * options.cell_height);
+    let sheet = image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("The sheet is the wrong size"))?;
+    sheet
+        .save(&options.output)
+        .with_context(|| format!("Unable to write {}", options.output.display()))?;
+    println!(
+        "Wrote {} ({}x{} cells)",
+        options.output.display(),
+        options.cell_width,
+        options.cell_height
+    );
+    if !missing.is_empty() {
+        let chars: String = missing.iter().collect();
+        println!(
+            "The font has no glyphs for {} characters: {}",
+            missing.len(),
+            chars
+        );
+    }
+
+    if options.preview {
+        let font_data = load_font_image(&fs::read(&options.output)?, ImageFormat::Png)?;
+        let rogue = RogueBuilder::new()
+            .with_inner_size(
+                (options.cell_width * 52) as usize,
+                (options.cell_height * 30) as usize,
+            )
+            .with_title(&format!("Font preview - {}", options.output.display()))
+            .with_font(font_data)
+            .build();
+        run(rogue, Box::new(Preview))?;
+    }
+    Ok(())
+}
+
+//
+// Rasterising
+//
+
+// The sheet's RGBA pixels, and the characters the font doesn't have
+fn render_sheet(font: &Font, options: &Options) -> (Vec<u8>, Vec<char>) {
+    let (cell_width, cell_height) = (options.cell_width, options.cell_height);
+    let sheet_width = 16 * cell_width;
+    let mut pixels = UNLIT.repeat((sheet_width * 16 * cell_height) as usize);
+    let mut missing = Vec::new();
+
+    // The font's line height and the width of its widest common letter at a
+    // size of 1 pixel, to fit the font to the cell.
+    let unit = font.v_metrics(Scale::uniform(1.0));
+    let line_height = unit.ascent - unit.descent;
+    let advance = font
+        .glyph('M')
+        .scaled(Scale::uniform(1.0))
+        .h_metrics()
+        .advance_width;
+    let fitted = (cell_height as f32 / line_height).min(cell_width as f32 / advance.max(0.01));
+    let text_scale = Scale::uniform(options.size.unwrap_or(fitted));
+    let stretched_scale = Scale {
+        x: cell_width as f32 / advance.max(0.01),
+        y: cell_height as f32 / line_height,
+    };
+
+    for code in 0..=255u8 {
+        let ch = options.codepage.char(code);
+        if ch == ' ' {
+            continue;
+        }
+        let glyph = font.glyph(ch);
+        if glyph.id().0 == 0 {
+            missing.push(ch);
+            continue;
+        }
+
+        let scale = if is_box_drawing(ch) {
+            stretched_scale
+        } else {
+            text_scale
+        };
+        let v_metrics = font.v_metrics(scale);
+        let glyph = glyph.scaled(scale);
+        let advance = glyph.h_metrics().advance_width;
+        let line = v_metrics.ascent - v_metrics.descent;
+        let x = (cell_width as f32 - advance) / 2.0;
+        let y = (cell_height as f32 - line) / 2.0 + v_metrics.ascent;
+        let glyph = glyph.positioned(point(x, y));
+
+        let bounds = match glyph.pixel_bounding_box() {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+        let origin_x = (code as u32 % 16) * cell_width;
+        let origin_y = (code as u32 / 16) * cell_height;
+        glyph.draw(|gx, gy, coverage| {
+            let px = gx as i32 + bounds.min.x;
+            let py = gy as i32 + bounds.min.y;
+            if px < 0 || py < 0 || px >= cell_width as i32 || py >= cell_height as i32 {
+                return;
+            }
+            let i = (((origin_y + py as u32) * sheet_width + origin_x + px as u32) * 4) as usize;
+            let value = if options.smooth {
+                (coverage.clamp(0.0, 1.0) * 255.0).round() as u8
+            } else if coverage >= 0.5 {
+                0xff
+            } else {
+                0
+            };
+            // Pixels already lit by an overlapping part of the glyph stay lit.
+            let value = value.max(pixels[i]);
+            pixels[i..i + 3].copy_from_slice(&[value; 3]);
+        });
+    }
+
+    (pixels, missing)
+}
+
+// Lines and blocks that are meant to meet the edges of the cell
+fn is_box_drawing(ch: char) -> bool {
+    ('\u{2500}'..='\u{259f}').contains(&ch)
+}
+
+//
+// Preview
+// The sheet drawn as a table, with some text to judge it by.
+//
+
+struct Preview;
+
+const SAMPLES: [&str; 4] = [
+    "The quick brown fox jumps over the lazy dog.",
+    "0123456789 !\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~",
+    "┌──┬──┐ ╔══╦══╗ ░░▒▒▓▓██ ▄▄▀▀▌▐",
+    "└──┴──┘ ╚══╩══╝ ☺☻♥♦♣♠ ←↑→↓ αβΓπΣσ",
+];
+
+impl Game for Preview {
+    fn start(&mut self) {}
+
+    fn tick(&mut self, sim_input: SimInput) -> TickResult {
+        if sim_input.key.key_pressed(Key::Escape) {
+            TickResult::Stop
+        } else {
+            TickResult::Continue
+        }
+    }
+
+    fn present(&self, present_input: PresentInput) {
+        let image = present_input.image;
+        let (label, ink, paper) = (0xff80_8080, 0xffff_ffff, 0xff00_0000);
+        image.clear(ink, paper);
+
+        for i in 0..16u32 {
+            let digit = format!("{:X}", i);
+            image.draw_string(Point::new(4 + 2 * i as i32, 1), &digit, label, paper);
+            image.draw_string(Point::new(1, 3 + i as i32), &digit, label, paper);
+        }
+        for code in 0..256u32 {
+            let p = Point::new(4 + 2 * (code % 16) as i32, 3 + (code / 16) as i32);
+            image.draw_glyph(p, code, ink, paper);
+        }
+        for (i, sample) in SAMPLES.iter().enumerate() {
+            image.draw_string(Point::new(1, 21 + i as i32), sample, ink, paper);
+        }
+        image.draw_string(Point::new(1, 27), "Esc to close", label, paper);
+    }
+}
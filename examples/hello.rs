@@ -34,7 +34,8 @@ impl Game for HelloDemo {
     }
 
     fn present(&self, present_input: PresentInput) {
-        for (i, e) in present_input.back_image.iter_mut().enumerate() {
+        let image = present_input.image;
+        for (i, e) in image.back_image.iter_mut().enumerate() {
             let x = (i as u32) % present_input.width;
             let y = (i as u32) / present_input.width;
             *e = if ((x ^ y) & 1) == 1 {
@@ -43,15 +44,15 @@ impl Game for HelloDemo {
                 0xffff00ffu32
             }
         }
-        present_input
+        image
             .back_image
             .iter_mut()
             .for_each(|x| *x = rand::thread_rng().gen());
-        present_input
+        image
             .fore_image
             .iter_mut()
             .for_each(|x| *x = rand::thread_rng().gen());
-        present_input
+        image
             .text_image
             .iter_mut()
             .for_each(|x| *x = rand::thread_rng().gen::<u8>() as u32);
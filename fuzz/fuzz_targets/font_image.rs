@@ -0,0 +1,25 @@
+//
+// Fuzzes load_font_image()
+//
+// The first byte picks the image format, and the rest is the image.  Run with
+// "cargo fuzz run font_image".
+//
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use md_mage::{load_font_image, ImageFormat};
+
+const FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Png,
+    ImageFormat::Bmp,
+    ImageFormat::Gif,
+    ImageFormat::Tga,
+];
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((&format, data)) = data.split_first() {
+        let format = FORMATS[format as usize % FORMATS.len()];
+        let _ = load_font_image(data, format);
+    }
+});
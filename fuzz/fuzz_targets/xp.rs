@@ -0,0 +1,26 @@
+//
+// Fuzzes the editor example's REXPaint loader
+//
+// Maps that load must save and load again the same.  Run with
+// "cargo fuzz run xp".
+//
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../examples/editor/document.rs"]
+mod document;
+#[path = "../../examples/editor/xp.rs"]
+mod xp;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(document) = xp::load_xp(data) {
+        let again = xp::load_xp(&xp::save_xp(&document)).unwrap();
+        assert_eq!(
+            (again.width, again.height, again.layers.len()),
+            (document.width, document.height, document.layers.len())
+        );
+    }
+});
@@ -0,0 +1,271 @@
+//
+// Accessibility settings
+//
+// The options a game's accessibility menu offers, applied by the engine with
+// Context::set_accessibility() and saved in the user's config directory:
+//
+//      min_contrast    ink too close to its paper is pushed towards white or
+//                      black until the WCAG contrast ratio is at least this,
+//                      e.g. 4.5, or 7 for high contrast
+//      large_font      draws the font at double size, so fewer cells fit.  The
+//                      engine publishes GridResized when the grid changes size.
+//      colour_filter   see colour_filter.rs
+//      screen_reader   see screen_reader.rs
+//
+// The file has a "name = value" line per setting.
+//
+
+use crate::{lerp_colour, Colour, ColourFilter, ColourVision, Image, RogueError, RogueResult};
+use std::{collections::HashMap, fs, io, path::PathBuf};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AccessibilitySettings {
+    pub min_contrast: Option<f32>,
+    pub large_font: bool,
+    pub colour_filter: ColourFilter,
+    pub screen_reader: bool,
+}
+
+impl AccessibilitySettings {
+    pub fn high_contrast() -> Self {
+        AccessibilitySettings {
+            min_contrast: Some(7.0),
+            ..Default::default()
+        }
+    }
+
+    pub fn large_font() -> Self {
+        AccessibilitySettings {
+            large_font: true,
+            ..Default::default()
+        }
+    }
+
+    //
+    // Saving and loading
+    //
+
+    fn path(game_name: &str) -> RogueResult<PathBuf> {
+        let dir = dirs::config_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config directory"))?;
+        Ok(dir.join(game_name).join("accessibility.cfg"))
+    }
+
+    // The saved settings, or the defaults if none have been saved.  Unknown
+    // settings are ignored.
+    pub fn load(game_name: &str) -> RogueResult<Self> {
+        let path = Self::path(game_name)?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut settings = Self::default();
+        for (line_number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = |message: &str| RogueError::BadConfig {
+                file: path.display().to_string(),
+                line: line_number + 1,
+                message: String::from(message),
+            };
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected 'setting = value'"))?;
+            let value = value.trim();
+            let flag = || {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| error("expected true or false"))
+            };
+            match name.trim() {
+                "min_contrast" => {
+                    settings.min_contrast = match value {
+                        "none" => None,
+                        _ => Some(value.parse().map_err(|_| error("expected a ratio"))?),
+                    }
+                }
+                "large_font" => settings.large_font = flag()?,
+                "colour_filter" => {
+                    settings.colour_filter =
+                        filter_from_name(value).ok_or_else(|| error("unknown colour filter"))?
+                }
+                "screen_reader" => settings.screen_reader = flag()?,
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self, game_name: &str) -> RogueResult<()> {
+        let path = Self::path(game_name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let min_contrast = self
+            .min_contrast
+            .map_or(String::from("none"), |ratio| ratio.to_string());
+        let text = format!(
+            "min_contrast = {}\nlarge_font = {}\ncolour_filter = {}\nscreen_reader = {}\n",
+            min_contrast,
+            self.large_font,
+            filter_name(self.colour_filter),
+            self.screen_reader
+        );
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+const VISIONS: [(ColourVision, &str); 3] = [
+    (ColourVision::Protanopia, "protanopia"),
+    (ColourVision::Deuteranopia, "deuteranopia"),
+    (ColourVision::Tritanopia, "tritanopia"),
+];
+
+fn filter_name(filter: ColourFilter) -> String {
+    let vision_name = |vision| {
+        VISIONS
+            .iter()
+            .find(|(v, _)| *v == vision)
+            .map_or("", |(_, name)| name)
+    };
+    match filter {
+        ColourFilter::None => String::from("none"),
+        ColourFilter::Simulate(vision) => format!("simulate {}", vision_name(vision)),
+        ColourFilter::Correct(vision) => format!("correct {}", vision_name(vision)),
+    }
+}
+
+fn filter_from_name(name: &str) -> Option<ColourFilter> {
+    if name == "none" {
+        return Some(ColourFilter::None);
+    }
+    let (kind, vision) = name.split_once(' ')?;
+    let vision = VISIONS
+        .iter()
+        .find(|(_, n)| *n == vision.trim())
+        .map(|(v, _)| *v)?;
+    match kind {
+        "simulate" => Some(ColourFilter::Simulate(vision)),
+        "correct" => Some(ColourFilter::Correct(vision)),
+        _ => None,
+    }
+}
+
+//
+// Contrast
+//
+
+// WCAG relative luminance
+fn luminance(colour: u32) -> f32 {
+    let channel = |shift: u32| {
+        let c = ((colour >> shift) & 0xff) as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(0) + 0.7152 * channel(8) + 0.0722 * channel(16)
+}
+
+// The WCAG contrast ratio, from 1 for the same colour to 21 for black and
+// white
+pub fn contrast_ratio(a: u32, b: u32) -> f32 {
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+// Moves the ink towards white or black, whichever contrasts more with the
+// paper, just far enough to reach the ratio or as far as it can go.
+pub fn enforce_contrast(ink: u32, paper: u32, min_ratio: f32) -> u32 {
+    if contrast_ratio(ink, paper) >= min_ratio {
+        return ink;
+    }
+    let white = Colour::White.into();
+    let black = Colour::Black.into();
+    let target = if contrast_ratio(white, paper) >= contrast_ratio(black, paper) {
+        white
+    } else {
+        black
+    };
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..12 {
+        let t = (low + high) / 2.0;
+        if contrast_ratio(lerp_colour(ink, target, t), paper) >= min_ratio {
+            high = t;
+        } else {
+            low = t;
+        }
+    }
+    lerp_colour(ink, target, high)
+}
+
+impl Image {
+    pub fn enforce_contrast(&mut self, min_ratio: f32) {
+        let mut cache = HashMap::new();
+        for (ink, &paper) in self.fore_image.iter_mut().zip(&self.back_image) {
+            *ink = *cache
+                .entry((*ink, paper))
+                .or_insert_with(|| enforce_contrast(*ink, paper, min_ratio));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_colour;
+
+    #[test]
+    fn colour_filters_round_trip_through_their_names() {
+        let mut filters = vec![ColourFilter::None];
+        for (vision, _) in VISIONS {
+            filters.push(ColourFilter::Simulate(vision));
+            filters.push(ColourFilter::Correct(vision));
+        }
+        for filter in filters {
+            assert_eq!(filter_from_name(&filter_name(filter)), Some(filter));
+        }
+        assert_eq!(
+            filter_name(ColourFilter::Correct(ColourVision::Tritanopia)),
+            "correct tritanopia"
+        );
+        assert_eq!(filter_from_name("simulate colour"), None);
+        assert_eq!(filter_from_name("invert protanopia"), None);
+    }
+
+    #[test]
+    fn contrast_ratios_follow_wcag() {
+        let (white, black) = (Colour::White.into(), Colour::Black.into());
+        assert!((contrast_ratio(white, black) - 21.0).abs() < 0.01);
+        assert!((contrast_ratio(black, white) - 21.0).abs() < 0.01);
+        assert_eq!(contrast_ratio(white, white), 1.0);
+        let grey = new_colour(118, 118, 118);
+        assert!((contrast_ratio(grey, white) - 4.54).abs() < 0.01);
+    }
+
+    #[test]
+    fn dim_ink_is_pushed_just_far_enough() {
+        let paper = new_colour(0, 0, 64);
+        let ink = new_colour(0, 0, 128);
+        let fixed = enforce_contrast(ink, paper, 4.5);
+        let ratio = contrast_ratio(fixed, paper);
+        assert!((4.5..4.7).contains(&ratio), "{}", ratio);
+        // Towards white, the better contrast on dark paper
+        assert!(luminance(fixed) > luminance(ink));
+
+        let good = new_colour(255, 255, 0);
+        assert_eq!(enforce_contrast(good, paper, 4.5), good);
+        // Ratios that can't be reached go as far as possible
+        assert_eq!(enforce_contrast(ink, paper, 30.0), Colour::White.into());
+
+        let mut image = Image::new(2, 1);
+        image.clear(ink, paper);
+        image.enforce_contrast(4.5);
+        assert_eq!(image.fore_image, [fixed; 2]);
+    }
+}
@@ -0,0 +1,287 @@
+//
+// Achievements
+//
+// The game defines achievements by id, then reports progress or unlocks them
+// from its code through Context::achievements().  Progress is saved in the
+// platform's data directory under the game's name, one achievement per line,
+// so unlocks carry over between runs.
+//
+// Achievements with a target of more than 1 unlock once their progress
+// reaches it (e.g. "kill 100 rats").  The engine announces unlocks with a
+// notification and saves the changes at the end of the tick.
+//
+
+use crate::{history::now, RogueResult};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AchievementDef {
+    pub name: String,
+    pub description: String,
+    pub target: u32,
+    // Hidden achievements aren't described until they unlock
+    pub hidden: bool,
+}
+
+impl AchievementDef {
+    pub fn new(name: &str, description: &str) -> Self {
+        AchievementDef {
+            name: String::from(name),
+            description: String::from(description),
+            target: 1,
+            hidden: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AchievementProgress {
+    pub progress: u32,
+    // When it unlocked, in seconds since the Unix epoch
+    pub unlocked: Option<u64>,
+}
+
+pub struct Achievements {
+    definitions: BTreeMap<String, AchievementDef>,
+    progress: BTreeMap<String, AchievementProgress>,
+    path: Option<PathBuf>,
+    dirty: bool,
+    // Unlocked since the engine last looked, for the toasts
+    unlocked: Vec<String>,
+}
+
+impl Achievements {
+    pub fn new() -> Self {
+        Achievements {
+            definitions: BTreeMap::new(),
+            progress: BTreeMap::new(),
+            path: None,
+            dirty: false,
+            unlocked: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, id: &str, achievement: AchievementDef) -> &mut Self {
+        self.definitions.insert(String::from(id), achievement);
+        self
+    }
+
+    pub fn definition(&self, id: &str) -> Option<&AchievementDef> {
+        self.definitions.get(id)
+    }
+
+    // All achievement ids, ordered by id
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.definitions.keys().map(|id| id.as_str())
+    }
+
+    //
+    // Saving and loading
+    // Lines that can't be read are skipped, as are achievements that are no
+    // longer defined when the file is written.
+    //
+
+    // Loads the progress kept in the user's data directory under the game's
+    // name, and saves there from now on.
+    pub fn open(&mut self, game_name: &str) -> RogueResult<()> {
+        let dir = dirs::data_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory"))?;
+        self.open_path(&dir.join(game_name).join("achievements.txt"))
+    }
+
+    pub fn open_path(&mut self, path: &Path) -> RogueResult<()> {
+        self.path = Some(path.to_path_buf());
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.progress = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let id = String::from(fields.next()?);
+                let progress = fields.next()?.parse().ok()?;
+                let unlocked = match fields.next()? {
+                    "-" => None,
+                    time => Some(time.parse().ok()?),
+                };
+                Some((id, AchievementProgress { progress, unlocked }))
+            })
+            .collect();
+        Ok(())
+    }
+
+    // Writes the progress if anything has changed since the last save.  Does
+    // nothing if no file has been opened.  A failed save isn't retried until
+    // something changes again.
+    pub fn save(&mut self) -> RogueResult<()> {
+        let path = match (&self.path, self.dirty) {
+            (Some(path), true) => path,
+            _ => return Ok(()),
+        };
+        self.dirty = false;
+        let mut text = String::new();
+        for (id, progress) in &self.progress {
+            if self.definitions.contains_key(id) {
+                let unlocked = progress
+                    .unlocked
+                    .map_or(String::from("-"), |t| t.to_string());
+                text += &format!("{}\t{}\t{}\n", id, progress.progress, unlocked);
+            }
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    //
+    // Progress
+    // Unknown ids are ignored, so a typo doesn't crash the game, but they are
+    // logged.
+    //
+
+    pub fn progress(&self, id: &str) -> AchievementProgress {
+        self.progress.get(id).copied().unwrap_or_default()
+    }
+
+    pub fn is_unlocked(&self, id: &str) -> bool {
+        self.progress(id).unlocked.is_some()
+    }
+
+    // Adds to the progress, unlocking the achievement if it reaches the
+    // target.  Returns true if it unlocked just now.
+    pub fn add_progress(&mut self, id: &str, amount: u32) -> bool {
+        let value = self.progress(id).progress.saturating_add(amount);
+        self.set_progress(id, value)
+    }
+
+    // Progress never goes down, so reporting a best-so-far value is fine.
+    pub fn set_progress(&mut self, id: &str, value: u32) -> bool {
+        let target = match self.definitions.get(id) {
+            Some(achievement) => achievement.target,
+            None => {
+                log::warn!("Unknown achievement '{}'", id);
+                return false;
+            }
+        };
+        let entry = self.progress.entry(String::from(id)).or_default();
+        if entry.unlocked.is_some() || value <= entry.progress {
+            return false;
+        }
+        entry.progress = value.min(target);
+        self.dirty = true;
+        if entry.progress < target {
+            return false;
+        }
+        entry.unlocked = Some(now());
+        log::info!("Achievement unlocked: {}", id);
+        self.unlocked.push(String::from(id));
+        true
+    }
+
+    pub fn unlock(&mut self, id: &str) -> bool {
+        self.set_progress(id, u32::MAX)
+    }
+
+    // Clears all progress, e.g. from an options screen.
+    pub fn reset(&mut self) {
+        self.progress.clear();
+        self.dirty = true;
+    }
+
+    pub fn unlocked_count(&self) -> usize {
+        self.definitions
+            .keys()
+            .filter(|id| self.is_unlocked(id))
+            .count()
+    }
+
+    pub(crate) fn take_unlocked(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.unlocked)
+    }
+}
+
+impl Default for Achievements {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn achievements() -> Achievements {
+        let mut rats = AchievementDef::new("Rat catcher", "Kill 3 rats");
+        rats.target = 3;
+        let mut achievements = Achievements::new();
+        achievements
+            .add("rats", rats)
+            .add("win", AchievementDef::new("Winner", "Win the game"));
+        achievements
+    }
+
+    #[test]
+    fn progress_unlocks_at_the_target() {
+        let mut achievements = achievements();
+        assert_eq!(achievements.ids().collect::<Vec<_>>(), ["rats", "win"]);
+        assert!(!achievements.add_progress("rats", 2));
+        assert!(!achievements.set_progress("rats", 1));
+        assert_eq!(achievements.progress("rats").progress, 2);
+        assert!(achievements.add_progress("rats", 5));
+        assert_eq!(achievements.progress("rats").progress, 3);
+        assert!(achievements.is_unlocked("rats"));
+        assert!(!achievements.add_progress("rats", 1));
+
+        assert!(achievements.unlock("win"));
+        assert!(!achievements.unlock("win"));
+        assert!(!achievements.unlock("typo"));
+        assert_eq!(achievements.unlocked_count(), 2);
+        assert_eq!(achievements.take_unlocked(), ["rats", "win"]);
+        assert!(achievements.take_unlocked().is_empty());
+
+        achievements.reset();
+        assert_eq!(achievements.unlocked_count(), 0);
+    }
+
+    #[test]
+    fn progress_is_saved_between_runs() {
+        let dir = std::env::temp_dir().join(format!("mage-achievements-{}", std::process::id()));
+        let path = dir.join("achievements.txt");
+
+        let mut first = achievements();
+        first.open_path(&path).unwrap();
+        first.add_progress("rats", 2);
+        first.unlock("win");
+        first.add_progress("typo", 1);
+        first.save().unwrap();
+        let text = fs::read_to_string(&path).unwrap_or_default();
+
+        // Lines that can't be read are skipped
+        fs::write(&path, format!("{}broken\tline\n", text)).unwrap();
+        let mut second = achievements();
+        second.open_path(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+
+        let win = first.progress("win");
+        assert_eq!(
+            text,
+            format!("rats\t2\t-\nwin\t1\t{}\n", win.unlocked.unwrap())
+        );
+        assert_eq!(second.progress("rats").progress, 2);
+        assert_eq!(second.progress("win"), win);
+        assert_eq!(second.progress("broken"), AchievementProgress::default());
+
+        // Saving without a file or without changes does nothing
+        assert!(achievements().save().is_ok());
+        assert!(second.save().is_ok());
+        assert!(!dir.exists());
+    }
+}
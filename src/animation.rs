@@ -0,0 +1,321 @@
+//
+// Animation
+//
+// Animations are sequences of characters or images shown for set durations.
+// The engine's Animator plays them at positions on the screen and draws them
+// over the game's own presentation.
+//
+
+use crate::{Char, Effect, Image, Point};
+use std::{rc::Rc, time::Duration};
+
+pub enum Frame {
+    Char(Char),
+    Image(Image),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    // Play once and then stop
+    Once,
+    // Restart from the first frame after the last
+    Loop,
+    // Play forwards then backwards
+    PingPong,
+}
+
+//
+// Animation
+//
+
+pub struct Animation {
+    frames: Vec<(Frame, Duration)>,
+    mode: LoopMode,
+}
+
+impl Animation {
+    pub fn new(mode: LoopMode) -> Self {
+        Animation {
+            frames: Vec::new(),
+            mode,
+        }
+    }
+
+    pub fn with_char(&mut self, ch: Char, duration: Duration) -> &mut Self {
+        self.frames.push((Frame::Char(ch), duration));
+        self
+    }
+
+    pub fn with_image(&mut self, image: Image, duration: Duration) -> &mut Self {
+        self.frames.push((Frame::Image(image), duration));
+        self
+    }
+
+    pub fn build(&mut self) -> Rc<Self> {
+        Rc::new(Animation {
+            frames: self.frames.drain(..).collect(),
+            mode: self.mode,
+        })
+    }
+
+    fn cycle_length(&self) -> Duration {
+        self.frames.iter().map(|(_, d)| *d).sum()
+    }
+
+    pub fn is_finished(&self, elapsed: Duration) -> bool {
+        self.mode == LoopMode::Once && elapsed >= self.cycle_length()
+    }
+
+    // Returns the frame to show after the animation has been playing for the
+    // given time.  Animations that play once hold their last frame.
+    pub fn frame_at(&self, elapsed: Duration) -> Option<&Frame> {
+        let cycle = self.cycle_length();
+        if cycle.is_zero() {
+            return self.frames.first().map(|(f, _)| f);
+        }
+
+        let nanos = elapsed.as_nanos();
+        let cycle_nanos = cycle.as_nanos();
+        let mut t = match self.mode {
+            LoopMode::Once => nanos.min(cycle_nanos - 1),
+            LoopMode::Loop => nanos % cycle_nanos,
+            LoopMode::PingPong => {
+                let t = nanos % (2 * cycle_nanos);
+                if t < cycle_nanos {
+                    t
+                } else {
+                    2 * cycle_nanos - 1 - t
+                }
+            }
+        };
+
+        for (frame, duration) in &self.frames {
+            if t < duration.as_nanos() {
+                return Some(frame);
+            }
+            t -= duration.as_nanos();
+        }
+        self.frames.last().map(|(f, _)| f)
+    }
+}
+
+//
+// Animator
+// Plays animations attached to positions on the screen.  Games that want an
+// animation to follow an entity should update its position each tick.  The
+// animator also drives effects, which stay on screen until detached.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnimationId(u64);
+
+struct Playing {
+    id: AnimationId,
+    animation: Rc<Animation>,
+    position: Point,
+    elapsed: Duration,
+}
+
+pub struct Animator {
+    next_id: u64,
+    playing: Vec<Playing>,
+    effects: Vec<(AnimationId, Box<dyn Effect>)>,
+}
+
+impl Animator {
+    pub fn new() -> Self {
+        Animator {
+            next_id: 0,
+            playing: Vec::new(),
+            effects: Vec::new(),
+        }
+    }
+
+    fn new_id(&mut self) -> AnimationId {
+        let id = AnimationId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    pub fn attach(&mut self, animation: &Rc<Animation>, position: Point) -> AnimationId {
+        let id = self.new_id();
+        self.playing.push(Playing {
+            id,
+            animation: Rc::clone(animation),
+            position,
+            elapsed: Duration::ZERO,
+        });
+        id
+    }
+
+    pub fn play<E>(&mut self, effect: E) -> AnimationId
+    where
+        E: Effect + 'static,
+    {
+        let id = self.new_id();
+        self.effects.push((id, Box::new(effect)));
+        id
+    }
+
+    pub fn detach(&mut self, id: AnimationId) {
+        self.playing.retain(|p| p.id != id);
+        self.effects.retain(|(i, _)| *i != id);
+    }
+
+    pub fn clear(&mut self) {
+        self.playing.clear();
+        self.effects.clear();
+    }
+
+    pub fn set_position(&mut self, id: AnimationId, position: Point) {
+        if let Some(playing) = self.playing.iter_mut().find(|p| p.id == id) {
+            playing.position = position;
+        }
+    }
+
+    pub fn is_playing(&self, id: AnimationId) -> bool {
+        self.playing.iter().any(|p| p.id == id) || self.effects.iter().any(|(i, _)| *i == id)
+    }
+
+    pub fn is_effect_finished(&self, id: AnimationId) -> bool {
+        !self
+            .effects
+            .iter()
+            .any(|(i, effect)| *i == id && !effect.is_finished())
+    }
+
+    // Advance all animations, removing those that have finished.
+    pub fn update(&mut self, dt: Duration) {
+        self.playing.iter_mut().for_each(|p| p.elapsed += dt);
+        self.playing.retain(|p| !p.animation.is_finished(p.elapsed));
+        self.effects
+            .iter_mut()
+            .for_each(|(_, effect)| effect.update(dt));
+    }
+
+    pub fn draw(&self, image: &mut Image) {
+        for playing in &self.playing {
+            match playing.animation.frame_at(playing.elapsed) {
+                Some(Frame::Char(ch)) => image.draw_char(playing.position, *ch),
+                Some(Frame::Image(frame)) => {
+                    image.blit(playing.position, frame.width, frame.height, frame)
+                }
+                None => {}
+            }
+        }
+        self.effects
+            .iter()
+            .for_each(|(_, effect)| effect.draw(image));
+    }
+}
+
+impl Default for Animator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // Frames a, b and c, shown for 10ms, 20ms and 10ms
+    fn abc(mode: LoopMode) -> Rc<Animation> {
+        let mut animation = Animation::new(mode);
+        for (ch, time) in [(b'a', 10), (b'b', 20), (b'c', 10)] {
+            animation.with_char(Char::new(ch, 0, 0), ms(time));
+        }
+        animation.build()
+    }
+
+    fn frames(animation: &Animation, times: &[u64]) -> String {
+        times
+            .iter()
+            .map(|&time| match animation.frame_at(ms(time)) {
+                Some(Frame::Char(ch)) => ch.ch as char,
+                _ => '?',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_follow_the_loop_mode() {
+        let times = [0, 9, 10, 29, 30, 39, 40, 45, 55, 75, 79, 80];
+        assert_eq!(frames(&abc(LoopMode::Once), &times), "aabbcccccccc");
+        assert_eq!(frames(&abc(LoopMode::Loop), &times), "aabbccaabcca");
+        assert_eq!(frames(&abc(LoopMode::PingPong), &times), "aabbccccbaaa");
+    }
+
+    #[test]
+    fn only_animations_played_once_finish() {
+        let once = abc(LoopMode::Once);
+        assert!(!once.is_finished(ms(39)));
+        assert!(once.is_finished(ms(40)));
+        assert!(!abc(LoopMode::Loop).is_finished(ms(1000)));
+
+        // Without any time to show them, the first frame is held
+        let mut still = Animation::new(LoopMode::Loop);
+        still.with_char(Char::new(b'x', 0, 0), Duration::ZERO);
+        assert_eq!(frames(&still.build(), &[0, 100]), "xx");
+        assert!(Animation::new(LoopMode::Once)
+            .build()
+            .frame_at(ms(0))
+            .is_none());
+    }
+
+    #[test]
+    fn the_animator_draws_until_animations_finish() {
+        let mut animator = Animator::new();
+        let once = animator.attach(&abc(LoopMode::Once), Point::new(0, 0));
+        let looped = animator.attach(&abc(LoopMode::Loop), Point::new(1, 0));
+        animator.set_position(looped, Point::new(2, 1));
+        animator.update(ms(15));
+
+        let mut image = Image::new(3, 2);
+        animator.draw(&mut image);
+        assert_eq!(image.text_image, [b'b' as u32, 0, 0, 0, 0, b'b' as u32]);
+
+        animator.update(ms(25));
+        assert!(!animator.is_playing(once));
+        assert!(animator.is_playing(looped));
+        animator.detach(looped);
+        assert!(!animator.is_playing(looped));
+    }
+
+    struct Countdown(u32);
+
+    impl Effect for Countdown {
+        fn update(&mut self, _: Duration) {
+            self.0 = self.0.saturating_sub(1);
+        }
+
+        fn draw(&self, image: &mut Image) {
+            image.draw_char(Point::new(0, 0), Char::new(b'0' + self.0 as u8, 0, 0));
+        }
+
+        fn is_finished(&self) -> bool {
+            self.0 == 0
+        }
+    }
+
+    #[test]
+    fn effects_stay_until_detached() {
+        let mut animator = Animator::new();
+        let effect = animator.play(Countdown(2));
+        animator.update(ms(1));
+        assert!(!animator.is_effect_finished(effect));
+        animator.update(ms(1));
+        assert!(animator.is_effect_finished(effect));
+        assert!(animator.is_playing(effect));
+
+        let mut image = Image::new(1, 1);
+        animator.draw(&mut image);
+        assert_eq!(image.text_image, [b'0' as u32]);
+        animator.clear();
+        assert!(!animator.is_playing(effect));
+    }
+}
@@ -0,0 +1,91 @@
+//
+// ASCII art
+//
+// Converts images to and from arrays of strings, one per row.  Handy for
+// defining prefabs in code, writing tests and printing maps to the log:
+//
+//      let legend = HashMap::from([
+//          ('#', Char::new(b'#', grey, black)),
+//          ('.', Char::new(250, dark_grey, black)),
+//      ]);
+//      let room = Image::from_strings(&["#####", "#...#", "#####"], &legend);
+//
+// Characters missing from the legend are drawn as themselves, white on black.
+//
+
+use crate::{Char, Colour, Image, Point};
+use std::collections::HashMap;
+
+impl Image {
+    // One string per row.  Every row has a character per cell, so the strings
+    // line up even where the image has wide characters.
+    pub fn to_strings(&self) -> Vec<String> {
+        let width = self.width as usize;
+        (0..self.height as usize)
+            .map(|y| {
+                self.text_image[y * width..(y + 1) * width]
+                    .iter()
+                    .map(|&code| self.glyph_char(code))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // The image is as wide as the longest row.  Shorter rows are padded with
+    // spaces, which also go through the legend.
+    pub fn from_strings(rows: &[&str], legend: &HashMap<char, Char>) -> Image {
+        let width = rows
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut image = Image::new(width as u32, rows.len() as u32);
+        for (y, row) in rows.iter().enumerate() {
+            let padding = std::iter::repeat_n(' ', width - row.chars().count());
+            for (x, ch) in row.chars().chain(padding).enumerate() {
+                let p = Point::new(x as i32, y as i32);
+                match legend.get(&ch) {
+                    Some(&cell) => image.draw_char(p, cell),
+                    None => image.draw_glyph(
+                        p,
+                        image.glyph_code(ch).unwrap_or(b'?' as u32),
+                        Colour::White.into(),
+                        Colour::Black.into(),
+                    ),
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_go_through_the_legend() {
+        let legend = HashMap::from([('#', Char::new(b'#', 1, 2)), ('.', Char::new(250, 3, 4))]);
+        let image = Image::from_strings(&["##", "#.#", "a\u{20ac}"], &legend);
+        assert_eq!((image.width, image.height), (3, 3));
+        assert_eq!(image.to_strings(), ["## ", "#\u{b7}#", "a? "]);
+        assert_eq!(image.text_image[4], 250);
+        assert_eq!((image.fore_image[4], image.back_image[4]), (3, 4));
+
+        // Padding and characters missing from the legend are white on black
+        let white = u32::from(Colour::White);
+        let black = u32::from(Colour::Black);
+        assert_eq!((image.fore_image[2], image.back_image[2]), (white, black));
+        assert_eq!((image.fore_image[6], image.back_image[6]), (white, black));
+    }
+
+    #[test]
+    fn images_round_trip_through_strings() {
+        let rows = ["+--+", "|@.|", "+--+"];
+        let image = Image::from_strings(&rows, &HashMap::new());
+        assert_eq!(image.to_strings(), rows);
+        assert!(Image::from_strings(&[], &HashMap::new())
+            .to_strings()
+            .is_empty());
+    }
+}
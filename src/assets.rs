@@ -0,0 +1,488 @@
+//
+// Assets
+//
+// Fonts, images and data files are loaded through the asset manager, which
+// caches them behind typed handles.  A name is looked up in order:
+//
+//      1. loose files relative to the asset root
+//      2. mounted directories and asset packs, most recently mounted first
+//      3. bytes embedded in the executable with include_bytes!
+//
+// so a development build can override packed or embedded data with loose
+// files.
+//
+// With the hot-reload feature, asset files are watched and reloaded while the
+// game runs.  The engine picks up changes before each tick, and each asset's
+// version number goes up when it is reloaded so the game can tell when to
+// rebuild anything derived from it.
+//
+
+use crate::{AssetPack, RogueError, RogueResult};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "hot-reload")]
+use std::sync::mpsc::{channel, Receiver};
+
+//
+// Asset types
+//
+
+pub trait Asset: Sized + 'static {
+    fn from_bytes(bytes: &[u8]) -> RogueResult<Self>;
+
+    // Bytes used by the loaded asset, reported in Context::memory_usage().
+    // Types that own heap memory should add it in.
+    fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+    }
+}
+
+// Raw bytes, e.g. font data to hand to RogueBuilder::with_font
+impl Asset for Vec<u8> {
+    fn from_bytes(bytes: &[u8]) -> RogueResult<Self> {
+        Ok(bytes.to_vec())
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.capacity()
+    }
+}
+
+impl Asset for String {
+    fn from_bytes(bytes: &[u8]) -> RogueResult<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| RogueError::BadContent {
+            file: String::from("text asset"),
+            message: e.to_string(),
+        })
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.capacity()
+    }
+}
+
+impl Asset for image::RgbaImage {
+    fn from_bytes(bytes: &[u8]) -> RogueResult<Self> {
+        image::load_from_memory(bytes)
+            .map(|image| image.to_rgba8())
+            .map_err(|e| RogueError::BadContent {
+                file: String::from("image asset"),
+                message: e.to_string(),
+            })
+    }
+
+    fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.as_raw().capacity()
+    }
+}
+
+//
+// Handles
+//
+
+pub struct AssetHandle<T> {
+    index: usize,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for AssetHandle<T> {}
+
+impl<T> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for AssetHandle<T> {}
+
+//
+// Asset manager
+//
+
+enum Source {
+    File(PathBuf),
+    // Index into the mounts
+    Pack(usize),
+    Embedded,
+}
+
+enum Mount {
+    Dir(PathBuf),
+    Pack(AssetPack),
+}
+
+// Returns the asset and its memory size
+type Loader = fn(&[u8]) -> RogueResult<(Box<dyn Any>, usize)>;
+
+struct Entry {
+    name: String,
+    source: Source,
+    loader: Loader,
+    value: Box<dyn Any>,
+    size: usize,
+    version: u32,
+}
+
+fn load_boxed<T: Asset>(bytes: &[u8]) -> RogueResult<(Box<dyn Any>, usize)> {
+    let asset = T::from_bytes(bytes)?;
+    let size = asset.memory_size();
+    Ok((Box::new(asset), size))
+}
+
+pub struct Assets {
+    root: PathBuf,
+    mounts: Vec<Mount>,
+    embedded: HashMap<String, &'static [u8]>,
+    entries: Vec<Entry>,
+    by_name: HashMap<String, usize>,
+    #[cfg(feature = "hot-reload")]
+    watcher: Option<notify::RecommendedWatcher>,
+    #[cfg(feature = "hot-reload")]
+    changes: Option<Receiver<PathBuf>>,
+}
+
+impl Assets {
+    pub fn new(root: &Path) -> Self {
+        Assets {
+            root: root.to_path_buf(),
+            mounts: Vec::new(),
+            embedded: HashMap::new(),
+            entries: Vec::new(),
+            by_name: HashMap::new(),
+            #[cfg(feature = "hot-reload")]
+            watcher: None,
+            #[cfg(feature = "hot-reload")]
+            changes: None,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn set_root(&mut self, root: &Path) {
+        self.root = root.to_path_buf();
+    }
+
+    // Adds a directory searched after the asset root.
+    pub fn mount_dir(&mut self, dir: &Path) -> &mut Self {
+        self.mounts.push(Mount::Dir(dir.to_path_buf()));
+        self
+    }
+
+    // Adds a pack file built with AssetPack::create().
+    pub fn mount_pack(&mut self, path: &Path) -> RogueResult<&mut Self> {
+        self.mounts.push(Mount::Pack(AssetPack::open(path)?));
+        Ok(self)
+    }
+
+    // Registers bytes built into the executable, used when no file or pack
+    // provides the name.
+    pub fn embed(&mut self, name: &str, bytes: &'static [u8]) -> &mut Self {
+        self.embedded.insert(String::from(name), bytes);
+        self
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    fn find(&self, name: &str) -> Option<Source> {
+        let path = self.root.join(name);
+        if path.is_file() {
+            return Some(Source::File(path));
+        }
+        for (index, mount) in self.mounts.iter().enumerate().rev() {
+            match mount {
+                Mount::Dir(dir) => {
+                    let path = dir.join(name);
+                    if path.is_file() {
+                        return Some(Source::File(path));
+                    }
+                }
+                Mount::Pack(pack) => {
+                    if pack.contains(name) {
+                        return Some(Source::Pack(index));
+                    }
+                }
+            }
+        }
+        if self.embedded.contains_key(name) {
+            Some(Source::Embedded)
+        } else {
+            None
+        }
+    }
+
+    fn read_source(&self, name: &str, source: &Source) -> RogueResult<Vec<u8>> {
+        match source {
+            Source::File(path) => Ok(fs::read(path)?),
+            Source::Pack(index) => match &self.mounts[*index] {
+                Mount::Pack(pack) => pack.read(name),
+                Mount::Dir(_) => unreachable!(),
+            },
+            Source::Embedded => Ok(self.embedded[name].to_vec()),
+        }
+    }
+
+    // Reads an asset's bytes without caching them.
+    pub fn read(&self, name: &str) -> RogueResult<Vec<u8>> {
+        let source = self.find(name).ok_or_else(|| not_found(name))?;
+        self.read_source(name, &source)
+    }
+
+    fn add<T: Asset>(&mut self, name: &str, source: Source, bytes: &[u8]) -> RogueResult<usize> {
+        let (value, size) = load_boxed::<T>(bytes)?;
+        let index = self.entries.len();
+        self.entries.push(Entry {
+            name: String::from(name),
+            source,
+            loader: load_boxed::<T>,
+            value,
+            size,
+            version: 0,
+        });
+        self.by_name.insert(String::from(name), index);
+        Ok(index)
+    }
+
+    fn handle<T: Asset>(&self, index: usize) -> RogueResult<AssetHandle<T>> {
+        if self.entries[index].value.is::<T>() {
+            Ok(AssetHandle {
+                index,
+                phantom: PhantomData,
+            })
+        } else {
+            Err(RogueError::BadContent {
+                file: self.entries[index].name.clone(),
+                message: String::from("asset was loaded as a different type"),
+            })
+        }
+    }
+
+    // Loads an asset by name, a '/' separated path.  Loading the same name
+    // again returns the cached asset.
+    pub fn load<T: Asset>(&mut self, name: &str) -> RogueResult<AssetHandle<T>> {
+        if let Some(&index) = self.by_name.get(name) {
+            return self.handle(index);
+        }
+        let source = self.find(name).ok_or_else(|| not_found(name))?;
+        let bytes = self.read_source(name, &source)?;
+        if let Source::File(path) = &source {
+            self.watch(path);
+        }
+        let index = self.add::<T>(name, source, &bytes)?;
+        self.handle(index)
+    }
+
+    pub fn get<T: Asset>(&self, handle: AssetHandle<T>) -> &T {
+        self.entries[handle.index]
+            .value
+            .downcast_ref()
+            .expect("Asset handle has the wrong type")
+    }
+
+    // Goes up by one every time the asset is reloaded.
+    pub fn version<T: Asset>(&self, handle: AssetHandle<T>) -> u32 {
+        self.entries[handle.index].version
+    }
+
+    pub fn name<T: Asset>(&self, handle: AssetHandle<T>) -> &str {
+        &self.entries[handle.index].name
+    }
+
+    // Bytes used by the loaded assets.  Embedded assets' bytes are part of
+    // the executable and aren't counted until they are loaded.
+    pub fn memory_size(&self) -> usize {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    fn reload_index(&mut self, index: usize) -> RogueResult<()> {
+        let entry = &self.entries[index];
+        if let Source::Embedded = entry.source {
+            return Ok(());
+        }
+        let bytes = self.read_source(&entry.name, &entry.source)?;
+        let entry = &mut self.entries[index];
+        let (value, size) = (entry.loader)(&bytes)?;
+        entry.value = value;
+        entry.size = size;
+        entry.version += 1;
+        Ok(())
+    }
+
+    // Reads the asset's file or pack again.  Embedded assets never change.
+    pub fn reload<T: Asset>(&mut self, handle: AssetHandle<T>) -> RogueResult<()> {
+        self.reload_index(handle.index)
+    }
+
+    //
+    // Hot reloading
+    //
+
+    #[cfg(feature = "hot-reload")]
+    fn watch(&mut self, path: &Path) {
+        use notify::{RecursiveMode, Watcher};
+
+        if self.watcher.is_none() {
+            let (sender, receiver) = channel();
+            let watcher =
+                notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                    if let Ok(event) = event {
+                        if event.kind.is_modify() || event.kind.is_create() {
+                            event.paths.into_iter().for_each(|path| {
+                                let _ = sender.send(path);
+                            });
+                        }
+                    }
+                });
+            match watcher {
+                Ok(watcher) => {
+                    self.watcher = Some(watcher);
+                    self.changes = Some(receiver);
+                }
+                Err(e) => log::warn!("Unable to watch assets: {}", e),
+            }
+        }
+        if let Some(watcher) = self.watcher.as_mut() {
+            if let Err(e) = watcher.watch(path, RecursiveMode::NonRecursive) {
+                log::warn!("Unable to watch {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "hot-reload"))]
+    fn watch(&mut self, _path: &Path) {}
+
+    // Called by the engine before each tick.  Assets that fail to reload (for
+    // example because an editor is half way through saving them) keep their
+    // old value.
+    #[cfg(feature = "hot-reload")]
+    pub(crate) fn update(&mut self) {
+        let changed = match &self.changes {
+            Some(changes) => changes.try_iter().collect::<Vec<_>>(),
+            None => return,
+        };
+        for path in changed {
+            let path = path.canonicalize().unwrap_or(path);
+            let indices = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| match &entry.source {
+                    Source::File(p) => p.canonicalize().is_ok_and(|p| p == path),
+                    Source::Pack(_) | Source::Embedded => false,
+                })
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>();
+            for index in indices {
+                if let Err(e) = self.reload_index(index) {
+                    log::warn!("Unable to reload {}: {}", self.entries[index].name, e);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "hot-reload"))]
+    pub(crate) fn update(&mut self) {}
+}
+
+fn not_found(name: &str) -> RogueError {
+    RogueError::AssetNotFound {
+        name: String::from(name),
+        location: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs f with a root and a mounted directory, each holding the files
+    fn with_dirs<F>(name: &str, root_files: &[(&str, &str)], mount_files: &[(&str, &str)], f: F)
+    where
+        F: FnOnce(&mut Assets, &Path),
+    {
+        let dir = std::env::temp_dir().join(format!("mage-assets-{}-{}", name, std::process::id()));
+        let (root, mount) = (dir.join("root"), dir.join("mount"));
+        for (dir, files) in [(&root, root_files), (&mount, mount_files)] {
+            fs::create_dir_all(dir).unwrap();
+            for (name, text) in files {
+                fs::write(dir.join(name), text).unwrap();
+            }
+        }
+        let mut assets = Assets::new(&root);
+        assets.mount_dir(&mount);
+        f(&mut assets, &root);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn loose_files_override_mounts_and_embedded_bytes() {
+        with_dirs(
+            "order",
+            &[("a.txt", "root")],
+            &[("a.txt", "mount"), ("b.txt", "mount")],
+            |assets, _| {
+                assets
+                    .embed("b.txt", b"embedded")
+                    .embed("c.txt", b"embedded");
+                assert_eq!(assets.read("a.txt").unwrap(), b"root");
+                assert_eq!(assets.read("b.txt").unwrap(), b"mount");
+                assert_eq!(assets.read("c.txt").unwrap(), b"embedded");
+                assert!(!assets.exists("d.txt"));
+                assert!(matches!(
+                    assets.read("d.txt"),
+                    Err(RogueError::AssetNotFound { .. })
+                ));
+            },
+        );
+    }
+
+    #[test]
+    fn assets_are_cached_by_name_and_type() {
+        with_dirs("cache", &[("a.txt", "hello")], &[], |assets, _| {
+            let text = assets.load::<String>("a.txt").unwrap();
+            assert_eq!(assets.get(text), "hello");
+            assert!(assets.load::<String>("a.txt").unwrap() == text);
+            assert_eq!(assets.name(text), "a.txt");
+            assert!(assets.memory_size() >= 5);
+            assert!(matches!(
+                assets.load::<Vec<u8>>("a.txt"),
+                Err(RogueError::BadContent { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn reloading_reads_the_file_again() {
+        with_dirs("reload", &[("a.txt", "old")], &[], |assets, root| {
+            assets.embed("e.txt", b"embedded");
+            let text = assets.load::<String>("a.txt").unwrap();
+            let embedded = assets.load::<String>("e.txt").unwrap();
+            fs::write(root.join("a.txt"), "new").unwrap();
+            assets.reload(text).unwrap();
+            assets.reload(embedded).unwrap();
+            assert_eq!(assets.get(text), "new");
+            assert_eq!(assets.version(text), 1);
+            assert_eq!(assets.version(embedded), 0);
+
+            // A failed reload keeps the old value
+            fs::write(root.join("a.txt"), [0xff]).unwrap();
+            assert!(assets.reload(text).is_err());
+            assert_eq!(assets.get(text), "new");
+        });
+    }
+}
@@ -0,0 +1,329 @@
+//
+// Autosave
+//
+// Saves the game in the background every so many turns or seconds, when the
+// window loses focus and when the game quits, turned on with
+// RogueBuilder::with_autosave().  The engine asks the game for a snapshot
+// with Game::autosave(): a closure that owns a copy of the state and
+// serialises it.  The copy is made on the main thread, between ticks, and the
+// closure is run on the autosave thread, so a slow encoder never stalls a
+// frame:
+//
+//      fn autosave(&self) -> Option<SaveSnapshot> {
+//          let state = self.state.clone();
+//          Some(Box::new(move || bincode::serialize(&state).unwrap()))
+//      }
+//
+// Snapshots are double-buffered: one is written while the next waits, and a
+// newer snapshot replaces a waiting one.  Files are written in the save file
+// format of save.rs, stamped with the version given to with_autosave(), and
+// replaced atomically, so a crash mid-save leaves the previous autosave.  The
+// game counts its turns with Context::autosave().end_turn().
+//
+
+use crate::save::{encode_save, write_save_file};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+pub type SaveSnapshot = Box<dyn FnOnce() -> Vec<u8> + Send>;
+
+#[derive(Default)]
+struct Shared {
+    pending: Option<SaveSnapshot>,
+    writing: bool,
+    stop: bool,
+    last_error: Option<String>,
+}
+
+pub struct Autosave {
+    path: PathBuf,
+    version: u32,
+    every_turns: Option<u32>,
+    interval: Option<Duration>,
+    on_focus_loss: bool,
+    on_quit: bool,
+    turns: u32,
+    since_save: Duration,
+    due: bool,
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Autosave {
+    // Saves to the file when the game quits, until other times are set.
+    pub fn new(path: &Path, version: u32) -> Self {
+        Autosave {
+            path: path.to_path_buf(),
+            version,
+            every_turns: None,
+            interval: None,
+            on_focus_loss: false,
+            on_quit: true,
+            turns: 0,
+            since_save: Duration::ZERO,
+            due: false,
+            shared: Arc::new((Mutex::new(Shared::default()), Condvar::new())),
+            worker: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Saves after this many calls to end_turn(), or never with None.
+    pub fn with_turns(&mut self, turns: Option<u32>) -> &mut Self {
+        self.every_turns = turns.filter(|&turns| turns > 0);
+        self
+    }
+
+    // Saves when this long has passed since the last save, or never with None.
+    pub fn with_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.interval = interval.filter(|interval| !interval.is_zero());
+        self
+    }
+
+    pub fn with_focus_loss(&mut self, save: bool) -> &mut Self {
+        self.on_focus_loss = save;
+        self
+    }
+
+    pub fn with_quit(&mut self, save: bool) -> &mut Self {
+        self.on_quit = save;
+        self
+    }
+
+    pub fn end_turn(&mut self) {
+        self.turns += 1;
+        if self.every_turns.is_some_and(|every| self.turns >= every) {
+            self.due = true;
+        }
+    }
+
+    // Saves after the current tick.
+    pub fn request(&mut self) {
+        self.due = true;
+    }
+
+    // Whether a snapshot is being written or waiting to be
+    pub fn is_saving(&self) -> bool {
+        let shared = self.shared.0.lock().unwrap();
+        shared.writing || shared.pending.is_some()
+    }
+
+    // Why the last autosave failed, cleared by the next one that succeeds
+    pub fn last_error(&self) -> Option<String> {
+        self.shared.0.lock().unwrap().last_error.clone()
+    }
+
+    //
+    // Called by the engine
+    //
+
+    pub(crate) fn focus_lost(&mut self) {
+        if self.on_focus_loss {
+            self.due = true;
+        }
+    }
+
+    pub(crate) fn saves_on_quit(&self) -> bool {
+        self.on_quit
+    }
+
+    // Called after each tick.  Returns true if a snapshot should be taken.
+    pub(crate) fn update(&mut self, dt: Duration) -> bool {
+        self.since_save += dt;
+        if self
+            .interval
+            .is_some_and(|interval| self.since_save >= interval)
+        {
+            self.due = true;
+        }
+        self.due
+    }
+
+    // Hands a snapshot to the autosave thread, starting it the first time.
+    pub(crate) fn submit(&mut self, snapshot: Option<SaveSnapshot>) {
+        self.due = false;
+        self.turns = 0;
+        self.since_save = Duration::ZERO;
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        if self.worker.is_none() {
+            self.shared.0.lock().unwrap().stop = false;
+            let shared = Arc::clone(&self.shared);
+            let (path, version) = (self.path.clone(), self.version);
+            let worker = thread::Builder::new()
+                .name(String::from("autosave"))
+                .spawn(move || write_snapshots(&shared, &path, version));
+            match worker {
+                Ok(worker) => self.worker = Some(worker),
+                Err(e) => {
+                    log::error!("Unable to start the autosave thread: {}", e);
+                    return;
+                }
+            }
+        }
+        let (lock, ready) = &*self.shared;
+        lock.lock().unwrap().pending = Some(snapshot);
+        ready.notify_all();
+    }
+
+    // Waits for the snapshots that have been submitted to be written.
+    pub(crate) fn finish(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            let (lock, ready) = &*self.shared;
+            lock.lock().unwrap().stop = true;
+            ready.notify_all();
+            if worker.join().is_err() {
+                log::error!("The autosave thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Autosave {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+fn write_snapshots(shared: &(Mutex<Shared>, Condvar), path: &Path, version: u32) {
+    let (lock, ready) = shared;
+    loop {
+        let snapshot = {
+            let mut state = lock.lock().unwrap();
+            while state.pending.is_none() && !state.stop {
+                state = ready.wait(state).unwrap();
+            }
+            match state.pending.take() {
+                Some(snapshot) => {
+                    state.writing = true;
+                    snapshot
+                }
+                None => return,
+            }
+        };
+
+        let result = write_save_file(path, &encode_save(version, &snapshot()));
+        let mut state = lock.lock().unwrap();
+        state.writing = false;
+        match result {
+            Ok(()) => state.last_error = None,
+            Err(e) => {
+                log::error!("Unable to autosave to {}: {}", path.display(), e);
+                state.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::save_version;
+    use std::{fs, sync::mpsc};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mage-{}-{}.sav", name, std::process::id()))
+    }
+
+    fn snapshot(data: &'static [u8]) -> Option<SaveSnapshot> {
+        Some(Box::new(move || data.to_vec()))
+    }
+
+    #[test]
+    fn saves_fall_due_by_turns_time_and_focus() {
+        let mut autosave = Autosave::new(Path::new("unused.sav"), 1);
+        autosave
+            .with_turns(Some(2))
+            .with_interval(Some(Duration::from_secs(10)));
+        autosave.end_turn();
+        assert!(!autosave.update(Duration::from_secs(4)));
+        autosave.end_turn();
+        assert!(autosave.update(Duration::ZERO));
+
+        // Submitting starts counting again, even without a snapshot
+        autosave.submit(None);
+        assert!(!autosave.update(Duration::from_secs(9)));
+        assert!(autosave.update(Duration::from_secs(1)));
+        autosave.submit(None);
+
+        autosave.focus_lost();
+        assert!(!autosave.update(Duration::ZERO));
+        autosave.with_focus_loss(true).focus_lost();
+        assert!(autosave.update(Duration::ZERO));
+        autosave.submit(None);
+
+        // Zero turns or time mean never
+        autosave
+            .with_turns(Some(0))
+            .with_interval(Some(Duration::ZERO));
+        autosave.end_turn();
+        assert!(!autosave.update(Duration::from_secs(100)));
+        assert!(autosave.saves_on_quit());
+    }
+
+    #[test]
+    fn snapshots_are_written_in_the_background() {
+        let path = temp_path("autosave");
+        let mut autosave = Autosave::new(&path, 7);
+        autosave.submit(snapshot(b"state"));
+        autosave.finish();
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(save_version(&bytes).unwrap(), (7, &b"state"[..]));
+        assert!(!autosave.is_saving());
+        assert_eq!(autosave.last_error(), None);
+    }
+
+    #[test]
+    fn newer_snapshots_replace_waiting_ones() {
+        let path = temp_path("autosave-newer");
+        let mut autosave = Autosave::new(&path, 1);
+        let (started, wait_for_start) = mpsc::channel();
+        let (release, wait_for_release) = mpsc::channel::<()>();
+        autosave.submit(Some(Box::new(move || {
+            started.send(()).unwrap();
+            wait_for_release.recv().unwrap();
+            b"first".to_vec()
+        })));
+        wait_for_start.recv().unwrap();
+
+        // Written while the first is being made, so only the last is kept
+        autosave.submit(Some(Box::new(|| panic!("replaced snapshots aren't run"))));
+        autosave.submit(snapshot(b"third"));
+        assert!(autosave.is_saving());
+        release.send(()).unwrap();
+        autosave.finish();
+
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(save_version(&bytes).unwrap().1, b"third");
+    }
+
+    #[test]
+    fn failures_are_kept_until_a_save_succeeds() {
+        // A file can't be a directory to save in
+        let blocker = temp_path("autosave-blocker");
+        fs::write(&blocker, b"").unwrap();
+        let mut autosave = Autosave::new(&blocker.join("game.sav"), 1);
+        autosave.submit(snapshot(b"state"));
+        autosave.finish();
+        let _ = fs::remove_file(&blocker);
+        assert!(autosave.last_error().is_some());
+
+        let path = temp_path("autosave-retry");
+        autosave.path = path.clone();
+        autosave.submit(snapshot(b"state"));
+        autosave.finish();
+        let _ = fs::remove_file(&path);
+        assert_eq!(autosave.last_error(), None);
+    }
+}
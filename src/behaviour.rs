@@ -0,0 +1,356 @@
+//
+// Behaviour trees
+//
+// Monster AI is built by composing small behaviours:
+//
+//      Selector    tries its children in order until one doesn't fail
+//      Sequence    runs its children in order until one doesn't succeed
+//      Condition   a test that succeeds or fails
+//      Action      does something, usually moving or attacking
+//      Invert      swaps success and failure
+//      Utility     runs the child with the highest score
+//
+// For example, a monster that flees when hurt, chases the player when it can
+// see them and patrols otherwise:
+//
+//      Behaviour::selector(vec![
+//          Behaviour::sequence(vec![Behaviour::condition(is_hurt), Behaviour::action(flee)]),
+//          Behaviour::sequence(vec![Behaviour::condition(sees_player), Behaviour::action(chase)]),
+//          Behaviour::action(patrol),
+//      ])
+//
+// Trees are evaluated from the root on every turn, so there is no state
+// hidden in the tree.  Anything an actor needs to remember (its patrol route,
+// where it last saw the player) goes in its blackboard.
+//
+// An actor with a Brain component takes its turns through
+// World::take_ai_turn(), which runs the tree and schedules the actor's next
+// turn after the delay its action set.
+//
+
+use crate::{Entity, World};
+use std::{any::Any, collections::HashMap, rc::Rc};
+
+// The delay before an actor's next turn if its action doesn't set one
+pub const DEFAULT_TURN_DELAY: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BehaviourStatus {
+    Success,
+    Failure,
+    // The action is still going (e.g. walking a long path) and should carry
+    // on next turn
+    Running,
+}
+
+//
+// Blackboard
+// Per-actor memory, keyed by name.
+//
+
+pub struct Blackboard {
+    values: HashMap<String, Box<dyn Any>>,
+}
+
+impl Blackboard {
+    pub fn new() -> Self {
+        Blackboard {
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn set<T: 'static>(&mut self, key: &str, value: T) {
+        self.values.insert(String::from(key), Box::new(value));
+    }
+
+    // Returns None if the key is missing or holds a different type.
+    pub fn get<T: 'static>(&self, key: &str) -> Option<&T> {
+        self.values.get(key)?.downcast_ref()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, key: &str) -> Option<&mut T> {
+        self.values.get_mut(key)?.downcast_mut()
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.values.remove(key).is_some()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+impl Default for Blackboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//
+// Trees
+//
+
+// What a behaviour can see and change.  G is the game's own state (the map,
+// message log...) that isn't kept in the world.
+pub struct AiContext<'a, G> {
+    pub world: &'a mut World,
+    pub game: &'a mut G,
+    pub entity: Entity,
+    pub blackboard: &'a mut Blackboard,
+    // Time until the actor's next turn
+    pub delay: u64,
+}
+
+type ConditionFn<G> = Box<dyn Fn(&mut AiContext<G>) -> bool>;
+type ActionFn<G> = Box<dyn Fn(&mut AiContext<G>) -> BehaviourStatus>;
+type ScoreFn<G> = Box<dyn Fn(&mut AiContext<G>) -> f32>;
+
+pub enum Behaviour<G> {
+    Selector(Vec<Behaviour<G>>),
+    Sequence(Vec<Behaviour<G>>),
+    Condition(ConditionFn<G>),
+    Action(ActionFn<G>),
+    Invert(Box<Behaviour<G>>),
+    Utility(Vec<(ScoreFn<G>, Behaviour<G>)>),
+}
+
+impl<G> Behaviour<G> {
+    pub fn selector(children: Vec<Behaviour<G>>) -> Self {
+        Behaviour::Selector(children)
+    }
+
+    pub fn sequence(children: Vec<Behaviour<G>>) -> Self {
+        Behaviour::Sequence(children)
+    }
+
+    pub fn condition(f: impl Fn(&mut AiContext<G>) -> bool + 'static) -> Self {
+        Behaviour::Condition(Box::new(f))
+    }
+
+    pub fn action(f: impl Fn(&mut AiContext<G>) -> BehaviourStatus + 'static) -> Self {
+        Behaviour::Action(Box::new(f))
+    }
+
+    pub fn invert(child: Behaviour<G>) -> Self {
+        Behaviour::Invert(Box::new(child))
+    }
+
+    // Children are scored every turn and only the best is run.  Children
+    // scoring zero or less are never run.
+    pub fn utility(children: Vec<(ScoreFn<G>, Behaviour<G>)>) -> Self {
+        Behaviour::Utility(children)
+    }
+
+    pub fn tick(&self, context: &mut AiContext<G>) -> BehaviourStatus {
+        match self {
+            Behaviour::Selector(children) => children
+                .iter()
+                .map(|child| child.tick(context))
+                .find(|&status| status != BehaviourStatus::Failure)
+                .unwrap_or(BehaviourStatus::Failure),
+            Behaviour::Sequence(children) => children
+                .iter()
+                .map(|child| child.tick(context))
+                .find(|&status| status != BehaviourStatus::Success)
+                .unwrap_or(BehaviourStatus::Success),
+            Behaviour::Condition(f) => {
+                if f(context) {
+                    BehaviourStatus::Success
+                } else {
+                    BehaviourStatus::Failure
+                }
+            }
+            Behaviour::Action(f) => f(context),
+            Behaviour::Invert(child) => match child.tick(context) {
+                BehaviourStatus::Success => BehaviourStatus::Failure,
+                BehaviourStatus::Failure => BehaviourStatus::Success,
+                BehaviourStatus::Running => BehaviourStatus::Running,
+            },
+            Behaviour::Utility(children) => {
+                let best = children
+                    .iter()
+                    .map(|(score, child)| (score(context), child))
+                    .filter(|(score, _)| *score > 0.0)
+                    .max_by(|a, b| a.0.total_cmp(&b.0));
+                match best {
+                    Some((_, child)) => child.tick(context),
+                    None => BehaviourStatus::Failure,
+                }
+            }
+        }
+    }
+}
+
+//
+// Brains
+// The component that gives an entity AI.  Trees are shared between all the
+// actors of a kind.
+//
+
+pub struct Brain<G> {
+    tree: Rc<Behaviour<G>>,
+    pub blackboard: Blackboard,
+}
+
+impl<G> Brain<G> {
+    pub fn new(tree: Rc<Behaviour<G>>) -> Self {
+        Brain {
+            tree,
+            blackboard: Blackboard::new(),
+        }
+    }
+}
+
+impl World {
+    // If the next entity to act has a Brain<G>, runs its tree, reschedules it
+    // and returns it.  Returns None when it's the turn of an entity without a
+    // brain (usually the player), leaving that turn to the game.
+    pub fn take_ai_turn<G: 'static>(&mut self, game: &mut G) -> Option<(Entity, BehaviourStatus)> {
+        let (entity, _) = self.scheduler().peek()?;
+        if !self.has::<Brain<G>>(entity) {
+            return None;
+        }
+        self.scheduler().next_turn();
+
+        // The brain is taken out while it runs so behaviours can borrow the
+        // world.
+        let mut brain = self.remove::<Brain<G>>(entity)?;
+        let mut context = AiContext {
+            world: self,
+            game,
+            entity,
+            blackboard: &mut brain.blackboard,
+            delay: DEFAULT_TURN_DELAY,
+        };
+        let status = brain.tree.tick(&mut context);
+        let delay = context.delay;
+
+        if self.is_alive(entity) {
+            self.insert(entity, brain);
+            if !self.scheduler().is_scheduled(entity) {
+                self.scheduler().schedule(entity, delay);
+            }
+        }
+        Some((entity, status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The game records the actions that ran
+    type Log = Vec<&'static str>;
+
+    fn act(name: &'static str, status: BehaviourStatus) -> Behaviour<Log> {
+        Behaviour::action(move |context: &mut AiContext<Log>| {
+            context.game.push(name);
+            status
+        })
+    }
+
+    fn run(tree: &Behaviour<Log>) -> (BehaviourStatus, Log) {
+        let mut world = World::new();
+        let entity = world.spawn();
+        let mut game = Log::new();
+        let mut blackboard = Blackboard::new();
+        let status = tree.tick(&mut AiContext {
+            world: &mut world,
+            game: &mut game,
+            entity,
+            blackboard: &mut blackboard,
+            delay: DEFAULT_TURN_DELAY,
+        });
+        (status, game)
+    }
+
+    #[test]
+    fn selectors_and_sequences_stop_early() {
+        use BehaviourStatus::*;
+
+        let tree = Behaviour::selector(vec![
+            act("a", Failure),
+            act("b", Running),
+            act("c", Success),
+        ]);
+        assert_eq!(run(&tree), (Running, vec!["a", "b"]));
+        let tree = Behaviour::sequence(vec![
+            act("a", Success),
+            act("b", Failure),
+            act("c", Success),
+        ]);
+        assert_eq!(run(&tree), (Failure, vec!["a", "b"]));
+        let tree = Behaviour::sequence(vec![
+            Behaviour::invert(Behaviour::condition(|_| false)),
+            act("a", Success),
+        ]);
+        assert_eq!(run(&tree), (Success, vec!["a"]));
+        assert_eq!(run(&Behaviour::selector(Vec::new())).0, Failure);
+        assert_eq!(run(&Behaviour::invert(act("a", Running))).0, Running);
+    }
+
+    #[test]
+    fn utility_runs_the_best_scoring_child() {
+        use BehaviourStatus::*;
+
+        let score = |value: f32| -> ScoreFn<Log> { Box::new(move |_| value) };
+        let tree = Behaviour::utility(vec![
+            (score(0.2), act("low", Success)),
+            (score(0.9), act("high", Success)),
+            (score(0.5), act("middle", Success)),
+        ]);
+        assert_eq!(run(&tree), (Success, vec!["high"]));
+        let tree = Behaviour::utility(vec![(score(0.0), act("never", Success))]);
+        assert_eq!(run(&tree), (Failure, vec![]));
+    }
+
+    #[test]
+    fn blackboards_hold_values_by_name_and_type() {
+        let mut blackboard = Blackboard::new();
+        blackboard.set("target", (3, 4));
+        assert_eq!(blackboard.get::<(i32, i32)>("target"), Some(&(3, 4)));
+        assert_eq!(blackboard.get::<u32>("target"), None);
+        *blackboard.get_mut::<(i32, i32)>("target").unwrap() = (5, 6);
+        assert_eq!(blackboard.get::<(i32, i32)>("target"), Some(&(5, 6)));
+        assert!(blackboard.remove("target"));
+        assert!(!blackboard.contains("target"));
+    }
+
+    #[test]
+    fn actors_with_brains_take_their_turns() {
+        let mut world = World::new();
+        let player = world.spawn();
+        let monster = world.spawn();
+        let tree = Rc::new(Behaviour::action(|context: &mut AiContext<Log>| {
+            context.game.push("moved");
+            context.delay = 50;
+            let turns = context.blackboard.get::<u32>("turns").copied().unwrap_or(0);
+            context.blackboard.set("turns", turns + 1);
+            BehaviourStatus::Success
+        }));
+        world.insert(monster, Brain::new(tree));
+        world.scheduler().schedule(monster, 0);
+        world.scheduler().schedule(player, 75);
+
+        let mut game = Log::new();
+        assert_eq!(
+            world.take_ai_turn(&mut game),
+            Some((monster, BehaviourStatus::Success))
+        );
+        assert_eq!(
+            world.take_ai_turn(&mut game),
+            Some((monster, BehaviourStatus::Success))
+        );
+        // Now it's the player's turn, at time 75
+        assert_eq!(world.take_ai_turn(&mut game), None);
+        assert_eq!(world.scheduler().peek(), Some((player, 75)));
+        assert_eq!(game, ["moved", "moved"]);
+        let brain = world.get::<Brain<Log>>(monster).unwrap();
+        assert_eq!(brain.blackboard.get::<u32>("turns"), Some(&2));
+    }
+}
@@ -0,0 +1,276 @@
+//
+// Pixel canvas
+//
+// A drawing surface with a finer resolution than the cells, for graphs,
+// mini-maps and smooth effects.  Pixels are set individually or with lines
+// and circles, then the canvas is drawn onto an image in one of two modes:
+//
+//      HalfBlock   2 pixels per cell, one above the other, each with its own
+//                  colour, using the cp437 half-block characters.
+//      Braille     8 pixels per cell in a 2x4 grid, sharing one colour.  The
+//                  character code is the braille dot pattern (code n is
+//                  U+2800 + n), so the image must be shown with a font whose
+//                  glyphs are the 256 braille patterns.  braille_char() gives
+//                  the Unicode character, e.g. for copying as text.
+//
+
+use crate::{Char, Grid, Image, Point};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelMode {
+    HalfBlock,
+    Braille,
+}
+
+impl PixelMode {
+    // Pixels per cell across and down
+    pub fn cell_size(&self) -> (u32, u32) {
+        match self {
+            PixelMode::HalfBlock => (1, 2),
+            PixelMode::Braille => (2, 4),
+        }
+    }
+}
+
+// cp437 codes of the half blocks
+const LOWER_HALF: u8 = 220;
+const UPPER_HALF: u8 = 223;
+
+// The bit of a braille pattern for each dot, by row then column
+const BRAILLE_DOTS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+pub fn braille_char(pattern: u8) -> char {
+    std::char::from_u32(0x2800 + pattern as u32).unwrap_or(' ')
+}
+
+pub struct PixelCanvas {
+    mode: PixelMode,
+    pixels: Grid<Option<u32>>,
+    paper: u32,
+}
+
+impl PixelCanvas {
+    // A canvas covering a number of cells
+    pub fn new(mode: PixelMode, width: u32, height: u32) -> Self {
+        let (cw, ch) = mode.cell_size();
+        PixelCanvas {
+            mode,
+            pixels: Grid::new(width * cw, height * ch, None),
+            paper: 0,
+        }
+    }
+
+    // The colour behind unset pixels
+    pub fn with_paper(&mut self, paper: u32) -> &mut Self {
+        self.paper = paper;
+        self
+    }
+
+    // Bytes used by the pixels
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() - std::mem::size_of::<Grid<Option<u32>>>()
+            + self.pixels.memory_size()
+    }
+
+    pub fn mode(&self) -> PixelMode {
+        self.mode
+    }
+
+    // The size in pixels
+    pub fn width(&self) -> u32 {
+        self.pixels.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.pixels.height()
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(None);
+    }
+
+    pub fn set_pixel(&mut self, p: Point, colour: u32) {
+        self.pixels.set(p, Some(colour));
+    }
+
+    pub fn unset_pixel(&mut self, p: Point) {
+        self.pixels.set(p, None);
+    }
+
+    pub fn pixel(&self, p: Point) -> Option<u32> {
+        self.pixels.get(p).copied().flatten()
+    }
+
+    pub fn line(&mut self, from: Point, to: Point, colour: u32) {
+        let (dx, dy) = ((to.x - from.x).abs(), -(to.y - from.y).abs());
+        let (sx, sy) = ((to.x - from.x).signum(), (to.y - from.y).signum());
+        let mut p = from;
+        let mut error = dx + dy;
+        loop {
+            self.set_pixel(p, colour);
+            if p == to {
+                break;
+            }
+            let e2 = error * 2;
+            if e2 >= dy {
+                error += dy;
+                p.x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                p.y += sy;
+            }
+        }
+    }
+
+    pub fn circle(&mut self, centre: Point, radius: u32, colour: u32) {
+        self.circle_spans(centre, radius, |canvas, y, left, right| {
+            canvas.set_pixel(Point::new(left, y), colour);
+            canvas.set_pixel(Point::new(right, y), colour);
+        });
+    }
+
+    pub fn fill_circle(&mut self, centre: Point, radius: u32, colour: u32) {
+        self.circle_spans(centre, radius, |canvas, y, left, right| {
+            (left..=right).for_each(|x| canvas.set_pixel(Point::new(x, y), colour));
+        });
+    }
+
+    // Calls f with the left and right ends of each row of a circle, using the
+    // midpoint algorithm.  Rows may be visited more than once.
+    fn circle_spans(
+        &mut self,
+        centre: Point,
+        radius: u32,
+        mut f: impl FnMut(&mut Self, i32, i32, i32),
+    ) {
+        let (mut x, mut y) = (radius as i32, 0);
+        let mut error = 1 - x;
+        while x >= y {
+            f(self, centre.y + y, centre.x - x, centre.x + x);
+            f(self, centre.y - y, centre.x - x, centre.x + x);
+            f(self, centre.y + x, centre.x - y, centre.x + y);
+            f(self, centre.y - x, centre.x - y, centre.x + y);
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    // Draws the canvas with its top-left cell at p.
+    pub fn draw(&self, image: &mut Image, p: Point) {
+        let (cw, ch) = self.mode.cell_size();
+        let (cw, ch) = (cw as i32, ch as i32);
+        for y in 0..self.height() as i32 / ch {
+            for x in 0..self.width() as i32 / cw {
+                let q = Point::new(p.x + x, p.y + y);
+                let cell = match self.mode {
+                    PixelMode::HalfBlock => {
+                        let top = self.pixel(Point::new(x, y * 2));
+                        let bottom = self.pixel(Point::new(x, y * 2 + 1));
+                        match (top, bottom) {
+                            (None, None) => Char::new(b' ', self.paper, self.paper),
+                            (Some(top), bottom) => {
+                                Char::new(UPPER_HALF, top, bottom.unwrap_or(self.paper))
+                            }
+                            (None, Some(bottom)) => Char::new(LOWER_HALF, bottom, self.paper),
+                        }
+                    }
+                    PixelMode::Braille => {
+                        let mut pattern = 0;
+                        let mut ink = self.paper;
+                        for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                            for (dx, bit) in row.iter().enumerate() {
+                                let pixel = Point::new(x * 2 + dx as i32, y * 4 + dy as i32);
+                                if let Some(colour) = self.pixel(pixel) {
+                                    pattern |= bit;
+                                    ink = colour;
+                                }
+                            }
+                        }
+                        Char::new(pattern, ink, self.paper)
+                    }
+                };
+                image.draw_char(q, cell);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_pixels(canvas: &PixelCanvas) -> Vec<(i32, i32)> {
+        let mut pixels = vec![];
+        for y in 0..canvas.height() as i32 {
+            for x in 0..canvas.width() as i32 {
+                if canvas.pixel(Point::new(x, y)).is_some() {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn lines_and_circles_set_pixels() {
+        let mut canvas = PixelCanvas::new(PixelMode::Braille, 3, 2);
+        assert_eq!((canvas.width(), canvas.height()), (6, 8));
+
+        canvas.line(Point::new(0, 0), Point::new(4, 2), 1);
+        assert_eq!(
+            set_pixels(&canvas),
+            [(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]
+        );
+
+        canvas.clear();
+        canvas.circle(Point::new(2, 2), 1, 1);
+        assert_eq!(set_pixels(&canvas), [(2, 1), (1, 2), (3, 2), (2, 3)]);
+        canvas.fill_circle(Point::new(2, 2), 1, 1);
+        assert_eq!(
+            set_pixels(&canvas),
+            [(2, 1), (1, 2), (2, 2), (3, 2), (2, 3)]
+        );
+        canvas.unset_pixel(Point::new(2, 2));
+        assert_eq!(canvas.pixel(Point::new(2, 2)), None);
+
+        // Off the canvas is ignored
+        canvas.set_pixel(Point::new(-1, 0), 1);
+        assert_eq!(canvas.pixel(Point::new(-1, 0)), None);
+    }
+
+    #[test]
+    fn half_blocks_give_each_pixel_its_own_colour() {
+        let mut canvas = PixelCanvas::new(PixelMode::HalfBlock, 3, 1);
+        canvas.with_paper(9);
+        canvas.set_pixel(Point::new(0, 0), 1);
+        canvas.set_pixel(Point::new(0, 1), 2);
+        canvas.set_pixel(Point::new(1, 1), 3);
+        let mut image = Image::new(3, 1);
+        canvas.draw(&mut image, Point::new(0, 0));
+        assert_eq!(
+            image.text_image,
+            [UPPER_HALF, LOWER_HALF, b' '].map(u32::from)
+        );
+        assert_eq!(image.fore_image, [1, 3, 9]);
+        assert_eq!(image.back_image, [2, 9, 9]);
+    }
+
+    #[test]
+    fn braille_cells_combine_eight_dots() {
+        let mut canvas = PixelCanvas::new(PixelMode::Braille, 2, 1);
+        canvas.set_pixel(Point::new(0, 0), 5);
+        canvas.set_pixel(Point::new(1, 3), 5);
+        let mut image = Image::new(3, 1);
+        image.clear(0, 0);
+        canvas.draw(&mut image, Point::new(1, 0));
+        assert_eq!(image.text_image, [b' ' as u32, 0x81, 0]);
+        assert_eq!(image.fore_image[1], 5);
+        assert_eq!(braille_char(0x81), '\u{2881}');
+    }
+}
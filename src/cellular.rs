@@ -0,0 +1,425 @@
+//
+// Fire, smoke and water
+//
+// A simple cellular simulation run once per turn over the map.  Each cell
+// holds an intensity from 0 to MAX_INTENSITY for each substance:
+//
+//      Fire    burns down over time, gives off smoke and spreads to flammable
+//              neighbours.  Water puts it out, turning to steam.
+//      Smoke   drifts to neighbouring cells and thins out.
+//      Water   flows from deeper cells to shallower ones and slowly
+//              evaporates.
+//
+// The game supplies which cells are passable and how flammable they are, and
+// draws the result over the map with colour ramps, usually onto an image
+// used as an effects layer.
+//
+
+use crate::{lerp_colour, new_colour, Char, GameRng, Grid, Image, Point};
+
+pub const MAX_INTENSITY: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Substance {
+    Fire,
+    Smoke,
+    Water,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimCell {
+    pub fire: u8,
+    pub smoke: u8,
+    pub water: u8,
+}
+
+impl SimCell {
+    pub fn get(&self, substance: Substance) -> u8 {
+        match substance {
+            Substance::Fire => self.fire,
+            Substance::Smoke => self.smoke,
+            Substance::Water => self.water,
+        }
+    }
+
+    fn get_mut(&mut self, substance: Substance) -> &mut u8 {
+        match substance {
+            Substance::Fire => &mut self.fire,
+            Substance::Smoke => &mut self.smoke,
+            Substance::Water => &mut self.water,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fire == 0 && self.smoke == 0 && self.water == 0
+    }
+}
+
+fn raise(level: &mut u8, amount: u8) {
+    *level = level.saturating_add(amount).min(MAX_INTENSITY);
+}
+
+//
+// Simulation
+//
+
+pub struct CellularSim {
+    cells: Grid<SimCell>,
+    next: Grid<SimCell>,
+    // Chance per turn of each cell of water losing a level
+    evaporation: f64,
+}
+
+impl CellularSim {
+    pub fn new(width: u32, height: u32) -> Self {
+        CellularSim {
+            cells: Grid::new(width, height, SimCell::default()),
+            next: Grid::new(width, height, SimCell::default()),
+            evaporation: 0.02,
+        }
+    }
+
+    pub fn with_evaporation(&mut self, chance: f64) -> &mut Self {
+        self.evaporation = chance;
+        self
+    }
+
+    pub fn grid(&self) -> &Grid<SimCell> {
+        &self.cells
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.fill(SimCell::default());
+    }
+
+    pub fn intensity(&self, p: Point, substance: Substance) -> u8 {
+        self.cells.get(p).map_or(0, |cell| cell.get(substance))
+    }
+
+    pub fn add(&mut self, p: Point, substance: Substance, amount: u8) {
+        if let Some(cell) = self.cells.get_mut(p) {
+            raise(cell.get_mut(substance), amount);
+        }
+    }
+
+    pub fn remove(&mut self, p: Point, substance: Substance) {
+        if let Some(cell) = self.cells.get_mut(p) {
+            *cell.get_mut(substance) = 0;
+        }
+    }
+
+    // Puts out fire within a radius, e.g. for a spell or a bucket of water.
+    pub fn extinguish(&mut self, centre: Point, radius: u32) {
+        let r = radius as i64;
+        let points = self
+            .cells
+            .iter()
+            .filter(|(p, cell)| {
+                let (dx, dy) = ((p.x - centre.x) as i64, (p.y - centre.y) as i64);
+                cell.fire > 0 && dx * dx + dy * dy <= r * r
+            })
+            .map(|(p, _)| p)
+            .collect::<Vec<_>>();
+        for p in points {
+            self.remove(p, Substance::Fire);
+            self.add(p, Substance::Smoke, 1);
+        }
+    }
+
+    // Advances the simulation by one turn.  `flammability` gives how readily
+    // a cell catches fire, from 0 (stone) to MAX_INTENSITY (dry grass).
+    pub fn step(
+        &mut self,
+        rng: &mut GameRng,
+        passable: impl Fn(Point) -> bool,
+        flammability: impl Fn(Point) -> u8,
+    ) {
+        self.next.cells_mut().copy_from_slice(self.cells.cells());
+
+        for i in 0..self.cells.cells().len() {
+            let p = self.cells.point(i);
+            let cell = self.cells.cells()[i];
+            if !passable(p) {
+                self.next.cells_mut()[i] = SimCell::default();
+                continue;
+            }
+            if cell.is_empty() {
+                continue;
+            }
+            let neighbours = self
+                .cells
+                .neighbours(p)
+                .take(4)
+                .filter(|&q| passable(q))
+                .collect::<Vec<_>>();
+
+            // Fire
+            if cell.fire > 0 {
+                let next = &mut self.next.cells_mut()[i];
+                if cell.water > 0 {
+                    next.fire = 0;
+                    next.water -= 1;
+                    raise(&mut next.smoke, 2);
+                } else {
+                    let burn = if flammability(p) > 0 { 1 } else { 2 };
+                    next.fire = next.fire.saturating_sub(burn);
+                    raise(&mut next.smoke, 1);
+                    for &q in &neighbours {
+                        let fuel = flammability(q).min(MAX_INTENSITY);
+                        let wet = self.cells.get(q).is_some_and(|c| c.water > 0);
+                        let chance = fuel as u64 * cell.fire as u64;
+                        let max = MAX_INTENSITY as u64 * MAX_INTENSITY as u64;
+                        if fuel > 0 && !wet && rng.below(max) < chance {
+                            if let Some(target) = self.next.get_mut(q) {
+                                target.fire = target.fire.max(fuel);
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Smoke drifts to a random neighbour with less smoke and thins
+            if cell.smoke > 0 {
+                let thinner = neighbours
+                    .iter()
+                    .copied()
+                    .filter(|&q| self.cells.get(q).is_some_and(|c| c.smoke < cell.smoke))
+                    .collect::<Vec<_>>();
+                if let Some(&q) = rng.choose(&thinner) {
+                    if let Some(target) = self.next.get_mut(q) {
+                        raise(&mut target.smoke, 1);
+                        self.next.cells_mut()[i].smoke -= 1;
+                    }
+                }
+                if rng.chance(1.0 / 3.0) {
+                    let next = &mut self.next.cells_mut()[i];
+                    next.smoke = next.smoke.saturating_sub(1);
+                }
+            }
+
+            // Water levels out and evaporates
+            if cell.water > 0 {
+                for &q in &neighbours {
+                    let lower = self.cells.get(q).is_some_and(|c| c.water + 1 < cell.water);
+                    if lower && self.next.cells()[i].water > 0 {
+                        if let Some(target) = self.next.get_mut(q) {
+                            raise(&mut target.water, 1);
+                            self.next.cells_mut()[i].water -= 1;
+                        }
+                    }
+                }
+                if rng.chance(self.evaporation) {
+                    let next = &mut self.next.cells_mut()[i];
+                    next.water = next.water.saturating_sub(1);
+                }
+            }
+        }
+
+        std::mem::swap(&mut self.cells, &mut self.next);
+    }
+
+    //
+    // Drawing
+    //
+
+    // Draws every non-empty cell with the map's top-left corner at origin.
+    // Substances with a glyph replace the character and ink; the others only
+    // tint the paper so the map shows through.  Fire is drawn over water and
+    // water over smoke.
+    pub fn draw(&self, image: &mut Image, origin: Point, style: &CellularStyle) {
+        let order = [Substance::Fire, Substance::Water, Substance::Smoke];
+        for (p, cell) in self.cells.iter() {
+            let substance = match order.iter().find(|&&s| cell.get(s) > 0) {
+                Some(&substance) => substance,
+                None => continue,
+            };
+            let level = cell.get(substance) as f32 / MAX_INTENSITY as f32;
+            let (ramp, glyph) = style.get(substance);
+            let colour = ramp.at(level);
+            let q = Point::new(origin.x + p.x, origin.y + p.y);
+            let i = match (q.x >= 0 && q.y >= 0)
+                .then(|| image.coords_to_index(q.x as u32, q.y as u32))
+                .flatten()
+            {
+                Some(i) => i,
+                None => continue,
+            };
+            match glyph {
+                Some(ch) => {
+                    let paper = image.back_image[i];
+                    image.draw_char(q, Char::new(ch, colour, paper));
+                }
+                None => image.back_image[i] = colour,
+            }
+        }
+    }
+}
+
+//
+// Colour ramps
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColourRamp {
+    stops: Vec<u32>,
+}
+
+impl ColourRamp {
+    // Colours evenly spaced from low to high intensity
+    pub fn new(stops: &[u32]) -> Self {
+        ColourRamp {
+            stops: stops.to_vec(),
+        }
+    }
+
+    // The colour at t, from 0 to 1
+    pub fn at(&self, t: f32) -> u32 {
+        match self.stops.len() {
+            0 => 0,
+            1 => self.stops[0],
+            n => {
+                let t = t.clamp(0.0, 1.0) * (n - 1) as f32;
+                let i = (t as usize).min(n - 2);
+                lerp_colour(self.stops[i], self.stops[i + 1], t - i as f32)
+            }
+        }
+    }
+}
+
+pub struct CellularStyle {
+    pub fire: (ColourRamp, Option<u8>),
+    pub smoke: (ColourRamp, Option<u8>),
+    pub water: (ColourRamp, Option<u8>),
+}
+
+impl CellularStyle {
+    pub fn get(&self, substance: Substance) -> (&ColourRamp, Option<u8>) {
+        let (ramp, glyph) = match substance {
+            Substance::Fire => &self.fire,
+            Substance::Smoke => &self.smoke,
+            Substance::Water => &self.water,
+        };
+        (ramp, *glyph)
+    }
+}
+
+impl Default for CellularStyle {
+    fn default() -> Self {
+        CellularStyle {
+            fire: (
+                ColourRamp::new(&[
+                    new_colour(128, 0, 0),
+                    new_colour(255, 96, 0),
+                    new_colour(255, 255, 128),
+                ]),
+                Some(b'^'),
+            ),
+            smoke: (
+                ColourRamp::new(&[new_colour(24, 24, 24), new_colour(96, 96, 96)]),
+                None,
+            ),
+            water: (
+                ColourRamp::new(&[new_colour(0, 32, 96), new_colour(0, 64, 192)]),
+                None,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(sim: &CellularSim, x: i32) -> SimCell {
+        *sim.grid().get(Point::new(x, 0)).unwrap()
+    }
+
+    #[test]
+    fn fire_burns_down_and_water_puts_it_out() {
+        let mut rng = GameRng::new(0);
+        let mut sim = CellularSim::new(2, 1);
+        sim.with_evaporation(0.0);
+        sim.add(Point::new(0, 0), Substance::Fire, 5);
+        sim.add(Point::new(1, 0), Substance::Fire, 5);
+        sim.add(Point::new(1, 0), Substance::Water, 3);
+        // Fire on stone burns out twice as fast
+        sim.step(&mut rng, |_| true, |_| 0);
+        assert_eq!(cell(&sim, 0).fire, 3);
+        assert_eq!(
+            cell(&sim, 1),
+            SimCell {
+                fire: 0,
+                smoke: 2,
+                water: 1
+            }
+        );
+        // The rest of the water ran next door
+        assert_eq!(cell(&sim, 0).water, 1);
+    }
+
+    #[test]
+    fn fire_spreads_to_fuel_but_not_through_walls() {
+        let mut rng = GameRng::new(0);
+        let mut sim = CellularSim::new(4, 1);
+        sim.add(Point::new(0, 0), Substance::Fire, 20);
+        assert_eq!(
+            sim.intensity(Point::new(0, 0), Substance::Fire),
+            MAX_INTENSITY
+        );
+        // The hottest fire in the driest fuel always spreads
+        sim.step(&mut rng, |p| p.x != 2, |_| MAX_INTENSITY);
+        assert_eq!(cell(&sim, 0).fire, 9);
+        assert_eq!(cell(&sim, 1).fire, MAX_INTENSITY);
+        assert!(cell(&sim, 2).is_empty());
+
+        sim.extinguish(Point::new(0, 0), 1);
+        assert_eq!(cell(&sim, 0).fire, 0);
+        assert_eq!(cell(&sim, 1).fire, 0);
+    }
+
+    #[test]
+    fn water_flows_to_shallower_cells() {
+        let mut rng = GameRng::new(0);
+        let mut sim = CellularSim::new(3, 1);
+        sim.with_evaporation(0.0);
+        sim.add(Point::new(1, 0), Substance::Water, 5);
+        sim.step(&mut rng, |_| true, |_| 0);
+        let water = (0..3).map(|x| cell(&sim, x).water).collect::<Vec<_>>();
+        assert_eq!(water, [1, 3, 1]);
+
+        sim.with_evaporation(1.0);
+        sim.step(&mut rng, |_| true, |_| 0);
+        sim.step(&mut rng, |_| true, |_| 0);
+        assert_eq!(sim.grid().cells().iter().map(|c| c.water).sum::<u8>(), 0);
+    }
+
+    #[test]
+    fn ramps_blend_between_their_stops() {
+        let ramp = ColourRamp::new(&[0x00, 0x80, 0xff]);
+        assert_eq!(ramp.at(0.0), 0x00);
+        assert_eq!(ramp.at(0.25), 0x40);
+        assert_eq!(ramp.at(1.0), 0xff);
+        assert_eq!(ramp.at(2.0), 0xff);
+        assert_eq!(ColourRamp::new(&[]).at(0.5), 0);
+        assert_eq!(ColourRamp::new(&[7]).at(0.5), 7);
+    }
+
+    #[test]
+    fn fire_is_drawn_as_glyphs_and_water_tints_the_paper() {
+        let mut sim = CellularSim::new(3, 1);
+        sim.add(Point::new(0, 0), Substance::Fire, MAX_INTENSITY);
+        sim.add(Point::new(1, 0), Substance::Water, MAX_INTENSITY);
+        let style = CellularStyle::default();
+        let mut image = Image::new(3, 1);
+        image.clear(1, 2);
+        sim.draw(&mut image, Point::new(0, 0), &style);
+        assert_eq!(image.text_image[0], b'^' as u32);
+        assert_eq!(image.fore_image[0], new_colour(255, 255, 128));
+        assert_eq!(image.back_image[1], new_colour(0, 64, 192));
+        assert_eq!(image.back_image[2], 2);
+
+        // Cells off the image are skipped
+        sim.draw(&mut image, Point::new(-1, 0), &style);
+        assert_eq!(image.back_image[0], new_colour(0, 64, 192));
+    }
+}
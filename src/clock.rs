@@ -0,0 +1,204 @@
+//
+// World clock
+//
+// Keeps the time of day for surface levels with day and night.  The ambient
+// light colour follows the time of day through a list of keyframes, and the
+// engine tints the regions of the screen showing the map with it after the
+// game has presented, so the UI stays untouched.
+//
+// The clock runs in real time when given a day length.  Turn-based games
+// usually leave it stopped and call advance() as game time passes.
+//
+
+use crate::{lerp_colour, new_colour, Image, Point, Rect};
+use std::time::Duration;
+
+pub struct WorldClock {
+    // Days since the start, the fraction being the time of day (0 = midnight)
+    time: f64,
+    day_length: Option<Duration>,
+    // (time of day, colour), in order of time
+    keyframes: Vec<(f32, u32)>,
+    regions: Vec<Rect>,
+}
+
+impl WorldClock {
+    pub fn new() -> Self {
+        let night = new_colour(64, 64, 128);
+        let dawn = new_colour(255, 176, 128);
+        let day = new_colour(255, 255, 255);
+        let dusk = new_colour(224, 128, 112);
+        WorldClock {
+            time: 0.5,
+            day_length: None,
+            keyframes: vec![
+                (0.0, night),
+                (0.2, night),
+                (0.25, dawn),
+                (0.32, day),
+                (0.7, day),
+                (0.77, dusk),
+                (0.83, night),
+                (1.0, night),
+            ],
+            regions: Vec::new(),
+        }
+    }
+
+    // Runs the clock in real time, taking this long for a whole day.  None
+    // stops it.
+    pub fn set_day_length(&mut self, day_length: Option<Duration>) {
+        self.day_length = day_length.filter(|d| !d.is_zero());
+    }
+
+    // Moves time on by a number of days, e.g. 1.0 / 1440.0 for a minute.
+    pub fn advance(&mut self, days: f64) {
+        self.time = (self.time + days).max(0.0);
+    }
+
+    pub fn day(&self) -> u64 {
+        self.time as u64
+    }
+
+    // From 0 (midnight) to 1
+    pub fn time_of_day(&self) -> f32 {
+        self.time.fract() as f32
+    }
+
+    pub fn set_time_of_day(&mut self, time_of_day: f32) {
+        self.time = self.time.trunc() + time_of_day.rem_euclid(1.0) as f64;
+    }
+
+    // The time of day on a 24 hour clock
+    pub fn hours_minutes(&self) -> (u32, u32) {
+        let minutes = (self.time_of_day() * 1440.0) as u32;
+        (minutes / 60, minutes % 60)
+    }
+
+    // Replaces the ambient colours.  Times run from 0 to 1 and should include
+    // both ends so the colour wraps smoothly at midnight.
+    pub fn set_keyframes(&mut self, keyframes: &[(f32, u32)]) {
+        self.keyframes = keyframes.to_vec();
+        self.keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+
+    pub fn ambient(&self) -> u32 {
+        let t = self.time_of_day();
+        let white = new_colour(255, 255, 255);
+        let after = self.keyframes.iter().position(|&(time, _)| time >= t);
+        match after {
+            None => self.keyframes.last().map_or(white, |&(_, c)| c),
+            Some(0) => self.keyframes[0].1,
+            Some(i) => {
+                let (t0, c0) = self.keyframes[i - 1];
+                let (t1, c1) = self.keyframes[i];
+                let span = t1 - t0;
+                if span > 0.0 {
+                    lerp_colour(c0, c1, (t - t0) / span)
+                } else {
+                    c1
+                }
+            }
+        }
+    }
+
+    //
+    // Tinted regions
+    //
+
+    // Marks part of the main window (usually the map view) to be lit by the
+    // ambient colour.
+    pub fn tint_region(&mut self, rect: Rect) {
+        self.regions.push(rect);
+    }
+
+    pub fn clear_regions(&mut self) {
+        self.regions.clear();
+    }
+
+    pub(crate) fn update(&mut self, dt: Duration) {
+        if let Some(day_length) = self.day_length {
+            self.advance(dt.as_secs_f64() / day_length.as_secs_f64());
+        }
+    }
+
+    pub(crate) fn apply(&self, image: &mut Image) {
+        if self.regions.is_empty() {
+            return;
+        }
+        let ambient = self.ambient();
+        for rect in &self.regions {
+            image.tint(Point::new(rect.x, rect.y), rect.width, rect.height, ambient);
+        }
+    }
+}
+
+impl Default for WorldClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tint_colour;
+
+    #[test]
+    fn time_moves_on_by_days() {
+        let mut clock = WorldClock::new();
+        assert_eq!(clock.hours_minutes(), (12, 0));
+        clock.advance(0.75 + 1.0 / 1440.0);
+        assert_eq!(clock.day(), 1);
+        assert_eq!(clock.hours_minutes(), (6, 1));
+        clock.set_time_of_day(-0.25);
+        assert_eq!((clock.day(), clock.hours_minutes()), (1, (18, 0)));
+        clock.advance(-5.0);
+        assert_eq!((clock.day(), clock.time_of_day()), (0, 0.0));
+    }
+
+    #[test]
+    fn real_time_clocks_follow_the_day_length() {
+        let mut clock = WorldClock::new();
+        clock.update(Duration::from_secs(60));
+        assert_eq!(clock.hours_minutes(), (12, 0));
+        clock.set_day_length(Some(Duration::from_secs(240)));
+        clock.update(Duration::from_secs(60));
+        assert_eq!(clock.hours_minutes(), (18, 0));
+        clock.set_day_length(Some(Duration::ZERO));
+        clock.update(Duration::from_secs(60));
+        assert_eq!(clock.hours_minutes(), (18, 0));
+    }
+
+    #[test]
+    fn the_ambient_colour_blends_between_keyframes() {
+        let mut clock = WorldClock::new();
+        clock.set_keyframes(&[(1.0, 0xff), (0.5, 0x80), (0.0, 0x00)]);
+        clock.set_time_of_day(0.25);
+        assert_eq!(clock.ambient(), 0x40);
+        clock.set_time_of_day(0.5);
+        assert_eq!(clock.ambient(), 0x80);
+        clock.set_keyframes(&[(0.2, 0x10), (0.4, 0x20)]);
+        clock.set_time_of_day(0.1);
+        assert_eq!(clock.ambient(), 0x10);
+        clock.set_time_of_day(0.9);
+        assert_eq!(clock.ambient(), 0x20);
+    }
+
+    #[test]
+    fn only_the_regions_are_tinted() {
+        let mut clock = WorldClock::new();
+        clock.set_time_of_day(0.0);
+        let mut image = Image::new(4, 1);
+        let white = new_colour(255, 255, 255);
+        image.clear(white, white);
+        clock.apply(&mut image);
+        assert_eq!(image.fore_image, [white; 4]);
+
+        clock.tint_region(Rect::new(1, 0, 2, 1));
+        clock.apply(&mut image);
+        let night = tint_colour(white, new_colour(64, 64, 128));
+        assert_eq!(image.fore_image, [white, night, night, white]);
+        assert_eq!(image.back_image[1], night);
+    }
+}
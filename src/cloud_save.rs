@@ -0,0 +1,562 @@
+//
+// Save syncing
+//
+// Copies save slots between the game's local save folder and a sync backend,
+// such as a folder shared through a cloud drive, so a player can carry on a
+// run on another machine.  Backends implement SyncBackend; FolderBackend is
+// the one provided, and store APIs such as Steam Cloud can be added the same
+// way.
+//
+//      let local = FolderBackend::new(&save_dir);
+//      let remote = FolderBackend::new(&cloud_dir);
+//      let mut sync = SaveSync::new(local, Box::new(remote));
+//      sync.local_mut().write_slot("slot1", turn, &saves.encode(&data))?;
+//      sync.sync_all(|conflict| ask_player(conflict))?;
+//
+// Each slot carries the time it was written, the game's turn count and a
+// hash of its data.  The state of each slot at the last sync is remembered in
+// the local folder, so a slot changed on one side only is copied to the
+// other, and a slot changed on both sides is a conflict the game resolves,
+// e.g. by asking the player which to keep.  Without a previous sync, the side
+// that is later in both time and turns wins, and anything else is a conflict.
+//
+
+use crate::{history::now, save::write_save_file, stable_hash, RogueError, RogueResult};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+//
+// Slots
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveSlotInfo {
+    pub name: String,
+    // Seconds since the Unix epoch
+    pub modified: u64,
+    pub turn: u64,
+    // stable_hash() of the data
+    pub hash: u64,
+}
+
+impl SaveSlotInfo {
+    // Written as "modified turn hash"
+    fn to_line(&self) -> String {
+        format!("{} {} {:016x}", self.modified, self.turn, self.hash)
+    }
+
+    fn from_line(name: &str, line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        Some(SaveSlotInfo {
+            name: String::from(name),
+            modified: fields.next()?.parse().ok()?,
+            turn: fields.next()?.parse().ok()?,
+            hash: u64::from_str_radix(fields.next()?, 16).ok()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveSlot {
+    pub info: SaveSlotInfo,
+    pub data: Vec<u8>,
+}
+
+impl SaveSlot {
+    // A slot written now
+    pub fn new(name: &str, turn: u64, data: &[u8]) -> Self {
+        SaveSlot {
+            info: SaveSlotInfo {
+                name: String::from(name),
+                modified: now(),
+                turn,
+                hash: stable_hash(data),
+            },
+            data: data.to_vec(),
+        }
+    }
+}
+
+//
+// Backends
+//
+
+pub trait SyncBackend {
+    // A name for the backend, used in errors and to keep the sync state of
+    // different backends apart.  It should be usable in a file name.
+    fn name(&self) -> &str;
+
+    fn slots(&self) -> RogueResult<Vec<SaveSlotInfo>>;
+
+    // None if there is no such slot
+    fn read(&self, name: &str) -> RogueResult<Option<SaveSlot>>;
+
+    fn write(&mut self, slot: &SaveSlot) -> RogueResult<()>;
+}
+
+// Slots kept as files in a folder: name.sav holds the data and name.meta the
+// SaveSlotInfo.  The data is written first, so a slot is only listed once
+// both are in place.
+pub struct FolderBackend {
+    dir: PathBuf,
+    name: String,
+}
+
+impl FolderBackend {
+    pub fn new(dir: &Path) -> Self {
+        FolderBackend {
+            dir: dir.to_path_buf(),
+            name: String::from("folder"),
+        }
+    }
+
+    pub fn with_name(&mut self, name: &str) -> &mut Self {
+        self.name = String::from(name);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn write_slot(&mut self, name: &str, turn: u64, data: &[u8]) -> RogueResult<()> {
+        self.write(&SaveSlot::new(name, turn, data))
+    }
+
+    pub fn read_slot(&self, name: &str) -> RogueResult<Option<Vec<u8>>> {
+        Ok(self.read(name)?.map(|slot| slot.data))
+    }
+
+    fn file(&self, name: &str, extension: &str) -> PathBuf {
+        let name = name.replace(['/', '\\', ':'], "_");
+        self.dir.join(format!("{}.{}", name, extension))
+    }
+
+    fn read_info(&self, name: &str) -> RogueResult<Option<SaveSlotInfo>> {
+        let path = self.file(name, "meta");
+        match fs::read_to_string(&path) {
+            Ok(text) => SaveSlotInfo::from_line(name, &text)
+                .map(Some)
+                .ok_or_else(|| RogueError::BadConfig {
+                    file: path.display().to_string(),
+                    line: 1,
+                    message: String::from("not a save slot's details"),
+                }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl SyncBackend for FolderBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn slots(&self) -> RogueResult<Vec<SaveSlotInfo>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut slots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "meta") {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    slots.extend(self.read_info(name)?);
+                }
+            }
+        }
+        slots.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(slots)
+    }
+
+    fn read(&self, name: &str) -> RogueResult<Option<SaveSlot>> {
+        let info = match self.read_info(name)? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let data = fs::read(self.file(name, "sav"))?;
+        Ok(Some(SaveSlot { info, data }))
+    }
+
+    fn write(&mut self, slot: &SaveSlot) -> RogueResult<()> {
+        let name = &slot.info.name;
+        write_save_file(&self.file(name, "sav"), &slot.data)?;
+        write_save_file(&self.file(name, "meta"), slot.info.to_line().as_bytes())
+    }
+}
+
+//
+// Syncing
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncConflict {
+    pub slot: String,
+    pub local: SaveSlotInfo,
+    pub remote: SaveSlotInfo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictResolution {
+    KeepLocal,
+    KeepRemote,
+    // Leave both as they are and ask again next sync
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncAction {
+    Unchanged,
+    Pushed,
+    Pulled,
+    Skipped,
+}
+
+pub struct SaveSync {
+    local: FolderBackend,
+    remote: Box<dyn SyncBackend>,
+    // Each slot as it was on both sides after its last sync
+    synced: HashMap<String, SaveSlotInfo>,
+}
+
+impl SaveSync {
+    pub fn new(local: FolderBackend, remote: Box<dyn SyncBackend>) -> Self {
+        let mut sync = SaveSync {
+            local,
+            remote,
+            synced: HashMap::new(),
+        };
+        if let Ok(text) = fs::read_to_string(sync.state_path()) {
+            sync.synced = text
+                .lines()
+                .filter_map(|line| {
+                    let (name, info) = line.split_once('\t')?;
+                    Some((String::from(name), SaveSlotInfo::from_line(name, info)?))
+                })
+                .collect();
+        }
+        sync
+    }
+
+    pub fn local(&self) -> &FolderBackend {
+        &self.local
+    }
+
+    pub fn local_mut(&mut self) -> &mut FolderBackend {
+        &mut self.local
+    }
+
+    pub fn remote(&self) -> &dyn SyncBackend {
+        self.remote.as_ref()
+    }
+
+    // Copies the local slot to the backend.
+    pub fn push(&mut self, name: &str) -> RogueResult<()> {
+        let slot = self
+            .local
+            .read(name)?
+            .ok_or_else(|| missing_slot(name, "the local saves"))?;
+        self.remote.write(&slot)?;
+        self.set_synced(slot.info)
+    }
+
+    // Copies the backend's slot to the local folder.
+    pub fn pull(&mut self, name: &str) -> RogueResult<()> {
+        let slot = self
+            .remote
+            .read(name)?
+            .ok_or_else(|| missing_slot(name, self.remote.name()))?;
+        self.local.write(&slot)?;
+        self.set_synced(slot.info)
+    }
+
+    // Brings a slot up to date on both sides, asking resolve() which to keep
+    // if both have changed.
+    pub fn sync_slot<F>(&mut self, name: &str, mut resolve: F) -> RogueResult<SyncAction>
+    where
+        F: FnMut(&SyncConflict) -> ConflictResolution,
+    {
+        let local = self.local.read_info(name)?;
+        let remote = self.remote.read(name)?.map(|slot| slot.info);
+        let (local, remote) = match (local, remote) {
+            (None, None) => return Ok(SyncAction::Unchanged),
+            (Some(_), None) => return self.push(name).map(|_| SyncAction::Pushed),
+            (None, Some(_)) => return self.pull(name).map(|_| SyncAction::Pulled),
+            (Some(local), Some(remote)) => (local, remote),
+        };
+        if local.hash == remote.hash {
+            if self.synced.get(name) != Some(&local) {
+                self.set_synced(local)?;
+            }
+            return Ok(SyncAction::Unchanged);
+        }
+
+        let action = match self.synced.get(name).map(|synced| synced.hash) {
+            Some(synced) if synced == remote.hash => SyncAction::Pushed,
+            Some(synced) if synced == local.hash => SyncAction::Pulled,
+            Some(_) => SyncAction::Skipped,
+            None if local.modified >= remote.modified && local.turn >= remote.turn => {
+                SyncAction::Pushed
+            }
+            None if remote.modified >= local.modified && remote.turn >= local.turn => {
+                SyncAction::Pulled
+            }
+            None => SyncAction::Skipped,
+        };
+        let action = match action {
+            SyncAction::Skipped => {
+                let conflict = SyncConflict {
+                    slot: String::from(name),
+                    local,
+                    remote,
+                };
+                match resolve(&conflict) {
+                    ConflictResolution::KeepLocal => SyncAction::Pushed,
+                    ConflictResolution::KeepRemote => SyncAction::Pulled,
+                    ConflictResolution::Skip => SyncAction::Skipped,
+                }
+            }
+            action => action,
+        };
+        match action {
+            SyncAction::Pushed => self.push(name)?,
+            SyncAction::Pulled => self.pull(name)?,
+            _ => {}
+        }
+        Ok(action)
+    }
+
+    // Syncs every slot on either side.
+    pub fn sync_all<F>(&mut self, mut resolve: F) -> RogueResult<Vec<(String, SyncAction)>>
+    where
+        F: FnMut(&SyncConflict) -> ConflictResolution,
+    {
+        let mut names = self
+            .local
+            .slots()?
+            .into_iter()
+            .chain(self.remote.slots()?)
+            .map(|info| info.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| {
+                let action = self.sync_slot(&name, &mut resolve)?;
+                Ok((name, action))
+            })
+            .collect()
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.local
+            .dir
+            .join(format!("sync-{}.txt", self.remote.name()))
+    }
+
+    fn set_synced(&mut self, info: SaveSlotInfo) -> RogueResult<()> {
+        self.synced.insert(info.name.clone(), info);
+        let mut names = self.synced.keys().collect::<Vec<_>>();
+        names.sort();
+        let text = names
+            .into_iter()
+            .map(|name| format!("{}\t{}\n", name, self.synced[name].to_line()))
+            .collect::<String>();
+        write_save_file(&self.state_path(), text.as_bytes())
+    }
+}
+
+fn missing_slot(name: &str, location: &str) -> RogueError {
+    RogueError::SlotNotFound {
+        slot: String::from(name),
+        location: String::from(location),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    // A backend the test can still change after handing it to the SaveSync
+    #[derive(Clone, Default)]
+    struct MemoryBackend {
+        slots: Rc<RefCell<HashMap<String, SaveSlot>>>,
+    }
+
+    impl SyncBackend for MemoryBackend {
+        fn name(&self) -> &str {
+            "memory"
+        }
+
+        fn slots(&self) -> RogueResult<Vec<SaveSlotInfo>> {
+            Ok(self
+                .slots
+                .borrow()
+                .values()
+                .map(|slot| slot.info.clone())
+                .collect())
+        }
+
+        fn read(&self, name: &str) -> RogueResult<Option<SaveSlot>> {
+            Ok(self.slots.borrow().get(name).cloned())
+        }
+
+        fn write(&mut self, slot: &SaveSlot) -> RogueResult<()> {
+            self.slots
+                .borrow_mut()
+                .insert(slot.info.name.clone(), slot.clone());
+            Ok(())
+        }
+    }
+
+    fn slot(modified: u64, turn: u64, data: &[u8]) -> SaveSlot {
+        SaveSlot {
+            info: SaveSlotInfo {
+                name: String::from("slot"),
+                modified,
+                turn,
+                hash: stable_hash(data),
+            },
+            data: data.to_vec(),
+        }
+    }
+
+    // Runs the test with a SaveSync between a fresh folder and memory
+    fn with_sync(test: &str, f: impl FnOnce(&Path, MemoryBackend)) {
+        let dir = std::env::temp_dir().join(format!("mage-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        f(&dir, MemoryBackend::default());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn new_sync(dir: &Path, remote: &MemoryBackend) -> SaveSync {
+        SaveSync::new(FolderBackend::new(dir), Box::new(remote.clone()))
+    }
+
+    fn never(_: &SyncConflict) -> ConflictResolution {
+        panic!("there should be no conflict")
+    }
+
+    fn data(sync: &SaveSync, remote: &MemoryBackend) -> (Vec<u8>, Vec<u8>) {
+        let local = sync.local().read_slot("slot").unwrap().unwrap();
+        (local, remote.slots.borrow()["slot"].data.clone())
+    }
+
+    #[test]
+    fn slots_on_one_side_are_copied() {
+        with_sync("sync-copy", |dir, mut remote| {
+            let mut sync = new_sync(dir, &remote);
+            sync.local_mut().write_slot("mine", 1, b"local").unwrap();
+            remote.write(&slot(5, 5, b"remote")).unwrap();
+            let actions = sync.sync_all(never).unwrap();
+            assert_eq!(
+                actions,
+                vec![
+                    (String::from("mine"), SyncAction::Pushed),
+                    (String::from("slot"), SyncAction::Pulled),
+                ]
+            );
+            assert_eq!(remote.slots.borrow()["mine"].data, b"local");
+            assert_eq!(sync.local().read_slot("slot").unwrap().unwrap(), b"remote");
+            assert_eq!(sync.sync_all(never).unwrap()[1].1, SyncAction::Unchanged);
+        });
+    }
+
+    #[test]
+    fn changes_since_the_last_sync_are_copied() {
+        with_sync("sync-changes", |dir, mut remote| {
+            let mut sync = new_sync(dir, &remote);
+            sync.local_mut().write(&slot(10, 10, b"one")).unwrap();
+            sync.push("slot").unwrap();
+
+            // Older in both time and turns, but only changed remotely
+            remote.write(&slot(1, 1, b"two")).unwrap();
+            assert_eq!(sync.sync_slot("slot", never).unwrap(), SyncAction::Pulled);
+            assert_eq!(data(&sync, &remote), (b"two".to_vec(), b"two".to_vec()));
+
+            // The sync state is kept in the local folder
+            let mut sync = new_sync(dir, &remote);
+            sync.local_mut().write(&slot(0, 0, b"three")).unwrap();
+            assert_eq!(sync.sync_slot("slot", never).unwrap(), SyncAction::Pushed);
+            assert_eq!(data(&sync, &remote), (b"three".to_vec(), b"three".to_vec()));
+        });
+    }
+
+    #[test]
+    fn changes_on_both_sides_conflict() {
+        with_sync("sync-conflict", |dir, mut remote| {
+            let mut sync = new_sync(dir, &remote);
+            sync.local_mut().write(&slot(1, 1, b"base")).unwrap();
+            sync.push("slot").unwrap();
+            sync.local_mut().write(&slot(3, 3, b"local")).unwrap();
+            remote.write(&slot(2, 2, b"remote")).unwrap();
+
+            let mut conflicts = Vec::new();
+            let mut skip = |conflict: &SyncConflict| {
+                conflicts.push(conflict.clone());
+                ConflictResolution::Skip
+            };
+            assert_eq!(
+                sync.sync_slot("slot", &mut skip).unwrap(),
+                SyncAction::Skipped
+            );
+            assert_eq!(
+                sync.sync_slot("slot", &mut skip).unwrap(),
+                SyncAction::Skipped
+            );
+            assert_eq!(conflicts.len(), 2);
+            assert_eq!(conflicts[0].slot, "slot");
+            assert_eq!((conflicts[0].local.turn, conflicts[0].remote.turn), (3, 2));
+            assert_eq!(
+                data(&sync, &remote),
+                (b"local".to_vec(), b"remote".to_vec())
+            );
+
+            let keep_remote = |_: &SyncConflict| ConflictResolution::KeepRemote;
+            assert_eq!(
+                sync.sync_slot("slot", keep_remote).unwrap(),
+                SyncAction::Pulled
+            );
+            assert_eq!(
+                data(&sync, &remote),
+                (b"remote".to_vec(), b"remote".to_vec())
+            );
+        });
+    }
+
+    #[test]
+    fn without_a_sync_the_later_slot_wins() {
+        with_sync("sync-first", |dir, mut remote| {
+            let mut sync = new_sync(dir, &remote);
+            sync.local_mut().write(&slot(5, 5, b"local")).unwrap();
+            remote.write(&slot(4, 5, b"remote")).unwrap();
+            assert_eq!(sync.sync_slot("slot", never).unwrap(), SyncAction::Pushed);
+        });
+        with_sync("sync-first-remote", |dir, mut remote| {
+            let mut sync = new_sync(dir, &remote);
+            sync.local_mut().write(&slot(5, 5, b"local")).unwrap();
+            remote.write(&slot(6, 7, b"remote")).unwrap();
+            assert_eq!(sync.sync_slot("slot", never).unwrap(), SyncAction::Pulled);
+        });
+        // Later but with fewer turns, so it isn't clear which to keep
+        with_sync("sync-first-unclear", |dir, mut remote| {
+            let mut sync = new_sync(dir, &remote);
+            sync.local_mut().write(&slot(9, 1, b"local")).unwrap();
+            remote.write(&slot(2, 8, b"remote")).unwrap();
+            let keep_local = |_: &SyncConflict| ConflictResolution::KeepLocal;
+            assert_eq!(
+                sync.sync_slot("slot", keep_local).unwrap(),
+                SyncAction::Pushed
+            );
+            assert_eq!(data(&sync, &remote), (b"local".to_vec(), b"local".to_vec()));
+        });
+    }
+}
@@ -0,0 +1,199 @@
+//
+// Colour vision filters
+//
+// Applied to the whole frame after everything else is drawn:
+//
+//      Simulate    shows the frame as a player with a colour vision
+//                  deficiency would see it, for checking that the game is
+//                  readable without telling red from green, say.
+//      Correct     shifts the colours the player can't tell apart towards
+//                  ones they can (daltonisation), as an option for players.
+//
+// The simulation uses the Machado, Oliveira and Fernandes (2009) matrices for
+// full dichromacy, applied to linear RGB.
+//
+
+use crate::Image;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourVision {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColourFilter {
+    #[default]
+    None,
+    Simulate(ColourVision),
+    Correct(ColourVision),
+}
+
+type Matrix = [[f32; 3]; 3];
+
+const PROTANOPIA: Matrix = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+
+const DEUTERANOPIA: Matrix = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+
+const TRITANOPIA: Matrix = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.147602],
+    [0.004733, 0.691367, 0.303900],
+];
+
+// How the colour lost to the deficiency is spread over the channels that can
+// still be seen
+const CORRECTION: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+impl ColourVision {
+    fn matrix(self) -> &'static Matrix {
+        match self {
+            ColourVision::Protanopia => &PROTANOPIA,
+            ColourVision::Deuteranopia => &DEUTERANOPIA,
+            ColourVision::Tritanopia => &TRITANOPIA,
+        }
+    }
+}
+
+fn multiply(m: &Matrix, c: [f32; 3]) -> [f32; 3] {
+    let row = |r: &[f32; 3]| r[0] * c[0] + r[1] * c[1] + r[2] * c[2];
+    [row(&m[0]), row(&m[1]), row(&m[2])]
+}
+
+fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Filters one colour, keeping its alpha.
+pub fn filter_colour(colour: u32, filter: ColourFilter) -> u32 {
+    let (vision, correct) = match filter {
+        ColourFilter::None => return colour,
+        ColourFilter::Simulate(vision) => (vision, false),
+        ColourFilter::Correct(vision) => (vision, true),
+    };
+    let channel = |shift: u32| ((colour >> shift) & 0xff) as f32 / 255.0;
+    let original = [channel(0), channel(8), channel(16)];
+
+    let result = if correct {
+        // Correction works on the sRGB values, as the error is added back to
+        // what the player sees
+        let linear = original.map(to_linear);
+        let seen = multiply(vision.matrix(), linear).map(to_srgb);
+        let error = [0, 1, 2].map(|i| original[i] - seen[i]);
+        let shift = multiply(&CORRECTION, error);
+        [0, 1, 2].map(|i| (original[i] + shift[i]).clamp(0.0, 1.0))
+    } else {
+        multiply(vision.matrix(), original.map(to_linear)).map(to_srgb)
+    };
+
+    let byte = |c: f32| (c * 255.0).round() as u32;
+    (colour & 0xff00_0000) | byte(result[0]) | (byte(result[1]) << 8) | (byte(result[2]) << 16)
+}
+
+impl Image {
+    pub fn apply_colour_filter(&mut self, filter: ColourFilter) {
+        if filter == ColourFilter::None {
+            return;
+        }
+        // Frames use few distinct colours, so each is only worked out once
+        let mut cache = HashMap::new();
+        for colour in self.fore_image.iter_mut().chain(self.back_image.iter_mut()) {
+            *colour = *cache
+                .entry(*colour)
+                .or_insert_with(|| filter_colour(*colour, filter));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_colour;
+
+    const VISIONS: [ColourVision; 3] = [
+        ColourVision::Protanopia,
+        ColourVision::Deuteranopia,
+        ColourVision::Tritanopia,
+    ];
+
+    fn channels(colour: u32) -> [i32; 3] {
+        [0, 8, 16].map(|shift| ((colour >> shift) & 0xff) as i32)
+    }
+
+    fn distance(a: u32, b: u32) -> i32 {
+        let (a, b) = (channels(a), channels(b));
+        (0..3).map(|i| (a[i] - b[i]).abs()).sum()
+    }
+
+    #[test]
+    fn greys_look_the_same_to_everyone() {
+        for vision in VISIONS {
+            for filter in [
+                ColourFilter::Simulate(vision),
+                ColourFilter::Correct(vision),
+            ] {
+                for grey in [0, 128, 255] {
+                    let colour = new_colour(grey, grey, grey);
+                    let filtered = filter_colour(colour, filter);
+                    assert!(distance(filtered, colour) <= 3, "{:?} {}", filter, grey);
+                    assert_eq!(filtered >> 24, colour >> 24);
+                }
+            }
+        }
+        let orange = new_colour(255, 128, 0);
+        assert_eq!(filter_colour(orange, ColourFilter::None), orange);
+    }
+
+    #[test]
+    fn red_and_green_are_confused_and_corrected() {
+        let red = new_colour(200, 40, 40);
+        let green = new_colour(40, 160, 40);
+        let seen = |colour| filter_colour(colour, ColourFilter::Simulate(ColourVision::Protanopia));
+        let corrected =
+            |colour| filter_colour(colour, ColourFilter::Correct(ColourVision::Protanopia));
+
+        let normal = distance(red, green);
+        let protan = distance(seen(red), seen(green));
+        assert!(protan < normal * 2 / 3, "{} {}", protan, normal);
+
+        // Correction moves the difference into the blue they can still see
+        let blue = |a, b| (channels(a)[2] - channels(b)[2]).abs();
+        let before = blue(seen(red), seen(green));
+        let after = blue(seen(corrected(red)), seen(corrected(green)));
+        assert!(after > before * 2, "{} {}", after, before);
+    }
+
+    #[test]
+    fn filters_apply_to_ink_and_paper() {
+        let filter = ColourFilter::Simulate(ColourVision::Deuteranopia);
+        let (red, blue) = (new_colour(255, 0, 0), new_colour(0, 0, 255));
+        let mut image = Image::new(2, 1);
+        image.clear(red, blue);
+        image.apply_colour_filter(filter);
+        assert_eq!(image.fore_image, [filter_colour(red, filter); 2]);
+        assert_eq!(image.back_image, [filter_colour(blue, filter); 2]);
+    }
+}
@@ -0,0 +1,635 @@
+//
+// Content registry
+//
+// Loads the game's definitions (items, monsters, tiles...) from data files at
+// startup so they can be tweaked without recompiling.  Each kind of content is
+// a serde type stored in a file named after the kind, either RON or TOML, that
+// maps string ids to definitions:
+//
+//      items.ron:   { "sword": (damage: 6, weight: 3), "dagger": (...) }
+//      items.toml:  [sword]
+//                   damage = 6
+//
+// Mods live in sub-directories of the mods directory and are loaded after the
+// base data.  A definition in a mod replaces the base definition with the
+// same id.  A mod can instead patch definitions with a <kind>.patch.toml
+// file, which changes only the fields it gives, merging tables:
+//
+//      items.patch.toml:   [sword]
+//                          damage = 8
+//
+// Only definitions read from TOML files can be patched.  A mod may have a
+// mod.toml manifest, with every field optional:
+//
+//      name = "Sharper Swords"
+//      version = "1.2"
+//      after = ["more-monsters"]   # mods (by directory name) to load first
+//      before = ["balance-fixes"]  # mods to load later
+//      enabled = false
+//
+// Mods are loaded in the order their manifests ask for, and otherwise in
+// alphabetical order.  When more than one mod replaces or patches the same
+// definition, the last one wins and the clash is reported by conflicts(), so
+// a mod menu can warn the player.
+//
+
+use crate::{RogueError, RogueResult};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub trait Content: DeserializeOwned + 'static {
+    // The name of the kind of content, and its file name without extension
+    const KIND: &'static str;
+
+    // The other content this definition refers to, as (kind, id) pairs.  These
+    // are checked by ContentRegistry::validate().
+    fn references(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+}
+
+struct Table<T> {
+    definitions: BTreeMap<String, T>,
+}
+
+//
+// Mods
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModInfo {
+    // The mod's directory name, which other mods use to order themselves
+    pub id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub dir: PathBuf,
+    pub after: Vec<String>,
+    pub before: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ModManifest {
+    name: Option<String>,
+    version: Option<String>,
+    after: Vec<String>,
+    before: Vec<String>,
+    enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModConflict {
+    // Several mods replace or patch the same definition, in load order
+    SameDefinition {
+        kind: &'static str,
+        id: String,
+        mods: Vec<String>,
+    },
+    // A mod patches a definition that doesn't exist
+    MissingDefinition {
+        kind: &'static str,
+        id: String,
+        mod_id: String,
+    },
+}
+
+pub struct ContentRegistry {
+    base_dir: PathBuf,
+    mods_dir: Option<PathBuf>,
+    disabled_mods: Vec<String>,
+    tables: HashMap<TypeId, Box<dyn Any>>,
+    // Ids by kind, and the references made by each definition, for validation
+    ids: HashMap<&'static str, Vec<String>>,
+    references: Vec<(&'static str, String, &'static str, String)>,
+    conflicts: Vec<ModConflict>,
+}
+
+impl ContentRegistry {
+    pub fn new(base_dir: &Path) -> Self {
+        ContentRegistry {
+            base_dir: base_dir.to_path_buf(),
+            mods_dir: None,
+            disabled_mods: Vec::new(),
+            tables: HashMap::new(),
+            ids: HashMap::new(),
+            references: Vec::new(),
+            conflicts: Vec::new(),
+        }
+    }
+
+    pub fn with_mods_dir(&mut self, mods_dir: &Path) -> &mut Self {
+        self.mods_dir = Some(mods_dir.to_path_buf());
+        self
+    }
+
+    // Skips these mods, by id, e.g. as chosen by the player in a mod menu
+    pub fn with_disabled_mods(&mut self, ids: &[&str]) -> &mut Self {
+        self.disabled_mods = ids.iter().map(|&id| String::from(id)).collect();
+        self
+    }
+
+    // The enabled mods in load order
+    pub fn mods(&self) -> RogueResult<Vec<ModInfo>> {
+        let mods_dir = match &self.mods_dir {
+            Some(mods_dir) => mods_dir,
+            None => return Ok(Vec::new()),
+        };
+        let entries = match fs::read_dir(mods_dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let mut dirs = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>();
+        dirs.sort();
+
+        let mut mods = Vec::new();
+        for dir in dirs {
+            let id = match dir.file_name().and_then(|name| name.to_str()) {
+                Some(id) => String::from(id),
+                None => continue,
+            };
+            let manifest_path = dir.join("mod.toml");
+            let manifest = if manifest_path.is_file() {
+                let text = fs::read_to_string(&manifest_path)?;
+                toml::from_str::<ModManifest>(&text).map_err(|e| RogueError::BadContent {
+                    file: manifest_path.display().to_string(),
+                    message: e.to_string(),
+                })?
+            } else {
+                ModManifest::default()
+            };
+            if manifest.enabled == Some(false) || self.disabled_mods.contains(&id) {
+                continue;
+            }
+            mods.push(ModInfo {
+                name: manifest.name.unwrap_or_else(|| id.clone()),
+                id,
+                version: manifest.version,
+                dir,
+                after: manifest.after,
+                before: manifest.before,
+            });
+        }
+        order_mods(mods, mods_dir)
+    }
+
+    // The directories searched for data files, base data first.  Mods are
+    // left out if their load order can't be worked out.
+    pub fn data_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.base_dir.clone()];
+        dirs.extend(self.mods().unwrap_or_default().into_iter().map(|m| m.dir));
+        dirs
+    }
+
+    // Clashes between mods found by load(), for every kind loaded
+    pub fn conflicts(&self) -> &[ModConflict] {
+        &self.conflicts
+    }
+
+    //
+    // Loading
+    //
+
+    // Reads the kind's file from the base directory and from every mod, and
+    // then applies each mod's patches.  It is not an error for a directory to
+    // have no file for this kind.
+    pub fn load<T: Content>(&mut self) -> RogueResult<()> {
+        let mut definitions = BTreeMap::new();
+        // The TOML each definition was read from, for patching
+        let mut values = BTreeMap::<String, toml::Value>::new();
+        // The mods that replaced or patched each definition
+        let mut changed_by = BTreeMap::<String, Vec<String>>::new();
+        self.conflicts.retain(|conflict| match conflict {
+            ModConflict::SameDefinition { kind, .. } => *kind != T::KIND,
+            ModConflict::MissingDefinition { kind, .. } => *kind != T::KIND,
+        });
+
+        let base = (None, self.base_dir.clone());
+        let mods = self.mods()?.into_iter().map(|m| (Some(m.id), m.dir));
+        for (mod_id, dir) in std::iter::once(base).chain(mods) {
+            let (file, file_values) = load_file::<T>(&dir)?;
+            for id in file.keys() {
+                values.remove(id);
+                if let Some(mod_id) = &mod_id {
+                    changed_by
+                        .entry(id.clone())
+                        .or_default()
+                        .push(mod_id.clone());
+                }
+            }
+            definitions.extend(file);
+            values.extend(file_values);
+
+            let mod_id = match mod_id {
+                Some(mod_id) => mod_id,
+                None => continue,
+            };
+            let patch_path = dir.join(format!("{}.patch.toml", T::KIND));
+            if !patch_path.is_file() {
+                continue;
+            }
+            let bad_patch = |message: String| RogueError::BadContent {
+                file: patch_path.display().to_string(),
+                message,
+            };
+            let text = fs::read_to_string(&patch_path)?;
+            let patches = toml::from_str::<BTreeMap<String, toml::Value>>(&text)
+                .map_err(|e| bad_patch(e.to_string()))?;
+            for (id, patch) in patches {
+                let value = match values.get_mut(&id) {
+                    Some(value) => value,
+                    None if definitions.contains_key(&id) => {
+                        return Err(bad_patch(format!(
+                            "{} isn't from a TOML file, so it can't be patched",
+                            id
+                        )));
+                    }
+                    None => {
+                        self.conflicts.push(ModConflict::MissingDefinition {
+                            kind: T::KIND,
+                            id,
+                            mod_id: mod_id.clone(),
+                        });
+                        continue;
+                    }
+                };
+                merge_toml(value, patch);
+                let definition = value
+                    .clone()
+                    .try_into::<T>()
+                    .map_err(|e| bad_patch(format!("{}: {}", id, e)))?;
+                definitions.insert(id.clone(), definition);
+                changed_by.entry(id).or_default().push(mod_id.clone());
+            }
+        }
+        for (id, mut mods) in changed_by {
+            mods.dedup();
+            if mods.len() > 1 {
+                self.conflicts.push(ModConflict::SameDefinition {
+                    kind: T::KIND,
+                    id,
+                    mods,
+                });
+            }
+        }
+
+        self.ids
+            .insert(T::KIND, definitions.keys().cloned().collect());
+        self.references.retain(|(kind, ..)| *kind != T::KIND);
+        for (id, definition) in &definitions {
+            for (ref_kind, ref_id) in definition.references() {
+                self.references
+                    .push((T::KIND, id.clone(), ref_kind, ref_id));
+            }
+        }
+        self.tables
+            .insert(TypeId::of::<T>(), Box::new(Table { definitions }));
+        Ok(())
+    }
+
+    // Checks that every reference names a loaded definition.
+    pub fn validate(&self) -> RogueResult<()> {
+        for (kind, id, ref_kind, ref_id) in &self.references {
+            let found = self
+                .ids
+                .get(ref_kind)
+                .is_some_and(|ids| ids.contains(ref_id));
+            if !found {
+                return Err(RogueError::MissingReference {
+                    kind,
+                    id: id.clone(),
+                    reference: format!("{}:{}", ref_kind, ref_id),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    //
+    // Lookup
+    //
+
+    fn table<T: Content>(&self) -> Option<&Table<T>> {
+        self.tables
+            .get(&TypeId::of::<T>())
+            .and_then(|table| table.downcast_ref())
+    }
+
+    pub fn get<T: Content>(&self, id: &str) -> Option<&T> {
+        self.table::<T>()?.definitions.get(id)
+    }
+
+    pub fn contains<T: Content>(&self, id: &str) -> bool {
+        self.get::<T>(id).is_some()
+    }
+
+    // All definitions of a kind, ordered by id
+    pub fn all<T: Content>(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.table::<T>()
+            .into_iter()
+            .flat_map(|table| table.definitions.iter())
+            .map(|(id, definition)| (id.as_str(), definition))
+    }
+}
+
+type Definitions<T> = (BTreeMap<String, T>, BTreeMap<String, toml::Value>);
+
+// The definitions in a directory's file for the kind, and the TOML of each if
+// the file is TOML
+fn load_file<T: Content>(dir: &Path) -> RogueResult<Definitions<T>> {
+    let bad_content = |path: &Path, message: String| RogueError::BadContent {
+        file: path.display().to_string(),
+        message,
+    };
+
+    let ron_path = dir.join(format!("{}.ron", T::KIND));
+    let toml_path = dir.join(format!("{}.toml", T::KIND));
+    if ron_path.is_file() {
+        let text = fs::read_to_string(&ron_path)?;
+        let definitions =
+            ron::from_str(&text).map_err(|e| bad_content(&ron_path, e.to_string()))?;
+        Ok((definitions, BTreeMap::new()))
+    } else if toml_path.is_file() {
+        let text = fs::read_to_string(&toml_path)?;
+        let definitions =
+            toml::from_str(&text).map_err(|e| bad_content(&toml_path, e.to_string()))?;
+        let values = toml::from_str(&text).map_err(|e| bad_content(&toml_path, e.to_string()))?;
+        Ok((definitions, values))
+    } else {
+        Ok((BTreeMap::new(), BTreeMap::new()))
+    }
+}
+
+// Tables are merged key by key, and anything else is replaced.
+fn merge_toml(value: &mut toml::Value, patch: toml::Value) {
+    match (value, patch) {
+        (toml::Value::Table(table), toml::Value::Table(patch)) => {
+            for (key, patch) in patch {
+                match table.get_mut(&key) {
+                    Some(value) => merge_toml(value, patch),
+                    None => {
+                        table.insert(key, patch);
+                    }
+                }
+            }
+        }
+        (value, patch) => *value = patch,
+    }
+}
+
+// Orders mods so that each comes after the mods it names in after and before
+// those in before, breaking ties alphabetically.  Mods named that aren't
+// installed are ignored.
+fn order_mods(mut mods: Vec<ModInfo>, mods_dir: &Path) -> RogueResult<Vec<ModInfo>> {
+    let index = |id: &str| mods.iter().position(|m| m.id == id);
+    // Edges from each mod to the mods that must load after it
+    let mut later = vec![Vec::new(); mods.len()];
+    let mut waiting_on = vec![0; mods.len()];
+    for (i, m) in mods.iter().enumerate() {
+        let edges = m
+            .after
+            .iter()
+            .filter_map(|id| index(id).map(|first| (first, i)))
+            .chain(
+                m.before
+                    .iter()
+                    .filter_map(|id| index(id).map(|next| (i, next))),
+            );
+        for (first, next) in edges {
+            if first != next && !later[first].contains(&next) {
+                later[first].push(next);
+                waiting_on[next] += 1;
+            }
+        }
+    }
+
+    // Mods are sorted by id, so the lowest ready index is the first
+    // alphabetically
+    let mut ready = (0..mods.len())
+        .filter(|&i| waiting_on[i] == 0)
+        .collect::<BTreeSet<_>>();
+    let mut order = Vec::with_capacity(mods.len());
+    while let Some(i) = ready.pop_first() {
+        order.push(i);
+        for &next in &later[i] {
+            waiting_on[next] -= 1;
+            if waiting_on[next] == 0 {
+                ready.insert(next);
+            }
+        }
+    }
+    if order.len() < mods.len() {
+        let stuck = (0..mods.len())
+            .filter(|i| !order.contains(i))
+            .map(|i| mods[i].id.as_str())
+            .collect::<Vec<_>>();
+        return Err(RogueError::BadContent {
+            file: mods_dir.display().to_string(),
+            message: format!(
+                "the load order of {} goes round in a circle",
+                stuck.join(", ")
+            ),
+        });
+    }
+
+    let mut slots = mods.drain(..).map(Some).collect::<Vec<_>>();
+    Ok(order.into_iter().filter_map(|i| slots[i].take()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Item {
+        damage: u32,
+        weight: u32,
+        #[serde(default)]
+        needs: Option<String>,
+    }
+
+    impl Content for Item {
+        const KIND: &'static str = "items";
+
+        fn references(&self) -> Vec<(&'static str, String)> {
+            self.needs
+                .iter()
+                .map(|id| (Self::KIND, id.clone()))
+                .collect()
+        }
+    }
+
+    // Runs the test with a fresh directory holding the files
+    fn with_files(test: &str, files: &[(&str, &str)], f: impl FnOnce(&Path)) {
+        let dir = std::env::temp_dir().join(format!("mage-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        f(&dir);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn registry(dir: &Path) -> ContentRegistry {
+        let mut registry = ContentRegistry::new(&dir.join("base"));
+        registry.with_mods_dir(&dir.join("mods"));
+        registry
+    }
+
+    fn item(registry: &ContentRegistry, id: &str) -> (u32, u32) {
+        let item = registry.get::<Item>(id).unwrap();
+        (item.damage, item.weight)
+    }
+
+    #[test]
+    fn mods_load_in_the_order_they_ask_for() {
+        let files = [
+            ("mods/a/mod.toml", "after = [\"c\"]"),
+            ("mods/b/mod.toml", "name = \"Bees\"\nversion = \"1.2\""),
+            ("mods/c/mod.toml", "before = [\"b\", \"missing\"]"),
+            ("mods/d/mod.toml", "enabled = false"),
+            ("mods/e/items.toml", ""),
+            ("mods/not-a-mod.txt", ""),
+        ];
+        with_files("content-order", &files, |dir| {
+            let mut registry = registry(dir);
+            registry.with_disabled_mods(&["e"]);
+            let mods = registry.mods().unwrap();
+            let ids = mods.iter().map(|m| m.id.as_str()).collect::<Vec<_>>();
+            assert_eq!(ids, ["c", "a", "b"]);
+            assert_eq!(mods[2].name, "Bees");
+            assert_eq!(mods[2].version.as_deref(), Some("1.2"));
+            assert_eq!(mods[0].name, "c");
+
+            let dirs = registry.data_dirs();
+            assert_eq!(dirs[0], dir.join("base"));
+            assert_eq!(dirs[1], dir.join("mods").join("c"));
+        });
+    }
+
+    #[test]
+    fn circular_load_orders_are_rejected() {
+        let files = [
+            ("mods/x/mod.toml", "after = [\"y\"]"),
+            ("mods/y/mod.toml", "after = [\"x\"]"),
+            ("mods/z/mod.toml", ""),
+        ];
+        with_files("content-circle", &files, |dir| {
+            let mut registry = registry(dir);
+            match registry.mods() {
+                Err(RogueError::BadContent { message, .. }) => {
+                    assert!(message.contains("x, y goes round"), "{}", message)
+                }
+                _ => panic!("the load order should be rejected"),
+            }
+            assert!(registry.load::<Item>().is_err());
+            assert_eq!(registry.data_dirs(), [dir.join("base")]);
+        });
+    }
+
+    #[test]
+    fn mods_replace_and_patch_definitions() {
+        let files = [
+            (
+                "base/items.toml",
+                "[sword]\ndamage = 6\nweight = 3\n[dagger]\ndamage = 2\nweight = 1",
+            ),
+            ("mods/a/items.toml", "[dagger]\ndamage = 3\nweight = 1"),
+            (
+                "mods/b/items.patch.toml",
+                "[sword]\ndamage = 8\n[dagger]\nweight = 2\n[axe]\ndamage = 1",
+            ),
+        ];
+        with_files("content-patch", &files, |dir| {
+            let mut registry = registry(dir);
+            registry.load::<Item>().unwrap();
+            assert_eq!(item(&registry, "sword"), (8, 3));
+            assert_eq!(item(&registry, "dagger"), (3, 2));
+            assert!(!registry.contains::<Item>("axe"));
+            let ids = registry.all::<Item>().map(|(id, _)| id).collect::<Vec<_>>();
+            assert_eq!(ids, ["dagger", "sword"]);
+
+            let conflicts = vec![
+                ModConflict::MissingDefinition {
+                    kind: "items",
+                    id: String::from("axe"),
+                    mod_id: String::from("b"),
+                },
+                ModConflict::SameDefinition {
+                    kind: "items",
+                    id: String::from("dagger"),
+                    mods: vec![String::from("a"), String::from("b")],
+                },
+            ];
+            assert_eq!(registry.conflicts(), conflicts.as_slice());
+            // Loading again doesn't repeat the conflicts
+            registry.load::<Item>().unwrap();
+            assert_eq!(registry.conflicts(), conflicts.as_slice());
+
+            // Without the patching mod
+            registry.with_disabled_mods(&["b"]);
+            registry.load::<Item>().unwrap();
+            assert_eq!(item(&registry, "sword"), (6, 3));
+            assert!(registry.conflicts().is_empty());
+        });
+    }
+
+    #[test]
+    fn bad_patches_are_rejected() {
+        let files = [
+            ("base/items.ron", "{ \"sword\": (damage: 6, weight: 3) }"),
+            ("mods/a/items.patch.toml", "[sword]\ndamage = 8"),
+        ];
+        with_files("content-ron-patch", &files, |dir| {
+            let mut registry = registry(dir);
+            assert!(matches!(
+                registry.load::<Item>(),
+                Err(RogueError::BadContent { .. })
+            ));
+        });
+
+        let files = [
+            ("base/items.toml", "[sword]\ndamage = 6\nweight = 3"),
+            ("mods/a/items.patch.toml", "[sword]\ndamage = \"sharp\""),
+        ];
+        with_files("content-bad-patch", &files, |dir| {
+            assert!(registry(dir).load::<Item>().is_err());
+        });
+    }
+
+    #[test]
+    fn references_are_validated() {
+        let files = [(
+            "base/items.toml",
+            "[sword]\ndamage = 6\nweight = 3\nneeds = \"whetstone\"",
+        )];
+        with_files("content-references", &files, |dir| {
+            let mut registry = registry(dir);
+            registry.load::<Item>().unwrap();
+            assert!(matches!(
+                registry.validate(),
+                Err(RogueError::MissingReference { kind: "items", .. })
+            ));
+        });
+
+        let files = [(
+            "base/items.toml",
+            "[sword]\ndamage = 6\nweight = 3\nneeds = \"dagger\"\n[dagger]\ndamage = 2\nweight = 1",
+        )];
+        with_files("content-references-found", &files, |dir| {
+            let mut registry = registry(dir);
+            registry.load::<Item>().unwrap();
+            registry.validate().unwrap();
+        });
+    }
+}
@@ -0,0 +1,559 @@
+//
+// Engine context
+//
+// Services provided by the engine that the game can use during tick().
+//
+
+#[cfg(feature = "net")]
+use crate::FrameBroadcaster;
+use crate::{
+    external::ExternalInputs,
+    window::{WindowHandle, WindowRequest},
+    AccessibilitySettings, Achievements, AdapterInfo, Animator, Assets, Autosave, Caret,
+    ColourFilter, CrashReport, DailyChallenge, DebugOverlay, Diagnostics, EventBus, GameRng,
+    InputOptions, InputSource, Key, KeyEcho, LightMap, MemoryUsage, MouseState, Notifications,
+    NotifyStyle, Point, Profiler, RogueResult, ScreenReader, StateHashLog, Tooltips, Weather,
+    WorldClock,
+};
+#[cfg(feature = "presence")]
+use crate::{presence::PresenceState, Presence, PresenceBackend};
+use arboard::Clipboard;
+use std::{
+    cell::Cell,
+    io,
+    path::{Path, PathBuf},
+};
+
+// The largest zoom set with the zoom keys or set_zoom()
+const MAX_ZOOM: u32 = 8;
+
+pub struct Context {
+    clipboard: Option<Clipboard>,
+    next_window: u32,
+    pub(crate) window_requests: Vec<WindowRequest>,
+    pub(crate) windows: Vec<(WindowHandle, MouseState)>,
+    pub(crate) ime_position: Option<Point>,
+    pub(crate) caret: Caret,
+    pub(crate) light_map: LightMap,
+    pub(crate) effects_params: [f32; 4],
+    pub(crate) clear_colour: Option<u32>,
+    pub(crate) animator: Animator,
+    pub(crate) tooltips: Tooltips,
+    events: EventBus,
+    pub(crate) assets: Assets,
+    rng: GameRng,
+    daily: Option<DailyChallenge>,
+    pub(crate) clock: WorldClock,
+    pub(crate) weather: Weather,
+    pub(crate) profiler: Profiler,
+    pub(crate) adapter_info: Option<AdapterInfo>,
+    achievements: Achievements,
+    pub(crate) notifications: Notifications,
+    pub(crate) screen_reader: ScreenReader,
+    accessibility: AccessibilitySettings,
+    input_options: InputOptions,
+    pub(crate) key_echo: KeyEcho,
+    #[cfg(feature = "net")]
+    pub(crate) broadcaster: Option<FrameBroadcaster>,
+    pub(crate) external: ExternalInputs,
+    pub(crate) diagnostics: Option<Diagnostics>,
+    pub(crate) state_hashes: Option<StateHashLog>,
+    pub(crate) autosave: Option<Autosave>,
+    pub(crate) debug_overlay: DebugOverlay,
+    zoom: u32,
+    zoom_keys: Option<(Key, Key)>,
+    escape_quits: bool,
+    // Cleared before each tick, so a widget only keeps Escape while it goes
+    // on claiming it
+    escape_claimed: Cell<bool>,
+    // Set by the engine each tick
+    pub(crate) grid_memory: usize,
+    pub(crate) atlas_memory: usize,
+    game_memory: Vec<(String, usize)>,
+    #[cfg(feature = "presence")]
+    pub(crate) presence: PresenceState,
+}
+
+impl Context {
+    pub(crate) fn new() -> Self {
+        Context {
+            clipboard: None,
+            next_window: 0,
+            window_requests: Vec::new(),
+            windows: Vec::new(),
+            ime_position: None,
+            caret: Caret::new(),
+            light_map: LightMap::new(),
+            effects_params: [0.0; 4],
+            clear_colour: None,
+            animator: Animator::new(),
+            tooltips: Tooltips::new(),
+            events: EventBus::new(),
+            assets: Assets::new(Path::new(".")),
+            rng: GameRng::from_time(),
+            daily: None,
+            clock: WorldClock::new(),
+            weather: Weather::default(),
+            profiler: Profiler::default(),
+            adapter_info: None,
+            achievements: Achievements::new(),
+            notifications: Notifications::new(),
+            screen_reader: ScreenReader::new(),
+            accessibility: AccessibilitySettings::default(),
+            input_options: InputOptions::default(),
+            key_echo: KeyEcho::new(),
+            #[cfg(feature = "net")]
+            broadcaster: None,
+            external: ExternalInputs::new(),
+            diagnostics: None,
+            state_hashes: None,
+            autosave: None,
+            debug_overlay: DebugOverlay::new(),
+            zoom: 1,
+            zoom_keys: Some((Key::Equals, Key::Minus)),
+            escape_quits: true,
+            escape_claimed: Cell::new(false),
+            grid_memory: 0,
+            atlas_memory: 0,
+            game_memory: Vec::new(),
+            #[cfg(feature = "presence")]
+            presence: PresenceState::new(),
+        }
+    }
+
+    // Animations attached here are advanced by the engine every tick and drawn
+    // over the main window after the game's present().
+    pub fn animator(&mut self) -> &mut Animator {
+        &mut self.animator
+    }
+
+    // Descriptions registered here are shown in a box near the mouse cursor
+    // once it has hovered over a cell for long enough.
+    pub fn tooltips(&mut self) -> &mut Tooltips {
+        &mut self.tooltips
+    }
+
+    // Events published here by the engine or the game stay queued until they
+    // are drained.
+    pub fn events(&mut self) -> &mut EventBus {
+        &mut self.events
+    }
+
+    // Asset paths are relative to the working directory unless the game sets
+    // a different root.
+    pub fn assets(&mut self) -> &mut Assets {
+        &mut self.assets
+    }
+
+    // The game's random number generator, seeded from the clock or from
+    // RogueBuilder::with_seed().  Games that replay runs from a seed replace
+    // it with GameRng::new(seed).
+    pub fn rng(&mut self) -> &mut GameRng {
+        &mut self.rng
+    }
+
+    // Seeds the RNG for a daily challenge.  The challenge is remembered until
+    // end_daily() so the game can tell a daily run from a normal one.
+    pub fn start_daily(&mut self, challenge: DailyChallenge) {
+        log::info!("Daily challenge for {}", challenge.date());
+        self.rng = challenge.rng();
+        self.daily = Some(challenge);
+    }
+
+    // Ends the daily run, reseeding the RNG from the clock.
+    pub fn end_daily(&mut self) -> Option<DailyChallenge> {
+        self.rng = GameRng::from_time();
+        self.daily.take()
+    }
+
+    pub fn daily(&self) -> Option<&DailyChallenge> {
+        self.daily.as_ref()
+    }
+
+    // The time of day.  Regions registered with the clock are tinted with the
+    // ambient light after present().
+    pub fn clock(&mut self) -> &mut WorldClock {
+        &mut self.clock
+    }
+
+    // The light on each cell of the main window, applied by the renderer when
+    // enabled.  See light_map.rs.
+    pub fn light_map(&mut self) -> &mut LightMap {
+        &mut self.light_map
+    }
+
+    // Passed to the effects shader set with RogueBuilder::with_effects_shader()
+    // each frame, as EffectsInfo::params.  See the GPU effects in render.rs.
+    pub fn set_effects_params(&mut self, params: [f32; 4]) {
+        self.effects_params = params;
+    }
+
+    pub fn effects_params(&self) -> [f32; 4] {
+        self.effects_params
+    }
+
+    // Rain, snow or fog drawn over an area of the main window every frame,
+    // under the ambient tint.
+    pub fn weather(&mut self) -> &mut Weather {
+        &mut self.weather
+    }
+
+    // Timings of the most recent frames, broken down into phases.
+    pub fn profiler(&mut self) -> &mut Profiler {
+        &mut self.profiler
+    }
+
+    // Draws the main window's cells at a whole multiple of the font's size, so
+    // fewer fit.  The large font option doubles it.  Takes effect from the
+    // next frame, when the grid is resized and the game told.  A grid fixed
+    // with RogueBuilder::with_grid_size() is scaled to the window instead.
+    pub fn set_zoom(&mut self, zoom: u32) {
+        self.zoom = zoom.clamp(1, MAX_ZOOM);
+    }
+
+    pub fn zoom(&self) -> u32 {
+        self.zoom
+    }
+
+    // The keys that zoom in and out when pressed with Ctrl, Ctrl+= and Ctrl+-
+    // by default, or None to turn them off.
+    pub fn set_zoom_keys(&mut self, keys: Option<(Key, Key)>) {
+        self.zoom_keys = keys;
+    }
+
+    pub fn zoom_keys(&self) -> Option<(Key, Key)> {
+        self.zoom_keys
+    }
+
+    // Whether pressing Escape quits the game.  Turn it off to handle Escape
+    // in the game, e.g. to open a pause menu.
+    pub fn set_escape_quits(&mut self, quits: bool) {
+        self.escape_quits = quits;
+    }
+
+    // False while a widget has claimed Escape, even if it normally quits
+    pub fn escape_quits(&self) -> bool {
+        self.escape_quits && !self.escape_claimed.get()
+    }
+
+    // Keeps the next Escape press from quitting the game, so a widget can use
+    // it.  It lasts until the next tick, so widgets that want Escape claim it
+    // every tick they are open, e.g. an open context menu.
+    pub fn claim_escape(&self) {
+        self.escape_claimed.set(true);
+    }
+
+    pub(crate) fn begin_tick(&mut self) {
+        self.escape_claimed.set(false);
+    }
+
+    // The frame timings and memory usage panel.  See ui/debug_overlay.rs.
+    pub fn debug_overlay(&mut self) -> &mut DebugOverlay {
+        &mut self.debug_overlay
+    }
+
+    // Memory used by the engine's resources and reported by the game.  See
+    // memory.rs.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            grids: self.grid_memory,
+            glyph_atlases: self.atlas_memory,
+            assets: self.assets.memory_size(),
+            game: self.game_memory.clone(),
+        }
+    }
+
+    // Adds the game's own resources to memory_usage(), e.g. its canvases or
+    // audio buffers, replacing any earlier report with the same name.
+    pub fn report_memory(&mut self, name: &str, bytes: usize) {
+        match self.game_memory.iter_mut().find(|(n, _)| n == name) {
+            Some((_, size)) => *size = bytes,
+            None => self.game_memory.push((String::from(name), bytes)),
+        }
+    }
+
+    // Shows a message in a corner of the main window for a few seconds.
+    pub fn notify(&mut self, text: &str, style: NotifyStyle) {
+        self.notifications.push(text, style);
+        self.screen_reader.announce(text);
+    }
+
+    // Where notifications go, how long they stay and their colours
+    pub fn notifications(&mut self) -> &mut Notifications {
+        &mut self.notifications
+    }
+
+    // A text description of each frame for screen readers, when enabled
+    pub fn screen_reader(&mut self) -> &mut ScreenReader {
+        &mut self.screen_reader
+    }
+
+    // Applied to the main window after everything is drawn, e.g. from an
+    // accessibility option.
+    pub fn set_colour_filter(&mut self, filter: ColourFilter) {
+        self.accessibility.colour_filter = filter;
+    }
+
+    pub fn colour_filter(&self) -> ColourFilter {
+        self.accessibility.colour_filter
+    }
+
+    // Takes effect from the next frame.  See accessibility.rs.
+    pub fn set_accessibility(&mut self, settings: AccessibilitySettings) {
+        self.screen_reader.set_enabled(settings.screen_reader);
+        self.accessibility = settings;
+    }
+
+    pub fn accessibility(&self) -> AccessibilitySettings {
+        AccessibilitySettings {
+            screen_reader: self.screen_reader.is_enabled(),
+            ..self.accessibility
+        }
+    }
+
+    // Sticky modifiers, key repeat and key echo.  Takes effect from the next
+    // key pressed.  See input.rs.
+    pub fn set_input_options(&mut self, options: InputOptions) {
+        if !options.key_echo {
+            self.key_echo.clear();
+        }
+        self.input_options = options;
+    }
+
+    pub fn input_options(&self) -> InputOptions {
+        self.input_options
+    }
+
+    // The colours of the keys shown by the key echo option
+    pub fn key_echo(&mut self) -> &mut KeyEcho {
+        &mut self.key_echo
+    }
+
+    // Input from outside the window, merged into SimInput from the next tick.
+    // See external.rs.
+    pub fn add_input_source<S: InputSource + 'static>(&mut self, source: S) {
+        self.external.add_source(Box::new(source));
+    }
+
+    pub fn remove_input_source(&mut self, name: &str) {
+        self.external.remove_source(name);
+    }
+
+    pub(crate) fn poll_input_sources(&mut self) {
+        self.external.poll(&mut self.events);
+    }
+
+    // Where the presence is shown, e.g. DiscordPresence.  See presence.rs.
+    #[cfg(feature = "presence")]
+    pub fn set_presence_backend<B: PresenceBackend + 'static>(&mut self, backend: B) {
+        self.presence.set_backend(Box::new(backend));
+    }
+
+    #[cfg(feature = "presence")]
+    pub fn set_presence(&mut self, presence: Presence) {
+        self.presence.set(Some(presence));
+    }
+
+    #[cfg(feature = "presence")]
+    pub fn clear_presence(&mut self) {
+        self.presence.set(None);
+    }
+
+    #[cfg(feature = "presence")]
+    pub fn presence(&self) -> Option<&Presence> {
+        self.presence.presence()
+    }
+
+    // Set up with RogueBuilder::with_diagnostics()
+    pub fn diagnostics(&mut self) -> Option<&mut Diagnostics> {
+        self.diagnostics.as_mut()
+    }
+
+    // Set up with RogueBuilder::with_autosave().  See autosave.rs.
+    pub fn autosave(&mut self) -> Option<&mut Autosave> {
+        self.autosave.as_mut()
+    }
+
+    // Set up with RogueBuilder::with_state_hashes().  See state_hash.rs.
+    pub fn state_hashes(&mut self) -> Option<&mut StateHashLog> {
+        self.state_hashes.as_mut()
+    }
+
+    // Saves a diagnostics report now and returns its path.  See
+    // diagnostics.rs.
+    pub fn save_diagnostics(&mut self) -> RogueResult<PathBuf> {
+        self.write_diagnostics(None)
+    }
+
+    pub(crate) fn write_diagnostics(
+        &mut self,
+        crash: Option<&CrashReport>,
+    ) -> RogueResult<PathBuf> {
+        let adapter = self
+            .adapter_info
+            .as_ref()
+            .map_or(String::from("unknown"), |info| {
+                format!("{} ({:?}, {:?})", info.name, info.backend, info.device_type)
+            });
+        let report = format!(
+            "md-mage {}
+Platform: {} {}
+Adapter: {}
+Seed: {} ({})
+",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            adapter,
+            self.rng.seed_words(),
+            self.rng.seed()
+        );
+        match &self.diagnostics {
+            Some(diagnostics) => diagnostics.save(report, crash),
+            None => {
+                Err(io::Error::new(io::ErrorKind::NotFound, "Diagnostics are not enabled").into())
+            }
+        }
+    }
+
+    // The frame broadcaster, if the game was built with spectators
+    #[cfg(feature = "net")]
+    pub fn spectators(&mut self) -> Option<&mut FrameBroadcaster> {
+        self.broadcaster.as_mut()
+    }
+
+    // Achievements defined and unlocked by the game.  The engine announces
+    // unlocks and saves the progress after each tick.
+    pub fn achievements(&mut self) -> &mut Achievements {
+        &mut self.achievements
+    }
+
+    pub(crate) fn update_achievements(&mut self) {
+        for id in self.achievements.take_unlocked() {
+            if let Some(achievement) = self.achievements.definition(&id) {
+                let mut text = format!("Achievement unlocked: {}", achievement.name);
+                if !achievement.description.is_empty() {
+                    text = format!("{}\n{}", text, achievement.description);
+                }
+                self.notify(&text, NotifyStyle::Success);
+            }
+        }
+        if let Err(e) = self.achievements.save() {
+            log::warn!("Unable to save achievements: {}", e);
+        }
+    }
+
+    // The graphics device the main window is drawn with, for bug reports.
+    pub fn adapter_info(&self) -> Option<&AdapterInfo> {
+        self.adapter_info.as_ref()
+    }
+
+    //
+    // Clipboard
+    // The system clipboard is opened on first use since it may not be
+    // available (e.g. on a headless machine).
+    //
+
+    fn clipboard(&mut self) -> RogueResult<&mut Clipboard> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(Clipboard::new()?);
+        }
+        Ok(self.clipboard.as_mut().unwrap())
+    }
+
+    pub fn clipboard_text(&mut self) -> Option<String> {
+        self.clipboard().ok()?.get_text().ok()
+    }
+
+    pub fn set_clipboard_text(&mut self, text: &str) -> RogueResult<()> {
+        self.clipboard()?.set_text(String::from(text))?;
+        Ok(())
+    }
+
+    //
+    // Secondary windows
+    // Windows are opened and closed after the current tick.  Their contents are
+    // drawn by the Game trait's present_window() function.  The size is given
+    // in characters.
+    //
+
+    pub fn open_window(&mut self, title: &str, width: u32, height: u32) -> WindowHandle {
+        let handle = WindowHandle(self.next_window);
+        self.next_window += 1;
+        self.window_requests.push(WindowRequest::Open {
+            handle,
+            title: String::from(title),
+            width,
+            height,
+        });
+        handle
+    }
+
+    pub fn close_window(&mut self, handle: WindowHandle) {
+        self.window_requests.push(WindowRequest::Close(handle));
+    }
+
+    // Returns false once the window has been closed, either by the game or by
+    // the player.
+    pub fn is_window_open(&self, handle: WindowHandle) -> bool {
+        self.windows.iter().any(|(h, _)| *h == handle)
+    }
+
+    pub fn window_mouse(&self, handle: WindowHandle) -> Option<MouseState> {
+        self.windows
+            .iter()
+            .find(|(h, _)| *h == handle)
+            .map(|(_, mouse)| *mouse)
+    }
+
+    //
+    // Text entry
+    // Typed characters, including text committed by an IME, arrive in
+    // SimInput::text.  While editing text, the game should tell the engine
+    // where the text cursor is so the IME candidate box appears under it.
+    //
+    // The text being composed (the preedit) is not available: winit 0.25 only
+    // reports text once the IME commits it, so the composition is shown in
+    // the IME's own window rather than in the game's text field.
+    //
+
+    // The cell the text cursor is in.  The candidate box is placed below it.
+    pub fn set_ime_position(&mut self, p: Point) {
+        self.ime_position = Some(p);
+    }
+
+    // The text field's caret, placed every tick while it has focus.  The IME
+    // position follows it.  See ui/caret.rs.
+    pub fn caret(&mut self) -> &mut Caret {
+        &mut self.caret
+    }
+
+    //
+    // Background
+    // The colour of the main window outside the cells.  See
+    // RogueBuilder::with_clear_colour().
+    //
+
+    pub fn set_clear_colour(&mut self, colour: u32) {
+        self.clear_colour = Some(colour);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claiming_escape_lasts_until_the_next_tick() {
+        let mut ctx = Context::new();
+        assert!(ctx.escape_quits());
+        ctx.claim_escape();
+        assert!(!ctx.escape_quits());
+        ctx.begin_tick();
+        assert!(ctx.escape_quits());
+
+        ctx.set_escape_quits(false);
+        ctx.begin_tick();
+        assert!(!ctx.escape_quits());
+    }
+}
@@ -0,0 +1,183 @@
+//
+// Crash screen
+//
+// When enabled with RogueBuilder::with_crash_screen(), a panic in the game's
+// start(), tick() or present() no longer closes the window.  The game is
+// stopped, a crash log with the message and backtrace is written, and the
+// window shows what went wrong until the player presses Escape, so they can
+// report it.
+//
+
+use crate::{new_colour, wrap_text, Image, Point};
+use std::{
+    backtrace::Backtrace,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    pub message: String,
+    pub location: Option<String>,
+    pub backtrace: String,
+    // Where the crash log was written, if it could be
+    pub log_file: Option<PathBuf>,
+}
+
+// Filled in by the panic hook, which runs before the stack unwinds and so is
+// the only place the backtrace can be captured.
+static LAST_PANIC: Mutex<Option<CrashReport>> = Mutex::new(None);
+
+pub(crate) fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|s| String::from(*s))
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("Unknown panic"));
+        let report = CrashReport {
+            message,
+            location: info.location().map(|l| l.to_string()),
+            backtrace: Backtrace::force_capture().to_string(),
+            log_file: None,
+        };
+        if let Ok(mut last) = LAST_PANIC.lock() {
+            *last = Some(report);
+        }
+        previous(info);
+    }));
+}
+
+// Runs part of the game, turning a panic into a crash report and writing it
+// to the log file.  When the crash screen is off, panics carry on as usual.
+pub(crate) fn guard<R>(log_file: Option<&Path>, f: impl FnOnce() -> R) -> Result<R, CrashReport> {
+    let log_file = match log_file {
+        Some(log_file) => log_file,
+        None => return Ok(f()),
+    };
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|_| {
+        let mut report = LAST_PANIC
+            .lock()
+            .ok()
+            .and_then(|mut last| last.take())
+            .unwrap_or_else(|| CrashReport {
+                message: String::from("Unknown panic"),
+                location: None,
+                backtrace: String::new(),
+                log_file: None,
+            });
+        match report.write(log_file) {
+            Ok(()) => report.log_file = Some(log_file.to_path_buf()),
+            Err(e) => log::error!("Unable to write {}: {}", log_file.display(), e),
+        }
+        report
+    })
+}
+
+impl CrashReport {
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        let text = format!(
+            "Crashed at {} (seconds since 1970)\n\n{}\n{}\n\n{}\n",
+            time,
+            self.message,
+            self.location.as_deref().unwrap_or(""),
+            self.backtrace
+        );
+        std::fs::write(path, text)
+    }
+
+    // Fills the image with the report.  The backtrace is cut off where it no
+    // longer fits; the whole of it is in the log file.
+    pub fn draw(&self, image: &mut Image) {
+        let ink = new_colour(255, 255, 255);
+        let paper = new_colour(128, 0, 0);
+        let title = new_colour(255, 255, 0);
+        image.clear(ink, paper);
+
+        let width = image.width.saturating_sub(2);
+        let mut y = 1;
+        let mut line = |image: &mut Image, text: &str, ink: u32| {
+            for text in wrap_text(text, width as usize) {
+                image.draw_string(Point::new(1, y), &text, ink, paper);
+                y += 1;
+            }
+        };
+        line(image, "The game has crashed", title);
+        line(image, "", ink);
+        line(image, &self.message, ink);
+        if let Some(location) = &self.location {
+            line(image, &format!("at {}", location), ink);
+        }
+        line(image, "", ink);
+        match &self.log_file {
+            Some(log_file) => line(
+                image,
+                &format!("Details have been saved to {}", log_file.display()),
+                title,
+            ),
+            None => line(image, "The crash log could not be saved", title),
+        }
+        line(image, "Press Escape to exit", title);
+        line(image, "", ink);
+        for text in self.backtrace.lines() {
+            line(image, text.trim_end(), ink);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_become_crash_reports() {
+        install_panic_hook();
+        assert_eq!(guard(None, || 3).unwrap(), 3);
+
+        let path = std::env::temp_dir().join(format!("mage-crash-{}.log", std::process::id()));
+        let report = guard(Some(&path), || panic!("out of {}", "mana")).unwrap_err();
+        let log = std::fs::read_to_string(&path).unwrap_or_default();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(report.message, "out of mana");
+        assert!(report.location.unwrap().contains("crash.rs"));
+        assert_eq!(report.log_file.as_deref(), Some(path.as_path()));
+        assert!(log.starts_with("Crashed at "));
+        assert!(log.contains("\n\nout of mana\n"));
+    }
+
+    #[test]
+    fn the_report_fills_the_screen() {
+        let report = CrashReport {
+            message: String::from("boom"),
+            location: Some(String::from("src/game.rs:1:2")),
+            backtrace: String::from("0: main\n1: start\n"),
+            log_file: None,
+        };
+        let mut image = Image::new(30, 11);
+        report.draw(&mut image);
+        let expected = [
+            "",
+            " The game has crashed",
+            "",
+            " boom",
+            " at src/game.rs:1:2",
+            "",
+            " The crash log could not be",
+            " saved",
+            " Press Escape to exit",
+            "",
+            " 0: main",
+        ]
+        .map(|row| format!("{:30}", row));
+        assert_eq!(image.to_strings(), expected);
+        assert_eq!(image.back_image[0], new_colour(128, 0, 0));
+    }
+}
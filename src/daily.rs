@@ -0,0 +1,111 @@
+//
+// Daily challenges
+//
+// Everyone playing on the same day (UTC) gets the same seed, so they all play
+// the same world and can compare scores.  The seed comes from the date and the
+// game's name, so different games don't share dailies.
+//
+// Start the run with Context::start_daily(), which seeds the context's RNG,
+// and make the run's record with DailyChallenge::record() so the run history
+// knows it was a daily.  Splitting streams off the RNG by name (see rng.rs)
+// keeps the level generation the same for everyone however the players act.
+//
+
+use crate::{
+    history::{civil_from_days, now},
+    rng::hash_name,
+    seed_to_words, GameRng, RunRecord,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DailyChallenge {
+    // Days since the Unix epoch
+    day: i64,
+    date: String,
+    seed: u64,
+}
+
+impl DailyChallenge {
+    pub fn today(game_name: &str) -> Self {
+        Self::for_day(game_name, (now() / 86400) as i64)
+    }
+
+    // The challenge for a day, counted from the Unix epoch, e.g. to replay
+    // yesterday's.
+    pub fn for_day(game_name: &str, day: i64) -> Self {
+        let (year, month, day_of_month) = civil_from_days(day);
+        let date = format!("{:04}-{:02}-{:02}", year, month, day_of_month);
+        let seed = hash_name(&format!("{}/daily/{}", game_name, date));
+        DailyChallenge { day, date, seed }
+    }
+
+    pub fn day(&self) -> i64 {
+        self.day
+    }
+
+    // As YYYY-MM-DD
+    pub fn date(&self) -> &str {
+        &self.date
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn seed_words(&self) -> String {
+        seed_to_words(self.seed)
+    }
+
+    pub fn rng(&self) -> GameRng {
+        GameRng::new(self.seed)
+    }
+
+    // A record of a daily run that has just ended
+    pub fn record(&self, name: &str, score: i64, cause: &str) -> RunRecord {
+        let mut record = RunRecord::new(name, score, self.seed, cause);
+        record.daily = Some(self.date.clone());
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Context;
+
+    #[test]
+    fn the_seed_depends_on_the_day_and_the_game() {
+        let challenge = DailyChallenge::for_day("mage", 19_000);
+        assert_eq!(challenge.date(), "2022-01-08");
+        assert_eq!(challenge.day(), 19_000);
+        assert_eq!(challenge.seed(), hash_name("mage/daily/2022-01-08"));
+        assert_eq!(challenge.seed_words(), seed_to_words(challenge.seed()));
+        assert_eq!(challenge.rng().seed(), challenge.seed());
+
+        assert_eq!(DailyChallenge::for_day("mage", 19_000), challenge);
+        assert_ne!(
+            DailyChallenge::for_day("mage", 19_001).seed(),
+            challenge.seed()
+        );
+        assert_ne!(
+            DailyChallenge::for_day("other", 19_000).seed(),
+            challenge.seed()
+        );
+        assert_eq!(DailyChallenge::today("mage").day(), (now() / 86400) as i64);
+    }
+
+    #[test]
+    fn daily_runs_seed_the_context_and_are_recorded() {
+        let challenge = DailyChallenge::for_day("mage", 19_000);
+        let mut ctx = Context::new();
+        ctx.start_daily(challenge.clone());
+        assert_eq!(ctx.rng().seed(), challenge.seed());
+        assert_eq!(ctx.daily(), Some(&challenge));
+        assert_eq!(ctx.end_daily(), Some(challenge.clone()));
+        assert_eq!(ctx.daily(), None);
+
+        let record = challenge.record("Ann", 10, "Drowned");
+        assert_eq!(record.seed, challenge.seed());
+        assert_eq!(record.daily.as_deref(), Some("2022-01-08"));
+    }
+}
@@ -0,0 +1,231 @@
+//
+// Diagnostics reports
+//
+// A zip file for players to attach to bug reports, turned on with
+// RogueBuilder::with_diagnostics().  It holds:
+//
+//      report.txt      engine version, platform, graphics adapter and seed
+//      log.txt         the recent log, if the engine's logger is installed
+//      crash.txt       the crash report, when made after a crash
+//      frames/         the last few frames as text, if set_frame_history() is
+//                      used, in the golden frame format
+//
+// and any files the game adds, e.g. its save or settings.  A report is saved
+// when the player presses the diagnostics key, after a crash if the crash
+// screen is on, or whenever the game calls Context::save_diagnostics().
+//
+
+use crate::{golden_text, recent_log, CrashReport, Image, Key, RogueResult};
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Cursor, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, DateTime, ZipWriter};
+
+pub struct Diagnostics {
+    dir: PathBuf,
+    key: Option<Key>,
+    frame_history: usize,
+    frames: VecDeque<String>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl Diagnostics {
+    pub(crate) fn new(dir: &Path, key: Option<Key>) -> Self {
+        Diagnostics {
+            dir: dir.to_path_buf(),
+            key,
+            frame_history: 0,
+            frames: VecDeque::new(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn key(&self) -> Option<Key> {
+        self.key
+    }
+
+    pub fn set_key(&mut self, key: Option<Key>) {
+        self.key = key;
+    }
+
+    // How many of the last frames go in the report.  Each frame is dumped as
+    // text after it is drawn, which costs a little time, so this is 0 by
+    // default.
+    pub fn set_frame_history(&mut self, frames: usize) {
+        self.frame_history = frames;
+        while self.frames.len() > frames {
+            self.frames.pop_front();
+        }
+    }
+
+    // Adds a file to every report, replacing any with the same name.
+    pub fn add_file(&mut self, name: &str, data: &[u8]) {
+        self.remove_file(name);
+        self.files.push((String::from(name), data.to_vec()));
+    }
+
+    pub fn remove_file(&mut self, name: &str) {
+        self.files.retain(|(n, _)| n != name);
+    }
+
+    pub(crate) fn record_frame(&mut self, image: &Image) {
+        if self.frame_history == 0 {
+            return;
+        }
+        if self.frames.len() == self.frame_history {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(golden_text(image));
+    }
+
+    // Writes the report and returns its path.  report is the text for
+    // report.txt, made by the context.
+    pub(crate) fn save(&self, report: String, crash: Option<&CrashReport>) -> RogueResult<PathBuf> {
+        let mut files = vec![(String::from("report.txt"), report.into_bytes())];
+        let log = recent_log();
+        if !log.is_empty() {
+            files.push((
+                String::from("log.txt"),
+                (log.join("\n") + "\n").into_bytes(),
+            ));
+        }
+        if let Some(crash) = crash {
+            let text = format!(
+                "{}\n{}\n\n{}\n",
+                crash.message,
+                crash.location.as_deref().unwrap_or(""),
+                crash.backtrace
+            );
+            files.push((String::from("crash.txt"), text.into_bytes()));
+        }
+        for (i, frame) in self.frames.iter().enumerate() {
+            let name = format!("frames/frame-{:03}.txt", i);
+            files.push((name, frame.clone().into_bytes()));
+        }
+        files.extend(self.files.iter().cloned());
+
+        fs::create_dir_all(&self.dir)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        let path = self.dir.join(format!("diagnostics-{}.zip", now));
+        fs::write(&path, zip(&files, now)?)?;
+        Ok(path)
+    }
+}
+
+//
+// Zip files
+// Files are stored without compression, which is fine for a few text files.
+// The zip crate switches to ZIP64 for files or archives too big for the
+// original format.
+//
+
+// The time in the MS-DOS format zip files use, or 1980 for earlier times
+fn zip_time(unix_time: u64) -> DateTime {
+    let days = (unix_time / 86400) as i64;
+    let seconds = unix_time % 86400;
+    // Days to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    DateTime::from_date_and_time(
+        year.clamp(1980, 2107) as u16,
+        month as u8,
+        day as u8,
+        (seconds / 3600) as u8,
+        (seconds % 3600 / 60) as u8,
+        (seconds % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+fn zip(files: &[(String, Vec<u8>)], unix_time: u64) -> RogueResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(zip_time(unix_time))
+            .large_file(contents.len() as u64 >= u32::MAX as u64);
+        zip.start_file(name.as_str(), options)
+            .map_err(io::Error::from)?;
+        zip.write_all(contents)?;
+    }
+    let data = zip.finish().map_err(io::Error::from)?;
+    Ok(data.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    fn read_back(data: Vec<u8>) -> Vec<(String, Vec<u8>, DateTime)> {
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut contents = Vec::new();
+                // Reading to the end checks the CRC
+                file.read_to_end(&mut contents).unwrap();
+                let time = file.last_modified().unwrap();
+                (file.name().to_string(), contents, time)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_read_back() {
+        let files = vec![
+            (String::from("report.txt"), b"mage 0.2.0\n".to_vec()),
+            (String::from("frames/frame-000.txt"), b"@..\n".to_vec()),
+            (String::from("empty.txt"), Vec::new()),
+            (String::from("saves/slot-1.sav"), (0..=255).collect()),
+        ];
+        // 2021-03-04 05:06:08
+        let read = read_back(zip(&files, 1_614_834_368).unwrap());
+
+        assert_eq!(read.len(), files.len());
+        for ((name, contents, time), (expected_name, expected)) in read.iter().zip(&files) {
+            assert_eq!(name, expected_name);
+            assert_eq!(contents, expected);
+            let date = (time.year(), time.month(), time.day());
+            let clock = (time.hour(), time.minute(), time.second());
+            assert_eq!((date, clock), ((2021, 3, 4), (5, 6, 8)));
+        }
+    }
+
+    #[test]
+    fn times_before_1980_are_clamped() {
+        let time = zip_time(0);
+        assert_eq!((time.year(), time.month(), time.day()), (1980, 1, 1));
+        let time = zip_time(951_782_400); // 2000-02-29
+        assert_eq!((time.year(), time.month(), time.day()), (2000, 2, 29));
+    }
+
+    #[test]
+    fn more_files_than_the_original_format_can_count() {
+        let files = (0..70_000)
+            .map(|i| (format!("frames/{}.txt", i), Vec::new()))
+            .collect::<Vec<_>>();
+        let archive = ZipArchive::new(Cursor::new(zip(&files, 0).unwrap())).unwrap();
+        assert_eq!(archive.len(), 70_000);
+    }
+}
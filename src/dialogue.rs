@@ -0,0 +1,321 @@
+//
+// Dialogue
+//
+// Conversations are graphs of nodes.  Each node has a speaker, some text and
+// either a list of choices for the player or the id of the node that follows.
+// Choices can have a condition that decides whether they are offered, and
+// nodes and choices can have effects that run when they are reached.
+//
+// Conditions and effects are strings that the engine passes to the game
+// through DialogueHooks, so they can mean whatever the game wants, e.g.
+// "has:key" or "give:gold:10".
+//
+// With the content feature, dialogues are loaded from dialogue.ron by the
+// content registry:
+//
+//      {
+//          "innkeeper": (
+//              start: "hello",
+//              nodes: {
+//                  "hello": (
+//                      speaker: "Innkeeper",
+//                      text: "What'll it be?",
+//                      choices: [
+//                          (text: "A room.", next: Some("room")),
+//                          (text: "Use the key.", condition: Some("has:key"), next: Some("key")),
+//                          (text: "Nothing."),
+//                      ],
+//                  ),
+//                  ...
+//              },
+//          ),
+//      }
+//
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "content")]
+use crate::Content;
+#[cfg(feature = "content")]
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "content", derive(Deserialize))]
+pub struct DialogueChoice {
+    pub text: String,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub condition: Option<String>,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub effects: Vec<String>,
+    // None ends the conversation
+    #[cfg_attr(feature = "content", serde(default))]
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "content", derive(Deserialize))]
+pub struct DialogueNode {
+    #[cfg_attr(feature = "content", serde(default))]
+    pub speaker: String,
+    pub text: String,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub effects: Vec<String>,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub choices: Vec<DialogueChoice>,
+    // The node shown after this one when it has no choices.  None ends the
+    // conversation.
+    #[cfg_attr(feature = "content", serde(default))]
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "content", derive(Deserialize))]
+pub struct Dialogue {
+    pub start: String,
+    pub nodes: BTreeMap<String, DialogueNode>,
+}
+
+impl Dialogue {
+    pub fn new(start: &str) -> Self {
+        Dialogue {
+            start: String::from(start),
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_node(&mut self, id: &str, node: DialogueNode) -> &mut Self {
+        self.nodes.insert(String::from(id), node);
+        self
+    }
+
+    // Ids that are linked to but don't exist
+    pub fn missing_nodes(&self) -> Vec<String> {
+        let mut missing = std::iter::once(&self.start)
+            .chain(self.nodes.values().flat_map(|node| {
+                node.next
+                    .iter()
+                    .chain(node.choices.iter().filter_map(|c| c.next.as_ref()))
+            }))
+            .filter(|id| !self.nodes.contains_key(*id))
+            .cloned()
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+}
+
+#[cfg(feature = "content")]
+impl Content for Dialogue {
+    const KIND: &'static str = "dialogue";
+}
+
+// Implemented by the game to give meaning to conditions and effects
+pub trait DialogueHooks {
+    fn check(&self, _condition: &str) -> bool {
+        true
+    }
+
+    fn apply(&mut self, _effect: &str) {}
+}
+
+//
+// Conversations
+// Walks a dialogue, keeping track of the current node and which of its
+// choices are on offer.
+//
+
+pub struct Conversation {
+    dialogue: Dialogue,
+    current: Option<String>,
+    // Indices of the current node's choices whose conditions passed
+    available: Vec<usize>,
+}
+
+impl Conversation {
+    pub fn new(dialogue: &Dialogue, hooks: &mut dyn DialogueHooks) -> Self {
+        let mut conversation = Conversation {
+            dialogue: dialogue.clone(),
+            current: None,
+            available: Vec::new(),
+        };
+        conversation.enter(Some(dialogue.start.clone()), hooks);
+        conversation
+    }
+
+    fn enter(&mut self, id: Option<String>, hooks: &mut dyn DialogueHooks) {
+        self.current = id.filter(|id| self.dialogue.nodes.contains_key(id));
+        self.available.clear();
+        if let Some(node) = self.node() {
+            let effects = node.effects.clone();
+            let available = node
+                .choices
+                .iter()
+                .enumerate()
+                .filter(|(_, choice)| choice.condition.as_ref().is_none_or(|c| hooks.check(c)))
+                .map(|(i, _)| i)
+                .collect();
+            self.available = available;
+            effects.iter().for_each(|effect| hooks.apply(effect));
+        }
+    }
+
+    pub fn node_id(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    pub fn node(&self) -> Option<&DialogueNode> {
+        self.dialogue.nodes.get(self.current.as_ref()?)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current.is_none()
+    }
+
+    // The choices on offer, in order
+    pub fn choices(&self) -> Vec<&DialogueChoice> {
+        match self.node() {
+            Some(node) => self.available.iter().map(|&i| &node.choices[i]).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Picks one of the choices returned by choices().
+    pub fn choose(&mut self, index: usize, hooks: &mut dyn DialogueHooks) -> bool {
+        let choice = match (self.node(), self.available.get(index)) {
+            (Some(node), Some(&i)) => node.choices[i].clone(),
+            _ => return false,
+        };
+        choice.effects.iter().for_each(|effect| hooks.apply(effect));
+        self.enter(choice.next, hooks);
+        true
+    }
+
+    // Moves on from a node without choices.
+    pub fn advance(&mut self, hooks: &mut dyn DialogueHooks) -> bool {
+        match self.node() {
+            Some(node) if node.choices.is_empty() => {
+                let next = node.next.clone();
+                self.enter(next, hooks);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn end(&mut self) {
+        self.current = None;
+        self.available.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Passes conditions starting with "yes" and records the effects
+    #[derive(Default)]
+    struct Hooks {
+        effects: Vec<String>,
+    }
+
+    impl DialogueHooks for Hooks {
+        fn check(&self, condition: &str) -> bool {
+            condition.starts_with("yes")
+        }
+
+        fn apply(&mut self, effect: &str) {
+            self.effects.push(String::from(effect));
+        }
+    }
+
+    fn choice(text: &str, condition: Option<&str>, next: Option<&str>) -> DialogueChoice {
+        DialogueChoice {
+            text: String::from(text),
+            condition: condition.map(String::from),
+            effects: vec![format!("chose:{}", text)],
+            next: next.map(String::from),
+        }
+    }
+
+    fn node(text: &str, choices: Vec<DialogueChoice>, next: Option<&str>) -> DialogueNode {
+        DialogueNode {
+            speaker: String::from("Innkeeper"),
+            text: String::from(text),
+            effects: vec![format!("said:{}", text)],
+            choices,
+            next: next.map(String::from),
+        }
+    }
+
+    fn innkeeper() -> Dialogue {
+        let mut dialogue = Dialogue::new("hello");
+        dialogue
+            .with_node(
+                "hello",
+                node(
+                    "What'll it be?",
+                    vec![
+                        choice("A room.", None, Some("room")),
+                        choice("Use the key.", Some("no:key"), Some("key")),
+                        choice("Pay.", Some("yes:gold"), Some("thanks")),
+                        choice("Nothing.", None, None),
+                    ],
+                    None,
+                ),
+            )
+            .with_node("room", node("Upstairs.", Vec::new(), Some("bye")))
+            .with_node("bye", node("Sleep well.", Vec::new(), None));
+        dialogue
+    }
+
+    #[test]
+    fn missing_nodes_are_found() {
+        assert_eq!(innkeeper().missing_nodes(), ["key", "thanks"]);
+        assert_eq!(Dialogue::new("start").missing_nodes(), ["start"]);
+    }
+
+    #[test]
+    fn conversations_offer_the_choices_that_pass() {
+        let mut hooks = Hooks::default();
+        let mut conversation = Conversation::new(&innkeeper(), &mut hooks);
+        assert_eq!(conversation.node_id(), Some("hello"));
+        let choices = conversation
+            .choices()
+            .iter()
+            .map(|choice| choice.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(choices, ["A room.", "Pay.", "Nothing."]);
+        // Nodes with choices wait for one
+        assert!(!conversation.advance(&mut hooks));
+        assert!(!conversation.choose(3, &mut hooks));
+
+        assert!(conversation.choose(0, &mut hooks));
+        assert_eq!(conversation.node_id(), Some("room"));
+        assert!(conversation.advance(&mut hooks));
+        assert!(conversation.advance(&mut hooks));
+        assert!(conversation.is_finished());
+        assert!(conversation.choices().is_empty());
+        assert_eq!(
+            hooks.effects,
+            [
+                "said:What'll it be?",
+                "chose:A room.",
+                "said:Upstairs.",
+                "said:Sleep well."
+            ]
+        );
+    }
+
+    #[test]
+    fn links_to_missing_nodes_end_the_conversation() {
+        let mut hooks = Hooks::default();
+        let mut conversation = Conversation::new(&innkeeper(), &mut hooks);
+        assert!(conversation.choose(1, &mut hooks));
+        assert!(conversation.is_finished());
+
+        let mut conversation = Conversation::new(&innkeeper(), &mut hooks);
+        conversation.end();
+        assert_eq!(conversation.node(), None);
+    }
+}
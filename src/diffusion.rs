@@ -0,0 +1,159 @@
+//
+// Diffusion maps
+//
+// Values that spread out to neighbouring cells and fade away over time, such
+// as the scent a player leaves behind for monsters to follow, or heat and gas
+// spreading through a room.  Each turn, every passable cell moves part of the
+// way towards the average of its passable neighbours and then decays.
+//
+// The map updates in place, reusing a second buffer, so large maps can be
+// stepped every turn without allocating.
+//
+
+use crate::{Grid, Point};
+
+pub struct DiffusionMap {
+    values: Grid<f32>,
+    scratch: Grid<f32>,
+    spread: f32,
+    decay: f32,
+}
+
+impl DiffusionMap {
+    pub fn new(width: u32, height: u32) -> Self {
+        DiffusionMap {
+            values: Grid::new(width, height, 0.0),
+            scratch: Grid::new(width, height, 0.0),
+            spread: 0.5,
+            decay: 0.05,
+        }
+    }
+
+    // How far (0 to 1) a cell moves towards its neighbours' average each step
+    pub fn with_spread(&mut self, spread: f32) -> &mut Self {
+        self.spread = spread.clamp(0.0, 1.0);
+        self
+    }
+
+    // The fraction (0 to 1) of the value lost each step
+    pub fn with_decay(&mut self, decay: f32) -> &mut Self {
+        self.decay = decay.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn grid(&self) -> &Grid<f32> {
+        &self.values
+    }
+
+    pub fn clear(&mut self) {
+        self.values.fill(0.0);
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.values.reset(width, height, 0.0);
+        self.scratch.reset(width, height, 0.0);
+    }
+
+    pub fn value(&self, p: Point) -> f32 {
+        self.values.get(p).copied().unwrap_or(0.0)
+    }
+
+    pub fn set(&mut self, p: Point, value: f32) {
+        self.values.set(p, value);
+    }
+
+    // Adds to a cell, e.g. where the player is standing.
+    pub fn add(&mut self, p: Point, amount: f32) {
+        if let Some(value) = self.values.get_mut(p) {
+            *value += amount;
+        }
+    }
+
+    // Advances the map by one turn.  Impassable cells are emptied and take no
+    // part in spreading.
+    pub fn step(&mut self, passable: impl Fn(Point) -> bool) {
+        let keep = 1.0 - self.decay;
+        let spread = self.spread;
+        let values = &self.values;
+        for (i, new) in self.scratch.cells_mut().iter_mut().enumerate() {
+            let p = values.point(i);
+            if !passable(p) {
+                *new = 0.0;
+                continue;
+            }
+            let (total, count) = values
+                .neighbours(p)
+                .filter(|&q| passable(q))
+                .fold((0.0, 0), |(total, count), q| {
+                    (total + values.get(q).copied().unwrap_or(0.0), count + 1)
+                });
+            let value = values.cells()[i];
+            let target = if count > 0 {
+                total / count as f32
+            } else {
+                value
+            };
+            let value = (value + (target - value) * spread) * keep;
+            *new = if value < f32::EPSILON { 0.0 } else { value };
+        }
+        std::mem::swap(&mut self.values, &mut self.scratch);
+    }
+
+    // The neighbouring cell with the strongest value, for following a scent
+    // upwind.  None if no neighbour is stronger than here.
+    pub fn strongest_neighbour(&self, from: Point) -> Option<Point> {
+        let here = self.value(from);
+        self.values
+            .neighbours(from)
+            .map(|q| (self.value(q), q))
+            .filter(|&(v, _)| v > here)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, q)| q)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(map: &DiffusionMap) -> Vec<f32> {
+        map.grid().cells().to_vec()
+    }
+
+    #[test]
+    fn values_spread_to_passable_neighbours() {
+        let mut map = DiffusionMap::new(4, 1);
+        map.with_decay(0.0);
+        map.add(Point::new(1, 0), 10.0);
+        // Nothing passes the wall at x = 3
+        let passable = |p: Point| p.x != 3;
+        map.step(passable);
+        assert_eq!(row(&map), [5.0, 5.0, 5.0, 0.0]);
+        assert_eq!(
+            map.strongest_neighbour(Point::new(3, 0)),
+            Some(Point::new(2, 0))
+        );
+        assert_eq!(map.strongest_neighbour(Point::new(1, 0)), None);
+
+        map.set(Point::new(3, 0), 8.0);
+        map.step(passable);
+        assert_eq!(row(&map), [5.0, 5.0, 5.0, 0.0]);
+    }
+
+    #[test]
+    fn values_decay_away() {
+        let mut map = DiffusionMap::new(1, 1);
+        map.with_spread(1.0).with_decay(0.5);
+        map.set(Point::new(0, 0), 8.0);
+        map.step(|_| true);
+        assert_eq!(map.value(Point::new(0, 0)), 4.0);
+        for _ in 0..200 {
+            map.step(|_| true);
+        }
+        assert_eq!(map.value(Point::new(0, 0)), 0.0);
+
+        map.resize(2, 2);
+        assert_eq!(row(&map), [0.0; 4]);
+        assert_eq!(map.value(Point::new(5, 5)), 0.0);
+    }
+}
@@ -0,0 +1,135 @@
+//
+// Dijkstra maps
+//
+// A Dijkstra map holds, for every cell, the cost of the cheapest path to the
+// nearest goal.  Anything standing on the map can head for a goal by stepping
+// to the neighbour with the lowest value, so a single map serves every
+// monster chasing the player, or every item looking for a way to the stairs.
+// Movement is 8-way and diagonal steps cost the same as orthogonal ones.
+//
+
+use crate::{Grid, Point};
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+pub const UNREACHABLE: u32 = u32::MAX;
+
+pub struct DijkstraMap {
+    grid: Grid<u32>,
+}
+
+impl DijkstraMap {
+    pub fn new(width: u32, height: u32) -> Self {
+        DijkstraMap {
+            grid: Grid::new(width, height, UNREACHABLE),
+        }
+    }
+
+    pub fn grid(&self) -> &Grid<u32> {
+        &self.grid
+    }
+
+    // Recalculates the map in place.  Cells for which passable() is false are
+    // never entered, but goals are always reached.
+    pub fn compute(&mut self, goals: &[Point], passable: impl Fn(Point) -> bool) {
+        self.compute_with_costs(goals, |p| if passable(p) { Some(1) } else { None });
+    }
+
+    // As compute(), but each cell has a cost to enter (e.g. higher for
+    // shallow water), or None if it can't be entered.
+    pub fn compute_with_costs(&mut self, goals: &[Point], cost: impl Fn(Point) -> Option<u32>) {
+        self.grid.fill(UNREACHABLE);
+        let mut open = BinaryHeap::new();
+        for &goal in goals {
+            if self.grid.set(goal, 0) {
+                open.push(Reverse((0, goal.x, goal.y)));
+            }
+        }
+
+        while let Some(Reverse((distance, x, y))) = open.pop() {
+            let p = Point::new(x, y);
+            if self.grid.get(p).is_some_and(|&d| d < distance) {
+                continue;
+            }
+            let neighbours = self.grid.neighbours(p).collect::<Vec<_>>();
+            for q in neighbours {
+                let step = match cost(q) {
+                    Some(step) => step,
+                    None => continue,
+                };
+                let new = distance.saturating_add(step);
+                if let Some(old) = self.grid.get_mut(q) {
+                    if new < *old {
+                        *old = new;
+                        open.push(Reverse((new, q.x, q.y)));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn distance(&self, p: Point) -> Option<u32> {
+        self.grid.get(p).copied().filter(|&d| d != UNREACHABLE)
+    }
+
+    // The neighbour that is closest to a goal, or None if already at a goal
+    // or no goal can be reached.
+    pub fn next_step(&self, from: Point) -> Option<Point> {
+        let here = self.distance(from)?;
+        self.grid
+            .neighbours(from)
+            .filter_map(|q| self.distance(q).map(|d| (d, q)))
+            .filter(|&(d, _)| d < here)
+            .min_by_key(|&(d, _)| d)
+            .map(|(_, q)| q)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // '#' is a wall and '~' costs 5 to enter
+    fn map(rows: &[&str]) -> Grid<char> {
+        Grid::from_fn(rows[0].len() as u32, rows.len() as u32, |p| {
+            rows[p.y as usize].as_bytes()[p.x as usize] as char
+        })
+    }
+
+    #[test]
+    fn distances_go_around_walls() {
+        let map = map(&["....", "###.", "....", "#..."]);
+        let mut dijkstra = DijkstraMap::new(4, 4);
+        dijkstra.compute(&[Point::new(0, 0)], |p| map.get(p) != Some(&'#'));
+        let distances = (0..4)
+            .map(|x| dijkstra.distance(Point::new(x, 2)))
+            .collect::<Vec<_>>();
+        assert_eq!(distances, [Some(6), Some(5), Some(4), Some(4)]);
+        assert_eq!(dijkstra.distance(Point::new(0, 1)), None);
+        assert_eq!(dijkstra.distance(Point::new(9, 9)), None);
+
+        // Following the map leads to the goal
+        let mut p = Point::new(0, 2);
+        while let Some(next) = dijkstra.next_step(p) {
+            assert_eq!(dijkstra.distance(next), dijkstra.distance(p).map(|d| d - 1));
+            p = next;
+        }
+        assert_eq!(p, Point::new(0, 0));
+        assert_eq!(dijkstra.next_step(Point::new(0, 3)), None);
+    }
+
+    #[test]
+    fn costly_cells_are_avoided_and_goals_share_the_map() {
+        let map = map(&[".~.", "...", "..."]);
+        let mut dijkstra = DijkstraMap::new(3, 3);
+        dijkstra.compute_with_costs(&[Point::new(0, 0), Point::new(2, 2)], |p| {
+            match map.get(p) {
+                Some('~') => Some(5),
+                _ => Some(1),
+            }
+        });
+        assert_eq!(dijkstra.distance(Point::new(1, 0)), Some(5));
+        assert_eq!(dijkstra.distance(Point::new(2, 0)), Some(2));
+        assert_eq!(dijkstra.distance(Point::new(2, 2)), Some(0));
+        assert_eq!(dijkstra.next_step(Point::new(2, 0)), Some(Point::new(2, 1)));
+    }
+}
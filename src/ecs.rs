@@ -0,0 +1,721 @@
+//
+// Entity-component storage
+//
+// A small ECS for turn-based grid games.  Entities are indices into a
+// generational arena, so a stale Entity for a despawned thing never refers to
+// whatever reused its slot.  Components of any type can be attached to
+// entities and are stored per type.  The world also owns a turn scheduler and
+// a spatial index so that despawned entities stop taking turns and vanish from
+// the map.
+//
+// With the serde feature, a World can be saved with serde, e.g. with bincode
+// inside a SaveSchema save.  Components are only saved for the types given a
+// name with register_component(), which must be done on the loaded world as
+// well as the saved one:
+//
+//      world.register_component::<Health>("health")?;
+//
+// A loaded world keeps the saved components of each type until the type is
+// registered, so the order doesn't matter, and saving it again before then
+// keeps them.  The components are encoded with bincode whatever the world is
+// saved with.
+//
+
+#[cfg(feature = "serde")]
+use crate::{RogueError, RogueResult};
+use crate::{SpatialIndex, TurnScheduler};
+#[cfg(feature = "serde")]
+use serde::{
+    de::{self, DeserializeOwned},
+    ser, Deserialize, Deserializer, Serialize, Serializer,
+};
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+//
+// Component storage
+// Components are stored in a vector indexed by the entity's index, alongside
+// the generation of the entity that owns them.
+//
+
+trait AnyStorage {
+    fn remove_index(&mut self, index: u32);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+struct Storage<T> {
+    components: Vec<Option<(u32, T)>>,
+}
+
+impl<T> Storage<T> {
+    fn new() -> Self {
+        Storage {
+            components: Vec::new(),
+        }
+    }
+
+    fn get(&self, entity: Entity) -> Option<&T> {
+        match self.components.get(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => {
+                Some(component)
+            }
+            _ => None,
+        }
+    }
+
+    fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.components.get_mut(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => {
+                Some(component)
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        let index = entity.index as usize;
+        if index >= self.components.len() {
+            self.components.resize_with(index + 1, || None);
+        }
+        let old = self.components[index].replace((entity.generation, component));
+        old.filter(|(generation, _)| *generation == entity.generation)
+            .map(|(_, component)| component)
+    }
+
+    fn remove(&mut self, entity: Entity) -> Option<T> {
+        self.get(entity)?;
+        self.components[entity.index as usize]
+            .take()
+            .map(|(_, component)| component)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.components
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                slot.as_ref().map(|(generation, component)| {
+                    let entity = Entity {
+                        index: index as u32,
+                        generation: *generation,
+                    };
+                    (entity, component)
+                })
+            })
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.components
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                slot.as_mut().map(|(generation, component)| {
+                    let entity = Entity {
+                        index: index as u32,
+                        generation: *generation,
+                    };
+                    (entity, component)
+                })
+            })
+    }
+}
+
+impl<T: 'static> AnyStorage for Storage<T> {
+    fn remove_index(&mut self, index: u32) {
+        if let Some(slot) = self.components.get_mut(index as usize) {
+            *slot = None;
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+//
+// World
+//
+
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    // Dead slots whose generation has run out, which are never reused
+    retired: usize,
+    storages: HashMap<TypeId, Box<dyn AnyStorage>>,
+    scheduler: TurnScheduler,
+    spatial: SpatialIndex,
+    #[cfg(feature = "serde")]
+    codecs: HashMap<TypeId, Codec>,
+    // Saved components of types that haven't been registered yet, by name
+    #[cfg(feature = "serde")]
+    unregistered: BTreeMap<String, Vec<u8>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World {
+            generations: Vec::new(),
+            alive: Vec::new(),
+            free: Vec::new(),
+            retired: 0,
+            storages: HashMap::new(),
+            scheduler: TurnScheduler::new(),
+            spatial: SpatialIndex::new(),
+            #[cfg(feature = "serde")]
+            codecs: HashMap::new(),
+            #[cfg(feature = "serde")]
+            unregistered: BTreeMap::new(),
+        }
+    }
+
+    //
+    // Entities
+    //
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                let i = index as usize;
+                self.generations[i] += 1;
+                self.alive[i] = true;
+                Entity {
+                    index,
+                    generation: self.generations[i],
+                }
+            }
+            None => {
+                let index = self.generations.len() as u32;
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity {
+                    index,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    // Spawns an entity and returns a builder for adding its components.
+    pub fn build_entity(&mut self) -> EntityBuilder<'_> {
+        let entity = self.spawn();
+        EntityBuilder {
+            world: self,
+            entity,
+        }
+    }
+
+    // Removes the entity, its components, its position in the spatial index
+    // and any turns it has scheduled.
+    // Returns false if it had already been despawned.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        self.alive[entity.index as usize] = false;
+        // A slot whose generation has run out is retired rather than reused,
+        // so no stale Entity can ever match it again
+        if entity.generation < u32::MAX {
+            self.free.push(entity.index);
+        } else {
+            self.retired += 1;
+        }
+        self.storages
+            .values_mut()
+            .for_each(|storage| storage.remove_index(entity.index));
+        self.scheduler.remove(entity);
+        self.spatial.remove(entity);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let i = entity.index as usize;
+        i < self.alive.len() && self.alive[i] && self.generations[i] == entity.generation
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.alive
+            .iter()
+            .enumerate()
+            .filter(|(_, &alive)| alive)
+            .map(move |(index, _)| Entity {
+                index: index as u32,
+                generation: self.generations[index],
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.alive.len() - self.free.len() - self.retired
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn scheduler(&mut self) -> &mut TurnScheduler {
+        &mut self.scheduler
+    }
+
+    pub fn spatial(&self) -> &SpatialIndex {
+        &self.spatial
+    }
+
+    pub fn spatial_mut(&mut self) -> &mut SpatialIndex {
+        &mut self.spatial
+    }
+
+    //
+    // Components
+    //
+
+    fn storage<T: 'static>(&self) -> Option<&Storage<T>> {
+        self.storages
+            .get(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any().downcast_ref())
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> Option<&mut Storage<T>> {
+        self.storages
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|storage| storage.as_any_mut().downcast_mut())
+    }
+
+    // Attaches a component, returning the one it replaced.  Components cannot
+    // be attached to dead entities.
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Storage::<T>::new()))
+            .as_any_mut()
+            .downcast_mut::<Storage<T>>()
+            .and_then(|storage| storage.insert(entity, component))
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.storage_mut::<T>()?.remove(entity)
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        self.storage::<T>()?.get(entity)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.storage_mut::<T>()?.get_mut(entity)
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    //
+    // Queries
+    // To change components while looking at others, collect the entities
+    // first with `with` and then use get_mut.
+    //
+
+    pub fn query<T: 'static>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.storage::<T>()
+            .into_iter()
+            .flat_map(|storage| storage.iter())
+    }
+
+    pub fn query_mut<T: 'static>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.storage_mut::<T>()
+            .into_iter()
+            .flat_map(|storage| storage.iter_mut())
+    }
+
+    pub fn query2<A: 'static, B: 'static>(&self) -> impl Iterator<Item = (Entity, &A, &B)> {
+        let b = self.storage::<B>();
+        self.query::<A>()
+            .filter_map(move |(entity, a)| b?.get(entity).map(|b| (entity, a, b)))
+    }
+
+    // The entities that have a component of the given type
+    pub fn with<T: 'static>(&self) -> Vec<Entity> {
+        self.query::<T>().map(|(entity, _)| entity).collect()
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//
+// Saving
+//
+
+#[cfg(feature = "serde")]
+type EncodeFn = fn(&dyn AnyStorage) -> bincode::Result<Vec<u8>>;
+
+#[cfg(feature = "serde")]
+struct Codec {
+    name: String,
+    encode: EncodeFn,
+}
+
+#[cfg(feature = "serde")]
+fn encode_storage<T: Serialize + 'static>(storage: &dyn AnyStorage) -> bincode::Result<Vec<u8>> {
+    let components = storage
+        .as_any()
+        .downcast_ref::<Storage<T>>()
+        .map(|storage| storage.iter().collect::<Vec<_>>())
+        .unwrap_or_default();
+    bincode::serialize(&components)
+}
+
+#[cfg(feature = "serde")]
+impl World {
+    // Saves components of the type under the name, and loads any the world
+    // was loaded with under that name.  Each type needs its own name.
+    pub fn register_component<T>(&mut self, name: &str) -> RogueResult<()>
+    where
+        T: Serialize + DeserializeOwned + 'static,
+    {
+        let bad = |message: String| RogueError::BadComponents {
+            component: String::from(name),
+            message,
+        };
+        let type_id = TypeId::of::<T>();
+        let taken = self
+            .codecs
+            .iter()
+            .any(|(&other, codec)| other != type_id && codec.name == name);
+        if taken {
+            return Err(bad(String::from("another type has the name")));
+        }
+        // Nothing changes unless the saved components decode, so they aren't
+        // lost to a mistake such as registering the wrong type
+        let components = match self.unregistered.get(name) {
+            Some(data) => {
+                bincode::deserialize::<Vec<(Entity, T)>>(data).map_err(|e| bad(e.to_string()))?
+            }
+            None => Vec::new(),
+        };
+        self.unregistered.remove(name);
+        self.codecs.insert(
+            type_id,
+            Codec {
+                name: String::from(name),
+                encode: encode_storage::<T>,
+            },
+        );
+
+        // Components of entities despawned since loading are dropped
+        for (entity, component) in components {
+            self.insert(entity, component);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct SavedWorldRef<'a> {
+    generations: &'a [u32],
+    alive: &'a [bool],
+    free: &'a [u32],
+    scheduler: &'a TurnScheduler,
+    spatial: &'a SpatialIndex,
+    components: BTreeMap<&'a str, Vec<u8>>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SavedWorld {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    scheduler: TurnScheduler,
+    spatial: SpatialIndex,
+    components: BTreeMap<String, Vec<u8>>,
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for World {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut components = self
+            .unregistered
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.clone()))
+            .collect::<BTreeMap<_, _>>();
+        for (type_id, codec) in &self.codecs {
+            if let Some(storage) = self.storages.get(type_id) {
+                let data = (codec.encode)(storage.as_ref()).map_err(ser::Error::custom)?;
+                components.insert(codec.name.as_str(), data);
+            }
+        }
+        SavedWorldRef {
+            generations: &self.generations,
+            alive: &self.alive,
+            free: &self.free,
+            scheduler: &self.scheduler,
+            spatial: &self.spatial,
+            components,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for World {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedWorld::deserialize(deserializer)?;
+
+        // The free list must be exactly the dead entities that aren't retired
+        if saved.alive.len() != saved.generations.len() {
+            return Err(de::Error::custom("the saved entities don't add up"));
+        }
+        let mut free = saved.free.clone();
+        free.sort_unstable();
+        let (retired, dead): (Vec<_>, Vec<_>) = saved
+            .alive
+            .iter()
+            .enumerate()
+            .filter(|(_, &alive)| !alive)
+            .map(|(index, _)| index as u32)
+            .partition(|&index| saved.generations[index as usize] == u32::MAX);
+        if free != dead {
+            return Err(de::Error::custom("the saved entities don't add up"));
+        }
+
+        Ok(World {
+            generations: saved.generations,
+            alive: saved.alive,
+            free: saved.free,
+            retired: retired.len(),
+            storages: HashMap::new(),
+            scheduler: saved.scheduler,
+            spatial: saved.spatial,
+            codecs: HashMap::new(),
+            unregistered: saved.components,
+        })
+    }
+}
+
+//
+// EntityBuilder
+//
+
+pub struct EntityBuilder<'a> {
+    world: &'a mut World,
+    entity: Entity,
+}
+
+impl<'a> EntityBuilder<'a> {
+    pub fn with<T: 'static>(&mut self, component: T) -> &mut Self {
+        self.world.insert(self.entity, component);
+        self
+    }
+
+    pub fn build(&mut self) -> Entity {
+        self.entity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point;
+
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    struct Health(i32);
+
+    #[derive(Debug, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    struct Name(String);
+
+    #[test]
+    fn stale_entities_never_see_a_reused_slot() {
+        let mut world = World::new();
+        let rat = world.build_entity().with(Health(3)).build();
+        assert!(world.despawn(rat));
+        assert!(!world.despawn(rat));
+
+        let bat = world.spawn();
+        assert_eq!(bat.index(), rat.index());
+        assert_ne!(bat.generation(), rat.generation());
+        assert!(!world.is_alive(rat) && world.is_alive(bat));
+        assert_eq!(world.get::<Health>(bat), None);
+        assert_eq!(world.insert(rat, Health(1)), None);
+        assert_eq!(world.get::<Health>(bat), None);
+        assert_eq!(world.len(), 1);
+    }
+
+    #[test]
+    fn slots_are_retired_when_their_generations_run_out() {
+        let mut world = World::new();
+        let old = world.spawn();
+        world.generations[0] = u32::MAX - 1;
+        world.despawn(Entity {
+            index: 0,
+            generation: u32::MAX - 1,
+        });
+        let last = world.spawn();
+        assert_eq!((last.index(), last.generation()), (0, u32::MAX));
+        assert!(world.despawn(last));
+
+        let next = world.spawn();
+        assert_eq!(next.index(), 1);
+        assert!(!world.is_alive(old) && !world.is_alive(last));
+        assert_eq!(world.len(), 1);
+        assert_eq!(world.entities().collect::<Vec<_>>(), vec![next]);
+    }
+
+    #[test]
+    fn components_are_stored_per_type() {
+        let mut world = World::new();
+        let hero = world
+            .build_entity()
+            .with(Health(10))
+            .with(Name(String::from("hero")))
+            .build();
+        let wall = world.build_entity().with(Health(99)).build();
+
+        assert_eq!(world.insert(hero, Health(8)), Some(Health(10)));
+        world.get_mut::<Health>(wall).unwrap().0 -= 1;
+        assert_eq!(world.with::<Health>(), vec![hero, wall]);
+        assert_eq!(
+            world
+                .query2::<Health, Name>()
+                .map(|(entity, health, _)| (entity, health.0))
+                .collect::<Vec<_>>(),
+            vec![(hero, 8)]
+        );
+        for (_, health) in world.query_mut::<Health>() {
+            health.0 += 1;
+        }
+        assert_eq!(world.remove::<Health>(wall), Some(Health(99)));
+        assert!(!world.has::<Health>(wall) && world.has::<Name>(hero));
+    }
+
+    #[test]
+    fn despawning_leaves_the_map_and_the_turn_order() {
+        let mut world = World::new();
+        let (a, b) = (world.spawn(), world.spawn());
+        world.spatial_mut().insert(a, Point::new(1, 1));
+        world.spatial_mut().insert(b, Point::new(1, 1));
+        world.scheduler().schedule(a, 1);
+        world.scheduler().schedule(b, 2);
+
+        world.despawn(a);
+        assert_eq!(world.spatial().entities_at(Point::new(1, 1)), &[b]);
+        assert_eq!(world.scheduler().next_turn(), Some(b));
+        assert_eq!(world.entities().collect::<Vec<_>>(), vec![b]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn worlds_round_trip_through_a_save() {
+        use crate::SaveSchema;
+
+        let mut world = World::new();
+        world.register_component::<Health>("health").unwrap();
+        world.register_component::<Name>("name").unwrap();
+        let gone = world.build_entity().with(Health(1)).build();
+        let hero = world
+            .build_entity()
+            .with(Health(10))
+            .with(Name(String::from("hero")))
+            .build();
+        let rat = world.build_entity().with(Health(2)).build();
+        world.despawn(gone);
+        world.spatial_mut().insert(hero, Point::new(3, 4));
+        world.scheduler().schedule(rat, 5);
+        world.scheduler().schedule(hero, 7);
+
+        let path = std::env::temp_dir().join(format!("mage-world-{}.sav", std::process::id()));
+        let saves = SaveSchema::new(1);
+        saves
+            .save(&path, &bincode::serialize(&world).unwrap())
+            .unwrap();
+        let data = saves.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut loaded = bincode::deserialize::<World>(&data).unwrap();
+
+        // Until a type is registered its components stay saved, not lost
+        assert_eq!(loaded.get::<Health>(hero), None);
+        let resaved = bincode::deserialize::<World>(&bincode::serialize(&loaded).unwrap());
+        loaded = resaved.unwrap();
+        loaded.register_component::<Health>("health").unwrap();
+        loaded.register_component::<Name>("name").unwrap();
+
+        assert_eq!(loaded.entities().collect::<Vec<_>>(), vec![hero, rat]);
+        assert!(!loaded.is_alive(gone));
+        assert_eq!(loaded.get::<Health>(hero), Some(&Health(10)));
+        assert_eq!(loaded.get::<Name>(hero), Some(&Name(String::from("hero"))));
+        assert_eq!(loaded.get::<Health>(rat), Some(&Health(2)));
+        assert_eq!(loaded.spatial().position(hero), Some(Point::new(3, 4)));
+        assert_eq!(loaded.scheduler().next_turn(), Some(rat));
+        assert_eq!(loaded.scheduler().next_turn(), Some(hero));
+        // The reused slot still gets a new generation
+        let next = loaded.spawn();
+        assert_eq!(next.index(), gone.index());
+        assert_ne!(next.generation(), gone.generation());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn bad_saved_worlds_are_refused() {
+        let mut world = World::new();
+        world.register_component::<Health>("health").unwrap();
+        assert!(world.register_component::<Name>("health").is_err());
+        let a = world.build_entity().with(Health(1)).build();
+
+        // Components saved as another type
+        let data = bincode::serialize(&world).unwrap();
+        let mut loaded = bincode::deserialize::<World>(&data).unwrap();
+        assert!(matches!(
+            loaded.register_component::<Name>("health"),
+            Err(RogueError::BadComponents { .. })
+        ));
+        // which leaves them to be registered as the right type
+        loaded.register_component::<Health>("health").unwrap();
+        assert_eq!(loaded.get::<Health>(a), Some(&Health(1)));
+
+        // Components of an entity despawned before the type was registered
+        let mut loaded = bincode::deserialize::<World>(&data).unwrap();
+        loaded.despawn(a);
+        let b = loaded.spawn();
+        loaded.register_component::<Health>("health").unwrap();
+        assert_eq!(loaded.get::<Health>(b), None);
+
+        // A free list that doesn't match the dead entities
+        world.despawn(a);
+        world.free.push(7);
+        let data = bincode::serialize(&world).unwrap();
+        assert!(bincode::deserialize::<World>(&data).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//
+// Text effects
+//
+// Time-driven ways of drawing text: typewriter reveals, scrolling marquees and
+// colour fades.  Effects can be updated and drawn by the game directly, or
+// handed to the engine's Animator to be driven automatically.
+//
+
+use crate::{lerp_colour, Image, Point};
+use std::time::Duration;
+
+pub trait Effect {
+    fn update(&mut self, dt: Duration);
+    fn draw(&self, image: &mut Image);
+    fn is_finished(&self) -> bool;
+}
+
+//
+// Typewriter
+// Reveals text one character at a time.
+//
+
+pub struct Typewriter {
+    position: Point,
+    text: String,
+    ink: u32,
+    paper: u32,
+    delay: Duration,
+    elapsed: Duration,
+}
+
+impl Typewriter {
+    pub fn new(position: Point, text: &str, ink: u32, paper: u32, delay: Duration) -> Self {
+        Typewriter {
+            position,
+            text: String::from(text),
+            ink,
+            paper,
+            delay,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn visible_chars(&self) -> usize {
+        if self.delay.is_zero() {
+            self.text.len()
+        } else {
+            (self.elapsed.as_nanos() / self.delay.as_nanos()) as usize
+        }
+    }
+
+    // Reveal the whole text immediately, e.g. when the player presses a key.
+    pub fn skip(&mut self) {
+        self.elapsed = self.delay * self.text.chars().count() as u32;
+    }
+}
+
+impl Effect for Typewriter {
+    fn update(&mut self, dt: Duration) {
+        self.elapsed += dt;
+    }
+
+    fn draw(&self, image: &mut Image) {
+        let end = self
+            .text
+            .char_indices()
+            .nth(self.visible_chars())
+            .map_or(self.text.len(), |(i, _)| i);
+        image.draw_string(self.position, &self.text[..end], self.ink, self.paper);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.visible_chars() >= self.text.chars().count()
+    }
+}
+
+//
+// Marquee
+// Scrolls text that is too long for its width, wrapping around with a gap.
+// Text that fits is drawn as is.  Marquees never finish.
+//
+
+pub struct Marquee {
+    position: Point,
+    width: u32,
+    text: String,
+    ink: u32,
+    paper: u32,
+    step: Duration,
+    elapsed: Duration,
+}
+
+// Number of spaces between the end of the text and its start wrapping around
+const MARQUEE_GAP: usize = 4;
+
+impl Marquee {
+    pub fn new(
+        position: Point,
+        width: u32,
+        text: &str,
+        ink: u32,
+        paper: u32,
+        step: Duration,
+    ) -> Self {
+        Marquee {
+            position,
+            width,
+            text: String::from(text),
+            ink,
+            paper,
+            step,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl Effect for Marquee {
+    fn update(&mut self, dt: Duration) {
+        self.elapsed += dt;
+    }
+
+    fn draw(&self, image: &mut Image) {
+        let width = self.width as usize;
+        let chars = self.text.chars().collect::<Vec<_>>();
+        let visible = if chars.len() <= width || self.step.is_zero() {
+            chars.iter().take(width).collect::<String>()
+        } else {
+            let cycle = chars.len() + MARQUEE_GAP;
+            let offset = (self.elapsed.as_nanos() / self.step.as_nanos()) as usize % cycle;
+            (0..width)
+                .map(|i| *chars.get((offset + i) % cycle).unwrap_or(&' '))
+                .collect::<String>()
+        };
+        image.draw_string(self.position, &visible, self.ink, self.paper);
+    }
+
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+//
+// Fade
+// Blends the text's ink between two colours over time.  Fading in from the
+// paper colour makes text appear out of the background.
+//
+
+pub struct Fade {
+    position: Point,
+    text: String,
+    from: u32,
+    to: u32,
+    paper: u32,
+    duration: Duration,
+    elapsed: Duration,
+}
+
+impl Fade {
+    pub fn new(
+        position: Point,
+        text: &str,
+        from: u32,
+        to: u32,
+        paper: u32,
+        duration: Duration,
+    ) -> Self {
+        Fade {
+            position,
+            text: String::from(text),
+            from,
+            to,
+            paper,
+            duration,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn fade_in(position: Point, text: &str, ink: u32, paper: u32, duration: Duration) -> Self {
+        Fade::new(position, text, paper, ink, paper, duration)
+    }
+
+    pub fn fade_out(position: Point, text: &str, ink: u32, paper: u32, duration: Duration) -> Self {
+        Fade::new(position, text, ink, paper, paper, duration)
+    }
+
+    pub fn ink(&self) -> u32 {
+        if self.duration.is_zero() {
+            self.to
+        } else {
+            let t = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+            lerp_colour(self.from, self.to, t)
+        }
+    }
+}
+
+impl Effect for Fade {
+    fn update(&mut self, dt: Duration) {
+        self.elapsed += dt;
+    }
+
+    fn draw(&self, image: &mut Image) {
+        image.draw_string(self.position, &self.text, self.ink(), self.paper);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // The text an effect draws on a blank line
+    fn drawn(effect: &dyn Effect, width: u32) -> String {
+        let mut image = Image::new(width, 1);
+        image.clear(0, 0);
+        effect.draw(&mut image);
+        image
+            .text_image
+            .iter()
+            .map(|&code| image.glyph_char(code))
+            .collect()
+    }
+
+    #[test]
+    fn typewriters_reveal_a_character_at_a_time() {
+        let mut typewriter = Typewriter::new(Point::new(0, 0), "héllo", 0, 0, ms(10));
+        assert_eq!(drawn(&typewriter, 6), "      ");
+        typewriter.update(ms(25));
+        assert_eq!(drawn(&typewriter, 6), "hé    ");
+        assert!(!typewriter.is_finished());
+        typewriter.skip();
+        assert!(typewriter.is_finished());
+        assert_eq!(drawn(&typewriter, 6), "héllo ");
+
+        let instant = Typewriter::new(Point::new(1, 0), "hi", 0, 0, Duration::ZERO);
+        assert!(instant.is_finished());
+        assert_eq!(drawn(&instant, 3), " hi");
+    }
+
+    #[test]
+    fn marquees_scroll_text_that_doesnt_fit() {
+        let mut marquee = Marquee::new(Point::new(0, 0), 4, "abcdef", 0, 0, ms(10));
+        assert_eq!(drawn(&marquee, 4), "abcd");
+        marquee.update(ms(30));
+        assert_eq!(drawn(&marquee, 4), "def ");
+        // Round the gap and back to the start
+        marquee.update(ms(70));
+        assert_eq!(drawn(&marquee, 4), "abcd");
+        assert!(!marquee.is_finished());
+
+        let mut short = Marquee::new(Point::new(0, 0), 4, "ab", 0, 0, ms(10));
+        short.update(ms(10));
+        assert_eq!(drawn(&short, 4), "ab  ");
+    }
+
+    #[test]
+    fn fades_blend_the_ink() {
+        let mut fade = Fade::fade_in(Point::new(0, 0), "x", 0xff00_00ff, 0xff00_0000, ms(100));
+        assert_eq!(fade.ink(), 0xff00_0000);
+        fade.update(ms(50));
+        assert_eq!(fade.ink(), 0xff00_0080);
+        assert!(!fade.is_finished());
+        fade.update(ms(100));
+        assert_eq!(fade.ink(), 0xff00_00ff);
+        assert!(fade.is_finished());
+
+        let out = Fade::fade_out(Point::new(0, 0), "x", 1, 2, Duration::ZERO);
+        assert_eq!(out.ink(), 2);
+        assert!(out.is_finished());
+    }
+}
@@ -0,0 +1,97 @@
+//
+// Event bus
+//
+// A typed message queue shared by the engine and the game.  Any type can be
+// published as an event, and each type has its own queue.  Events stay queued
+// until they are drained, so a system that publishes during one tick can be
+// read by another system later in the same tick or in the next one.
+//
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+pub struct EventBus {
+    queues: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            queues: HashMap::new(),
+        }
+    }
+
+    fn queue_mut<E: 'static>(&mut self) -> &mut Vec<E> {
+        self.queues
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(Vec::<E>::new()))
+            .downcast_mut()
+            .expect("Event queue has the wrong type")
+    }
+
+    pub fn publish<E: 'static>(&mut self, event: E) {
+        self.queue_mut().push(event);
+    }
+
+    // The queued events of a type, oldest first, without removing them
+    pub fn read<E: 'static>(&self) -> &[E] {
+        self.queues
+            .get(&TypeId::of::<E>())
+            .and_then(|queue| queue.downcast_ref::<Vec<E>>())
+            .map_or(&[], |queue| queue.as_slice())
+    }
+
+    // Removes and returns the queued events of a type, oldest first.
+    pub fn drain<E: 'static>(&mut self) -> Vec<E> {
+        std::mem::take(self.queue_mut())
+    }
+
+    pub fn clear(&mut self) {
+        self.queues.clear();
+    }
+}
+
+// Published by the engine when the main window's grid changes size, e.g.
+// after a resize, a zoom or when the large font option changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridResized {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Damage(u32);
+
+    #[test]
+    fn each_type_has_its_own_queue() {
+        let mut bus = EventBus::new();
+        assert!(bus.read::<Damage>().is_empty());
+        bus.publish(Damage(3));
+        bus.publish(GridResized {
+            width: 80,
+            height: 25,
+        });
+        bus.publish(Damage(5));
+
+        // Reading leaves the events queued, draining removes them
+        assert_eq!(bus.read::<Damage>(), [Damage(3), Damage(5)]);
+        assert_eq!(bus.drain::<Damage>(), [Damage(3), Damage(5)]);
+        assert!(bus.drain::<Damage>().is_empty());
+        assert_eq!(bus.read::<GridResized>().len(), 1);
+
+        bus.clear();
+        assert!(bus.read::<GridResized>().is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+//
+// Autoexplore
+//
+// Walks the player towards the nearest unexplored cell they can reach, one
+// step per call, until the map is done or something interesting comes into
+// view.  The game keeps track of which cells have been seen and passes in the
+// points of interest (items, monsters, stairs) currently visible; each one
+// interrupts exploring the first time it is reported.
+//
+
+use crate::{DijkstraMap, Grid, Point};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExploreStep {
+    // Move to this neighbouring cell
+    Move(Point),
+    // These points of interest have just been seen
+    Interrupted(Vec<Point>),
+    // Nothing reachable is left to explore
+    Done,
+}
+
+pub struct AutoExplore {
+    map: DijkstraMap,
+    noticed: HashSet<Point>,
+}
+
+impl AutoExplore {
+    pub fn new() -> Self {
+        AutoExplore {
+            map: DijkstraMap::new(0, 0),
+            noticed: HashSet::new(),
+        }
+    }
+
+    // Forgets the points of interest already reported, e.g. on a new level.
+    pub fn reset(&mut self) {
+        self.noticed.clear();
+    }
+
+    // `explored` is true for cells the player has seen.  `passable` is only
+    // asked about explored cells.
+    pub fn next_step(
+        &mut self,
+        from: Point,
+        explored: &Grid<bool>,
+        passable: impl Fn(Point) -> bool,
+        interests: &[Point],
+    ) -> ExploreStep {
+        let new = interests
+            .iter()
+            .copied()
+            .filter(|&p| self.noticed.insert(p))
+            .collect::<Vec<_>>();
+        if !new.is_empty() {
+            return ExploreStep::Interrupted(new);
+        }
+
+        if self.map.grid().width() != explored.width()
+            || self.map.grid().height() != explored.height()
+        {
+            self.map = DijkstraMap::new(explored.width(), explored.height());
+        }
+        let goals = explored
+            .iter()
+            .filter(|(_, &seen)| !seen)
+            .map(|(p, _)| p)
+            .collect::<Vec<_>>();
+        self.map.compute(&goals, |p| {
+            explored.get(p).copied().unwrap_or(false) && passable(p)
+        });
+
+        match self.map.next_step(from) {
+            Some(p) => ExploreStep::Move(p),
+            None => ExploreStep::Done,
+        }
+    }
+}
+
+impl Default for AutoExplore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exploring_heads_for_the_nearest_unseen_cell() {
+        // A corridor seen up to x = 3, with a wall at (2, 1)
+        let explored = Grid::from_fn(6, 3, |p| p.x <= 3);
+        let passable = |p: Point| p != Point::new(2, 1);
+        let mut explore = AutoExplore::new();
+        assert_eq!(
+            explore.next_step(Point::new(0, 1), &explored, passable, &[]),
+            ExploreStep::Move(Point::new(1, 1))
+        );
+        assert_eq!(
+            explore.next_step(Point::new(3, 1), &explored, passable, &[]),
+            ExploreStep::Move(Point::new(4, 1))
+        );
+
+        let explored = Grid::new(6, 3, true);
+        assert_eq!(
+            explore.next_step(Point::new(0, 1), &explored, passable, &[]),
+            ExploreStep::Done
+        );
+    }
+
+    #[test]
+    fn points_of_interest_interrupt_once() {
+        let explored = Grid::from_fn(4, 1, |p| p.x < 3);
+        let mut explore = AutoExplore::new();
+        let (a, b) = (Point::new(2, 0), Point::new(1, 0));
+        let step = |explore: &mut AutoExplore, interests: &[Point]| {
+            explore.next_step(Point::new(0, 0), &explored, |_| true, interests)
+        };
+        assert_eq!(step(&mut explore, &[a]), ExploreStep::Interrupted(vec![a]));
+        assert_eq!(
+            step(&mut explore, &[a, b]),
+            ExploreStep::Interrupted(vec![b])
+        );
+        assert_eq!(step(&mut explore, &[a, b]), ExploreStep::Move(b));
+        explore.reset();
+        assert_eq!(step(&mut explore, &[a]), ExploreStep::Interrupted(vec![a]));
+    }
+}
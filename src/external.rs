@@ -0,0 +1,238 @@
+//
+// External input
+//
+// Input from outside the window merged into SimInput, e.g. commands from a
+// stream's chat so the audience can play, or a harness driving the game in
+// tests.  An InputSource is polled once per tick and returns what has
+// arrived since, without blocking:
+//
+//      Key         pressed for one tick, as if typed.  Keys queue up, and the
+//                  keyboard goes first when both press a key in the same tick.
+//                  At most MAX_PENDING_KEYS wait, and the oldest are dropped
+//                  beyond that, so a flood of chat can't back up the game.
+//      Text        added to SimInput::text
+//      Command     published on the event bus for the game to interpret, e.g.
+//                  a vote for the next move
+//
+// ChannelSource suits sources that run on their own thread, such as a chat
+// client: it hands out Senders that any thread can push input into.
+//
+//      let (source, sender) = ChannelSource::new("chat");
+//      ctx.add_input_source(source);
+//      thread::spawn(move || read_chat(sender));
+//
+
+use crate::{EventBus, Key};
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+// Keys waiting to be pressed are dropped, oldest first, beyond this
+const MAX_PENDING_KEYS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExternalKey {
+    pub key: Key,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl ExternalKey {
+    pub fn new(key: Key) -> Self {
+        ExternalKey {
+            key,
+            shift: false,
+            ctrl: false,
+            alt: false,
+        }
+    }
+}
+
+// Published on the event bus.  source is the name of the InputSource, and from
+// who sent it there, e.g. a chat user's name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalCommand {
+    pub source: String,
+    pub from: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExternalInput {
+    Key(ExternalKey),
+    Text(String),
+    Command { from: String, text: String },
+}
+
+pub trait InputSource {
+    fn name(&self) -> &str;
+
+    // Must not block
+    fn poll(&mut self) -> Vec<ExternalInput>;
+}
+
+pub struct ChannelSource {
+    name: String,
+    receiver: Receiver<ExternalInput>,
+}
+
+impl ChannelSource {
+    pub fn new(name: &str) -> (Self, Sender<ExternalInput>) {
+        let (sender, receiver) = channel();
+        let source = ChannelSource {
+            name: String::from(name),
+            receiver,
+        };
+        (source, sender)
+    }
+}
+
+impl InputSource for ChannelSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn poll(&mut self) -> Vec<ExternalInput> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+//
+// ExternalInputs
+// The sources added to the context, and what they sent that hasn't reached
+// the game yet.
+//
+
+pub(crate) struct ExternalInputs {
+    sources: Vec<Box<dyn InputSource>>,
+    keys: VecDeque<ExternalKey>,
+    text: String,
+}
+
+impl ExternalInputs {
+    pub fn new() -> Self {
+        ExternalInputs {
+            sources: Vec::new(),
+            keys: VecDeque::new(),
+            text: String::new(),
+        }
+    }
+
+    pub fn add_source(&mut self, source: Box<dyn InputSource>) {
+        self.sources.push(source);
+    }
+
+    pub fn remove_source(&mut self, name: &str) {
+        self.sources.retain(|source| source.name() != name);
+    }
+
+    pub fn poll(&mut self, events: &mut EventBus) {
+        for source in self.sources.iter_mut() {
+            for input in source.poll() {
+                match input {
+                    ExternalInput::Key(key) => {
+                        if self.keys.len() == MAX_PENDING_KEYS {
+                            self.keys.pop_front();
+                        }
+                        self.keys.push_back(key);
+                    }
+                    ExternalInput::Text(text) => self.text.push_str(&text),
+                    ExternalInput::Command { from, text } => events.publish(ExternalCommand {
+                        source: String::from(source.name()),
+                        from,
+                        text,
+                    }),
+                }
+            }
+        }
+    }
+
+    pub fn next_key(&mut self) -> Option<ExternalKey> {
+        self.keys.pop_front()
+    }
+
+    pub fn take_text(&mut self) -> String {
+        std::mem::take(&mut self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_feed_keys_text_and_commands() {
+        let (chat, sender) = ChannelSource::new("chat");
+        let (harness, other) = ChannelSource::new("harness");
+        let mut inputs = ExternalInputs::new();
+        inputs.add_source(Box::new(chat));
+        inputs.add_source(Box::new(harness));
+        let mut events = EventBus::new();
+
+        let thread = std::thread::spawn(move || {
+            sender
+                .send(ExternalInput::Key(ExternalKey::new(Key::Up)))
+                .unwrap();
+            sender
+                .send(ExternalInput::Text(String::from("he")))
+                .unwrap();
+            sender
+                .send(ExternalInput::Command {
+                    from: String::from("ann"),
+                    text: String::from("!left"),
+                })
+                .unwrap();
+        });
+        thread.join().unwrap();
+        other
+            .send(ExternalInput::Key(ExternalKey::new(Key::Down)))
+            .unwrap();
+        other
+            .send(ExternalInput::Text(String::from("llo")))
+            .unwrap();
+        inputs.poll(&mut events);
+
+        assert_eq!(inputs.next_key(), Some(ExternalKey::new(Key::Up)));
+        assert_eq!(inputs.next_key(), Some(ExternalKey::new(Key::Down)));
+        assert_eq!(inputs.next_key(), None);
+        assert_eq!(inputs.take_text(), "hello");
+        assert_eq!(inputs.take_text(), "");
+        assert_eq!(
+            events.drain::<ExternalCommand>(),
+            [ExternalCommand {
+                source: String::from("chat"),
+                from: String::from("ann"),
+                text: String::from("!left"),
+            }]
+        );
+
+        inputs.remove_source("harness");
+        // The removed source is dropped, closing its channel
+        assert!(other
+            .send(ExternalInput::Text(String::from("gone")))
+            .is_err());
+    }
+
+    #[test]
+    fn the_oldest_keys_are_dropped_when_too_many_wait() {
+        let (source, sender) = ChannelSource::new("chat");
+        let mut inputs = ExternalInputs::new();
+        inputs.add_source(Box::new(source));
+        let mut events = EventBus::new();
+
+        sender
+            .send(ExternalInput::Key(ExternalKey::new(Key::Up)))
+            .unwrap();
+        for _ in 1..=MAX_PENDING_KEYS {
+            sender
+                .send(ExternalInput::Key(ExternalKey::new(Key::Down)))
+                .unwrap();
+        }
+        inputs.poll(&mut events);
+
+        let keys = std::iter::from_fn(|| inputs.next_key()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![ExternalKey::new(Key::Down); MAX_PENDING_KEYS]);
+    }
+}
@@ -0,0 +1,241 @@
+//
+// Fallback glyphs
+//
+// The main font only has the 256 characters of code page 437.  Characters
+// it doesn't have are looked up, in order, in the fallback fonts added with
+// Glyphs::add_fallback_font() or RogueBuilder::with_fallback_font(), e.g. a
+// symbols font.  Like the main font, each is a 16x16 sheet of glyphs,
+// together with the characters the glyphs are for, in the sheet's order.
+//
+// Glyphs are only copied into the font atlas the first time they are drawn.
+// They get the codes after 255, which are stored in the red and green
+// channels of the text image, and are added to the atlas below the main font,
+// scaled to its cell size.
+//
+// Each main font has its own table of fallback fonts and codes, a Glyphs,
+// which clones of the font share.  The image a window hands to the game
+// shares its font's table, so codes drawn into it are the ones the window's
+// atlas has.  Other images have their own table until given one with
+// Image::set_glyphs(), and blitting between images with different tables
+// looks the characters up again in the destination's.
+//
+
+use crate::{char_to_cp437_code, cp437_to_char, RogueFontData};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+// Codes have 16 bits, the first 256 of which are the main font
+pub const MAX_GLYPHS: u32 = 0x10000;
+
+struct FallbackFont {
+    font: RogueFontData,
+    chars: Vec<char>,
+}
+
+#[derive(Default)]
+struct GlyphAtlas {
+    fonts: Vec<FallbackFont>,
+    // Characters looked up so far, and their codes if a font had them
+    codes: BTreeMap<char, Option<u32>>,
+    // The (font, glyph) copied into each code after 255
+    slots: Vec<(usize, usize)>,
+    // Double-width characters in the main font, and the code of their left
+    // halves
+    wide: HashMap<char, u8>,
+    chars: Vec<char>,
+    // Changes whenever a glyph is added, so the renderer knows to rebuild
+    generation: u64,
+}
+
+//
+// Glyphs
+// A font's fallback fonts and the codes given out so far.  Clones share the
+// same table.
+//
+
+#[derive(Clone, Default)]
+pub struct Glyphs {
+    atlas: Arc<Mutex<GlyphAtlas>>,
+}
+
+impl Glyphs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A panic while the table was locked can't leave it inconsistent, so a
+    // poisoned lock is used anyway.
+    fn lock(&self) -> MutexGuard<'_, GlyphAtlas> {
+        self.atlas.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Whether the two share a table, and so give out the same codes
+    pub fn is_shared_with(&self, other: &Glyphs) -> bool {
+        Arc::ptr_eq(&self.atlas, &other.atlas)
+    }
+
+    // Adds a font to the end of the fallback chain.  chars lists the
+    // characters of the glyphs, left to right and top to bottom; any beyond
+    // 256 are ignored.
+    pub fn add_fallback_font(&self, font: RogueFontData, chars: &str) {
+        let mut atlas = self.lock();
+        atlas.fonts.push(FallbackFont {
+            font,
+            chars: chars.chars().take(256).collect(),
+        });
+        // Characters that were missing might be in the new font
+        atlas.codes.retain(|_, code| code.is_some());
+    }
+
+    // The code to draw a character with: its code page 437 code, or one from
+    // a fallback font, or None if no font has it.
+    pub fn code(&self, ch: char) -> Option<u32> {
+        if let Some(code) = char_to_cp437_code(ch) {
+            return Some(code as u32);
+        }
+        let mut atlas = self.lock();
+        if let Some(&code) = atlas.codes.get(&ch) {
+            return code;
+        }
+        let found = atlas.fonts.iter().enumerate().find_map(|(font, fallback)| {
+            fallback
+                .chars
+                .iter()
+                .position(|&c| c == ch)
+                .map(|glyph| (font, glyph))
+        });
+        let code = match found {
+            Some(slot) if 256 + (atlas.slots.len() as u32) < MAX_GLYPHS => {
+                atlas.slots.push(slot);
+                atlas.chars.push(ch);
+                atlas.generation += 1;
+                Some(255 + atlas.slots.len() as u32)
+            }
+            _ => None,
+        };
+        atlas.codes.insert(ch, code);
+        code
+    }
+
+    // The character drawn with a code
+    pub fn char(&self, code: u32) -> char {
+        match code {
+            0..=255 => cp437_to_char(code as u8),
+            _ => self
+                .lock()
+                .chars
+                .get(code as usize - 256)
+                .copied()
+                .unwrap_or(' '),
+        }
+    }
+
+    // The left half of the character's glyph is at code in the main font and
+    // the right half at code + 1.  See present.rs.
+    pub fn register_wide_glyph(&self, ch: char, code: u8) {
+        self.lock().wide.insert(ch, code);
+    }
+
+    pub(crate) fn wide_glyph(&self, ch: char) -> Option<u8> {
+        self.lock().wide.get(&ch).copied()
+    }
+
+    // Changes whenever a glyph is added
+    pub(crate) fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+}
+
+// Builds the font atlas: the main font followed by rows of its fallback
+// glyphs, 16 to a row.  Rows that would make it taller than max_height pixels
+// are left out, and their glyphs are drawn blank.  Returns the pixels and the
+// atlas size.
+pub(crate) fn build_atlas(font: &RogueFontData, max_height: u32) -> (Vec<u32>, (u32, u32)) {
+    let atlas = font.glyphs.lock();
+    let (fonts, slots) = (&atlas.fonts[..], &atlas.slots[..]);
+
+    let width = 16 * font.width;
+    let wanted_rows = 16 + (slots.len() as u32).div_ceil(16);
+    let rows = wanted_rows.min((max_height / font.height).max(16));
+    if rows < wanted_rows {
+        log::warn!("The font atlas is full, so some fallback glyphs won't be drawn");
+    }
+    let height = rows * font.height;
+    let mut data = vec![0; (width * height) as usize];
+    data[..font.data.len()].copy_from_slice(&font.data);
+
+    for (i, &(source, glyph)) in slots.iter().enumerate() {
+        let code = 256 + i as u32;
+        let (x, y) = ((code % 16) * font.width, (code / 16) * font.height);
+        if y + font.height > height {
+            break;
+        }
+        // Nearest-neighbour scaling to the main font's cell size
+        let source = &fonts[source].font;
+        let source_x = (glyph as u32 % 16) * source.width;
+        let source_y = (glyph as u32 / 16) * source.height;
+        let source_stride = 16 * source.width;
+        for dy in 0..font.height {
+            let sy = source_y + dy * source.height / font.height;
+            for dx in 0..font.width {
+                let sx = source_x + dx * source.width / font.width;
+                data[((y + dy) * width + x + dx) as usize] =
+                    source.data[(sy * source_stride + sx) as usize];
+            }
+        }
+    }
+
+    (data, (width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Image, Point};
+
+    fn symbols() -> RogueFontData {
+        RogueFontData {
+            data: vec![0; 256],
+            width: 1,
+            height: 1,
+            glyphs: Glyphs::new(),
+        }
+    }
+
+    #[test]
+    fn each_table_has_its_own_codes() {
+        let (a, b) = (Glyphs::new(), Glyphs::new());
+        a.add_fallback_font(symbols(), "★☆");
+        b.add_fallback_font(symbols(), "☆★");
+
+        assert_eq!(a.code('A'), Some(b'A' as u32));
+        assert_eq!(a.code('☆'), Some(256));
+        assert_eq!(b.code('★'), Some(256));
+        assert_eq!(a.char(256), '☆');
+        assert_eq!(b.char(256), '★');
+        assert_eq!(Glyphs::new().code('★'), None);
+        assert!(a.clone().is_shared_with(&a) && !a.is_shared_with(&b));
+    }
+
+    #[test]
+    fn blitting_looks_codes_up_in_the_destination() {
+        let (a, b) = (Glyphs::new(), Glyphs::new());
+        a.add_fallback_font(symbols(), "★☆");
+        b.add_fallback_font(symbols(), "☆");
+        let mut sprite = Image::new(2, 1);
+        sprite.set_glyphs(&a);
+        sprite.draw_string(Point::new(0, 0), "☆★", 0, 0);
+
+        let mut screen = Image::new(2, 1);
+        screen.set_glyphs(&b);
+        screen.blit(Point::new(0, 0), 2, 1, &sprite);
+        assert_eq!(screen.glyph_char(screen.text_image[0]), '☆');
+        // The destination's fonts don't have the star
+        assert_eq!(screen.text_image[1], b'?' as u32);
+
+        sprite.set_glyphs(&b);
+        assert_eq!(sprite.glyph_char(sprite.text_image[0]), '☆');
+    }
+}
@@ -0,0 +1,175 @@
+//
+// Golden frames
+//
+// For regression tests of screens and generated maps that don't need a GPU.
+// A frame is dumped as plain text in three parts, separated by "--" lines:
+//
+//      the characters, one line per row
+//      a letter per cell naming its ink and paper colours
+//      the legend of those letters, e.g. "a ffffff/000000" (ink/paper)
+//
+// and compared with a file checked in next to the tests.  Running the tests
+// with UPDATE_GOLDEN=1 in the environment writes the files instead.
+//
+
+use crate::{Game, Image, PresentInput};
+use std::{fmt::Write, path::Path};
+
+// Cell size given to the game's present(), which only matters to games that
+// look at pixel sizes
+const CELL_SIZE: u32 = 8;
+
+const LEGEND_LETTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+// Runs the game's present() on an image of the given size in cells.
+pub fn capture_frame(game: &dyn Game, width: u32, height: u32) -> Image {
+    let mut image = Image::new(width, height);
+    game.present(PresentInput {
+        width,
+        height,
+        cell_width: CELL_SIZE,
+        cell_height: CELL_SIZE,
+        pixel_width: width * CELL_SIZE,
+        pixel_height: height * CELL_SIZE,
+        scale_factor: 1.0,
+        image: &mut image,
+    });
+    image
+}
+
+pub fn golden_text(image: &Image) -> String {
+    let mut text = String::new();
+    let rows = (0..image.height as usize).map(|y| {
+        let start = y * image.width as usize;
+        start..start + image.width as usize
+    });
+
+    for row in rows.clone() {
+        let line = image.text_image[row]
+            .iter()
+            .map(|&code| image.glyph_char(code))
+            .collect::<String>();
+        let _ = writeln!(text, "{}", line.trim_end());
+    }
+
+    // Colour pairs get letters in the order they first appear.  Beyond the
+    // 62 letters, cells are marked '?'.
+    text.push_str("--\n");
+    let mut legend = Vec::<(u32, u32)>::new();
+    for row in rows {
+        for i in row {
+            let pair = (image.fore_image[i], image.back_image[i]);
+            let index = legend.iter().position(|&p| p == pair).unwrap_or_else(|| {
+                legend.push(pair);
+                legend.len() - 1
+            });
+            text.push(LEGEND_LETTERS.chars().nth(index).unwrap_or('?'));
+        }
+        text.push('\n');
+    }
+
+    text.push_str("--\n");
+    for (letter, (ink, paper)) in LEGEND_LETTERS.chars().zip(legend) {
+        let _ = writeln!(text, "{} {}/{}", letter, hex_colour(ink), hex_colour(paper));
+    }
+    text
+}
+
+fn hex_colour(colour: u32) -> String {
+    let (r, g, b) = (colour & 0xff, (colour >> 8) & 0xff, (colour >> 16) & 0xff);
+    format!("{:02x}{:02x}{:02x}", r, g, b)
+}
+
+// Compares an image with a golden file, returning a diff of the lines that
+// differ if they don't match.
+pub fn compare_golden(image: &Image, path: &Path) -> Result<(), String> {
+    let actual = golden_text(image);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        return std::fs::write(path, actual)
+            .map_err(|e| format!("Unable to write {}: {}", path.display(), e));
+    }
+    let expected = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "Unable to read {}: {} (run with UPDATE_GOLDEN=1 to create it)",
+            path.display(),
+            e
+        )
+    })?;
+    if expected == actual {
+        return Ok(());
+    }
+
+    let mut diff = format!("{} doesn't match:\n", path.display());
+    let expected_lines = expected.lines().collect::<Vec<_>>();
+    let actual_lines = actual.lines().collect::<Vec<_>>();
+    for i in 0..expected_lines.len().max(actual_lines.len()) {
+        let (e, a) = (expected_lines.get(i), actual_lines.get(i));
+        if e != a {
+            let _ = writeln!(diff, "line {}:", i + 1);
+            if let Some(e) = e {
+                let _ = writeln!(diff, "- {}", e);
+            }
+            if let Some(a) = a {
+                let _ = writeln!(diff, "+ {}", a);
+            }
+        }
+    }
+    Err(diff)
+}
+
+// Panics with the diff if the image doesn't match the golden file.
+pub fn assert_golden(image: &Image, path: &Path) {
+    if let Err(diff) = compare_golden(image, path) {
+        panic!("{}", diff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_colour, Point, SimInput, TickResult};
+
+    struct Greeting;
+
+    impl Game for Greeting {
+        fn start(&mut self) {}
+
+        fn tick(&mut self, _sim_input: SimInput) -> TickResult {
+            TickResult::Continue
+        }
+
+        fn present(&self, present_input: PresentInput) {
+            let image = present_input.image;
+            image.clear(new_colour(255, 255, 255), 0);
+            image.draw_string(Point::new(1, 0), "Hi", new_colour(255, 0, 0), 0);
+        }
+    }
+
+    const GREETING: &str = " Hi\n\n--\nabba\naaaa\n--\na ffffff/000000\nb ff0000/000000\n";
+
+    #[test]
+    fn frames_dump_characters_and_colours() {
+        let image = capture_frame(&Greeting, 4, 2);
+        assert_eq!(golden_text(&image), GREETING);
+    }
+
+    #[test]
+    fn mismatches_list_the_lines_that_differ() {
+        let path = std::env::temp_dir().join(format!("mage-golden-{}.txt", std::process::id()));
+        let image = capture_frame(&Greeting, 4, 2);
+        let missing = compare_golden(&image, &path).unwrap_err();
+        assert!(missing.contains("UPDATE_GOLDEN=1"));
+
+        std::fs::write(&path, GREETING).unwrap();
+        let matched = compare_golden(&image, &path);
+        std::fs::write(&path, GREETING.replace("Hi", "Ho")).unwrap();
+        let diff = compare_golden(&image, &path).unwrap_err();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(matched, Ok(()));
+        assert_eq!(
+            diff,
+            format!("{} doesn't match:\nline 1:\n-  Ho\n+  Hi\n", path.display())
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//
+// Grid
+//
+// A rectangle of values, one per map cell, stored row by row.  Used for maps
+// and for the per-cell data computed from them (distances, noise, scent...).
+//
+// Grids can be saved with serde, when the serde feature is on, or in a compact
+// binary format for caching generated levels and storing them in save games:
+//
+//      "MGRD", a version byte, the width and height as little-endian u32s,
+//      then runs of cells as (count, value) byte pairs, row by row
+//
+// The game says how to turn its cells into bytes and back, e.g. a tile enum
+// to its index.
+//
+
+use crate::{Point, RogueError, RogueResult};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+const GRID_MAGIC: &[u8; 4] = b"MGRD";
+const GRID_VERSION: u8 = 1;
+
+// The eight neighbouring directions, orthogonal ones first
+pub const DIRECTIONS: [Point; 8] = [
+    Point { x: 0, y: -1 },
+    Point { x: 1, y: 0 },
+    Point { x: 0, y: 1 },
+    Point { x: -1, y: 0 },
+    Point { x: 1, y: -1 },
+    Point { x: 1, y: 1 },
+    Point { x: -1, y: 1 },
+    Point { x: -1, y: -1 },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "GridData<T>"))]
+pub struct Grid<T> {
+    width: u32,
+    height: u32,
+    cells: Vec<T>,
+}
+
+// What a grid is deserialised from, checked before it becomes one so a bad
+// file can't give a grid whose cells don't match its size
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+struct GridData<T> {
+    width: u32,
+    height: u32,
+    cells: Vec<T>,
+}
+
+impl<T> TryFrom<GridData<T>> for Grid<T> {
+    type Error = RogueError;
+
+    fn try_from(data: GridData<T>) -> RogueResult<Self> {
+        let size = data.width as u64 * data.height as u64;
+        if data.cells.len() as u64 != size {
+            return Err(RogueError::BadMapData(format!(
+                "{} cells for a {}x{} grid",
+                data.cells.len(),
+                data.width,
+                data.height
+            )));
+        }
+        Ok(Grid {
+            width: data.width,
+            height: data.height,
+            cells: data.cells,
+        })
+    }
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(width: u32, height: u32, value: T) -> Self {
+        Grid {
+            width,
+            height,
+            cells: vec![value; (width * height) as usize],
+        }
+    }
+
+    pub fn from_fn(width: u32, height: u32, f: impl Fn(Point) -> T) -> Self {
+        let cells = (0..height as i32)
+            .flat_map(|y| (0..width as i32).map(move |x| Point::new(x, y)))
+            .map(f)
+            .collect();
+        Grid {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.cells.iter_mut().for_each(|cell| *cell = value.clone());
+    }
+
+    // Changes the size, filling every cell with the value.
+    pub fn reset(&mut self, width: u32, height: u32, value: T) {
+        self.width = width;
+        self.height = height;
+        self.cells.clear();
+        self.cells.resize((width * height) as usize, value);
+    }
+}
+
+impl<T> Grid<T> {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Bytes used by the cells, not counting any memory they own
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.cells.capacity() * std::mem::size_of::<T>()
+    }
+
+    pub fn in_bounds(&self, p: Point) -> bool {
+        p.x >= 0 && p.y >= 0 && (p.x as u32) < self.width && (p.y as u32) < self.height
+    }
+
+    pub fn index(&self, p: Point) -> Option<usize> {
+        if self.in_bounds(p) {
+            Some((p.y as u32 * self.width + p.x as u32) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn point(&self, index: usize) -> Point {
+        let width = self.width.max(1) as usize;
+        Point::new((index % width) as i32, (index / width) as i32)
+    }
+
+    pub fn get(&self, p: Point) -> Option<&T> {
+        self.index(p).map(|i| &self.cells[i])
+    }
+
+    pub fn get_mut(&mut self, p: Point) -> Option<&mut T> {
+        self.index(p).map(move |i| &mut self.cells[i])
+    }
+
+    // Returns false if the point is outside the grid.
+    pub fn set(&mut self, p: Point, value: T) -> bool {
+        match self.get_mut(p) {
+            Some(cell) => {
+                *cell = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cells(&self) -> &[T] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [T] {
+        &mut self.cells
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Point, &T)> {
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(i, cell)| (self.point(i), cell))
+    }
+
+    // The in-bounds neighbours of a point, orthogonal ones first
+    pub fn neighbours(&self, p: Point) -> impl Iterator<Item = Point> + '_ {
+        DIRECTIONS
+            .iter()
+            .map(move |d| Point::new(p.x + d.x, p.y + d.y))
+            .filter(move |&q| self.in_bounds(q))
+    }
+
+    // Saves the grid in the binary format, turning each cell into a byte.
+    pub fn encode(&self, cell: impl Fn(&T) -> u8) -> Vec<u8> {
+        let mut data = Vec::with_capacity(13 + self.cells.len() / 4);
+        data.extend_from_slice(GRID_MAGIC);
+        data.push(GRID_VERSION);
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+
+        let mut run: Option<(u8, u8)> = None;
+        for value in self.cells.iter().map(cell) {
+            run = match run {
+                Some((count, v)) if v == value && count < u8::MAX => Some((count + 1, v)),
+                Some((count, v)) => {
+                    data.extend_from_slice(&[count, v]);
+                    Some((1, value))
+                }
+                None => Some((1, value)),
+            };
+        }
+        if let Some((count, v)) = run {
+            data.extend_from_slice(&[count, v]);
+        }
+        data
+    }
+
+    // Loads a grid saved by encode(), turning each byte back into a cell.
+    pub fn decode(data: &[u8], cell: impl Fn(u8) -> T) -> RogueResult<Grid<T>> {
+        let bad = |message: &str| RogueError::BadMapData(message.to_string());
+        if data.len() < 13 || &data[..4] != GRID_MAGIC {
+            return Err(bad("not a map"));
+        }
+        if data[4] != GRID_VERSION {
+            return Err(bad("unknown version"));
+        }
+        let width = u32::from_le_bytes([data[5], data[6], data[7], data[8]]);
+        let height = u32::from_le_bytes([data[9], data[10], data[11], data[12]]);
+        let size = width as u64 * height as u64;
+
+        let runs = &data[13..];
+        if runs.len() % 2 != 0 {
+            return Err(bad("truncated"));
+        }
+        // Check the size before allocating so a corrupt header can't ask for
+        // gigabytes
+        let total = runs.chunks(2).map(|run| run[0] as u64).sum::<u64>();
+        if total != size {
+            return Err(bad("the cells don't match the size"));
+        }
+
+        let mut cells = Vec::with_capacity(size as usize);
+        for run in runs.chunks(2) {
+            cells.extend((0..run[0]).map(|_| cell(run[1])));
+        }
+        Ok(Grid {
+            width,
+            height,
+            cells,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_are_stored_row_by_row() {
+        let mut grid = Grid::from_fn(3, 2, |p| p.x + p.y * 10);
+        assert_eq!(grid.cells(), [0, 1, 2, 10, 11, 12]);
+        assert_eq!(grid.index(Point::new(2, 1)), Some(5));
+        assert_eq!(grid.point(5), Point::new(2, 1));
+        assert_eq!(grid.get(Point::new(3, 0)), None);
+        assert_eq!(grid.get(Point::new(0, -1)), None);
+        assert!(grid.set(Point::new(1, 1), 99));
+        assert!(!grid.set(Point::new(1, 2), 99));
+        assert_eq!(
+            grid.iter().find(|(_, &v)| v == 99).unwrap().0,
+            Point::new(1, 1)
+        );
+
+        grid.reset(1, 4, 7);
+        assert_eq!((grid.width(), grid.height()), (1, 4));
+        assert_eq!(grid.cells(), [7; 4]);
+    }
+
+    #[test]
+    fn neighbours_stay_in_bounds() {
+        let grid = Grid::new(3, 3, ());
+        assert_eq!(grid.neighbours(Point::new(1, 1)).count(), 8);
+        assert_eq!(
+            grid.neighbours(Point::new(0, 0)).collect::<Vec<_>>(),
+            [Point::new(1, 0), Point::new(0, 1), Point::new(1, 1)]
+        );
+    }
+
+    #[test]
+    fn grids_encode_as_runs() {
+        let grid = Grid::from_fn(3, 2, |p| if p.y == 1 && p.x > 0 { 'b' } else { 'a' });
+        let data = grid.encode(|&ch| ch as u8);
+        assert_eq!(&data[..5], b"MGRD\x01");
+        assert_eq!(&data[5..13], [3, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(&data[13..], [4, b'a', 2, b'b']);
+        assert_eq!(Grid::decode(&data, char::from).unwrap(), grid);
+
+        // Runs longer than a byte can count are split
+        let long = Grid::new(300, 1, 7u8);
+        let data = long.encode(|&v| v);
+        assert_eq!(&data[13..], [255, 7, 45, 7]);
+        assert_eq!(Grid::decode(&data, |v| v).unwrap(), long);
+    }
+
+    #[test]
+    fn bad_map_data_is_rejected() {
+        let data = Grid::new(2, 2, 1u8).encode(|&v| v);
+        let decode = |data: &[u8]| Grid::decode(data, |v| v).unwrap_err().to_string();
+        assert!(decode(b"MGRD").contains("not a map"));
+        assert!(decode(&[b"XGRD", &data[4..]].concat()).contains("not a map"));
+        assert!(decode(&[&data[..4], &[2], &data[5..]].concat()).contains("unknown version"));
+        assert!(decode(&data[..data.len() - 1]).contains("truncated"));
+        assert!(decode(&[&data[..], &[1, 1]].concat()).contains("don't match"));
+    }
+
+    #[cfg(feature = "content")]
+    #[test]
+    fn deserialised_grids_must_match_their_size() {
+        let grid: Grid<u8> = ron::from_str("(width: 2, height: 1, cells: [1, 2])").unwrap();
+        assert_eq!(grid.cells(), [1, 2]);
+        assert_eq!(
+            ron::to_string(&grid).unwrap(),
+            "(width:2,height:1,cells:[1,2])"
+        );
+        assert!(ron::from_str::<Grid<u8>>("(width: 2, height: 2, cells: [1])").is_err());
+    }
+}
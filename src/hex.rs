@@ -0,0 +1,322 @@
+//
+// Hex grids
+//
+// Coordinates for maps of hexagons with pointy tops, laid out in rows.  Hex
+// uses axial coordinates (q along a row, r down the rows), which make
+// distances, lines and ranges simple.  Hex maps are stored in an ordinary
+// Grid using "odd-r" offset coordinates, where odd rows are pushed half a hex
+// to the right:
+//
+//       0 1 2 3            row 0
+//        0 1 2 3           row 1
+//       0 1 2 3            row 2
+//
+// On screen each hex takes two cells of a row, so the map comes out in the
+// same staggered shape.
+//
+
+use crate::{Char, Grid, Image, Point};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Hex {
+    pub q: i32,
+    pub r: i32,
+}
+
+// The six neighbouring directions, clockwise from the east
+pub const HEX_DIRECTIONS: [Hex; 6] = [
+    Hex { q: 1, r: 0 },
+    Hex { q: 1, r: -1 },
+    Hex { q: 0, r: -1 },
+    Hex { q: -1, r: 0 },
+    Hex { q: -1, r: 1 },
+    Hex { q: 0, r: 1 },
+];
+
+impl Hex {
+    pub fn new(q: i32, r: i32) -> Self {
+        Hex { q, r }
+    }
+
+    // The third cube coordinate, so that q + r + s = 0
+    pub fn s(&self) -> i32 {
+        -self.q - self.r
+    }
+
+    pub fn from_offset(p: Point) -> Self {
+        Hex::new(p.x - (p.y - (p.y & 1)) / 2, p.y)
+    }
+
+    pub fn to_offset(self) -> Point {
+        Point::new(self.q + (self.r - (self.r & 1)) / 2, self.r)
+    }
+
+    pub fn neighbour(self, direction: usize) -> Self {
+        let d = HEX_DIRECTIONS[direction % 6];
+        Hex::new(self.q + d.q, self.r + d.r)
+    }
+
+    pub fn neighbours(self) -> impl Iterator<Item = Hex> {
+        (0..6).map(move |d| self.neighbour(d))
+    }
+
+    pub fn distance(self, other: Hex) -> u32 {
+        let (dq, dr, ds) = (self.q - other.q, self.r - other.r, self.s() - other.s());
+        ((dq.abs() + dr.abs() + ds.abs()) / 2) as u32
+    }
+
+    // Every hex on the straight line between two hexes, including both ends
+    pub fn line_to(self, other: Hex) -> Vec<Hex> {
+        let n = self.distance(other);
+        if n == 0 {
+            return vec![self];
+        }
+        // Nudging the ends slightly keeps the line off hex edges, where
+        // rounding would go either way.
+        let (aq, ar) = (self.q as f32 + 1e-6, self.r as f32 + 2e-6);
+        let (bq, br) = (other.q as f32 + 1e-6, other.r as f32 + 2e-6);
+        (0..=n)
+            .map(|i| {
+                let t = i as f32 / n as f32;
+                hex_round(aq + (bq - aq) * t, ar + (br - ar) * t)
+            })
+            .collect()
+    }
+
+    // Every hex within a distance, including this one
+    pub fn range(self, radius: u32) -> Vec<Hex> {
+        let n = radius as i32;
+        (-n..=n)
+            .flat_map(|dq| {
+                let low = (-n).max(-dq - n);
+                let high = n.min(-dq + n);
+                (low..=high).map(move |dr| Hex::new(self.q + dq, self.r + dr))
+            })
+            .collect()
+    }
+
+    // The hexes at exactly a distance, clockwise
+    pub fn ring(self, radius: u32) -> Vec<Hex> {
+        if radius == 0 {
+            return vec![self];
+        }
+        let mut hex = Hex::new(
+            self.q + HEX_DIRECTIONS[4].q * radius as i32,
+            self.r + HEX_DIRECTIONS[4].r * radius as i32,
+        );
+        let mut ring = Vec::with_capacity(6 * radius as usize);
+        for direction in 0..6 {
+            for _ in 0..radius {
+                ring.push(hex);
+                hex = hex.neighbour(direction);
+            }
+        }
+        ring
+    }
+}
+
+fn hex_round(q: f32, r: f32) -> Hex {
+    let s = -q - r;
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+    Hex::new(rq as i32, rr as i32)
+}
+
+//
+// Field of view
+// A hex is visible if the line to it from the origin isn't blocked.  Opaque
+// hexes (walls) are visible themselves but hide what's behind them.
+//
+
+pub fn hex_fov(origin: Hex, radius: u32, opaque: impl Fn(Hex) -> bool) -> HashSet<Hex> {
+    origin
+        .range(radius)
+        .into_iter()
+        .filter(|&target| {
+            let line = origin.line_to(target);
+            line.iter()
+                .skip(1)
+                .take(line.len().saturating_sub(2))
+                .all(|&hex| !opaque(hex))
+        })
+        .collect()
+}
+
+//
+// Path finding
+// A* over hexes.  Each hex has a cost to enter, or None if it can't be
+// entered.  Returns the steps after `from` up to and including `to`.
+//
+
+pub fn hex_path(from: Hex, to: Hex, cost: impl Fn(Hex) -> Option<u32>) -> Option<Vec<Hex>> {
+    let mut open = BinaryHeap::new();
+    let mut came_from = HashMap::new();
+    let mut costs = HashMap::new();
+    costs.insert(from, 0u32);
+    open.push((Reverse(from.distance(to)), Reverse(0u32), from.q, from.r));
+
+    while let Some((_, Reverse(so_far), q, r)) = open.pop() {
+        let hex = Hex::new(q, r);
+        if hex == to {
+            let mut path = vec![to];
+            let mut current = to;
+            while let Some(&previous) = came_from.get(&current) {
+                if previous == from {
+                    break;
+                }
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(if from == to { Vec::new() } else { path });
+        }
+        if costs.get(&hex).is_some_and(|&c| c < so_far) {
+            continue;
+        }
+        for next in hex.neighbours() {
+            let step = match cost(next) {
+                Some(step) => step,
+                None => continue,
+            };
+            let new = so_far + step;
+            if costs.get(&next).is_none_or(|&old| new < old) {
+                costs.insert(next, new);
+                came_from.insert(next, hex);
+                open.push((
+                    Reverse(new + next.distance(to)),
+                    Reverse(new),
+                    next.q,
+                    next.r,
+                ));
+            }
+        }
+    }
+    None
+}
+
+//
+// Drawing
+// Each hex is drawn in the left of a pair of cells, with odd rows shifted
+// one cell to the right.
+//
+
+pub fn hex_to_screen(hex: Hex, origin: Point) -> Point {
+    let p = hex.to_offset();
+    Point::new(origin.x + p.x * 2 + (p.y & 1), origin.y + p.y)
+}
+
+// The hex drawn at (or just left of) a screen cell, e.g. under the mouse
+pub fn screen_to_hex(p: Point, origin: Point) -> Hex {
+    let row = p.y - origin.y;
+    let col = (p.x - origin.x - (row & 1)).div_euclid(2);
+    Hex::from_offset(Point::new(col, row))
+}
+
+impl Image {
+    // Draws a hex map stored in odd-r offset coordinates.  The cells between
+    // hexes are left as they are.
+    pub fn draw_hex_map<T>(&mut self, origin: Point, map: &Grid<T>, f: impl Fn(Hex, &T) -> Char) {
+        for (p, cell) in map.iter() {
+            let hex = Hex::from_offset(p);
+            self.draw_char(hex_to_screen(hex, origin), f(hex, cell));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_coordinates_convert_both_ways() {
+        for y in -3..4 {
+            for x in -3..4 {
+                let p = Point::new(x, y);
+                assert_eq!(Hex::from_offset(p).to_offset(), p);
+            }
+        }
+        // Odd rows are pushed right, so the hex below and right of (1, 0) is
+        // (1, 1) in offset coordinates
+        let hex = Hex::from_offset(Point::new(1, 0));
+        assert_eq!(hex.neighbour(5).to_offset(), Point::new(1, 1));
+        assert_eq!(hex.s(), -1);
+    }
+
+    #[test]
+    fn distances_lines_and_rings_agree() {
+        let origin = Hex::new(0, 0);
+        let target = Hex::new(3, -1);
+        assert_eq!(origin.distance(target), 3);
+        let line = origin.line_to(target);
+        assert_eq!(line.len(), 4);
+        assert_eq!((line[0], line[3]), (origin, target));
+        assert!(line.windows(2).all(|w| w[0].distance(w[1]) == 1));
+        assert_eq!(origin.line_to(origin), [origin]);
+
+        assert_eq!(origin.range(2).len(), 19);
+        assert!(origin.range(2).iter().all(|h| origin.distance(*h) <= 2));
+        let ring = origin.ring(2);
+        assert_eq!(ring.len(), 12);
+        assert!(ring.iter().all(|h| origin.distance(*h) == 2));
+        assert_eq!(origin.ring(0), [origin]);
+    }
+
+    #[test]
+    fn walls_hide_what_is_behind_them() {
+        let wall = Hex::new(1, 0);
+        let seen = hex_fov(Hex::new(0, 0), 3, |hex| hex == wall);
+        assert!(seen.contains(&wall));
+        assert!(!seen.contains(&Hex::new(2, 0)));
+        assert!(!seen.contains(&Hex::new(3, 0)));
+        assert!(seen.contains(&Hex::new(0, 3)));
+        // Only hexes beyond the wall are hidden
+        let hidden = Hex::new(0, 0)
+            .range(3)
+            .into_iter()
+            .filter(|hex| !seen.contains(hex))
+            .collect::<Vec<_>>();
+        assert_eq!(hidden.len(), 5);
+        assert!(hidden.iter().all(|hex| hex.q >= 2), "{:?}", hidden);
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let (from, to) = (Hex::new(0, 0), Hex::new(3, 0));
+        let walls = [Hex::new(1, 0), Hex::new(2, 0), Hex::new(2, -1)];
+        let cost = |hex: Hex| (!walls.contains(&hex) && hex.distance(from) <= 4).then_some(1);
+        let path = hex_path(from, to, cost).unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!(path.last(), Some(&to));
+        assert_eq!(from.distance(path[0]), 1);
+        assert!(path.windows(2).all(|w| w[0].distance(w[1]) == 1));
+        assert!(path.iter().all(|hex| !walls.contains(hex)));
+
+        assert_eq!(hex_path(from, from, cost), Some(Vec::new()));
+        assert_eq!(hex_path(from, to, |hex| (hex == from).then_some(1)), None);
+    }
+
+    #[test]
+    fn hexes_are_drawn_staggered() {
+        let map = Grid::from_fn(3, 2, |p| b'a' + (p.y * 3 + p.x) as u8);
+        let mut image = Image::new(8, 2);
+        image.clear(0, 0);
+        image.draw_hex_map(Point::new(1, 0), &map, |_, &ch| Char::new(ch, 1, 0));
+        assert_eq!(image.to_strings(), [" a b c  ", "  d e f "]);
+
+        let origin = Point::new(1, 0);
+        for hex in [Hex::new(0, 0), Hex::new(2, 0), Hex::new(1, 1)] {
+            let p = hex_to_screen(hex, origin);
+            assert_eq!(screen_to_hex(p, origin), hex);
+            assert_eq!(screen_to_hex(p.offset(1, 0), origin), hex);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//
+// Run history
+//
+// An append-only record of every game played: who played it, the score, the
+// seed the world was generated from and how it ended.  The records are kept one
+// per line in the platform's data directory so that hall of fame and statistics
+// screens survive between runs.
+//
+
+use crate::RogueResult;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+//
+// RunRecord
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunRecord {
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub name: String,
+    pub score: i64,
+    pub seed: u64,
+    pub cause: String,
+    // The date of the daily challenge, for daily runs.  See daily.rs.
+    pub daily: Option<String>,
+}
+
+impl RunRecord {
+    // Creates a record of a run that has just ended.
+    pub fn new(name: &str, score: i64, seed: u64, cause: &str) -> Self {
+        RunRecord {
+            timestamp: now(),
+            name: String::from(name),
+            score,
+            seed,
+            cause: String::from(cause),
+            daily: None,
+        }
+    }
+
+    // The date of the run as YYYY-MM-DD (UTC).
+    pub fn date(&self) -> String {
+        let (year, month, day) = civil_from_days((self.timestamp / 86400) as i64);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
+    // Records are stored as tab-separated fields, so tabs and newlines in the
+    // text are replaced with spaces.  The daily date is only written for
+    // daily runs, so older histories still read.
+    fn to_line(&self) -> String {
+        let clean = |text: &str| text.replace(['\t', '\n', '\r'], " ");
+        let mut line = format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.timestamp,
+            clean(&self.name),
+            self.score,
+            self.seed,
+            clean(&self.cause)
+        );
+        if let Some(date) = &self.daily {
+            line.push('\t');
+            line.push_str(&clean(date));
+        }
+        line.push('\n');
+        line
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        Some(RunRecord {
+            timestamp: fields.next()?.parse().ok()?,
+            name: String::from(fields.next()?),
+            score: fields.next()?.parse().ok()?,
+            seed: fields.next()?.parse().ok()?,
+            cause: String::from(fields.next()?),
+            daily: fields.next().map(String::from),
+        })
+    }
+}
+
+// Seconds since the Unix epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// Converts days since the Unix epoch to a (year, month, day) date.  This is
+// Howard Hinnant's algorithm for the proleptic Gregorian calendar.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+//
+// RunHistory
+// Lines that can't be read are skipped rather than losing the whole history.
+//
+
+pub struct RunHistory {
+    path: PathBuf,
+    records: Vec<RunRecord>,
+}
+
+impl RunHistory {
+    // Opens the history kept in the user's data directory under the game's
+    // name.
+    pub fn open(game_name: &str) -> RogueResult<Self> {
+        let dir = dirs::data_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory"))?;
+        Self::open_path(&dir.join(game_name).join("history.txt"))
+    }
+
+    pub fn open_path(path: &Path) -> RogueResult<Self> {
+        let records = match fs::read_to_string(path) {
+            Ok(text) => text.lines().filter_map(RunRecord::from_line).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(RunHistory {
+            path: path.to_path_buf(),
+            records,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add(&mut self, record: RunRecord) -> RogueResult<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(record.to_line().as_bytes())?;
+        self.records.push(record);
+        Ok(())
+    }
+
+    //
+    // Queries
+    //
+
+    // All runs in the order they were played
+    pub fn records(&self) -> &[RunRecord] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    // The highest scoring runs, best first.  Equal scores are ordered by who
+    // got there first.
+    pub fn top(&self, count: usize) -> Vec<&RunRecord> {
+        let mut records = self.records.iter().collect::<Vec<_>>();
+        records.sort_by(|a, b| b.score.cmp(&a.score).then(a.timestamp.cmp(&b.timestamp)));
+        records.truncate(count);
+        records
+    }
+
+    // The most recent runs, newest first
+    pub fn recent(&self, count: usize) -> Vec<&RunRecord> {
+        self.records.iter().rev().take(count).collect()
+    }
+
+    pub fn best(&self) -> Option<&RunRecord> {
+        self.top(1).into_iter().next()
+    }
+
+    pub fn by_name<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a RunRecord> + 'a {
+        self.records
+            .iter()
+            .filter(move |record| record.name == name)
+    }
+
+    // The runs of the daily challenge for a date (YYYY-MM-DD), best first
+    pub fn daily(&self, date: &str) -> Vec<&RunRecord> {
+        let mut records = self
+            .records
+            .iter()
+            .filter(|record| record.daily.as_deref() == Some(date))
+            .collect::<Vec<_>>();
+        records.sort_by(|a, b| b.score.cmp(&a.score).then(a.timestamp.cmp(&b.timestamp)));
+        records
+    }
+
+    // For games that only allow one attempt at each daily
+    pub fn played_daily(&self, date: &str) -> bool {
+        self.records
+            .iter()
+            .any(|record| record.daily.as_deref() == Some(date))
+    }
+
+    // The 1-based position the score would take in the hall of fame.
+    pub fn rank(&self, score: i64) -> usize {
+        self.records.iter().filter(|r| r.score >= score).count() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(timestamp: u64, name: &str, score: i64) -> RunRecord {
+        RunRecord {
+            timestamp,
+            ..RunRecord::new(name, score, 7, "Killed by a rat")
+        }
+    }
+
+    fn names(records: Vec<&RunRecord>) -> Vec<&str> {
+        records.iter().map(|record| record.name.as_str()).collect()
+    }
+
+    #[test]
+    fn dates_are_found_from_timestamps() {
+        assert_eq!(record(0, "a", 0).date(), "1970-01-01");
+        assert_eq!(record(951_782_400, "a", 0).date(), "2000-02-29");
+        assert_eq!(record(1_709_251_199, "a", 0).date(), "2024-02-29");
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn records_are_read_back_from_their_lines() {
+        let mut run = record(100, "Tab\tName", 42);
+        run.cause = String::from("Fell\ndown");
+        let read = RunRecord::from_line(run.to_line().trim_end()).unwrap();
+        assert_eq!(read.name, "Tab Name");
+        assert_eq!(read.cause, "Fell down");
+        assert_eq!(read.daily, None);
+
+        run.daily = Some(String::from("2024-03-01"));
+        let read = RunRecord::from_line(run.to_line().trim_end()).unwrap();
+        assert_eq!(read.daily.as_deref(), Some("2024-03-01"));
+        assert_eq!(
+            RunRecord::from_line("100\tname\tnot a score\t7\tcause"),
+            None
+        );
+    }
+
+    #[test]
+    fn runs_are_ranked_by_score_then_time() {
+        let dir = std::env::temp_dir().join(format!("mage-history-{}", std::process::id()));
+        let path = dir.join("history.txt");
+        let mut history = RunHistory::open_path(&path).unwrap();
+        assert!(history.is_empty());
+        history.add(record(3, "Ann", 50)).unwrap();
+        history.add(record(1, "Bob", 80)).unwrap();
+        history.add(record(2, "Cat", 50)).unwrap();
+        let mut daily = record(4, "Ann", 60);
+        daily.daily = Some(String::from("2024-03-01"));
+        history.add(daily).unwrap();
+
+        // A line that can't be read doesn't lose the others
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "garbage\n").unwrap();
+        let history = RunHistory::open_path(&path);
+        let _ = fs::remove_dir_all(&dir);
+        let history = history.unwrap();
+
+        assert_eq!(history.len(), 4);
+        assert_eq!(names(history.top(3)), ["Bob", "Ann", "Cat"]);
+        assert_eq!(names(history.recent(2)), ["Ann", "Cat"]);
+        assert_eq!(history.best().unwrap().name, "Bob");
+        assert_eq!(history.by_name("Ann").count(), 2);
+        assert_eq!(names(history.daily("2024-03-01")), ["Ann"]);
+        assert!(history.played_daily("2024-03-01"));
+        assert!(!history.played_daily("2024-03-02"));
+        assert_eq!(history.rank(90), 1);
+        assert_eq!(history.rank(50), 5);
+    }
+}
@@ -0,0 +1,632 @@
+//
+// Input state
+//
+// Keyboard and mouse state passed to the Game trait's tick() function, along
+// with helpers to map the mouse onto the character grid.
+//
+
+use crate::{touch::TouchTracker, ExternalKey, Key, Point, Rect, SimInput};
+use std::time::{Duration, Instant};
+use winit::event::{ElementState, MouseButton as WinitMouseButton, MouseScrollDelta, WindowEvent};
+
+//
+// InputOptions
+// Accessibility options for the keyboard, set with Context::set_input_options():
+//
+//      sticky_modifiers    Shift, Ctrl and Alt can be pressed and released
+//                          before the key they go with, instead of held.
+//                          Pressing one twice cancels it.
+//      repeat              how held keys repeat: as the OS does, not at all, or
+//                          after a delay at a fixed interval
+//      key_echo            shows the keys pressed in the corner of the window,
+//                          for streams and tutorial recordings
+//
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyRepeat {
+    #[default]
+    System,
+    Off,
+    Custom {
+        delay: Duration,
+        interval: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputOptions {
+    pub sticky_modifiers: bool,
+    pub repeat: KeyRepeat,
+    pub key_echo: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Modifiers {
+    alt: bool,
+    ctrl: bool,
+    shift: bool,
+}
+
+// The key being held down, for repeating it
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    vkey: Option<Key>,
+    scancode: ScanCode,
+    next_repeat: Instant,
+    // The character it typed, typed again when it repeats
+    ch: Option<char>,
+}
+
+fn is_modifier(key: Key) -> bool {
+    matches!(
+        key,
+        Key::LShift | Key::RShift | Key::LControl | Key::RControl | Key::LAlt | Key::RAlt
+    )
+}
+
+//
+// InputState
+// All of the input gathered by the engine between ticks.
+//
+
+pub(crate) struct InputState {
+    pub key: KeyState,
+    pub mouse: MouseState,
+    pub touches: TouchTracker,
+    pub text: String,
+    // The modifiers actually held, as opposed to those in key, which also
+    // has the sticky ones for the tick they are used in
+    held_modifiers: Modifiers,
+    sticky: Modifiers,
+    held_key: Option<HeldKey>,
+    // Set when the OS repeats a key, so the character it types is ignored too
+    ignore_text: bool,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        InputState {
+            key: KeyState {
+                vkey: None,
+                pressed: false,
+                alt: false,
+                ctrl: false,
+                shift: false,
+                scancode: None,
+            },
+            mouse: MouseState::default(),
+            touches: TouchTracker::new(),
+            text: String::new(),
+            held_modifiers: Modifiers::default(),
+            sticky: Modifiers::default(),
+            held_key: None,
+            ignore_text: false,
+        }
+    }
+
+    // Returns false for the OS's key repeats when the engine does its own.
+    pub fn handle_key(
+        &mut self,
+        pressed: bool,
+        vkey: Option<Key>,
+        scancode: ScanCode,
+        options: &InputOptions,
+    ) -> bool {
+        let held = self.held_key.is_some_and(|key| key.scancode == scancode);
+        self.ignore_text = pressed && held && options.repeat != KeyRepeat::System;
+        if self.ignore_text {
+            return false;
+        }
+        if pressed && !held {
+            let delay = match options.repeat {
+                KeyRepeat::Custom { delay, .. } => delay,
+                _ => Duration::ZERO,
+            };
+            self.held_key = Some(HeldKey {
+                vkey,
+                scancode,
+                next_repeat: Instant::now() + delay,
+                ch: None,
+            });
+        } else if !pressed && held {
+            self.held_key = None;
+        }
+
+        if pressed && options.sticky_modifiers && !held {
+            match vkey {
+                Some(Key::LShift | Key::RShift) => self.sticky.shift = !self.sticky.shift,
+                Some(Key::LControl | Key::RControl) => self.sticky.ctrl = !self.sticky.ctrl,
+                Some(Key::LAlt | Key::RAlt) => self.sticky.alt = !self.sticky.alt,
+                _ => {}
+            }
+        }
+
+        self.key.pressed = pressed;
+        self.key.vkey = vkey;
+        self.key.scancode = Some(scancode);
+        true
+    }
+
+    pub fn push_text(&mut self, ch: char) {
+        if self.ignore_text {
+            return;
+        }
+        self.text.push(ch);
+        if let Some(key) = &mut self.held_key {
+            key.ch = Some(ch);
+        }
+    }
+
+    pub fn set_modifiers(&mut self, alt: bool, ctrl: bool, shift: bool) {
+        self.held_modifiers = Modifiers { alt, ctrl, shift };
+        self.key.alt = alt;
+        self.key.ctrl = ctrl;
+        self.key.shift = shift;
+    }
+
+    // A key pressed by an external input source, for this tick only
+    pub fn inject_key(&mut self, key: ExternalKey) {
+        self.key.pressed = true;
+        self.key.vkey = Some(key.key);
+        self.key.scancode = None;
+        self.key.shift |= key.shift;
+        self.key.ctrl |= key.ctrl;
+        self.key.alt |= key.alt;
+    }
+
+    // Called before each tick to add the engine's key repeats and the sticky
+    // modifiers.
+    pub fn begin_tick(&mut self, options: &InputOptions) {
+        if let (KeyRepeat::Custom { interval, .. }, Some(key)) =
+            (options.repeat, &mut self.held_key)
+        {
+            let now = Instant::now();
+            if now >= key.next_repeat && !self.key.pressed {
+                self.key.pressed = true;
+                self.key.vkey = key.vkey;
+                self.key.scancode = Some(key.scancode);
+                self.text.extend(key.ch);
+                key.next_repeat = now + interval;
+            }
+        }
+
+        let modifier = self.key.vkey.is_some_and(is_modifier);
+        if !options.sticky_modifiers {
+            self.sticky = Modifiers::default();
+        } else if self.key.pressed && !modifier {
+            self.key.alt |= self.sticky.alt;
+            self.key.ctrl |= self.sticky.ctrl;
+            self.key.shift |= self.sticky.shift;
+            if self.sticky.shift && !self.held_modifiers.shift {
+                self.text = self.text.to_uppercase();
+            }
+            self.sticky = Modifiers::default();
+        }
+    }
+
+    // The key pressed this tick with its modifiers, e.g. "Ctrl+S", for the
+    // key echo.  Modifier keys on their own aren't shown.
+    pub fn key_label(&self) -> Option<String> {
+        let key = self
+            .key
+            .vkey
+            .filter(|&key| self.key.pressed && !is_modifier(key))?;
+        let mut label = String::new();
+        for (held, name) in [
+            (self.key.ctrl, "Ctrl+"),
+            (self.key.alt, "Alt+"),
+            (self.key.shift, "Shift+"),
+        ] {
+            if held {
+                label.push_str(name);
+            }
+        }
+        label.push_str(&key.name());
+        Some(label)
+    }
+
+    pub fn end_tick(&mut self) {
+        self.key.pressed = false;
+        self.key.vkey = None;
+        self.key.scancode = None;
+        let Modifiers { alt, ctrl, shift } = self.held_modifiers;
+        self.key.alt = alt;
+        self.key.ctrl = ctrl;
+        self.key.shift = shift;
+        self.mouse.end_tick();
+        self.touches.end_tick();
+        self.text.clear();
+    }
+}
+
+//
+// KeyState
+// vkey depends on the keyboard layout, whereas scancode identifies the
+// physical key.  Keys that have no virtual key code still report a scancode.
+//
+
+pub struct KeyState {
+    pub pressed: bool,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub vkey: Option<Key>,
+    pub scancode: Option<ScanCode>,
+}
+
+impl KeyState {
+    pub fn alt_pressed(&self) -> bool {
+        self.alt && !self.ctrl && !self.shift
+    }
+    pub fn ctrl_pressed(&self) -> bool {
+        !self.alt && self.ctrl && !self.shift
+    }
+    pub fn shift_pressed(&self) -> bool {
+        !self.alt && !self.ctrl && self.shift
+    }
+    pub fn key_pressed(&self, key: Key) -> bool {
+        if self.pressed {
+            if let Some(vkey) = self.vkey {
+                if key == vkey {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    pub fn scancode_pressed(&self, code: ScanCode) -> bool {
+        self.pressed && self.scancode == Some(code)
+    }
+}
+
+//
+// Scancodes
+// Platform scancodes for the physical keys commonly used for movement, named
+// after their position on a US QWERTY keyboard.  For example, scancode::W is
+// the key labelled Z on an AZERTY keyboard.
+//
+
+pub type ScanCode = u32;
+
+#[cfg(not(target_os = "macos"))]
+pub mod scancode {
+    use super::ScanCode;
+
+    pub const Q: ScanCode = 0x10;
+    pub const W: ScanCode = 0x11;
+    pub const E: ScanCode = 0x12;
+    pub const A: ScanCode = 0x1e;
+    pub const S: ScanCode = 0x1f;
+    pub const D: ScanCode = 0x20;
+    pub const Z: ScanCode = 0x2c;
+    pub const X: ScanCode = 0x2d;
+    pub const C: ScanCode = 0x2e;
+}
+
+#[cfg(target_os = "macos")]
+pub mod scancode {
+    use super::ScanCode;
+
+    pub const Q: ScanCode = 0x0c;
+    pub const W: ScanCode = 0x0d;
+    pub const E: ScanCode = 0x0e;
+    pub const A: ScanCode = 0x00;
+    pub const S: ScanCode = 0x01;
+    pub const D: ScanCode = 0x02;
+    pub const Z: ScanCode = 0x06;
+    pub const X: ScanCode = 0x07;
+    pub const C: ScanCode = 0x08;
+}
+
+//
+// MouseButton
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u16),
+}
+
+impl From<WinitMouseButton> for MouseButton {
+    fn from(button: WinitMouseButton) -> Self {
+        match button {
+            WinitMouseButton::Left => MouseButton::Left,
+            WinitMouseButton::Right => MouseButton::Right,
+            WinitMouseButton::Middle => MouseButton::Middle,
+            WinitMouseButton::Other(n) => MouseButton::Other(n),
+        }
+    }
+}
+
+//
+// MouseState
+// x and y are in window pixels from the top-left corner of the grid, which is
+// the window's corner unless the grid is centred.  grid_x and grid_y are the
+// cell under the mouse, worked out by the engine, which stays right when the
+// grid is zoomed or scaled by a fraction.  The clicked flags are only set for
+// the tick in which the button went down.  The wheel values are the lines
+// scrolled this tick, positive for up and left.
+//
+
+// Touchpads report scrolling in pixels, which are converted to lines
+const PIXELS_PER_LINE: f64 = 16.0;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MouseState {
+    pub on_screen: bool,
+    pub left_pressed: bool,
+    pub right_pressed: bool,
+    pub middle_pressed: bool,
+    pub left_clicked: bool,
+    pub right_clicked: bool,
+    pub middle_clicked: bool,
+    pub x: i32,
+    pub y: i32,
+    pub grid_x: i32,
+    pub grid_y: i32,
+    pub wheel_x: f32,
+    pub wheel_y: f32,
+}
+
+impl MouseState {
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::CursorEntered { .. } => self.on_screen = true,
+            WindowEvent::CursorLeft { .. } => self.on_screen = false,
+            // The position is set by RenderState::place_mouse().
+            WindowEvent::CursorMoved { .. } => {}
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = state == ElementState::Pressed;
+                let (was_pressed, clicked) = match MouseButton::from(button) {
+                    MouseButton::Left => (&mut self.left_pressed, &mut self.left_clicked),
+                    MouseButton::Right => (&mut self.right_pressed, &mut self.right_clicked),
+                    MouseButton::Middle => (&mut self.middle_pressed, &mut self.middle_clicked),
+                    MouseButton::Other(_) => return,
+                };
+                *clicked |= pressed && !*was_pressed;
+                *was_pressed = pressed;
+            }
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => {
+                    self.wheel_x += x;
+                    self.wheel_y += y;
+                }
+                MouseScrollDelta::PixelDelta(p) => {
+                    self.wheel_x += (p.x / PIXELS_PER_LINE) as f32;
+                    self.wheel_y += (p.y / PIXELS_PER_LINE) as f32;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // Called by the engine after each tick
+    pub(crate) fn end_tick(&mut self) {
+        self.left_clicked = false;
+        self.right_clicked = false;
+        self.middle_clicked = false;
+        self.wheel_x = 0.0;
+        self.wheel_y = 0.0;
+    }
+
+    pub fn pressed(&self, button: MouseButton) -> bool {
+        match button {
+            MouseButton::Left => self.left_pressed,
+            MouseButton::Right => self.right_pressed,
+            MouseButton::Middle => self.middle_pressed,
+            MouseButton::Other(_) => false,
+        }
+    }
+
+    pub fn clicked(&self, button: MouseButton) -> bool {
+        match button {
+            MouseButton::Left => self.left_clicked,
+            MouseButton::Right => self.right_clicked,
+            MouseButton::Middle => self.middle_clicked,
+            MouseButton::Other(_) => false,
+        }
+    }
+
+    // The cell at the mouse's position for cells of a given size in pixels.
+    // Use mouse_cell() for the engine's own grid.  A size of 0 is taken as 1.
+    pub fn cell(&self, cell_width: u32, cell_height: u32) -> Point {
+        let size = |size: u32| size.clamp(1, i32::MAX as u32) as i32;
+        Point::new(
+            self.x.div_euclid(size(cell_width)),
+            self.y.div_euclid(size(cell_height)),
+        )
+    }
+}
+
+impl<'a> SimInput<'a> {
+    pub fn mouse_cell(&self) -> Option<Point> {
+        self.mouse
+            .filter(|mouse| mouse.on_screen)
+            .map(|mouse| Point::new(mouse.grid_x, mouse.grid_y))
+    }
+}
+
+//
+// HitRegions
+// A registry of named rectangles, rebuilt by the game each frame, that answers
+// which region the mouse is over.  Regions added later are considered to be on
+// top of earlier ones.
+//
+
+#[derive(Default)]
+pub struct HitRegions {
+    regions: Vec<(String, Rect)>,
+}
+
+impl HitRegions {
+    pub fn new() -> Self {
+        HitRegions {
+            regions: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    pub fn add(&mut self, name: &str, rect: Rect) {
+        self.regions.push((String::from(name), rect));
+    }
+
+    pub fn hit(&self, p: Point) -> Option<&str> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|(_, rect)| rect.contains(p))
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn hovered(&self, sim_input: &SimInput) -> Option<&str> {
+        sim_input.mouse_cell().and_then(|p| self.hit(p))
+    }
+
+    pub fn clicked(&self, sim_input: &SimInput) -> Option<&str> {
+        match sim_input.mouse {
+            Some(MouseState {
+                left_clicked: true, ..
+            }) => self.hovered(sim_input),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(input: &mut InputState, key: Key, scancode: ScanCode, options: &InputOptions) {
+        input.handle_key(true, Some(key), scancode, options);
+    }
+
+    #[test]
+    fn sticky_modifiers_apply_to_the_next_key() {
+        let options = InputOptions {
+            sticky_modifiers: true,
+            ..InputOptions::default()
+        };
+        let mut input = InputState::new();
+        press(&mut input, Key::LShift, 0x2a, &options);
+        input.handle_key(false, Some(Key::LShift), 0x2a, &options);
+        input.begin_tick(&options);
+        // The modifier on its own isn't a key to echo
+        assert_eq!(input.key_label(), None);
+        input.end_tick();
+
+        press(&mut input, Key::LControl, 0x1d, &options);
+        input.end_tick();
+        press(&mut input, Key::A, scancode::A, &options);
+        input.push_text('a');
+        input.begin_tick(&options);
+        assert!(input.key.shift && input.key.ctrl && !input.key.alt);
+        assert_eq!(input.text, "A");
+        assert_eq!(input.key_label().as_deref(), Some("Ctrl+Shift+A"));
+        input.end_tick();
+
+        // Used up by the key, and pressing one twice cancels it
+        press(&mut input, Key::B, 0x30, &options);
+        input.begin_tick(&options);
+        assert!(!input.key.shift && !input.key.ctrl);
+        input.end_tick();
+        for _ in 0..2 {
+            press(&mut input, Key::RAlt, 0x38, &options);
+            input.handle_key(false, Some(Key::RAlt), 0x38, &options);
+        }
+        press(&mut input, Key::B, 0x30, &options);
+        input.begin_tick(&options);
+        assert!(!input.key.alt);
+    }
+
+    #[test]
+    fn the_engine_repeats_keys_itself() {
+        let options = InputOptions {
+            repeat: KeyRepeat::Custom {
+                delay: Duration::ZERO,
+                interval: Duration::ZERO,
+            },
+            ..InputOptions::default()
+        };
+        let mut input = InputState::new();
+        assert!(input.handle_key(true, Some(Key::X), scancode::X, &options));
+        input.push_text('x');
+        input.begin_tick(&options);
+        input.end_tick();
+
+        // The OS's repeats, and the characters they type, are ignored
+        assert!(!input.handle_key(true, Some(Key::X), scancode::X, &options));
+        input.push_text('x');
+        assert!(!input.key.pressed && input.text.is_empty());
+        input.begin_tick(&options);
+        assert!(input.key.key_pressed(Key::X));
+        assert!(input.key.scancode_pressed(scancode::X));
+        assert_eq!(input.text, "x");
+        input.end_tick();
+
+        input.handle_key(false, Some(Key::X), scancode::X, &options);
+        input.end_tick();
+        input.begin_tick(&options);
+        assert!(!input.key.pressed);
+
+        // Keys aren't repeated at all when repeating is off
+        let options = InputOptions {
+            repeat: KeyRepeat::Off,
+            ..InputOptions::default()
+        };
+        press(&mut input, Key::X, scancode::X, &options);
+        input.end_tick();
+        assert!(!input.handle_key(true, Some(Key::X), scancode::X, &options));
+        input.begin_tick(&options);
+        assert!(!input.key.pressed);
+    }
+
+    #[test]
+    fn held_modifiers_outlast_the_tick() {
+        let options = InputOptions::default();
+        let mut input = InputState::new();
+        input.set_modifiers(false, true, false);
+        input.inject_key(ExternalKey {
+            key: Key::S,
+            shift: true,
+            ctrl: false,
+            alt: false,
+        });
+        assert_eq!(input.key_label().as_deref(), Some("Ctrl+Shift+S"));
+        assert!(!input.key.ctrl_pressed() && !input.key.shift_pressed());
+        input.begin_tick(&options);
+        input.end_tick();
+        assert!(input.key.ctrl_pressed());
+        assert!(!input.key.shift && input.key.vkey.is_none());
+    }
+
+    #[test]
+    fn mouse_cells_round_towards_negative_infinity() {
+        let mouse = MouseState {
+            x: -1,
+            y: 17,
+            ..MouseState::default()
+        };
+        assert_eq!(mouse.cell(8, 8), Point::new(-1, 2));
+        assert_eq!(mouse.cell(0, 1), Point::new(-1, 17));
+        assert_eq!(mouse.cell(u32::MAX, u32::MAX), Point::new(-1, 0));
+        assert!(!mouse.pressed(MouseButton::Other(4)));
+    }
+
+    #[test]
+    fn later_hit_regions_are_on_top() {
+        let mut regions = HitRegions::new();
+        regions.add("map", Rect::new(0, 0, 10, 10));
+        regions.add("button", Rect::new(2, 2, 3, 1));
+        assert_eq!(regions.hit(Point::new(3, 2)), Some("button"));
+        assert_eq!(regions.hit(Point::new(3, 3)), Some("map"));
+        assert_eq!(regions.hit(Point::new(10, 0)), None);
+        regions.clear();
+        assert_eq!(regions.hit(Point::new(3, 2)), None);
+    }
+}
@@ -0,0 +1,383 @@
+//
+// Input mapping
+//
+// Maps keys to game-defined actions so that bindings can be changed without
+// touching the game logic.  Directions are provided as a ready-made action set
+// covering the numpad, arrow keys and vi-keys.
+//
+
+use crate::{Key, KeyState, Point, RogueError, RogueResult, ScanCode};
+use std::{fs, io, path::Path};
+
+//
+// Binding
+// Key bindings are either layout-dependent virtual keys or physical scancodes.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(Key),
+    Scan(ScanCode),
+}
+
+impl Binding {
+    pub fn matches(&self, key: &KeyState) -> bool {
+        match *self {
+            Binding::Key(vkey) => key.key_pressed(vkey),
+            Binding::Scan(code) => key.scancode_pressed(code),
+        }
+    }
+
+    // The binding pressed this tick, preferring the virtual key.
+    pub fn pressed(key: &KeyState) -> Option<Binding> {
+        if !key.pressed {
+            return None;
+        }
+        key.vkey
+            .map(Binding::Key)
+            .or_else(|| key.scancode.map(Binding::Scan))
+    }
+
+    // Virtual keys are named after the Key variant and scancodes are written
+    // as a '#' followed by the number, e.g. "Numpad8" or "#17".
+    pub fn name(&self) -> String {
+        match *self {
+            Binding::Key(key) => key.name(),
+            Binding::Scan(code) => format!("#{}", code),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Binding> {
+        match name.strip_prefix('#') {
+            Some(code) => code.parse().ok().map(Binding::Scan),
+            None => Key::from_name(name).map(Binding::Key),
+        }
+    }
+}
+
+//
+// InputMap
+//
+
+pub struct InputMap<A> {
+    bindings: Vec<(Binding, A)>,
+}
+
+impl<A> InputMap<A>
+where
+    A: Copy + PartialEq,
+{
+    pub fn new() -> Self {
+        InputMap {
+            bindings: Vec::new(),
+        }
+    }
+
+    // A binding can only trigger one action, so binding it again replaces the
+    // previous action.
+    pub fn bind(&mut self, binding: Binding, action: A) -> &mut Self {
+        self.unbind(binding);
+        self.bindings.push((binding, action));
+        self
+    }
+
+    pub fn unbind(&mut self, binding: Binding) -> &mut Self {
+        self.bindings.retain(|(b, _)| *b != binding);
+        self
+    }
+
+    pub fn unbind_action(&mut self, action: A) -> &mut Self {
+        self.bindings.retain(|(_, a)| *a != action);
+        self
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = &(Binding, A)> {
+        self.bindings.iter()
+    }
+
+    pub fn bindings_for(&self, action: A) -> impl Iterator<Item = Binding> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, a)| *a == action)
+            .map(|(b, _)| *b)
+    }
+
+    // Returns the action triggered by the key pressed this tick, if any.
+    pub fn action(&self, key: &KeyState) -> Option<A> {
+        self.bindings
+            .iter()
+            .find(|(b, _)| b.matches(key))
+            .map(|(_, a)| *a)
+    }
+
+    //
+    // Saving and loading
+    // Bindings are stored one per line as "binding = action".  The game
+    // provides the names of its actions.
+    //
+
+    pub fn save<F>(&self, path: &Path, name: F) -> io::Result<()>
+    where
+        F: Fn(A) -> String,
+    {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = self
+            .bindings
+            .iter()
+            .map(|(binding, action)| format!("{} = {}\n", binding.name(), name(*action)))
+            .collect::<String>();
+        fs::write(path, text)
+    }
+
+    // Replaces the current bindings with those in the file.  Lines with
+    // unknown bindings or actions are ignored, so old files still load after
+    // actions are renamed, but lines that aren't bindings at all are errors.
+    pub fn load<F>(&mut self, path: &Path, action: F) -> RogueResult<()>
+    where
+        F: Fn(&str) -> Option<A>,
+    {
+        let text = fs::read_to_string(path)?;
+        let mut bindings = Vec::new();
+        for (line_number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (binding, name) = line.split_once('=').ok_or_else(|| RogueError::BadConfig {
+                file: path.display().to_string(),
+                line: line_number + 1,
+                message: String::from("expected 'binding = action'"),
+            })?;
+            if let (Some(binding), Some(action)) =
+                (Binding::from_name(binding.trim()), action(name.trim()))
+            {
+                bindings.push((binding, action));
+            }
+        }
+        self.bindings.clear();
+        for (binding, action) in bindings {
+            self.bind(binding, action);
+        }
+        Ok(())
+    }
+}
+
+impl<A> Default for InputMap<A>
+where
+    A: Copy + PartialEq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//
+// Direction
+// The 8 compass directions plus waiting on the spot.  North is towards the top
+// of the screen.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+    Wait,
+}
+
+impl Direction {
+    pub fn delta(self) -> (i32, i32) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::NorthEast => (1, -1),
+            Direction::East => (1, 0),
+            Direction::SouthEast => (1, 1),
+            Direction::South => (0, 1),
+            Direction::SouthWest => (-1, 1),
+            Direction::West => (-1, 0),
+            Direction::NorthWest => (-1, -1),
+            Direction::Wait => (0, 0),
+        }
+    }
+
+    pub fn offset(self, p: Point) -> Point {
+        let (dx, dy) = self.delta();
+        Point::new(p.x + dx, p.y + dy)
+    }
+
+    // The direction closest to the given vector, with y increasing downwards.
+    pub fn from_vector(dx: f64, dy: f64) -> Self {
+        if dx == 0.0 && dy == 0.0 {
+            return Direction::Wait;
+        }
+
+        // Split the circle into 8 sectors, starting with east and going
+        // clockwise (since y points down).
+        let octant = (dy.atan2(dx) / std::f64::consts::FRAC_PI_4).round() as i32;
+        match octant.rem_euclid(8) {
+            0 => Direction::East,
+            1 => Direction::SouthEast,
+            2 => Direction::South,
+            3 => Direction::SouthWest,
+            4 => Direction::West,
+            5 => Direction::NorthWest,
+            6 => Direction::North,
+            _ => Direction::NorthEast,
+        }
+    }
+}
+
+impl InputMap<Direction> {
+    // The standard roguelike movement keys: numpad, arrow keys and vi-keys.
+    pub fn directions() -> Self {
+        use Direction::*;
+        use Key as K;
+
+        let mut map = InputMap::new();
+        [
+            (K::Numpad8, North),
+            (K::Numpad9, NorthEast),
+            (K::Numpad6, East),
+            (K::Numpad3, SouthEast),
+            (K::Numpad2, South),
+            (K::Numpad1, SouthWest),
+            (K::Numpad4, West),
+            (K::Numpad7, NorthWest),
+            (K::Numpad5, Wait),
+            (K::Up, North),
+            (K::Right, East),
+            (K::Down, South),
+            (K::Left, West),
+            (K::K, North),
+            (K::U, NorthEast),
+            (K::L, East),
+            (K::N, SouthEast),
+            (K::J, South),
+            (K::B, SouthWest),
+            (K::H, West),
+            (K::Y, NorthWest),
+            (K::Period, Wait),
+        ]
+        .iter()
+        .for_each(|&(key, direction)| {
+            map.bind(Binding::Key(key), direction);
+        });
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_state(vkey: Option<Key>, scancode: Option<ScanCode>) -> KeyState {
+        KeyState {
+            pressed: true,
+            shift: false,
+            ctrl: false,
+            alt: false,
+            vkey,
+            scancode,
+        }
+    }
+
+    #[test]
+    fn bindings_are_named_both_ways() {
+        for binding in [Binding::Key(Key::Numpad8), Binding::Scan(17)] {
+            assert_eq!(Binding::from_name(&binding.name()), Some(binding));
+        }
+        assert_eq!(Binding::Scan(17).name(), "#17");
+        assert_eq!(Binding::from_name("#x"), None);
+        assert_eq!(Binding::from_name("NotAKey"), None);
+    }
+
+    #[test]
+    fn bindings_trigger_one_action() {
+        let mut map = InputMap::new();
+        map.bind(Binding::Key(Key::A), 1)
+            .bind(Binding::Scan(30), 2)
+            .bind(Binding::Key(Key::A), 3);
+        assert_eq!(map.action(&key_state(Some(Key::A), None)), Some(3));
+        assert_eq!(map.action(&key_state(None, Some(30))), Some(2));
+        assert_eq!(map.action(&key_state(Some(Key::B), Some(31))), None);
+
+        let mut released = key_state(Some(Key::A), None);
+        released.pressed = false;
+        assert_eq!(map.action(&released), None);
+        assert_eq!(Binding::pressed(&released), None);
+        assert_eq!(
+            Binding::pressed(&key_state(Some(Key::A), Some(30))),
+            Some(Binding::Key(Key::A))
+        );
+
+        map.unbind_action(3);
+        assert_eq!(map.bindings_for(3).count(), 0);
+        assert_eq!(map.bindings_for(2).collect::<Vec<_>>(), [Binding::Scan(30)]);
+    }
+
+    #[test]
+    fn bindings_are_saved_and_loaded() {
+        let dir = std::env::temp_dir().join(format!("mage-input-map-{}", std::process::id()));
+        let path = dir.join("keys.txt");
+        let names = ["north", "wait"];
+        let name = |action: usize| String::from(names[action]);
+        let action = |name: &str| names.iter().position(|&n| n == name);
+
+        let mut map = InputMap::new();
+        map.bind(Binding::Key(Key::K), 0).bind(Binding::Scan(76), 1);
+        map.save(&path, name).unwrap();
+        let mut loaded = InputMap::new();
+        loaded.bind(Binding::Key(Key::Z), 1);
+        loaded.load(&path, action).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+
+        // Unknown actions and keys are skipped, but not lines without a '='
+        fs::write(&path, "K = north\nK = fly\nNotAKey = wait\n\n").unwrap();
+        let mut skipped = InputMap::new();
+        skipped.load(&path, action).unwrap();
+        fs::write(&path, "K = north\njunk\n").unwrap();
+        let junk = skipped.load(&path, action);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(saved, "K = north\n#76 = wait\n");
+        assert_eq!(
+            loaded.bindings().collect::<Vec<_>>(),
+            map.bindings().collect::<Vec<_>>()
+        );
+        assert_eq!(skipped.bindings().count(), 1);
+        assert!(matches!(junk, Err(RogueError::BadConfig { line: 2, .. })));
+        assert_eq!(skipped.bindings().count(), 1);
+    }
+
+    #[test]
+    fn vectors_snap_to_the_nearest_direction() {
+        assert_eq!(Direction::from_vector(0.0, 0.0), Direction::Wait);
+        assert_eq!(Direction::from_vector(3.0, 0.5), Direction::East);
+        assert_eq!(Direction::from_vector(1.0, 1.0), Direction::SouthEast);
+        assert_eq!(Direction::from_vector(-0.2, -5.0), Direction::North);
+        assert_eq!(Direction::from_vector(-1.0, 0.1), Direction::West);
+        assert_eq!(Direction::from_vector(1.0, -1.1), Direction::NorthEast);
+        for direction in [Direction::SouthWest, Direction::NorthWest] {
+            let (dx, dy) = direction.delta();
+            assert_eq!(Direction::from_vector(dx as f64, dy as f64), direction);
+        }
+        assert_eq!(Direction::South.offset(Point::new(2, 2)), Point::new(2, 3));
+    }
+
+    #[test]
+    fn the_standard_directions_cover_the_numpad_and_vi_keys() {
+        let map = InputMap::directions();
+        let direction = |key| map.action(&key_state(Some(key), None));
+        assert_eq!(direction(Key::Numpad7), Some(Direction::NorthWest));
+        assert_eq!(direction(Key::Y), Some(Direction::NorthWest));
+        assert_eq!(direction(Key::Left), Some(Direction::West));
+        assert_eq!(direction(Key::Period), Some(Direction::Wait));
+        assert_eq!(map.bindings_for(Direction::North).count(), 3);
+    }
+}
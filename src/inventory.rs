@@ -0,0 +1,484 @@
+//
+// Inventory and equipment
+//
+// Containers for the game's own item type.  Items that can stack are merged
+// into a single entry with a count, and inventories can be limited by number
+// of entries and by total weight.  Equipment is a set of typed slots, each
+// holding one item.  Changes are published on the event bus so that message
+// logs and quest trackers can react to them.
+//
+
+use crate::{Entity, EventBus};
+
+pub trait Item: Clone {
+    fn name(&self) -> String;
+
+    // Weight of a single item
+    fn weight(&self) -> u32 {
+        0
+    }
+
+    // Whether the two items can share an inventory entry
+    fn stacks_with(&self, _other: &Self) -> bool {
+        false
+    }
+}
+
+// Implemented by items that can be worn or wielded in slots of type S
+pub trait Equippable<S> {
+    fn fits(&self, slot: S) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InventoryError {
+    // No free entries or slots
+    NoSpace,
+    // Over the weight limit
+    TooHeavy,
+    // The item doesn't fit the equipment slot
+    WrongSlot,
+    // Fewer items than requested
+    NotEnough,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InventoryEvent<I> {
+    Added {
+        owner: Option<Entity>,
+        item: I,
+        count: u32,
+    },
+    Removed {
+        owner: Option<Entity>,
+        item: I,
+        count: u32,
+    },
+    Equipped {
+        owner: Option<Entity>,
+        item: I,
+    },
+    Unequipped {
+        owner: Option<Entity>,
+        item: I,
+    },
+}
+
+//
+// Inventory
+//
+
+pub struct Inventory<I> {
+    owner: Option<Entity>,
+    entries: Vec<(I, u32)>,
+    max_entries: Option<usize>,
+    max_weight: Option<u32>,
+}
+
+impl<I> Inventory<I>
+where
+    I: Item + 'static,
+{
+    pub fn new() -> Self {
+        Inventory {
+            owner: None,
+            entries: Vec::new(),
+            max_entries: None,
+            max_weight: None,
+        }
+    }
+
+    // Events will name the entity carrying the inventory.
+    pub fn with_owner(&mut self, owner: Entity) -> &mut Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn with_max_entries(&mut self, max_entries: usize) -> &mut Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_weight(&mut self, max_weight: u32) -> &mut Self {
+        self.max_weight = Some(max_weight);
+        self
+    }
+
+    pub fn entries(&self) -> &[(I, u32)] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.entries
+            .iter()
+            .map(|(item, count)| item.weight() * count)
+            .sum()
+    }
+
+    // Checks whether the items could be added without changing anything.
+    pub fn can_add(&self, item: &I, count: u32) -> Result<(), InventoryError> {
+        if let Some(max_weight) = self.max_weight {
+            if self.weight() + item.weight() * count > max_weight {
+                return Err(InventoryError::TooHeavy);
+            }
+        }
+        let stacks = self.entries.iter().any(|(i, _)| i.stacks_with(item));
+        match self.max_entries {
+            Some(max) if !stacks && self.entries.len() >= max => Err(InventoryError::NoSpace),
+            _ => Ok(()),
+        }
+    }
+
+    // Adds the items, merging them into an existing entry if they stack.
+    // Returns the index of the entry they went into.
+    pub fn add(
+        &mut self,
+        item: I,
+        count: u32,
+        events: &mut EventBus,
+    ) -> Result<usize, InventoryError> {
+        self.can_add(&item, count)?;
+        let index = match self.entries.iter().position(|(i, _)| i.stacks_with(&item)) {
+            Some(index) => {
+                self.entries[index].1 += count;
+                index
+            }
+            None => {
+                self.entries.push((item.clone(), count));
+                self.entries.len() - 1
+            }
+        };
+        events.publish(InventoryEvent::Added {
+            owner: self.owner,
+            item,
+            count,
+        });
+        Ok(index)
+    }
+
+    // Takes items from an entry, removing the entry once it is empty.
+    pub fn remove(
+        &mut self,
+        index: usize,
+        count: u32,
+        events: &mut EventBus,
+    ) -> Result<I, InventoryError> {
+        let (item, available) = self
+            .entries
+            .get_mut(index)
+            .ok_or(InventoryError::NotEnough)?;
+        if *available < count {
+            return Err(InventoryError::NotEnough);
+        }
+        *available -= count;
+        let item = item.clone();
+        if *available == 0 {
+            self.entries.remove(index);
+        }
+        events.publish(InventoryEvent::Removed {
+            owner: self.owner,
+            item: item.clone(),
+            count,
+        });
+        Ok(item)
+    }
+}
+
+impl<I> Default for Inventory<I>
+where
+    I: Item + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Inventory entries are traditionally labelled a-z then A-Z.
+pub fn inventory_letter(index: usize) -> Option<char> {
+    match index {
+        0..=25 => Some((b'a' + index as u8) as char),
+        26..=51 => Some((b'A' + (index - 26) as u8) as char),
+        _ => None,
+    }
+}
+
+pub fn inventory_index(letter: char) -> Option<usize> {
+    match letter {
+        'a'..='z' => Some(letter as usize - 'a' as usize),
+        'A'..='Z' => Some(letter as usize - 'A' as usize + 26),
+        _ => None,
+    }
+}
+
+//
+// Equipment
+//
+
+pub struct EquipmentSlots<S, I> {
+    owner: Option<Entity>,
+    slots: Vec<(S, Option<I>)>,
+}
+
+impl<S, I> EquipmentSlots<S, I>
+where
+    S: Copy + PartialEq,
+    I: Item + Equippable<S> + 'static,
+{
+    pub fn new() -> Self {
+        EquipmentSlots {
+            owner: None,
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn with_owner(&mut self, owner: Entity) -> &mut Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn with_slot(&mut self, slot: S) -> &mut Self {
+        self.slots.push((slot, None));
+        self
+    }
+
+    pub fn slots(&self) -> &[(S, Option<I>)] {
+        &self.slots
+    }
+
+    pub fn get(&self, slot: S) -> Option<&I> {
+        self.slots
+            .iter()
+            .find(|(s, _)| *s == slot)
+            .and_then(|(_, item)| item.as_ref())
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.slots
+            .iter()
+            .filter_map(|(_, item)| item.as_ref())
+            .map(|item| item.weight())
+            .sum()
+    }
+
+    // Puts the item in the slot, returning whatever was there before.
+    pub fn equip(
+        &mut self,
+        slot: S,
+        item: I,
+        events: &mut EventBus,
+    ) -> Result<Option<I>, InventoryError> {
+        if !item.fits(slot) {
+            return Err(InventoryError::WrongSlot);
+        }
+        let old = self.unequip(slot, events);
+        let entry = self
+            .slots
+            .iter_mut()
+            .find(|(s, _)| *s == slot)
+            .ok_or(InventoryError::WrongSlot)?;
+        entry.1 = Some(item.clone());
+        events.publish(InventoryEvent::Equipped {
+            owner: self.owner,
+            item,
+        });
+        Ok(old)
+    }
+
+    pub fn unequip(&mut self, slot: S, events: &mut EventBus) -> Option<I> {
+        let item = self
+            .slots
+            .iter_mut()
+            .find(|(s, _)| *s == slot)
+            .and_then(|(_, item)| item.take())?;
+        events.publish(InventoryEvent::Unequipped {
+            owner: self.owner,
+            item: item.clone(),
+        });
+        Some(item)
+    }
+}
+
+impl<S, I> Default for EquipmentSlots<S, I>
+where
+    S: Copy + PartialEq,
+    I: Item + Equippable<S> + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Thing {
+        Arrow,
+        Sword,
+        Helm,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Slot {
+        Hand,
+        Head,
+    }
+
+    impl Item for Thing {
+        fn name(&self) -> String {
+            format!("{:?}", self)
+        }
+
+        fn weight(&self) -> u32 {
+            match self {
+                Thing::Arrow => 1,
+                Thing::Sword => 10,
+                Thing::Helm => 5,
+            }
+        }
+
+        fn stacks_with(&self, other: &Self) -> bool {
+            *self == Thing::Arrow && *other == Thing::Arrow
+        }
+    }
+
+    impl Equippable<Slot> for Thing {
+        fn fits(&self, slot: Slot) -> bool {
+            matches!(
+                (self, slot),
+                (Thing::Sword, Slot::Hand) | (Thing::Helm, Slot::Head)
+            )
+        }
+    }
+
+    #[test]
+    fn stacking_items_share_an_entry() {
+        let mut events = EventBus::new();
+        let mut inventory = Inventory::new();
+        assert_eq!(inventory.add(Thing::Arrow, 5, &mut events), Ok(0));
+        assert_eq!(inventory.add(Thing::Sword, 1, &mut events), Ok(1));
+        assert_eq!(inventory.add(Thing::Arrow, 3, &mut events), Ok(0));
+        assert_eq!(inventory.entries(), [(Thing::Arrow, 8), (Thing::Sword, 1)]);
+        assert_eq!(inventory.weight(), 18);
+
+        assert_eq!(
+            inventory.remove(0, 9, &mut events),
+            Err(InventoryError::NotEnough)
+        );
+        assert_eq!(inventory.remove(0, 8, &mut events), Ok(Thing::Arrow));
+        assert_eq!(inventory.entries(), [(Thing::Sword, 1)]);
+        assert_eq!(
+            inventory.remove(5, 1, &mut events),
+            Err(InventoryError::NotEnough)
+        );
+
+        assert_eq!(
+            events.drain::<InventoryEvent<Thing>>().last(),
+            Some(&InventoryEvent::Removed {
+                owner: None,
+                item: Thing::Arrow,
+                count: 8
+            })
+        );
+    }
+
+    #[test]
+    fn inventories_are_limited_by_entries_and_weight() {
+        let mut events = EventBus::new();
+        let mut inventory = Inventory::new();
+        inventory.with_max_entries(2).with_max_weight(20);
+        inventory.add(Thing::Arrow, 1, &mut events).unwrap();
+        inventory.add(Thing::Sword, 1, &mut events).unwrap();
+        assert_eq!(
+            inventory.add(Thing::Helm, 1, &mut events),
+            Err(InventoryError::NoSpace)
+        );
+        // Arrows still stack into their entry, up to the weight limit
+        assert_eq!(inventory.can_add(&Thing::Arrow, 9), Ok(()));
+        assert_eq!(
+            inventory.add(Thing::Arrow, 10, &mut events),
+            Err(InventoryError::TooHeavy)
+        );
+        assert_eq!(inventory.entries()[0], (Thing::Arrow, 1));
+        assert_eq!(events.drain::<InventoryEvent<Thing>>().len(), 2);
+    }
+
+    #[test]
+    fn letters_map_to_entries() {
+        for (index, letter) in [(0, 'a'), (25, 'z'), (26, 'A'), (51, 'Z')] {
+            assert_eq!(inventory_letter(index), Some(letter));
+            assert_eq!(inventory_index(letter), Some(index));
+        }
+        assert_eq!(inventory_letter(52), None);
+        assert_eq!(inventory_index('1'), None);
+    }
+
+    #[test]
+    fn equipping_swaps_items_in_their_slots() {
+        let mut world = crate::World::new();
+        let owner = world.spawn();
+        let mut events = EventBus::new();
+        let mut equipment = EquipmentSlots::new();
+        equipment
+            .with_owner(owner)
+            .with_slot(Slot::Hand)
+            .with_slot(Slot::Head);
+
+        assert_eq!(
+            equipment.equip(Slot::Head, Thing::Sword, &mut events),
+            Err(InventoryError::WrongSlot)
+        );
+        assert_eq!(
+            equipment.equip(Slot::Hand, Thing::Sword, &mut events),
+            Ok(None)
+        );
+        assert_eq!(
+            equipment.equip(Slot::Hand, Thing::Sword, &mut events),
+            Ok(Some(Thing::Sword))
+        );
+        equipment
+            .equip(Slot::Head, Thing::Helm, &mut events)
+            .unwrap();
+        assert_eq!(equipment.get(Slot::Head), Some(&Thing::Helm));
+        assert_eq!(equipment.weight(), 15);
+
+        assert_eq!(
+            equipment.unequip(Slot::Head, &mut events),
+            Some(Thing::Helm)
+        );
+        assert_eq!(equipment.unequip(Slot::Head, &mut events), None);
+        assert_eq!(
+            events.drain::<InventoryEvent<Thing>>(),
+            [
+                InventoryEvent::Equipped {
+                    owner: Some(owner),
+                    item: Thing::Sword
+                },
+                InventoryEvent::Unequipped {
+                    owner: Some(owner),
+                    item: Thing::Sword
+                },
+                InventoryEvent::Equipped {
+                    owner: Some(owner),
+                    item: Thing::Sword
+                },
+                InventoryEvent::Equipped {
+                    owner: Some(owner),
+                    item: Thing::Helm
+                },
+                InventoryEvent::Unequipped {
+                    owner: Some(owner),
+                    item: Thing::Helm
+                },
+            ]
+        );
+    }
+}
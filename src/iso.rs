@@ -0,0 +1,200 @@
+//
+// Isometric projection
+//
+// Draws a square tile map turned 45 degrees, so that map rows run diagonally
+// down and to the right across the screen.  Each tile is a few cells wide and
+// one row high, and neighbouring tiles interlock like bricks:
+//
+//              ##              (0, 0)
+//            ..  ..            (0, 1) (1, 0)
+//          ..  @@  ..          (0, 2) (1, 1) (2, 0)
+//
+// Sprites (images several cells tall, like trees, walls or large monsters)
+// stand on a tile and are drawn back to front with the tiles so nearer things
+// cover further ones.
+//
+
+use crate::{Char, Grid, Image, Point};
+
+pub struct IsoProjection {
+    origin: Point,
+    tile_width: u32,
+}
+
+// An image standing on a map tile.  The anchor is the cell of the image that
+// lines up with the left cell of the tile, usually on its bottom row.  Cells
+// with a character code of 0 are transparent.
+pub struct IsoSprite<'a> {
+    pub position: Point,
+    pub image: &'a Image,
+    pub anchor: Point,
+}
+
+impl IsoProjection {
+    // The origin is where the left cell of map tile (0, 0) is drawn.
+    pub fn new(origin: Point) -> Self {
+        IsoProjection {
+            origin,
+            tile_width: 2,
+        }
+    }
+
+    // Tiles are rounded up to an even number of cells.
+    pub fn with_tile_width(&mut self, width: u32) -> &mut Self {
+        self.tile_width = (width.max(1) + 1) & !1;
+        self
+    }
+
+    pub fn with_origin(&mut self, origin: Point) -> &mut Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn tile_width(&self) -> u32 {
+        self.tile_width
+    }
+
+    // The screen cell of the left of a map tile
+    pub fn to_screen(&self, p: Point) -> Point {
+        let half = (self.tile_width / 2) as i32;
+        Point::new(
+            self.origin.x + (p.x - p.y) * half,
+            self.origin.y + p.x + p.y,
+        )
+    }
+
+    // The map tile covering a screen cell, e.g. under the mouse
+    pub fn to_map(&self, p: Point) -> Point {
+        let half = (self.tile_width / 2) as i32;
+        let mut across = (p.x - self.origin.x).div_euclid(half);
+        let down = p.y - self.origin.y;
+        // Each row only holds tiles where across and down are both odd or
+        // both even, so the other half of a tile belongs to the one before.
+        if (across + down) & 1 != 0 {
+            across -= 1;
+        }
+        Point::new((across + down) / 2, (down - across) / 2)
+    }
+
+    // Tiles and sprites with a lower depth are further away.
+    pub fn depth(&self, p: Point) -> i32 {
+        p.x + p.y
+    }
+}
+
+impl Image {
+    // Draws a map through a projection, filling each tile's cells with the
+    // Char returned for it, then the sprites standing on the map.
+    pub fn draw_iso_map<T>(
+        &mut self,
+        projection: &IsoProjection,
+        map: &Grid<T>,
+        tile: impl Fn(Point, &T) -> Char,
+        sprites: &[IsoSprite],
+    ) {
+        // Sort everything back to front, tiles before the sprites standing on
+        // them and left to right within a row.
+        let mut order = map
+            .iter()
+            .map(|(p, _)| (projection.depth(p), 0, p.x, None))
+            .chain(
+                sprites
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| (projection.depth(s.position), 1, s.position.x, Some(i))),
+            )
+            .collect::<Vec<_>>();
+        order.sort_by_key(|&(depth, layer, x, _)| (depth, layer, x));
+
+        for (depth, _, x, sprite) in order {
+            match sprite {
+                None => {
+                    let p = Point::new(x, depth - x);
+                    if let Some(cell) = map.get(p) {
+                        let ch = tile(p, cell);
+                        let s = projection.to_screen(p);
+                        self.draw_rect_filled(s, projection.tile_width, 1, ch);
+                    }
+                }
+                Some(i) => {
+                    let sprite = &sprites[i];
+                    let s = projection.to_screen(sprite.position);
+                    self.draw_sprite(
+                        Point::new(s.x - sprite.anchor.x, s.y - sprite.anchor.y),
+                        sprite.image,
+                    );
+                }
+            }
+        }
+    }
+
+    // Draws an image skipping its transparent cells.
+    fn draw_sprite(&mut self, p: Point, image: &Image) {
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let i = (y * image.width + x) as usize;
+                let ch = image.text_image[i];
+                if ch != 0 {
+                    let q = Point::new(p.x + x as i32, p.y + y as i32);
+                    self.draw_glyph(q, ch, image.fore_image[i], image.back_image[i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_cell_of_a_tile_maps_back_to_it() {
+        let mut projection = IsoProjection::new(Point::new(10, 2));
+        projection.with_tile_width(3);
+        assert_eq!(projection.tile_width(), 4);
+        assert_eq!(projection.to_screen(Point::new(1, 0)), Point::new(12, 3));
+        assert_eq!(projection.to_screen(Point::new(0, 1)), Point::new(8, 3));
+        for y in -2..3 {
+            for x in -2..3 {
+                let p = Point::new(x, y);
+                let s = projection.to_screen(p);
+                for dx in 0..4 {
+                    assert_eq!(projection.to_map(s.offset(dx, 0)), p, "{:?} + {}", p, dx);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_interlock_and_sprites_stand_on_them() {
+        let map = Grid::from_fn(3, 3, |p| if p == Point::new(1, 1) { b'@' } else { b'.' });
+        let mut tree = Image::new(2, 2);
+        tree.draw_glyph(Point::new(0, 0), b'T' as u32, 1, 0);
+        tree.draw_glyph(Point::new(0, 1), b'|' as u32, 1, 0);
+        // The tree stands on (1, 0), so the nearer tile (1, 1) covers its
+        // trunk.  Its right column is transparent, so the tile under it shows.
+        let sprite = IsoSprite {
+            position: Point::new(1, 0),
+            image: &tree,
+            anchor: Point::new(0, 0),
+        };
+        let mut image = Image::new(10, 5);
+        image.clear(0, 0);
+        image.draw_iso_map(
+            &IsoProjection::new(Point::new(4, 0)),
+            &map,
+            |_, &ch| Char::new(ch, 1, 0),
+            &[sprite],
+        );
+        assert_eq!(
+            image.to_strings(),
+            [
+                "    ..    ",
+                "   ..T.   ",
+                "  ..@@..  ",
+                "   ....   ",
+                "    ..    ",
+            ]
+        );
+    }
+}
@@ -1,87 +1,243 @@
+mod accessibility;
+mod achievements;
+mod animation;
+mod ascii;
+mod assets;
+mod autosave;
+mod behaviour;
+mod canvas;
+mod cellular;
+mod clock;
+mod cloud_save;
+mod colour_filter;
+#[cfg(feature = "content")]
+mod content;
+mod context;
+mod crash;
+mod daily;
+mod diagnostics;
+mod dialogue;
+mod diffusion;
+mod dijkstra;
+mod ecs;
+mod effects;
+mod events;
+mod explore;
+mod external;
 pub mod generation;
+mod glyphs;
+mod golden;
+mod grid;
+mod hex;
+mod history;
+mod input;
+mod input_map;
+mod inventory;
+mod iso;
+mod key;
+mod light_map;
+mod locale;
+mod logging;
+mod loot;
+mod memory;
+mod morgue;
+#[cfg(feature = "generation")]
+mod names;
+#[cfg(feature = "net")]
+pub mod net;
+mod noise;
+mod pack;
+mod palette;
+#[cfg(feature = "plugins")]
+mod plugin;
+#[cfg(feature = "presence")]
+mod presence;
 mod present;
+mod profiler;
+mod quest;
+mod raycast;
 mod render;
+mod rng;
+mod save;
+mod screen_reader;
+mod seed;
+mod software;
+mod spatial;
+#[cfg(feature = "net")]
+mod spectate;
+mod state_hash;
+mod status;
+mod telnet;
+mod touch;
+mod turns;
+mod ui;
+mod weather;
+mod window;
+mod window_handle;
+mod window_pixels;
 
+pub use accessibility::*;
+pub use achievements::*;
+pub use animation::*;
+pub use assets::*;
+pub use autosave::*;
+pub use behaviour::*;
+pub use canvas::*;
+pub use cellular::*;
+pub use clock::WorldClock;
+pub use cloud_save::*;
+pub use colour_filter::*;
+#[cfg(feature = "content")]
+pub use content::*;
+pub use context::Context;
+pub use crash::CrashReport;
+pub use daily::DailyChallenge;
+pub use diagnostics::Diagnostics;
+pub use dialogue::*;
+pub use diffusion::DiffusionMap;
+pub use dijkstra::*;
+pub use ecs::*;
+pub use effects::*;
+pub use events::{EventBus, GridResized};
+pub use explore::*;
+pub use external::{ChannelSource, ExternalCommand, ExternalInput, ExternalKey, InputSource};
+#[cfg(feature = "dungeon-generation")]
 pub use generation::*;
+pub use glyphs::{Glyphs, MAX_GLYPHS};
+pub use golden::*;
+pub use grid::*;
+pub use hex::*;
+pub use history::*;
 pub use image::ImageFormat;
+pub use input::*;
+pub use input_map::*;
+pub use inventory::*;
+pub use iso::*;
+pub use key::Key;
+pub use light_map::LightMap;
+pub use locale::*;
+pub use logging::*;
+pub use loot::*;
+pub use memory::*;
+pub use morgue::*;
+#[cfg(feature = "generation")]
+pub use names::*;
+pub use noise::*;
+pub use pack::AssetPack;
+pub use palette::*;
+#[cfg(feature = "plugins")]
+pub use plugin::*;
+#[cfg(feature = "presence")]
+pub use presence::{DiscordPresence, Presence, PresenceBackend};
 pub use present::*;
-pub use winit::event::VirtualKeyCode;
+pub use profiler::*;
+pub use quest::*;
+pub use raycast::*;
+pub use render::{list_adapters, EdgePolicy, GraphicsBackend, GraphicsOptions};
+pub use rng::GameRng;
+pub use save::*;
+pub use screen_reader::*;
+pub use seed::*;
+pub use software::rasterise;
+pub use spatial::SpatialIndex;
+#[cfg(feature = "net")]
+pub use spectate::{FrameBroadcaster, SpectatorClient};
+pub use state_hash::*;
+pub use status::*;
+pub use telnet::TelnetServer;
+pub use touch::Gesture;
+pub use turns::*;
+pub use ui::*;
+pub use weather::*;
+pub use wgpu::{AdapterInfo, Backend, DeviceType, PowerPreference};
+pub use window::{FullscreenMode, WindowHandle, WindowPosition};
 
-use bytemuck::cast_slice;
 use futures::executor::block_on;
-use image::{EncodableLayout, GenericImageView};
+use image::GenericImageView;
 use render::*;
-use std::{cmp::max, mem::replace, time::Duration};
+use std::{
+    cmp::max,
+    mem::replace,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 use thiserror::Error;
-use wgpu::SwapChainError;
+#[cfg(feature = "window-persistence")]
+use window::WindowGeometry;
+use window::{FullscreenState, WindowRegistry};
 use winit::{
     dpi::PhysicalSize,
     event::{ElementState, Event, KeyboardInput, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Fullscreen, WindowBuilder},
+    window::{Icon, Window, WindowBuilder},
 };
 
 pub trait Game {
     fn start(&mut self);
     fn tick(&mut self, sim_input: SimInput) -> TickResult;
     fn present(&self, present_input: PresentInput);
-}
 
-pub enum TickResult {
-    Continue,
-    Stop,
-}
+    // Called before the next tick when the main window's grid changes size,
+    // so layouts can be worked out once.  GridResized is published too.
+    fn resize(&mut self, _width: u32, _height: u32) {}
 
-pub struct KeyState {
-    pub pressed: bool,
-    pub shift: bool,
-    pub ctrl: bool,
-    pub alt: bool,
-    pub vkey: Option<VirtualKeyCode>,
-}
+    // Called to draw the contents of windows opened with Context::open_window().
+    fn present_window(&self, _window: WindowHandle, _present_input: PresentInput) {}
 
-impl KeyState {
-    pub fn alt_pressed(&self) -> bool {
-        self.alt && !self.ctrl && !self.shift
-    }
-    pub fn ctrl_pressed(&self) -> bool {
-        !self.alt && self.ctrl && !self.shift
-    }
-    pub fn shift_pressed(&self) -> bool {
-        !self.alt && !self.ctrl && self.shift
-    }
-    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
-        if self.pressed {
-            if let Some(vkey) = self.vkey {
-                if key == vkey {
-                    return true;
-                }
-            }
-        }
-        false
+    // Called when an autosave is due, for a snapshot of the state to save on
+    // the autosave thread.  See autosave.rs.
+    fn autosave(&self) -> Option<SaveSnapshot> {
+        None
     }
 }
 
-pub struct MouseState {
-    pub on_screen: bool,
-    pub left_pressed: bool,
-    pub right_pressed: bool,
-    pub x: i32,
-    pub y: i32,
+pub enum TickResult {
+    Continue,
+    Stop,
 }
 
 pub struct SimInput<'a> {
     pub dt: Duration,
     pub width: u32,
     pub height: u32,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    pub scale_factor: f64,
     pub key: &'a KeyState,
     pub mouse: Option<MouseState>,
+    pub gestures: &'a [Gesture],
+    pub text: &'a str,
+    pub ctx: &'a mut Context,
 }
 
 pub fn new_colour(r: u8, g: u8, b: u8) -> u32 {
     0xff000000u32 + ((b as u32) << 16) + ((g as u32) << 8) + (r as u32)
 }
 
+// Blend between two colours, where t = 0 gives a and t = 1 gives b.
+pub fn lerp_colour(a: u32, b: u32, t: f32) -> u32 {
+    let t = t.clamp(0.0, 1.0);
+    (0..4).fold(0, |colour, channel| {
+        let shift = channel * 8;
+        let ca = ((a >> shift) & 0xff) as f32;
+        let cb = ((b >> shift) & 0xff) as f32;
+        colour | (((ca + (cb - ca) * t).round() as u32) << shift)
+    })
+}
+
+// Multiply two colours channel by channel, e.g. to light a colour with an
+// ambient tint.  White leaves the colour unchanged.
+pub fn tint_colour(colour: u32, tint: u32) -> u32 {
+    (0..3).fold(colour & 0xff000000, |result, channel| {
+        let shift = channel * 8;
+        let c = (colour >> shift) & 0xff;
+        let t = (tint >> shift) & 0xff;
+        result | ((c * t / 255) << shift)
+    })
+}
+
 pub enum Colour {
     Black,
     Red,
@@ -122,6 +278,81 @@ pub enum RogueError {
 
     #[error("Unable to read font data")]
     BadFont,
+
+    #[error("Unable to read icon data")]
+    BadIcon,
+
+    #[error("The font's {font_width}x{font_height} characters don't fit in a {window_width}x{window_height} window")]
+    FontTooLarge {
+        font_width: u32,
+        font_height: u32,
+        window_width: u32,
+        window_height: u32,
+    },
+
+    #[error("Asset {name} not found{}", location.as_ref().map_or(String::new(), |l| format!(" in {}", l)))]
+    AssetNotFound {
+        name: String,
+        location: Option<String>,
+    },
+
+    #[error("Unable to parse {file} at line {line}: {message}")]
+    BadConfig {
+        file: String,
+        line: usize,
+        message: String,
+    },
+
+    #[error(transparent)]
+    ClipboardError(#[from] arboard::Error),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error("Unable to read {file}: {message}")]
+    BadContent { file: String, message: String },
+
+    #[error("Unable to read map data: {0}")]
+    BadMapData(String),
+
+    #[error("Unable to encode or decode a network message: {0}")]
+    BadMessage(String),
+
+    #[error("A logger has already been installed")]
+    LoggerInstalled,
+
+    #[error("Not a save file")]
+    BadSave,
+
+    #[error("Unable to read the saved {component} components: {message}")]
+    BadComponents { component: String, message: String },
+
+    #[error(
+        "The save is version {version}, which is newer than this game reads (version {current})"
+    )]
+    SaveTooNew { version: u32, current: u32 },
+
+    #[error("Saves can't be migrated from version {from} to version {to}")]
+    MissingMigration { from: u32, to: u32 },
+
+    #[error("Unable to migrate the save from version {from}: {message}")]
+    MigrationFailed { from: u32, message: String },
+
+    #[error("Plugin {plugin} was denied: {message}")]
+    PluginDenied { plugin: String, message: String },
+
+    #[error("Plugin {plugin} failed: {message}")]
+    PluginFailed { plugin: String, message: String },
+
+    #[error("Save slot {slot} not found in {location}")]
+    SlotNotFound { slot: String, location: String },
+
+    #[error("{kind} '{id}' refers to unknown {reference}")]
+    MissingReference {
+        kind: &'static str,
+        id: String,
+        reference: String,
+    },
 }
 
 pub type RogueResult<T> = Result<T, RogueError>;
@@ -134,12 +365,48 @@ pub struct RogueBuilder {
     inner_size: (usize, usize),
     title: String,
     font: RogueFont,
+    fallback_fonts: Vec<(RogueFontData, String)>,
+    icon: Option<Vec<u8>>,
+    app_id: Option<String>,
+    monitor: Option<usize>,
+    video_mode: Option<(u32, u32)>,
+    fullscreen_mode: FullscreenMode,
+    position: WindowPosition,
+    crash_log: Option<PathBuf>,
+    diagnostics: Option<(PathBuf, Option<Key>)>,
+    state_hashes: Option<PathBuf>,
+    autosave: Option<(PathBuf, u32)>,
+    debug_overlay: Option<Key>,
+    zoom_keys: Option<(Key, Key)>,
+    escape_quits: bool,
+    graphics: GraphicsOptions,
+    transparent: bool,
+    decorations: bool,
+    resizable: bool,
+    always_on_top: bool,
+    maximised: bool,
+    seed: Option<u64>,
+    #[cfg(feature = "net")]
+    spectators: Option<String>,
+    accessibility: AccessibilitySettings,
+    input_options: InputOptions,
+    #[cfg(feature = "window-persistence")]
+    geometry_name: Option<String>,
 }
 
+// Clones share the font's fallback glyphs.  See glyphs.rs.
+#[derive(Clone)]
 pub struct RogueFontData {
     data: Vec<u32>,
     width: u32,
     height: u32,
+    glyphs: Glyphs,
+}
+
+impl RogueFontData {
+    pub fn glyphs(&self) -> &Glyphs {
+        &self.glyphs
+    }
 }
 
 enum RogueFont {
@@ -153,6 +420,33 @@ impl RogueBuilder {
             inner_size: (100, 100),
             title: "md-rogue window".to_string(),
             font: RogueFont::Default,
+            fallback_fonts: Vec::new(),
+            icon: None,
+            app_id: None,
+            monitor: None,
+            video_mode: None,
+            fullscreen_mode: FullscreenMode::default(),
+            position: WindowPosition::default(),
+            crash_log: None,
+            diagnostics: None,
+            state_hashes: None,
+            autosave: None,
+            debug_overlay: None,
+            zoom_keys: Some((Key::Equals, Key::Minus)),
+            escape_quits: true,
+            graphics: GraphicsOptions::default(),
+            transparent: false,
+            decorations: true,
+            resizable: true,
+            always_on_top: false,
+            maximised: false,
+            seed: None,
+            #[cfg(feature = "net")]
+            spectators: None,
+            accessibility: AccessibilitySettings::default(),
+            input_options: InputOptions::default(),
+            #[cfg(feature = "window-persistence")]
+            geometry_name: None,
         }
     }
 
@@ -171,11 +465,301 @@ impl RogueBuilder {
         self
     }
 
+    // Characters missing from the font are looked for in the fallback fonts,
+    // in the order they were added.  chars lists the characters in the
+    // font's sheet.  See glyphs.rs.
+    pub fn with_fallback_font(&mut self, font: RogueFontData, chars: &str) -> &mut Self {
+        self.fallback_fonts.push((font, String::from(chars)));
+        self
+    }
+
+    // The icon can be in any format the image crate can detect (PNG, ICO
+    // etc).  It is decoded when the window is created.
+    pub fn with_icon(&mut self, data: &[u8]) -> &mut Self {
+        self.icon = Some(Vec::from(data));
+        self
+    }
+
+    // Used to group windows in the taskbar.  This sets the Wayland app ID and
+    // the X11 WM_CLASS, and is ignored on other platforms.
+    pub fn with_app_id(&mut self, app_id: &str) -> &mut Self {
+        self.app_id = Some(String::from(app_id));
+        self
+    }
+
+    // Index into the list of monitors reported by the OS.  The window opens on
+    // this monitor and fullscreen uses it.  By default, fullscreen uses
+    // whichever monitor the window is currently on.
+    pub fn with_monitor(&mut self, index: usize) -> &mut Self {
+        self.monitor = Some(index);
+        self
+    }
+
+    // Preferred resolution for exclusive fullscreen.  If the monitor does not
+    // support it, the largest video mode is used.
+    pub fn with_video_mode(&mut self, width: u32, height: u32) -> &mut Self {
+        self.video_mode = Some((width, height));
+        self
+    }
+
+    pub fn with_fullscreen_mode(&mut self, mode: FullscreenMode) -> &mut Self {
+        self.fullscreen_mode = mode;
+        self
+    }
+
+    pub fn with_position(&mut self, position: WindowPosition) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    // Show panics in the window instead of closing it, writing the details
+    // to a crash log file.  See crash.rs.
+    pub fn with_crash_screen(&mut self, log_file: &Path) -> &mut Self {
+        self.crash_log = Some(log_file.to_path_buf());
+        self
+    }
+
+    // Save diagnostics reports for bug reports in the directory, when the key
+    // is pressed and after a crash.  See diagnostics.rs.
+    pub fn with_diagnostics(&mut self, dir: &Path, key: Option<Key>) -> &mut Self {
+        self.diagnostics = Some((dir.to_path_buf(), key));
+        self
+    }
+
+    // Log the hashes of the state the game registers each turn to the file,
+    // to compare runs for determinism bugs.  See state_hash.rs.
+    pub fn with_state_hashes(&mut self, log_file: &Path) -> &mut Self {
+        self.state_hashes = Some(log_file.to_path_buf());
+        self
+    }
+
+    // Autosave to the file, stamped with the game's save format version.  It
+    // saves on quit until Context::autosave() sets other times.  See
+    // autosave.rs.
+    pub fn with_autosave(&mut self, save_file: &Path, version: u32) -> &mut Self {
+        self.autosave = Some((save_file.to_path_buf(), version));
+        self
+    }
+
+    // Toggle the debug overlay, with frame timings and memory usage, with the
+    // key.  See ui/debug_overlay.rs.
+    pub fn with_debug_overlay(&mut self, key: Key) -> &mut Self {
+        self.debug_overlay = Some(key);
+        self
+    }
+
+    // The keys that zoom the cells in and out when pressed with Ctrl, or None
+    // to leave zooming to the game.  See Context::set_zoom().
+    pub fn with_zoom_keys(&mut self, keys: Option<(Key, Key)>) -> &mut Self {
+        self.zoom_keys = keys;
+        self
+    }
+
+    // Whether Escape quits the game, which it does by default.  See
+    // Context::set_escape_quits() and Context::claim_escape().
+    pub fn with_escape_quits(&mut self, quits: bool) -> &mut Self {
+        self.escape_quits = quits;
+        self
+    }
+
+    // Give the game a fixed grid, e.g. 80x25 for a virtual terminal, that is
+    // scaled to fit the window instead of growing and shrinking with it.  The
+    // window starts large enough to show the whole grid.
+    pub fn with_grid_size(&mut self, columns: u32, rows: u32) -> &mut Self {
+        self.graphics.grid_size = Some((columns.max(1), rows.max(1)));
+        self
+    }
+
+    // Limits on the grid's size in cells when it follows the window's size.
+    // The window can't be made smaller than the minimum.
+    pub fn with_min_grid_size(&mut self, columns: u32, rows: u32) -> &mut Self {
+        self.graphics.min_grid_size = Some((columns.max(1), rows.max(1)));
+        self
+    }
+
+    pub fn with_max_grid_size(&mut self, columns: u32, rows: u32) -> &mut Self {
+        self.graphics.max_grid_size = Some((columns.max(1), rows.max(1)));
+        self
+    }
+
+    // Force a graphics API, for drivers where the default one misbehaves.
+    pub fn with_backend(&mut self, backend: GraphicsBackend) -> &mut Self {
+        self.graphics.backend = backend;
+        self
+    }
+
+    // Prefer the integrated (LowPower) or discrete (HighPerformance) GPU.
+    pub fn with_power_preference(&mut self, preference: PowerPreference) -> &mut Self {
+        self.graphics.power_preference = preference;
+        self
+    }
+
+    // Use the first adapter whose name contains the given text, ignoring
+    // case.  If none does, the adapter is chosen as usual.  See
+    // list_adapters() for the names.
+    pub fn with_adapter(&mut self, name: &str) -> &mut Self {
+        self.graphics.adapter_name = Some(String::from(name));
+        self
+    }
+
+    // Treat colours as linear rather than sRGB.  See GraphicsOptions.
+    pub fn with_linear_colours(&mut self, linear: bool) -> &mut Self {
+        self.graphics.linear_colours = linear;
+        self
+    }
+
+    // Draws effects over the main window's cells with a WGSL compute shader.
+    // See the GPU effects in render.rs for its bindings.
+    pub fn with_effects_shader(&mut self, wgsl: &str) -> &mut Self {
+        self.graphics.effects_shader = Some(String::from(wgsl));
+        self
+    }
+
+    // The colour outside the cells, which can be changed later with
+    // Context::set_clear_colour().
+    pub fn with_clear_colour(&mut self, colour: u32) -> &mut Self {
+        self.graphics.clear_colour = colour;
+        self
+    }
+
+    // How the grid fits a window that isn't a whole number of cells.
+    pub fn with_edge_policy(&mut self, policy: EdgePolicy) -> &mut Self {
+        self.graphics.edge_policy = policy;
+        self
+    }
+
+    // Ask for a window that shows what is behind it where the colours are
+    // transparent, e.g. a clear colour of 0.  Not all platforms support it.
+    pub fn with_transparent(&mut self, transparent: bool) -> &mut Self {
+        self.transparent = transparent;
+        self
+    }
+
+    // Turn off the title bar and border, e.g. for an overlay or a launcher.
+    pub fn with_decorations(&mut self, decorations: bool) -> &mut Self {
+        self.decorations = decorations;
+        self
+    }
+
+    // Stop the player resizing the window.  Fullscreen still works.
+    pub fn with_resizable(&mut self, resizable: bool) -> &mut Self {
+        self.resizable = resizable;
+        self
+    }
+
+    // Keep the window above other windows, e.g. for a tool used alongside
+    // another program.
+    pub fn with_always_on_top(&mut self, always_on_top: bool) -> &mut Self {
+        self.always_on_top = always_on_top;
+        self
+    }
+
+    // Open the window maximised.  The inner size is used when the player
+    // restores it.
+    pub fn with_maximised(&mut self, maximised: bool) -> &mut Self {
+        self.maximised = maximised;
+        self
+    }
+
+    // Seed the context's RNG instead of seeding it from the clock.  The text
+    // is read with parse_seed(), so it can come straight from a config file
+    // or the player.
+    pub fn with_seed(&mut self, text: &str) -> &mut Self {
+        self.seed = Some(parse_seed(text));
+        self
+    }
+
+    // Take the seed from a "--seed <text>" or "--seed=<text>" command line
+    // argument, if there is one.
+    pub fn with_seed_from_args(&mut self) -> &mut Self {
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let text = match arg.strip_prefix("--seed") {
+                Some("") => args.next(),
+                Some(text) => text.strip_prefix('=').map(String::from),
+                None => None,
+            };
+            if let Some(text) = text {
+                self.with_seed(&text);
+            }
+        }
+        self
+    }
+
+    // Start with the screen reader description enabled.  See screen_reader.rs.
+    pub fn with_screen_reader(&mut self, enabled: bool) -> &mut Self {
+        self.accessibility.screen_reader = enabled;
+        self
+    }
+
+    // Filter the colours of every frame.  See colour_filter.rs.
+    pub fn with_colour_filter(&mut self, filter: ColourFilter) -> &mut Self {
+        self.accessibility.colour_filter = filter;
+        self
+    }
+
+    // Start with accessibility settings, usually those saved by the game's
+    // options screen with AccessibilitySettings::save().
+    pub fn with_accessibility(&mut self, settings: AccessibilitySettings) -> &mut Self {
+        self.accessibility = settings;
+        self
+    }
+
+    // Serve every frame to spectators connecting to the address over
+    // WebSocket.  See spectate.rs.
+    #[cfg(feature = "net")]
+    pub fn with_spectators(&mut self, addr: &str) -> &mut Self {
+        self.spectators = Some(String::from(addr));
+        self
+    }
+
+    // Start with sticky modifiers, key repeat or key echo.  See input.rs.
+    pub fn with_input_options(&mut self, options: InputOptions) -> &mut Self {
+        self.input_options = options;
+        self
+    }
+
+    // Save the window's position and size on exit, and restore them on the
+    // next run.  The name is used as the sub-directory in the user's config
+    // directory, and the saved geometry overrides the inner size and position.
+    #[cfg(feature = "window-persistence")]
+    pub fn with_saved_geometry(&mut self, name: &str) -> &mut Self {
+        self.geometry_name = Some(String::from(name));
+        self
+    }
+
     pub fn build(&mut self) -> Self {
         RogueBuilder {
             inner_size: self.inner_size,
             title: self.title.clone(),
             font: replace(&mut self.font, RogueFont::Default),
+            fallback_fonts: std::mem::take(&mut self.fallback_fonts),
+            icon: self.icon.take(),
+            app_id: self.app_id.take(),
+            monitor: self.monitor,
+            video_mode: self.video_mode,
+            fullscreen_mode: self.fullscreen_mode,
+            position: self.position,
+            crash_log: self.crash_log.take(),
+            diagnostics: self.diagnostics.take(),
+            state_hashes: self.state_hashes.take(),
+            autosave: self.autosave.take(),
+            debug_overlay: self.debug_overlay,
+            zoom_keys: self.zoom_keys,
+            escape_quits: self.escape_quits,
+            graphics: self.graphics.clone(),
+            transparent: self.transparent,
+            decorations: self.decorations,
+            resizable: self.resizable,
+            always_on_top: self.always_on_top,
+            maximised: self.maximised,
+            seed: self.seed,
+            #[cfg(feature = "net")]
+            spectators: self.spectators.take(),
+            accessibility: self.accessibility,
+            input_options: self.input_options,
+            #[cfg(feature = "window-persistence")]
+            geometry_name: self.geometry_name.take(),
         }
     }
 }
@@ -186,26 +770,42 @@ impl Default for RogueBuilder {
     }
 }
 
+// Any pixels right of or below the 16x16 grid of whole glyphs are ignored.
 pub fn load_font_image(data: &[u8], format: ImageFormat) -> RogueResult<RogueFontData> {
     let font_image =
         image::load_from_memory_with_format(data, format).map_err(|_| RogueError::BadFont)?;
     let dimensions = font_image.dimensions();
-    let font_rgba = font_image.to_rgba8();
-    let font_data = font_rgba.as_bytes();
-    let data_u32: &[u32] = cast_slice(font_data);
     let char_width = dimensions.0 / 16;
     let char_height = dimensions.1 / 16;
     if char_width == 0 || char_height == 0 {
         return Err(RogueError::BadFont);
     }
 
+    // The pixels are copied out one by one, as the image's bytes may not be
+    // aligned for u32s.
+    let font_rgba = font_image.to_rgba8();
+    let glyphs = image::imageops::crop_imm(&font_rgba, 0, 0, 16 * char_width, 16 * char_height);
+    let data = glyphs
+        .to_image()
+        .pixels()
+        .map(|pixel| u32::from_le_bytes(pixel.0))
+        .collect();
+
     Ok(RogueFontData {
         width: char_width,
         height: char_height,
-        data: Vec::from(data_u32),
+        data,
+        glyphs: Glyphs::new(),
     })
 }
 
+fn load_icon(data: &[u8]) -> RogueResult<Icon> {
+    let icon_image = image::load_from_memory(data).map_err(|_| RogueError::BadIcon)?;
+    let (width, height) = icon_image.dimensions();
+    let icon_rgba = icon_image.to_rgba8().into_raw();
+    Icon::from_rgba(icon_rgba, width, height).map_err(|_| RogueError::BadIcon)
+}
+
 pub fn run(rogue: RogueBuilder, game: Box<dyn Game>) -> RogueResult<()> {
     block_on(run_internal(rogue, game))
 }
@@ -215,44 +815,155 @@ pub async fn run_internal(rogue: RogueBuilder, mut game: Box<dyn Game>) -> Rogue
         RogueFont::Default => load_font_image(include_bytes!("font1.png"), ImageFormat::Png)?,
         RogueFont::Custom(font) => font,
     };
+    for (font, chars) in rogue.fallback_fonts {
+        font_data.glyphs.add_fallback_font(font, &chars);
+    }
+
+    // A fixed grid fits the window at its normal size to begin with
+    let (columns, rows) = rogue.graphics.grid_size.unwrap_or((0, 0));
+    let width = max(rogue.inner_size.0 as u32, columns * font_data.width);
+    let height = max(rogue.inner_size.1 as u32, rows * font_data.height);
+    let width = max(20, width) / font_data.width * font_data.width;
+    let height = max(20, height) / font_data.height * font_data.height;
+    if width == 0 || height == 0 {
+        return Err(RogueError::FontTooLarge {
+            font_width: font_data.width,
+            font_height: font_data.height,
+            window_width: rogue.inner_size.0 as u32,
+            window_height: rogue.inner_size.1 as u32,
+        });
+    }
+    let inner_size = PhysicalSize::new(width, height);
+    let (min_columns, min_rows) = rogue.graphics.min_grid_size.unwrap_or((20, 20));
 
-    let width = max(20, rogue.inner_size.0 as u32) / font_data.width * font_data.width;
-    let height = max(20, rogue.inner_size.1 as u32) / font_data.height * font_data.height;
+    let icon = match rogue.icon {
+        Some(data) => Some(load_icon(&data)?),
+        None => None,
+    };
 
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_inner_size(PhysicalSize::new(width, height))
+    let monitor = rogue
+        .monitor
+        .and_then(|index| event_loop.available_monitors().nth(index));
+    let position = rogue.position.resolve(
+        monitor.as_ref(),
+        event_loop.primary_monitor().as_ref(),
+        inner_size,
+    );
+
+    #[cfg(feature = "window-persistence")]
+    let geometry_name = rogue.geometry_name;
+    #[cfg(feature = "window-persistence")]
+    let (position, inner_size) = match geometry_name.as_deref().and_then(WindowGeometry::load) {
+        Some(geometry) => (Some(geometry.position), geometry.size),
+        None => (position, inner_size),
+    };
+
+    let mut window_builder = WindowBuilder::new()
+        .with_inner_size(inner_size)
         .with_title(rogue.title)
         .with_min_inner_size(PhysicalSize::new(
-            20 * font_data.width,
-            20 * font_data.height,
+            min_columns * font_data.width,
+            min_rows * font_data.height,
         ))
-        .build(&event_loop)?;
-    let mut render = RenderState::new(&window, &font_data).await?;
-
-    let mut key_state = KeyState {
-        vkey: None,
-        pressed: false,
-        alt: false,
-        ctrl: false,
-        shift: false,
-    };
+        .with_window_icon(icon.clone())
+        .with_transparent(rogue.transparent)
+        .with_decorations(rogue.decorations)
+        .with_resizable(rogue.resizable)
+        .with_always_on_top(rogue.always_on_top)
+        .with_maximized(rogue.maximised);
+
+    if let Some(position) = position {
+        window_builder = window_builder.with_position(position);
+    }
+
+    #[cfg(windows)]
+    {
+        use winit::platform::windows::WindowBuilderExtWindows;
+        window_builder = window_builder.with_taskbar_icon(icon);
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    if let Some(app_id) = rogue.app_id {
+        use winit::platform::unix::WindowBuilderExtUnix;
+        window_builder = window_builder
+            .with_app_id(app_id.clone())
+            .with_class(app_id.clone(), app_id);
+    }
+
+    let window = window_builder.build(&event_loop)?;
+    let mut render = Renderer::new(&window, &font_data, &rogue.graphics).await?;
 
-    game.start();
+    let mut input = InputState::new();
+    let mut context = Context::new();
+    context.adapter_info = Some(render.adapter_info().clone());
+    if let Some(seed) = rogue.seed {
+        *context.rng() = GameRng::new(seed);
+    }
+    log::info!("Seed: {}", context.rng().seed_words());
+    context.set_escape_quits(rogue.escape_quits);
+    context.set_accessibility(rogue.accessibility);
+    context.set_input_options(rogue.input_options);
+    #[cfg(feature = "net")]
+    if let Some(addr) = &rogue.spectators {
+        let broadcaster = FrameBroadcaster::bind(addr.as_str())?;
+        log::info!("Serving spectators on {}", broadcaster.local_addr()?);
+        context.broadcaster = Some(broadcaster);
+    }
+    context.debug_overlay.set_key(rogue.debug_overlay);
+    context.set_zoom_keys(rogue.zoom_keys);
+    let mut windows = WindowRegistry::new();
+    let mut last_tick = Instant::now();
+    let mut grid_size = render.chars_size();
+    let mut fullscreen = FullscreenState::new(rogue.fullscreen_mode, monitor, rogue.video_mode);
+
+    let crash_log = rogue.crash_log;
+    if crash_log.is_some() {
+        crash::install_panic_hook();
+    }
+    if let Some((dir, key)) = &rogue.diagnostics {
+        context.diagnostics = Some(Diagnostics::new(dir, *key));
+    }
+    if let Some(path) = &rogue.state_hashes {
+        match StateHashLog::create(path) {
+            Ok(log) => context.state_hashes = Some(log),
+            Err(e) => log::error!("Unable to create the state hash log: {}", e),
+        }
+    }
+    if let Some((path, version)) = &rogue.autosave {
+        context.autosave = Some(Autosave::new(path, *version));
+    }
+    let mut crash = crash::guard(crash_log.as_deref(), || game.start()).err();
+    let mut crash_diagnosed = false;
 
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run(move |event, target, control_flow| {
         *control_flow = ControlFlow::Poll;
 
         match event {
             //
             // Windowed Events
             //
-            Event::WindowEvent { event, window_id } if window.id() == window_id => {
+            // Secondary windows handle their own events first, and events
+            // from windows that have already closed are dropped.
+            Event::WindowEvent { event, window_id }
+                if !windows.handle_event(window_id, &event) && window_id == window.id() =>
+            {
                 match event {
                     //
                     // Closing the window
                     //
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Focused(false) => {
+                        if let Some(autosave) = &mut context.autosave {
+                            autosave.focus_lost();
+                        }
+                    }
 
                     //
                     // Keyboard Events
@@ -262,22 +973,53 @@ pub async fn run_internal(rogue: RogueBuilder, mut game: Box<dyn Game>) -> Rogue
                             KeyboardInput {
                                 state,
                                 virtual_keycode,
+                                scancode,
                                 ..
                             },
                         ..
                     } => {
-                        key_state.pressed = state == ElementState::Pressed;
-                        key_state.vkey = virtual_keycode;
+                        let pressed = state == ElementState::Pressed;
+                        let vkey = virtual_keycode.map(Key::from);
+                        let options = context.input_options();
+                        if !input.handle_key(pressed, vkey, scancode, &options) {
+                            return;
+                        }
+                        let diagnostics_key = context.diagnostics.as_ref().and_then(|d| d.key());
+                        if pressed && vkey.is_some() && vkey == diagnostics_key {
+                            let (text, style) = match context.save_diagnostics() {
+                                Ok(path) => (
+                                    format!("Diagnostics saved to {}", path.display()),
+                                    NotifyStyle::Info,
+                                ),
+                                Err(e) => (
+                                    format!("Unable to save diagnostics: {}", e),
+                                    NotifyStyle::Error,
+                                ),
+                            };
+                            context.notify(&text, style);
+                        }
+                        if pressed && vkey.is_some() && vkey == context.debug_overlay.key() {
+                            context.debug_overlay.toggle();
+                        }
+                        if let (true, true, Some(key), Some((zoom_in, zoom_out))) =
+                            (pressed, input.key.ctrl, vkey, context.zoom_keys())
+                        {
+                            if key == zoom_in {
+                                context.set_zoom(context.zoom() + 1);
+                            } else if key == zoom_out {
+                                context.set_zoom(context.zoom() - 1);
+                            }
+                        }
 
                         //
                         // Check for system keys
                         //
-                        match key_state {
+                        match input.key {
                             KeyState {
                                 pressed: true,
-                                vkey: Some(VirtualKeyCode::Escape),
+                                vkey: Some(Key::Escape),
                                 ..
-                            } => {
+                            } if context.escape_quits() => {
                                 //
                                 // Exit
                                 //
@@ -288,37 +1030,46 @@ pub async fn run_internal(rogue: RogueBuilder, mut game: Box<dyn Game>) -> Rogue
                                 shift: false,
                                 ctrl: false,
                                 alt: true,
-                                vkey: Some(VirtualKeyCode::Return),
+                                vkey: Some(Key::Return),
+                                ..
                             } => {
                                 //
                                 // Toggle fullscreen
                                 //
-                                if window.fullscreen().is_some() {
-                                    window.set_fullscreen(None);
-                                } else if let Some(monitor) = window.current_monitor() {
-                                    if let Some(video_mode) = monitor.video_modes().next() {
-                                        if cfg!(any(target_os = "macos", unix)) {
-                                            window.set_fullscreen(Some(Fullscreen::Borderless(
-                                                Some(monitor),
-                                            )));
-                                        } else {
-                                            window.set_fullscreen(Some(Fullscreen::Exclusive(
-                                                video_mode,
-                                            )));
-                                        }
-                                    };
-                                };
+                                fullscreen.toggle(&window);
                             }
                             _ => {}
                         }
                     }
                     //
+                    // Text entry
+                    //
+                    WindowEvent::ReceivedCharacter(ch) if !ch.is_control() => input.push_text(ch),
+                    //
                     // Modifier keys
                     //
                     WindowEvent::ModifiersChanged(mods) => {
-                        key_state.alt = mods.alt();
-                        key_state.ctrl = mods.ctrl();
-                        key_state.shift = mods.shift();
+                        input.set_modifiers(mods.alt(), mods.ctrl(), mods.shift());
+                    }
+                    //
+                    // Mouse events
+                    //
+                    WindowEvent::CursorEntered { .. }
+                    | WindowEvent::CursorLeft { .. }
+                    | WindowEvent::CursorMoved { .. }
+                    | WindowEvent::MouseInput { .. }
+                    | WindowEvent::MouseWheel { .. } => {
+                        input.mouse.handle_event(&event);
+                        if let WindowEvent::CursorMoved { position, .. } = event {
+                            render.place_mouse(&mut input.mouse, position);
+                        }
+                    }
+                    //
+                    // Touch events
+                    //
+                    WindowEvent::Touch(mut touch) => {
+                        touch.location = render.window_to_grid(touch.location);
+                        input.touches.handle_touch(&touch, render.font_size())
                     }
                     //
                     // Resizing
@@ -335,24 +1086,222 @@ pub async fn run_internal(rogue: RogueBuilder, mut game: Box<dyn Game>) -> Rogue
             // Idle
             //
             Event::MainEventsCleared => {
-                if let TickResult::Stop = simulate(game.as_mut(), &render, &key_state) {
-                    *control_flow = ControlFlow::Exit;
+                windows.sync(&mut context);
+                let (grids, atlases) = windows.memory();
+                context.grid_memory = render.grid_memory() + grids;
+                context.atlas_memory = render.atlas_memory() + atlases;
+                context.assets.update();
+                input.touches.update(render.font_size());
+                let large_font = context.accessibility().large_font;
+                render.set_zoom(context.zoom() * if large_font { 2 } else { 1 });
+                let (width, height) = render.chars_size();
+                context.light_map.resize(width, height);
+                if render.chars_size() != grid_size {
+                    grid_size = render.chars_size();
+                    context.events().publish(GridResized {
+                        width: grid_size.0,
+                        height: grid_size.1,
+                    });
+                    if crash.is_none() {
+                        let (width, height) = grid_size;
+                        let result =
+                            crash::guard(crash_log.as_deref(), || game.resize(width, height));
+                        if let Err(report) = result {
+                            crash = Some(report);
+                        }
+                    }
+                }
+                context.poll_input_sources();
+                if !input.key.pressed {
+                    if let Some(key) = context.external.next_key() {
+                        input.inject_key(key);
+                    }
+                }
+                input.text.push_str(&context.external.take_text());
+                let options = context.input_options();
+                input.begin_tick(&options);
+                if options.key_echo {
+                    if let Some(label) = input.key_label() {
+                        context.key_echo.push(&label);
+                    }
                 }
-                key_state.pressed = false;
-                key_state.vkey = None;
+                let now = Instant::now();
+                let dt = now - last_tick;
+                last_tick = now;
+                if crash.is_none() {
+                    let result = crash::guard(crash_log.as_deref(), || {
+                        simulate(game.as_mut(), dt, &window, &render, &input, &mut context)
+                    });
+                    context.profiler.record(Phase::Tick, now);
+                    match result {
+                        Ok(TickResult::Stop) => *control_flow = ControlFlow::Exit,
+                        Ok(TickResult::Continue) => {}
+                        Err(report) => crash = Some(report),
+                    }
+                }
+                let autosave_due = context
+                    .autosave
+                    .as_mut()
+                    .is_some_and(|autosave| autosave.update(dt));
+                if autosave_due && crash.is_none() {
+                    match crash::guard(crash_log.as_deref(), || game.autosave()) {
+                        Ok(snapshot) => context.autosave.as_mut().unwrap().submit(snapshot),
+                        Err(report) => crash = Some(report),
+                    }
+                }
+                let mouse_cell = Some(input.mouse)
+                    .filter(|mouse| mouse.on_screen)
+                    .map(|mouse| Point::new(mouse.grid_x, mouse.grid_y));
+                context.tooltips.update(mouse_cell, dt);
+                input.end_tick();
+                context.animator.update(dt);
+                context.clock.update(dt);
+                context.weather.update(dt);
+                context.update_achievements();
+                context.notifications.update(dt);
+                context.key_echo.update(dt);
+                if let Some(p) = context.caret.update(dt) {
+                    context.ime_position.get_or_insert(p);
+                }
+                #[cfg(feature = "presence")]
+                context.presence.update();
+                if let Some(colour) = context.clear_colour.take() {
+                    render.set_clear_colour(colour);
+                }
+                // The candidate box goes below the cell with the text cursor.
+                if let Some(p) = context.ime_position.take() {
+                    window.set_ime_position(render.cell_to_window(Point::new(p.x, p.y + 1)));
+                }
+                windows.end_tick();
+                windows.process_requests(
+                    &mut context,
+                    target,
+                    &font_data,
+                    render.graphics_options(),
+                );
                 window.request_redraw();
+                windows.request_redraws();
             }
             //
             // Redraw
             //
-            Event::RedrawRequested(_) => {
-                present(game.as_ref(), &mut render);
-                match render.render() {
-                    Ok(_) => {}
-                    Err(SwapChainError::Lost) => render.resize(window.inner_size()),
-                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    Err(e) => eprintln!("{:?}", e),
-                };
+            Event::RedrawRequested(window_id) if window_id == window.id() => {
+                let start = Instant::now();
+                if crash.is_none() {
+                    let result = crash::guard(crash_log.as_deref(), || {
+                        game.present(present_input(&window, &mut render))
+                    });
+                    if let Err(report) = result {
+                        crash = Some(report);
+                    }
+                }
+                match &crash {
+                    Some(report) => {
+                        report.draw(render.image());
+                        render.set_light(None);
+                        if !crash_diagnosed && context.diagnostics.is_some() {
+                            crash_diagnosed = true;
+                            match context.write_diagnostics(Some(report)) {
+                                Ok(path) => log::info!("Diagnostics saved to {}", path.display()),
+                                Err(e) => log::error!("Unable to save diagnostics: {}", e),
+                            }
+                        }
+                    }
+                    None => {
+                        context.animator.draw(render.image());
+                        context.weather.draw(render.image());
+                        context.clock.apply(render.image());
+                        // The engine's overlays are left unlit
+                        let world = context
+                            .light_map
+                            .is_enabled()
+                            .then(|| render.image().clone());
+                        context.tooltips.draw(render.image());
+                        context.notifications.draw(render.image());
+                        context.key_echo.draw(render.image());
+                        let memory = context.memory_usage();
+                        context
+                            .debug_overlay
+                            .draw(render.image(), &context.profiler, &memory);
+                        let light = world.and_then(|world| {
+                            context.light_map.frame_light(&world, render.image())
+                        });
+                        render.set_light(light.as_deref());
+                        let settings = context.accessibility();
+                        if let Some(ratio) = settings.min_contrast {
+                            render.image().enforce_contrast(ratio);
+                        }
+                        render.image().apply_colour_filter(settings.colour_filter);
+                        let tooltip = context.tooltips.visible().map(|(_, text)| text);
+                        let extra = tooltip
+                            .into_iter()
+                            .map(|text| ("Tooltip", text))
+                            .collect::<Vec<_>>();
+                        context.screen_reader.update(render.image(), &extra);
+                        if let Some(diagnostics) = &mut context.diagnostics {
+                            diagnostics.record_frame(render.image());
+                        }
+                    }
+                }
+                #[cfg(feature = "net")]
+                if let Some(broadcaster) = &mut context.broadcaster {
+                    broadcaster.broadcast(render.image());
+                }
+                render.set_caret(context.caret.visible().filter(|_| crash.is_none()));
+                render.set_effects(crash.is_none().then_some(context.effects_params));
+                context.profiler.record(Phase::Present, start);
+
+                let start = Instant::now();
+                render.upload();
+                context.profiler.record(Phase::Upload, start);
+                let start = Instant::now();
+                let result = render.draw();
+                context.profiler.record(Phase::Gpu, start);
+                context.profiler.end_frame();
+                if let Err(e) = result {
+                    match block_on(render.recover(&window, e)) {
+                        Ok(()) => context.adapter_info = Some(render.adapter_info().clone()),
+                        Err(e) => {
+                            log::error!("Unable to render: {}", e);
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                }
+            }
+            Event::RedrawRequested(window_id) if crash.is_none() => {
+                let result = crash::guard(crash_log.as_deref(), || {
+                    windows.redraw(window_id, game.as_ref())
+                });
+                if let Err(report) = result {
+                    crash = Some(report);
+                }
+            }
+
+            //
+            // Shutting down
+            //
+            Event::LoopDestroyed => {
+                // A game that has crashed isn't saved over its last autosave
+                if let Some(autosave) = &mut context.autosave {
+                    if autosave.saves_on_quit() && crash.is_none() {
+                        if let Ok(snapshot) = crash::guard(crash_log.as_deref(), || game.autosave())
+                        {
+                            autosave.submit(snapshot);
+                        }
+                    }
+                    autosave.finish();
+                }
+                #[cfg(feature = "window-persistence")]
+                if let (Some(name), Some(geometry)) = (
+                    geometry_name.as_deref(),
+                    fullscreen.windowed_geometry(&window),
+                ) {
+                    if let Err(e) = geometry.save(name) {
+                        log::warn!("Unable to save window geometry: {}", e);
+                    }
+                }
+                #[cfg(feature = "presence")]
+                context.presence.shut_down();
             }
 
             _ => {} // No more events
@@ -360,30 +1309,113 @@ pub async fn run_internal(rogue: RogueBuilder, mut game: Box<dyn Game>) -> Rogue
     });
 }
 
-fn simulate(game: &mut dyn Game, render: &RenderState, key_state: &KeyState) -> TickResult {
+fn simulate(
+    game: &mut dyn Game,
+    dt: Duration,
+    window: &Window,
+    render: &Renderer,
+    input: &InputState,
+    context: &mut Context,
+) -> TickResult {
     let (width, height) = render.chars_size();
+    let (cell_width, cell_height) = render.cell_size();
+    let (pixel_width, pixel_height) = render.pixel_size();
     let sim_input = SimInput {
-        dt: Duration::ZERO,
+        dt,
         width,
         height,
-        key: key_state,
-        mouse: None,
+        cell_width,
+        cell_height,
+        pixel_width,
+        pixel_height,
+        scale_factor: window.scale_factor(),
+        key: &input.key,
+        mouse: Some(input.mouse),
+        gestures: input.touches.gestures(),
+        text: &input.text,
+        ctx: context,
     };
 
+    sim_input.ctx.begin_tick();
     game.tick(sim_input)
 }
 
-fn present(game: &dyn Game, render: &mut RenderState) {
+pub(crate) fn present_input<'a>(window: &Window, render: &'a mut Renderer) -> PresentInput<'a> {
     let (width, height) = render.chars_size();
-    let (fore_image, back_image, text_image) = render.images();
+    let (cell_width, cell_height) = render.cell_size();
+    let (pixel_width, pixel_height) = render.pixel_size();
 
-    let present_input = PresentInput {
+    PresentInput {
         width,
         height,
-        fore_image,
-        back_image,
-        text_image,
-    };
+        cell_width,
+        cell_height,
+        pixel_width,
+        pixel_height,
+        scale_factor: window.scale_factor(),
+        image: render.image(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let pixels = RgbaImage::from_fn(width, height, |x, y| {
+            image::Rgba([x as u8, y as u8, 0, 255])
+        });
+        let mut data = Vec::new();
+        DynamicImage::ImageRgba8(pixels)
+            .write_to(&mut data, ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    fn is_bad(result: RogueResult<RogueFontData>) -> bool {
+        matches!(result, Err(RogueError::BadFont))
+    }
 
-    game.present(present_input);
+    #[test]
+    fn fonts_are_cropped_to_whole_glyphs() {
+        let font = load_font_image(&png(33, 17), ImageFormat::Png).unwrap();
+        assert_eq!((font.width, font.height), (2, 1));
+        assert_eq!(font.data.len(), 32 * 16);
+        // The last pixel of the first row, before the extra column
+        assert_eq!(font.data[31], u32::from_le_bytes([31, 0, 0, 255]));
+    }
+
+    #[test]
+    fn fonts_smaller_than_the_grid_are_rejected() {
+        assert!(is_bad(load_font_image(&png(15, 32), ImageFormat::Png)));
+        assert!(is_bad(load_font_image(&png(32, 15), ImageFormat::Png)));
+        assert!(is_bad(load_font_image(&png(1, 1), ImageFormat::Png)));
+    }
+
+    #[test]
+    fn unaligned_data_loads() {
+        let data = png(16, 16);
+        let mut shifted = vec![0];
+        shifted.extend_from_slice(&data);
+        let font = load_font_image(&shifted[1..], ImageFormat::Png).unwrap();
+        assert_eq!(font.data.len(), 256);
+    }
+
+    #[test]
+    fn truncated_and_garbage_data_is_rejected() {
+        let data = include_bytes!("font1.png");
+        for len in (0..data.len()).step_by(97) {
+            assert!(
+                is_bad(load_font_image(&data[..len], ImageFormat::Png)),
+                "{} bytes",
+                len
+            );
+        }
+        assert!(is_bad(load_font_image(b"not an image", ImageFormat::Png)));
+        assert!(is_bad(load_font_image(&png(32, 32), ImageFormat::Bmp)));
+        let mut garbage = data.to_vec();
+        garbage[100..200].iter_mut().for_each(|b| *b = 0xaa);
+        assert!(is_bad(load_font_image(&garbage, ImageFormat::Png)));
+    }
 }
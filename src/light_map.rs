@@ -0,0 +1,207 @@
+//
+// Light map
+//
+// The light falling on each cell of the main window, multiplied into the ink
+// and paper by the renderer's shader, so a lighting system can hand over its
+// output without rewriting every cell's colours each frame.  Turn it on with
+// Context::light_map().set_enabled(true) and set the light during tick():
+//
+//      ctx.light_map().fill(new_colour(40, 40, 60));
+//      ctx.light_map().set_intensity(player, 1.0);
+//
+// Light is a colour, so white leaves a cell as drawn and coloured light tints
+// it.  The map is resized to the grid before each tick, filling new cells
+// with white.  The engine's own overlays, such as tooltips and
+// notifications, are always drawn unlit.
+//
+
+use crate::{new_colour, tint_colour, Image, Point};
+
+const WHITE: u32 = 0xffff_ffff;
+
+pub struct LightMap {
+    enabled: bool,
+    width: u32,
+    height: u32,
+    cells: Vec<u32>,
+}
+
+impl LightMap {
+    pub fn new() -> Self {
+        LightMap {
+            enabled: false,
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn fill(&mut self, light: u32) {
+        self.cells.iter_mut().for_each(|cell| *cell = light);
+    }
+
+    pub fn get(&self, p: Point) -> Option<u32> {
+        self.index(p).map(|i| self.cells[i])
+    }
+
+    pub fn set(&mut self, p: Point, light: u32) {
+        if let Some(i) = self.index(p) {
+            self.cells[i] = light;
+        }
+    }
+
+    // White light, from 0 for dark to 1 for full
+    pub fn set_intensity(&mut self, p: Point, intensity: f32) {
+        let level = (intensity.clamp(0.0, 1.0) * 255.0).round() as u8;
+        self.set(p, new_colour(level, level, level));
+    }
+
+    fn index(&self, p: Point) -> Option<usize> {
+        if p.x >= 0 && p.y >= 0 && (p.x as u32) < self.width && (p.y as u32) < self.height {
+            Some((p.y as u32 * self.width + p.x as u32) as usize)
+        } else {
+            None
+        }
+    }
+
+    // Called by the engine before each tick.  Light is kept for the cells
+    // still in the grid.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        if (width, height) == (self.width, self.height) {
+            return;
+        }
+        let mut cells = vec![WHITE; (width * height) as usize];
+        for y in 0..height.min(self.height) {
+            for x in 0..width.min(self.width) {
+                cells[(y * width + x) as usize] = self.cells[(y * self.width + x) as usize];
+            }
+        }
+        self.width = width;
+        self.height = height;
+        self.cells = cells;
+    }
+
+    // The light to upload for a frame, with the cells the engine drew over
+    // since world was copied from the image left white.  None if the map
+    // doesn't match the image.
+    pub(crate) fn frame_light(&self, world: &Image, image: &Image) -> Option<Vec<u32>> {
+        if (image.width, image.height) != (self.width, self.height)
+            || (world.width, world.height) != (self.width, self.height)
+        {
+            return None;
+        }
+        let light = (0..self.cells.len())
+            .map(|i| {
+                let drawn_over = world.text_image[i] != image.text_image[i]
+                    || world.fore_image[i] != image.fore_image[i]
+                    || world.back_image[i] != image.back_image[i];
+                if drawn_over {
+                    WHITE
+                } else {
+                    self.cells[i]
+                }
+            })
+            .collect();
+        Some(light)
+    }
+
+    // Lights the image on the CPU, for output without the renderer, such as
+    // the telnet server.
+    pub fn apply(&self, image: &mut Image) {
+        if !self.enabled || (image.width, image.height) != (self.width, self.height) {
+            return;
+        }
+        for (i, &light) in self.cells.iter().enumerate() {
+            image.fore_image[i] = tint_colour(image.fore_image[i], light);
+            image.back_image[i] = tint_colour(image.back_image[i], light);
+        }
+    }
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_is_kept_when_the_grid_resizes() {
+        let mut light = LightMap::new();
+        light.resize(3, 2);
+        assert_eq!(light.get(Point::new(2, 1)), Some(WHITE));
+        light.fill(7);
+        light.set(Point::new(1, 1), 9);
+        light.set(Point::new(3, 0), 9);
+        assert_eq!(light.get(Point::new(-1, 0)), None);
+
+        light.resize(2, 3);
+        assert_eq!((light.width(), light.height()), (2, 3));
+        let cells = (0..3)
+            .flat_map(|y| (0..2).map(move |x| Point::new(x, y)))
+            .map(|p| light.get(p).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(cells, [7, 7, 7, 9, WHITE, WHITE]);
+    }
+
+    #[test]
+    fn cells_drawn_over_are_left_unlit() {
+        let mut light = LightMap::new();
+        light.resize(2, 1);
+        light.set_intensity(Point::new(0, 0), 0.5);
+        light.set_intensity(Point::new(1, 0), 2.0);
+        assert_eq!(light.get(Point::new(0, 0)), Some(new_colour(128, 128, 128)));
+        assert_eq!(light.get(Point::new(1, 0)), Some(new_colour(255, 255, 255)));
+
+        let mut world = Image::new(2, 1);
+        world.clear(0, 0);
+        let mut image = Image::new(2, 1);
+        image.clear(0, 0);
+        assert_eq!(
+            light.frame_light(&world, &image),
+            Some(vec![new_colour(128, 128, 128), new_colour(255, 255, 255)])
+        );
+        image.back_image[0] = 1;
+        assert_eq!(
+            light.frame_light(&world, &image),
+            Some(vec![WHITE, new_colour(255, 255, 255)])
+        );
+        assert_eq!(light.frame_light(&world, &Image::new(3, 1)), None);
+    }
+
+    #[test]
+    fn applying_tints_ink_and_paper() {
+        let mut light = LightMap::new();
+        light.resize(1, 1);
+        light.set_intensity(Point::new(0, 0), 0.5);
+        let mut image = Image::new(1, 1);
+        image.clear(new_colour(255, 0, 100), new_colour(0, 255, 0));
+
+        light.apply(&mut image);
+        assert_eq!(image.fore_image, [new_colour(255, 0, 100)]);
+
+        light.set_enabled(true);
+        light.apply(&mut image);
+        assert_eq!(image.fore_image, [new_colour(128, 0, 50)]);
+        assert_eq!(image.back_image, [new_colour(0, 128, 0)]);
+    }
+}
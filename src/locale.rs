@@ -0,0 +1,295 @@
+//
+// Localisation
+//
+// String tables hold the game's text for one language, loaded through the
+// asset manager from "locale/<language>.lang".  Each line maps a key to its
+// text, and blank lines and lines starting with '#' are ignored:
+//
+//      # English
+//      greeting = Welcome to the dungeon, {name}!
+//      kills.one = You killed {count} monster.
+//      kills.other = You killed {count} monsters.
+//
+// Arguments in braces are replaced when the text is looked up with t!().
+// Plural forms are chosen by the language's plural rule from the count
+// argument, and a missing key falls back to a second language (usually the
+// one the game was written in), then to the key itself.
+//
+//      t!(strings, "greeting", name = player.name)
+//      t!(strings, "kills", count = kills)
+//
+
+use crate::{Assets, RogueError, RogueResult};
+use std::collections::HashMap;
+
+//
+// Plural rules
+// These map a count to the CLDR plural category used as the key suffix.
+//
+
+pub type PluralRule = fn(u64) -> &'static str;
+
+// English, German, Spanish, Italian...
+pub fn plural_one_other(n: u64) -> &'static str {
+    if n == 1 {
+        "one"
+    } else {
+        "other"
+    }
+}
+
+// French, Portuguese (Brazil): zero counts as singular
+pub fn plural_zero_one_other(n: u64) -> &'static str {
+    if n <= 1 {
+        "one"
+    } else {
+        "other"
+    }
+}
+
+// Russian, Ukrainian, Polish-like rules
+pub fn plural_slavic(n: u64) -> &'static str {
+    match (n % 10, n % 100) {
+        (1, r) if r != 11 => "one",
+        (2..=4, r) if !(12..=14).contains(&r) => "few",
+        _ => "many",
+    }
+}
+
+// Chinese, Japanese, Korean: no plural forms
+pub fn plural_none(_n: u64) -> &'static str {
+    "other"
+}
+
+fn default_plural_rule(language: &str) -> PluralRule {
+    match language.split(['-', '_']).next().unwrap_or("") {
+        "fr" | "pt" => plural_zero_one_other,
+        "ru" | "uk" | "pl" | "be" => plural_slavic,
+        "zh" | "ja" | "ko" | "th" | "vi" => plural_none,
+        _ => plural_one_other,
+    }
+}
+
+//
+// String tables
+//
+
+struct StringTable {
+    language: String,
+    plural_rule: PluralRule,
+    strings: HashMap<String, String>,
+}
+
+impl StringTable {
+    fn parse(language: &str, name: &str, text: &str) -> RogueResult<Self> {
+        let mut strings = HashMap::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| RogueError::BadContent {
+                file: String::from(name),
+                message: format!("line {} has no '='", line_number + 1),
+            })?;
+            strings.insert(String::from(key.trim()), value.trim().replace("\\n", "\n"));
+        }
+        Ok(StringTable {
+            language: String::from(language),
+            plural_rule: default_plural_rule(language),
+            strings,
+        })
+    }
+
+    fn get(&self, key: &str, count: Option<u64>) -> Option<&str> {
+        let plural = count.and_then(|count| {
+            self.strings
+                .get(&format!("{}.{}", key, (self.plural_rule)(count)))
+                .or_else(|| self.strings.get(&format!("{}.other", key)))
+        });
+        plural.or_else(|| self.strings.get(key)).map(|s| s.as_str())
+    }
+}
+
+pub struct Strings {
+    current: Option<StringTable>,
+    fallback: Option<StringTable>,
+}
+
+impl Strings {
+    pub fn new() -> Self {
+        Strings {
+            current: None,
+            fallback: None,
+        }
+    }
+
+    fn load_table(assets: &Assets, language: &str) -> RogueResult<StringTable> {
+        let name = format!("locale/{}.lang", language);
+        let bytes = assets.read(&name)?;
+        let text = String::from_utf8(bytes).map_err(|e| RogueError::BadContent {
+            file: name.clone(),
+            message: e.to_string(),
+        })?;
+        StringTable::parse(language, &name, &text)
+    }
+
+    // Switches to a language, e.g. "en" or "pt-BR".
+    pub fn load(&mut self, assets: &Assets, language: &str) -> RogueResult<()> {
+        self.current = Some(Self::load_table(assets, language)?);
+        Ok(())
+    }
+
+    // Sets the language used for keys missing from the current one.
+    pub fn load_fallback(&mut self, assets: &Assets, language: &str) -> RogueResult<()> {
+        self.fallback = Some(Self::load_table(assets, language)?);
+        Ok(())
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.current.as_ref().map(|table| table.language.as_str())
+    }
+
+    // Replaces the plural rule chosen from the language code.
+    pub fn set_plural_rule(&mut self, rule: PluralRule) {
+        if let Some(table) = self.current.as_mut() {
+            table.plural_rule = rule;
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.current
+            .iter()
+            .chain(self.fallback.iter())
+            .any(|table| table.strings.contains_key(key))
+    }
+
+    // The raw text for a key, before arguments are replaced.
+    pub fn get<'a>(&'a self, key: &'a str, count: Option<u64>) -> &'a str {
+        self.current
+            .iter()
+            .chain(self.fallback.iter())
+            .find_map(|table| table.get(key, count))
+            .unwrap_or(key)
+    }
+
+    // Looks up the text and replaces the {name} arguments.  A count argument
+    // chooses the plural form.  Usually called through t!().
+    pub fn format(&self, key: &str, count: Option<u64>, args: &[(&str, String)]) -> String {
+        let mut text = String::from(self.get(key, count));
+        if let Some(count) = count {
+            text = text.replace("{count}", &count.to_string());
+        }
+        for (name, value) in args {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+}
+
+impl Default for Strings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[macro_export]
+macro_rules! t {
+    ($strings:expr, $key:expr, count = $count:expr $(, $name:ident = $value:expr)* $(,)?) => {
+        $strings.format(
+            $key,
+            Some($count as u64),
+            &[$((stringify!($name), $value.to_string())),*],
+        )
+    };
+    ($strings:expr, $key:expr $(, $name:ident = $value:expr)* $(,)?) => {
+        $strings.format($key, None, &[$((stringify!($name), $value.to_string())),*])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn assets() -> Assets {
+        let mut assets = Assets::new(Path::new("no such directory"));
+        assets
+            .embed(
+                "locale/en.lang",
+                b"# English\n\
+                  greeting = Hello, {name}!\n\
+                  \n\
+                  kills.one = You killed {count} monster.\n\
+                  kills.other = You killed {count} monsters.\n\
+                  only.en = English\n",
+            )
+            .embed(
+                "locale/ru.lang",
+                "kills.one = {count} \u{43c}\u{43e}\u{43d}\u{441}\u{442}\u{440}\n\
+                 kills.few = {count} \u{43c}\u{43e}\u{43d}\u{441}\u{442}\u{440}\u{430}\n\
+                 kills.many = {count} \u{43c}\u{43e}\u{43d}\u{441}\u{442}\u{440}\u{43e}\u{432}\n\
+                 lines = one\\ntwo\n"
+                    .as_bytes(),
+            )
+            .embed("locale/bad.lang", b"no equals sign\n");
+        assets
+    }
+
+    #[test]
+    fn plural_rules_choose_categories() {
+        let slavic = [1, 2, 5, 11, 12, 21, 22, 25, 111]
+            .map(plural_slavic)
+            .to_vec();
+        assert_eq!(
+            slavic,
+            ["one", "few", "many", "many", "many", "one", "few", "many", "many"]
+        );
+        assert_eq!(plural_one_other(0), "other");
+        assert_eq!(plural_zero_one_other(0), "one");
+        assert_eq!(default_plural_rule("pt-BR")(0), "one");
+        assert_eq!(default_plural_rule("ja")(1), "other");
+    }
+
+    #[test]
+    fn text_is_formatted_with_arguments_and_plurals() {
+        let mut strings = Strings::new();
+        strings.load(&assets(), "en").unwrap();
+        assert_eq!(strings.language(), Some("en"));
+        assert_eq!(t!(strings, "greeting", name = "Al"), "Hello, Al!");
+        assert_eq!(t!(strings, "kills", count = 1), "You killed 1 monster.");
+        assert_eq!(t!(strings, "kills", count = 3), "You killed 3 monsters.");
+    }
+
+    #[test]
+    fn missing_keys_fall_back() {
+        let assets = assets();
+        let mut strings = Strings::new();
+        strings.load(&assets, "ru").unwrap();
+        strings.load_fallback(&assets, "en").unwrap();
+        assert_eq!(
+            t!(strings, "kills", count = 22),
+            "22 \u{43c}\u{43e}\u{43d}\u{441}\u{442}\u{440}\u{430}"
+        );
+        assert_eq!(t!(strings, "greeting", name = "Al"), "Hello, Al!");
+        assert_eq!(strings.get("lines", None), "one\ntwo");
+        assert_eq!(strings.get("missing", None), "missing");
+        assert!(strings.contains("only.en"));
+
+        // Without a few form, the rule falls back to other, then the key
+        strings.load(&assets, "en").unwrap();
+        strings.set_plural_rule(|_| "few");
+        assert_eq!(t!(strings, "kills", count = 2), "You killed 2 monsters.");
+    }
+
+    #[test]
+    fn bad_tables_are_rejected() {
+        let mut strings = Strings::new();
+        assert!(matches!(
+            strings.load(&assets(), "bad"),
+            Err(RogueError::BadContent { .. })
+        ));
+        assert!(strings.load(&assets(), "fr").is_err());
+        assert_eq!(strings.language(), None);
+    }
+}
@@ -0,0 +1,135 @@
+//
+// Logging
+//
+// The engine reports problems through the log crate, so games can send them
+// wherever they like by installing any logger.  For games without one,
+// init_logging() installs a simple logger that writes to stderr and also
+// keeps warnings and errors for the game to show on screen, usually by
+// calling MessageLog::mirror_log() every tick.
+//
+// It also keeps the last few hundred messages of every level for diagnostics
+// reports.  See diagnostics.rs.
+//
+
+use crate::{RogueError, RogueResult};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{collections::VecDeque, sync::Mutex};
+
+// Messages kept for the screen are dropped, oldest first, beyond this
+const MAX_PENDING: usize = 100;
+const MAX_RECENT: usize = 500;
+
+struct ScreenLogger {
+    pending: Mutex<Vec<(Level, String)>>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+static LOGGER: ScreenLogger = ScreenLogger {
+    pending: Mutex::new(Vec::new()),
+    recent: Mutex::new(VecDeque::new()),
+};
+
+impl Log for ScreenLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "[{}] {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        eprintln!("{}", line);
+        if let Ok(mut recent) = self.recent.lock() {
+            if recent.len() == MAX_RECENT {
+                recent.pop_front();
+            }
+            recent.push_back(line);
+        }
+        if record.level() <= Level::Warn {
+            if let Ok(mut pending) = self.pending.lock() {
+                if pending.len() == MAX_PENDING {
+                    pending.remove(0);
+                }
+                pending.push((record.level(), record.args().to_string()));
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// Installs the engine's logger, showing messages up to the given level.
+// Fails if a logger has already been installed.
+pub fn init_logging(level: LevelFilter) -> RogueResult<()> {
+    log::set_logger(&LOGGER).map_err(|_| RogueError::LoggerInstalled)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+// Takes the warnings and errors logged since the last call.
+pub fn take_log_messages() -> Vec<(Level, String)> {
+    LOGGER
+        .pending
+        .lock()
+        .map(|mut pending| std::mem::take(&mut *pending))
+        .unwrap_or_default()
+}
+
+// The last messages logged, oldest first, without removing them.  Empty if the
+// game installed its own logger.
+pub fn recent_log() -> Vec<String> {
+    LOGGER
+        .recent
+        .lock()
+        .map(|recent| recent.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageLog;
+
+    // The logger is global, so this is the only test that installs it.  Other
+    // tests may log at the same time, so only our own messages are checked.
+    #[test]
+    fn warnings_are_kept_for_the_screen() {
+        init_logging(LevelFilter::Info).unwrap();
+        assert!(init_logging(LevelFilter::Info).is_err());
+
+        log::info!(target: "mage-test", "loading level 3");
+        log::debug!(target: "mage-test", "too quiet to see");
+        log::warn!(target: "mage-test", "missing tile 'lava'");
+        log::error!(target: "mage-test", "save failed");
+
+        let recent = recent_log()
+            .into_iter()
+            .filter(|line| line.contains("mage-test"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recent,
+            [
+                "[INFO] mage-test: loading level 3",
+                "[WARN] mage-test: missing tile 'lava'",
+                "[ERROR] mage-test: save failed",
+            ]
+        );
+
+        let mut messages = MessageLog::new(10);
+        messages.mirror_log();
+        let mirrored = messages
+            .messages()
+            .filter(|text| *text == "missing tile 'lava'" || *text == "save failed")
+            .count();
+        assert_eq!(mirrored, 2);
+        assert!(!take_log_messages()
+            .iter()
+            .any(|(_, text)| text == "save failed"));
+    }
+}
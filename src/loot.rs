@@ -0,0 +1,394 @@
+//
+// Loot tables
+//
+// Decide what a monster drops or a chest holds.  A table always gives its
+// guaranteed entries, then makes a number of weighted rolls over its drops.
+// A drop can be an item, another table (for shared sub-tables such as "gems")
+// or nothing.  Drops can be limited to a range of dungeon depths and can
+// become more or less likely the deeper the player goes.
+//
+// With the content feature, tables are loaded from loot.ron by the content
+// registry:
+//
+//      {
+//          "goblin": (
+//              rolls: (1, 2),
+//              guaranteed: [Item(id: "gold", count: (1, 10))],
+//              drops: [
+//                  (entry: Item(id: "dagger")),
+//                  (entry: Table("gems"), rarity: Rare, min_depth: 5, per_depth: 2),
+//                  (entry: Nothing, weight: Some(50)),
+//              ],
+//          ),
+//      }
+//
+
+use crate::GameRng;
+use std::collections::HashMap;
+
+#[cfg(feature = "content")]
+use crate::{Content, ContentRegistry};
+#[cfg(feature = "content")]
+use serde::Deserialize;
+
+// Nested tables deeper than this are ignored, in case a table includes itself
+const MAX_NESTING: u32 = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "content", derive(Deserialize))]
+pub enum Rarity {
+    #[default]
+    Common,
+    Uncommon,
+    Rare,
+    Epic,
+    Legendary,
+}
+
+impl Rarity {
+    // The weight used for drops that don't give their own
+    pub fn weight(self) -> u32 {
+        match self {
+            Rarity::Common => 100,
+            Rarity::Uncommon => 40,
+            Rarity::Rare => 15,
+            Rarity::Epic => 5,
+            Rarity::Legendary => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "content", derive(Deserialize))]
+pub enum LootEntry {
+    // Between count.0 and count.1 (inclusive) of an item
+    Item {
+        id: String,
+        #[cfg_attr(feature = "content", serde(default = "single"))]
+        count: (u32, u32),
+    },
+    // A roll on another table
+    Table(String),
+    Nothing,
+}
+
+#[cfg(feature = "content")]
+fn single() -> (u32, u32) {
+    (1, 1)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "content", derive(Deserialize))]
+pub struct LootDrop {
+    pub entry: LootEntry,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub rarity: Rarity,
+    // Overrides the rarity's weight
+    #[cfg_attr(feature = "content", serde(default))]
+    pub weight: Option<u32>,
+    // Added to the weight for every level deeper than min_depth
+    #[cfg_attr(feature = "content", serde(default))]
+    pub per_depth: i32,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub min_depth: u32,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub max_depth: Option<u32>,
+}
+
+impl LootDrop {
+    pub fn new(entry: LootEntry, rarity: Rarity) -> Self {
+        LootDrop {
+            entry,
+            rarity,
+            weight: None,
+            per_depth: 0,
+            min_depth: 0,
+            max_depth: None,
+        }
+    }
+
+    // The drop's weight at a depth, zero if it can't drop there
+    pub fn weight_at(&self, depth: u32) -> u32 {
+        if depth < self.min_depth || self.max_depth.is_some_and(|max| depth > max) {
+            return 0;
+        }
+        let base = self.weight.unwrap_or_else(|| self.rarity.weight()) as i64;
+        let levels = (depth - self.min_depth) as i64;
+        (base + self.per_depth as i64 * levels).clamp(0, u32::MAX as i64) as u32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "content", derive(Deserialize))]
+pub struct LootTable {
+    // Between rolls.0 and rolls.1 (inclusive) weighted picks from the drops
+    #[cfg_attr(feature = "content", serde(default = "single"))]
+    pub rolls: (u32, u32),
+    #[cfg_attr(feature = "content", serde(default))]
+    pub guaranteed: Vec<LootEntry>,
+    #[cfg_attr(feature = "content", serde(default))]
+    pub drops: Vec<LootDrop>,
+}
+
+impl LootTable {
+    pub fn new() -> Self {
+        LootTable {
+            rolls: (1, 1),
+            guaranteed: Vec::new(),
+            drops: Vec::new(),
+        }
+    }
+
+    pub fn with_rolls(&mut self, min: u32, max: u32) -> &mut Self {
+        self.rolls = (min, max.max(min));
+        self
+    }
+
+    pub fn with_guaranteed(&mut self, entry: LootEntry) -> &mut Self {
+        self.guaranteed.push(entry);
+        self
+    }
+
+    pub fn with_drop(&mut self, drop: LootDrop) -> &mut Self {
+        self.drops.push(drop);
+        self
+    }
+}
+
+impl Default for LootTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "content")]
+impl Content for LootTable {
+    const KIND: &'static str = "loot";
+
+    fn references(&self) -> Vec<(&'static str, String)> {
+        self.guaranteed
+            .iter()
+            .chain(self.drops.iter().map(|drop| &drop.entry))
+            .filter_map(|entry| match entry {
+                LootEntry::Table(id) => Some((Self::KIND, id.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// One item that dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loot {
+    pub id: String,
+    pub count: u32,
+    // Guaranteed items are Common
+    pub rarity: Rarity,
+}
+
+//
+// Rolling
+//
+
+pub struct LootTables {
+    tables: HashMap<String, LootTable>,
+}
+
+impl LootTables {
+    pub fn new() -> Self {
+        LootTables {
+            tables: HashMap::new(),
+        }
+    }
+
+    // Takes the tables loaded by the content registry.
+    #[cfg(feature = "content")]
+    pub fn from_registry(registry: &ContentRegistry) -> Self {
+        LootTables {
+            tables: registry
+                .all::<LootTable>()
+                .map(|(id, table)| (String::from(id), table.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn insert(&mut self, id: &str, table: LootTable) {
+        self.tables.insert(String::from(id), table);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&LootTable> {
+        self.tables.get(id)
+    }
+
+    // Rolls on a table for a depth.  Items that drop more than once are
+    // merged.  Use a stream of the game's RNG (e.g. rng.stream("loot")) to
+    // keep drops reproducible.
+    pub fn roll(&self, id: &str, depth: u32, rng: &mut GameRng) -> Vec<Loot> {
+        let mut loot = Vec::new();
+        self.roll_table(id, depth, rng, Rarity::Common, 0, &mut loot);
+        loot
+    }
+
+    fn roll_table(
+        &self,
+        id: &str,
+        depth: u32,
+        rng: &mut GameRng,
+        rarity: Rarity,
+        nesting: u32,
+        loot: &mut Vec<Loot>,
+    ) {
+        let table = match self.tables.get(id) {
+            Some(table) if nesting < MAX_NESTING => table,
+            _ => return,
+        };
+        for entry in &table.guaranteed {
+            self.add_entry(entry, depth, rng, rarity, nesting, loot);
+        }
+        let weights = table
+            .drops
+            .iter()
+            .map(|drop| drop.weight_at(depth))
+            .collect::<Vec<_>>();
+        let rolls = rng.range(table.rolls.0 as i32, table.rolls.1 as i32 + 1);
+        for _ in 0..rolls {
+            if let Some(i) = rng.weighted(&weights) {
+                let drop = &table.drops[i];
+                // Items from a nested table are at least as rare as the drop
+                let rarity = rarity.max(drop.rarity);
+                self.add_entry(&drop.entry, depth, rng, rarity, nesting, loot);
+            }
+        }
+    }
+
+    fn add_entry(
+        &self,
+        entry: &LootEntry,
+        depth: u32,
+        rng: &mut GameRng,
+        rarity: Rarity,
+        nesting: u32,
+        loot: &mut Vec<Loot>,
+    ) {
+        match entry {
+            LootEntry::Item { id, count } => {
+                let count = rng.range(count.0 as i32, count.1 as i32 + 1) as u32;
+                if count == 0 {
+                    return;
+                }
+                match loot.iter_mut().find(|l| l.id == *id && l.rarity == rarity) {
+                    Some(existing) => existing.count += count,
+                    None => loot.push(Loot {
+                        id: id.clone(),
+                        count,
+                        rarity,
+                    }),
+                }
+            }
+            LootEntry::Table(table) => {
+                self.roll_table(table, depth, rng, rarity, nesting + 1, loot)
+            }
+            LootEntry::Nothing => {}
+        }
+    }
+}
+
+impl Default for LootTables {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, min: u32, max: u32) -> LootEntry {
+        LootEntry::Item {
+            id: String::from(id),
+            count: (min, max),
+        }
+    }
+
+    #[test]
+    fn weights_change_with_depth() {
+        let mut drop = LootDrop::new(LootEntry::Nothing, Rarity::Rare);
+        drop.min_depth = 2;
+        drop.max_depth = Some(5);
+        drop.per_depth = -10;
+        assert_eq!(drop.weight_at(1), 0);
+        assert_eq!(drop.weight_at(2), 15);
+        assert_eq!(drop.weight_at(3), 5);
+        assert_eq!(drop.weight_at(4), 0);
+        assert_eq!(drop.weight_at(6), 0);
+        drop.weight = Some(7);
+        drop.per_depth = 1;
+        assert_eq!(drop.weight_at(5), 10);
+    }
+
+    #[test]
+    fn guaranteed_items_drop_and_repeats_merge() {
+        let mut tables = LootTables::new();
+        let mut table = LootTable::new();
+        table
+            .with_rolls(3, 3)
+            .with_guaranteed(item("gold", 5, 5))
+            .with_drop(LootDrop::new(item("gold", 1, 1), Rarity::Common))
+            .with_drop(LootDrop::new(item("empty", 0, 0), Rarity::Common));
+        // Only gold can drop at this depth
+        table.drops[1].min_depth = 10;
+        tables.insert("chest", table);
+
+        let loot = tables.roll("chest", 1, &mut GameRng::new(0));
+        assert_eq!(
+            loot,
+            [Loot {
+                id: String::from("gold"),
+                count: 8,
+                rarity: Rarity::Common
+            }]
+        );
+        assert!(tables.roll("missing", 1, &mut GameRng::new(0)).is_empty());
+    }
+
+    #[test]
+    fn nested_tables_pass_on_their_rarity() {
+        let mut tables = LootTables::new();
+        let mut gems = LootTable::new();
+        gems.with_drop(LootDrop::new(item("ruby", 1, 1), Rarity::Common));
+        let mut boss = LootTable::new();
+        boss.with_drop(LootDrop::new(
+            LootEntry::Table(String::from("gems")),
+            Rarity::Epic,
+        ));
+        // A table that includes itself stops after a few levels
+        let mut looping = LootTable::new();
+        looping
+            .with_guaranteed(item("coin", 1, 1))
+            .with_guaranteed(LootEntry::Table(String::from("loop")));
+        tables.insert("gems", gems);
+        tables.insert("boss", boss);
+        tables.insert("loop", looping);
+
+        let mut rng = GameRng::new(4);
+        assert_eq!(tables.roll("boss", 0, &mut rng)[0].rarity, Rarity::Epic);
+        assert_eq!(tables.roll("gems", 0, &mut rng)[0].rarity, Rarity::Common);
+        assert_eq!(tables.roll("loop", 0, &mut rng)[0].count, MAX_NESTING);
+    }
+
+    #[cfg(feature = "content")]
+    #[test]
+    fn tables_are_read_with_defaults() {
+        let table: LootTable = ron::from_str(
+            "(drops: [(entry: Item(id: \"dagger\")), (entry: Table(\"gems\"), rarity: Rare)])",
+        )
+        .unwrap();
+        assert_eq!(table.rolls, (1, 1));
+        assert_eq!(table.drops[0].entry, item("dagger", 1, 1));
+        assert_eq!(table.drops[1].weight_at(0), 15);
+        assert_eq!(
+            table.references(),
+            [(LootTable::KIND, String::from("gems"))]
+        );
+    }
+}
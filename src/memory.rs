@@ -0,0 +1,115 @@
+//
+// Memory usage
+//
+// How much memory the engine's resources use, for budgeting on targets with
+// little of it, such as the web.  Context::memory_usage() reports:
+//
+//      grids           the cells of every window, on the CPU and in the GPU
+//                      textures they are copied to
+//      glyph_atlases   the font textures, with the fallback glyphs drawn so
+//                      far, and the font data kept to rebuild them
+//      assets          the assets loaded through Context::assets()
+//      game            whatever the game reports with Context::report_memory(),
+//                      e.g. its canvases or audio buffers
+//
+// Sizes are estimates: they count the buffers themselves, not the allocator's
+// or the graphics driver's overhead.  Image, Grid and PixelCanvas have a
+// memory_size() the game can use for its own reports.  The debug overlay
+// shows the same numbers.
+//
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub grids: usize,
+    pub glyph_atlases: usize,
+    pub assets: usize,
+    // (name, bytes) in the order they were first reported
+    pub game: Vec<(String, usize)>,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.grids
+            + self.glyph_atlases
+            + self.assets
+            + self.game.iter().map(|(_, bytes)| bytes).sum::<usize>()
+    }
+
+    // Each part with its size, e.g. ("Grids", "1.2 MB"), ending with the total
+    pub fn lines(&self) -> Vec<(String, String)> {
+        let engine = [
+            ("Grids", self.grids),
+            ("Glyph atlases", self.glyph_atlases),
+            ("Assets", self.assets),
+        ];
+        engine
+            .iter()
+            .map(|&(name, bytes)| (String::from(name), bytes))
+            .chain(self.game.iter().cloned())
+            .chain(std::iter::once((String::from("Total"), self.total())))
+            .map(|(name, bytes)| (name, format_bytes(bytes)))
+            .collect()
+    }
+}
+
+// e.g. "512 B", "3.5 KB" or "12.0 MB"
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Grid};
+
+    #[test]
+    fn bytes_are_shown_in_the_largest_unit() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(3584), "3.5 KB");
+        assert_eq!(format_bytes(12 * 1024 * 1024), "12.0 MB");
+        assert_eq!(format_bytes(5 << 50), "5120.0 TB");
+    }
+
+    #[test]
+    fn the_game_reports_its_own_parts() {
+        let mut ctx = Context::new();
+        let grid = Grid::new(10, 10, 0u32);
+        assert_eq!(grid.memory_size(), std::mem::size_of::<Grid<u32>>() + 400);
+        ctx.report_memory("Map", grid.memory_size());
+        ctx.report_memory("Audio", 2048);
+        ctx.report_memory("Map", 100);
+
+        let mut usage = ctx.memory_usage();
+        assert_eq!(
+            usage.game,
+            [(String::from("Map"), 100), (String::from("Audio"), 2048)]
+        );
+        usage.grids = 1024;
+        assert_eq!(
+            usage.total(),
+            1024 + usage.glyph_atlases + usage.assets + 2148
+        );
+        let lines = usage.lines();
+        let names = lines
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Grids", "Glyph atlases", "Assets", "Map", "Audio", "Total"]
+        );
+        assert_eq!(lines[0].1, "1.0 KB");
+        assert_eq!(lines[3].1, "100 B");
+    }
+}
@@ -0,0 +1,293 @@
+//
+// Morgue files
+//
+// When a run ends, the game can write a character dump in the tradition of
+// NetHack and DCSS: a title, sections of text or name/value pairs supplied by
+// the game, and optionally the final map.  Dumps are written as plain text or
+// as HTML (which keeps the map's colours) to the user's documents folder.
+//
+
+use crate::{history, Image, RogueResult};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MorgueFormat {
+    Text,
+    Html,
+}
+
+impl MorgueFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            MorgueFormat::Text => "txt",
+            MorgueFormat::Html => "html",
+        }
+    }
+}
+
+enum Section {
+    Text(String, Vec<String>),
+    Fields(String, Vec<(String, String)>),
+}
+
+pub struct Morgue {
+    title: String,
+    sections: Vec<Section>,
+    map: Option<Image>,
+}
+
+impl Morgue {
+    pub fn new(title: &str) -> Self {
+        Morgue {
+            title: String::from(title),
+            sections: Vec::new(),
+            map: None,
+        }
+    }
+
+    pub fn with_text(&mut self, title: &str, text: &str) -> &mut Self {
+        let lines = text.lines().map(String::from).collect();
+        self.sections
+            .push(Section::Text(String::from(title), lines));
+        self
+    }
+
+    pub fn with_fields(&mut self, title: &str, fields: &[(&str, &str)]) -> &mut Self {
+        let fields = fields
+            .iter()
+            .map(|&(name, value)| (String::from(name), String::from(value)))
+            .collect();
+        self.sections
+            .push(Section::Fields(String::from(title), fields));
+        self
+    }
+
+    pub fn with_map(&mut self, map: &Image) -> &mut Self {
+        self.map = Some(map.clone());
+        self
+    }
+
+    //
+    // Rendering
+    //
+
+    fn field_lines(fields: &[(String, String)]) -> Vec<String> {
+        let width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        fields
+            .iter()
+            .map(|(name, value)| format!("{:width$} : {}", name, value, width = width))
+            .collect()
+    }
+
+    fn map_lines(map: &Image) -> Vec<String> {
+        (0..map.height as usize)
+            .map(|y| {
+                let row = y * map.width as usize..(y + 1) * map.width as usize;
+                map.text_image[row]
+                    .iter()
+                    .map(|&ch| map.glyph_char(ch))
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n{}\n", self.title, "=".repeat(self.title.len()));
+        for section in &self.sections {
+            let (title, lines) = match section {
+                Section::Text(title, lines) => (title, lines.clone()),
+                Section::Fields(title, fields) => (title, Self::field_lines(fields)),
+            };
+            text += &format!("\n{}\n{}\n", title, "-".repeat(title.len()));
+            lines.iter().for_each(|line| text += &format!("{}\n", line));
+        }
+        if let Some(map) = &self.map {
+            text += "\nMap\n---\n";
+            Self::map_lines(map)
+                .iter()
+                .for_each(|line| text += &format!("{}\n", line));
+        }
+        text
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n\
+             <body style=\"background: #000; color: #ccc; font-family: monospace\">\n<h1>{0}</h1>\n",
+            escape(&self.title)
+        );
+        for section in &self.sections {
+            let (title, lines) = match section {
+                Section::Text(title, lines) => (title, lines.clone()),
+                Section::Fields(title, fields) => (title, Self::field_lines(fields)),
+            };
+            html += &format!("<h2>{}</h2>\n<pre>\n", escape(title));
+            lines
+                .iter()
+                .for_each(|line| html += &format!("{}\n", escape(line)));
+            html += "</pre>\n";
+        }
+        if let Some(map) = &self.map {
+            html += "<h2>Map</h2>\n<pre>\n";
+            html += &map_html(map);
+            html += "</pre>\n";
+        }
+        html += "</body>\n</html>\n";
+        html
+    }
+
+    //
+    // Saving
+    //
+
+    pub fn render(&self, format: MorgueFormat) -> String {
+        match format {
+            MorgueFormat::Text => self.to_text(),
+            MorgueFormat::Html => self.to_html(),
+        }
+    }
+
+    // Writes the dump to the user's documents folder as
+    // "<game>/morgue-<name>-<date>-<time>.<ext>" and returns its path.
+    pub fn save(&self, game_name: &str, name: &str, format: MorgueFormat) -> RogueResult<PathBuf> {
+        let dir = dirs::document_dir()
+            .or_else(dirs::home_dir)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No documents directory"))?;
+        let timestamp = history::now();
+        let (year, month, day) = history::civil_from_days((timestamp / 86400) as i64);
+        let seconds = timestamp % 86400;
+        let name = name
+            .chars()
+            .map(|ch| {
+                if ch.is_alphanumeric() || ch == '-' {
+                    ch
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        let file_name = format!(
+            "morgue-{}-{:04}{:02}{:02}-{:02}{:02}{:02}.{}",
+            name,
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            format.extension()
+        );
+        let path = dir.join(game_name).join(file_name);
+        self.save_path(&path, format)?;
+        Ok(path)
+    }
+
+    pub fn save_path(&self, path: &Path, format: MorgueFormat) -> RogueResult<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.render(format))?;
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn css_colour(colour: u32) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        colour & 0xff,
+        (colour >> 8) & 0xff,
+        (colour >> 16) & 0xff
+    )
+}
+
+// Each run of cells with the same colours becomes a single span.
+fn map_html(map: &Image) -> String {
+    let mut html = String::new();
+    for y in 0..map.height as usize {
+        let mut x = 0;
+        let width = map.width as usize;
+        while x < width {
+            let i = y * width + x;
+            let colours = (map.fore_image[i], map.back_image[i]);
+            let mut text = String::new();
+            while x < width
+                && (map.fore_image[y * width + x], map.back_image[y * width + x]) == colours
+            {
+                let ch = map.glyph_char(map.text_image[y * width + x]);
+                text += &escape(&ch.to_string());
+                x += 1;
+            }
+            html += &format!(
+                "<span style=\"color: {}; background: {}\">{}</span>",
+                css_colour(colours.0),
+                css_colour(colours.1),
+                text
+            );
+        }
+        html += "\n";
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_colour, Point};
+
+    fn morgue() -> Morgue {
+        let mut map = Image::new(4, 2);
+        map.clear(new_colour(255, 255, 255), 0);
+        map.draw_string(Point::new(0, 0), "<@", new_colour(255, 0, 0), 0);
+        let mut morgue = Morgue::new("Al & Co");
+        morgue
+            .with_fields("Stats", &[("Str", "12"), ("Level", "3")])
+            .with_text("Notes", "Died\nAgain")
+            .with_map(&map);
+        morgue
+    }
+
+    #[test]
+    fn text_dumps_line_up_fields_and_trim_the_map() {
+        assert_eq!(
+            morgue().render(MorgueFormat::Text),
+            "Al & Co\n=======\n\
+             \nStats\n-----\nStr   : 12\nLevel : 3\n\
+             \nNotes\n-----\nDied\nAgain\n\
+             \nMap\n---\n<@\n\n"
+        );
+    }
+
+    #[test]
+    fn html_dumps_escape_text_and_colour_the_map() {
+        let html = morgue().render(MorgueFormat::Html);
+        assert!(html.contains("<h1>Al &amp; Co</h1>"));
+        assert!(html.contains("<pre>\nStr   : 12\nLevel : 3\n</pre>"));
+        assert!(html.contains(
+            "<span style=\"color: #ff0000; background: #000000\">&lt;@</span>\
+             <span style=\"color: #ffffff; background: #000000\">  </span>\n"
+        ));
+    }
+
+    #[test]
+    fn dumps_are_saved_in_either_format() {
+        let dir = std::env::temp_dir().join(format!("mage-morgue-{}", std::process::id()));
+        let path = dir.join("dumps").join("morgue.txt");
+        let result = morgue().save_path(&path, MorgueFormat::Text);
+        let text = fs::read_to_string(&path);
+        let _ = fs::remove_dir_all(&dir);
+        result.unwrap();
+        assert_eq!(text.unwrap(), morgue().to_text());
+        assert_eq!(MorgueFormat::Html.extension(), "html");
+    }
+}
@@ -0,0 +1,281 @@
+//
+// Name generators
+//
+// Two ways of making up names for characters, artefacts and places:
+//
+// MarkovNames learns which letters follow which from a list of example words
+// and produces new words in the same style.
+//
+// NameGrammar expands templates made of literal text and #symbol# references
+// to other rules, e.g.
+//
+//      town    -> "#prefix##suffix#", "#prefix# #suffix#"
+//      prefix  -> "Raven", "Stone", "Ash"
+//      suffix  -> "ford", "hollow", "mere"
+//
+// Both take the RNG to use, so names are reproducible from the game's seed.
+//
+
+use crate::GameRng;
+use std::collections::HashMap;
+
+//
+// Markov chains
+//
+
+// Marks the start and end of a word in the chain
+const BOUNDARY: char = '\0';
+
+pub struct MarkovNames {
+    order: usize,
+    min_len: usize,
+    max_len: usize,
+    // Following letters and how often they appeared, by preceding letters
+    chain: HashMap<String, Vec<(char, u32)>>,
+    words: Vec<String>,
+}
+
+impl MarkovNames {
+    // The order is how many letters of context choose the next letter.  Two
+    // or three work well for names.
+    pub fn new(order: usize) -> Self {
+        MarkovNames {
+            order: order.max(1),
+            min_len: 3,
+            max_len: 12,
+            chain: HashMap::new(),
+            words: Vec::new(),
+        }
+    }
+
+    pub fn with_length(&mut self, min_len: usize, max_len: usize) -> &mut Self {
+        self.min_len = min_len;
+        self.max_len = max_len.max(min_len);
+        self
+    }
+
+    // Adds example words.  Can be called more than once.
+    pub fn train<'a>(&mut self, words: impl IntoIterator<Item = &'a str>) -> &mut Self {
+        for word in words {
+            let word = word.trim().to_lowercase();
+            if word.is_empty() {
+                continue;
+            }
+            let letters = vec![BOUNDARY; self.order]
+                .into_iter()
+                .chain(word.chars())
+                .chain(std::iter::once(BOUNDARY))
+                .collect::<Vec<_>>();
+            for window in letters.windows(self.order + 1) {
+                let context = window[..self.order].iter().collect::<String>();
+                let next = window[self.order];
+                let followers = self.chain.entry(context).or_default();
+                match followers.iter_mut().find(|(ch, _)| *ch == next) {
+                    Some((_, count)) => *count += 1,
+                    None => followers.push((next, 1)),
+                }
+            }
+            self.words.push(word);
+        }
+        self
+    }
+
+    // Trains from a word list with one word per line.
+    pub fn train_from_text(&mut self, text: &str) -> &mut Self {
+        self.train(text.lines())
+    }
+
+    fn generate_once(&self, rng: &mut GameRng) -> Option<String> {
+        let mut context = vec![BOUNDARY; self.order];
+        let mut word = String::new();
+        loop {
+            let followers = self.chain.get(&context.iter().collect::<String>())?;
+            let weights = followers.iter().map(|(_, n)| *n).collect::<Vec<_>>();
+            let next = followers[rng.weighted(&weights)?].0;
+            if next == BOUNDARY {
+                break;
+            }
+            word.push(next);
+            if word.chars().count() > self.max_len {
+                return None;
+            }
+            context.remove(0);
+            context.push(next);
+        }
+        Some(word)
+    }
+
+    // Makes a new capitalised name within the length limits that isn't one of
+    // the training words.  Returns None if the chain can't produce one (e.g.
+    // it hasn't been trained).
+    pub fn generate(&self, rng: &mut GameRng) -> Option<String> {
+        (0..100)
+            .filter_map(|_| self.generate_once(rng))
+            .find(|word| word.chars().count() >= self.min_len && !self.words.contains(word))
+            .map(|word| capitalise(&word))
+    }
+}
+
+fn capitalise(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+//
+// Template grammars
+//
+
+// Expansions deeper than this are left unexpanded, to stop runaway recursion
+const MAX_DEPTH: u32 = 16;
+
+pub struct NameGrammar {
+    rules: HashMap<String, Vec<String>>,
+}
+
+impl NameGrammar {
+    pub fn new() -> Self {
+        NameGrammar {
+            rules: HashMap::new(),
+        }
+    }
+
+    // Adds alternatives for a symbol.
+    pub fn with_rule(&mut self, symbol: &str, expansions: &[&str]) -> &mut Self {
+        self.rules
+            .entry(String::from(symbol))
+            .or_default()
+            .extend(expansions.iter().map(|e| String::from(*e)));
+        self
+    }
+
+    // Reads rules written one per line as "symbol: option | option | ...".
+    // Blank lines and lines starting with '#' are ignored.
+    pub fn with_rules_from_text(&mut self, text: &str) -> &mut Self {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((symbol, options)) = line.split_once(':') {
+                let options = options.split('|').map(str::trim).collect::<Vec<_>>();
+                self.with_rule(symbol.trim(), &options);
+            }
+        }
+        self
+    }
+
+    pub fn has_rule(&self, symbol: &str) -> bool {
+        self.rules.contains_key(symbol)
+    }
+
+    // Expands a symbol.  Unknown symbols expand to themselves in #s.
+    pub fn generate(&self, symbol: &str, rng: &mut GameRng) -> String {
+        self.expand_symbol(symbol, rng, 0)
+    }
+
+    // Expands the #symbol# references in a template.
+    pub fn expand(&self, template: &str, rng: &mut GameRng) -> String {
+        self.expand_template(template, rng, 0)
+    }
+
+    fn expand_symbol(&self, symbol: &str, rng: &mut GameRng, depth: u32) -> String {
+        match self
+            .rules
+            .get(symbol)
+            .and_then(|options| rng.choose(options))
+        {
+            Some(template) if depth < MAX_DEPTH => self.expand_template(template, rng, depth + 1),
+            _ => format!("#{}#", symbol),
+        }
+    }
+
+    fn expand_template(&self, template: &str, rng: &mut GameRng, depth: u32) -> String {
+        let mut result = String::new();
+        let mut parts = template.split('#');
+        // Text outside #s alternates with symbol names
+        if let Some(text) = parts.next() {
+            result.push_str(text);
+        }
+        while let Some(symbol) = parts.next() {
+            match parts.next() {
+                Some(text) => {
+                    result.push_str(&self.expand_symbol(symbol, rng, depth));
+                    result.push_str(text);
+                }
+                // Unmatched '#'
+                None => {
+                    result.push('#');
+                    result.push_str(symbol);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Default for NameGrammar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markov_names_are_new_words_in_the_same_style() {
+        let mut names = MarkovNames::new(2);
+        names
+            .with_length(4, 8)
+            .train(["Aldric", "Aldwin", "Baldric", "Cedric", "Edwin", "Godric"])
+            .train_from_text("Osric\nWulfric\n\n");
+        let mut rng = GameRng::new(5);
+        for _ in 0..20 {
+            let name = names.generate(&mut rng).unwrap();
+            let lower = name.to_lowercase();
+            assert!((4..=8).contains(&name.chars().count()), "{}", name);
+            assert!(name.starts_with(char::is_uppercase), "{}", name);
+            assert!(!names.words.contains(&lower), "{}", name);
+            // Every pair of letters was seen in the training words
+            let letters = lower.chars().collect::<Vec<_>>();
+            for pair in letters.windows(3) {
+                assert!(
+                    names.chain[&pair[..2].iter().collect::<String>()]
+                        .iter()
+                        .any(|&(ch, _)| ch == pair[2]),
+                    "{}",
+                    name
+                );
+            }
+        }
+        assert_eq!(MarkovNames::new(2).generate(&mut rng), None);
+    }
+
+    #[test]
+    fn grammars_expand_symbols() {
+        let mut grammar = NameGrammar::new();
+        grammar
+            .with_rule("town", &["#prefix##suffix#"])
+            .with_rules_from_text("# Parts\nprefix: Raven | Stone\n\nsuffix: ford\n");
+        let mut rng = GameRng::new(9);
+        let town = grammar.generate("town", &mut rng);
+        assert!(town == "Ravenford" || town == "Stoneford", "{}", town);
+        assert!(grammar.has_rule("prefix"));
+        assert_eq!(grammar.expand("Near #suffix#", &mut rng), "Near ford");
+        assert_eq!(
+            grammar.expand("#unknown# and # alone", &mut rng),
+            "#unknown# and # alone"
+        );
+    }
+
+    #[test]
+    fn recursive_rules_stop_expanding() {
+        let mut grammar = NameGrammar::new();
+        grammar.with_rule("loop", &["a#loop#"]);
+        let text = grammar.generate("loop", &mut GameRng::new(0));
+        assert_eq!(text, format!("{}#loop#", "a".repeat(MAX_DEPTH as usize)));
+    }
+}
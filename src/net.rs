@@ -0,0 +1,488 @@
+//
+// Networking
+//
+// A small message transport for multiplayer games, enabled with the "net"
+// feature.  A NetServer accepts TCP connections from NetClients, and both ends
+// send any serde type as a message.  Messages are encoded with bincode and
+// framed with their length, so each arrives whole.
+//
+// Sockets never block the game: poll() is called once per tick to accept
+// connections, send what is queued and return the messages that have arrived.
+// Clients connecting and disconnecting are published as NetEvents on the event
+// bus:
+//
+//      let messages = server.poll::<Command>(ctx.events());
+//      for event in ctx.events().drain::<NetEvent>() { ... }
+//
+// Lockstep helps shared-turn games, where the turn only advances once every
+// player has chosen what to do.  The server submits each player's command and
+// broadcasts the turns it completes; every client then plays the same
+// commands in the same order, so their games stay in step:
+//
+//      ServerTick:     lockstep.submit(client, command);
+//                      if let Some(turn) = lockstep.advance() {
+//                          server.broadcast(&turn)?;
+//                      }
+//      ClientTick:     for turn in client.poll::<LockstepTurn<Command>>(ctx.events()) {
+//                          play(turn.commands);
+//                      }
+//
+
+use crate::{EventBus, RogueError, RogueResult};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+// Larger messages are taken as a corrupt stream, and the connection is closed
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+pub type ClientId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetEvent {
+    // On the server
+    ClientConnected(ClientId),
+    ClientDisconnected(ClientId),
+    // On a client, after connecting and when the server goes away
+    Connected,
+    Disconnected,
+}
+
+//
+// Framing
+//
+
+fn encode<M: Serialize>(message: &M) -> RogueResult<Vec<u8>> {
+    let data = bincode::serialize(message).map_err(|e| RogueError::BadMessage(e.to_string()))?;
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(RogueError::BadMessage(format!(
+            "{} bytes is too large",
+            data.len()
+        )));
+    }
+    let mut frame = (data.len() as u32).to_le_bytes().to_vec();
+    frame.extend(data);
+    Ok(frame)
+}
+
+struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    // Sends as much as the socket will take.  Returns false if the connection
+    // has failed.
+    fn flush(&mut self) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    // Adds the messages that have arrived whole.  Returns false if the
+    // connection has closed or sent something that isn't a message.
+    fn receive<M: DeserializeOwned>(&mut self, messages: &mut Vec<M>) -> bool {
+        let mut open = true;
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    open = false;
+                    break;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+
+        let mut start = 0;
+        while let Some(header) = self.incoming.get(start..start + 4) {
+            let size = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            if size > MAX_MESSAGE_SIZE {
+                return false;
+            }
+            let data = match self.incoming.get(start + 4..start + 4 + size) {
+                Some(data) => data,
+                None => break,
+            };
+            match bincode::deserialize(data) {
+                Ok(message) => messages.push(message),
+                Err(e) => {
+                    log::warn!("Closing connection after a bad message: {}", e);
+                    return false;
+                }
+            }
+            start += 4 + size;
+        }
+        self.incoming.drain(..start);
+        open
+    }
+}
+
+//
+// Server
+//
+
+pub struct NetServer {
+    listener: TcpListener,
+    clients: Vec<(ClientId, Connection)>,
+    next_id: ClientId,
+}
+
+impl NetServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> RogueResult<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(NetServer {
+            listener,
+            clients: Vec::new(),
+            next_id: 0,
+        })
+    }
+
+    // The address being listened on, e.g. to find the port after binding to
+    // port 0
+    pub fn local_addr(&self) -> RogueResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn clients(&self) -> Vec<ClientId> {
+        self.clients.iter().map(|(id, _)| *id).collect()
+    }
+
+    // Messages to a client that has gone are dropped.
+    pub fn send<M: Serialize>(&mut self, client: ClientId, message: &M) -> RogueResult<()> {
+        let frame = encode(message)?;
+        if let Some((_, connection)) = self.clients.iter_mut().find(|(id, _)| *id == client) {
+            connection.outgoing.extend(frame);
+        }
+        Ok(())
+    }
+
+    pub fn broadcast<M: Serialize>(&mut self, message: &M) -> RogueResult<()> {
+        let frame = encode(message)?;
+        for (_, connection) in self.clients.iter_mut() {
+            connection.outgoing.extend_from_slice(&frame);
+        }
+        Ok(())
+    }
+
+    // Closes the connection straight away, without sending what is queued.
+    // No event is published.
+    pub fn disconnect(&mut self, client: ClientId) {
+        self.clients.retain(|(id, _)| *id != client);
+    }
+
+    // Call once per tick.  Returns the messages that have arrived, oldest
+    // first.
+    pub fn poll<M: DeserializeOwned>(&mut self, events: &mut EventBus) -> Vec<(ClientId, M)> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => match Connection::new(stream) {
+                    Ok(connection) => {
+                        let id = self.next_id;
+                        self.next_id += 1;
+                        log::info!("Client {} connected from {}", id, addr);
+                        self.clients.push((id, connection));
+                        events.publish(NetEvent::ClientConnected(id));
+                    }
+                    Err(e) => log::warn!("Unable to set up connection from {}: {}", addr, e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Unable to accept connection: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut messages = Vec::new();
+        self.clients.retain_mut(|(id, connection)| {
+            let mut received = Vec::new();
+            let open = connection.receive(&mut received) && connection.flush();
+            messages.extend(received.into_iter().map(|message| (*id, message)));
+            if !open {
+                log::info!("Client {} disconnected", id);
+                events.publish(NetEvent::ClientDisconnected(*id));
+            }
+            open
+        });
+        messages
+    }
+}
+
+//
+// Client
+//
+
+pub struct NetClient {
+    connection: Option<Connection>,
+    announced: bool,
+}
+
+impl NetClient {
+    // Blocks until connected or refused.
+    pub fn connect(addr: impl ToSocketAddrs) -> RogueResult<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(NetClient {
+            connection: Some(Connection::new(stream)?),
+            announced: false,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    // Messages sent after the connection is lost are dropped.
+    pub fn send<M: Serialize>(&mut self, message: &M) -> RogueResult<()> {
+        let frame = encode(message)?;
+        if let Some(connection) = &mut self.connection {
+            connection.outgoing.extend(frame);
+        }
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        self.connection = None;
+    }
+
+    // Call once per tick.  Returns the messages that have arrived, oldest
+    // first.
+    pub fn poll<M: DeserializeOwned>(&mut self, events: &mut EventBus) -> Vec<M> {
+        let mut messages = Vec::new();
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None => return messages,
+        };
+        if !self.announced {
+            self.announced = true;
+            events.publish(NetEvent::Connected);
+        }
+        if !(connection.receive(&mut messages) && connection.flush()) {
+            log::info!("Disconnected from server");
+            self.connection = None;
+            events.publish(NetEvent::Disconnected);
+        }
+        messages
+    }
+}
+
+//
+// Lockstep
+//
+
+// The commands of every player for a turn, in the order of their ids
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockstepTurn<C> {
+    pub turn: u64,
+    pub commands: Vec<(ClientId, C)>,
+}
+
+pub struct Lockstep<C> {
+    turn: u64,
+    players: Vec<ClientId>,
+    pending: HashMap<ClientId, C>,
+}
+
+impl<C> Lockstep<C> {
+    pub fn new() -> Self {
+        Lockstep {
+            turn: 0,
+            players: Vec::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    // The turn waiting for commands
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    pub fn players(&self) -> &[ClientId] {
+        &self.players
+    }
+
+    // A player joining has to submit a command for the current turn too.
+    pub fn add_player(&mut self, player: ClientId) {
+        if let Err(index) = self.players.binary_search(&player) {
+            self.players.insert(index, player);
+        }
+    }
+
+    // The turn no longer waits for a player that has left, e.g. on
+    // NetEvent::ClientDisconnected.
+    pub fn remove_player(&mut self, player: ClientId) {
+        self.players.retain(|&p| p != player);
+        self.pending.remove(&player);
+    }
+
+    // Returns false if the player isn't playing or has already submitted a
+    // command this turn.
+    pub fn submit(&mut self, player: ClientId, command: C) -> bool {
+        if !self.players.contains(&player) || self.pending.contains_key(&player) {
+            return false;
+        }
+        self.pending.insert(player, command);
+        true
+    }
+
+    pub fn has_submitted(&self, player: ClientId) -> bool {
+        self.pending.contains_key(&player)
+    }
+
+    // The players the turn is waiting for
+    pub fn waiting_for(&self) -> Vec<ClientId> {
+        self.players
+            .iter()
+            .copied()
+            .filter(|player| !self.pending.contains_key(player))
+            .collect()
+    }
+
+    // Completes the turn once every player has submitted a command.
+    pub fn advance(&mut self) -> Option<LockstepTurn<C>> {
+        if self.players.is_empty() || self.pending.len() < self.players.len() {
+            return None;
+        }
+        let pending = &mut self.pending;
+        let commands = self
+            .players
+            .iter()
+            .filter_map(|&player| pending.remove(&player).map(|c| (player, c)))
+            .collect();
+        let turn = LockstepTurn {
+            turn: self.turn,
+            commands,
+        };
+        self.turn += 1;
+        Some(turn)
+    }
+}
+
+impl<C> Default for Lockstep<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // Polls until f returns something, or gives up after a few seconds
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return value;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn messages_arrive_whole_in_both_directions() {
+        let mut server = NetServer::bind("127.0.0.1:0").unwrap();
+        let mut client = NetClient::connect(server.local_addr().unwrap()).unwrap();
+        let mut server_events = EventBus::new();
+        let mut client_events = EventBus::new();
+
+        // Larger than a single read
+        let long = "x".repeat(10_000);
+        client.send(&String::from("hello")).unwrap();
+        client.send(&long).unwrap();
+        assert!(client.poll::<String>(&mut client_events).is_empty());
+        assert_eq!(client_events.drain::<NetEvent>(), [NetEvent::Connected]);
+
+        let mut messages = Vec::new();
+        wait_for(|| {
+            messages.extend(server.poll::<String>(&mut server_events));
+            (messages.len() == 2).then_some(())
+        });
+        assert_eq!(messages, [(0, String::from("hello")), (0, long)]);
+        assert_eq!(
+            server_events.drain::<NetEvent>(),
+            [NetEvent::ClientConnected(0)]
+        );
+        assert_eq!(server.clients(), [0]);
+
+        server.broadcast(&(7u8, 'x')).unwrap();
+        server.poll::<()>(&mut server_events);
+        let reply = wait_for(|| client.poll::<(u8, char)>(&mut client_events).pop());
+        assert_eq!(reply, (7, 'x'));
+
+        client.disconnect();
+        assert!(!client.is_connected());
+        wait_for(|| {
+            server.poll::<()>(&mut server_events);
+            server.clients().is_empty().then_some(())
+        });
+        assert_eq!(
+            server_events.drain::<NetEvent>(),
+            [NetEvent::ClientDisconnected(0)]
+        );
+    }
+
+    #[test]
+    fn lockstep_waits_for_every_player() {
+        let mut lockstep = Lockstep::new();
+        assert_eq!(lockstep.advance(), None);
+        lockstep.add_player(2);
+        lockstep.add_player(1);
+        lockstep.add_player(2);
+        assert_eq!(lockstep.players(), [1, 2]);
+
+        assert!(lockstep.submit(2, 'b'));
+        assert!(!lockstep.submit(2, 'c'));
+        assert!(!lockstep.submit(3, 'c'));
+        assert!(lockstep.has_submitted(2));
+        assert_eq!(lockstep.waiting_for(), [1]);
+        assert_eq!(lockstep.advance(), None);
+
+        assert!(lockstep.submit(1, 'a'));
+        assert_eq!(
+            lockstep.advance(),
+            Some(LockstepTurn {
+                turn: 0,
+                commands: vec![(1, 'a'), (2, 'b')],
+            })
+        );
+        assert_eq!(lockstep.turn(), 1);
+
+        // Players leaving don't hold the turn up
+        lockstep.submit(1, 'd');
+        lockstep.remove_player(2);
+        assert_eq!(lockstep.advance().unwrap().commands, [(1, 'd')]);
+    }
+}
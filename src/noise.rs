@@ -0,0 +1,152 @@
+//
+// Noise maps
+//
+// Works out how loud each cell is when something makes a noise.  Sound
+// spreads out from each source losing some volume with every cell it passes
+// through: a little through open floor, more through doors, and a lot (or
+// all of it) through walls.  The game decides the loss for each cell.  Where
+// several sounds overlap, the loudest wins.
+//
+// Monsters can then check the loudness at their position against how well
+// they hear, and stealthy players can check how far their footsteps carry.
+//
+
+use crate::{Grid, Point};
+use std::collections::BinaryHeap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoiseSource {
+    pub position: Point,
+    pub volume: u32,
+}
+
+impl NoiseSource {
+    pub fn new(position: Point, volume: u32) -> Self {
+        NoiseSource { position, volume }
+    }
+}
+
+pub struct NoiseMap {
+    grid: Grid<u32>,
+}
+
+impl NoiseMap {
+    pub fn new(width: u32, height: u32) -> Self {
+        NoiseMap {
+            grid: Grid::new(width, height, 0),
+        }
+    }
+
+    pub fn grid(&self) -> &Grid<u32> {
+        &self.grid
+    }
+
+    pub fn clear(&mut self) {
+        self.grid.fill(0);
+    }
+
+    // Recalculates the map in place from the sources.  `loss` gives the volume
+    // lost entering a cell, or None if no sound gets through at all.
+    pub fn propagate(&mut self, sources: &[NoiseSource], loss: impl Fn(Point) -> Option<u32>) {
+        self.clear();
+        let mut open = BinaryHeap::new();
+        for source in sources {
+            if let Some(cell) = self.grid.get_mut(source.position) {
+                if source.volume > *cell {
+                    *cell = source.volume;
+                    open.push((source.volume, source.position.x, source.position.y));
+                }
+            }
+        }
+
+        // Loudest cells first, so each cell is settled by its loudest path
+        while let Some((volume, x, y)) = open.pop() {
+            let p = Point::new(x, y);
+            if self.grid.get(p).is_some_and(|&v| v > volume) {
+                continue;
+            }
+            let neighbours = self.grid.neighbours(p).collect::<Vec<_>>();
+            for q in neighbours {
+                let new = match loss(q) {
+                    Some(loss) => volume.saturating_sub(loss.max(1)),
+                    None => continue,
+                };
+                if let Some(old) = self.grid.get_mut(q) {
+                    if new > *old {
+                        *old = new;
+                        open.push((new, q.x, q.y));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn loudness(&self, p: Point) -> u32 {
+        self.grid.get(p).copied().unwrap_or(0)
+    }
+
+    // Whether a listener at p with the given hearing threshold hears anything
+    pub fn heard(&self, p: Point, threshold: u32) -> bool {
+        self.loudness(p) > 0 && self.loudness(p) >= threshold
+    }
+
+    // The neighbouring cell the sound is loudest in, for moving towards the
+    // noise.  None if there's nothing louder nearby.
+    pub fn towards_noise(&self, from: Point) -> Option<Point> {
+        let here = self.loudness(from);
+        self.grid
+            .neighbours(from)
+            .map(|q| (self.loudness(q), q))
+            .filter(|&(v, _)| v > here)
+            .max_by_key(|&(v, _)| v)
+            .map(|(_, q)| q)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sound_fades_and_walls_block_it() {
+        // A wall at x = 3 lets nothing through and a door at (3, 1) loses 5
+        let loss = |p: Point| match (p.x, p.y) {
+            (3, 1) => Some(5),
+            (3, _) => None,
+            _ => Some(1),
+        };
+        let mut noise = NoiseMap::new(6, 3);
+        noise.propagate(&[NoiseSource::new(Point::new(0, 1), 10)], loss);
+        let row = (0..6)
+            .map(|x| noise.loudness(Point::new(x, 1)))
+            .collect::<Vec<_>>();
+        assert_eq!(row, [10, 9, 8, 3, 2, 1]);
+        assert_eq!(noise.loudness(Point::new(3, 0)), 0);
+        assert_eq!(noise.loudness(Point::new(4, 0)), 2);
+        assert_eq!(noise.loudness(Point::new(9, 9)), 0);
+    }
+
+    #[test]
+    fn the_loudest_source_wins() {
+        let mut noise = NoiseMap::new(5, 1);
+        noise.propagate(
+            &[
+                NoiseSource::new(Point::new(0, 0), 4),
+                NoiseSource::new(Point::new(4, 0), 3),
+            ],
+            |_| Some(0),
+        );
+        // Every cell loses at least one
+        assert_eq!(noise.grid().cells(), [4, 3, 2, 2, 3]);
+        assert!(noise.heard(Point::new(1, 0), 3));
+        assert!(!noise.heard(Point::new(2, 0), 3));
+        assert_eq!(
+            noise.towards_noise(Point::new(2, 0)),
+            Some(Point::new(1, 0))
+        );
+        assert_eq!(noise.towards_noise(Point::new(0, 0)), None);
+
+        noise.clear();
+        assert!(!noise.heard(Point::new(0, 0), 0));
+    }
+}
@@ -0,0 +1,213 @@
+//
+// Asset packs
+//
+// A pack bundles a directory of assets into a single file so that a shipped
+// game is just the executable and its data file.  The format is simple:
+//
+//      "MAGEPAK1"
+//      entry count                     u32
+//      for each entry:
+//          name length                 u16
+//          name (UTF-8, '/' separated)
+//          offset from start of file   u64
+//          length                      u64
+//      file data
+//
+// All numbers are little-endian.  Only the index is read when a pack is
+// opened; file data is read on demand.
+//
+
+use crate::{RogueError, RogueResult};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const PACK_MAGIC: &[u8; 8] = b"MAGEPAK1";
+
+pub struct AssetPack {
+    path: PathBuf,
+    // Offset and length by name
+    entries: BTreeMap<String, (u64, u64)>,
+}
+
+impl AssetPack {
+    pub fn open(path: &Path) -> RogueResult<Self> {
+        let bad_pack = |message: &str| RogueError::BadContent {
+            file: path.display().to_string(),
+            message: String::from(message),
+        };
+
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != PACK_MAGIC {
+            return Err(bad_pack("not an asset pack"));
+        }
+
+        let count = read_u32(&mut file)?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let name_len = read_u16(&mut file)? as usize;
+            let mut name = vec![0u8; name_len];
+            file.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| bad_pack("bad file name"))?;
+            let offset = read_u64(&mut file)?;
+            let length = read_u64(&mut file)?;
+            entries.insert(name, (offset, length));
+        }
+
+        Ok(AssetPack {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|name| name.as_str())
+    }
+
+    pub fn read(&self, name: &str) -> RogueResult<Vec<u8>> {
+        let &(offset, length) =
+            self.entries
+                .get(name)
+                .ok_or_else(|| RogueError::AssetNotFound {
+                    name: String::from(name),
+                    location: Some(self.path.display().to_string()),
+                })?;
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![0u8; length as usize];
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    //
+    // Building packs
+    //
+
+    // Writes every file under the directory into a new pack.  Names are the
+    // paths relative to the directory, so they match the names given to
+    // Assets::load().
+    pub fn create(pack_path: &Path, dir: &Path) -> RogueResult<()> {
+        let mut files = Vec::new();
+        collect_files(dir, dir, &mut files)?;
+        files.sort();
+
+        let index_len: usize = files.iter().map(|(name, _)| 2 + name.len() + 8 + 8).sum();
+        let mut offset = (PACK_MAGIC.len() + 4 + index_len) as u64;
+
+        let mut out = File::create(pack_path)?;
+        out.write_all(PACK_MAGIC)?;
+        out.write_all(&(files.len() as u32).to_le_bytes())?;
+        let mut lengths = Vec::new();
+        for (name, path) in &files {
+            let length = fs::metadata(path)?.len();
+            out.write_all(&(name.len() as u16).to_le_bytes())?;
+            out.write_all(name.as_bytes())?;
+            out.write_all(&offset.to_le_bytes())?;
+            out.write_all(&length.to_le_bytes())?;
+            offset += length;
+            lengths.push(length);
+        }
+        for ((_, path), length) in files.iter().zip(lengths) {
+            let bytes = fs::read(path)?;
+            if bytes.len() as u64 != length {
+                return Err(RogueError::BadContent {
+                    file: path.display().to_string(),
+                    message: String::from("file changed while building the pack"),
+                });
+            }
+            out.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+}
+
+fn collect_files(base: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> RogueResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(base, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(base) {
+            let name = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, path));
+        }
+    }
+    Ok(())
+}
+
+fn read_u16(file: &mut File) -> RogueResult<u16> {
+    let mut bytes = [0u8; 2];
+    file.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(file: &mut File) -> RogueResult<u32> {
+    let mut bytes = [0u8; 4];
+    file.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(file: &mut File) -> RogueResult<u64> {
+    let mut bytes = [0u8; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assets;
+
+    #[test]
+    fn packs_hold_a_directory_of_files() {
+        let dir = std::env::temp_dir().join(format!("mage-pack-{}", std::process::id()));
+        let data = dir.join("data");
+        fs::create_dir_all(data.join("fonts")).unwrap();
+        fs::write(data.join("a.txt"), "first").unwrap();
+        fs::write(data.join("fonts").join("b.txt"), "second").unwrap();
+        fs::write(dir.join("loose.txt"), "loose").unwrap();
+        let path = dir.join("data.pak");
+        let created = AssetPack::create(&path, &data);
+
+        let pack = AssetPack::open(&path).map(|pack| {
+            let names = pack.names().map(String::from).collect::<Vec<_>>();
+            let reads = ["a.txt", "fonts/b.txt", "c.txt"].map(|name| pack.read(name));
+            (names, pack.contains("loose.txt"), reads)
+        });
+        let not_pack = AssetPack::open(&dir.join("loose.txt"));
+        let mut assets = Assets::new(&dir.join("nowhere"));
+        assets.mount_dir(&dir);
+        let mounted = assets.mount_pack(&path).map(|_| ());
+        let read = assets.read("a.txt");
+        let _ = fs::remove_dir_all(&dir);
+
+        created.unwrap();
+        let (names, contains_loose, [a, b, c]) = pack.unwrap();
+        assert_eq!(names, ["a.txt", "fonts/b.txt"]);
+        assert_eq!(a.unwrap(), b"first");
+        assert_eq!(b.unwrap(), b"second");
+        assert!(!contains_loose);
+        assert!(matches!(c, Err(RogueError::AssetNotFound { .. })));
+        assert!(not_pack.is_err());
+
+        // The pack was mounted last, so is searched before the directory
+        mounted.unwrap();
+        assert_eq!(read.unwrap(), b"first");
+    }
+}
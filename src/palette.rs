@@ -0,0 +1,223 @@
+//
+// Palettes
+//
+// A game that draws with a fixed set of colours can keep them in a Palette
+// and refer to them by index.  Ranges of the palette can then be cycled on a
+// timer, rotating their colours one slot at a time, for water shimmer, lava
+// glow and portals at almost no cost:
+//
+//      let mut palette = Palette::new(&colours);
+//      palette.with_cycle(16, 4, Duration::from_millis(150));
+//
+// Either draw with palette.colour(index), which gives the cycled colour, or
+// draw with the palette's base colours and apply() the palette to the image
+// afterwards, which swaps each base colour in a cycled range for its current
+// one.  A palette is also an Effect, so the Animator can update and apply it.
+//
+
+use crate::{Effect, Image};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteCycle {
+    pub start: usize,
+    pub len: usize,
+    // How long each step of the rotation lasts
+    pub interval: Duration,
+    // Rotates towards the start of the range instead of the end
+    pub reverse: bool,
+}
+
+pub struct Palette {
+    base: Vec<u32>,
+    current: Vec<u32>,
+    cycles: Vec<(PaletteCycle, Duration)>,
+}
+
+impl Palette {
+    pub fn new(colours: &[u32]) -> Self {
+        Palette {
+            base: colours.to_vec(),
+            current: colours.to_vec(),
+            cycles: Vec::new(),
+        }
+    }
+
+    // Cycles a range of slots.  Ranges past the end of the palette are cut
+    // short.
+    pub fn with_cycle(&mut self, start: usize, len: usize, interval: Duration) -> &mut Self {
+        self.add_cycle(PaletteCycle {
+            start,
+            len,
+            interval,
+            reverse: false,
+        });
+        self
+    }
+
+    pub fn add_cycle(&mut self, cycle: PaletteCycle) {
+        let len = cycle.len.min(self.base.len().saturating_sub(cycle.start));
+        if len > 1 && !cycle.interval.is_zero() {
+            self.cycles
+                .push((PaletteCycle { len, ..cycle }, Duration::ZERO));
+        }
+    }
+
+    // Stops cycling and puts the base colours back.
+    pub fn clear_cycles(&mut self) {
+        self.cycles.clear();
+        self.current.copy_from_slice(&self.base);
+    }
+
+    pub fn len(&self) -> usize {
+        self.base.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.base.is_empty()
+    }
+
+    // The colour in a slot now, after cycling.  Slots past the end are black.
+    pub fn colour(&self, index: usize) -> u32 {
+        self.current.get(index).copied().unwrap_or(0xff00_0000)
+    }
+
+    pub fn base_colour(&self, index: usize) -> u32 {
+        self.base.get(index).copied().unwrap_or(0xff00_0000)
+    }
+
+    pub fn set_colour(&mut self, index: usize, colour: u32) {
+        if let Some(slot) = self.base.get_mut(index) {
+            *slot = colour;
+            self.refresh();
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.current.copy_from_slice(&self.base);
+        for (cycle, elapsed) in self.cycles.iter() {
+            let steps = (elapsed.as_nanos() / cycle.interval.as_nanos()) as usize % cycle.len;
+            let shift = if cycle.reverse {
+                cycle.len - steps
+            } else {
+                steps
+            };
+            for i in 0..cycle.len {
+                let to = cycle.start + (i + shift) % cycle.len;
+                self.current[to] = self.base[cycle.start + i];
+            }
+        }
+    }
+
+    // Swaps the ink and paper of every cell drawn in a base colour of a
+    // cycled range for that slot's current colour.
+    pub fn apply(&self, image: &mut Image) {
+        let swaps = self
+            .cycles
+            .iter()
+            .flat_map(|(cycle, _)| cycle.start..cycle.start + cycle.len)
+            .map(|i| (self.base[i], self.current[i]))
+            .filter(|(from, to)| from != to)
+            .collect::<Vec<_>>();
+        if swaps.is_empty() {
+            return;
+        }
+        let swap = |colour: &mut u32| {
+            if let Some(&(_, to)) = swaps.iter().find(|(from, _)| from == colour) {
+                *colour = to;
+            }
+        };
+        image.fore_image.iter_mut().for_each(swap);
+        image.back_image.iter_mut().for_each(swap);
+    }
+}
+
+impl Effect for Palette {
+    fn update(&mut self, dt: Duration) {
+        if self.cycles.is_empty() {
+            return;
+        }
+        for (_, elapsed) in self.cycles.iter_mut() {
+            *elapsed += dt;
+        }
+        self.refresh();
+    }
+
+    fn draw(&self, image: &mut Image) {
+        self.apply(image);
+    }
+
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn colours(palette: &Palette) -> Vec<u32> {
+        (0..palette.len()).map(|i| palette.colour(i)).collect()
+    }
+
+    #[test]
+    fn cycles_rotate_their_range() {
+        let mut palette = Palette::new(&[1, 2, 3, 4, 5]);
+        palette.with_cycle(1, 3, ms(10));
+        palette.update(ms(5));
+        assert_eq!(colours(&palette), [1, 2, 3, 4, 5]);
+        palette.update(ms(5));
+        assert_eq!(colours(&palette), [1, 4, 2, 3, 5]);
+        palette.update(ms(20));
+        assert_eq!(colours(&palette), [1, 2, 3, 4, 5]);
+        assert_eq!(palette.colour(5), 0xff00_0000);
+
+        // Base colours changed mid-cycle are cycled too
+        palette.update(ms(10));
+        palette.set_colour(2, 9);
+        assert_eq!(colours(&palette), [1, 4, 2, 9, 5]);
+        assert_eq!(palette.base_colour(2), 9);
+
+        palette.clear_cycles();
+        assert_eq!(colours(&palette), [1, 2, 9, 4, 5]);
+    }
+
+    #[test]
+    fn reversed_and_short_cycles() {
+        let mut palette = Palette::new(&[1, 2, 3, 4, 5]);
+        palette.add_cycle(PaletteCycle {
+            start: 1,
+            len: 3,
+            interval: ms(10),
+            reverse: true,
+        });
+        // Too short or without an interval, so ignored
+        palette.with_cycle(4, 1, ms(10));
+        palette.with_cycle(0, 2, Duration::ZERO);
+        palette.update(ms(10));
+        assert_eq!(colours(&palette), [1, 3, 4, 2, 5]);
+
+        // Cut short to the last two slots
+        let mut palette = Palette::new(&[1, 2, 3, 4, 5]);
+        palette.with_cycle(3, 10, ms(10));
+        palette.update(ms(10));
+        assert_eq!(colours(&palette), [1, 2, 3, 5, 4]);
+    }
+
+    #[test]
+    fn applying_swaps_base_colours_in_the_image() {
+        let mut palette = Palette::new(&[1, 2, 3, 4, 5]);
+        palette.with_cycle(1, 3, ms(10));
+        palette.update(ms(10));
+        let mut image = Image::new(2, 1);
+        image.clear(2, 5);
+        image.fore_image[1] = 4;
+        palette.draw(&mut image);
+        assert_eq!(image.fore_image, [4, 3]);
+        assert_eq!(image.back_image, [5, 5]);
+    }
+}
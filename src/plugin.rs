@@ -0,0 +1,605 @@
+//
+// Plugin sandbox
+//
+// The host side of a sandbox for third-party script mods, enabled with the
+// "plugins" feature.  A plugin never touches the game directly: everything
+// it can do goes through a PluginHost, which checks each call against what
+// the game has allowed.  A plugin can:
+//
+//      read and write the data the game registers for plugins, as bytes,
+//      where the game marks each entry read-only or read-write
+//      emit events, which the game takes after each call
+//      draw on a PixelCanvas the game provides and later draws on an image
+//      write to the log
+//
+// The plugins themselves run on a PluginRuntime, whose imports forward to
+// the host's calls.  WasmRuntime runs WebAssembly modules with wasmtime.
+// Each call into a plugin is made through PluginHost::call(), so the limits
+// on data size and events apply per call:
+//
+//      let mut runtime = WasmRuntime::new()?;
+//      runtime.load("weather-mod", &fs::read("weather.wasm")?)?;
+//      let mut host = PluginHost::new(PixelCanvas::new(PixelMode::Braille, 64, 32));
+//      host.register_data("player", PluginAccess::Read, &player_bytes);
+//      host.call(&mut runtime, "weather-mod", "on_turn")?;
+//      for event in host.take_events() { ... }
+//
+// A WebAssembly plugin exports its memory as "memory", and the functions the
+// game calls, which take and return nothing.  It imports these from "mage",
+// where strings and byte arrays are passed as a pointer and a length:
+//
+//      read(name, name_len, buffer, buffer_len) -> i32
+//                              copies as much of the data as fits into the
+//                              buffer and returns the data's full length
+//      write(name, name_len, data, data_len)
+//      emit(name, name_len, payload, payload_len)
+//      set_pixel(x, y, colour)
+//      line(x0, y0, x1, y1, colour)
+//      clear_canvas()
+//      log(text, text_len)
+//
+// Every pointer and length is checked against the plugin's memory.  A call
+// the host refuses, or one with bad arguments, stops the plugin, and
+// PluginHost::call() returns why.  Each call, and loading, can use a limited
+// amount of fuel (roughly one unit per instruction), and a plugin's memory
+// can't grow past a limit.
+//
+
+use crate::{PixelCanvas, PixelMode, Point, RogueError, RogueResult};
+use std::collections::{BTreeMap, HashMap};
+use wasmtime::{
+    Caller, Config, Engine, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+};
+
+const DEFAULT_MAX_DATA_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_EVENTS: usize = 64;
+const DEFAULT_FUEL: u64 = 10_000_000;
+const DEFAULT_MAX_MEMORY: usize = 16 * 1024 * 1024;
+const MAX_TABLE_ELEMENTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginAccess {
+    Read,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginEvent {
+    pub plugin: String,
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+// Runs plugin code.  Its host imports should call the PluginHost passed to
+// call() and nothing else.
+pub trait PluginRuntime {
+    fn load(&mut self, plugin: &str, module: &[u8]) -> RogueResult<()>;
+    fn unload(&mut self, plugin: &str);
+    fn call(&mut self, plugin: &str, function: &str, host: &mut PluginHost) -> RogueResult<()>;
+}
+
+pub struct PluginHost {
+    data: BTreeMap<String, (PluginAccess, Vec<u8>)>,
+    canvas: PixelCanvas,
+    events: Vec<PluginEvent>,
+    max_data_size: usize,
+    max_events: usize,
+    // The plugin being called and the events it has emitted in this call
+    current: Option<String>,
+    call_events: usize,
+}
+
+impl PluginHost {
+    pub fn new(canvas: PixelCanvas) -> Self {
+        PluginHost {
+            data: BTreeMap::new(),
+            canvas,
+            events: Vec::new(),
+            max_data_size: DEFAULT_MAX_DATA_SIZE,
+            max_events: DEFAULT_MAX_EVENTS,
+            current: None,
+            call_events: 0,
+        }
+    }
+
+    // The largest a data entry can be written by a plugin, and the most
+    // events a plugin can emit in one call
+    pub fn with_limits(&mut self, max_data_size: usize, max_events: usize) -> &mut Self {
+        self.max_data_size = max_data_size;
+        self.max_events = max_events;
+        self
+    }
+
+    //
+    // The game's side
+    //
+
+    pub fn register_data(&mut self, name: &str, access: PluginAccess, data: &[u8]) {
+        self.data
+            .insert(String::from(name), (access, data.to_vec()));
+    }
+
+    pub fn unregister_data(&mut self, name: &str) {
+        self.data.remove(name);
+    }
+
+    pub fn data(&self, name: &str) -> Option<&[u8]> {
+        self.data.get(name).map(|(_, data)| data.as_slice())
+    }
+
+    pub fn canvas(&self) -> &PixelCanvas {
+        &self.canvas
+    }
+
+    pub fn canvas_mut(&mut self) -> &mut PixelCanvas {
+        &mut self.canvas
+    }
+
+    // The events emitted since the last time they were taken
+    pub fn take_events(&mut self) -> Vec<PluginEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Calls a function of a plugin with this host.
+    pub fn call(
+        &mut self,
+        runtime: &mut dyn PluginRuntime,
+        plugin: &str,
+        function: &str,
+    ) -> RogueResult<()> {
+        self.current = Some(String::from(plugin));
+        self.call_events = 0;
+        let result = runtime.call(plugin, function, self);
+        self.current = None;
+        result
+    }
+
+    //
+    // The plugin's side, called by the runtime's imports
+    //
+
+    pub fn read(&self, name: &str) -> RogueResult<&[u8]> {
+        self.data(name)
+            .ok_or_else(|| self.denied(format!("there is no data called {}", name)))
+    }
+
+    pub fn write(&mut self, name: &str, data: &[u8]) -> RogueResult<()> {
+        match self.data.get(name) {
+            Some((PluginAccess::ReadWrite, _)) if data.len() <= self.max_data_size => {}
+            Some((PluginAccess::ReadWrite, _)) => {
+                return Err(self.denied(format!("{} bytes is too much for {}", data.len(), name)))
+            }
+            Some((PluginAccess::Read, _)) => {
+                return Err(self.denied(format!("{} is read-only", name)))
+            }
+            None => return Err(self.denied(format!("there is no data called {}", name))),
+        }
+        if let Some((_, entry)) = self.data.get_mut(name) {
+            *entry = data.to_vec();
+        }
+        Ok(())
+    }
+
+    pub fn emit(&mut self, name: &str, payload: &[u8]) -> RogueResult<()> {
+        let plugin = self.calling()?;
+        if self.call_events >= self.max_events {
+            return Err(self.denied(format!("more than {} events", self.max_events)));
+        }
+        if payload.len() > self.max_data_size {
+            return Err(self.denied(format!("{} bytes is too much for an event", payload.len())));
+        }
+        self.call_events += 1;
+        self.events.push(PluginEvent {
+            plugin,
+            name: String::from(name),
+            payload: payload.to_vec(),
+        });
+        Ok(())
+    }
+
+    // Pixels off the canvas are ignored.
+    pub fn set_pixel(&mut self, x: i32, y: i32, colour: u32) {
+        self.canvas.set_pixel(Point::new(x, y), colour);
+    }
+
+    // The ends are clamped to near the canvas, so a plugin can't make the
+    // host draw billions of pixels off it.
+    pub fn line(&mut self, from: (i32, i32), to: (i32, i32), colour: u32) {
+        let (width, height) = (self.canvas.width() as i32, self.canvas.height() as i32);
+        let clamp = |(x, y): (i32, i32)| {
+            Point::new(x.clamp(-width, 2 * width), y.clamp(-height, 2 * height))
+        };
+        self.canvas.line(clamp(from), clamp(to), colour);
+    }
+
+    pub fn clear_canvas(&mut self) {
+        self.canvas.clear();
+    }
+
+    pub fn log(&self, text: &str) {
+        log::info!("[{}] {}", self.current.as_deref().unwrap_or("plugin"), text);
+    }
+
+    fn calling(&self) -> RogueResult<String> {
+        self.current
+            .clone()
+            .ok_or_else(|| self.denied(String::from("called outside PluginHost::call()")))
+    }
+
+    // Stands in for the host while a runtime holds it
+    fn placeholder() -> Self {
+        PluginHost::new(PixelCanvas::new(PixelMode::HalfBlock, 0, 0))
+    }
+
+    fn denied(&self, message: String) -> RogueError {
+        RogueError::PluginDenied {
+            plugin: self
+                .current
+                .clone()
+                .unwrap_or_else(|| String::from("unknown")),
+            message,
+        }
+    }
+}
+
+//
+// WasmRuntime
+// Runs WebAssembly plugins, each in its own store with its own limits.
+//
+
+struct PluginState {
+    plugin: String,
+    // The host of the current call, moved here for the imports to use
+    host: Option<PluginHost>,
+    limits: StoreLimits,
+}
+
+struct WasmPlugin {
+    store: Store<PluginState>,
+    instance: Instance,
+}
+
+pub struct WasmRuntime {
+    engine: Engine,
+    linker: Linker<PluginState>,
+    plugins: HashMap<String, WasmPlugin>,
+    fuel: u64,
+    max_memory: usize,
+}
+
+impl WasmRuntime {
+    pub fn new() -> RogueResult<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| failed("mage", e))?;
+        let mut linker = Linker::new(&engine);
+        add_imports(&mut linker).map_err(|e| failed("mage", e))?;
+        Ok(WasmRuntime {
+            engine,
+            linker,
+            plugins: HashMap::new(),
+            fuel: DEFAULT_FUEL,
+            max_memory: DEFAULT_MAX_MEMORY,
+        })
+    }
+
+    // The fuel each call (and loading) can use, and the most memory, in
+    // bytes, a plugin can have.  Plugins already loaded keep their memory
+    // limit.
+    pub fn with_limits(&mut self, fuel: u64, max_memory: usize) -> &mut Self {
+        self.fuel = fuel;
+        self.max_memory = max_memory;
+        self
+    }
+}
+
+impl PluginRuntime for WasmRuntime {
+    // The module can be WebAssembly's binary or text format.  Loading a
+    // plugin again replaces it.
+    fn load(&mut self, plugin: &str, module: &[u8]) -> RogueResult<()> {
+        let module = Module::new(&self.engine, module).map_err(|e| failed(plugin, e))?;
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .table_elements(MAX_TABLE_ELEMENTS)
+            .instances(1)
+            .memories(1)
+            .tables(1)
+            .build();
+        let mut store = Store::new(
+            &self.engine,
+            PluginState {
+                plugin: String::from(plugin),
+                host: None,
+                limits,
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.fuel).map_err(|e| failed(plugin, e))?;
+        let instance = self
+            .linker
+            .instantiate(&mut store, &module)
+            .map_err(|e| failed(plugin, e))?;
+        self.plugins
+            .insert(String::from(plugin), WasmPlugin { store, instance });
+        Ok(())
+    }
+
+    fn unload(&mut self, plugin: &str) {
+        self.plugins.remove(plugin);
+    }
+
+    fn call(&mut self, plugin: &str, function: &str, host: &mut PluginHost) -> RogueResult<()> {
+        let fuel = self.fuel;
+        let loaded = self
+            .plugins
+            .get_mut(plugin)
+            .ok_or_else(|| RogueError::PluginFailed {
+                plugin: String::from(plugin),
+                message: String::from("it isn't loaded"),
+            })?;
+        let store = &mut loaded.store;
+        let function = loaded
+            .instance
+            .get_typed_func::<(), ()>(&mut *store, function)
+            .map_err(|e| failed(plugin, e))?;
+        store.set_fuel(fuel).map_err(|e| failed(plugin, e))?;
+
+        store.data_mut().host = Some(std::mem::replace(host, PluginHost::placeholder()));
+        let result = function.call(&mut *store, ());
+        if let Some(state) = store.data_mut().host.take() {
+            *host = state;
+        }
+        result.map_err(|e| failed(plugin, e))
+    }
+}
+
+// Errors from the host's calls are passed on as they are.
+fn failed(plugin: &str, error: wasmtime::Error) -> RogueError {
+    match error.downcast::<RogueError>() {
+        Ok(error) => error,
+        Err(error) => {
+            let message = match error.downcast_ref::<Trap>() {
+                Some(Trap::OutOfFuel) => String::from("it ran out of fuel"),
+                Some(trap) => trap.to_string(),
+                _ => format!("{:#}", error),
+            };
+            RogueError::PluginFailed {
+                plugin: String::from(plugin),
+                message,
+            }
+        }
+    }
+}
+
+//
+// Imports
+//
+
+fn add_imports(linker: &mut Linker<PluginState>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        "mage",
+        "read",
+        |mut caller: Caller<'_, PluginState>, name: u32, name_len: u32, buffer: u32, len: u32| {
+            let (memory, state) = memory(&mut caller)?;
+            let host = host(state)?;
+            let name = string(host, memory, name, name_len)?;
+            let data = host.read(&name)?;
+            let buffer = bytes_mut(host, memory, buffer, len)?;
+            let copied = data.len().min(buffer.len());
+            buffer[..copied].copy_from_slice(&data[..copied]);
+            Ok(data.len() as u32)
+        },
+    )?;
+    linker.func_wrap(
+        "mage",
+        "write",
+        |mut caller: Caller<'_, PluginState>, name: u32, name_len: u32, data: u32, len: u32| {
+            let (memory, state) = memory(&mut caller)?;
+            let host = host(state)?;
+            let name = string(host, memory, name, name_len)?;
+            let data = bytes(host, memory, data, len)?;
+            host.write(&name, data)?;
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "mage",
+        "emit",
+        |mut caller: Caller<'_, PluginState>, name: u32, name_len: u32, data: u32, len: u32| {
+            let (memory, state) = memory(&mut caller)?;
+            let host = host(state)?;
+            let name = string(host, memory, name, name_len)?;
+            let payload = bytes(host, memory, data, len)?;
+            host.emit(&name, payload)?;
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "mage",
+        "set_pixel",
+        |mut caller: Caller<'_, PluginState>, x: i32, y: i32, colour: u32| {
+            host(caller.data_mut())?.set_pixel(x, y, colour);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "mage",
+        "line",
+        |mut caller: Caller<'_, PluginState>, x0: i32, y0: i32, x1: i32, y1: i32, colour: u32| {
+            host(caller.data_mut())?.line((x0, y0), (x1, y1), colour);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "mage",
+        "clear_canvas",
+        |mut caller: Caller<'_, PluginState>| {
+            host(caller.data_mut())?.clear_canvas();
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "mage",
+        "log",
+        |mut caller: Caller<'_, PluginState>, text: u32, len: u32| {
+            let (memory, state) = memory(&mut caller)?;
+            let host = host(state)?;
+            let text = string(host, memory, text, len)?;
+            host.log(&text);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+// The plugin's memory and its state
+fn memory<'a>(
+    caller: &'a mut Caller<'_, PluginState>,
+) -> RogueResult<(&'a mut [u8], &'a mut PluginState)> {
+    match caller.get_export("memory").and_then(|e| e.into_memory()) {
+        Some(memory) => Ok(memory.data_and_store_mut(caller)),
+        None => Err(RogueError::PluginDenied {
+            plugin: caller.data().plugin.clone(),
+            message: String::from("it doesn't export its memory"),
+        }),
+    }
+}
+
+// Loading runs the plugin's start function, which has no host to call.
+fn host(state: &mut PluginState) -> RogueResult<&mut PluginHost> {
+    let plugin = &state.plugin;
+    state.host.as_mut().ok_or_else(|| RogueError::PluginDenied {
+        plugin: plugin.clone(),
+        message: String::from("called outside PluginHost::call()"),
+    })
+}
+
+fn range(host: &PluginHost, memory: &[u8], at: u32, len: u32) -> RogueResult<(usize, usize)> {
+    let (start, end) = (at as usize, at as usize + len as usize);
+    if end <= memory.len() {
+        Ok((start, end))
+    } else {
+        Err(host.denied(format!("{} bytes at {} is outside its memory", len, at)))
+    }
+}
+
+fn bytes<'a>(host: &PluginHost, memory: &'a [u8], at: u32, len: u32) -> RogueResult<&'a [u8]> {
+    let (start, end) = range(host, memory, at, len)?;
+    Ok(&memory[start..end])
+}
+
+fn bytes_mut<'a>(
+    host: &PluginHost,
+    memory: &'a mut [u8],
+    at: u32,
+    len: u32,
+) -> RogueResult<&'a mut [u8]> {
+    let (start, end) = range(host, memory, at, len)?;
+    Ok(&mut memory[start..end])
+}
+
+fn string(host: &PluginHost, memory: &[u8], at: u32, len: u32) -> RogueResult<String> {
+    let bytes = bytes(host, memory, at, len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| host.denied(String::from("a string isn't UTF-8")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUGIN: &str = r#"
+        (module
+            (import "mage" "read" (func $read (param i32 i32 i32 i32) (result i32)))
+            (import "mage" "write" (func $write (param i32 i32 i32 i32)))
+            (import "mage" "emit" (func $emit (param i32 i32 i32 i32)))
+            (import "mage" "set_pixel" (func $set_pixel (param i32 i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "player")
+            (data (i32.const 16) "moved")
+            (data (i32.const 32) "score")
+            (func (export "on_turn")
+                (call $emit (i32.const 16) (i32.const 5) (i32.const 64)
+                    (call $read (i32.const 0) (i32.const 6) (i32.const 64) (i32.const 16)))
+                (call $set_pixel (i32.const 1) (i32.const 2) (i32.const 7)))
+            (func (export "score")
+                (call $write (i32.const 32) (i32.const 5) (i32.const 64) (i32.const 2)))
+            (func (export "cheat")
+                (call $write (i32.const 0) (i32.const 6) (i32.const 64) (i32.const 2)))
+            (func (export "wild")
+                (call $emit (i32.const 16) (i32.const 5) (i32.const 65500) (i32.const 100)))
+            (func (export "spin")
+                (loop (br 0)))
+            (func (export "grow")
+                (if (i32.eq (memory.grow (i32.const 2)) (i32.const -1))
+                    (then unreachable))))
+    "#;
+
+    fn setup() -> (WasmRuntime, PluginHost) {
+        let mut runtime = WasmRuntime::new().unwrap();
+        runtime.with_limits(100_000, 4 * 65536);
+        runtime.load("test", PLUGIN.as_bytes()).unwrap();
+        let mut host = PluginHost::new(PixelCanvas::new(PixelMode::HalfBlock, 4, 4));
+        host.register_data("player", PluginAccess::Read, b"at 3,4");
+        host.register_data("score", PluginAccess::ReadWrite, b"");
+        (runtime, host)
+    }
+
+    fn denied(result: RogueResult<()>) -> bool {
+        matches!(result, Err(RogueError::PluginDenied { .. }))
+    }
+
+    fn failed(result: RogueResult<()>) -> bool {
+        matches!(result, Err(RogueError::PluginFailed { .. }))
+    }
+
+    #[test]
+    fn plugins_use_the_host() {
+        let (mut runtime, mut host) = setup();
+        host.call(&mut runtime, "test", "on_turn").unwrap();
+        assert_eq!(
+            host.take_events(),
+            vec![PluginEvent {
+                plugin: String::from("test"),
+                name: String::from("moved"),
+                payload: b"at 3,4".to_vec(),
+            }]
+        );
+        assert_eq!(host.canvas().pixel(Point::new(1, 2)), Some(7));
+
+        host.call(&mut runtime, "test", "score").unwrap();
+        assert_eq!(host.data("score"), Some(&b"at"[..]));
+    }
+
+    #[test]
+    fn plugins_are_checked() {
+        let (mut runtime, mut host) = setup();
+        assert!(denied(host.call(&mut runtime, "test", "cheat")));
+        assert!(denied(host.call(&mut runtime, "test", "wild")));
+        assert_eq!(host.data("player"), Some(&b"at 3,4"[..]));
+        assert!(host.take_events().is_empty());
+
+        host.with_limits(64, 0);
+        assert!(denied(host.call(&mut runtime, "test", "on_turn")));
+        // The host is handed back after a failed call
+        host.with_limits(64, 1);
+        host.call(&mut runtime, "test", "on_turn").unwrap();
+    }
+
+    #[test]
+    fn plugins_are_limited() {
+        let (mut runtime, mut host) = setup();
+        match host.call(&mut runtime, "test", "spin") {
+            Err(RogueError::PluginFailed { message, .. }) => assert!(message.contains("fuel")),
+            _ => panic!("the plugin should have run out of fuel"),
+        }
+        // Within the memory limit once, but not twice
+        host.call(&mut runtime, "test", "grow").unwrap();
+        assert!(failed(host.call(&mut runtime, "test", "grow")));
+
+        assert!(failed(host.call(&mut runtime, "test", "missing")));
+        assert!(failed(host.call(&mut runtime, "other", "on_turn")));
+        assert!(failed(runtime.load("bad", b"not a module")));
+        runtime.unload("test");
+        assert!(failed(host.call(&mut runtime, "test", "on_turn")));
+    }
+}
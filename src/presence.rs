@@ -0,0 +1,436 @@
+//
+// Rich presence
+//
+// Shows what the player is doing on their profile in services like Discord,
+// e.g. "Wizard, depth 5" and "Fighting a dragon", enabled with the "presence"
+// feature.  The game sets a backend once and then the presence whenever it
+// changes:
+//
+//      ctx.set_presence_backend(DiscordPresence::new("<application id>"));
+//      ctx.set_presence(Presence { depth: Some(5), ..presence });
+//
+// The engine sends changes to the backend at most every few seconds, as the
+// services limit how often presence can change, and clears the presence when
+// the game exits.  Other services, such as Steam, are added by implementing
+// PresenceBackend.
+//
+
+use crate::{RogueError, RogueResult};
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// How often the engine sends changes to the backend
+const UPDATE_INTERVAL: Duration = Duration::from_secs(4);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Presence {
+    pub character: Option<String>,
+    pub depth: Option<u32>,
+    pub status: Option<String>,
+    // When the run started, shown as the time played
+    pub started: Option<SystemTime>,
+    // The name of an image uploaded to the service
+    pub image: Option<String>,
+}
+
+impl Presence {
+    // The character and depth as one line, e.g. "Wizard, depth 5"
+    pub fn summary(&self) -> Option<String> {
+        match (&self.character, self.depth) {
+            (Some(character), Some(depth)) => Some(format!("{}, depth {}", character, depth)),
+            (Some(character), None) => Some(character.clone()),
+            (None, Some(depth)) => Some(format!("Depth {}", depth)),
+            (None, None) => None,
+        }
+    }
+}
+
+pub trait PresenceBackend {
+    fn set(&mut self, presence: &Presence) -> RogueResult<()>;
+    fn clear(&mut self) -> RogueResult<()>;
+}
+
+//
+// PresenceState
+// The backend and the presence waiting to be sent, kept in the context.
+//
+
+pub(crate) struct PresenceState {
+    backend: Option<Box<dyn PresenceBackend>>,
+    presence: Option<Presence>,
+    changed: bool,
+    last_sent: Option<Instant>,
+}
+
+impl PresenceState {
+    pub fn new() -> Self {
+        PresenceState {
+            backend: None,
+            presence: None,
+            changed: false,
+            last_sent: None,
+        }
+    }
+
+    pub fn set_backend(&mut self, backend: Box<dyn PresenceBackend>) {
+        self.backend = Some(backend);
+        self.changed = true;
+    }
+
+    pub fn presence(&self) -> Option<&Presence> {
+        self.presence.as_ref()
+    }
+
+    pub fn set(&mut self, presence: Option<Presence>) {
+        if presence != self.presence {
+            self.presence = presence;
+            self.changed = true;
+        }
+    }
+
+    // Called by the engine each tick.
+    pub fn update(&mut self) {
+        let due = self
+            .last_sent
+            .is_none_or(|last| last.elapsed() >= UPDATE_INTERVAL);
+        let backend = match &mut self.backend {
+            Some(backend) if self.changed && due => backend,
+            _ => return,
+        };
+        self.changed = false;
+        self.last_sent = Some(Instant::now());
+        let result = match &self.presence {
+            Some(presence) => backend.set(presence),
+            None => backend.clear(),
+        };
+        if let Err(e) = result {
+            log::warn!("Unable to update presence: {}", e);
+        }
+    }
+
+    // Called by the engine on exit.
+    pub fn shut_down(&mut self) {
+        if let Some(backend) = &mut self.backend {
+            if let Err(e) = backend.clear() {
+                log::warn!("Unable to clear presence: {}", e);
+            }
+        }
+    }
+}
+
+//
+// Discord
+// Talks to the Discord client running on the same machine over its IPC
+// socket.  Each message is an opcode and a JSON payload, each preceded by
+// their length.
+//
+
+const OP_HANDSHAKE: u32 = 0;
+const OP_FRAME: u32 = 1;
+
+trait IpcStream: Read + Write {}
+impl<T: Read + Write> IpcStream for T {}
+
+pub struct DiscordPresence {
+    client_id: String,
+    stream: Option<Box<dyn IpcStream>>,
+    nonce: u64,
+}
+
+impl DiscordPresence {
+    // The client id is the application id from Discord's developer portal.
+    // The connection is made when the presence is first set, and again if
+    // Discord is restarted.
+    pub fn new(client_id: &str) -> Self {
+        DiscordPresence {
+            client_id: String::from(client_id),
+            stream: None,
+            nonce: 0,
+        }
+    }
+
+    #[cfg(unix)]
+    fn open() -> io::Result<Box<dyn IpcStream>> {
+        use std::os::unix::net::UnixStream;
+        let dir = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok())
+            .unwrap_or_else(|| String::from("/tmp"));
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "Discord is not running");
+        for i in 0..10 {
+            match UnixStream::connect(format!("{}/discord-ipc-{}", dir, i)) {
+                Ok(stream) => {
+                    // So a Discord that has stopped answering can't hang the game
+                    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+                    return Ok(Box::new(stream));
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    #[cfg(windows)]
+    fn open() -> io::Result<Box<dyn IpcStream>> {
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "Discord is not running");
+        for i in 0..10 {
+            let pipe = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(format!(r"\\?\pipe\discord-ipc-{}", i));
+            match pipe {
+                Ok(pipe) => return Ok(Box::new(pipe)),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn open() -> io::Result<Box<dyn IpcStream>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Discord presence is not supported on this platform",
+        ))
+    }
+
+    fn write_frame(stream: &mut dyn IpcStream, opcode: u32, json: &str) -> io::Result<()> {
+        let mut frame = opcode.to_le_bytes().to_vec();
+        frame.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        frame.extend_from_slice(json.as_bytes());
+        stream.write_all(&frame)
+    }
+
+    // Returns the reply's opcode and JSON payload.
+    fn read_frame(stream: &mut dyn IpcStream) -> io::Result<(u32, String)> {
+        let mut header = [0; 8];
+        stream.read_exact(&mut header)?;
+        let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let mut payload = vec![0; len as usize];
+        stream.read_exact(&mut payload)?;
+        Ok((opcode, String::from_utf8_lossy(&payload).into_owned()))
+    }
+
+    // Sends a command, connecting first if needed, and waits for the reply.
+    fn send(&mut self, args: &str) -> RogueResult<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let mut stream = Self::open()?;
+                let handshake =
+                    format!(r#"{{"v":1,"client_id":{}}}"#, json_string(&self.client_id));
+                Self::write_frame(stream.as_mut(), OP_HANDSHAKE, &handshake)?;
+                Self::read_frame(stream.as_mut())?;
+                self.stream.insert(stream)
+            }
+        };
+
+        self.nonce += 1;
+        let command = format!(
+            r#"{{"cmd":"SET_ACTIVITY","args":{},"nonce":"{}"}}"#,
+            args, self.nonce
+        );
+        let result = Self::write_frame(stream.as_mut(), OP_FRAME, &command)
+            .and_then(|_| Self::read_frame(stream.as_mut()));
+        match result {
+            Ok((_, reply)) if reply.contains(r#""evt":"ERROR""#) => {
+                Err(RogueError::BadMessage(format!("Discord replied {}", reply)))
+            }
+            Ok(_) => Ok(()),
+            Err(e) => {
+                // Connect again next time, in case Discord was restarted
+                self.stream = None;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+impl PresenceBackend for DiscordPresence {
+    fn set(&mut self, presence: &Presence) -> RogueResult<()> {
+        let mut fields = Vec::new();
+        if let Some(summary) = presence.summary() {
+            fields.push(format!(r#""details":{}"#, json_string(&summary)));
+        }
+        if let Some(status) = &presence.status {
+            fields.push(format!(r#""state":{}"#, json_string(status)));
+        }
+        if let Some(started) = presence.started {
+            let seconds = started
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs());
+            fields.push(format!(r#""timestamps":{{"start":{}}}"#, seconds));
+        }
+        if let Some(image) = &presence.image {
+            fields.push(format!(
+                r#""assets":{{"large_image":{}}}"#,
+                json_string(image)
+            ));
+        }
+        let args = format!(
+            r#"{{"pid":{},"activity":{{{}}}}}"#,
+            std::process::id(),
+            fields.join(",")
+        );
+        self.send(&args)
+    }
+
+    fn clear(&mut self) -> RogueResult<()> {
+        if self.stream.is_none() {
+            return Ok(());
+        }
+        self.send(&format!(r#"{{"pid":{}}}"#, std::process::id()))
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            ch if (ch as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    // Records what it is asked to show
+    struct Recorder(Rc<RefCell<Vec<Option<String>>>>);
+
+    impl PresenceBackend for Recorder {
+        fn set(&mut self, presence: &Presence) -> RogueResult<()> {
+            self.0.borrow_mut().push(presence.summary());
+            Ok(())
+        }
+
+        fn clear(&mut self) -> RogueResult<()> {
+            self.0.borrow_mut().push(None);
+            Ok(())
+        }
+    }
+
+    // Plays back Discord's replies and keeps what was written
+    struct FakeDiscord {
+        replies: io::Cursor<Vec<u8>>,
+        written: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Read for FakeDiscord {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for FakeDiscord {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(opcode: u32, json: &str) -> Vec<u8> {
+        let mut frame = opcode.to_le_bytes().to_vec();
+        frame.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        frame.extend_from_slice(json.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn summaries_join_the_character_and_depth() {
+        let mut presence = Presence::default();
+        assert_eq!(presence.summary(), None);
+        presence.depth = Some(5);
+        assert_eq!(presence.summary().unwrap(), "Depth 5");
+        presence.character = Some(String::from("Wizard"));
+        assert_eq!(presence.summary().unwrap(), "Wizard, depth 5");
+        presence.depth = None;
+        assert_eq!(presence.summary().unwrap(), "Wizard");
+    }
+
+    #[test]
+    fn changes_are_sent_at_most_every_interval() {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let mut state = PresenceState::new();
+        let wizard = Presence {
+            character: Some(String::from("Wizard")),
+            ..Presence::default()
+        };
+        state.set(Some(wizard.clone()));
+        state.update();
+        assert!(sent.borrow().is_empty());
+
+        state.set_backend(Box::new(Recorder(sent.clone())));
+        state.update();
+        assert_eq!(*sent.borrow(), [Some(String::from("Wizard"))]);
+
+        // Too soon after the last one, so it waits
+        state.set(Some(Presence {
+            depth: Some(2),
+            ..wizard
+        }));
+        state.update();
+        assert_eq!(sent.borrow().len(), 1);
+        state.last_sent = Some(Instant::now() - UPDATE_INTERVAL);
+        state.update();
+        state.update();
+        assert_eq!(sent.borrow()[1..], [Some(String::from("Wizard, depth 2"))]);
+        assert_eq!(state.presence().unwrap().depth, Some(2));
+
+        state.shut_down();
+        assert_eq!(sent.borrow()[2], None);
+    }
+
+    #[test]
+    fn discord_is_sent_set_activity_commands() {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut replies = frame(OP_FRAME, r#"{"evt":null}"#);
+        replies.extend(frame(OP_FRAME, r#"{"evt":"ERROR"}"#));
+        let mut discord = DiscordPresence::new("123");
+        discord.stream = Some(Box::new(FakeDiscord {
+            replies: io::Cursor::new(replies),
+            written: written.clone(),
+        }));
+
+        let presence = Presence {
+            character: Some(String::from("Wiz \"the\" ard")),
+            status: Some(String::from("Fighting")),
+            started: Some(UNIX_EPOCH + Duration::from_secs(100)),
+            ..Presence::default()
+        };
+        discord.set(&presence).unwrap();
+        let command = format!(
+            concat!(
+                r#"{{"cmd":"SET_ACTIVITY","args":{{"pid":{},"activity":{{"#,
+                r#""details":"Wiz \"the\" ard","state":"Fighting","timestamps":{{"start":100}}"#,
+                r#"}}}},"nonce":"1"}}"#
+            ),
+            std::process::id()
+        );
+        assert_eq!(*written.borrow(), frame(OP_FRAME, &command));
+
+        assert!(discord.clear().is_err());
+        // Running out of replies drops the connection, to make it again
+        assert!(discord.clear().is_err());
+        assert!(discord.stream.is_none());
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}é"), r#""a\"b\\c\nd\u0001é""#);
+    }
+}
@@ -6,7 +6,10 @@
 // Copyright (C)2021 Matt Davies, all rights reserved.
 //
 
-use std::cmp::min;
+use crate::{tint_colour, Glyphs};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{cmp::min, mem::replace};
 
 //
 // PresentInput
@@ -16,22 +19,17 @@ use std::cmp::min;
 pub struct PresentInput<'a> {
     pub width: u32,
     pub height: u32,
-    pub fore_image: &'a mut Vec<u32>,
-    pub back_image: &'a mut Vec<u32>,
-    pub text_image: &'a mut Vec<u32>,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    pub scale_factor: f64,
+    pub image: &'a mut Image,
 }
 
 impl<'a> PresentInput<'a> {
     pub fn blit(&mut self, p: Point, dst_width: u32, dst_height: u32, image: &Image) {
-        let blitops = BlitOps {
-            src: BlitRect::new(0, 0, image.width, image.height),
-            dst: BlitRect::new(0, 0, self.width, self.height),
-            src_blit: BlitRect::new(0, 0, image.width, image.height),
-            dst_blit: BlitRect::new(p.x, p.y, dst_width, dst_height),
-        };
-        blit(&image.fore_image, &mut self.fore_image, &blitops);
-        blit(&image.back_image, &mut self.back_image, &blitops);
-        blit(&image.text_image, &mut self.text_image, &blitops);
+        self.image.blit(p, dst_width, dst_height, image);
     }
 }
 
@@ -40,7 +38,8 @@ impl<'a> PresentInput<'a> {
 // An X, Y coordinate
 //
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Point {
     pub x: i32,
     pub y: i32,
@@ -50,6 +49,44 @@ impl Point {
     pub fn new(x: i32, y: i32) -> Self {
         Point { x, y }
     }
+
+    // Moves the point, stopping at the limits of i32 rather than overflowing,
+    // so drawing near them clips instead of panicking.
+    pub fn offset(&self, dx: i64, dy: i64) -> Point {
+        let add = |a: i32, d: i64| (a as i64 + d).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        Point::new(add(self.x, dx), add(self.y, dy))
+    }
+}
+
+//
+// Rect
+// A rectangle of cells with its top-left corner at (x, y)
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        p.x >= self.x
+            && p.y >= self.y
+            && (p.x as i64) < self.x as i64 + self.width as i64
+            && (p.y as i64) < self.y as i64 + self.height as i64
+    }
 }
 
 //
@@ -71,16 +108,111 @@ impl Char {
 }
 
 //
-// RogueImage
-// This represents a rectangular collection of RogueChars to render sprites and screens.
+// Code page 437
+// The font follows the layout of the original IBM PC character set.  These
+// convert between character codes and the Unicode characters they look like,
+// for exporting screens as text and drawing translated strings.
 //
 
+const CP437_LOW: &str = "☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const CP437_HIGH: [&str; 4] = [
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■ ",
+];
+
+pub fn cp437_to_char(code: u8) -> char {
+    match code {
+        0 => ' ',
+        1..=31 => CP437_LOW.chars().nth(code as usize - 1).unwrap_or(' '),
+        127 => '⌂',
+        128..=255 => {
+            let i = code as usize - 128;
+            CP437_HIGH[i / 32].chars().nth(i % 32).unwrap_or(' ')
+        }
+        _ => code as char,
+    }
+}
+
+// Characters the font doesn't have are drawn as '?'.
+pub fn char_to_cp437(ch: char) -> u8 {
+    char_to_cp437_code(ch).unwrap_or(b'?')
+}
+
+pub fn char_to_cp437_code(ch: char) -> Option<u8> {
+    match ch {
+        ' '..='~' => Some(ch as u8),
+        '⌂' => Some(127),
+        _ => CP437_HIGH
+            .iter()
+            .flat_map(|row| row.chars())
+            .position(|c| c == ch)
+            .map(|i| i as u8 + 128)
+            .or_else(|| CP437_LOW.chars().position(|c| c == ch).map(|i| i as u8 + 1)),
+    }
+}
+
+//
+// Wide characters
+// CJK text and some symbols are drawn two cells wide.  A font can include
+// such glyphs by splitting each into two neighbouring slots, left half first,
+// and registering the character with Glyphs::register_wide_glyph() on the
+// font's table.  Other wide characters are drawn with their single-cell
+// glyph, or '?', followed by a blank cell.
+//
+// The right-hand cell of a wide character has WIDE_CONTINUATION set in the
+// text image, above the glyph code the shader uses, so that drawing over
+// either half can blank the other instead of leaving half a glyph behind.
+//
+
+pub const WIDE_CONTINUATION: u32 = 0x0100_0000;
+
+// The number of cells a character takes up: 2 for the East Asian wide and
+// fullwidth ranges, otherwise 1.
+pub fn char_width(ch: char) -> u32 {
+    match ch as u32 {
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+pub fn text_width(text: &str) -> u32 {
+    text.chars().map(char_width).sum()
+}
+
+//
+// Image
+// This represents a rectangular collection of Chars to render sprites and screens.
+// The engine hands the game an Image covering the whole window via PresentInput.
+//
+// Codes above 255 in the text image are fallback glyphs from the image's
+// Glyphs table.  The window's image shares its font's table; a new image has
+// a table of its own, which has no fallback fonts, until set_glyphs() is
+// called.
+//
+
+#[derive(Clone)]
 pub struct Image {
     pub width: u32,
     pub height: u32,
     pub fore_image: Vec<u32>,
     pub back_image: Vec<u32>,
     pub text_image: Vec<u32>,
+    glyphs: Glyphs,
 }
 
 impl Image {
@@ -92,9 +224,45 @@ impl Image {
             fore_image: vec![0; size],
             back_image: vec![0; size],
             text_image: vec![0; size],
+            glyphs: Glyphs::new(),
         }
     }
 
+    pub fn glyphs(&self) -> &Glyphs {
+        &self.glyphs
+    }
+
+    // Gives the image another table, e.g. the one of the image it will be
+    // blitted to, so drawing into it can use that font's fallback glyphs.
+    // Fallback glyphs already drawn are looked up again in the new table.
+    pub fn set_glyphs(&mut self, glyphs: &Glyphs) {
+        if !self.glyphs.is_shared_with(glyphs) {
+            let old = replace(&mut self.glyphs, glyphs.clone());
+            for code in self.text_image.iter_mut() {
+                *code = translate_code(*code, &old, glyphs);
+            }
+        }
+    }
+
+    // The code to draw a character with.  See Glyphs::code().
+    pub fn glyph_code(&self, ch: char) -> Option<u32> {
+        self.glyphs.code(ch)
+    }
+
+    // The character drawn with a code from the text image, ignoring the
+    // flags above the code.
+    pub fn glyph_char(&self, code: u32) -> char {
+        self.glyphs.char(code & 0xffff)
+    }
+
+    // Bytes used by the cells
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + 4 * (self.fore_image.capacity()
+                + self.back_image.capacity()
+                + self.text_image.capacity())
+    }
+
     pub fn coords_to_index(&self, x: u32, y: u32) -> Option<usize> {
         if x < self.width && y < self.height {
             Some((y * self.width + x) as usize)
@@ -103,23 +271,16 @@ impl Image {
         }
     }
 
+    // The part of a rectangle inside the image.  A rectangle entirely outside
+    // gets a width or height of 0.
     pub fn clip(&self, p: Point, width: u32, height: u32) -> (u32, u32, u32, u32) {
-        let mut x = p.x;
-        let mut y = p.y;
-        let mut width = width;
-        let mut height = height;
-        if x < 0 {
-            width += x as u32;
-            x = 0;
-        }
-        if y < 0 {
-            height += y as u32;
-            y = 0;
-        }
-        let x = x as u32;
-        let y = y as u32;
-        width = min(width, self.width - x);
-        height = min(height, self.height - y);
+        let axis = |start: i32, length: u32, size: u32| {
+            let end = (start as i64 + length as i64).min(size as i64);
+            let start = (start as i64).clamp(0, size as i64);
+            (start as u32, (end - start).max(0) as u32)
+        };
+        let (x, width) = axis(p.x, width, self.width);
+        let (y, height) = axis(p.y, height, self.height);
 
         (x, y, width, height)
     }
@@ -134,28 +295,78 @@ impl Image {
     }
 
     pub fn draw_char(&mut self, p: Point, ch: Char) {
+        self.draw_glyph(p, ch.ch as u32, ch.ink, ch.paper);
+    }
+
+    // Like draw_char(), but for any glyph code, including those of fallback
+    // fonts.  See glyph_code().
+    pub fn draw_glyph(&mut self, p: Point, code: u32, ink: u32, paper: u32) {
         if p.x >= 0 && p.y >= 0 {
             if let Some(i) = self.coords_to_index(p.x as u32, p.y as u32) {
-                self.fore_image[i] = ch.ink;
-                self.back_image[i] = ch.paper;
-                self.text_image[i] = ch.ch as u32;
+                self.split_wide(p.x as u32, p.y as u32);
+                self.fore_image[i] = ink;
+                self.back_image[i] = paper;
+                self.text_image[i] = code;
+            }
+        }
+    }
+
+    // Draws a double-width glyph whose halves are at code and code + 1 in the
+    // font.  See Glyphs::register_wide_glyph().
+    pub fn draw_wide_char(&mut self, p: Point, code: u8, ink: u32, paper: u32) {
+        self.draw_wide_pair(p, code as u32, code as u32 + 1, ink, paper);
+    }
+
+    fn draw_wide_pair(&mut self, p: Point, left: u32, right: u32, ink: u32, paper: u32) {
+        let right_p = p.offset(1, 0);
+        self.draw_glyph(p, left, ink, paper);
+        self.draw_glyph(right_p, right, ink, paper);
+        if right_p.x >= 0 && right_p.y >= 0 {
+            if let Some(i) = self.coords_to_index(right_p.x as u32, right_p.y as u32) {
+                self.text_image[i] |= WIDE_CONTINUATION;
+            }
+        }
+    }
+
+    // Blanks the other half of a wide character that is about to be partly
+    // drawn over.
+    fn split_wide(&mut self, x: u32, y: u32) {
+        let is_continuation = |image: &Self, x: u32| {
+            image
+                .coords_to_index(x, y)
+                .is_some_and(|i| image.text_image[i] & WIDE_CONTINUATION != 0)
+        };
+        if is_continuation(self, x) && x > 0 {
+            if let Some(i) = self.coords_to_index(x - 1, y) {
+                self.text_image[i] = b' ' as u32;
+            }
+        }
+        if is_continuation(self, x + 1) {
+            if let Some(i) = self.coords_to_index(x + 1, y) {
+                self.text_image[i] = b' ' as u32;
             }
         }
     }
 
+    // Wide characters take two cells.  See text_width().
     pub fn draw_string(&mut self, p: Point, text: &str, ink: u32, paper: u32) {
-        let (x, y, w, _) = self.clip(p, text.len() as u32, 1);
-
-        if let Some(i) = self.coords_to_index(x, y) {
-            let w = w as usize;
-            self.fore_image[i..i + w].iter_mut().for_each(|x| *x = ink);
-            self.back_image[i..i + w]
-                .iter_mut()
-                .for_each(|x| *x = paper);
-            self.text_image[i..i + w]
-                .iter_mut()
-                .enumerate()
-                .for_each(|(j, x)| *x = (text.as_bytes()[j]) as u32);
+        let mut x = p.x;
+        for ch in text.chars() {
+            if x >= self.width as i32 {
+                break;
+            }
+            let q = Point::new(x, p.y);
+            let code = self.glyph_code(ch).unwrap_or(b'?' as u32);
+            if char_width(ch) == 2 {
+                match self.glyphs.wide_glyph(ch) {
+                    Some(code) => self.draw_wide_char(q, code, ink, paper),
+                    None => self.draw_wide_pair(q, code, b' ' as u32, ink, paper),
+                }
+                x += 2;
+            } else {
+                self.draw_glyph(q, code, ink, paper);
+                x += 1;
+            }
         }
     }
 
@@ -166,16 +377,11 @@ impl Image {
             // Draw top
             self.draw_rect_filled(p, width, 1, ch);
             // Draw bottom
-            self.draw_rect_filled(Point::new(p.x, p.y + (height as i32) - 1), width, 1, ch);
+            self.draw_rect_filled(p.offset(0, height as i64 - 1), width, 1, ch);
             // Draw left
-            self.draw_rect_filled(Point::new(p.x, p.y + 1), 1, height - 2, ch);
+            self.draw_rect_filled(p.offset(0, 1), 1, height - 2, ch);
             // Draw right
-            self.draw_rect_filled(
-                Point::new(p.x + (width as i32) - 1, p.y + 1),
-                1,
-                height - 2,
-                ch,
-            );
+            self.draw_rect_filled(p.offset(width as i64 - 1, 1), 1, height - 2, ch);
         }
     }
 
@@ -183,6 +389,12 @@ impl Image {
         // Clip the coords and size to the image
         let (x, y, width, height) = self.clip(p, width, height);
 
+        if width > 0 {
+            for row in y..y + height {
+                self.split_wide(x, row);
+                self.split_wide(x + width - 1, row);
+            }
+        }
         if let Some(mut i) = self.coords_to_index(x, y) {
             let width = width as usize;
             (0..height).for_each(|_| {
@@ -201,26 +413,78 @@ impl Image {
             });
         }
     }
+
+    // Multiplies the ink and paper of a rectangle by a colour.
+    pub fn tint(&mut self, p: Point, width: u32, height: u32, colour: u32) {
+        let (x, y, width, height) = self.clip(p, width, height);
+
+        if let Some(mut i) = self.coords_to_index(x, y) {
+            let width = width as usize;
+            (0..height).for_each(|_| {
+                self.fore_image[i..i + width]
+                    .iter_mut()
+                    .for_each(|x| *x = tint_colour(*x, colour));
+                self.back_image[i..i + width]
+                    .iter_mut()
+                    .for_each(|x| *x = tint_colour(*x, colour));
+
+                i += self.width as usize;
+            });
+        }
+    }
+
+    pub fn blit(&mut self, p: Point, dst_width: u32, dst_height: u32, image: &Image) {
+        let blitops = BlitOps {
+            src: BlitRect::new(0, 0, image.width, image.height),
+            dst: BlitRect::new(0, 0, self.width, self.height),
+            src_blit: BlitRect::new(0, 0, image.width, image.height),
+            dst_blit: BlitRect::new(p.x, p.y, dst_width, dst_height),
+        };
+        blit(&image.fore_image, &mut self.fore_image, &blitops);
+        blit(&image.back_image, &mut self.back_image, &blitops);
+        let has_fallbacks = || image.text_image.iter().any(|&code| code & 0xffff > 255);
+        if image.glyphs.is_shared_with(&self.glyphs) || !has_fallbacks() {
+            blit(&image.text_image, &mut self.text_image, &blitops);
+        } else {
+            let text = image
+                .text_image
+                .iter()
+                .map(|&code| translate_code(code, &image.glyphs, &self.glyphs))
+                .collect::<Vec<_>>();
+            blit(&text, &mut self.text_image, &blitops);
+        }
+    }
 }
 
 //
 // Blitting
 //
 
+// The code for the same character in another table, keeping the flags above
+// the code.  Characters the other table has no glyph for become '?'.
+fn translate_code(code: u32, from: &Glyphs, to: &Glyphs) -> u32 {
+    let glyph = code & 0xffff;
+    if glyph < 256 {
+        return code;
+    }
+    let glyph = to.code(from.char(glyph)).unwrap_or(b'?' as u32);
+    (code & !0xffff) | glyph
+}
+
 struct BlitRect {
-    x: i32,
-    y: i32,
-    w: i32,
-    h: i32,
+    x: i64,
+    y: i64,
+    w: i64,
+    h: i64,
 }
 
 impl BlitRect {
     fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
         BlitRect {
-            x,
-            y,
-            w: width as i32,
-            h: height as i32,
+            x: x as i64,
+            y: y as i64,
+            w: width as i64,
+            h: height as i64,
         }
     }
 }
@@ -232,46 +496,47 @@ struct BlitOps {
     dst_blit: BlitRect, // Rectangle to blit to within dst rectangle
 }
 
-fn blit<T>(src: &Vec<T>, dst: &mut Vec<T>, ops: &BlitOps)
+// Clips one axis of a blit, returning the source start, destination start
+// and length.  The length is 0 or less if nothing is left.
+fn clip_blit_axis(
+    (mut s, mut d): (i64, i64),
+    length: i64,
+    src_size: i64,
+    dst_size: i64,
+) -> (i64, i64, i64) {
+    let mut length = length;
+    // Clip to the left (or top) edges, moving both rectangles together
+    if s < 0 {
+        d -= s;
+        length += s;
+        s = 0;
+    }
+    if d < 0 {
+        s -= d;
+        length += d;
+        d = 0;
+    }
+    // Clip to the right (or bottom) edges
+    length = length.min(src_size - s).min(dst_size - d);
+    (s, d, length)
+}
+
+fn blit<T>(src: &[T], dst: &mut [T], ops: &BlitOps)
 where
     T: Copy,
 {
-    let mut sx = ops.src_blit.x;
-    let mut sy = ops.src_blit.y;
-    let mut sw = ops.src_blit.w;
-    let mut sh = ops.src_blit.h;
-    let mut dx = ops.dst_blit.x;
-    let mut dy = ops.dst_blit.y;
-    let mut dw = ops.dst_blit.w;
-    let mut dh = ops.dst_blit.h;
-
-    // If the source blit area is before the full area, we need to clip to the
-    // edge of the full area.
-    if sx < 0 {
-        sw += dx; // Shrink source
-        dw += dx; // Shrink destination
-        dx -= dx; // Shift the origin to the right
-        sx = 0;
-    }
-    // If the source blit area is over the right edge of the full area, we need
-    // to clip to the right edge of the full area.
-    if sx + sw > ops.src.w {
-        sw = ops.src.w - sx;
-    }
-    // Clip the source to the destination
-    let width = min(sw, dw);
-
-    // Now do the same for the Y axis
-    if sy < 0 {
-        sh += dy; // Shrink source
-        dh += dy; // Shrink destination
-        dy -= dy; // Shift the origin to the right
-        sy = 0;
-    }
-    if sy + sh > ops.src.h {
-        sh = ops.src.h - sy;
-    }
-    let height = min(sh, dh);
+    let (sx, dx, width) = clip_blit_axis(
+        (ops.src_blit.x, ops.dst_blit.x),
+        min(ops.src_blit.w, ops.dst_blit.w),
+        ops.src.w,
+        ops.dst.w,
+    );
+    let (sy, dy, height) = clip_blit_axis(
+        (ops.src_blit.y, ops.dst_blit.y),
+        min(ops.src_blit.h, ops.dst_blit.h),
+        ops.src.h,
+        ops.dst.h,
+    );
 
     if width > 0 && height > 0 {
         // Now we copy source into destination
@@ -289,3 +554,255 @@ where
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_GLYPHS;
+    use proptest::prelude::*;
+
+    fn text(image: &Image) -> String {
+        image
+            .text_image
+            .iter()
+            .map(|&code| match code & WIDE_CONTINUATION {
+                0 => image.glyph_char(code),
+                _ => '+',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn filling_over_half_a_wide_character_blanks_the_other_half() {
+        let mut image = Image::new(6, 1);
+        image.clear(0, 0);
+        image.draw_string(Point::new(0, 0), "日日日", 1, 0);
+        assert_eq!(text(&image), "?+?+?+");
+
+        // Covers the right half of the first and the left half of the last
+        image.draw_rect_filled(Point::new(1, 0), 4, 1, Char::new(b'#', 1, 0));
+        assert_eq!(text(&image), " #### ");
+    }
+
+    //
+    // Clipping
+    // Every drawing function is given coordinates and sizes anywhere in the
+    // range of their types, and must only change the cells it was asked to.
+    //
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Clear,
+        DrawChar(Point),
+        DrawGlyph(Point, u32),
+        DrawWideChar(Point),
+        DrawString(Point, String),
+        DrawRect(Point, u32, u32),
+        DrawRectFilled(Point, u32, u32),
+        Tint(Point, u32, u32),
+        Blit(Point, u32, u32, (u32, u32)),
+        PresentBlit(Point, u32, u32, (u32, u32)),
+    }
+
+    fn coord() -> impl Strategy<Value = i32> {
+        prop_oneof![
+            Just(i32::MIN),
+            Just(i32::MAX),
+            Just(i32::MIN + 1),
+            Just(i32::MAX - 1),
+            -12..12i32,
+            any::<i32>(),
+        ]
+    }
+
+    fn length() -> impl Strategy<Value = u32> {
+        prop_oneof![
+            Just(0u32),
+            Just(u32::MAX),
+            Just(u32::MAX - 1),
+            Just(i32::MAX as u32 + 1),
+            0..12u32,
+            any::<u32>(),
+        ]
+    }
+
+    fn point() -> impl Strategy<Value = Point> {
+        (coord(), coord()).prop_map(|(x, y)| Point::new(x, y))
+    }
+
+    fn size() -> impl Strategy<Value = (u32, u32)> {
+        (0..8u32, 0..8u32)
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        let text = prop::collection::vec(prop::sample::select(vec!['a', '日', '★']), 0..6)
+            .prop_map(|chars| chars.into_iter().collect::<String>());
+        prop_oneof![
+            Just(Op::Clear),
+            point().prop_map(Op::DrawChar),
+            (point(), 0..MAX_GLYPHS).prop_map(|(p, code)| Op::DrawGlyph(p, code)),
+            point().prop_map(Op::DrawWideChar),
+            (point(), text).prop_map(|(p, text)| Op::DrawString(p, text)),
+            (point(), length(), length()).prop_map(|(p, w, h)| Op::DrawRect(p, w, h)),
+            (point(), length(), length()).prop_map(|(p, w, h)| Op::DrawRectFilled(p, w, h)),
+            (point(), length(), length()).prop_map(|(p, w, h)| Op::Tint(p, w, h)),
+            (point(), length(), length(), size())
+                .prop_map(|(p, w, h, size)| Op::Blit(p, w, h, size)),
+            (point(), length(), length(), size())
+                .prop_map(|(p, w, h, size)| Op::PresentBlit(p, w, h, size)),
+        ]
+    }
+
+    // An image whose cells all differ, so any change shows
+    fn numbered(width: u32, height: u32, base: u32) -> Image {
+        let mut image = Image::new(width, height);
+        for i in 0..(width * height) {
+            image.fore_image[i as usize] = base + i;
+            image.back_image[i as usize] = base + 1000 + i;
+            image.text_image[i as usize] = b'A' as u32 + i % 26;
+        }
+        image
+    }
+
+    // The cells an operation may change, as x and y ranges that can reach
+    // beyond the image, and whether it may change the text.
+    fn target(image: &Image, op: &Op) -> ((i64, i64), (i64, i64), bool) {
+        let rect = |p: Point, w: u32, h: u32| {
+            let (x, y) = (p.x as i64, p.y as i64);
+            ((x, x + w as i64), (y, y + h as i64))
+        };
+        let ((xs, ys), text) = match *op {
+            Op::Clear => (rect(Point::new(0, 0), image.width, image.height), true),
+            Op::DrawChar(p) | Op::DrawGlyph(p, _) => (rect(p, 1, 1), true),
+            Op::DrawWideChar(p) => (rect(p, 2, 1), true),
+            Op::DrawString(p, ref text) => (rect(p, text_width(text), 1), true),
+            Op::DrawRect(p, w, h) | Op::DrawRectFilled(p, w, h) => (rect(p, w, h), true),
+            Op::Tint(p, w, h) => (rect(p, w, h), false),
+            Op::Blit(p, w, h, (sw, sh)) | Op::PresentBlit(p, w, h, (sw, sh)) => {
+                (rect(p, w.min(sw), h.min(sh)), true)
+            }
+        };
+        (xs, ys, text)
+    }
+
+    fn apply(image: &mut Image, op: &Op) {
+        let ch = Char::new(b'#', 7, 8);
+        match *op {
+            Op::Clear => image.clear(7, 8),
+            Op::DrawChar(p) => image.draw_char(p, ch),
+            Op::DrawGlyph(p, code) => image.draw_glyph(p, code, 7, 8),
+            Op::DrawWideChar(p) => image.draw_wide_char(p, b'x', 7, 8),
+            Op::DrawString(p, ref text) => image.draw_string(p, text, 7, 8),
+            Op::DrawRect(p, w, h) => image.draw_rect(p, w, h, ch),
+            Op::DrawRectFilled(p, w, h) => image.draw_rect_filled(p, w, h, ch),
+            Op::Tint(p, w, h) => image.tint(p, w, h, 0x8080_8080),
+            Op::Blit(p, w, h, (sw, sh)) => image.blit(p, w, h, &numbered(sw, sh, 5000)),
+            Op::PresentBlit(p, w, h, (sw, sh)) => {
+                let (width, height) = (image.width, image.height);
+                let mut input = PresentInput {
+                    width,
+                    height,
+                    cell_width: 8,
+                    cell_height: 8,
+                    pixel_width: 8 * width,
+                    pixel_height: 8 * height,
+                    scale_factor: 1.0,
+                    image,
+                };
+                input.blit(p, w, h, &numbered(sw, sh, 5000));
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn clip_stays_inside_the_image(
+            (width, height) in size(),
+            p in point(),
+            w in length(),
+            h in length(),
+        ) {
+            let image = Image::new(width, height);
+            let (x, y, cw, ch) = image.clip(p, w, h);
+            prop_assert!(x + cw <= width && y + ch <= height);
+
+            // The clipped rectangle is the overlap of the two
+            let overlap = |start: i32, length: u32, size: u32| {
+                let end = (start as i64 + length as i64).min(size as i64);
+                (end - (start as i64).max(0)).max(0) as u32
+            };
+            prop_assert_eq!(cw, overlap(p.x, w, width));
+            prop_assert_eq!(ch, overlap(p.y, h, height));
+            if cw > 0 && ch > 0 {
+                prop_assert_eq!((x as i64, y as i64), (p.x.max(0) as i64, p.y.max(0) as i64));
+            }
+        }
+
+        #[test]
+        fn drawing_only_changes_the_target(
+            (width, height) in size(),
+            ops in prop::collection::vec(op(), 1..8),
+        ) {
+            let mut image = numbered(width, height, 0);
+            for op in &ops {
+                let before = image.clone();
+                apply(&mut image, op);
+                prop_assert_eq!(image.fore_image.len(), (width * height) as usize);
+
+                let ((x0, x1), (y0, y1), changes_text) = target(&before, op);
+                let inside = |x: i64, y: i64| (x0..x1).contains(&x) && (y0..y1).contains(&y);
+                for y in 0..height as i64 {
+                    for x in 0..width as i64 {
+                        let i = (y * width as i64 + x) as usize;
+                        let text_changed = image.text_image[i] != before.text_image[i];
+                        if inside(x, y) {
+                            prop_assert!(changes_text || !text_changed, "{:?} at ({}, {})", op, x, y);
+                            continue;
+                        }
+                        prop_assert_eq!(image.fore_image[i], before.fore_image[i], "{:?} at ({}, {})", op, x, y);
+                        prop_assert_eq!(image.back_image[i], before.back_image[i], "{:?} at ({}, {})", op, x, y);
+                        // Only the other half of a wide character drawn over
+                        // at the target's edge may be blanked
+                        if text_changed {
+                            let is_wide = |x: i64| {
+                                (0..width as i64).contains(&x)
+                                    && before.text_image[(y * width as i64 + x) as usize]
+                                        & WIDE_CONTINUATION
+                                        != 0
+                            };
+                            let half = (is_wide(x + 1) && inside(x + 1, y))
+                                || (is_wide(x) && inside(x - 1, y));
+                            prop_assert!(half && image.text_image[i] == b' ' as u32, "{:?} at ({}, {})", op, x, y);
+                        }
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn blitting_copies_the_overlap(
+            (width, height) in size(),
+            p in point(),
+            w in length(),
+            h in length(),
+            (sw, sh) in size(),
+        ) {
+            let mut image = numbered(width, height, 0);
+            let sprite = numbered(sw, sh, 5000);
+            image.blit(p, w, h, &sprite);
+
+            for y in 0..height as i64 {
+                for x in 0..width as i64 {
+                    let (sx, sy) = (x - p.x as i64, y - p.y as i64);
+                    if (0..w.min(sw) as i64).contains(&sx) && (0..h.min(sh) as i64).contains(&sy) {
+                        let i = (y * width as i64 + x) as usize;
+                        let j = (sy * sw as i64 + sx) as usize;
+                        prop_assert_eq!(image.fore_image[i], sprite.fore_image[j]);
+                        prop_assert_eq!(image.back_image[i], sprite.back_image[j]);
+                        prop_assert_eq!(image.text_image[i], sprite.text_image[j]);
+                    }
+                }
+            }
+        }
+    }
+}
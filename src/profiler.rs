@@ -0,0 +1,260 @@
+//
+// Frame profiler
+//
+// Records how long each phase of a frame takes, to show whether the game or
+// the engine is the bottleneck:
+//
+//      Tick        the game's tick()
+//      Present     the game's present() and the overlays drawn after it
+//      Upload      copying the cell grid into the GPU textures
+//      Gpu         waiting for a frame from the swap chain and submitting the
+//                  draw.  wgpu can't time work on the GPU itself, but this
+//                  is where a slow GPU or vsync shows up.
+//
+// The most recent frames are kept in a ring buffer and can be saved in the
+// Chrome trace format, to be viewed in chrome://tracing or Perfetto.
+//
+
+use crate::RogueResult;
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Tick,
+    Present,
+    Upload,
+    Gpu,
+}
+
+pub const PHASES: [Phase; 4] = [Phase::Tick, Phase::Present, Phase::Upload, Phase::Gpu];
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Tick => "tick",
+            Phase::Present => "present",
+            Phase::Upload => "upload",
+            Phase::Gpu => "gpu",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameTimings {
+    pub frame: u64,
+    // (start since the profiler was created, duration) of each phase, in the
+    // order of PHASES
+    spans: [(Duration, Duration); 4],
+}
+
+impl FrameTimings {
+    pub fn duration(&self, phase: Phase) -> Duration {
+        self.spans[phase as usize].1
+    }
+
+    pub fn start(&self, phase: Phase) -> Duration {
+        self.spans[phase as usize].0
+    }
+
+    // The time spent in all the phases
+    pub fn total(&self) -> Duration {
+        self.spans.iter().map(|&(_, duration)| duration).sum()
+    }
+}
+
+pub struct Profiler {
+    enabled: bool,
+    epoch: Instant,
+    capacity: usize,
+    frames: VecDeque<FrameTimings>,
+    current: FrameTimings,
+}
+
+impl Profiler {
+    pub fn new(capacity: usize) -> Self {
+        Profiler {
+            enabled: true,
+            epoch: Instant::now(),
+            capacity: capacity.max(1),
+            frames: VecDeque::with_capacity(capacity),
+            current: FrameTimings::default(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // The recorded frames, oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &FrameTimings> {
+        self.frames.iter()
+    }
+
+    pub fn latest(&self) -> Option<&FrameTimings> {
+        self.frames.back()
+    }
+
+    // The mean duration of a phase over the recorded frames
+    pub fn average(&self, phase: Phase) -> Duration {
+        if self.frames.is_empty() {
+            Duration::ZERO
+        } else {
+            let total = self
+                .frames
+                .iter()
+                .map(|f| f.duration(phase))
+                .sum::<Duration>();
+            total / self.frames.len() as u32
+        }
+    }
+
+    pub(crate) fn record(&mut self, phase: Phase, start: Instant) {
+        let duration = start.elapsed();
+        log::trace!(
+            "Frame {} {} took {:?}",
+            self.current.frame,
+            phase.name(),
+            duration
+        );
+        if self.enabled {
+            self.current.spans[phase as usize] = (start - self.epoch, duration);
+        }
+    }
+
+    // Called once the frame has been handed to the GPU.
+    pub(crate) fn end_frame(&mut self) {
+        if self.enabled {
+            if self.frames.len() == self.capacity {
+                self.frames.pop_front();
+            }
+            self.frames.push_back(self.current);
+        }
+        self.current = FrameTimings {
+            frame: self.current.frame + 1,
+            ..FrameTimings::default()
+        };
+    }
+
+    //
+    // Chrome tracing
+    //
+
+    pub fn chrome_trace(&self) -> String {
+        let mut json = String::from("[\n");
+        let events = self.frames.iter().flat_map(|frame| {
+            PHASES
+                .iter()
+                .filter(move |&&phase| !frame.duration(phase).is_zero())
+                .map(move |&phase| (frame, phase))
+        });
+        for (i, (frame, phase)) in events.enumerate() {
+            if i > 0 {
+                json.push_str(",\n");
+            }
+            let _ = write!(
+                json,
+                "  {{\"name\":\"{}\",\"cat\":\"frame\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1,\"args\":{{\"frame\":{}}}}}",
+                phase.name(),
+                frame.start(phase).as_micros(),
+                frame.duration(phase).as_micros(),
+                frame.frame
+            );
+        }
+        json.push_str("\n]\n");
+        json
+    }
+
+    pub fn save_chrome_trace(&self, path: &Path) -> RogueResult<()> {
+        std::fs::write(path, self.chrome_trace())?;
+        Ok(())
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new(240)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // Ends a frame with the given tick and present durations, one after the
+    // other starting at `start`.
+    fn frame(profiler: &mut Profiler, start: Duration, tick: Duration, present: Duration) {
+        profiler.current.spans[Phase::Tick as usize] = (start, tick);
+        profiler.current.spans[Phase::Present as usize] = (start + tick, present);
+        profiler.end_frame();
+    }
+
+    #[test]
+    fn frames_are_kept_in_a_ring() {
+        let mut profiler = Profiler::new(2);
+        frame(&mut profiler, ms(0), ms(2), ms(4));
+        frame(&mut profiler, ms(10), ms(4), ms(4));
+        frame(&mut profiler, ms(20), ms(6), ms(4));
+        let frames = profiler.frames().map(|f| f.frame).collect::<Vec<_>>();
+        assert_eq!(frames, [1, 2]);
+        assert_eq!(profiler.average(Phase::Tick), ms(5));
+        assert_eq!(profiler.average(Phase::Gpu), Duration::ZERO);
+        let latest = profiler.latest().unwrap();
+        assert_eq!(latest.total(), ms(10));
+        assert_eq!(latest.start(Phase::Present), ms(26));
+
+        // Frames still count while disabled but aren't kept
+        profiler.set_enabled(false);
+        frame(&mut profiler, ms(30), ms(1), ms(1));
+        profiler.set_enabled(true);
+        profiler.clear();
+        frame(&mut profiler, ms(40), ms(1), ms(1));
+        let frames = profiler.frames().map(|f| f.frame).collect::<Vec<_>>();
+        assert_eq!(frames, [4]);
+    }
+
+    #[test]
+    fn recording_times_a_phase() {
+        let mut profiler = Profiler::new(1);
+        let start = Instant::now();
+        profiler.record(Phase::Upload, start);
+        profiler.end_frame();
+        let latest = profiler.latest().unwrap();
+        assert_eq!(latest.start(Phase::Upload), start - profiler.epoch);
+        assert_eq!(latest.duration(Phase::Tick), Duration::ZERO);
+    }
+
+    #[test]
+    fn chrome_traces_skip_phases_that_did_not_run() {
+        let mut profiler = Profiler::new(4);
+        frame(&mut profiler, ms(1), ms(2), Duration::ZERO);
+        frame(&mut profiler, ms(5), ms(1), Duration::from_micros(1500));
+        assert_eq!(
+            profiler.chrome_trace(),
+            concat!(
+                "[\n",
+                "  {\"name\":\"tick\",\"cat\":\"frame\",\"ph\":\"X\",\"ts\":1000,\"dur\":2000,\"pid\":1,\"tid\":1,\"args\":{\"frame\":0}},\n",
+                "  {\"name\":\"tick\",\"cat\":\"frame\",\"ph\":\"X\",\"ts\":5000,\"dur\":1000,\"pid\":1,\"tid\":1,\"args\":{\"frame\":1}},\n",
+                "  {\"name\":\"present\",\"cat\":\"frame\",\"ph\":\"X\",\"ts\":6000,\"dur\":1500,\"pid\":1,\"tid\":1,\"args\":{\"frame\":1}}\n",
+                "]\n"
+            )
+        );
+        assert_eq!(Profiler::new(1).chrome_trace(), "[\n\n]\n");
+    }
+}
@@ -0,0 +1,438 @@
+//
+// Quests
+//
+// Quests are made of objectives, each counting up to a target ("kill 10
+// rats", "find the amulet").  A quest can only be started once the quests it
+// depends on are complete, and it completes by itself when all of its
+// required objectives are done.  Every change is published on the event bus
+// as a QuestEvent so message logs and achievements can react to it.
+//
+// Quest definitions can be loaded from quests.ron with the content feature.
+// The player's progress is kept separately in a QuestLogState so that it can
+// be written into save files with serde.
+//
+
+use crate::EventBus;
+use std::collections::BTreeMap;
+
+#[cfg(feature = "content")]
+use crate::Content;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct ObjectiveDef {
+    pub description: String,
+    #[cfg_attr(feature = "serde", serde(default = "one"))]
+    pub target: u32,
+    // Optional objectives don't have to be done to complete the quest
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub optional: bool,
+}
+
+#[cfg(feature = "serde")]
+fn one() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct QuestDef {
+    pub title: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub description: String,
+    // Ids of quests that must be completed first
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prerequisites: Vec<String>,
+    pub objectives: Vec<ObjectiveDef>,
+}
+
+impl QuestDef {
+    pub fn new(title: &str, description: &str) -> Self {
+        QuestDef {
+            title: String::from(title),
+            description: String::from(description),
+            prerequisites: Vec::new(),
+            objectives: Vec::new(),
+        }
+    }
+
+    pub fn with_prerequisite(&mut self, quest: &str) -> &mut Self {
+        self.prerequisites.push(String::from(quest));
+        self
+    }
+
+    pub fn with_objective(&mut self, description: &str, target: u32, optional: bool) -> &mut Self {
+        self.objectives.push(ObjectiveDef {
+            description: String::from(description),
+            target: target.max(1),
+            optional,
+        });
+        self
+    }
+}
+
+#[cfg(feature = "content")]
+impl Content for QuestDef {
+    const KIND: &'static str = "quests";
+
+    fn references(&self) -> Vec<(&'static str, String)> {
+        self.prerequisites
+            .iter()
+            .map(|id| (Self::KIND, id.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum QuestState {
+    // Prerequisites not yet complete
+    Locked,
+    Available,
+    Active,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuestEvent {
+    Started(String),
+    Progressed {
+        quest: String,
+        objective: usize,
+        progress: u32,
+        target: u32,
+    },
+    ObjectiveDone {
+        quest: String,
+        objective: usize,
+    },
+    Completed(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuestError {
+    UnknownQuest,
+    UnknownObjective,
+    // The quest is locked, already started or finished
+    WrongState(QuestState),
+}
+
+// The player's progress, for saving and loading
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QuestLogState {
+    // State and objective counts of quests that have been started
+    pub quests: BTreeMap<String, (QuestState, Vec<u32>)>,
+}
+
+//
+// Quest log
+//
+
+pub struct QuestLog {
+    definitions: BTreeMap<String, QuestDef>,
+    state: QuestLogState,
+}
+
+impl QuestLog {
+    pub fn new() -> Self {
+        QuestLog {
+            definitions: BTreeMap::new(),
+            state: QuestLogState::default(),
+        }
+    }
+
+    pub fn add(&mut self, id: &str, quest: QuestDef) -> &mut Self {
+        self.definitions.insert(String::from(id), quest);
+        self
+    }
+
+    // Takes the quests loaded by the content registry.
+    #[cfg(feature = "content")]
+    pub fn add_from_registry(&mut self, registry: &crate::ContentRegistry) -> &mut Self {
+        for (id, quest) in registry.all::<QuestDef>() {
+            self.add(id, quest.clone());
+        }
+        self
+    }
+
+    pub fn definition(&self, id: &str) -> Option<&QuestDef> {
+        self.definitions.get(id)
+    }
+
+    // All quest ids, ordered by id
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.definitions.keys().map(|id| id.as_str())
+    }
+
+    pub fn state(&self, id: &str) -> Option<QuestState> {
+        let quest = self.definitions.get(id)?;
+        if let Some((state, _)) = self.state.quests.get(id) {
+            return Some(*state);
+        }
+        let unlocked = quest
+            .prerequisites
+            .iter()
+            .all(|p| self.state(p) == Some(QuestState::Completed));
+        Some(if unlocked {
+            QuestState::Available
+        } else {
+            QuestState::Locked
+        })
+    }
+
+    // Ids of the quests in a state
+    pub fn with_state(&self, state: QuestState) -> Vec<&str> {
+        self.ids()
+            .filter(|id| self.state(id) == Some(state))
+            .collect()
+    }
+
+    // (progress, target) for each objective
+    pub fn objectives(&self, id: &str) -> Vec<(u32, u32)> {
+        let quest = match self.definitions.get(id) {
+            Some(quest) => quest,
+            None => return Vec::new(),
+        };
+        let counts = self.state.quests.get(id).map(|(_, counts)| counts);
+        quest
+            .objectives
+            .iter()
+            .enumerate()
+            .map(|(i, o)| {
+                let progress = counts.and_then(|c| c.get(i)).copied().unwrap_or(0);
+                (progress, o.target)
+            })
+            .collect()
+    }
+
+    //
+    // Changes
+    //
+
+    pub fn start(&mut self, id: &str, events: &mut EventBus) -> Result<(), QuestError> {
+        let quest = self.definitions.get(id).ok_or(QuestError::UnknownQuest)?;
+        match self.state(id) {
+            Some(QuestState::Available) => {}
+            Some(state) => return Err(QuestError::WrongState(state)),
+            None => return Err(QuestError::UnknownQuest),
+        }
+        let counts = vec![0; quest.objectives.len()];
+        self.state
+            .quests
+            .insert(String::from(id), (QuestState::Active, counts));
+        events.publish(QuestEvent::Started(String::from(id)));
+        Ok(())
+    }
+
+    // Adds to an objective's count.  Progress past the target is ignored.
+    pub fn progress(
+        &mut self,
+        id: &str,
+        objective: usize,
+        amount: u32,
+        events: &mut EventBus,
+    ) -> Result<(), QuestError> {
+        let quest = self.definitions.get(id).ok_or(QuestError::UnknownQuest)?;
+        let target = quest
+            .objectives
+            .get(objective)
+            .ok_or(QuestError::UnknownObjective)?
+            .target;
+        let (state, counts) = match self.state.quests.get_mut(id) {
+            Some(entry) if entry.0 == QuestState::Active => entry,
+            Some(entry) => return Err(QuestError::WrongState(entry.0)),
+            None => return Err(QuestError::WrongState(QuestState::Available)),
+        };
+
+        let old = counts[objective];
+        let new = old.saturating_add(amount).min(target);
+        if new == old {
+            return Ok(());
+        }
+        counts[objective] = new;
+        events.publish(QuestEvent::Progressed {
+            quest: String::from(id),
+            objective,
+            progress: new,
+            target,
+        });
+        if new == target {
+            events.publish(QuestEvent::ObjectiveDone {
+                quest: String::from(id),
+                objective,
+            });
+        }
+
+        let done = quest
+            .objectives
+            .iter()
+            .zip(counts.iter())
+            .all(|(o, &count)| o.optional || count >= o.target);
+        if done {
+            *state = QuestState::Completed;
+            events.publish(QuestEvent::Completed(String::from(id)));
+        }
+        Ok(())
+    }
+
+    // Completes an objective outright.
+    pub fn complete_objective(
+        &mut self,
+        id: &str,
+        objective: usize,
+        events: &mut EventBus,
+    ) -> Result<(), QuestError> {
+        self.progress(id, objective, u32::MAX, events)
+    }
+
+    pub fn fail(&mut self, id: &str, events: &mut EventBus) -> Result<(), QuestError> {
+        match self.state.quests.get_mut(id) {
+            Some((state, _)) if *state == QuestState::Active => {
+                *state = QuestState::Failed;
+                events.publish(QuestEvent::Failed(String::from(id)));
+                Ok(())
+            }
+            Some((state, _)) => Err(QuestError::WrongState(*state)),
+            None => match self.state(id) {
+                Some(state) => Err(QuestError::WrongState(state)),
+                None => Err(QuestError::UnknownQuest),
+            },
+        }
+    }
+
+    //
+    // Persistence
+    //
+
+    pub fn save_state(&self) -> QuestLogState {
+        self.state.clone()
+    }
+
+    pub fn load_state(&mut self, state: QuestLogState) {
+        self.state = state;
+    }
+}
+
+impl Default for QuestLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> QuestLog {
+        let mut rats = QuestDef::new("Rats", "Clear the cellar.");
+        rats.with_objective("Kill rats", 3, false)
+            .with_objective("Find the cheese", 1, true);
+        let mut king = QuestDef::new("Rat King", "");
+        king.with_prerequisite("rats")
+            .with_objective("Kill the king", 0, false);
+        let mut log = QuestLog::new();
+        log.add("rats", rats).add("king", king);
+        log
+    }
+
+    #[test]
+    fn quests_unlock_when_their_prerequisites_complete() {
+        let mut events = EventBus::new();
+        let mut log = log();
+        assert_eq!(log.state("king"), Some(QuestState::Locked));
+        assert_eq!(
+            log.start("king", &mut events),
+            Err(QuestError::WrongState(QuestState::Locked))
+        );
+        assert_eq!(
+            log.start("wolves", &mut events),
+            Err(QuestError::UnknownQuest)
+        );
+
+        log.start("rats", &mut events).unwrap();
+        assert_eq!(
+            log.start("rats", &mut events),
+            Err(QuestError::WrongState(QuestState::Active))
+        );
+        // The optional objective isn't needed
+        log.progress("rats", 0, 2, &mut events).unwrap();
+        assert_eq!(log.state("rats"), Some(QuestState::Active));
+        log.progress("rats", 0, 5, &mut events).unwrap();
+        assert_eq!(log.objectives("rats"), [(3, 3), (0, 1)]);
+        assert_eq!(log.state("rats"), Some(QuestState::Completed));
+        assert_eq!(log.with_state(QuestState::Available), ["king"]);
+
+        assert_eq!(
+            events.drain::<QuestEvent>(),
+            [
+                QuestEvent::Started(String::from("rats")),
+                QuestEvent::Progressed {
+                    quest: String::from("rats"),
+                    objective: 0,
+                    progress: 2,
+                    target: 3
+                },
+                QuestEvent::Progressed {
+                    quest: String::from("rats"),
+                    objective: 0,
+                    progress: 3,
+                    target: 3
+                },
+                QuestEvent::ObjectiveDone {
+                    quest: String::from("rats"),
+                    objective: 0
+                },
+                QuestEvent::Completed(String::from("rats")),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_active_quests_progress_or_fail() {
+        let mut events = EventBus::new();
+        let mut log = log();
+        assert_eq!(
+            log.progress("rats", 0, 1, &mut events),
+            Err(QuestError::WrongState(QuestState::Available))
+        );
+        assert_eq!(
+            log.fail("rats", &mut events),
+            Err(QuestError::WrongState(QuestState::Available))
+        );
+        log.start("rats", &mut events).unwrap();
+        assert_eq!(
+            log.progress("rats", 2, 1, &mut events),
+            Err(QuestError::UnknownObjective)
+        );
+        log.fail("rats", &mut events).unwrap();
+        assert_eq!(
+            log.complete_objective("rats", 0, &mut events),
+            Err(QuestError::WrongState(QuestState::Failed))
+        );
+        assert_eq!(
+            log.fail("wolves", &mut events),
+            Err(QuestError::UnknownQuest)
+        );
+    }
+
+    #[test]
+    fn progress_is_saved_and_loaded() {
+        let mut events = EventBus::new();
+        let mut log = log();
+        log.start("rats", &mut events).unwrap();
+        log.complete_objective("rats", 1, &mut events).unwrap();
+        let state = log.save_state();
+
+        let mut loaded = self::log();
+        loaded.load_state(state);
+        assert_eq!(loaded.state("rats"), Some(QuestState::Active));
+        assert_eq!(loaded.objectives("rats"), [(0, 3), (1, 1)]);
+        // Targets of zero are raised to one
+        assert_eq!(loaded.objectives("king"), [(0, 1)]);
+    }
+}
@@ -0,0 +1,263 @@
+//
+// Raycaster
+//
+// A first-person view of a grid map, drawn like the old "3D" dungeon
+// crawlers.  A ray is cast across the map for every column of the view and
+// the wall it hits is drawn as a vertical strip, shorter the further away it
+// is.  Walls are built from half-block characters, so their tops and bottoms
+// have twice the vertical resolution of the cells, and fade into the
+// darkness with distance.
+//
+
+use crate::{lerp_colour, new_colour, Char, Grid, Image, Point, Rect};
+
+// Where the viewer stands and which way they face.  Positions are in map
+// cells (the centre of cell (x, y) is (x + 0.5, y + 0.5)) and the angle is in
+// radians clockwise from east, as y goes down the map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+}
+
+impl Pose {
+    pub fn new(x: f32, y: f32, angle: f32) -> Self {
+        Pose { x, y, angle }
+    }
+
+    // Standing in the centre of a cell facing one of the eight directions
+    pub fn at_cell(p: Point, direction: Point) -> Self {
+        let angle = (direction.y as f32).atan2(direction.x as f32);
+        Pose::new(p.x as f32 + 0.5, p.y as f32 + 0.5, angle)
+    }
+}
+
+// cp437 codes for the characters walls are made of
+const FULL_BLOCK: u8 = 219;
+const LOWER_HALF: u8 = 220;
+const UPPER_HALF: u8 = 223;
+
+pub struct Raycaster {
+    fov: f32,
+    max_distance: f32,
+    ceiling: u32,
+    floor: u32,
+    fog: u32,
+}
+
+impl Raycaster {
+    pub fn new() -> Self {
+        Raycaster {
+            fov: std::f32::consts::FRAC_PI_3,
+            max_distance: 16.0,
+            ceiling: new_colour(32, 32, 40),
+            floor: new_colour(64, 56, 48),
+            fog: new_colour(0, 0, 0),
+        }
+    }
+
+    // The horizontal field of view in radians
+    pub fn with_fov(&mut self, fov: f32) -> &mut Self {
+        self.fov = fov.clamp(0.1, std::f32::consts::PI - 0.1);
+        self
+    }
+
+    // Walls fade completely into the fog colour at this distance and rays go
+    // no further.
+    pub fn with_max_distance(&mut self, distance: f32) -> &mut Self {
+        self.max_distance = distance.max(1.0);
+        self
+    }
+
+    pub fn with_colours(&mut self, ceiling: u32, floor: u32, fog: u32) -> &mut Self {
+        self.ceiling = ceiling;
+        self.floor = floor;
+        self.fog = fog;
+        self
+    }
+
+    // Draws the view into a rectangle of the image.  `wall` gives the colour
+    // of the walls in a cell, or None if it can be seen through.  Cells
+    // outside the map are solid.
+    pub fn draw<T>(
+        &self,
+        image: &mut Image,
+        rect: Rect,
+        map: &Grid<T>,
+        pose: Pose,
+        wall: impl Fn(&T) -> Option<u32>,
+    ) {
+        let half_rows = rect.height as f32 * 2.0;
+        // Lay the columns out on a flat plane in front of the viewer rather
+        // than by equal angles, which would bend straight walls.
+        let plane = (self.fov / 2.0).tan();
+        let (dir_x, dir_y) = (pose.angle.cos(), pose.angle.sin());
+
+        for column in 0..rect.width {
+            let offset = (2.0 * (column as f32 + 0.5) / rect.width as f32 - 1.0) * plane;
+            let ray = (dir_x - dir_y * offset, dir_y + dir_x * offset);
+            let hit = self.cast(map, pose, ray, &wall);
+
+            // Wall height in half rows.  A half row is about as tall as a
+            // column is wide, so walls are scaled like the plane is.
+            let (top, bottom, colour) = match hit {
+                Some((distance, colour, side)) => {
+                    let height = rect.width as f32 / (2.0 * plane * distance.max(0.01));
+                    let top = (half_rows - height) / 2.0;
+                    let shade = if side { 0.75 } else { 1.0 };
+                    let colour = lerp_colour(colour, 0, 1.0 - shade);
+                    let colour = lerp_colour(colour, self.fog, distance / self.max_distance);
+                    (top.round() as i32, (top + height).round() as i32, colour)
+                }
+                None => (rect.height as i32, rect.height as i32, self.fog),
+            };
+
+            for row in 0..rect.height as i32 {
+                let p = Point::new(rect.x + column as i32, rect.y + row);
+                let above = (row * 2 + 1) as f32 <= half_rows / 2.0;
+                let background = if above { self.ceiling } else { self.floor };
+                let upper = row * 2 >= top && row * 2 < bottom;
+                let lower = row * 2 + 1 >= top && row * 2 + 1 < bottom;
+                let ch = match (upper, lower) {
+                    (true, true) => Char::new(FULL_BLOCK, colour, colour),
+                    (true, false) => Char::new(UPPER_HALF, colour, self.floor),
+                    (false, true) => Char::new(LOWER_HALF, colour, self.ceiling),
+                    (false, false) => Char::new(b' ', background, background),
+                };
+                image.draw_char(p, ch);
+            }
+        }
+    }
+
+    // Steps the ray from cell edge to cell edge (DDA) until it enters a
+    // wall.  Returns the distance along the view direction, the wall's colour
+    // and whether it hit a north or south face.
+    fn cast<T>(
+        &self,
+        map: &Grid<T>,
+        pose: Pose,
+        (ray_x, ray_y): (f32, f32),
+        wall: impl Fn(&T) -> Option<u32>,
+    ) -> Option<(f32, u32, bool)> {
+        let mut cell = Point::new(pose.x.floor() as i32, pose.y.floor() as i32);
+        let delta_x = if ray_x == 0.0 {
+            f32::INFINITY
+        } else {
+            (1.0 / ray_x).abs()
+        };
+        let delta_y = if ray_y == 0.0 {
+            f32::INFINITY
+        } else {
+            (1.0 / ray_y).abs()
+        };
+        let (step_x, mut side_x) = if ray_x < 0.0 {
+            (-1, (pose.x - cell.x as f32) * delta_x)
+        } else {
+            (1, (cell.x as f32 + 1.0 - pose.x) * delta_x)
+        };
+        let (step_y, mut side_y) = if ray_y < 0.0 {
+            (-1, (pose.y - cell.y as f32) * delta_y)
+        } else {
+            (1, (cell.y as f32 + 1.0 - pose.y) * delta_y)
+        };
+
+        loop {
+            let (distance, side) = if side_x < side_y {
+                cell.x += step_x;
+                side_x += delta_x;
+                (side_x - delta_x, false)
+            } else {
+                cell.y += step_y;
+                side_y += delta_y;
+                (side_y - delta_y, true)
+            };
+            if distance > self.max_distance {
+                return None;
+            }
+            match map.get(cell) {
+                Some(value) => {
+                    if let Some(colour) = wall(value) {
+                        return Some((distance, colour, side));
+                    }
+                }
+                None => return Some((distance, self.fog, side)),
+            }
+        }
+    }
+}
+
+impl Default for Raycaster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty room walled in on all four sides
+    fn room() -> Grid<bool> {
+        Grid::from_fn(10, 9, |p| p.x == 0 || p.x == 9 || p.y == 0 || p.y == 8)
+    }
+
+    fn solid(&wall: &bool) -> Option<u32> {
+        if wall {
+            Some(new_colour(200, 200, 200))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn rays_stop_at_the_first_wall() {
+        let raycaster = Raycaster::new();
+        let east = Pose::at_cell(Point::new(1, 4), Point::new(1, 0));
+        let south = Pose::at_cell(Point::new(1, 7), Point::new(0, 1));
+        assert!((south.angle - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+        let (distance, _, side) = raycaster.cast(&room(), east, (1.0, 0.0), solid).unwrap();
+        assert!((distance - 7.5).abs() < 1e-5);
+        assert!(!side);
+        let (distance, _, side) = raycaster.cast(&room(), south, (0.0, 1.0), solid).unwrap();
+        assert!((distance - 0.5).abs() < 1e-5);
+        assert!(side);
+
+        let mut short = Raycaster::new();
+        short.with_max_distance(4.0);
+        assert!(short.cast(&room(), east, (1.0, 0.0), solid).is_none());
+    }
+
+    #[test]
+    fn near_walls_fill_the_view_and_far_ones_fade_out() {
+        let ceiling = new_colour(1, 1, 1);
+        let floor = new_colour(2, 2, 2);
+        let mut raycaster = Raycaster::new();
+        raycaster
+            .with_max_distance(4.0)
+            .with_colours(ceiling, floor, 0);
+        let rect = Rect::new(0, 0, 4, 4);
+        let column =
+            |image: &Image| -> Vec<u32> { (0..4).map(|y| image.text_image[y * 4]).collect() };
+
+        // Half a cell from a wall, it covers all but a half row at each end
+        let mut image = Image::new(4, 4);
+        let south = Pose::at_cell(Point::new(1, 7), Point::new(0, 1));
+        raycaster.draw(&mut image, rect, &room(), south, solid);
+        assert_eq!(
+            column(&image),
+            [LOWER_HALF, FULL_BLOCK, FULL_BLOCK, UPPER_HALF].map(u32::from)
+        );
+        assert_eq!(image.back_image[0], ceiling);
+        assert_eq!(image.back_image[12], floor);
+
+        // Beyond the maximum distance there's only ceiling and floor
+        let mut image = Image::new(4, 4);
+        let east = Pose::at_cell(Point::new(1, 4), Point::new(1, 0));
+        raycaster.draw(&mut image, rect, &room(), east, solid);
+        assert_eq!(column(&image), [b' ' as u32; 4]);
+        let backs = (0..4).map(|y| image.back_image[y * 4]).collect::<Vec<_>>();
+        assert_eq!(backs, [ceiling, ceiling, floor, floor]);
+    }
+}
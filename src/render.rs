@@ -2,28 +2,36 @@
 // ASCII renderer
 //
 
-use std::num::NonZeroU32;
+use std::{mem::replace, num::NonZeroU32, time::Instant};
 
 use bytemuck::cast_slice;
 use bytemuck_derive::{Pod, Zeroable};
 use thiserror::Error;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, BufferBindingType, BufferUsage,
-    Color, ColorTargetState, ColorWrite, CommandEncoderDescriptor, Device, DeviceDescriptor,
-    Extent3d, Features, FragmentState, FrontFace, ImageCopyTexture, ImageDataLayout, Instance,
-    Limits, LoadOp, MultisampleState, Operations, Origin3d, PipelineLayoutDescriptor, PolygonMode,
-    PowerPreference, PresentMode, PrimitiveState, PrimitiveTopology, Queue,
-    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
-    RequestAdapterOptions, RequestDeviceError, ShaderFlags, ShaderModuleDescriptor, ShaderSource,
-    ShaderStage, Surface, SwapChain, SwapChainDescriptor, SwapChainError, Texture,
-    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsage,
-    TextureViewDescriptor, TextureViewDimension, VertexState,
+    Adapter, AdapterInfo, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry,
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    BlendState, Buffer, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites,
+    CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, Device, DeviceDescriptor, Extent3d, Features, FragmentState,
+    FrontFace, ImageCopyTexture, ImageDataLayout, Instance, Limits, LoadOp, MultisampleState,
+    Operations, Origin3d, PipelineLayoutDescriptor, PolygonMode, PowerPreference, PresentMode,
+    PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment, RenderPassDescriptor,
+    RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, RequestDeviceError,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StorageTextureAccess, Surface,
+    SurfaceConfiguration, SurfaceError, Texture, TextureAspect, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
+    TextureViewDimension, VertexState,
+};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    window::Window,
 };
-use winit::{dpi::PhysicalSize, window::Window};
 
-use crate::RogueFontData;
+use crate::{
+    glyphs, new_colour, software::SoftwareRenderer, window_handle::WindowHandle, CaretStyle, Image,
+    MouseState, Point, RogueFontData,
+};
 
 //
 // Rendering system errors that are passed into Results
@@ -39,10 +47,168 @@ pub enum RenderError {
 
     #[error("Could not find a texture format compatible with the swap chain")]
     BadSwapChainFormat,
+
+    #[error("{limit} of {requested} is more than the graphics device's maximum of {max}")]
+    LimitExceeded {
+        limit: &'static str,
+        requested: u32,
+        max: u32,
+    },
+
+    #[error("The window's surface was lost")]
+    SurfaceLost,
+
+    #[error("The window's surface is out of date")]
+    SurfaceOutdated,
+
+    #[error("Timed out waiting for the next frame")]
+    Timeout,
+
+    #[error("Unable to compile the effects shader: {0}")]
+    BadShader(String),
+
+    #[error("The graphics device is out of memory")]
+    OutOfMemory,
+
+    #[error("Unable to draw with the software renderer: {0}")]
+    Software(String),
+}
+
+impl From<SurfaceError> for RenderError {
+    fn from(e: SurfaceError) -> Self {
+        match e {
+            SurfaceError::Lost => RenderError::SurfaceLost,
+            SurfaceError::Outdated => RenderError::SurfaceOutdated,
+            SurfaceError::Timeout => RenderError::Timeout,
+            SurfaceError::OutOfMemory => RenderError::OutOfMemory,
+        }
+    }
 }
 
 pub type RenderResult<T> = Result<T, RenderError>;
 
+//
+// Adapter selection
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GraphicsBackend {
+    // Whichever of Vulkan, DX12 and Metal the platform has
+    #[default]
+    Auto,
+    Vulkan,
+    Dx12,
+    Dx11,
+    Metal,
+    Gl,
+}
+
+impl GraphicsBackend {
+    fn bits(&self) -> Backends {
+        match self {
+            GraphicsBackend::Auto => Backends::PRIMARY,
+            GraphicsBackend::Vulkan => Backends::VULKAN,
+            GraphicsBackend::Dx12 => Backends::DX12,
+            GraphicsBackend::Dx11 => Backends::DX11,
+            GraphicsBackend::Metal => Backends::METAL,
+            GraphicsBackend::Gl => Backends::GL,
+        }
+    }
+}
+
+// How the graphics device is chosen.  Set with the RogueBuilder's
+// with_backend(), with_power_preference() and with_adapter().
+#[derive(Debug, Clone)]
+pub struct GraphicsOptions {
+    pub backend: GraphicsBackend,
+    pub power_preference: PowerPreference,
+    // Part of the name of the adapter to use, ignoring case, e.g. "intel"
+    pub adapter_name: Option<String>,
+    // Colours are normally sRGB, as picked in a paint program, and are
+    // converted if the swap chain wants linear colours.  Games that work out
+    // their colours in linear space turn the conversion off.
+    pub linear_colours: bool,
+    // See RenderState::set_clear_colour()
+    pub clear_colour: u32,
+    pub edge_policy: EdgePolicy,
+    // A fixed grid in cells, scaled to fit the window, instead of one that
+    // grows and shrinks with it
+    pub grid_size: Option<(u32, u32)>,
+    // Limits on a grid that follows the window's size.  A window too large
+    // for the maximum has space left over, dealt with by the edge policy.
+    pub min_grid_size: Option<(u32, u32)>,
+    pub max_grid_size: Option<(u32, u32)>,
+    // WGSL for the GPU effects stage, described below
+    pub effects_shader: Option<String>,
+}
+
+// What to do with the pixels left over at the right and bottom when the
+// window isn't a whole number of cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgePolicy {
+    // Leave them in the clear colour
+    #[default]
+    Truncate,
+    // Share them out on all sides, centring the grid
+    Centre,
+    // Stretch the grid slightly to cover them
+    Scale,
+    // Add a row and column of cells that are cut off at the edge
+    Partial,
+}
+
+impl Default for GraphicsOptions {
+    fn default() -> Self {
+        GraphicsOptions {
+            backend: GraphicsBackend::default(),
+            power_preference: PowerPreference::default(),
+            adapter_name: None,
+            linear_colours: false,
+            clear_colour: new_colour(0, 0, 0),
+            edge_policy: EdgePolicy::default(),
+            grid_size: None,
+            min_grid_size: None,
+            max_grid_size: None,
+            effects_shader: None,
+        }
+    }
+}
+
+// The graphics devices available through a backend, so a game can offer a
+// choice of adapter in its settings.
+pub fn list_adapters(backend: GraphicsBackend) -> Vec<AdapterInfo> {
+    Instance::new(backend.bits())
+        .enumerate_adapters(backend.bits())
+        .map(|adapter| adapter.get_info())
+        .collect()
+}
+
+async fn choose_adapter(
+    instance: &Instance,
+    backends: Backends,
+    surface: &Surface,
+    options: &GraphicsOptions,
+) -> Option<Adapter> {
+    if let Some(name) = &options.adapter_name {
+        let name = name.to_lowercase();
+        let adapter = instance
+            .enumerate_adapters(backends)
+            .filter(|adapter| adapter.get_info().name.to_lowercase().contains(&name))
+            .find(|adapter| !surface.get_supported_formats(adapter).is_empty());
+        match adapter {
+            Some(adapter) => return Some(adapter),
+            None => log::warn!("No graphics adapter matches \"{}\"", name),
+        }
+    }
+    instance
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: options.power_preference,
+            force_fallback_adapter: false,
+            compatible_surface: Some(surface),
+        })
+        .await
+}
+
 //
 // Rendering state and interface
 //
@@ -51,78 +217,126 @@ pub struct RenderState {
     surface: Surface,
     device: Device,
     queue: Queue,
-    swapchain_desc: SwapChainDescriptor,
-    swapchain: SwapChain,
+    surface_config: SurfaceConfiguration,
     render_pipeline: RenderPipeline,
 
     fg_texture: RogueTexture,
     bg_texture: RogueTexture,
     chars_texture: RogueTexture,
+    light_texture: RogueTexture,
     font_texture: RogueTexture,
+    // Written by the effects stage, or 1x1 and unused without one
+    effects_texture: RogueTexture,
+    effects: Option<EffectsStage>,
     texture_bind_group_layout: BindGroupLayout,
     texture_bind_group: BindGroup,
 
     uniform_bind_group: BindGroup,
+    uniform_buffer: Buffer,
+    uniforms: RenderInfo,
 
     font_char_size: (u32, u32),
-    size: (u32, u32),
+    // Window pixels per font pixel, e.g. 2 for the large font option
+    zoom: u32,
+    image: Image,
+
+    // Kept to rebuild the font texture if the device is lost
+    font: RogueFontData,
+    // Frames in a row that failed because the surface was lost
+    lost_frames: u32,
+    max_texture_size: u32,
+    atlas_generation: u64,
+    // Kept to choose the same adapter if the device is lost
+    options: GraphicsOptions,
+    adapter_info: AdapterInfo,
 }
 
+// Surface losses in a row that are dealt with by recreating the swap chain
+// before the whole device is recreated
+const MAX_LOST_FRAMES: u32 = 3;
+
 impl RenderState {
-    pub async fn new(window: &Window, font: &RogueFontData) -> RenderResult<Self> {
+    pub async fn new(
+        window: &Window,
+        font: &RogueFontData,
+        options: &GraphicsOptions,
+    ) -> RenderResult<Self> {
         let inner_size = window.inner_size();
 
         // An instance represents access to the WGPU API.  Here we decide which
-        // back-end to use (Vulkan, DX12, Metal etc), but we let WGPU decide by
-        // stating PRIMARY.
-        let instance = Instance::new(wgpu::BackendBit::PRIMARY);
+        // back-end to use (Vulkan, DX12, Metal etc).  Unless the game asks for
+        // a particular one, we let WGPU decide by stating PRIMARY.  If none of
+        // those work, as in many VMs and on old hardware, we fall back to GL
+        // and DX11, which includes software drivers such as llvmpipe.
+        let mut candidates = vec![options.backend.bits()];
+        if options.backend == GraphicsBackend::Auto {
+            candidates.push(Backends::SECONDARY);
+        }
+        let mut found = None;
+        for backends in candidates {
+            let instance = Instance::new(backends);
 
-        // This can be unsafe since we know the window has a valid window
-        // handle, otherwise we wouldn't get here.  The surface is an interface
-        // to the OS window that will host the rendering.
-        let surface = unsafe { instance.create_surface(window) };
+            // This can be unsafe since we know the window has a valid window
+            // handle, otherwise we wouldn't get here.  The surface is an
+            // interface to the OS window that will host the rendering.
+            let surface = unsafe { instance.create_surface(&WindowHandle::new(window)) };
 
-        // The adapter represents a physical graphics/compute device.  We need a
-        // device that can handle the surface we will be rendering to.
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::default(),
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .ok_or(RenderError::AdapterNotFound)?;
+            // The adapter represents a physical graphics/compute device.  We
+            // need a device that can handle the surface we will be rendering
+            // to.
+            if let Some(adapter) = choose_adapter(&instance, backends, &surface, options).await {
+                found = Some((surface, adapter));
+                break;
+            }
+            log::warn!("No graphics adapter found for {:?}", backends);
+        }
+        let (surface, adapter) = found.ok_or(RenderError::AdapterNotFound)?;
+        let adapter_info = adapter.get_info();
+        log::info!(
+            "Rendering with {} ({:?})",
+            adapter_info.name,
+            adapter_info.backend
+        );
 
         // Now we create the device and queue from the adapter.  A device is a
         // logical software construct around the physical device.  It serves as
         // the interface for creating many resources.  A queue is used to
         // deliver commands to the GPU to carry out actions, such as writing to
         // texture buffers.
+        //
+        // We ask for the largest textures the adapter supports, as the grid
+        // textures grow with the window.
+        let max_texture_size = adapter.limits().max_texture_dimension_2d;
         let (device, queue) = adapter
             .request_device(
                 &DeviceDescriptor {
                     label: Some("Render device"),
                     features: Features::empty(),
-                    limits: Limits::default(),
+                    limits: Limits {
+                        max_texture_dimension_2d: max_texture_size,
+                        ..Limits::default()
+                    },
                 },
                 None,
             )
             .await?;
+        let (width, height) = fit_to_limits("window", inner_size, max_texture_size);
 
-        // We create the swap chain descriptor that provides the configuration
-        // for creating the swap chain.  However, we keep it around because we
-        // need to recreate the swap chain every time the window resizes.
-        let swapchain_desc = SwapChainDescriptor {
-            usage: TextureUsage::RENDER_ATTACHMENT,
-            format: adapter
-                .get_swap_chain_preferred_format(&surface)
+        // We configure the surface with the frames it hands out, its swap
+        // chain.  The configuration is kept because we need to reconfigure the
+        // surface every time the window resizes.  The adapter's preferred
+        // format comes first.
+        let surface_config = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: *surface
+                .get_supported_formats(&adapter)
+                .first()
                 .ok_or(RenderError::BadSwapChainFormat)?,
-            width: inner_size.width,
-            height: inner_size.height,
+            width,
+            height,
             present_mode: PresentMode::Fifo,
         };
-
-        // Now we create the swap chain that will target a particular surface.
-        let swapchain = device.create_swap_chain(&surface, &swapchain_desc);
+        surface.configure(&device, &surface_config);
 
         // Set up the textures we will use to render the ASCII graphics.  There are four:
         //
@@ -130,25 +344,57 @@ impl RenderState {
         // * Background colours.  Each pixel represents the paper colour of a character on the screen.
         // * ASCII characters.  Each red channel of a pixel represents the ASCII code.
         // * Font texture.  A 16x16 character grid of the font texture.
-        let size = (
-            inner_size.width / font.width,
-            inner_size.height / font.height,
-        );
+        //
+        // A font too big for a texture can't be drawn at all, but the grid
+        // textures are never larger than the swap chain, which is capped.
+        let check = |limit, requested: u32| {
+            if requested > max_texture_size {
+                Err(RenderError::LimitExceeded {
+                    limit,
+                    requested,
+                    max: max_texture_size,
+                })
+            } else {
+                Ok(())
+            }
+        };
+        check("Font texture width", 16 * font.width)?;
+        check("Font texture height", 16 * font.height)?;
+        let (size, offset, scale) =
+            grid_layout(options, (width, height), (font.width, font.height));
         let fg_texture = RogueTexture::new(&device, size);
         let bg_texture = RogueTexture::new(&device, size);
         let chars_texture = RogueTexture::new(&device, size);
-        let mut font_texture = RogueTexture::new(&device, (16 * font.width, 16 * font.height));
+        let light_texture = RogueTexture::new(&device, size);
+        let effects = match &options.effects_shader {
+            Some(source) => Some(EffectsStage::new(&device, source)?),
+            None => None,
+        };
+        let effects_size = match &effects {
+            Some(_) => {
+                let size = effects_size(size, (font.width, font.height));
+                check("Effects texture width", size.width)?;
+                check("Effects texture height", size.height)?;
+                Some((size.width, size.height))
+            }
+            None => None,
+        };
+        let effects_texture = RogueTexture::for_effects(&device, effects_size);
+        //
+        // The font texture also has the fallback glyphs drawn so far, and is
+        // rebuilt when more are needed.  See glyphs.rs.
+        let atlas_generation = font.glyphs().generation();
+        let (atlas, atlas_size) = glyphs::build_atlas(font, max_texture_size);
+        let font_texture = RogueTexture::new(&device, atlas_size);
 
         // Load the font data into the font texture
-        font_texture.storage.copy_from_slice(font.data.as_slice());
-        font_texture.update(&queue);
+        font_texture.update(&queue, atlas.as_slice());
 
         // Now we load the shader in that contains both the vertex and fragment
         // shaders as a single WGSL file.
         let shader_src = include_str!("shader.wgsl");
-        let shader = device.create_shader_module(&ShaderModuleDescriptor {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("ASCII engine shader"),
-            flags: ShaderFlags::all(),
             source: ShaderSource::Wgsl(shader_src.into()),
         });
 
@@ -160,7 +406,7 @@ impl RenderState {
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: ShaderStage::FRAGMENT,
+                        visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Texture {
                             multisampled: false,
                             sample_type: TextureSampleType::Float { filterable: false },
@@ -170,7 +416,7 @@ impl RenderState {
                     },
                     BindGroupLayoutEntry {
                         binding: 1,
-                        visibility: ShaderStage::FRAGMENT,
+                        visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Texture {
                             multisampled: false,
                             sample_type: TextureSampleType::Float { filterable: false },
@@ -180,7 +426,7 @@ impl RenderState {
                     },
                     BindGroupLayoutEntry {
                         binding: 2,
-                        visibility: ShaderStage::FRAGMENT,
+                        visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Texture {
                             multisampled: false,
                             sample_type: TextureSampleType::Float { filterable: false },
@@ -190,7 +436,27 @@ impl RenderState {
                     },
                     BindGroupLayoutEntry {
                         binding: 3,
-                        visibility: ShaderStage::FRAGMENT,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Texture {
                             multisampled: false,
                             sample_type: TextureSampleType::Float { filterable: false },
@@ -203,29 +469,43 @@ impl RenderState {
         let texture_bind_group = Self::create_texture_bind_group(
             &device,
             &texture_bind_group_layout,
-            &fg_texture,
-            &bg_texture,
-            &chars_texture,
-            &font_texture,
+            [
+                &fg_texture,
+                &bg_texture,
+                &chars_texture,
+                &font_texture,
+                &light_texture,
+                &effects_texture,
+            ],
         );
 
         // Next is to create the uniform buffer based on RenderInfo struct.
         let uniforms = RenderInfo {
             font_width: font.width,
             font_height: font.height,
-            _padding: [0; 2],
+            decode_srgb: (surface_config.format.describe().srgb && !options.linear_colours) as u32,
+            caret_style: 0,
+            clear: colour_to_rgba(options.clear_colour),
+            offset,
+            scale,
+            caret_colour: [0.0; 4],
+            caret: [0; 2],
+            caret_own_colour: 0,
+            lighting: 0,
+            effects: effects.is_some() as u32,
+            _padding: [0; 3],
         };
         let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Uniform buffer"),
             contents: cast_slice(&[uniforms]),
-            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("Uniforms bin group layout"),
                 entries: &[BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStage::FRAGMENT,
+                    visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -261,17 +541,17 @@ impl RenderState {
             layout: Some(&render_pipeline_layout),
             vertex: VertexState {
                 module: &shader,
-                entry_point: "main",
+                entry_point: "vs_main",
                 buffers: &[],
             },
             fragment: Some(FragmentState {
                 module: &shader,
-                entry_point: "main",
-                targets: &[ColorTargetState {
-                    format: swapchain_desc.format,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: surface_config.format,
                     blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrite::ALL,
-                }],
+                    write_mask: ColorWrites::ALL,
+                })],
             }),
             primitive: PrimitiveState {
                 topology: PrimitiveTopology::TriangleStrip,
@@ -279,7 +559,7 @@ impl RenderState {
                 front_face: FrontFace::Cw,
                 cull_mode: None,
                 polygon_mode: PolygonMode::Fill,
-                clamp_depth: false,
+                unclipped_depth: false,
                 conservative: false,
             },
             depth_stencil: None,
@@ -288,117 +568,185 @@ impl RenderState {
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
+            multiview: None,
         });
 
         Ok(RenderState {
             surface,
             device,
             queue,
-            swapchain_desc,
-            swapchain,
+            surface_config,
             render_pipeline,
 
             fg_texture,
             bg_texture,
             chars_texture,
+            light_texture,
             font_texture,
+            effects_texture,
+            effects,
             texture_bind_group_layout,
             texture_bind_group,
 
             uniform_bind_group,
+            uniform_buffer,
+            uniforms,
 
             font_char_size: (font.width, font.height),
-            size,
+            zoom: 1,
+            image: font_image(font, size),
+
+            font: font.clone(),
+            lost_frames: 0,
+            max_texture_size,
+            atlas_generation,
+            options: options.clone(),
+            adapter_info,
         })
     }
 
+    // The textures are in binding order: fore, back, text, font, light and
+    // effects.
     fn create_texture_bind_group(
         device: &Device,
         texture_bind_group_layout: &BindGroupLayout,
-        fore_image: &RogueTexture,
-        back_image: &RogueTexture,
-        text_image: &RogueTexture,
-        font_image: &RogueTexture,
+        textures: [&RogueTexture; 6],
     ) -> BindGroup {
+        let views = textures
+            .iter()
+            .map(|texture| {
+                texture
+                    .texture
+                    .create_view(&TextureViewDescriptor::default())
+            })
+            .collect::<Vec<_>>();
+        let entries = views
+            .iter()
+            .enumerate()
+            .map(|(i, view)| BindGroupEntry {
+                binding: i as u32,
+                resource: BindingResource::TextureView(view),
+            })
+            .collect::<Vec<_>>();
         device.create_bind_group(&BindGroupDescriptor {
             label: Some("Texture bind group"),
             layout: texture_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(
-                        &fore_image
-                            .texture
-                            .create_view(&TextureViewDescriptor::default()),
-                    ),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(
-                        &back_image
-                            .texture
-                            .create_view(&TextureViewDescriptor::default()),
-                    ),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: BindingResource::TextureView(
-                        &text_image
-                            .texture
-                            .create_view(&TextureViewDescriptor::default()),
-                    ),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: BindingResource::TextureView(
-                        &font_image
-                            .texture
-                            .create_view(&TextureViewDescriptor::default()),
-                    ),
-                },
-            ],
+            entries: &entries,
         })
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        self.swapchain_desc.width = new_size.width;
-        self.swapchain_desc.height = new_size.height;
-        self.swapchain = self
-            .device
-            .create_swap_chain(&self.surface, &self.swapchain_desc);
+        // Minimised windows have no size and keep their old swap chain until
+        // they are restored.
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        let (width, height) = fit_to_limits("window", new_size, self.max_texture_size);
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        self.surface.configure(&self.device, &self.surface_config);
 
-        let chars_size = (
-            new_size.width / self.font_char_size.0,
-            new_size.height / self.font_char_size.1,
+        let (chars_size, GridPlacement { offset, scale, .. }) = zoomed_layout(
+            &self.options,
+            (width, height),
+            self.font_char_size,
+            self.zoom,
         );
+        if (offset, scale) != (self.uniforms.offset, self.uniforms.scale) {
+            self.uniforms.offset = offset;
+            self.uniforms.scale = scale;
+            self.queue
+                .write_buffer(&self.uniform_buffer, 0, cast_slice(&[self.uniforms]));
+        }
 
-        if chars_size != self.size {
-            self.size = chars_size;
-            self.fg_texture = RogueTexture::new(&self.device, self.size);
-            self.bg_texture = RogueTexture::new(&self.device, self.size);
-            self.chars_texture = RogueTexture::new(&self.device, self.size);
+        if chars_size != self.chars_size() {
+            self.image = font_image(&self.font, chars_size);
+            self.fg_texture = RogueTexture::new(&self.device, chars_size);
+            self.bg_texture = RogueTexture::new(&self.device, chars_size);
+            self.chars_texture = RogueTexture::new(&self.device, chars_size);
+            self.light_texture = RogueTexture::new(&self.device, chars_size);
+            if let Some(effects) = &mut self.effects {
+                let size = effects_size(chars_size, self.font_char_size);
+                let size = fit_to_limits("effects texture", size, self.max_texture_size);
+                self.effects_texture = RogueTexture::for_effects(&self.device, Some(size));
+                effects.bind_group = None;
+            }
 
             self.texture_bind_group = Self::create_texture_bind_group(
                 &self.device,
                 &self.texture_bind_group_layout,
-                &self.fg_texture,
-                &self.bg_texture,
-                &self.chars_texture,
-                &self.font_texture,
+                [
+                    &self.fg_texture,
+                    &self.bg_texture,
+                    &self.chars_texture,
+                    &self.font_texture,
+                    &self.light_texture,
+                    &self.effects_texture,
+                ],
             );
         }
     }
 
-    pub fn render(&mut self) -> Result<(), SwapChainError> {
-        // Update the textures
-        self.fg_texture.update(&self.queue);
-        self.bg_texture.update(&self.queue);
-        self.chars_texture.update(&self.queue);
+    // Draws the font at a whole multiple of its size, so fewer cells fit in
+    // the window.
+    pub fn set_zoom(&mut self, zoom: u32) {
+        let zoom = zoom.max(1);
+        if zoom != self.zoom {
+            self.zoom = zoom;
+            let size = PhysicalSize::new(self.surface_config.width, self.surface_config.height);
+            self.resize(size);
+        }
+    }
+
+    pub fn render(&mut self) -> RenderResult<()> {
+        self.upload();
+        self.draw()
+    }
+
+    // Copies the image into the textures.
+    pub fn upload(&mut self) {
+        if self.font.glyphs().generation() != self.atlas_generation {
+            self.rebuild_atlas();
+        }
+        self.fg_texture
+            .update(&self.queue, self.image.fore_image.as_slice());
+        self.bg_texture
+            .update(&self.queue, self.image.back_image.as_slice());
+        self.chars_texture
+            .update(&self.queue, self.image.text_image.as_slice());
+    }
+
+    fn rebuild_atlas(&mut self) {
+        self.atlas_generation = self.font.glyphs().generation();
+        let (atlas, atlas_size) = glyphs::build_atlas(&self.font, self.max_texture_size);
+        if atlas_size != self.font_texture.size {
+            self.font_texture = RogueTexture::new(&self.device, atlas_size);
+            if let Some(effects) = &mut self.effects {
+                effects.bind_group = None;
+            }
+            self.texture_bind_group = Self::create_texture_bind_group(
+                &self.device,
+                &self.texture_bind_group_layout,
+                [
+                    &self.fg_texture,
+                    &self.bg_texture,
+                    &self.chars_texture,
+                    &self.font_texture,
+                    &self.light_texture,
+                    &self.effects_texture,
+                ],
+            );
+        }
+        self.font_texture.update(&self.queue, atlas.as_slice());
+    }
 
+    // Draws the textures uploaded by upload() to the window.
+    pub fn draw(&mut self) -> RenderResult<()> {
         // First, we fetch the current frame from the swap chain that we will
-        // render to.  The frame will have the view that covers the whole
-        // window.  We will use this later for the render pass.
-        let frame = self.swapchain.get_current_frame()?.output;
+        // render to.  Its view covers the whole window, and we will use it
+        // later for the render pass.
+        let frame = self.surface.get_current_texture()?;
+        let view = frame.texture.create_view(&TextureViewDescriptor::default());
 
         // Now we construct an encoder that acts like a factory for commands to
         // be sent to the device.
@@ -408,23 +756,34 @@ impl RenderState {
                 label: Some("Render encoder"),
             });
 
+        if let Some(effects) = self.effects.as_mut().filter(|effects| effects.enabled) {
+            effects.run(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                [
+                    &self.effects_texture,
+                    &self.fg_texture,
+                    &self.bg_texture,
+                    &self.chars_texture,
+                    &self.font_texture,
+                ],
+                self.font_char_size,
+            );
+        }
+
         {
             // A render pass describes the attachments that will be referenced during rendering.
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: Some("Main render pass"),
-                color_attachments: &[RenderPassColorAttachment {
-                    view: &frame.view,
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &view,
                     resolve_target: None,
                     ops: Operations {
-                        load: LoadOp::Clear(Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
+                        load: LoadOp::Clear(self.clear_colour()),
                         store: true,
                     },
-                }],
+                })],
                 depth_stencil_attachment: None,
             });
 
@@ -435,20 +794,734 @@ impl RenderState {
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
+        frame.present();
+        self.lost_frames = 0;
 
         Ok(())
     }
 
-    pub fn images(&mut self) -> (&mut Vec<u32>, &mut Vec<u32>, &mut Vec<u32>) {
-        (
-            &mut self.fg_texture.storage,
-            &mut self.bg_texture.storage,
-            &mut self.chars_texture.storage,
-        )
+    // Tries to carry on after draw() fails.  A lost or outdated surface (the
+    // window moved to another GPU, or the driver was reset) usually only
+    // needs a new swap chain, but if that keeps failing, or the device runs
+    // out of memory, the device and all its textures are recreated.  The
+    // image is kept, so the next frame looks the same.  Returns an error if
+    // rendering can't continue.
+    pub async fn recover(&mut self, window: &Window, error: RenderError) -> RenderResult<()> {
+        match error {
+            RenderError::Timeout => Ok(()),
+            RenderError::SurfaceLost | RenderError::SurfaceOutdated
+                if self.lost_frames < MAX_LOST_FRAMES =>
+            {
+                self.lost_frames += 1;
+                self.resize(window.inner_size());
+                Ok(())
+            }
+            RenderError::SurfaceLost | RenderError::SurfaceOutdated | RenderError::OutOfMemory => {
+                log::warn!("Recreating the graphics device after: {}", error);
+                let mut render = RenderState::new(window, &self.font, &self.options).await?;
+                render.set_zoom(self.zoom);
+                if render.chars_size() == self.chars_size() {
+                    render.image = replace(&mut self.image, Image::new(0, 0));
+                }
+                *self = render;
+                Ok(())
+            }
+            error => Err(error),
+        }
+    }
+
+    pub fn image(&mut self) -> &mut Image {
+        &mut self.image
+    }
+
+    pub fn chars_size(&self) -> (u32, u32) {
+        (self.image.width, self.image.height)
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) {
+        self.placement().cell_size()
+    }
+
+    // The size of a cell in font pixels, as used by window_to_grid()
+    pub fn font_size(&self) -> (u32, u32) {
+        self.font_char_size
+    }
+
+    pub fn pixel_size(&self) -> (u32, u32) {
+        (self.surface_config.width, self.surface_config.height)
+    }
+
+    // The colour of the window outside the cells, such as the strip at the
+    // right and bottom when the window isn't a whole number of cells.  Its
+    // alpha is used on platforms with transparent windows.
+    pub fn set_clear_colour(&mut self, colour: u32) {
+        if colour != self.options.clear_colour {
+            self.options.clear_colour = colour;
+            self.uniforms.clear = colour_to_rgba(colour);
+            self.queue
+                .write_buffer(&self.uniform_buffer, 0, cast_slice(&[self.uniforms]));
+        }
+    }
+
+    // The light on each cell, multiplied into its colours, or None to draw
+    // the cells as they are.  See light_map.rs.
+    pub fn set_light(&mut self, light: Option<&[u32]>) {
+        let light = light.filter(|light| light.len() == self.image.text_image.len());
+        if let Some(light) = light {
+            self.light_texture.update(&self.queue, light);
+        }
+        let lighting = light.is_some() as u32;
+        if lighting != self.uniforms.lighting {
+            self.uniforms.lighting = lighting;
+            self.queue
+                .write_buffer(&self.uniform_buffer, 0, cast_slice(&[self.uniforms]));
+        }
+    }
+
+    // Runs the effects stage with the given EffectsInfo::params, or hides
+    // its texture and stops running it.  Does nothing without a stage.
+    pub fn set_effects(&mut self, params: Option<[f32; 4]>) {
+        let effects = match &mut self.effects {
+            Some(effects) => effects,
+            None => return,
+        };
+        effects.enabled = params.is_some();
+        if let Some(params) = params {
+            effects.info.params = params;
+        }
+        let enabled = effects.enabled as u32;
+        if enabled != self.uniforms.effects {
+            self.uniforms.effects = enabled;
+            self.queue
+                .write_buffer(&self.uniform_buffer, 0, cast_slice(&[self.uniforms]));
+        }
+    }
+
+    // Draws a caret over a cell, or hides it.  See ui/caret.rs.
+    pub fn set_caret(&mut self, caret: Option<(Point, CaretStyle, Option<u32>)>) {
+        let mut uniforms = self.uniforms;
+        match caret {
+            Some((p, style, colour)) => {
+                uniforms.caret_style = 1 + style as u32;
+                uniforms.caret = [p.x, p.y];
+                uniforms.caret_own_colour = colour.is_some() as u32;
+                uniforms.caret_colour = colour.map_or([0.0; 4], colour_to_rgba);
+            }
+            None => uniforms.caret_style = 0,
+        }
+        if bytemuck::bytes_of(&uniforms) != bytemuck::bytes_of(&self.uniforms) {
+            self.uniforms = uniforms;
+            self.queue
+                .write_buffer(&self.uniform_buffer, 0, cast_slice(&[self.uniforms]));
+        }
+    }
+
+    // The render pass clears with a linear colour.  The shader covers every
+    // pixel, but this is what shows while a resize is catching up.
+    fn clear_colour(&self) -> Color {
+        let [r, g, b, a] = self.uniforms.clear;
+        let decode = |c: f32| {
+            if self.uniforms.decode_srgb == 0 {
+                c
+            } else if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        Color {
+            r: decode(r) as f64,
+            g: decode(g) as f64,
+            b: decode(b) as f64,
+            a: a as f64,
+        }
+    }
+
+    fn placement(&self) -> GridPlacement {
+        GridPlacement {
+            offset: self.uniforms.offset,
+            scale: self.uniforms.scale,
+            font_size: self.font_char_size,
+        }
+    }
+
+    pub fn window_to_grid(&self, p: PhysicalPosition<f64>) -> PhysicalPosition<f64> {
+        self.placement().window_to_grid(p)
+    }
+
+    pub fn cell_to_window(&self, cell: Point) -> PhysicalPosition<f64> {
+        self.placement().cell_to_window(cell)
+    }
+
+    pub fn place_mouse(&self, mouse: &mut MouseState, p: PhysicalPosition<f64>) {
+        self.placement().place_mouse(mouse, p)
+    }
+
+    // Bytes used by the grid's cells, on the CPU and in the GPU textures
+    pub fn grid_memory(&self) -> usize {
+        self.image.memory_size()
+            + self.fg_texture.memory_size()
+            + self.bg_texture.memory_size()
+            + self.chars_texture.memory_size()
+            + self.light_texture.memory_size()
+            + self.effects_texture.memory_size()
+    }
+
+    // Bytes used by the font texture and the copy of the font kept to rebuild
+    // it
+    pub fn atlas_memory(&self) -> usize {
+        self.font_texture.memory_size() + 4 * self.font.data.capacity()
+    }
+
+    pub fn adapter_info(&self) -> &AdapterInfo {
+        &self.adapter_info
+    }
+
+    pub fn graphics_options(&self) -> &GraphicsOptions {
+        &self.options
+    }
+}
+
+//
+// Renderer
+// A window's renderer: the GPU one, or the software rasteriser when there is
+// no graphics adapter to use, as in many VMs and on old hardware
+//
+
+pub enum Renderer {
+    Gpu(Box<RenderState>),
+    Software(Box<SoftwareRenderer>),
+}
+
+// Calls a method of whichever renderer is in use
+macro_rules! either {
+    ($renderer:expr, $render:ident => $call:expr) => {
+        match $renderer {
+            Renderer::Gpu($render) => $call,
+            Renderer::Software($render) => $call,
+        }
+    };
+}
+
+impl Renderer {
+    pub async fn new(
+        window: &Window,
+        font: &RogueFontData,
+        options: &GraphicsOptions,
+    ) -> RenderResult<Self> {
+        match RenderState::new(window, font, options).await {
+            Ok(render) => Ok(Renderer::Gpu(Box::new(render))),
+            // Without the fallback feature, this fails as before
+            Err(RenderError::AdapterNotFound) => {
+                let render = SoftwareRenderer::new(window, font, options)?;
+                log::warn!("No graphics adapter was found, so drawing with the CPU instead");
+                Ok(Renderer::Software(Box::new(render)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // As RenderState::recover().  A GPU renderer whose device can't be
+    // recreated carries on with the software renderer.
+    pub async fn recover(&mut self, window: &Window, error: RenderError) -> RenderResult<()> {
+        let render = match self {
+            Renderer::Gpu(render) => render,
+            Renderer::Software(_) => return Err(error),
+        };
+        match render.recover(window, error).await {
+            Err(RenderError::AdapterNotFound) => {
+                let mut software = SoftwareRenderer::new(window, &render.font, &render.options)?;
+                log::warn!("The graphics adapter has gone, so drawing with the CPU instead");
+                software.set_zoom(render.zoom);
+                if software.chars_size() == render.chars_size() {
+                    *software.image() = replace(&mut render.image, Image::new(0, 0));
+                }
+                *self = Renderer::Software(Box::new(software));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        either!(self, render => render.resize(new_size))
+    }
+
+    pub fn set_zoom(&mut self, zoom: u32) {
+        either!(self, render => render.set_zoom(zoom))
+    }
+
+    pub fn render(&mut self) -> RenderResult<()> {
+        either!(self, render => render.render())
+    }
+
+    pub fn upload(&mut self) {
+        either!(self, render => render.upload())
+    }
+
+    pub fn draw(&mut self) -> RenderResult<()> {
+        either!(self, render => render.draw())
+    }
+
+    pub fn image(&mut self) -> &mut Image {
+        either!(self, render => render.image())
     }
 
     pub fn chars_size(&self) -> (u32, u32) {
-        self.size
+        either!(self, render => render.chars_size())
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) {
+        either!(self, render => render.cell_size())
+    }
+
+    pub fn font_size(&self) -> (u32, u32) {
+        either!(self, render => render.font_size())
+    }
+
+    pub fn pixel_size(&self) -> (u32, u32) {
+        either!(self, render => render.pixel_size())
+    }
+
+    pub fn set_clear_colour(&mut self, colour: u32) {
+        either!(self, render => render.set_clear_colour(colour))
+    }
+
+    pub fn set_light(&mut self, light: Option<&[u32]>) {
+        either!(self, render => render.set_light(light))
+    }
+
+    pub fn set_effects(&mut self, params: Option<[f32; 4]>) {
+        either!(self, render => render.set_effects(params))
+    }
+
+    pub fn set_caret(&mut self, caret: Option<(Point, CaretStyle, Option<u32>)>) {
+        either!(self, render => render.set_caret(caret))
+    }
+
+    pub fn window_to_grid(&self, p: PhysicalPosition<f64>) -> PhysicalPosition<f64> {
+        either!(self, render => render.window_to_grid(p))
+    }
+
+    pub fn cell_to_window(&self, cell: Point) -> PhysicalPosition<f64> {
+        either!(self, render => render.cell_to_window(cell))
+    }
+
+    pub fn place_mouse(&self, mouse: &mut MouseState, p: PhysicalPosition<f64>) {
+        either!(self, render => render.place_mouse(mouse, p))
+    }
+
+    pub fn grid_memory(&self) -> usize {
+        either!(self, render => render.grid_memory())
+    }
+
+    pub fn atlas_memory(&self) -> usize {
+        either!(self, render => render.atlas_memory())
+    }
+
+    pub fn adapter_info(&self) -> &AdapterInfo {
+        either!(self, render => render.adapter_info())
+    }
+
+    pub fn graphics_options(&self) -> &GraphicsOptions {
+        either!(self, render => render.graphics_options())
+    }
+}
+
+//
+// GridPlacement
+// Where the grid is drawn in the window, for converting between the two
+//
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct GridPlacement {
+    // The grid's top-left corner in the window
+    pub offset: [f32; 2],
+    // Window pixels per font pixel
+    pub scale: [f32; 2],
+    pub font_size: (u32, u32),
+}
+
+impl GridPlacement {
+    // The size of a cell on screen, in window pixels, after zooming and
+    // scaling the grid.  It is rounded when the grid is scaled by a fraction.
+    pub(crate) fn cell_size(&self) -> (u32, u32) {
+        let (width, height) = self.font_size;
+        let [scale_x, scale_y] = self.scale;
+        let size = |font: u32, scale: f32| ((font as f32 * scale).round() as u32).max(1);
+        (size(width, scale_x), size(height, scale_y))
+    }
+
+    // Converts a position in the window to one in the grid, in pixels, for
+    // when the grid is centred or scaled.
+    pub(crate) fn window_to_grid(&self, p: PhysicalPosition<f64>) -> PhysicalPosition<f64> {
+        let [x, y] = self.offset;
+        let [scale_x, scale_y] = self.scale;
+        PhysicalPosition::new(
+            (p.x - x as f64) / scale_x as f64,
+            (p.y - y as f64) / scale_y as f64,
+        )
+    }
+
+    // The inverse of window_to_grid(): the window position of a point in the
+    // grid, in font pixels.
+    pub(crate) fn grid_to_window(&self, p: PhysicalPosition<f64>) -> PhysicalPosition<f64> {
+        let [x, y] = self.offset;
+        let [scale_x, scale_y] = self.scale;
+        PhysicalPosition::new(
+            p.x * scale_x as f64 + x as f64,
+            p.y * scale_y as f64 + y as f64,
+        )
+    }
+
+    // The window position of a cell's top-left corner
+    pub(crate) fn cell_to_window(&self, cell: Point) -> PhysicalPosition<f64> {
+        let (width, height) = self.font_size;
+        self.grid_to_window(PhysicalPosition::new(
+            cell.x as f64 * width as f64,
+            cell.y as f64 * height as f64,
+        ))
+    }
+
+    // Moves the mouse to a position in the window, working out where it is
+    // from the grid's top-left corner and which cell it is over.
+    pub(crate) fn place_mouse(&self, mouse: &mut MouseState, p: PhysicalPosition<f64>) {
+        let [x, y] = self.offset;
+        mouse.x = (p.x - x as f64).floor() as i32;
+        mouse.y = (p.y - y as f64).floor() as i32;
+        let grid = self.window_to_grid(p);
+        let (width, height) = self.font_size;
+        mouse.grid_x = (grid.x / width as f64).floor() as i32;
+        mouse.grid_y = (grid.y / height as f64).floor() as i32;
+    }
+}
+
+// The grid's size in cells and its placement in a window, drawing the font
+// at a whole multiple of its size.  A fixed grid is already scaled to fit
+// the window, so isn't zoomed.
+pub(crate) fn zoomed_layout(
+    options: &GraphicsOptions,
+    window_size: (u32, u32),
+    (font_width, font_height): (u32, u32),
+    zoom: u32,
+) -> ((u32, u32), GridPlacement) {
+    let zoom = if options.grid_size.is_some() { 1 } else { zoom };
+    let (chars_size, offset, [scale_x, scale_y]) = grid_layout(
+        options,
+        window_size,
+        (font_width * zoom, font_height * zoom),
+    );
+    let placement = GridPlacement {
+        offset,
+        scale: [scale_x * zoom as f32, scale_y * zoom as f32],
+        font_size: (font_width, font_height),
+    };
+    (chars_size, placement)
+}
+
+// How a grid of whole cells is fitted to the window's pixels.  Returns the
+// size of the grid in cells, the pixel offset of its top-left corner and the
+// scale from grid to window pixels.
+//
+// A fixed grid is scaled by the same amount on both axes and centred.  The
+// scale is a whole number, to keep the font crisp, unless the edge policy is
+// Scale or the window is too small to show the grid at its normal size.
+fn grid_layout(
+    options: &GraphicsOptions,
+    (width, height): (u32, u32),
+    (cell_width, cell_height): (u32, u32),
+) -> ((u32, u32), [f32; 2], [f32; 2]) {
+    let policy = options.edge_policy;
+    if let Some((columns, rows)) = options.grid_size {
+        let (grid_width, grid_height) =
+            ((columns * cell_width) as f32, (rows * cell_height) as f32);
+        let fit = (width as f32 / grid_width).min(height as f32 / grid_height);
+        let scale = if policy == EdgePolicy::Scale || fit < 1.0 {
+            fit
+        } else {
+            fit.floor()
+        };
+        let offset = |size: u32, grid: f32| ((size as f32 - grid * scale) / 2.0).max(0.0).floor();
+        return (
+            (columns, rows),
+            [offset(width, grid_width), offset(height, grid_height)],
+            [scale, scale],
+        );
+    }
+    let (min_x, min_y) = options.min_grid_size.unwrap_or((1, 1));
+    let (max_x, max_y) = options.max_grid_size.unwrap_or((u32::MAX, u32::MAX));
+    let axis = |size: u32, cell: u32, min: u32, max: u32| {
+        let (min, max) = (min.max(1), max.max(min.max(1)));
+        let whole = (size / cell).clamp(min, max);
+        match policy {
+            EdgePolicy::Truncate => (whole, 0.0, 1.0),
+            EdgePolicy::Centre => (whole, (size.saturating_sub(whole * cell) / 2) as f32, 1.0),
+            EdgePolicy::Scale => (whole, 0.0, size as f32 / (whole * cell) as f32),
+            EdgePolicy::Partial => (size.div_ceil(cell).clamp(min, max), 0.0, 1.0),
+        }
+    };
+    let (x_cells, x_offset, x_scale) = axis(width, cell_width, min_x, max_x);
+    let (y_cells, y_offset, y_scale) = axis(height, cell_height, min_y, max_y);
+    ((x_cells, y_cells), [x_offset, y_offset], [x_scale, y_scale])
+}
+
+// Windows larger than the biggest texture the device supports only draw in
+// their top-left corner, rather than failing.
+fn fit_to_limits(what: &str, size: PhysicalSize<u32>, max: u32) -> (u32, u32) {
+    let fitted = (size.width.clamp(1, max), size.height.clamp(1, max));
+    if fitted != (size.width, size.height) {
+        log::warn!(
+            "The {} is {}x{} but the graphics device can only draw {}x{}",
+            what,
+            size.width,
+            size.height,
+            fitted.0,
+            fitted.1
+        );
+    }
+    fitted
+}
+
+// The grid in font pixels, or u32::MAX on an axis too big to count, for
+// fit_to_limits() or the limit checks to catch.
+fn effects_size(chars_size: (u32, u32), cell: (u32, u32)) -> PhysicalSize<u32> {
+    let axis = |cells: u32, pixels: u32| cells.saturating_mul(pixels);
+    PhysicalSize::new(axis(chars_size.0, cell.0), axis(chars_size.1, cell.1))
+}
+
+// The image handed to the game, which uses the font's fallback glyphs
+pub(crate) fn font_image(font: &RogueFontData, (width, height): (u32, u32)) -> Image {
+    let mut image = Image::new(width, height);
+    image.set_glyphs(font.glyphs());
+    image
+}
+
+//
+// GPU effects
+//
+// A game can add a compute shader, written in WGSL, that draws into an effects
+// texture each frame before the cells are drawn.  The texture is the size of
+// the grid in font pixels and is blended over the cells by its alpha, so
+// glows, ripples and scanlines can be drawn without touching the cells.  Set
+// it with RogueBuilder::with_effects_shader().  The shader's entry point is
+// "main", dispatched in 8x8 workgroups over the texture, with these bindings:
+//
+//      struct EffectsInfo {
+//          time: f32,              // Seconds since the stage was created
+//          dt: f32,                // Seconds since the last frame
+//          frame: u32,             // Frames drawn so far
+//          size: vec2<u32>,        // The effects texture in pixels (at byte 16)
+//          grid: vec2<u32>,        // The grid in cells
+//          cell: vec2<u32>,        // A cell in pixels
+//          params: vec4<f32>,      // Set by Context::set_effects_params() (at byte 48)
+//      };
+//
+//      @group(0) @binding(0) var<uniform> info: EffectsInfo;
+//      @group(0) @binding(1) var effects: texture_storage_2d<rgba8unorm, write>;
+//      @group(0) @binding(2) var fore: texture_2d<f32>;    // Ink of each cell
+//      @group(0) @binding(3) var back: texture_2d<f32>;    // Paper of each cell
+//      @group(0) @binding(4) var text: texture_2d<f32>;    // Glyph of each cell
+//      @group(0) @binding(5) var font: texture_2d<f32>;    // The glyph atlas
+//
+//      @compute @workgroup_size(8, 8)
+//      fn main(@builtin(global_invocation_id) id: vec3<u32>) { ... }
+//
+// Every texel of the effects texture should be written each frame.  The
+// caret is drawn over the effects, and the stage is stopped while the crash
+// screen is showing.  The shader is validated when the renderer starts, and a
+// bad one is reported as RenderError::BadShader.  A texture bigger than the
+// graphics device allows is refused at start, and cut to fit after a resize.
+//
+
+const EFFECTS_WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct EffectsInfo {
+    time: f32,
+    dt: f32,
+    frame: u32,
+    _padding: u32,
+    size: [u32; 2],
+    grid: [u32; 2],
+    cell: [u32; 2],
+    _padding2: [u32; 2],
+    params: [f32; 4],
+}
+
+struct EffectsStage {
+    enabled: bool,
+    pipeline: ComputePipeline,
+    bind_group_layout: BindGroupLayout,
+    // Rebuilt when any of the textures it binds is recreated
+    bind_group: Option<BindGroup>,
+    info: EffectsInfo,
+    info_buffer: Buffer,
+    start: Instant,
+    last_frame: Instant,
+}
+
+impl EffectsStage {
+    fn new(device: &Device, source: &str) -> RenderResult<Self> {
+        validate_effects_shader(source)?;
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Effects shader"),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                multisampled: false,
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Effects bind group layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba8Unorm,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+            ],
+        });
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Effects pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Effects pipeline"),
+            layout: Some(&layout),
+            module: &module,
+            entry_point: "main",
+        });
+
+        let info: EffectsInfo = bytemuck::Zeroable::zeroed();
+        let info_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Effects info buffer"),
+            contents: cast_slice(&[info]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let now = Instant::now();
+
+        Ok(EffectsStage {
+            enabled: true,
+            pipeline,
+            bind_group_layout,
+            bind_group: None,
+            info,
+            info_buffer,
+            start: now,
+            last_frame: now,
+        })
+    }
+
+    // Records the compute pass that fills the effects texture, the first of
+    // the textures given, from the grid's textures.
+    fn run(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        textures: [&RogueTexture; 5],
+        cell: (u32, u32),
+    ) {
+        let [effects, ..] = textures;
+        let grid = textures[1].size;
+        let now = Instant::now();
+        self.info.time = (now - self.start).as_secs_f32();
+        self.info.dt = (now - self.last_frame).as_secs_f32();
+        self.info.size = [effects.size.0, effects.size.1];
+        self.info.grid = [grid.0, grid.1];
+        self.info.cell = [cell.0, cell.1];
+        self.last_frame = now;
+        queue.write_buffer(&self.info_buffer, 0, cast_slice(&[self.info]));
+        self.info.frame = self.info.frame.wrapping_add(1);
+
+        let info_buffer = &self.info_buffer;
+        let layout = &self.bind_group_layout;
+        let bind_group = self.bind_group.get_or_insert_with(|| {
+            let views = textures
+                .iter()
+                .map(|texture| {
+                    texture
+                        .texture
+                        .create_view(&TextureViewDescriptor::default())
+                })
+                .collect::<Vec<_>>();
+            let mut entries = vec![BindGroupEntry {
+                binding: 0,
+                resource: info_buffer.as_entire_binding(),
+            }];
+            entries.extend(views.iter().enumerate().map(|(i, view)| BindGroupEntry {
+                binding: i as u32 + 1,
+                resource: BindingResource::TextureView(view),
+            }));
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Effects bind group"),
+                layout,
+                entries: &entries,
+            })
+        });
+
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Effects pass"),
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(
+            effects.size.0.div_ceil(EFFECTS_WORKGROUP_SIZE),
+            effects.size.1.div_ceil(EFFECTS_WORKGROUP_SIZE),
+            1,
+        );
+    }
+}
+
+// wgpu only reports a bad shader through its error callback, which panics, so
+// the game's shader is checked first.
+fn validate_effects_shader(source: &str) -> RenderResult<()> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|err| RenderError::BadShader(err.emit_to_string(source)))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|err| RenderError::BadShader(err.to_string()))?;
+    let has_main = module
+        .entry_points
+        .iter()
+        .any(|entry| entry.name == "main" && entry.stage == naga::ShaderStage::Compute);
+    if has_main {
+        Ok(())
+    } else {
+        Err(RenderError::BadShader(String::from(
+            "there is no compute entry point called main",
+        )))
     }
 }
 
@@ -458,15 +1531,30 @@ impl RenderState {
 
 pub struct RogueTexture {
     pub size: (u32, u32),
-    pub storage: Vec<u32>,
     texture: Texture,
 }
 
 impl RogueTexture {
     fn new(device: &Device, size: (u32, u32)) -> Self {
-        let vec_size = (size.0 * size.1) as usize;
-        let storage = vec![0; vec_size];
+        Self::with_usage(
+            device,
+            size,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        )
+    }
 
+    // Written by the effects shader, or a 1x1 placeholder without one
+    fn for_effects(device: &Device, size: Option<(u32, u32)>) -> Self {
+        match size {
+            Some(size) => {
+                let usage = TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING;
+                Self::with_usage(device, size, usage)
+            }
+            None => Self::new(device, (1, 1)),
+        }
+    }
+
+    fn with_usage(device: &Device, size: (u32, u32), usage: TextureUsages) -> Self {
         let texture_size = Extent3d {
             width: size.0,
             height: size.1,
@@ -480,25 +1568,26 @@ impl RogueTexture {
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+            usage,
         });
 
-        RogueTexture {
-            size,
-            texture,
-            storage,
-        }
+        RogueTexture { size, texture }
     }
 
-    fn update(&mut self, queue: &Queue) {
+    fn memory_size(&self) -> usize {
+        4 * (self.size.0 * self.size.1) as usize
+    }
+
+    fn update(&self, queue: &Queue, data: &[u32]) {
         let (width, height) = self.size;
         queue.write_texture(
             ImageCopyTexture {
                 texture: &self.texture,
                 mip_level: 0,
                 origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
             },
-            cast_slice(&self.storage),
+            cast_slice(data),
             ImageDataLayout {
                 offset: 0,
                 bytes_per_row: NonZeroU32::new(4 * width),
@@ -518,5 +1607,136 @@ impl RogueTexture {
 struct RenderInfo {
     font_width: u32,  // Width of the font characters
     font_height: u32, // Height of the font characters
-    _padding: [u32; 2],
+    decode_srgb: u32, // Non-zero to convert colours from sRGB to linear
+    caret_style: u32, // 0 for no caret, or 1 + CaretStyle
+    clear: [f32; 4],  // Colour of the area not covered by whole cells
+    offset: [f32; 2], // Position of the grid in the window
+    scale: [f32; 2],  // Window pixels per grid pixel
+    caret_colour: [f32; 4],
+    caret: [i32; 2],       // The caret's cell
+    caret_own_colour: u32, // Non-zero to use caret_colour, not the cell's ink
+    lighting: u32,         // Non-zero to multiply the colours by the light texture
+    effects: u32,          // Non-zero to blend the effects texture over the cells
+    _padding: [u32; 3],
+}
+
+fn colour_to_rgba(colour: u32) -> [f32; 4] {
+    let channel = |shift: u32| ((colour >> shift) & 0xff) as f32 / 255.0;
+    [channel(0), channel(8), channel(16), channel(24)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(edge_policy: EdgePolicy) -> GraphicsOptions {
+        GraphicsOptions {
+            edge_policy,
+            ..GraphicsOptions::default()
+        }
+    }
+
+    #[test]
+    fn edge_policies_share_out_the_left_over_pixels() {
+        // 103x50 pixels of 10x10 cells leaves 3 pixels across
+        let size = (103, 50);
+        let cell = (10, 10);
+        let layout = |policy| grid_layout(&options(policy), size, cell);
+        assert_eq!(
+            layout(EdgePolicy::Truncate),
+            ((10, 5), [0.0, 0.0], [1.0, 1.0])
+        );
+        assert_eq!(
+            layout(EdgePolicy::Centre),
+            ((10, 5), [1.0, 0.0], [1.0, 1.0])
+        );
+        assert_eq!(
+            layout(EdgePolicy::Scale),
+            ((10, 5), [0.0, 0.0], [1.03, 1.0])
+        );
+        assert_eq!(
+            layout(EdgePolicy::Partial),
+            ((11, 5), [0.0, 0.0], [1.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn grids_that_follow_the_window_are_limited() {
+        let mut options = options(EdgePolicy::Centre);
+        options.min_grid_size = Some((20, 0));
+        options.max_grid_size = Some((40, 3));
+        let (cells, offset, _) = grid_layout(&options, (100, 100), (10, 10));
+        // Too narrow for the minimum, so nothing is left to centre across
+        assert_eq!(cells, (20, 3));
+        assert_eq!(offset, [0.0, 35.0]);
+
+        // Tiny windows still get a cell
+        let (cells, _, _) = grid_layout(&GraphicsOptions::default(), (3, 0), (10, 10));
+        assert_eq!(cells, (1, 1));
+    }
+
+    #[test]
+    fn fixed_grids_are_scaled_and_centred() {
+        let mut options = options(EdgePolicy::Truncate);
+        options.grid_size = Some((4, 2));
+        // Room for 2.5 times the 40x20 grid, so it is drawn twice its size
+        let layout = grid_layout(&options, (100, 60), (10, 10));
+        assert_eq!(layout, ((4, 2), [10.0, 10.0], [2.0, 2.0]));
+
+        options.edge_policy = EdgePolicy::Scale;
+        let layout = grid_layout(&options, (100, 60), (10, 10));
+        assert_eq!(layout, ((4, 2), [0.0, 5.0], [2.5, 2.5]));
+
+        // Windows too small for the grid shrink it whatever the policy
+        options.edge_policy = EdgePolicy::Truncate;
+        let layout = grid_layout(&options, (20, 20), (10, 10));
+        assert_eq!(layout, ((4, 2), [0.0, 5.0], [0.5, 0.5]));
+    }
+
+    #[test]
+    fn oversized_effects_textures_are_cut_to_fit() {
+        assert_eq!(effects_size((80, 25), (8, 16)), PhysicalSize::new(640, 400));
+        let huge = effects_size((u32::MAX / 2, 3), (8, 16));
+        assert_eq!(huge, PhysicalSize::new(u32::MAX, 48));
+        assert_eq!(fit_to_limits("effects texture", huge, 8192), (8192, 48));
+    }
+
+    #[test]
+    fn zooming_draws_bigger_cells() {
+        let options = options(EdgePolicy::Centre);
+        let (cells, placement) = zoomed_layout(&options, (100, 50), (8, 8), 2);
+        assert_eq!(cells, (6, 3));
+        assert_eq!(placement.offset, [2.0, 1.0]);
+        assert_eq!(placement.cell_size(), (16, 16));
+
+        // Fixed grids ignore the zoom
+        let mut options = options;
+        options.grid_size = Some((5, 5));
+        let (cells, placement) = zoomed_layout(&options, (100, 50), (8, 8), 2);
+        assert_eq!(cells, (5, 5));
+        assert_eq!(placement.scale, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn window_and_grid_positions_convert_both_ways() {
+        let placement = GridPlacement {
+            offset: [10.0, 20.0],
+            scale: [2.0, 3.0],
+            font_size: (8, 16),
+        };
+        let corner = placement.cell_to_window(Point::new(2, 1));
+        assert_eq!(corner, PhysicalPosition::new(42.0, 68.0));
+        assert_eq!(
+            placement.window_to_grid(corner),
+            PhysicalPosition::new(16.0, 16.0)
+        );
+
+        let mut mouse = MouseState::default();
+        placement.place_mouse(&mut mouse, PhysicalPosition::new(41.5, 67.0));
+        assert_eq!((mouse.x, mouse.y), (31, 47));
+        assert_eq!((mouse.grid_x, mouse.grid_y), (1, 0));
+        // Left of and above the grid
+        placement.place_mouse(&mut mouse, PhysicalPosition::new(0.0, 0.0));
+        assert_eq!((mouse.grid_x, mouse.grid_y), (-1, -1));
+    }
 }
@@ -0,0 +1,245 @@
+//
+// Random numbers
+//
+// A seedable generator whose sequence depends only on its seed, so a run can
+// be replayed from the seed on any platform and with any version of the rand
+// crate.  It implements rand::RngCore, so rand's distributions can be used
+// with it too.
+//
+// Separate streams can be split off by name (e.g. "names" or "loot") so that
+// adding a call in one system doesn't change the results of another.
+//
+
+use crate::{parse_seed, seed_to_words};
+use rand::{Error, RngCore};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GameRng {
+    seed: u64,
+    // xoshiro256** state
+    state: [u64; 4],
+}
+
+fn splitmix64(x: &mut u64) -> u64 {
+    *x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// FNV-1a, used to turn stream names and seed text into seeds
+pub(crate) fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        let mut x = seed;
+        let state = [
+            splitmix64(&mut x),
+            splitmix64(&mut x),
+            splitmix64(&mut x),
+            splitmix64(&mut x),
+        ];
+        GameRng { seed, state }
+    }
+
+    // Seeded from the clock, for games that don't need to replay runs
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        GameRng::new(nanos)
+    }
+
+    // Seeded from text typed by the player.  See parse_seed().
+    pub fn from_seed_str(text: &str) -> Self {
+        GameRng::new(parse_seed(text))
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // The seed as words, for showing to the player
+    pub fn seed_words(&self) -> String {
+        seed_to_words(self.seed)
+    }
+
+    // A generator for one system, derived from the seed and the name but not
+    // from how much this generator has been used.
+    pub fn stream(&self, name: &str) -> GameRng {
+        GameRng::new(self.seed ^ hash_name(name))
+    }
+
+    //
+    // Helpers
+    //
+
+    // A number in the range [low, high).  Returns low if the range is empty.
+    pub fn range(&mut self, low: i32, high: i32) -> i32 {
+        if high <= low {
+            return low;
+        }
+        let span = (high as i64 - low as i64) as u64;
+        (low as i64 + self.below(span) as i64) as i32
+    }
+
+    // A number in [0, n), without modulo bias
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+
+    // A number in [0, 1)
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+
+    // Rolls `count` dice with `sides` sides each and adds them up.
+    pub fn roll(&mut self, count: u32, sides: u32) -> u32 {
+        (0..count)
+            .map(|_| self.below(sides as u64) as u32 + 1)
+            .sum()
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[self.below(items.len() as u64) as usize])
+        }
+    }
+
+    // Picks an index with probability proportional to its weight.  Returns
+    // None if all the weights are zero.
+    pub fn weighted(&mut self, weights: &[u32]) -> Option<usize> {
+        let total: u64 = weights.iter().map(|&w| w as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = self.below(total);
+        for (i, &weight) in weights.iter().enumerate() {
+            if pick < weight as u64 {
+                return Some(i);
+            }
+            pick -= weight as u64;
+        }
+        None
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_depend_only_on_the_seed() {
+        // The first output of SplitMix64 seeded with 0
+        assert_eq!(splitmix64(&mut 0), 0xe220_a839_7b1d_cdaf);
+        let mut a = GameRng::new(42);
+        let mut b = GameRng::new(42);
+        let first = (0..8).map(|_| a.next_u64()).collect::<Vec<_>>();
+        assert_eq!(first, (0..8).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first[0], GameRng::new(43).next_u64());
+        assert_eq!(a.seed(), 42);
+    }
+
+    #[test]
+    fn streams_ignore_how_much_the_generator_was_used() {
+        let mut rng = GameRng::new(7);
+        let loot = rng.stream("loot");
+        rng.next_u64();
+        assert_eq!(rng.stream("loot"), loot);
+        assert_ne!(rng.stream("names"), loot);
+        assert_eq!(hash_name(""), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn helpers_stay_in_their_ranges() {
+        let mut rng = GameRng::new(1);
+        for _ in 0..1000 {
+            assert!((-3..4).contains(&rng.range(-3, 4)));
+            assert!(rng.below(10) < 10);
+            assert!((0.0..1.0).contains(&rng.unit()));
+            assert!((3..=18).contains(&rng.roll(3, 6)));
+            assert_eq!(rng.weighted(&[0, 5, 0]), Some(1));
+        }
+        assert_eq!(rng.range(5, 5), 5);
+        assert!(rng.range(i32::MIN, i32::MAX) < i32::MAX);
+        assert_eq!(rng.below(0), 0);
+        assert!(!rng.chance(0.0));
+        assert!(rng.chance(1.0));
+        assert_eq!(rng.weighted(&[0, 0]), None);
+        assert_eq!(rng.choose::<u8>(&[]), None);
+        assert_eq!(rng.choose(&[9]), Some(&9));
+    }
+
+    #[test]
+    fn shuffling_keeps_every_item() {
+        let mut rng = GameRng::new(3);
+        let mut items = (0..20).collect::<Vec<_>>();
+        rng.shuffle(&mut items);
+        assert_ne!(items, (0..20).collect::<Vec<_>>());
+        items.sort_unstable();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+
+        let mut bytes = [0; 11];
+        rng.fill_bytes(&mut bytes);
+        assert!(bytes.iter().any(|&b| b != 0));
+    }
+}
@@ -0,0 +1,237 @@
+//
+// Save games
+//
+// Saves are stamped with the version of the game's save format, so the game
+// can change its state types without breaking its players' saves.  Each time
+// the format changes, the game bumps the version and registers a migration
+// that turns a save of the old version into the new one:
+//
+//      let mut saves = SaveSchema::new(3);
+//      saves
+//          .with_migration(1, |data| add_gold_field(data))
+//          .with_migration(2, |data| split_inventory(data));
+//
+// Loading a version 1 save then runs both migrations in turn before handing
+// the data back.  The data is whatever bytes the game saves its state as, e.g.
+// bincode or RON, so a migration usually decodes the old types, converts them
+// and encodes the new ones.  A save that is newer than the game, or has no
+// way to the current version, is reported rather than loaded.
+//
+// A save file is "MAGESAVE", the version as a little-endian u32 and then the
+// data.  Files are written to a temporary file first and then renamed, so a
+// crash while saving leaves the old save intact.
+//
+
+use crate::{RogueError, RogueResult};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 8] = b"MAGESAVE";
+
+type Migration = Box<dyn Fn(Vec<u8>) -> Result<Vec<u8>, String>>;
+
+pub struct SaveSchema {
+    version: u32,
+    // Keyed by the version each migrates from, to the next version
+    migrations: HashMap<u32, Migration>,
+}
+
+impl SaveSchema {
+    // The version of the saves the game writes now
+    pub fn new(version: u32) -> Self {
+        SaveSchema {
+            version,
+            migrations: HashMap::new(),
+        }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    // Registers the migration from a version to the one after it.  A
+    // migration can fail with a message, e.g. when the old data doesn't
+    // decode.
+    pub fn with_migration<F>(&mut self, from: u32, migration: F) -> &mut Self
+    where
+        F: Fn(Vec<u8>) -> Result<Vec<u8>, String> + 'static,
+    {
+        self.migrations.insert(from, Box::new(migration));
+        self
+    }
+
+    // Whether a save of the version can be loaded
+    pub fn can_load(&self, version: u32) -> bool {
+        version <= self.version && (version..self.version).all(|v| self.migrations.contains_key(&v))
+    }
+
+    // Brings data saved with an older version up to date.
+    pub fn migrate(&self, version: u32, mut data: Vec<u8>) -> RogueResult<Vec<u8>> {
+        if version > self.version {
+            return Err(RogueError::SaveTooNew {
+                version,
+                current: self.version,
+            });
+        }
+        if let Some(from) = (version..self.version).find(|v| !self.migrations.contains_key(v)) {
+            return Err(RogueError::MissingMigration { from, to: from + 1 });
+        }
+        for from in version..self.version {
+            data = self.migrations[&from](data)
+                .map_err(|message| RogueError::MigrationFailed { from, message })?;
+        }
+        Ok(data)
+    }
+
+    //
+    // Save files
+    //
+
+    // The data stamped with the current version
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        encode_save(self.version, data)
+    }
+
+    // The data from a save made by encode(), migrated to the current version
+    pub fn decode(&self, bytes: &[u8]) -> RogueResult<Vec<u8>> {
+        let (version, data) = save_version(bytes)?;
+        self.migrate(version, data.to_vec())
+    }
+
+    pub fn save(&self, path: &Path, data: &[u8]) -> RogueResult<()> {
+        write_save_file(path, &self.encode(data))
+    }
+
+    pub fn load(&self, path: &Path) -> RogueResult<Vec<u8>> {
+        self.decode(&fs::read(path)?)
+    }
+}
+
+// The bytes of a save file holding the data
+pub fn encode_save(version: u32, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+// Replaces the file through a temporary file that is flushed to disk before
+// it is renamed over the old one.
+pub(crate) fn write_save_file(path: &Path, bytes: &[u8]) -> RogueResult<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temp = PathBuf::from(path);
+    temp.set_extension("tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+// The version a save was made with and its data, without migrating it, e.g.
+// to show old saves in a load menu.
+pub fn save_version(bytes: &[u8]) -> RogueResult<(u32, &[u8])> {
+    if bytes.len() < MAGIC.len() + 4 || !bytes.starts_with(MAGIC) {
+        return Err(RogueError::BadSave);
+    }
+    let (version, data) = bytes[MAGIC.len()..].split_at(4);
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    Ok((version, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each migration appends the version it migrates to
+    fn schema() -> SaveSchema {
+        let mut saves = SaveSchema::new(3);
+        saves
+            .with_migration(1, |mut data| {
+                data.push(2);
+                Ok(data)
+            })
+            .with_migration(2, |mut data| {
+                data.push(3);
+                Ok(data)
+            });
+        saves
+    }
+
+    #[test]
+    fn migrations_run_in_order() {
+        let saves = schema();
+        assert_eq!(saves.migrate(1, vec![1]).unwrap(), vec![1, 2, 3]);
+        assert_eq!(saves.migrate(2, vec![2]).unwrap(), vec![2, 3]);
+        assert_eq!(saves.migrate(3, vec![3]).unwrap(), vec![3]);
+        assert!(saves.can_load(1) && saves.can_load(3));
+    }
+
+    #[test]
+    fn saves_that_cant_be_migrated_are_reported() {
+        let saves = schema();
+        assert!(!saves.can_load(4) && !saves.can_load(0));
+        assert!(matches!(
+            saves.migrate(4, vec![]),
+            Err(RogueError::SaveTooNew {
+                version: 4,
+                current: 3
+            })
+        ));
+        assert!(matches!(
+            saves.migrate(0, vec![]),
+            Err(RogueError::MissingMigration { from: 0, to: 1 })
+        ));
+
+        let mut saves = schema();
+        saves.with_migration(2, |_| Err(String::from("no inventory")));
+        match saves.migrate(1, vec![1]) {
+            Err(RogueError::MigrationFailed { from, message }) => {
+                assert_eq!((from, message.as_str()), (2, "no inventory"));
+            }
+            _ => panic!("the migration should fail"),
+        }
+    }
+
+    #[test]
+    fn saves_are_stamped_with_their_version() {
+        let bytes = encode_save(2, b"data");
+        assert_eq!(&bytes[..8], MAGIC);
+        assert_eq!(save_version(&bytes).unwrap(), (2, &b"data"[..]));
+        assert_eq!(schema().decode(&bytes).unwrap(), b"data\x03");
+
+        assert!(matches!(
+            save_version(&bytes[..11]),
+            Err(RogueError::BadSave)
+        ));
+        assert!(matches!(
+            save_version(b"NOTASAVE\0\0\0\0"),
+            Err(RogueError::BadSave)
+        ));
+        // An empty save is still a save
+        assert_eq!(save_version(&bytes[..12]).unwrap(), (2, &[][..]));
+    }
+
+    #[test]
+    fn save_files_are_replaced() {
+        let dir = std::env::temp_dir().join(format!("mage-save-{}", std::process::id()));
+        let path = dir.join("saves").join("game.sav");
+        let saves = schema();
+        saves.save(&path, b"first").unwrap();
+        saves.save(&path, b"second").unwrap();
+        let loaded = saves.load(&path);
+        let temp = path.with_extension("tmp").exists();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(loaded.unwrap(), b"second");
+        assert!(!temp);
+    }
+}
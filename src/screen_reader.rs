@@ -0,0 +1,233 @@
+//
+// Screen reader support
+//
+// When enabled, the engine keeps a plain text description of each frame for
+// players using a screen reader or text-to-speech.  The game registers the
+// regions worth reading each frame, naming them and either letting the engine
+// read their text off the frame or giving the text itself (e.g. a description
+// of the map instead of its glyphs):
+//
+//      reader.clear();
+//      reader.add_region("Status", status_rect);
+//      reader.add_text("Map", map_rect, &describe_surroundings());
+//
+// Regions are read top to bottom, then left to right, with borders and other
+// line-drawing characters left out.  The engine adds the tooltip, and
+// announces notifications.
+//
+// The game, or a text-to-speech crate it hooks up, drains the updates: the
+// whole description when it changes, and one-off announcements such as
+// notifications as they happen.
+//
+
+use crate::{Image, Point, Rect, WIDE_CONTINUATION};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScreenReaderUpdate {
+    Frame(String),
+    Announcement(String),
+}
+
+struct ReadRegion {
+    name: String,
+    rect: Rect,
+    // Given by the game instead of being read off the frame
+    text: Option<String>,
+}
+
+pub struct ScreenReader {
+    enabled: bool,
+    regions: Vec<ReadRegion>,
+    text: String,
+    updates: Vec<ScreenReaderUpdate>,
+}
+
+impl ScreenReader {
+    pub fn new() -> Self {
+        ScreenReader {
+            enabled: false,
+            regions: Vec::new(),
+            text: String::new(),
+            updates: Vec::new(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.text.clear();
+            self.updates.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    //
+    // Regions
+    // Rebuilt by the game each frame, like HitRegions.
+    //
+
+    pub fn clear(&mut self) {
+        self.regions.clear();
+    }
+
+    pub fn add_region(&mut self, name: &str, rect: Rect) {
+        self.regions.push(ReadRegion {
+            name: String::from(name),
+            rect,
+            text: None,
+        });
+    }
+
+    pub fn add_text(&mut self, name: &str, rect: Rect, text: &str) {
+        self.regions.push(ReadRegion {
+            name: String::from(name),
+            rect,
+            text: Some(String::from(text)),
+        });
+    }
+
+    //
+    // Output
+    //
+
+    // Something to read out straight away, e.g. "The door is locked"
+    pub fn announce(&mut self, text: &str) {
+        if self.enabled {
+            self.updates
+                .push(ScreenReaderUpdate::Announcement(String::from(text)));
+        }
+    }
+
+    // The description of the last frame, one region per paragraph
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // Updates since the last drain, oldest first
+    pub fn drain(&mut self) -> Vec<ScreenReaderUpdate> {
+        std::mem::take(&mut self.updates)
+    }
+
+    // Called by the engine with the finished frame.  extra are regions the
+    // engine adds, such as the tooltip.
+    pub(crate) fn update(&mut self, image: &Image, extra: &[(&str, String)]) {
+        if !self.enabled {
+            return;
+        }
+        let mut regions = self.regions.iter().collect::<Vec<_>>();
+        regions.sort_by_key(|region| (region.rect.y, region.rect.x));
+
+        let mut paragraphs = regions
+            .iter()
+            .map(|region| {
+                let text = match &region.text {
+                    Some(text) => text.clone(),
+                    None => read_rect(image, region.rect),
+                };
+                (region.name.as_str(), text)
+            })
+            .chain(extra.iter().map(|(name, text)| (*name, text.clone())))
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(name, text)| format!("{}:\n{}", name, text.trim()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        paragraphs.push('\n');
+
+        if paragraphs != self.text {
+            self.text = paragraphs;
+            self.updates
+                .push(ScreenReaderUpdate::Frame(self.text.clone()));
+        }
+    }
+}
+
+// The text in a rectangle of the frame, one line per row.  Line-drawing
+// characters become spaces, runs of spaces are squashed, and blank rows are
+// left out.
+fn read_rect(image: &Image, rect: Rect) -> String {
+    let (x, y, width, height) = image.clip(Point::new(rect.x, rect.y), rect.width, rect.height);
+    let mut lines = Vec::new();
+    for row in y..y + height {
+        let start = match image.coords_to_index(x, row) {
+            Some(i) => i,
+            None => continue,
+        };
+        let line = image.text_image[start..start + width as usize]
+            .iter()
+            .filter(|&&code| code & WIDE_CONTINUATION == 0)
+            .map(|&code| match image.glyph_char(code) {
+                '\u{2500}'..='\u{259f}' => ' ',
+                ch => ch,
+            })
+            .collect::<String>();
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+impl Default for ScreenReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NinePatch;
+
+    fn frame() -> Image {
+        let mut image = Image::new(20, 5);
+        image.clear(0, 0);
+        image.draw_panel(Point::new(0, 0), 12, 3, &NinePatch::single(1, 0));
+        image.draw_string(Point::new(1, 1), "HP    10", 1, 0);
+        image.draw_string(Point::new(0, 4), "####", 1, 0);
+        image
+    }
+
+    #[test]
+    fn regions_are_read_top_to_bottom_without_borders() {
+        let mut reader = ScreenReader::new();
+        reader.set_enabled(true);
+        reader.add_text("Map", Rect::new(0, 4, 4, 1), "A dark room");
+        reader.add_region("Status", Rect::new(0, 0, 12, 3));
+        reader.add_region("Empty", Rect::new(14, 0, 6, 4));
+
+        let extra = [("Tooltip", String::from("A rat"))];
+        reader.update(&frame(), &extra);
+        reader.update(&frame(), &extra);
+        reader.announce("The door is locked");
+        let text = "Status:\nHP 10\n\nMap:\nA dark room\n\nTooltip:\nA rat\n";
+        assert_eq!(reader.text(), text);
+        assert_eq!(
+            reader.drain(),
+            [
+                ScreenReaderUpdate::Frame(String::from(text)),
+                ScreenReaderUpdate::Announcement(String::from("The door is locked")),
+            ]
+        );
+        assert!(reader.drain().is_empty());
+    }
+
+    #[test]
+    fn nothing_is_read_while_disabled() {
+        let mut reader = ScreenReader::default();
+        reader.add_region("Status", Rect::new(0, 0, 12, 3));
+        reader.update(&frame(), &[]);
+        reader.announce("Hello");
+        assert_eq!(reader.text(), "");
+        assert!(reader.drain().is_empty());
+
+        reader.set_enabled(true);
+        reader.update(&frame(), &[]);
+        reader.set_enabled(false);
+        assert_eq!(reader.text(), "");
+        assert!(reader.drain().is_empty());
+    }
+}
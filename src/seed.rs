@@ -0,0 +1,108 @@
+//
+// Seed strings
+//
+// Seeds written as words so players can read them out, type them in and share
+// them, e.g. "lynx-amber-tide".  Each word is a byte of the seed, most
+// significant first, and leading zero bytes are left out so small seeds give
+// short strings.
+//
+// parse_seed() takes anything a player might type as a seed: words, a number,
+// or any other text, which is hashed.
+//
+
+use crate::rng::hash_name;
+
+const SEED_WORDS: [&str; 256] = [
+    "acorn", "acre", "amber", "anchor", "apple", "arrow", "ash", "aspen", "aurora", "autumn",
+    "badger", "bamboo", "bark", "basin", "bat", "bay", "beach", "bear", "bee", "beetle", "bell",
+    "berry", "birch", "bison", "blade", "bloom", "boat", "bolt", "bone", "brass", "bread", "brick",
+    "bridge", "brook", "broom", "cabin", "cactus", "camel", "canary", "candle", "canoe", "canyon",
+    "cape", "cedar", "chalk", "cherry", "chess", "cider", "cinder", "clay", "cliff", "cloak",
+    "cloud", "clover", "coal", "cobalt", "cobra", "comet", "copper", "coral", "cotton", "crab",
+    "crane", "cricket", "crow", "crown", "crystal", "cup", "daisy", "dawn", "deer", "desert",
+    "dew", "dingo", "dove", "dragon", "drum", "dune", "dusk", "eagle", "earth", "echo", "elk",
+    "elm", "ember", "emerald", "falcon", "fern", "ferret", "field", "fig", "finch", "fire",
+    "fjord", "flame", "flint", "forest", "fossil", "fox", "frost", "garden", "garnet", "gecko",
+    "geyser", "ghost", "ginger", "glacier", "goat", "gold", "goose", "granite", "grape", "gull",
+    "hare", "harp", "hawk", "hazel", "heath", "heron", "hill", "holly", "honey", "horn", "hornet",
+    "horse", "ice", "iris", "iron", "island", "ivory", "ivy", "jade", "jaguar", "jasper", "jelly",
+    "jewel", "kelp", "kettle", "kiwi", "koala", "lagoon", "lake", "lantern", "lark", "lava",
+    "leaf", "lemon", "lily", "lime", "lion", "lizard", "llama", "lotus", "lynx", "mango", "maple",
+    "marble", "marsh", "meadow", "melon", "meteor", "mint", "mist", "mole", "moon", "moose",
+    "moss", "moth", "mouse", "mule", "nectar", "nettle", "newt", "night", "nova", "nut", "oak",
+    "oasis", "ocean", "olive", "onyx", "opal", "orange", "orchid", "otter", "owl", "oyster",
+    "panda", "pearl", "pebble", "pepper", "pine", "plum", "pond", "poppy", "quail", "quartz",
+    "rabbit", "raven", "reed", "ridge", "river", "robin", "rose", "ruby", "rust", "sage", "salt",
+    "sand", "seal", "shadow", "shell", "silver", "sky", "slate", "snail", "snow", "sparrow",
+    "spider", "spruce", "squid", "star", "stone", "storm", "straw", "sun", "swan", "thistle",
+    "thorn", "thunder", "tide", "tiger", "timber", "toad", "topaz", "tulip", "tundra", "turtle",
+    "valley", "velvet", "violet", "viper", "walnut", "wasp", "water", "wave", "whale", "wheat",
+    "willow", "wind", "wolf", "wren", "yak", "yew", "zebra", "zinc",
+];
+
+pub fn seed_to_words(seed: u64) -> String {
+    let bytes = seed.to_be_bytes();
+    let first = bytes.iter().position(|&b| b != 0).unwrap_or(7);
+    bytes[first..]
+        .iter()
+        .map(|&b| SEED_WORDS[b as usize])
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Reads a string made by seed_to_words().  Case is ignored, and the words can
+// be separated by dashes, spaces or underscores.  Returns None unless every
+// word is in the list and there are 1 to 8 of them.
+pub fn seed_from_words(text: &str) -> Option<u64> {
+    let words = text
+        .split(|c: char| c == '-' || c == '_' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    if words.is_empty() || words.len() > 8 {
+        return None;
+    }
+    words.iter().try_fold(0u64, |seed, word| {
+        let word = word.to_lowercase();
+        let byte = SEED_WORDS.binary_search(&word.as_str()).ok()?;
+        Some(seed << 8 | byte as u64)
+    })
+}
+
+pub fn parse_seed(text: &str) -> u64 {
+    let text = text.trim();
+    seed_from_words(text)
+        .or_else(|| text.parse().ok())
+        .unwrap_or_else(|| hash_name(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_word_list_is_sorted_for_searching() {
+        assert!(SEED_WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn seeds_round_trip_through_words() {
+        assert_eq!(seed_to_words(0), "acorn");
+        assert_eq!(seed_to_words(0x0102), "acre-amber");
+        assert_eq!(seed_to_words(u64::MAX).split('-').count(), 8);
+        for seed in [0, 1, 0xff, 0x1234_5678, u64::MAX] {
+            assert_eq!(seed_from_words(&seed_to_words(seed)), Some(seed));
+        }
+        assert_eq!(seed_from_words(" Acre_AMBER "), Some(0x0102));
+        assert_eq!(seed_from_words("acre amber"), Some(0x0102));
+        assert_eq!(seed_from_words("acre-banana"), None);
+        assert_eq!(seed_from_words("--"), None);
+        assert_eq!(seed_from_words(&["acorn"; 9].join("-")), None);
+    }
+
+    #[test]
+    fn anything_typed_is_a_seed() {
+        assert_eq!(parse_seed("acre-amber"), 0x0102);
+        assert_eq!(parse_seed(" 1234 "), 1234);
+        assert_eq!(parse_seed("my seed"), hash_name("my seed"));
+    }
+}
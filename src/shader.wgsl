@@ -1,5 +1,5 @@
 struct VertexOutput {
-    [[builtin(position)]] clip_position: vec4<f32>;
+    @builtin(position) clip_position: vec4<f32>,
 };
 
 // 1+-------+3
@@ -15,31 +15,57 @@ struct VertexOutput {
 // 3        1, 1
 
 // Foreground texture
-[[group(0), binding(0)]]
+@group(0) @binding(0)
 var t_fore: texture_2d<f32>;
 // Background texture
-[[group(0), binding(1)]]
+@group(0) @binding(1)
 var t_back: texture_2d<f32>;
 // ASCII chars texture
-[[group(0), binding(2)]]
+@group(0) @binding(2)
 var t_text: texture_2d<f32>;
 // Font texture
-[[group(0), binding(3)]]
+@group(0) @binding(3)
 var t_font: texture_2d<f32>;
+// Light texture.  Each pixel is the colour of the light on a cell.
+@group(0) @binding(4)
+var t_light: texture_2d<f32>;
+// Effects texture, drawn by the game's compute shader.  One pixel per grid
+// pixel, blended over the cells by its alpha.
+@group(0) @binding(5)
+var t_effects: texture_2d<f32>;
 
-[[block]]
 struct Uniforms {
-    font_width: u32;
-    font_height: u32;
+    font_width: u32,
+    font_height: u32,
+    // Non-zero if the colours are sRGB but the swap chain expects linear
+    // colours, which it encodes to sRGB itself
+    decode_srgb: u32,
+    // 0 for no caret, 1 for underline, 2 for block or 3 for bar
+    caret_style: u32,
+    // Colour of the area not covered by whole cells
+    clear: vec4<f32>,
+    // Position of the grid's top-left corner in the window, in pixels
+    offset: vec2<f32>,
+    // Window pixels per grid pixel
+    scale: vec2<f32>,
+    caret_colour: vec4<f32>,
+    // The cell the caret is over
+    caret: vec2<i32>,
+    // Non-zero to draw the caret in caret_colour rather than the cell's ink
+    caret_own_colour: u32,
+    // Non-zero to multiply the colours by the light texture
+    lighting: u32,
+    // Non-zero to blend the effects texture over the cells
+    effects: u32,
 };
 
-[[group(1), binding(0)]]
+@group(1) @binding(0)
 var<uniform> uniforms: Uniforms;
 
 
-[[stage(vertex)]]
-fn main(
-    [[builtin(vertex_index)]] in_vertex_index: u32,
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
 ) -> VertexOutput {
     var out: VertexOutput;
 
@@ -60,26 +86,37 @@ fn main(
     return out;
 }
 
-[[stage(fragment)]]
-
-fn main([[builtin(position)]] pos: vec4<f32>) -> [[location(0)]] vec4<f32> {
-    // Calculate the pixel coords
-    let p = vec2<f32>(pos.x - 0.5, pos.y - 0.5);
+// Convert an sRGB colour to linear.
+fn to_linear(c: vec4<f32>) -> vec4<f32> {
+    let rgb = vec3<f32>(c.r, c.g, c.b);
+    let low = rgb / 12.92;
+    let high = pow((rgb + vec3<f32>(0.055)) / 1.055, vec3<f32>(2.4));
+    let converted = mix(low, high, step(vec3<f32>(0.04045), rgb));
+    return vec4<f32>(converted.x, converted.y, converted.z, c.a);
+}
 
+// The colour of a pixel in the grid
+fn cell_colour(p: vec2<f32>) -> vec4<f32> {
     // Calculate the char coords and the local coords inside a character block
     let cp = vec2<i32>(i32(p.x / f32(uniforms.font_width)), i32(p.y / f32(uniforms.font_height)));
     let lp = vec2<i32>(i32(p.x) % i32(uniforms.font_width), i32(p.y) % i32(uniforms.font_height));
 
-    // Look up the textures
-    let fore = textureLoad(t_fore, cp, 0);
-    let back = textureLoad(t_back, cp, 0);
+    // Look up the textures, lighting the colours
+    var light: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    if (uniforms.lighting != 0u) {
+        let l = textureLoad(t_light, cp, 0);
+        light = vec4<f32>(l.r, l.g, l.b, 1.0);
+    }
+    let fore = textureLoad(t_fore, cp, 0) * light;
+    let back = textureLoad(t_back, cp, 0) * light;
     let text = textureLoad(t_text, cp, 0);
 
-    // Calculate the ASCII character code
-    let c = i32(text.x * 255.0);
+    // Calculate the character code.  Codes past 255 are fallback glyphs and
+    // carry on into the green channel.
+    let c = i32(round(text.x * 255.0)) + i32(round(text.y * 255.0)) * 256;
 
     // Calculate the character coords in the font texture.  We expect the font
-    // texture to be 16*16 characters.
+    // texture to be 16 characters wide.
     let fx: i32 = c % 16;
     let fy: i32 = c / 16;
 
@@ -90,10 +127,59 @@ fn main([[builtin(position)]] pos: vec4<f32>) -> [[location(0)]] vec4<f32> {
     // Fetch the pixel in the font texture
     let font_pix = textureLoad(t_font, vec2<i32>(lx, ly), 0);
 
+    // The caret goes over everything else
+    if (uniforms.caret_style != 0u && cp.x == uniforms.caret.x && cp.y == uniforms.caret.y) {
+        var caret_colour: vec4<f32> = fore;
+        if (uniforms.caret_own_colour != 0u) {
+            caret_colour = uniforms.caret_colour;
+        }
+        let thickness = max(vec2<i32>(1, 1), vec2<i32>(i32(uniforms.font_width) / 8, i32(uniforms.font_height) / 8));
+        if (uniforms.caret_style == 1u && lp.y >= i32(uniforms.font_height) - thickness.y) {
+            return caret_colour;
+        }
+        if (uniforms.caret_style == 2u) {
+            if (font_pix.r < 0.5) {
+                return caret_colour;
+            }
+            return back;
+        }
+        if (uniforms.caret_style == 3u && lp.x < thickness.x) {
+            return caret_colour;
+        }
+    }
+
+    var colour: vec4<f32> = fore;
     if (font_pix.r < 0.5) {
-        return back;
-    } else {
-        return fore;
+        colour = back;
     }
+    if (uniforms.effects != 0u) {
+        let e = textureLoad(t_effects, vec2<i32>(i32(p.x), i32(p.y)), 0);
+        let rgb = mix(vec3<f32>(colour.r, colour.g, colour.b), vec3<f32>(e.r, e.g, e.b), vec3<f32>(e.a));
+        colour = vec4<f32>(rgb.x, rgb.y, rgb.z, colour.a);
+    }
+    return colour;
 }
 
+@fragment
+fn fs_main(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    // Calculate the pixel coords in the grid, which may be offset to centre
+    // it in the window or scaled to fill it
+    let q = (vec2<f32>(pos.x, pos.y) - uniforms.offset) / uniforms.scale;
+    let p = floor(q);
+    let grid_size = textureDimensions(t_fore);
+    let grid_pixels = vec2<f32>(
+        f32(grid_size.x * i32(uniforms.font_width)),
+        f32(grid_size.y * i32(uniforms.font_height)),
+    );
+
+    // Pixels outside the cells show the clear colour
+    var colour: vec4<f32> = uniforms.clear;
+    if (q.x >= 0.0 && q.y >= 0.0 && p.x < grid_pixels.x && p.y < grid_pixels.y) {
+        colour = cell_colour(p);
+    }
+
+    if (uniforms.decode_srgb != 0u) {
+        return to_linear(colour);
+    }
+    return colour;
+}
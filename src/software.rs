@@ -0,0 +1,505 @@
+//
+// Software rasteriser
+//
+// Composites an image into RGBA pixels on the CPU, the same way the shader
+// does on the GPU: each pixel of a glyph in the font atlas that is more than
+// half red is drawn in the cell's ink, and the rest in its paper.
+//
+// Fallback glyphs are drawn from the same atlas the renderer builds, so the
+// image should share the font's glyph table, as the window's image does.
+//
+// When there is no graphics adapter to use, the SoftwareRenderer draws the
+// window this way instead, scaling the pixels to the window and putting them
+// in it with WindowPixels, which needs the fallback feature.  It lights the
+// cells and draws the caret as the shader does, but doesn't run the GPU
+// effects stage.  It keeps its atlas, rebuilding it when fallback glyphs are
+// added, as the GPU renderer does.  rasterise() builds one for each call, and
+// is handy for saving screenshots without a GPU read-back.
+//
+
+use crate::{
+    glyphs::build_atlas,
+    render::{font_image, zoomed_layout, GraphicsOptions, GridPlacement, RenderResult},
+    tint_colour,
+    window_pixels::WindowPixels,
+    CaretStyle, Image, MouseState, Point, RogueFontData,
+};
+use wgpu::{AdapterInfo, Backend, DeviceType};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    window::Window,
+};
+
+// The pixel buffer is (image.width * font width) x (image.height * font
+// height), one u32 per pixel in the same RGBA layout as new_colour().
+pub fn rasterise(image: &Image, font: &RogueFontData) -> Vec<u32> {
+    rasterise_with(image, font, &Atlas::new(font))
+}
+
+// The font atlas, and the generation of the font's glyph table it was built
+// from
+struct Atlas {
+    pixels: Vec<u32>,
+    size: (u32, u32),
+    generation: u64,
+}
+
+impl Atlas {
+    fn new(font: &RogueFontData) -> Self {
+        let generation = font.glyphs().generation();
+        let (pixels, size) = build_atlas(font, u32::MAX);
+        Atlas {
+            pixels,
+            size,
+            generation,
+        }
+    }
+
+    // Rebuilds the atlas if glyphs have been added to the font since
+    fn update(&mut self, font: &RogueFontData) {
+        if font.glyphs().generation() != self.generation {
+            *self = Atlas::new(font);
+        }
+    }
+}
+
+fn rasterise_with(image: &Image, font: &RogueFontData, atlas: &Atlas) -> Vec<u32> {
+    let (cell_width, cell_height) = (font.width as usize, font.height as usize);
+    let width = image.width as usize * cell_width;
+    let height = image.height as usize * cell_height;
+    let (atlas_width, atlas_height) = atlas.size;
+    let (atlas, atlas_width) = (&atlas.pixels, atlas_width as usize);
+    let glyph_count = atlas_height as usize / cell_height * 16;
+    let mut pixels = vec![0; width * height];
+
+    for cy in 0..image.height as usize {
+        for cx in 0..image.width as usize {
+            let i = cy * image.width as usize + cx;
+            let ink = image.fore_image[i];
+            let paper = image.back_image[i];
+            // Glyphs that didn't fit in the atlas are drawn blank
+            let ch = Some((image.text_image[i] & 0xffff) as usize)
+                .filter(|&ch| ch < glyph_count)
+                .unwrap_or(0);
+            let atlas_x = (ch % 16) * cell_width;
+            let atlas_y = (ch / 16) * cell_height;
+
+            for y in 0..cell_height {
+                let glyph_row = (atlas_y + y) * atlas_width + atlas_x;
+                let row = (cy * cell_height + y) * width + cx * cell_width;
+                for x in 0..cell_width {
+                    let lit = (atlas[glyph_row + x] & 0xff) >= 0x80;
+                    pixels[row + x] = if lit { ink } else { paper };
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+//
+// SoftwareRenderer
+// Has the same interface as RenderState, so the window can use either.
+//
+
+pub struct SoftwareRenderer {
+    pixels: WindowPixels,
+    window_size: (u32, u32),
+    placement: GridPlacement,
+    zoom: u32,
+    image: Image,
+    font: RogueFontData,
+    atlas: Atlas,
+    options: GraphicsOptions,
+    light: Option<Vec<u32>>,
+    caret: Option<(Point, CaretStyle, Option<u32>)>,
+    adapter_info: AdapterInfo,
+}
+
+impl SoftwareRenderer {
+    pub fn new(
+        window: &Window,
+        font: &RogueFontData,
+        options: &GraphicsOptions,
+    ) -> RenderResult<Self> {
+        let pixels = WindowPixels::new(window)?;
+        if options.effects_shader.is_some() {
+            log::warn!("The effects shader needs a graphics adapter, so won't be run");
+        }
+        let mut render = SoftwareRenderer {
+            pixels,
+            window_size: (0, 0),
+            placement: GridPlacement {
+                offset: [0.0; 2],
+                scale: [1.0; 2],
+                font_size: (font.width, font.height),
+            },
+            zoom: 1,
+            image: font_image(font, (0, 0)),
+            font: font.clone(),
+            atlas: Atlas::new(font),
+            options: options.clone(),
+            light: None,
+            caret: None,
+            adapter_info: AdapterInfo {
+                name: String::from("Software renderer"),
+                vendor: 0,
+                device: 0,
+                device_type: DeviceType::Cpu,
+                backend: Backend::Empty,
+            },
+        };
+        render.resize(window.inner_size());
+        Ok(render)
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        // Minimised windows have no size and keep the old one
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.window_size = (new_size.width, new_size.height);
+
+        let (chars_size, placement) = zoomed_layout(
+            &self.options,
+            self.window_size,
+            self.placement.font_size,
+            self.zoom,
+        );
+        self.placement = placement;
+        if chars_size != self.chars_size() {
+            self.image = font_image(&self.font, chars_size);
+        }
+    }
+
+    pub fn set_zoom(&mut self, zoom: u32) {
+        let zoom = zoom.max(1);
+        if zoom != self.zoom {
+            self.zoom = zoom;
+            let (width, height) = self.window_size;
+            self.resize(PhysicalSize::new(width, height));
+        }
+    }
+
+    pub fn render(&mut self) -> RenderResult<()> {
+        self.upload();
+        self.draw()
+    }
+
+    // The image is rasterised when it is drawn.
+    pub fn upload(&mut self) {}
+
+    pub fn draw(&mut self) -> RenderResult<()> {
+        let lit;
+        let image = match &self.light {
+            Some(light) => {
+                lit = light_image(&self.image, light);
+                &lit
+            }
+            None => &self.image,
+        };
+        self.atlas.update(&self.font);
+        let mut grid = rasterise_with(image, &self.font, &self.atlas);
+        if let Some(caret) = self.caret {
+            draw_caret(&mut grid, image, self.placement.font_size, caret);
+        }
+        let (font_width, font_height) = self.placement.font_size;
+        let grid_size = (image.width * font_width, image.height * font_height);
+        let pixels = compose(
+            &grid,
+            grid_size,
+            &self.placement,
+            self.window_size,
+            self.options.clear_colour,
+        );
+        self.pixels.present(&pixels, self.window_size)
+    }
+
+    pub fn image(&mut self) -> &mut Image {
+        &mut self.image
+    }
+
+    pub fn chars_size(&self) -> (u32, u32) {
+        (self.image.width, self.image.height)
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) {
+        self.placement.cell_size()
+    }
+
+    pub fn font_size(&self) -> (u32, u32) {
+        self.placement.font_size
+    }
+
+    pub fn pixel_size(&self) -> (u32, u32) {
+        self.window_size
+    }
+
+    pub fn set_clear_colour(&mut self, colour: u32) {
+        self.options.clear_colour = colour;
+    }
+
+    pub fn set_light(&mut self, light: Option<&[u32]>) {
+        self.light = light
+            .filter(|light| light.len() == self.image.text_image.len())
+            .map(<[u32]>::to_vec);
+    }
+
+    // There is no effects stage without a GPU.
+    pub fn set_effects(&mut self, _params: Option<[f32; 4]>) {}
+
+    pub fn set_caret(&mut self, caret: Option<(Point, CaretStyle, Option<u32>)>) {
+        self.caret = caret;
+    }
+
+    pub fn window_to_grid(&self, p: PhysicalPosition<f64>) -> PhysicalPosition<f64> {
+        self.placement.window_to_grid(p)
+    }
+
+    pub fn cell_to_window(&self, cell: Point) -> PhysicalPosition<f64> {
+        self.placement.cell_to_window(cell)
+    }
+
+    pub fn place_mouse(&self, mouse: &mut MouseState, p: PhysicalPosition<f64>) {
+        self.placement.place_mouse(mouse, p)
+    }
+
+    // Bytes used by the grid's cells and the window's pixels
+    pub fn grid_memory(&self) -> usize {
+        let (width, height) = self.window_size;
+        self.image.memory_size() + 4 * (width as usize * height as usize)
+    }
+
+    pub fn atlas_memory(&self) -> usize {
+        4 * (self.atlas.pixels.capacity() + self.font.data.capacity())
+    }
+
+    pub fn adapter_info(&self) -> &AdapterInfo {
+        &self.adapter_info
+    }
+
+    pub fn graphics_options(&self) -> &GraphicsOptions {
+        &self.options
+    }
+}
+
+// The image with its ink and paper multiplied by the light on each cell
+fn light_image(image: &Image, light: &[u32]) -> Image {
+    let mut lit = image.clone();
+    for (i, &light) in light.iter().enumerate() {
+        lit.fore_image[i] = tint_colour(lit.fore_image[i], light);
+        lit.back_image[i] = tint_colour(lit.back_image[i], light);
+    }
+    lit
+}
+
+// Draws the caret over the rasterised cells, as the shader does: a line
+// along the bottom or down the left, an eighth of the cell thick, or the
+// whole cell with the character in its paper colour.
+fn draw_caret(
+    pixels: &mut [u32],
+    image: &Image,
+    (font_width, font_height): (u32, u32),
+    (p, style, colour): (Point, CaretStyle, Option<u32>),
+) {
+    let i = match (p.x >= 0 && p.y >= 0)
+        .then(|| image.coords_to_index(p.x as u32, p.y as u32))
+        .flatten()
+    {
+        Some(i) => i,
+        None => return,
+    };
+    let (ink, paper) = (image.fore_image[i], image.back_image[i]);
+    let colour = colour.unwrap_or(ink);
+    let (cell_width, cell_height) = (font_width as usize, font_height as usize);
+    let (thickness_x, thickness_y) = ((cell_width / 8).max(1), (cell_height / 8).max(1));
+    let stride = image.width as usize * cell_width;
+    let (x0, y0) = (p.x as usize * cell_width, p.y as usize * cell_height);
+
+    for y in 0..cell_height {
+        for x in 0..cell_width {
+            let pixel = &mut pixels[(y0 + y) * stride + x0 + x];
+            *pixel = match style {
+                CaretStyle::Underline if y >= cell_height - thickness_y => colour,
+                CaretStyle::Bar if x < thickness_x => colour,
+                CaretStyle::Block if *pixel == paper => colour,
+                CaretStyle::Block => paper,
+                _ => *pixel,
+            };
+        }
+    }
+}
+
+// Scales the rasterised grid into the window's pixels, nearest-neighbour,
+// with the clear colour around it.  Windows take pixels as 0x00RRGGBB.
+fn compose(
+    grid: &[u32],
+    (grid_width, grid_height): (u32, u32),
+    placement: &GridPlacement,
+    (width, height): (u32, u32),
+    clear: u32,
+) -> Vec<u32> {
+    let to_xrgb = |c: u32| ((c & 0xff) << 16) | (c & 0xff00) | ((c >> 16) & 0xff);
+    // The grid pixel under the centre of each window pixel, on one axis
+    let axis = |size: u32, grid_size: u32, offset: f32, scale: f32| {
+        (0..size)
+            .map(|p| {
+                let g = ((p as f64 + 0.5 - offset as f64) / scale as f64).floor();
+                (g >= 0.0 && g < grid_size as f64).then_some(g as usize)
+            })
+            .collect::<Vec<_>>()
+    };
+    let [offset_x, offset_y] = placement.offset;
+    let [scale_x, scale_y] = placement.scale;
+    let columns = axis(width, grid_width, offset_x, scale_x);
+    let rows = axis(height, grid_height, offset_y, scale_y);
+
+    let clear = to_xrgb(clear);
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for row in &rows {
+        for column in &columns {
+            pixels.push(match (row, column) {
+                (Some(y), Some(x)) => to_xrgb(grid[y * grid_width as usize + x]),
+                _ => clear,
+            });
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Glyphs;
+
+    const INK: u32 = 0xff00_00ff;
+    const PAPER: u32 = 0xff00_ff00;
+
+    fn placement(offset: [f32; 2], scale: [f32; 2]) -> GridPlacement {
+        GridPlacement {
+            offset,
+            scale,
+            font_size: (1, 1),
+        }
+    }
+
+    // A 2x2 cell image in a 4x4 font, with paper everywhere
+    fn caret_on(style: CaretStyle, colour: Option<u32>) -> Vec<u32> {
+        let mut image = Image::new(2, 2);
+        image.fore_image = vec![INK; 4];
+        image.back_image = vec![PAPER; 4];
+        let mut pixels = vec![PAPER; 64];
+        draw_caret(
+            &mut pixels,
+            &image,
+            (4, 4),
+            (Point::new(1, 1), style, colour),
+        );
+        pixels
+    }
+
+    // The pixels of the caret's cell, row by row
+    fn cell(pixels: &[u32]) -> Vec<u32> {
+        (4..8)
+            .flat_map(|y| pixels[y * 8 + 4..y * 8 + 8].to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn the_atlas_is_rebuilt_when_glyphs_are_added() {
+        let font = |ink| RogueFontData {
+            data: vec![ink; 256],
+            width: 1,
+            height: 1,
+            glyphs: Glyphs::new(),
+        };
+        let main = font(0);
+        main.glyphs().add_fallback_font(font(0xff), "★");
+        let mut atlas = Atlas::new(&main);
+        atlas.update(&main);
+        assert_eq!(atlas.size, (16, 16));
+
+        // The glyph gets a code, and a row of the atlas, when first drawn
+        let mut image = font_image(&main, (1, 1));
+        image.draw_string(Point::new(0, 0), "★", INK, PAPER);
+        assert_eq!(rasterise_with(&image, &main, &atlas), vec![PAPER]);
+        atlas.update(&main);
+        assert_eq!(atlas.size, (16, 17));
+        assert_eq!(rasterise_with(&image, &main, &atlas), vec![INK]);
+        assert_eq!(rasterise(&image, &main), vec![INK]);
+    }
+
+    #[test]
+    fn composing_centres_and_converts_the_grid() {
+        let grid = [0x0000_00ff, 0x0000_ff00, 0x00ff_0000, 0xffff_ffff];
+        let pixels = compose(
+            &grid,
+            (2, 2),
+            &placement([1.0, 0.0], [1.0, 1.0]),
+            (4, 2),
+            0xff80_4020,
+        );
+        let clear = 0x0020_4080;
+        assert_eq!(
+            pixels,
+            vec![
+                clear,
+                0x00ff_0000,
+                0x0000_ff00,
+                clear, //
+                clear,
+                0x0000_00ff,
+                0x00ff_ffff,
+                clear,
+            ]
+        );
+    }
+
+    #[test]
+    fn composing_scales_nearest_neighbour() {
+        let grid = [1, 2];
+        let pixels = compose(&grid, (2, 1), &placement([0.0, 0.0], [2.0, 3.0]), (5, 4), 0);
+        let row = [0x0001_0000, 0x0001_0000, 0x0002_0000, 0x0002_0000, 0];
+        assert_eq!(&pixels[..5], &row);
+        assert_eq!(&pixels[10..15], &row);
+        assert!(pixels[15..].iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn carets_are_drawn_in_the_cell() {
+        let pixels = caret_on(CaretStyle::Underline, None);
+        let mut expected = vec![PAPER; 16];
+        expected[12..].copy_from_slice(&[INK; 4]);
+        assert_eq!(cell(&pixels), expected);
+        assert_eq!(pixels.iter().filter(|&&p| p == INK).count(), 4);
+
+        let pixels = caret_on(CaretStyle::Bar, Some(7));
+        let bar = (0..16)
+            .map(|i| if i % 4 == 0 { 7 } else { PAPER })
+            .collect::<Vec<_>>();
+        assert_eq!(cell(&pixels), bar);
+
+        // The paper takes the caret's colour and anything else the paper's
+        let mut pixels = vec![PAPER; 64];
+        pixels[5 * 8 + 5] = INK;
+        let mut image = Image::new(2, 2);
+        image.fore_image = vec![INK; 4];
+        image.back_image = vec![PAPER; 4];
+        let caret = (Point::new(1, 1), CaretStyle::Block, Some(7));
+        draw_caret(&mut pixels, &image, (4, 4), caret);
+        let mut block = vec![7; 16];
+        block[5] = PAPER;
+        assert_eq!(cell(&pixels), block);
+    }
+
+    #[test]
+    fn carets_outside_the_image_are_ignored() {
+        let image = Image::new(2, 2);
+        let mut pixels = vec![PAPER; 64];
+        for p in [Point::new(-1, 0), Point::new(2, 1), Point::new(0, 5)] {
+            draw_caret(&mut pixels, &image, (4, 4), (p, CaretStyle::Block, Some(7)));
+        }
+        assert!(pixels.iter().all(|&p| p == PAPER));
+    }
+}
@@ -0,0 +1,303 @@
+//
+// Spatial index
+//
+// Tracks which entities are in which cells so that questions like "what is
+// standing here?" don't need to look at every entity.  The World keeps its
+// index up to date when entities are despawned.
+//
+
+use crate::{Entity, Point, Rect};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Only the positions are saved, and the cells are rebuilt from them.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(into = "Vec<(Entity, Point)>", from = "Vec<(Entity, Point)>")
+)]
+pub struct SpatialIndex {
+    cells: HashMap<Point, Vec<Entity>>,
+    positions: HashMap<Entity, Point>,
+    // The corners of a box holding every cell that has been occupied since
+    // the last clear(), to limit the cells a query looks at
+    extent: Option<(Point, Point)>,
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        SpatialIndex {
+            cells: HashMap::new(),
+            positions: HashMap::new(),
+            extent: None,
+        }
+    }
+
+    // Places the entity at the given cell, moving it if it is already in the
+    // index.
+    pub fn insert(&mut self, entity: Entity, p: Point) {
+        self.remove(entity);
+        self.cells.entry(p).or_default().push(entity);
+        self.positions.insert(entity, p);
+        self.extent = Some(match self.extent {
+            Some((min, max)) => (
+                Point::new(min.x.min(p.x), min.y.min(p.y)),
+                Point::new(max.x.max(p.x), max.y.max(p.y)),
+            ),
+            None => (p, p),
+        });
+    }
+
+    pub fn move_to(&mut self, entity: Entity, p: Point) {
+        self.insert(entity, p);
+    }
+
+    // Returns the cell the entity was in.
+    pub fn remove(&mut self, entity: Entity) -> Option<Point> {
+        let p = self.positions.remove(&entity)?;
+        if let Some(entities) = self.cells.get_mut(&p) {
+            entities.retain(|&e| e != entity);
+            if entities.is_empty() {
+                self.cells.remove(&p);
+            }
+        }
+        Some(p)
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.positions.clear();
+        self.extent = None;
+    }
+
+    //
+    // Queries
+    //
+
+    pub fn position(&self, entity: Entity) -> Option<Point> {
+        self.positions.get(&entity).copied()
+    }
+
+    // Entities in the cell, in the order they arrived
+    pub fn entities_at(&self, p: Point) -> &[Entity] {
+        self.cells
+            .get(&p)
+            .map_or(&[], |entities| entities.as_slice())
+    }
+
+    pub fn is_occupied(&self, p: Point) -> bool {
+        self.cells.contains_key(&p)
+    }
+
+    // Entities in the rectangle, row by row and then in Entity order within
+    // a cell, so the order is the same on every run.
+    pub fn entities_in_rect(&self, rect: Rect) -> Vec<Entity> {
+        let (x, y) = (rect.x as i64, rect.y as i64);
+        self.entities_in_box(
+            (x, y),
+            (x + rect.width as i64 - 1, y + rect.height as i64 - 1),
+        )
+    }
+
+    // Entities in the cells from min to max inclusive.  Small boxes are
+    // scanned cell by cell, after clamping them to the occupied extent, and
+    // large ones by looking at each occupied cell.
+    fn entities_in_box(&self, min: (i64, i64), max: (i64, i64)) -> Vec<Entity> {
+        let (low, high) = match self.extent {
+            Some(extent) => extent,
+            None => return Vec::new(),
+        };
+        let (x0, y0) = (min.0.max(low.x as i64), min.1.max(low.y as i64));
+        let (x1, y1) = (max.0.min(high.x as i64), max.1.min(high.y as i64));
+        if x0 > x1 || y0 > y1 {
+            return Vec::new();
+        }
+        let area = (x1 - x0 + 1) as u128 * (y1 - y0 + 1) as u128;
+        let mut found = if area <= self.cells.len() as u128 {
+            // The clamped box is inside the extent, so fits in i32s
+            (y0..=y1)
+                .flat_map(|y| (x0..=x1).map(move |x| Point::new(x as i32, y as i32)))
+                .flat_map(|p| self.entities_at(p).iter().map(move |&entity| (p, entity)))
+                .collect::<Vec<_>>()
+        } else {
+            self.cells
+                .iter()
+                .filter(|(p, _)| {
+                    (x0..=x1).contains(&(p.x as i64)) && (y0..=y1).contains(&(p.y as i64))
+                })
+                .flat_map(|(&p, entities)| entities.iter().map(move |&entity| (p, entity)))
+                .collect::<Vec<_>>()
+        };
+        // The cells' map is in a different order on each run
+        found.sort_unstable_by_key(|&(p, entity)| (p.y, p.x, entity));
+        found.into_iter().map(|(_, entity)| entity).collect()
+    }
+
+    // The closest entity accepted by the filter within the given (Euclidean)
+    // radius.  Ties go to the lowest Entity.
+    pub fn nearest_within_radius<F>(&self, p: Point, radius: u32, filter: F) -> Option<Entity>
+    where
+        F: Fn(Entity) -> bool,
+    {
+        // Squared distances can need more than 64 bits for the largest radii.
+        let (x, y, r) = (p.x as i64, p.y as i64, radius as i64);
+        let distance = |q: &Point| {
+            let (dx, dy) = ((q.x as i64 - x) as i128, (q.y as i64 - y) as i128);
+            dx * dx + dy * dy
+        };
+        self.entities_in_box((x - r, y - r), (x + r, y + r))
+            .into_iter()
+            .filter(|&entity| filter(entity))
+            .filter_map(|entity| self.position(entity).map(|q| (entity, distance(&q))))
+            .filter(|&(_, d)| d <= (r as i128) * (r as i128))
+            .min_by_key(|&(e, d)| (d, e))
+            .map(|(entity, _)| entity)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SpatialIndex> for Vec<(Entity, Point)> {
+    fn from(index: SpatialIndex) -> Self {
+        let mut positions = index.positions.into_iter().collect::<Vec<_>>();
+        positions.sort_by_key(|&(entity, _)| entity);
+        positions
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Vec<(Entity, Point)>> for SpatialIndex {
+    fn from(positions: Vec<(Entity, Point)>) -> Self {
+        let mut index = SpatialIndex::new();
+        for (entity, p) in positions {
+            index.insert(entity, p);
+        }
+        index
+    }
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::World;
+
+    fn entities(count: usize) -> Vec<Entity> {
+        let mut world = World::new();
+        (0..count).map(|_| world.spawn()).collect()
+    }
+
+    #[test]
+    fn insert_move_and_remove() {
+        let e = entities(2);
+        let mut index = SpatialIndex::new();
+        index.insert(e[0], Point::new(1, 1));
+        index.insert(e[1], Point::new(1, 1));
+        assert_eq!(index.entities_at(Point::new(1, 1)), &[e[0], e[1]]);
+
+        index.move_to(e[0], Point::new(2, 3));
+        assert_eq!(index.entities_at(Point::new(1, 1)), &[e[1]]);
+        assert_eq!(index.position(e[0]), Some(Point::new(2, 3)));
+
+        assert_eq!(index.remove(e[1]), Some(Point::new(1, 1)));
+        assert!(!index.is_occupied(Point::new(1, 1)));
+        assert_eq!(index.remove(e[1]), None);
+    }
+
+    #[test]
+    fn rect_queries_scan_and_filter_alike() {
+        let e = entities(3);
+        let mut index = SpatialIndex::new();
+        index.insert(e[0], Point::new(0, 0));
+        index.insert(e[1], Point::new(5, 5));
+        index.insert(e[2], Point::new(10, 0));
+
+        // Small enough to scan cell by cell
+        assert_eq!(index.entities_in_rect(Rect::new(4, 4, 2, 1)), vec![]);
+        assert_eq!(index.entities_in_rect(Rect::new(5, 5, 1, 1)), vec![e[1]]);
+        // Large enough to filter the occupied cells
+        assert_eq!(
+            index.entities_in_rect(Rect::new(0, 0, 11, 10)),
+            vec![e[0], e[2], e[1]]
+        );
+    }
+
+    #[test]
+    fn results_come_out_in_the_same_order_every_run() {
+        let e = entities(4);
+        // Each index has its own randomly seeded maps
+        for _ in 0..8 {
+            let mut index = SpatialIndex::new();
+            index.insert(e[3], Point::new(2, 1));
+            index.insert(e[2], Point::new(-1, 0));
+            index.insert(e[1], Point::new(0, -1));
+            index.insert(e[0], Point::new(2, 1));
+            assert_eq!(
+                index.entities_in_rect(Rect::new(-50, -50, 100, 100)),
+                vec![e[1], e[2], e[0], e[3]]
+            );
+            assert_eq!(
+                index.entities_in_rect(Rect::new(2, 1, 1, 1)),
+                vec![e[0], e[3]]
+            );
+            // All at a distance of 1
+            let origin = Point::new(0, 0);
+            assert_eq!(index.nearest_within_radius(origin, 1, |_| true), Some(e[1]));
+            assert_eq!(
+                index.nearest_within_radius(origin, 1, |entity| entity != e[1]),
+                Some(e[2])
+            );
+        }
+    }
+
+    #[test]
+    fn extreme_coordinates_and_radii() {
+        let e = entities(2);
+        let mut index = SpatialIndex::new();
+        index.insert(e[0], Point::new(i32::MAX, i32::MAX));
+        index.insert(e[1], Point::new(i32::MIN, i32::MIN));
+
+        let everything = Rect::new(i32::MIN, i32::MIN, u32::MAX, u32::MAX);
+        assert_eq!(index.entities_in_rect(everything).len(), 1);
+        let corner = Rect::new(i32::MAX, i32::MAX, u32::MAX, u32::MAX);
+        assert_eq!(index.entities_in_rect(corner), vec![e[0]]);
+
+        let origin = Point::new(0, 0);
+        assert_eq!(index.nearest_within_radius(origin, 1000, |_| true), None);
+        assert!(index
+            .nearest_within_radius(origin, u32::MAX, |_| true)
+            .is_some());
+        let near_max = Point::new(i32::MAX - 1, i32::MAX);
+        assert_eq!(
+            index.nearest_within_radius(near_max, u32::MAX, |_| true),
+            Some(e[0])
+        );
+    }
+
+    #[test]
+    fn nearest_respects_radius_and_filter() {
+        let e = entities(3);
+        let mut index = SpatialIndex::new();
+        index.insert(e[0], Point::new(3, 0));
+        index.insert(e[1], Point::new(2, 2));
+        index.insert(e[2], Point::new(1, 0));
+
+        let origin = Point::new(0, 0);
+        assert_eq!(index.nearest_within_radius(origin, 5, |_| true), Some(e[2]));
+        assert_eq!(
+            index.nearest_within_radius(origin, 5, |entity| entity != e[2]),
+            Some(e[1])
+        );
+        // (2, 2) is just outside a radius of 2
+        assert_eq!(
+            index.nearest_within_radius(origin, 2, |entity| entity != e[2]),
+            None
+        );
+    }
+}
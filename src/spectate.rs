@@ -0,0 +1,793 @@
+//
+// Spectating
+//
+// A FrameBroadcaster serves the main window's frames over WebSocket, so a web
+// viewer or another mage game running a SpectatorClient can watch a game as
+// it is played, e.g. for tournaments or to see what a remote player sees.
+// Turn it on with RogueBuilder::with_spectators("0.0.0.0:9000").  Needs the
+// net feature.
+//
+// Each frame is a binary message of little-endian u32s:
+//
+//      kind            0 for a whole frame, 1 for the cells that changed
+//      width, height   in cells
+//      count           cells that follow, all of them for a whole frame
+//      cells           index (diffs only), glyph, ink, paper
+//
+// Glyphs are as in Image::text_image and colours have red in the low byte.
+// New spectators get a whole frame and diffs after that.  Spectators that
+// fall too far behind, or send more than the few bytes a viewer needs to, are
+// disconnected, and once there are as many as the broadcaster allows, new
+// ones are turned away.  Clients reject frames bigger than MAX_FRAME_CELLS
+// or shorter than their cell count says.
+//
+
+use crate::{Image, RogueError, RogueResult};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha1::{Digest, Sha1};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_MAX_RATE: Duration = Duration::from_millis(33);
+const DEFAULT_MAX_SPECTATORS: usize = 64;
+// Spectators with this much waiting to be sent are disconnected
+const MAX_BACKLOG: usize = 4 * 1024 * 1024;
+const MAX_HANDSHAKE: usize = 8 * 1024;
+// Spectators only send control messages, which can't be longer than this
+const MAX_SPECTATOR_PAYLOAD: usize = 125;
+// The most cells a frame can have, e.g. 1024x1024
+const MAX_FRAME_CELLS: u32 = 1024 * 1024;
+const HEADER_SIZE: usize = 16;
+const WHOLE_CELL_SIZE: usize = 12;
+const DIFF_CELL_SIZE: usize = 16;
+const MAX_FRAME_PAYLOAD: usize = HEADER_SIZE + DIFF_CELL_SIZE * MAX_FRAME_CELLS as usize;
+// The biggest WebSocket frame header, with a 64-bit length and a mask
+const MAX_WEBSOCKET_HEADER: usize = 14;
+
+const FRAME_WHOLE: u32 = 0;
+const FRAME_DIFF: u32 = 1;
+
+//
+// Frame encoding
+//
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+// A diff from the old frame, or the whole frame if there isn't an old one the
+// same size.  Returns None if nothing changed.
+fn encode_frame(old: Option<&Image>, new: &Image) -> Option<Vec<u8>> {
+    let old = old.filter(|old| old.width == new.width && old.height == new.height);
+    let cells = (0..new.text_image.len())
+        .map(|i| (i, [new.text_image[i], new.fore_image[i], new.back_image[i]]));
+
+    let mut data = Vec::new();
+    match old {
+        Some(old) => {
+            let changed = cells
+                .filter(|&(i, cell)| {
+                    cell != [old.text_image[i], old.fore_image[i], old.back_image[i]]
+                })
+                .collect::<Vec<_>>();
+            if changed.is_empty() {
+                return None;
+            }
+            for value in [FRAME_DIFF, new.width, new.height, changed.len() as u32] {
+                push_u32(&mut data, value);
+            }
+            for (i, cell) in changed {
+                push_u32(&mut data, i as u32);
+                cell.iter().for_each(|&value| push_u32(&mut data, value));
+            }
+        }
+        None => {
+            for value in [
+                FRAME_WHOLE,
+                new.width,
+                new.height,
+                new.text_image.len() as u32,
+            ] {
+                push_u32(&mut data, value);
+            }
+            for (_, cell) in cells {
+                cell.iter().for_each(|&value| push_u32(&mut data, value));
+            }
+        }
+    }
+    Some(data)
+}
+
+// Applies a frame message to the image.  Returns false if it is malformed,
+// too big, or the wrong length for its cell count, without touching the image.
+fn apply_frame(image: &mut Image, data: &[u8]) -> bool {
+    let mut values = data
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    let (kind, width, height, count) =
+        match (values.next(), values.next(), values.next(), values.next()) {
+            (Some(kind), Some(width), Some(height), Some(count)) => (kind, width, height, count),
+            _ => return false,
+        };
+    let cells = match width.checked_mul(height) {
+        Some(cells) if cells <= MAX_FRAME_CELLS => cells,
+        _ => return false,
+    };
+    let cell_size = match kind {
+        FRAME_WHOLE if count == cells => WHOLE_CELL_SIZE,
+        FRAME_DIFF if count <= cells => DIFF_CELL_SIZE,
+        _ => return false,
+    };
+    if data.len() != HEADER_SIZE + count as usize * cell_size {
+        return false;
+    }
+    // Diffs are checked before any of them are applied
+    if kind == FRAME_DIFF {
+        let in_range = data[HEADER_SIZE..]
+            .chunks_exact(DIFF_CELL_SIZE)
+            .all(|cell| u32::from_le_bytes([cell[0], cell[1], cell[2], cell[3]]) < cells);
+        if !in_range {
+            return false;
+        }
+    }
+    if image.width != width || image.height != height {
+        if kind != FRAME_WHOLE {
+            return false;
+        }
+        *image = Image::new(width, height);
+    }
+
+    for n in 0..count as usize {
+        let index = match kind {
+            FRAME_WHOLE => Some(n as u32),
+            _ => values.next(),
+        };
+        match (index, values.next(), values.next(), values.next()) {
+            (Some(index), Some(glyph), Some(ink), Some(paper))
+                if (index as usize) < image.text_image.len() =>
+            {
+                let i = index as usize;
+                image.text_image[i] = glyph;
+                image.fore_image[i] = ink;
+                image.back_image[i] = paper;
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+//
+// WebSocket (RFC 6455)
+// Only what is needed to send binary messages one way.
+//
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_BINARY: u8 = 2;
+const OPCODE_CLOSE: u8 = 8;
+const OPCODE_PING: u8 = 9;
+const OPCODE_PONG: u8 = 10;
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim());
+    sha1.update(WEBSOCKET_GUID);
+    BASE64.encode(sha1.finalize())
+}
+
+// A whole, unmasked message from the server
+fn websocket_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Parsed {
+    // The opcode, the unmasked payload and the bytes used
+    Frame(u8, Vec<u8>, usize),
+    // Not all of the frame has arrived
+    Incomplete,
+    // The payload is longer than allowed, so the connection should be closed
+    TooLong,
+}
+
+// The first frame in the data.  Payloads longer than max_len are refused as
+// soon as the length has arrived, without waiting for them.
+fn parse_websocket_frame(data: &[u8], max_len: usize) -> Parsed {
+    let (first, second) = match data {
+        [first, second, ..] => (*first, *second),
+        _ => return Parsed::Incomplete,
+    };
+    let (len, mut used) = match second & 0x7f {
+        126 => match data.get(2..4) {
+            Some(bytes) => (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 4),
+            None => return Parsed::Incomplete,
+        },
+        127 => match data.get(2..10) {
+            Some(bytes) => {
+                let mut len = [0; 8];
+                len.copy_from_slice(bytes);
+                (u64::from_be_bytes(len), 10)
+            }
+            None => return Parsed::Incomplete,
+        },
+        len => (len as u64, 2),
+    };
+    if len > max_len as u64 {
+        return Parsed::TooLong;
+    }
+    let len = len as usize;
+    let mask = if second & 0x80 != 0 {
+        match data.get(used..used + 4) {
+            Some(mask) => {
+                used += 4;
+                Some([mask[0], mask[1], mask[2], mask[3]])
+            }
+            None => return Parsed::Incomplete,
+        }
+    } else {
+        None
+    };
+    let mut payload = match data.get(used..used + len) {
+        Some(payload) => payload.to_vec(),
+        None => return Parsed::Incomplete,
+    };
+    if let Some(mask) = mask {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Parsed::Frame(first & 0x0f, payload, used + len)
+}
+
+//
+// Broadcaster
+//
+
+enum SpectatorState {
+    // Reading the HTTP upgrade request
+    Handshake,
+    // Waiting for its first, whole frame
+    Joined,
+    Watching,
+}
+
+struct Spectator {
+    stream: TcpStream,
+    addr: SocketAddr,
+    state: SpectatorState,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Spectator {
+    // Returns false if the connection has closed or failed, or the spectator
+    // has sent too much.
+    fn receive(&mut self) -> bool {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.incoming.extend_from_slice(&buffer[..n]);
+                    if self.incoming.len() > MAX_HANDSHAKE {
+                        log::warn!("Spectator {} sent too much", self.addr);
+                        return false;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+
+        if let SpectatorState::Handshake = self.state {
+            let end = match self.incoming.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(end) => end,
+                None => return self.incoming.len() < MAX_HANDSHAKE,
+            };
+            let request = String::from_utf8_lossy(&self.incoming[..end]).into_owned();
+            self.incoming.drain(..end + 4);
+            let key = request.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                    Some(value.trim().to_string())
+                } else {
+                    None
+                }
+            });
+            match key {
+                Some(key) => {
+                    let response = format!(
+                        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                        accept_key(&key)
+                    );
+                    self.outgoing.extend_from_slice(response.as_bytes());
+                    self.state = SpectatorState::Joined;
+                }
+                None => {
+                    let _ = self
+                        .stream
+                        .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+                    return false;
+                }
+            }
+        }
+
+        loop {
+            let (opcode, payload, used) =
+                match parse_websocket_frame(&self.incoming, MAX_SPECTATOR_PAYLOAD) {
+                    Parsed::Frame(opcode, payload, used) => (opcode, payload, used),
+                    Parsed::Incomplete => return true,
+                    Parsed::TooLong => {
+                        log::warn!("Spectator {} sent a message that is too long", self.addr);
+                        return false;
+                    }
+                };
+            self.incoming.drain(..used);
+            match opcode {
+                OPCODE_CLOSE => {
+                    let _ = self.stream.write_all(&websocket_frame(OPCODE_CLOSE, &[]));
+                    return false;
+                }
+                OPCODE_PING => self.outgoing.extend(websocket_frame(OPCODE_PONG, &payload)),
+                _ => {}
+            }
+        }
+    }
+
+    // Returns false if the connection has failed or fallen too far behind.
+    fn flush(&mut self) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        self.outgoing.len() < MAX_BACKLOG
+    }
+}
+
+pub struct FrameBroadcaster {
+    listener: TcpListener,
+    spectators: Vec<Spectator>,
+    // The last frame sent, which diffs are made against
+    sent: Option<Image>,
+    last_sent: Option<Instant>,
+    max_rate: Duration,
+    max_spectators: usize,
+}
+
+impl FrameBroadcaster {
+    pub fn bind(addr: impl ToSocketAddrs) -> RogueResult<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(FrameBroadcaster {
+            listener,
+            spectators: Vec::new(),
+            sent: None,
+            last_sent: None,
+            max_rate: DEFAULT_MAX_RATE,
+            max_spectators: DEFAULT_MAX_SPECTATORS,
+        })
+    }
+
+    // Frames drawn closer together than this are skipped.  Nothing is lost,
+    // as the next frame sent has all the changes.
+    pub fn set_max_rate(&mut self, interval: Duration) {
+        self.max_rate = interval;
+    }
+
+    // Connections beyond this many, counting those still connecting, are
+    // turned away.  The default is 64.
+    pub fn set_max_spectators(&mut self, max: usize) {
+        self.max_spectators = max;
+    }
+
+    pub fn local_addr(&self) -> RogueResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    // Spectators watching, not counting those still connecting
+    pub fn spectator_count(&self) -> usize {
+        self.spectators
+            .iter()
+            .filter(|spectator| !matches!(spectator.state, SpectatorState::Handshake))
+            .count()
+    }
+
+    // Called by the engine with each finished frame.
+    pub fn broadcast(&mut self, image: &Image) {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, addr)) => {
+                    if self.spectators.len() >= self.max_spectators {
+                        log::info!("Turned away spectator {}: too many watching", addr);
+                        let _ = stream.write_all(
+                            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+                        );
+                        continue;
+                    }
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        log::warn!("Unable to set up spectator {}: {}", addr, e);
+                        continue;
+                    }
+                    self.spectators.push(Spectator {
+                        stream,
+                        addr,
+                        state: SpectatorState::Handshake,
+                        incoming: Vec::new(),
+                        outgoing: Vec::new(),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Unable to accept spectator: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let due = self
+            .last_sent
+            .is_none_or(|last| now.duration_since(last) >= self.max_rate);
+        let joined = self
+            .spectators
+            .iter()
+            .any(|spectator| matches!(spectator.state, SpectatorState::Joined));
+        if due || joined {
+            self.last_sent = Some(now);
+            let diff = encode_frame(self.sent.as_ref(), image)
+                .map(|data| websocket_frame(OPCODE_BINARY, &data));
+            let whole = joined.then(|| {
+                websocket_frame(
+                    OPCODE_BINARY,
+                    &encode_frame(None, image).unwrap_or_default(),
+                )
+            });
+            for spectator in self.spectators.iter_mut() {
+                match spectator.state {
+                    SpectatorState::Handshake => {}
+                    SpectatorState::Joined => {
+                        spectator.outgoing.extend(whole.iter().flatten());
+                        spectator.state = SpectatorState::Watching;
+                    }
+                    SpectatorState::Watching => {
+                        spectator.outgoing.extend(diff.iter().flatten());
+                    }
+                }
+            }
+            self.sent = Some(image.clone());
+        }
+
+        self.spectators.retain_mut(|spectator| {
+            let open = spectator.receive() && spectator.flush();
+            if !open {
+                log::info!("Spectator {} left", spectator.addr);
+            }
+            open
+        });
+    }
+}
+
+//
+// Client
+//
+
+// Watches a game served by a FrameBroadcaster.  The game can blit image()
+// in its present().
+pub struct SpectatorClient {
+    stream: Option<TcpStream>,
+    incoming: Vec<u8>,
+    image: Image,
+}
+
+impl SpectatorClient {
+    // Blocks until the connection is set up.
+    pub fn connect(addr: impl ToSocketAddrs) -> RogueResult<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        let host = stream.peer_addr()?;
+        let key = BASE64.encode(rand::random::<[u8; 16]>());
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            host, key
+        );
+        stream.write_all(request.as_bytes())?;
+
+        // The response is read a line at a time so no frame data is taken
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut status = String::new();
+        reader.read_line(&mut status)?;
+        let mut accepted = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("sec-websocket-accept") {
+                    accepted = value.trim() == accept_key(&key);
+                }
+            }
+        }
+        if !status.contains(" 101 ") || !accepted {
+            return Err(RogueError::BadMessage(format!(
+                "Spectating refused: {}",
+                status.trim()
+            )));
+        }
+
+        let incoming = reader.buffer().to_vec();
+        stream.set_nonblocking(true)?;
+        Ok(SpectatorClient {
+            stream: Some(stream),
+            incoming,
+            image: Image::new(0, 0),
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // The game as last seen
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    // Call once per tick.  Returns true if the image has changed.
+    pub fn poll(&mut self) -> bool {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return false,
+        };
+        let mut open = true;
+        let mut buffer = [0; 16 * 1024];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    open = false;
+                    break;
+                }
+                Ok(n) => {
+                    self.incoming.extend_from_slice(&buffer[..n]);
+                    // Stop reading once a whole frame of the biggest size
+                    // could have arrived, so a bad server can't fill memory
+                    if self.incoming.len() > MAX_FRAME_PAYLOAD + MAX_WEBSOCKET_HEADER {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+
+        let mut changed = false;
+        loop {
+            let (opcode, payload, used) =
+                match parse_websocket_frame(&self.incoming, MAX_FRAME_PAYLOAD) {
+                    Parsed::Frame(opcode, payload, used) => (opcode, payload, used),
+                    Parsed::Incomplete => break,
+                    Parsed::TooLong => {
+                        log::warn!("The game being watched sent a frame that is too big");
+                        open = false;
+                        break;
+                    }
+                };
+            self.incoming.drain(..used);
+            match opcode {
+                OPCODE_BINARY if apply_frame(&mut self.image, &payload) => changed = true,
+                OPCODE_BINARY => {
+                    log::warn!("Bad frame from the game being watched");
+                    open = false;
+                }
+                OPCODE_CLOSE => open = false,
+                _ => {}
+            }
+        }
+        if !open {
+            self.stream = None;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Char, Point};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    fn cells(image: &Image) -> Vec<[u32; 3]> {
+        (0..image.text_image.len())
+            .map(|i| {
+                [
+                    image.text_image[i],
+                    image.fore_image[i],
+                    image.back_image[i],
+                ]
+            })
+            .collect()
+    }
+
+    fn frame(values: &[u32]) -> Vec<u8> {
+        let mut data = Vec::new();
+        values.iter().for_each(|&value| push_u32(&mut data, value));
+        data
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut game = Image::new(5, 3);
+        game.draw_string(Point::new(0, 1), "hello", 0xff00_ff00, 0xff00_0000);
+
+        let mut seen = Image::new(0, 0);
+        let whole = encode_frame(None, &game).unwrap();
+        assert_eq!(whole.len(), HEADER_SIZE + 15 * WHOLE_CELL_SIZE);
+        assert!(apply_frame(&mut seen, &whole));
+        assert_eq!((seen.width, seen.height), (5, 3));
+        assert_eq!(cells(&seen), cells(&game));
+
+        let old = game.clone();
+        assert_eq!(encode_frame(Some(&old), &game), None);
+        game.draw_char(Point::new(4, 2), Char::new(b'@', 1, 2));
+        let diff = encode_frame(Some(&old), &game).unwrap();
+        assert_eq!(diff.len(), HEADER_SIZE + DIFF_CELL_SIZE);
+        assert!(apply_frame(&mut seen, &diff));
+        assert_eq!(cells(&seen), cells(&game));
+    }
+
+    #[test]
+    fn bad_frames_are_rejected_without_changing_the_image() {
+        let mut seen = Image::new(2, 1);
+        let before = cells(&seen);
+        let bad = [
+            // Too short for the header
+            frame(&[FRAME_WHOLE, 2, 1]),
+            // Too big, before anything is allocated
+            frame(&[FRAME_WHOLE, 65536, 65536, 0]),
+            frame(&[FRAME_WHOLE, u32::MAX, 2, 0]),
+            // A whole frame with fewer cells than its size
+            frame(&[FRAME_WHOLE, 2, 1, 1, 7, 7, 7]),
+            // Fewer cells than the count says
+            frame(&[FRAME_WHOLE, 2, 1, 2, 7, 7, 7]),
+            // A diff to a different size, or out of range
+            frame(&[FRAME_DIFF, 3, 1, 1, 0, 7, 7, 7]),
+            frame(&[FRAME_DIFF, 2, 1, 2, 0, 7, 7, 7, 2, 7, 7, 7]),
+            frame(&[FRAME_DIFF, 2, 1, 3, 0, 7, 7, 7, 1, 7, 7, 7, 0, 7, 7, 7]),
+            // Trailing bytes
+            [frame(&[FRAME_DIFF, 2, 1, 0]), vec![0]].concat(),
+            frame(&[9, 2, 1, 0]),
+        ];
+        for data in &bad {
+            assert!(!apply_frame(&mut seen, data), "{:?}", data);
+            assert_eq!((seen.width, seen.height), (2, 1));
+            assert_eq!(cells(&seen), before);
+        }
+        assert!(apply_frame(
+            &mut seen,
+            &frame(&[FRAME_DIFF, 2, 1, 1, 1, 7, 8, 9])
+        ));
+        assert_eq!(cells(&seen)[1], [7, 8, 9]);
+    }
+
+    #[test]
+    fn websocket_frames_parse_and_unmask() {
+        for len in [0, 125, 126, 0xffff, 0x10000] {
+            let payload = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+            let data = websocket_frame(OPCODE_BINARY, &payload);
+            assert_eq!(
+                parse_websocket_frame(&data, 0x10000),
+                Parsed::Frame(OPCODE_BINARY, payload.clone(), data.len())
+            );
+            assert_eq!(
+                parse_websocket_frame(&data[..data.len() - 1], 0x10000),
+                Parsed::Incomplete
+            );
+        }
+
+        // A masked ping from a viewer
+        let mask = [1, 2, 3, 4];
+        let mut data = vec![0x80 | OPCODE_PING, 0x80 | 5];
+        data.extend_from_slice(&mask);
+        data.extend(b"hello".iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        data.extend_from_slice(&[0x88, 0]);
+        assert_eq!(
+            parse_websocket_frame(&data, MAX_SPECTATOR_PAYLOAD),
+            Parsed::Frame(OPCODE_PING, b"hello".to_vec(), 11)
+        );
+        assert_eq!(
+            parse_websocket_frame(&data[11..], MAX_SPECTATOR_PAYLOAD),
+            Parsed::Frame(OPCODE_CLOSE, Vec::new(), 2)
+        );
+    }
+
+    #[test]
+    fn long_websocket_frames_are_refused_from_their_header() {
+        // Only the header of a huge frame has arrived
+        let mut data = vec![0x82, 127];
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(
+            parse_websocket_frame(&data, MAX_FRAME_PAYLOAD),
+            Parsed::TooLong
+        );
+        let data = websocket_frame(OPCODE_BINARY, &[0; 126]);
+        assert_eq!(
+            parse_websocket_frame(&data[..4], MAX_SPECTATOR_PAYLOAD),
+            Parsed::TooLong
+        );
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_6455() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn spectators_beyond_the_limit_are_turned_away() {
+        let mut broadcaster = FrameBroadcaster::bind("127.0.0.1:0").unwrap();
+        broadcaster.set_max_spectators(1);
+        let addr = broadcaster.local_addr().unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+        let serving = {
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                let mut image = Image::new(3, 2);
+                image.draw_string(Point::new(0, 0), "abc", 1, 2);
+                while !done.load(Ordering::Relaxed) {
+                    broadcaster.broadcast(&image);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        let mut first = SpectatorClient::connect(addr).unwrap();
+        let start = Instant::now();
+        while !first.poll() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!((first.image().width, first.image().height), (3, 2));
+        assert_eq!(first.image().glyph_char(first.image().text_image[1]), 'b');
+
+        let second = SpectatorClient::connect(addr);
+        assert!(matches!(second, Err(RogueError::BadMessage(_))));
+        assert!(first.is_connected());
+
+        done.store(true, Ordering::Relaxed);
+        serving.join().unwrap();
+    }
+}
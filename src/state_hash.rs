@@ -0,0 +1,427 @@
+//
+// State hashes
+//
+// Catches determinism bugs in games that replay runs from a seed.  Turned on
+// with RogueBuilder::with_state_hashes(), it hashes whatever state the game
+// registers at the end of each turn and logs the hashes:
+//
+//      if let Some(hashes) = ctx.state_hashes() {
+//          hashes.begin_turn(turn);
+//          hashes.add("rng", &rng);
+//          hashes.add("map", &map);
+//          hashes.end_turn();
+//      }
+//
+// Each turn is a line of the log: the turn, the hash of all its parts and then
+// each part as name=hash.  Two runs from the same seed and inputs should give
+// the same log, and compare_state_hashes() finds the first turn where they
+// don't and the parts that differ.
+//
+// The hashes are FNV-1a over little-endian bytes, with sizes hashed as u64, so
+// they are the same on every platform and build.  Floats aren't Hash, so hash
+// their to_bits().
+//
+
+use crate::{RogueError, RogueResult};
+use std::{
+    fmt,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::Write,
+    path::Path,
+};
+
+//
+// StableHasher
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    pub fn new() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as i64 as u64);
+    }
+}
+
+pub fn stable_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+//
+// TurnHashes
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnHashes {
+    pub turn: u64,
+    // (name, hash) in the order they were added
+    pub parts: Vec<(String, u64)>,
+}
+
+impl TurnHashes {
+    // The hash of all the parts together
+    pub fn total(&self) -> u64 {
+        stable_hash(&self.parts)
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = format!("{} {:016x}", self.turn, self.total());
+        for (name, hash) in &self.parts {
+            line.push_str(&format!(" {}={:016x}", name, hash));
+        }
+        line.push('\n');
+        line
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let turn = fields.next()?.parse().ok()?;
+        let total = u64::from_str_radix(fields.next()?, 16).ok()?;
+        let parts = fields
+            .map(|field| {
+                let (name, hash) = field.split_once('=')?;
+                Some((String::from(name), u64::from_str_radix(hash, 16).ok()?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let turn = TurnHashes { turn, parts };
+        (turn.total() == total).then_some(turn)
+    }
+}
+
+//
+// StateHashLog
+//
+
+pub struct StateHashLog {
+    turns: Vec<TurnHashes>,
+    current: Option<TurnHashes>,
+    file: Option<File>,
+}
+
+impl StateHashLog {
+    // A log kept in memory only
+    pub fn new() -> Self {
+        StateHashLog {
+            turns: Vec::new(),
+            current: None,
+            file: None,
+        }
+    }
+
+    // A log that is also written to a file as each turn ends, replacing the
+    // file's contents.
+    pub fn create(path: &Path) -> RogueResult<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(StateHashLog {
+            file: Some(File::create(path)?),
+            ..Self::new()
+        })
+    }
+
+    // Starts hashing a turn, ending the one before if it wasn't ended.
+    pub fn begin_turn(&mut self, turn: u64) {
+        self.end_turn();
+        self.current = Some(TurnHashes {
+            turn,
+            parts: Vec::new(),
+        });
+    }
+
+    // Hashes a part of the state for the current turn.  Parts added outside a
+    // turn are ignored.  Names shouldn't contain spaces or '='.
+    pub fn add<T: Hash + ?Sized>(&mut self, name: &str, value: &T) {
+        if let Some(current) = &mut self.current {
+            let name = name.replace(|c: char| c.is_whitespace() || c == '=', "_");
+            current.parts.push((name, stable_hash(value)));
+        }
+    }
+
+    // Logs the current turn.  A log that can't be written is reported once
+    // and then kept in memory only.
+    pub fn end_turn(&mut self) {
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.write_all(current.to_line().as_bytes()) {
+                log::error!("Unable to write the state hash log: {}", e);
+                self.file = None;
+            }
+        }
+        self.turns.push(current);
+    }
+
+    pub fn turns(&self) -> &[TurnHashes] {
+        &self.turns
+    }
+
+    pub fn clear(&mut self) {
+        self.turns.clear();
+        self.current = None;
+    }
+}
+
+impl Default for StateHashLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reads a log written by StateHashLog.
+pub fn load_state_hashes(path: &Path) -> RogueResult<Vec<TurnHashes>> {
+    let text = fs::read_to_string(path)?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            TurnHashes::from_line(line).ok_or_else(|| RogueError::BadConfig {
+                file: path.display().to_string(),
+                line: i + 1,
+                message: String::from("not a turn's state hashes"),
+            })
+        })
+        .collect()
+}
+
+//
+// Comparing runs
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashDivergence {
+    // The runs logged different turns at the same point, e.g. because one
+    // skipped a turn
+    Turns { expected: u64, found: u64 },
+    // The named parts of a turn differ, including parts only one run has
+    Parts { turn: u64, parts: Vec<String> },
+    // One run logged more turns, starting with this one
+    Length { turn: u64 },
+}
+
+impl fmt::Display for HashDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashDivergence::Turns { expected, found } => {
+                write!(f, "Expected turn {} but found turn {}", expected, found)
+            }
+            HashDivergence::Parts { turn, parts } => {
+                write!(f, "Turn {} differs in {}", turn, parts.join(", "))
+            }
+            HashDivergence::Length { turn } => {
+                write!(f, "Only one run has turn {} onwards", turn)
+            }
+        }
+    }
+}
+
+// Finds the first difference between two runs' logs, or None if they match.
+pub fn compare_state_hashes(
+    expected: &[TurnHashes],
+    found: &[TurnHashes],
+) -> Option<HashDivergence> {
+    for (a, b) in expected.iter().zip(found) {
+        if a.turn != b.turn {
+            return Some(HashDivergence::Turns {
+                expected: a.turn,
+                found: b.turn,
+            });
+        }
+        if a.parts == b.parts {
+            continue;
+        }
+        let mut parts = Vec::<String>::new();
+        for (name, hash) in a.parts.iter().chain(&b.parts) {
+            let in_a = a.parts.iter().find(|(n, _)| n == name).map(|(_, h)| h);
+            let in_b = b.parts.iter().find(|(n, _)| n == name).map(|(_, h)| h);
+            if (in_a != Some(hash) || in_b != Some(hash)) && !parts.contains(name) {
+                parts.push(name.clone());
+            }
+        }
+        if parts.is_empty() {
+            // The same parts in a different order
+            parts.push(String::from("order"));
+        }
+        return Some(HashDivergence::Parts {
+            turn: a.turn,
+            parts,
+        });
+    }
+    let longer = if expected.len() > found.len() {
+        expected
+    } else {
+        found
+    };
+    longer
+        .get(expected.len().min(found.len()))
+        .map(|turn| HashDivergence::Length { turn: turn.turn })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(rng: u32, extra: Option<u64>) -> StateHashLog {
+        let mut log = StateHashLog::new();
+        log.add("ignored", &1);
+        for turn in 1..=3 {
+            log.begin_turn(turn);
+            log.add("rng", &(rng * turn as u32));
+            log.add("the map", "####");
+            if let Some(extra) = extra {
+                log.add("extra", &extra);
+            }
+        }
+        log.end_turn();
+        log
+    }
+
+    #[test]
+    fn hashes_are_fnv_1a_on_every_platform() {
+        let mut hasher = StableHasher::new();
+        assert_eq!(hasher.finish(), 0xcbf2_9ce4_8422_2325);
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(stable_hash(&7usize), stable_hash(&7u64));
+        assert_eq!(stable_hash(&-1isize), stable_hash(&u64::MAX));
+        assert_eq!(stable_hash(&-1i32), stable_hash(&u32::MAX));
+    }
+
+    #[test]
+    fn logs_round_trip_through_a_file() {
+        let path = std::env::temp_dir()
+            .join(format!("mage-state-hash-{}", std::process::id()))
+            .join("run.log");
+        let mut log = StateHashLog::create(&path).unwrap();
+        log.begin_turn(5);
+        log.add("rng", &42u32);
+        log.add("a=b", &1u8);
+        log.end_turn();
+        log.begin_turn(6);
+        log.end_turn();
+        let text = fs::read_to_string(&path).unwrap();
+        let loaded = load_state_hashes(&path);
+        fs::write(&path, "5 0000000000000000 rng=00\n").unwrap();
+        let corrupt = load_state_hashes(&path);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(log.turns()[0].parts[1].0, "a_b");
+        assert_eq!(loaded.unwrap(), log.turns());
+        let first = &log.turns()[0];
+        assert_eq!(
+            text.lines().next().unwrap(),
+            format!(
+                "5 {:016x} rng={:016x} a_b={:016x}",
+                first.total(),
+                stable_hash(&42u32),
+                stable_hash(&1u8)
+            )
+        );
+        assert!(matches!(
+            corrupt,
+            Err(RogueError::BadConfig { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn comparing_finds_the_first_difference() {
+        let a = run(3, None);
+        assert_eq!(a.turns().len(), 3);
+        assert_eq!(compare_state_hashes(a.turns(), run(3, None).turns()), None);
+
+        let divergence = compare_state_hashes(a.turns(), run(4, Some(0)).turns()).unwrap();
+        assert_eq!(
+            divergence,
+            HashDivergence::Parts {
+                turn: 1,
+                parts: vec![String::from("rng"), String::from("extra")],
+            }
+        );
+        assert_eq!(divergence.to_string(), "Turn 1 differs in rng, extra");
+
+        assert_eq!(
+            compare_state_hashes(a.turns(), &a.turns()[..2]),
+            Some(HashDivergence::Length { turn: 3 })
+        );
+        assert_eq!(
+            compare_state_hashes(a.turns(), &a.turns()[1..]),
+            Some(HashDivergence::Turns {
+                expected: 1,
+                found: 2
+            })
+        );
+
+        let mut swapped = a.turns().to_vec();
+        swapped[2].parts.reverse();
+        assert_eq!(
+            compare_state_hashes(a.turns(), &swapped),
+            Some(HashDivergence::Parts {
+                turn: 3,
+                parts: vec![String::from("order")],
+            })
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//
+// Status effects
+//
+// Poison, haste, blessings and the like: effects with a duration in turns that
+// may stack.  The game defines its own effect type (usually an enum) and each
+// creature holds a StatusEffects container.  When an effect wears off, a
+// StatusExpired event is published on the event bus.
+//
+
+use crate::{Entity, EventBus};
+
+// What happens when an effect is applied to a creature that already has it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stacking {
+    // Restart the duration
+    Replace,
+    // Add the new duration to what is left
+    Extend,
+    // Add a stack, up to a maximum, and restart the duration
+    Stack(u32),
+    // Keep the existing effect unchanged
+    Ignore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status<E> {
+    pub effect: E,
+    // Turns left, or None for effects that last until removed
+    pub turns: Option<u32>,
+    pub stacks: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusExpired<E> {
+    pub owner: Option<Entity>,
+    pub effect: E,
+}
+
+pub struct StatusEffects<E> {
+    owner: Option<Entity>,
+    statuses: Vec<Status<E>>,
+}
+
+impl<E> StatusEffects<E>
+where
+    E: Clone + PartialEq + 'static,
+{
+    pub fn new() -> Self {
+        StatusEffects {
+            owner: None,
+            statuses: Vec::new(),
+        }
+    }
+
+    // Expiry events will name the entity the effects belong to.
+    pub fn for_entity(entity: Entity) -> Self {
+        StatusEffects {
+            owner: Some(entity),
+            statuses: Vec::new(),
+        }
+    }
+
+    pub fn apply(&mut self, effect: E, turns: Option<u32>, stacking: Stacking) {
+        let existing = match self.statuses.iter_mut().find(|s| s.effect == effect) {
+            Some(existing) => existing,
+            None => {
+                self.statuses.push(Status {
+                    effect,
+                    turns,
+                    stacks: 1,
+                });
+                return;
+            }
+        };
+
+        match stacking {
+            Stacking::Replace => existing.turns = turns,
+            Stacking::Extend => {
+                existing.turns = match (existing.turns, turns) {
+                    (Some(a), Some(b)) => Some(a.saturating_add(b)),
+                    _ => None,
+                }
+            }
+            Stacking::Stack(max) => {
+                existing.stacks = existing.stacks.saturating_add(1).min(max.max(1));
+                existing.turns = turns;
+            }
+            Stacking::Ignore => {}
+        }
+    }
+
+    pub fn remove(&mut self, effect: &E) -> Option<Status<E>> {
+        let i = self.statuses.iter().position(|s| s.effect == *effect)?;
+        Some(self.statuses.remove(i))
+    }
+
+    pub fn clear(&mut self) {
+        self.statuses.clear();
+    }
+
+    pub fn has(&self, effect: &E) -> bool {
+        self.get(effect).is_some()
+    }
+
+    pub fn get(&self, effect: &E) -> Option<&Status<E>> {
+        self.statuses.iter().find(|s| s.effect == *effect)
+    }
+
+    pub fn stacks(&self, effect: &E) -> u32 {
+        self.get(effect).map_or(0, |s| s.stacks)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Status<E>> {
+        self.statuses.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statuses.is_empty()
+    }
+
+    // Counts down one turn, removing the effects that have run out and
+    // publishing a StatusExpired event for each of them.
+    pub fn tick(&mut self, events: &mut EventBus) {
+        let owner = self.owner;
+        self.statuses.retain(|status| {
+            let expired = status.turns == Some(0) || status.turns == Some(1);
+            if expired {
+                events.publish(StatusExpired {
+                    owner,
+                    effect: status.effect.clone(),
+                });
+            }
+            !expired
+        });
+        self.statuses.iter_mut().for_each(|status| {
+            if let Some(turns) = status.turns.as_mut() {
+                *turns -= 1;
+            }
+        });
+    }
+}
+
+impl<E> Default for StatusEffects<E>
+where
+    E: Clone + PartialEq + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Effect {
+        Poison,
+        Haste,
+    }
+
+    #[test]
+    fn stacking_rules() {
+        let mut effects = StatusEffects::new();
+        effects.apply(Effect::Poison, Some(3), Stacking::Extend);
+        effects.apply(Effect::Poison, Some(4), Stacking::Extend);
+        assert_eq!(effects.get(&Effect::Poison).unwrap().turns, Some(7));
+
+        effects.apply(Effect::Poison, Some(2), Stacking::Replace);
+        assert_eq!(effects.get(&Effect::Poison).unwrap().turns, Some(2));
+
+        effects.apply(Effect::Poison, Some(9), Stacking::Ignore);
+        assert_eq!(effects.get(&Effect::Poison).unwrap().turns, Some(2));
+
+        for _ in 0..5 {
+            effects.apply(Effect::Haste, Some(5), Stacking::Stack(3));
+        }
+        assert_eq!(effects.stacks(&Effect::Haste), 3);
+    }
+
+    #[test]
+    fn extending_saturates_and_permanent_wins() {
+        let mut effects = StatusEffects::new();
+        effects.apply(Effect::Poison, Some(u32::MAX - 1), Stacking::Extend);
+        effects.apply(Effect::Poison, Some(10), Stacking::Extend);
+        assert_eq!(effects.get(&Effect::Poison).unwrap().turns, Some(u32::MAX));
+
+        effects.apply(Effect::Poison, None, Stacking::Extend);
+        assert_eq!(effects.get(&Effect::Poison).unwrap().turns, None);
+    }
+
+    #[test]
+    fn tick_expires_and_publishes() {
+        let entity = crate::World::new().spawn();
+        let mut effects = StatusEffects::for_entity(entity);
+        let mut events = EventBus::new();
+        effects.apply(Effect::Poison, Some(2), Stacking::Replace);
+        effects.apply(Effect::Haste, None, Stacking::Replace);
+
+        effects.tick(&mut events);
+        assert_eq!(effects.get(&Effect::Poison).unwrap().turns, Some(1));
+        assert!(events.read::<StatusExpired<Effect>>().is_empty());
+
+        effects.tick(&mut events);
+        assert!(!effects.has(&Effect::Poison));
+        assert!(effects.has(&Effect::Haste));
+        assert_eq!(
+            events.drain::<StatusExpired<Effect>>(),
+            vec![StatusExpired {
+                owner: Some(entity),
+                effect: Effect::Poison,
+            }]
+        );
+    }
+}
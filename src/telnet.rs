@@ -0,0 +1,788 @@
+//
+// Telnet server
+//
+// Hosts a game for players connecting with a telnet client, like a Nethack
+// server, with no window or GPU.  Each connection is a session with its own
+// Game, made by the factory given to the server, and its own Context.  The
+// frame is sent as ANSI escape codes with 24-bit colours, only redrawing the
+// cells that changed, and the keys typed are turned back into SimInput.
+//
+//      let mut server = TelnetServer::bind("0.0.0.0:2323", || Box::new(MyGame::new()))?;
+//      server.run()?;
+//
+// The grid is the size of the player's terminal when it says (NAWS), or
+// 80x24.  Escape ends the session, as it closes the window in the engine.
+// Terminals only send key presses, so every key is reported pressed for one
+// tick, and keys typed faster than the tick rate are queued.  Mouse and touch
+// input aren't supported.
+//
+
+use crate::{
+    Context, Effect, Game, GridResized, Image, Key, KeyState, PresentInput, RogueResult, SimInput,
+    TickResult, WIDE_CONTINUATION,
+};
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_SIZE: (u32, u32) = (80, 24);
+// Terminals claiming to be larger than this are clamped
+const MAX_SIZE: (u32, u32) = (400, 200);
+const DEFAULT_TICK_RATE: Duration = Duration::from_millis(33);
+
+// Telnet commands and options (RFC 854, 857, 858, 1073)
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const IP: u8 = 244;
+const SE: u8 = 240;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const NAWS: u8 = 31;
+
+type GameFactory = Box<dyn FnMut() -> Box<dyn Game>>;
+
+//
+// Keys
+//
+
+// A key typed in the terminal, and the text it typed if any
+struct TypedKey {
+    vkey: Option<Key>,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    ch: Option<char>,
+}
+
+impl TypedKey {
+    fn key(vkey: Key) -> Self {
+        TypedKey {
+            vkey: Some(vkey),
+            ctrl: false,
+            alt: false,
+            shift: false,
+            ch: None,
+        }
+    }
+
+    fn char(ch: char) -> Self {
+        let (vkey, shift) = char_key(ch);
+        TypedKey {
+            vkey,
+            ctrl: false,
+            alt: false,
+            shift,
+            ch: Some(ch),
+        }
+    }
+}
+
+// The key for a character on a US keyboard, and whether shift is needed
+fn char_key(ch: char) -> (Option<Key>, bool) {
+    let key = match ch {
+        'a'..='z' | 'A'..='Z' => Key::from_name(&ch.to_ascii_uppercase().to_string()),
+        '0'..='9' => Key::from_name(&format!("Key{}", ch)),
+        ' ' => Some(Key::Space),
+        ',' => Some(Key::Comma),
+        '.' => Some(Key::Period),
+        '/' => Some(Key::Slash),
+        ';' => Some(Key::Semicolon),
+        '\'' => Some(Key::Apostrophe),
+        '-' => Some(Key::Minus),
+        '=' => Some(Key::Equals),
+        '[' => Some(Key::LBracket),
+        ']' => Some(Key::RBracket),
+        '\\' => Some(Key::Backslash),
+        '`' => Some(Key::Grave),
+        _ => None,
+    };
+    (key, ch.is_ascii_uppercase())
+}
+
+// The key for the final byte of a CSI or SS3 sequence, e.g. "ESC [ A" for up.
+// Sequences ending in '~' are looked up by their number instead.
+fn escape_key(last: u8, number: u32) -> Option<Key> {
+    Some(match (last, number) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', 1) | (b'~', 7) => Key::Home,
+        (b'F', _) | (b'~', 4) | (b'~', 8) => Key::End,
+        (b'P', _) => Key::F1,
+        (b'Q', _) => Key::F2,
+        (b'R', _) => Key::F3,
+        (b'S', _) => Key::F4,
+        (b'~', 2) => Key::Insert,
+        (b'~', 3) => Key::Delete,
+        (b'~', 5) => Key::PageUp,
+        (b'~', 6) => Key::PageDown,
+        (b'~', 15) => Key::F5,
+        (b'~', 17) => Key::F6,
+        (b'~', 18) => Key::F7,
+        (b'~', 19) => Key::F8,
+        (b'~', 20) => Key::F9,
+        (b'~', 21) => Key::F10,
+        (b'~', 23) => Key::F11,
+        (b'~', 24) => Key::F12,
+        _ => return None,
+    })
+}
+
+//
+// Sessions
+//
+
+struct Session {
+    stream: TcpStream,
+    addr: SocketAddr,
+    game: Box<dyn Game>,
+    context: Context,
+    size: (u32, u32),
+    // Bytes received but not yet parsed, e.g. half an escape sequence
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    keys: VecDeque<TypedKey>,
+    // The frame the terminal is showing, or None to redraw everything
+    shown: Option<Image>,
+    last_tick: Instant,
+    closing: bool,
+}
+
+impl Session {
+    fn new(stream: TcpStream, addr: SocketAddr, mut game: Box<dyn Game>) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        game.start();
+        let mut session = Session {
+            stream,
+            addr,
+            game,
+            context: Context::new(),
+            size: DEFAULT_SIZE,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            keys: VecDeque::new(),
+            shown: None,
+            last_tick: Instant::now(),
+            closing: false,
+        };
+        // Ask for character-at-a-time input without local echo, and the
+        // terminal's size
+        session.outgoing.extend_from_slice(&[
+            IAC,
+            WILL,
+            ECHO,
+            IAC,
+            WILL,
+            SUPPRESS_GO_AHEAD,
+            IAC,
+            DO,
+            SUPPRESS_GO_AHEAD,
+            IAC,
+            DO,
+            NAWS,
+        ]);
+        session.outgoing.extend_from_slice(b"\x1b[?1049h\x1b[?25l");
+        Ok(session)
+    }
+
+    // Returns false once the connection has closed.
+    fn receive(&mut self) -> bool {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        self.parse();
+        true
+    }
+
+    // Returns false if the connection has failed.
+    fn flush(&mut self) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    //
+    // Input
+    //
+
+    // Turns the bytes received into keys, leaving an incomplete telnet
+    // command at the end for next time.
+    fn parse(&mut self) {
+        let mut data = Vec::new();
+        let mut i = 0;
+
+        // Telnet commands are taken out first
+        while i < self.incoming.len() {
+            let byte = self.incoming[i];
+            if byte != IAC {
+                data.push(byte);
+                i += 1;
+                continue;
+            }
+            let command = match self.incoming.get(i + 1) {
+                Some(&command) => command,
+                None => break,
+            };
+            match command {
+                IAC => {
+                    data.push(IAC);
+                    i += 2;
+                }
+                WILL | WONT | DO | DONT => {
+                    if i + 2 >= self.incoming.len() {
+                        break;
+                    }
+                    i += 3;
+                }
+                SB => {
+                    let end = self.incoming[i..]
+                        .windows(2)
+                        .position(|pair| pair == [IAC, SE]);
+                    match end {
+                        Some(end) => {
+                            let sub = self.incoming[i + 2..i + end].to_vec();
+                            self.subnegotiation(&sub);
+                            i += end + 2;
+                        }
+                        None => break,
+                    }
+                }
+                IP => {
+                    self.closing = true;
+                    i += 2;
+                }
+                _ => i += 2,
+            }
+        }
+        self.incoming.drain(..i);
+
+        let mut j = 0;
+        while j < data.len() {
+            j += self.parse_key(&data[j..]);
+        }
+    }
+
+    fn subnegotiation(&mut self, sub: &[u8]) {
+        // Doubled IACs in the size are undoubled
+        let mut values = Vec::new();
+        let mut bytes = sub.iter().skip(1);
+        while let Some(&byte) = bytes.next() {
+            values.push(byte);
+            if byte == IAC {
+                bytes.next();
+            }
+        }
+        if sub.first() == Some(&NAWS) && values.len() >= 4 {
+            let width = u16::from_be_bytes([values[0], values[1]]) as u32;
+            let height = u16::from_be_bytes([values[2], values[3]]) as u32;
+            let size = (width.min(MAX_SIZE.0), height.min(MAX_SIZE.1));
+            if width > 0 && height > 0 && size != self.size {
+                self.size = size;
+                self.shown = None;
+                self.context.events().publish(GridResized {
+                    width: size.0,
+                    height: size.1,
+                });
+                self.game.resize(size.0, size.1);
+            }
+        }
+    }
+
+    // Parses one key from the start of the data and returns how many bytes
+    // it used.
+    fn parse_key(&mut self, data: &[u8]) -> usize {
+        let byte = data[0];
+        let (key, used) = match byte {
+            0x1b => match data.get(1) {
+                Some(b'[') | Some(b'O') => {
+                    let rest = &data[2..];
+                    match rest.iter().position(|b| (0x40..=0x7e).contains(b)) {
+                        Some(end) => {
+                            let number = std::str::from_utf8(&rest[..end])
+                                .ok()
+                                .and_then(|n| n.split(';').next()?.parse().ok())
+                                .unwrap_or(0);
+                            (escape_key(rest[end], number).map(TypedKey::key), end + 3)
+                        }
+                        None => (None, data.len()),
+                    }
+                }
+                // Alt sends ESC before the key
+                Some(&next) if next != 0x1b => {
+                    let used = self.parse_key(&data[1..]);
+                    if let Some(key) = self.keys.back_mut() {
+                        key.alt = true;
+                        key.ch = None;
+                    }
+                    return used + 1;
+                }
+                _ => (Some(TypedKey::key(Key::Escape)), 1),
+            },
+            b'\r' => {
+                let used = if matches!(data.get(1), Some(b'\n') | Some(0)) {
+                    2
+                } else {
+                    1
+                };
+                (Some(TypedKey::key(Key::Return)), used)
+            }
+            b'\n' => (Some(TypedKey::key(Key::Return)), 1),
+            b'\t' => (Some(TypedKey::key(Key::Tab)), 1),
+            0x08 | 0x7f => (Some(TypedKey::key(Key::Back)), 1),
+            0x01..=0x1a => {
+                let letter = (b'A' + byte - 1) as char;
+                let key = Key::from_name(&letter.to_string()).map(|vkey| TypedKey {
+                    ctrl: true,
+                    ..TypedKey::key(vkey)
+                });
+                (key, 1)
+            }
+            0x00..=0x1f => (None, 1),
+            _ => {
+                let len = match byte {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                let ch = data
+                    .get(..len)
+                    .and_then(|bytes| std::str::from_utf8(bytes).ok())
+                    .and_then(|s| s.chars().next());
+                (ch.map(TypedKey::char), len.min(data.len()))
+            }
+        };
+        if let Some(key) = key {
+            if key.vkey == Some(Key::Escape) && self.context.escape_quits() {
+                self.closing = true;
+            }
+            self.keys.push_back(key);
+        }
+        used
+    }
+
+    //
+    // Running the game
+    //
+
+    fn tick(&mut self) {
+        let now = Instant::now();
+        let dt = now - self.last_tick;
+        self.last_tick = now;
+        let (width, height) = self.size;
+        self.context.light_map.resize(width, height);
+
+        let typed = self.keys.pop_front();
+        let key = KeyState {
+            pressed: typed.as_ref().is_some_and(|key| key.vkey.is_some()),
+            shift: typed.as_ref().is_some_and(|key| key.shift),
+            ctrl: typed.as_ref().is_some_and(|key| key.ctrl),
+            alt: typed.as_ref().is_some_and(|key| key.alt),
+            vkey: typed.as_ref().and_then(|key| key.vkey),
+            scancode: None,
+        };
+        let text = typed
+            .and_then(|key| key.ch)
+            .map_or(String::new(), String::from);
+
+        self.context.begin_tick();
+        let result = self.game.tick(SimInput {
+            dt,
+            width,
+            height,
+            cell_width: 1,
+            cell_height: 1,
+            pixel_width: width,
+            pixel_height: height,
+            scale_factor: 1.0,
+            key: &key,
+            mouse: None,
+            gestures: &[],
+            text: &text,
+            ctx: &mut self.context,
+        });
+        if let TickResult::Stop = result {
+            self.closing = true;
+        }
+        self.context.animator.update(dt);
+        self.context.clock.update(dt);
+        self.context.weather.update(dt);
+        self.context.notifications.update(dt);
+    }
+
+    fn present(&mut self) {
+        let (width, height) = self.size;
+        let mut image = Image::new(width, height);
+        self.game.present(PresentInput {
+            width,
+            height,
+            cell_width: 1,
+            cell_height: 1,
+            pixel_width: width,
+            pixel_height: height,
+            scale_factor: 1.0,
+            image: &mut image,
+        });
+        self.context.animator.draw(&mut image);
+        self.context.weather.draw(&mut image);
+        self.context.clock.apply(&mut image);
+        self.context.light_map.apply(&mut image);
+        self.context.notifications.draw(&mut image);
+
+        let ansi = ansi_diff(self.shown.as_ref(), &image);
+        self.outgoing.extend_from_slice(ansi.as_bytes());
+        self.shown = Some(image);
+    }
+
+    fn goodbye(&mut self) {
+        self.outgoing
+            .extend_from_slice(b"\x1b[0m\x1b[2J\x1b[?25h\x1b[?1049l");
+        self.flush();
+    }
+}
+
+//
+// ANSI output
+//
+
+// The escape codes that turn the old frame into the new one, or draw all of
+// it if there isn't an old one the same size.
+fn ansi_diff(old: Option<&Image>, new: &Image) -> String {
+    let old = old.filter(|old| old.width == new.width && old.height == new.height);
+    let mut out = String::new();
+    if old.is_none() {
+        out.push_str("\x1b[0m\x1b[2J");
+    }
+    let mut colours = None;
+    let mut cursor = None;
+
+    for y in 0..new.height {
+        for x in 0..new.width {
+            let i = (y * new.width + x) as usize;
+            let cell = (new.text_image[i], new.fore_image[i], new.back_image[i]);
+            let unchanged = old.is_some_and(|old| {
+                (old.text_image[i], old.fore_image[i], old.back_image[i]) == cell
+            });
+            if unchanged || cell.0 & WIDE_CONTINUATION != 0 {
+                continue;
+            }
+            if cursor != Some((x, y)) {
+                let _ = write!(out, "\x1b[{};{}H", y + 1, x + 1);
+            }
+            if colours != Some((cell.1, cell.2)) {
+                let rgb = |c: u32| (c & 0xff, (c >> 8) & 0xff, (c >> 16) & 0xff);
+                let (fr, fg, fb) = rgb(cell.1);
+                let (br, bg, bb) = rgb(cell.2);
+                let _ = write!(
+                    out,
+                    "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                    fr, fg, fb, br, bg, bb
+                );
+                colours = Some((cell.1, cell.2));
+            }
+            let ch = match new.glyph_char(cell.0) {
+                ch if ch.is_control() => ' ',
+                ch => ch,
+            };
+            out.push(ch);
+            let wide = new
+                .text_image
+                .get(i + 1)
+                .is_some_and(|&next| x + 1 < new.width && next & WIDE_CONTINUATION != 0);
+            cursor = Some((x + if wide { 2 } else { 1 }, y));
+        }
+    }
+    out
+}
+
+//
+// Server
+//
+
+pub struct TelnetServer {
+    listener: TcpListener,
+    new_game: GameFactory,
+    sessions: Vec<Session>,
+    tick_rate: Duration,
+    max_sessions: usize,
+}
+
+impl TelnetServer {
+    pub fn bind<F>(addr: impl ToSocketAddrs, new_game: F) -> RogueResult<Self>
+    where
+        F: FnMut() -> Box<dyn Game> + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(TelnetServer {
+            listener,
+            new_game: Box::new(new_game),
+            sessions: Vec::new(),
+            tick_rate: DEFAULT_TICK_RATE,
+            max_sessions: usize::MAX,
+        })
+    }
+
+    pub fn with_tick_rate(&mut self, tick_rate: Duration) -> &mut Self {
+        self.tick_rate = tick_rate;
+        self
+    }
+
+    // Connections beyond this are told the server is full and closed.
+    pub fn with_max_sessions(&mut self, max_sessions: usize) -> &mut Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    pub fn local_addr(&self) -> RogueResult<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    // Serves sessions until the process is stopped.
+    pub fn run(&mut self) -> RogueResult<()> {
+        loop {
+            let start = Instant::now();
+            self.poll();
+            thread::sleep(self.tick_rate.saturating_sub(start.elapsed()));
+        }
+    }
+
+    // Accepts new connections and runs one tick and frame of every session.
+    // For games that run the server alongside something else.
+    pub fn poll(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, addr)) => {
+                    if self.sessions.len() >= self.max_sessions {
+                        let _ = stream.write_all(b"The server is full.\r\n");
+                        continue;
+                    }
+                    match Session::new(stream, addr, (self.new_game)()) {
+                        Ok(session) => {
+                            log::info!("Telnet session started from {}", addr);
+                            self.sessions.push(session);
+                        }
+                        Err(e) => log::warn!("Unable to start session for {}: {}", addr, e),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Unable to accept connection: {}", e);
+                    break;
+                }
+            }
+        }
+
+        self.sessions.retain_mut(|session| {
+            let open = session.receive();
+            if open && !session.closing {
+                session.tick();
+            }
+            if !open || session.closing {
+                log::info!("Telnet session from {} ended", session.addr);
+                session.goodbye();
+                return false;
+            }
+            session.present();
+            session.flush()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Point;
+    use std::{cell::RefCell, rc::Rc};
+
+    // Records the keys it is given and the sizes it is resized to
+    #[derive(Default)]
+    struct Recorder {
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Game for Recorder {
+        fn start(&mut self) {}
+
+        fn tick(&mut self, sim_input: SimInput) -> TickResult {
+            if let Some(vkey) = sim_input.key.vkey {
+                self.log.borrow_mut().push(format!("{:?}", vkey));
+            }
+            TickResult::Continue
+        }
+
+        fn present(&self, present_input: PresentInput) {
+            present_input.image.clear(0, 0);
+        }
+
+        fn resize(&mut self, width: u32, height: u32) {
+            self.log.borrow_mut().push(format!("{}x{}", width, height));
+        }
+    }
+
+    // A session and the other end of its connection
+    fn session() -> (Session, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        let session = Session::new(stream, addr, Box::new(Recorder::default())).unwrap();
+        (session, client)
+    }
+
+    // The key, shift, ctrl, alt and text of a typed key
+    type Typed = (Option<Key>, bool, bool, bool, Option<char>);
+
+    fn typed(session: &mut Session, data: &[u8]) -> Vec<Typed> {
+        session.incoming.extend_from_slice(data);
+        session.parse();
+        session
+            .keys
+            .drain(..)
+            .map(|key| (key.vkey, key.shift, key.ctrl, key.alt, key.ch))
+            .collect()
+    }
+
+    #[test]
+    fn bytes_become_keys() {
+        let (mut session, _client) = session();
+        let keys = typed(
+            &mut session,
+            "aZ\x1b[A\x1b[5~\x18\x1bx\r\n\u{e9}".as_bytes(),
+        );
+        assert_eq!(
+            keys,
+            [
+                (Some(Key::A), false, false, false, Some('a')),
+                (Some(Key::Z), true, false, false, Some('Z')),
+                (Some(Key::Up), false, false, false, None),
+                (Some(Key::PageUp), false, false, false, None),
+                (Some(Key::X), false, true, false, None),
+                (Some(Key::X), false, false, true, None),
+                (Some(Key::Return), false, false, false, None),
+                (None, false, false, false, Some('\u{e9}')),
+            ]
+        );
+        assert!(!session.closing);
+
+        // Escape ends the session unless the game has claimed it
+        typed(&mut session, b"\x1b");
+        assert!(session.closing);
+    }
+
+    #[test]
+    fn telnet_commands_are_taken_out() {
+        let (mut session, _client) = session();
+        // The window size arrives in two parts
+        let keys = typed(
+            &mut session,
+            &[b'a', IAC, WILL, NAWS, IAC, SB, NAWS, 0, 100, 0],
+        );
+        assert_eq!(keys.len(), 1);
+        assert_eq!(session.size, DEFAULT_SIZE);
+        let keys = typed(&mut session, &[30, IAC, SE, IAC, IAC, b'b']);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(session.size, (100, 30));
+        assert!(session.shown.is_none());
+
+        // Sizes are clamped, and interrupting ends the session
+        typed(
+            &mut session,
+            &[IAC, SB, NAWS, 255, 255, 255, 255, 0, 10, IAC, SE],
+        );
+        assert_eq!(session.size, (MAX_SIZE.0, 10));
+        typed(&mut session, &[IAC, IP]);
+        assert!(session.closing);
+    }
+
+    #[test]
+    fn frames_only_send_the_cells_that_changed() {
+        let mut old = Image::new(3, 1);
+        old.clear(0xff00_00ff, 0);
+        old.draw_string(Point::new(0, 0), "abc", 0xff00_00ff, 0);
+        assert_eq!(
+            ansi_diff(None, &old),
+            "\x1b[0m\x1b[2J\x1b[1;1H\x1b[38;2;255;0;0;48;2;0;0;0mabc"
+        );
+
+        let mut new = old.clone();
+        new.draw_string(Point::new(2, 0), "d", 0xff00_ff00, 0);
+        assert_eq!(
+            ansi_diff(Some(&old), &new),
+            "\x1b[1;3H\x1b[38;2;0;255;0;48;2;0;0;0md"
+        );
+        assert_eq!(ansi_diff(Some(&new), &new), "");
+    }
+
+    #[test]
+    fn the_server_runs_a_game_per_session() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let games = log.clone();
+        let mut server = TelnetServer::bind("127.0.0.1:0", move || {
+            Box::new(Recorder { log: games.clone() }) as Box<dyn Game>
+        })
+        .unwrap();
+        server.with_max_sessions(1);
+        let addr = server.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let start = Instant::now();
+        while server.session_count() == 0 && start.elapsed() < Duration::from_secs(5) {
+            server.poll();
+        }
+        assert_eq!(server.session_count(), 1);
+
+        let mut full = TcpStream::connect(addr).unwrap();
+        full.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"q").unwrap();
+        while log.borrow().is_empty() && start.elapsed() < Duration::from_secs(5) {
+            server.poll();
+        }
+        let mut refused = String::new();
+        let _ = full.read_to_string(&mut refused);
+        assert_eq!(refused, "The server is full.\r\n");
+        assert_eq!(*log.borrow(), ["Q"]);
+
+        client.write_all(b"\x1b").unwrap();
+        while server.session_count() > 0 && start.elapsed() < Duration::from_secs(5) {
+            server.poll();
+        }
+        let mut output = Vec::new();
+        let _ = client.read_to_end(&mut output);
+        assert!(output.starts_with(&[IAC, WILL, ECHO]));
+        assert!(output.ends_with(b"\x1b[?1049l"));
+    }
+}
@@ -0,0 +1,209 @@
+//
+// Touch input
+//
+// Turns raw touch events into gestures (taps, long presses and swipes) in cell
+// coordinates, which are passed to the game via SimInput.
+//
+
+use crate::{Direction, Point};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{Touch, TouchPhase},
+};
+
+// How long a finger must be held still to count as a long press
+const LONG_PRESS_TIME: Duration = Duration::from_millis(500);
+
+// How far a finger must move, in cells, before the touch becomes a swipe
+const SWIPE_DISTANCE: f64 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap(Point),
+    LongPress(Point),
+    Swipe {
+        from: Point,
+        to: Point,
+        direction: Direction,
+    },
+}
+
+struct ActiveTouch {
+    start: PhysicalPosition<f64>,
+    current: PhysicalPosition<f64>,
+    time: Instant,
+    long_pressed: bool,
+}
+
+pub(crate) struct TouchTracker {
+    touches: HashMap<u64, ActiveTouch>,
+    gestures: Vec<Gesture>,
+}
+
+impl TouchTracker {
+    pub fn new() -> Self {
+        TouchTracker {
+            touches: HashMap::new(),
+            gestures: Vec::new(),
+        }
+    }
+
+    pub fn handle_touch(&mut self, touch: &Touch, cell_size: (u32, u32)) {
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touches.insert(
+                    touch.id,
+                    ActiveTouch {
+                        start: touch.location,
+                        current: touch.location,
+                        time: Instant::now(),
+                        long_pressed: false,
+                    },
+                );
+            }
+            TouchPhase::Moved => {
+                if let Some(active) = self.touches.get_mut(&touch.id) {
+                    active.current = touch.location;
+                }
+            }
+            TouchPhase::Ended => {
+                if let Some(mut active) = self.touches.remove(&touch.id) {
+                    active.current = touch.location;
+                    let from = to_cell(active.start, cell_size);
+                    let to = to_cell(active.current, cell_size);
+                    let (dx, dy) = cell_delta(&active, cell_size);
+                    if dx.hypot(dy) >= SWIPE_DISTANCE {
+                        self.gestures.push(Gesture::Swipe {
+                            from,
+                            to,
+                            direction: Direction::from_vector(dx, dy),
+                        });
+                    } else if !active.long_pressed {
+                        self.gestures.push(Gesture::Tap(from));
+                    }
+                }
+            }
+            TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+            }
+        }
+    }
+
+    // Called before each tick to detect fingers that have been held down long
+    // enough to become long presses.
+    pub fn update(&mut self, cell_size: (u32, u32)) {
+        let now = Instant::now();
+        for active in self.touches.values_mut() {
+            let (dx, dy) = cell_delta(active, cell_size);
+            if !active.long_pressed
+                && now.duration_since(active.time) >= LONG_PRESS_TIME
+                && dx.hypot(dy) < SWIPE_DISTANCE
+            {
+                active.long_pressed = true;
+                self.gestures
+                    .push(Gesture::LongPress(to_cell(active.start, cell_size)));
+            }
+        }
+    }
+
+    pub fn gestures(&self) -> &[Gesture] {
+        &self.gestures
+    }
+
+    pub fn end_tick(&mut self) {
+        self.gestures.clear();
+    }
+}
+
+fn to_cell(p: PhysicalPosition<f64>, cell_size: (u32, u32)) -> Point {
+    Point::new(
+        (p.x / cell_size.0 as f64).floor() as i32,
+        (p.y / cell_size.1 as f64).floor() as i32,
+    )
+}
+
+fn cell_delta(active: &ActiveTouch, cell_size: (u32, u32)) -> (f64, f64) {
+    (
+        (active.current.x - active.start.x) / cell_size.0 as f64,
+        (active.current.y - active.start.y) / cell_size.1 as f64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::DeviceId;
+
+    const CELL: (u32, u32) = (10, 20);
+
+    fn touch(tracker: &mut TouchTracker, id: u64, phase: TouchPhase, x: f64, y: f64) {
+        let touch = Touch {
+            device_id: unsafe { DeviceId::dummy() },
+            phase,
+            location: PhysicalPosition::new(x, y),
+            force: None,
+            id,
+        };
+        tracker.handle_touch(&touch, CELL);
+    }
+
+    #[test]
+    fn short_touches_are_taps_and_long_moves_swipes() {
+        let mut tracker = TouchTracker::new();
+        touch(&mut tracker, 1, TouchPhase::Started, 15.0, 25.0);
+        touch(&mut tracker, 2, TouchPhase::Started, 0.0, 0.0);
+        touch(&mut tracker, 1, TouchPhase::Moved, 20.0, 30.0);
+        touch(&mut tracker, 1, TouchPhase::Ended, 24.0, 30.0);
+        // Two cells left and half a cell up
+        touch(&mut tracker, 2, TouchPhase::Moved, -20.0, -10.0);
+        touch(&mut tracker, 2, TouchPhase::Ended, -20.0, -10.0);
+        assert_eq!(
+            tracker.gestures(),
+            [
+                Gesture::Tap(Point::new(1, 1)),
+                Gesture::Swipe {
+                    from: Point::new(0, 0),
+                    to: Point::new(-2, -1),
+                    direction: Direction::West,
+                },
+            ]
+        );
+        tracker.end_tick();
+        assert!(tracker.gestures().is_empty());
+    }
+
+    #[test]
+    fn held_touches_become_long_presses() {
+        let mut tracker = TouchTracker::new();
+        touch(&mut tracker, 1, TouchPhase::Started, 35.0, 5.0);
+        tracker.update(CELL);
+        assert!(tracker.gestures().is_empty());
+
+        tracker.touches.get_mut(&1).unwrap().time -= LONG_PRESS_TIME;
+        tracker.update(CELL);
+        tracker.update(CELL);
+        assert_eq!(tracker.gestures(), [Gesture::LongPress(Point::new(3, 0))]);
+        // Lifting the finger afterwards isn't a tap as well
+        touch(&mut tracker, 1, TouchPhase::Ended, 35.0, 5.0);
+        assert_eq!(tracker.gestures().len(), 1);
+    }
+
+    #[test]
+    fn cancelled_and_moving_touches_do_nothing() {
+        let mut tracker = TouchTracker::new();
+        touch(&mut tracker, 1, TouchPhase::Started, 0.0, 0.0);
+        touch(&mut tracker, 1, TouchPhase::Cancelled, 0.0, 0.0);
+        touch(&mut tracker, 1, TouchPhase::Ended, 0.0, 0.0);
+
+        // A finger on the move isn't pressing
+        touch(&mut tracker, 2, TouchPhase::Started, 0.0, 0.0);
+        touch(&mut tracker, 2, TouchPhase::Moved, 0.0, 40.0);
+        tracker.touches.get_mut(&2).unwrap().time -= LONG_PRESS_TIME;
+        tracker.update(CELL);
+        assert!(tracker.gestures().is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//
+// Turn scheduling
+//
+// Entities act in order of game time.  After an entity acts, it is scheduled
+// again after a delay that depends on how long the action took, so fast
+// creatures get more turns than slow ones.  Entities scheduled for the same
+// time act in the order they were scheduled.
+//
+
+use crate::Entity;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TurnScheduler {
+    time: u64,
+    next_order: u64,
+    queue: BinaryHeap<Reverse<(u64, u64, Entity)>>,
+}
+
+impl TurnScheduler {
+    pub fn new() -> Self {
+        TurnScheduler {
+            time: 0,
+            next_order: 0,
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    // The time of the turn most recently returned by next_turn()
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn schedule(&mut self, entity: Entity, delay: u64) {
+        self.queue.push(Reverse((
+            self.time.saturating_add(delay),
+            self.next_order,
+            entity,
+        )));
+        self.next_order += 1;
+    }
+
+    // Returns the next entity to act, advancing the time to its turn.
+    pub fn next_turn(&mut self) -> Option<Entity> {
+        let Reverse((time, _, entity)) = self.queue.pop()?;
+        self.time = time;
+        Some(entity)
+    }
+
+    pub fn peek(&self) -> Option<(Entity, u64)> {
+        self.queue
+            .peek()
+            .map(|Reverse((time, _, entity))| (*entity, *time))
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        self.queue.retain(|Reverse((_, _, e))| *e != entity);
+    }
+
+    pub fn is_scheduled(&self, entity: Entity) -> bool {
+        self.queue.iter().any(|Reverse((_, _, e))| *e == entity)
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl Default for TurnScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::World;
+
+    #[test]
+    fn faster_entities_get_more_turns() {
+        let mut world = World::new();
+        let (fast, slow) = (world.spawn(), world.spawn());
+        let mut turns = TurnScheduler::new();
+        turns.schedule(slow, 0);
+        turns.schedule(fast, 0);
+
+        let mut order = Vec::new();
+        while order.len() < 6 {
+            let entity = turns.next_turn().unwrap();
+            order.push((entity, turns.time()));
+            turns.schedule(entity, if entity == fast { 50 } else { 100 });
+        }
+        // Entities due at the same time act in the order they were scheduled
+        assert_eq!(
+            order,
+            [
+                (slow, 0),
+                (fast, 0),
+                (fast, 50),
+                (slow, 100),
+                (fast, 100),
+                (fast, 150)
+            ]
+        );
+    }
+
+    #[test]
+    fn entities_can_be_removed() {
+        let mut world = World::new();
+        let (a, b) = (world.spawn(), world.spawn());
+        let mut turns = TurnScheduler::new();
+        assert!(turns.is_empty());
+        turns.schedule(a, 10);
+        turns.schedule(b, 20);
+        assert_eq!(turns.peek(), Some((a, 10)));
+        turns.remove(a);
+        assert!(!turns.is_scheduled(a));
+        assert!(turns.is_scheduled(b));
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns.next_turn(), Some(b));
+        assert_eq!(turns.time(), 20);
+        assert_eq!(turns.next_turn(), None);
+        // The time stays at the last turn
+        assert_eq!(turns.time(), 20);
+    }
+
+    #[test]
+    fn long_delays_wait_until_the_end_of_time() {
+        let mut world = World::new();
+        let (a, b) = (world.spawn(), world.spawn());
+        let mut turns = TurnScheduler::new();
+        turns.schedule(a, 10);
+        turns.next_turn();
+        turns.schedule(a, u64::MAX);
+        turns.schedule(b, 5);
+        assert_eq!(turns.peek(), Some((b, 15)));
+        turns.next_turn();
+        assert_eq!(turns.next_turn(), Some(a));
+        assert_eq!(turns.time(), u64::MAX);
+    }
+}
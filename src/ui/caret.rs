@@ -0,0 +1,167 @@
+//
+// Text caret
+//
+// The blinking cursor of a text field, drawn by the renderer over the main
+// window's cells after everything else so it can't be overdrawn, as in a
+// terminal.  A text field places the caret every tick it has focus:
+//
+//      ctx.caret().place(Point::new(x, y));
+//
+// and the caret hides on the first tick it isn't placed.  It stays solid for
+// a blink while it is moving, so it can be seen while typing.  The IME
+// candidate box follows it.
+//
+
+use crate::Point;
+use std::time::Duration;
+
+const DEFAULT_BLINK_RATE: Duration = Duration::from_millis(530);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaretStyle {
+    // A line along the bottom of the cell
+    #[default]
+    Underline,
+    // The whole cell, with the character in the paper colour
+    Block,
+    // A line down the left of the cell
+    Bar,
+}
+
+pub struct Caret {
+    style: CaretStyle,
+    // Time spent on and off, or None for a caret that doesn't blink
+    blink_rate: Option<Duration>,
+    // The caret's colour, or None for the ink of the cell it is over
+    colour: Option<u32>,
+    placed: Option<Point>,
+    position: Option<Point>,
+    shown_for: Duration,
+}
+
+impl Caret {
+    pub fn new() -> Self {
+        Caret {
+            style: CaretStyle::default(),
+            blink_rate: Some(DEFAULT_BLINK_RATE),
+            colour: None,
+            placed: None,
+            position: None,
+            shown_for: Duration::ZERO,
+        }
+    }
+
+    // Shows the caret at a cell of the main window until the end of the next
+    // tick.
+    pub fn place(&mut self, p: Point) {
+        self.placed = Some(p);
+    }
+
+    pub fn position(&self) -> Option<Point> {
+        self.position
+    }
+
+    pub fn style(&self) -> CaretStyle {
+        self.style
+    }
+
+    pub fn set_style(&mut self, style: CaretStyle) {
+        self.style = style;
+    }
+
+    pub fn blink_rate(&self) -> Option<Duration> {
+        self.blink_rate
+    }
+
+    pub fn set_blink_rate(&mut self, rate: Option<Duration>) {
+        self.blink_rate = rate.filter(|rate| !rate.is_zero());
+    }
+
+    pub fn set_colour(&mut self, colour: Option<u32>) {
+        self.colour = colour;
+    }
+
+    // Called by the engine after each tick.  Returns the new position if the
+    // caret has moved.
+    pub(crate) fn update(&mut self, dt: Duration) -> Option<Point> {
+        let placed = self.placed.take();
+        if placed == self.position {
+            self.shown_for += dt;
+            None
+        } else {
+            self.position = placed;
+            self.shown_for = Duration::ZERO;
+            placed
+        }
+    }
+
+    // Where to draw the caret this frame, if it is showing and not blinked
+    // off: the cell, style and colour.
+    pub(crate) fn visible(&self) -> Option<(Point, CaretStyle, Option<u32>)> {
+        let position = self.position?;
+        let on = self
+            .blink_rate
+            .is_none_or(|rate| (self.shown_for.as_millis() / rate.as_millis().max(1)) % 2 == 0);
+        on.then_some((position, self.style, self.colour))
+    }
+}
+
+impl Default for Caret {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn the_caret_shows_while_placed_and_blinks_when_still() {
+        let mut caret = Caret::new();
+        let p = Point::new(3, 1);
+        assert_eq!(caret.visible(), None);
+
+        caret.place(p);
+        assert_eq!(caret.update(ms(16)), Some(p));
+        assert_eq!(caret.visible(), Some((p, CaretStyle::Underline, None)));
+
+        caret.place(p);
+        assert_eq!(caret.update(ms(600)), None);
+        assert_eq!(caret.visible(), None);
+        caret.place(p);
+        caret.update(ms(500));
+        assert!(caret.visible().is_some());
+
+        // Moving keeps it solid for a blink
+        let q = Point::new(4, 1);
+        caret.place(q);
+        assert_eq!(caret.update(ms(16)), Some(q));
+        assert_eq!(caret.visible(), Some((q, CaretStyle::Underline, None)));
+
+        // Hidden on the first tick it isn't placed
+        assert_eq!(caret.update(ms(16)), None);
+        assert_eq!(caret.position(), None);
+        assert_eq!(caret.visible(), None);
+    }
+
+    #[test]
+    fn a_caret_without_a_blink_rate_stays_on() {
+        let mut caret = Caret::new();
+        caret.set_blink_rate(Some(Duration::ZERO));
+        assert_eq!(caret.blink_rate(), None);
+        caret.set_style(CaretStyle::Block);
+        caret.set_colour(Some(7));
+
+        let p = Point::new(0, 0);
+        for _ in 0..3 {
+            caret.place(p);
+            caret.update(ms(1000));
+            assert_eq!(caret.visible(), Some((p, CaretStyle::Block, Some(7))));
+        }
+    }
+}
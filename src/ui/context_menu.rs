@@ -0,0 +1,512 @@
+//
+// Context menu
+//
+// A popup list of actions opened at a cell, usually where the player
+// right-clicked.  Items can be disabled, have a hotkey shown next to them, or
+// open a submenu to the side.
+//
+// Up/Down move the selection, Enter or Right picks the item or opens its
+// submenu, Left goes back out of a submenu and Escape closes the menu.  An
+// item's hotkey picks it while its menu is the innermost one open.  Hovering
+// the mouse selects items, clicking picks them, and clicking outside the menu
+// closes it.  While it is open, the menu claims Escape so that closing it
+// doesn't quit the game.
+//
+
+use crate::{lerp_colour, text_width, Image, Key, NinePatch, Point, Rect, SimInput, Theme};
+
+const SUBMENU_ARROW: &str = ">";
+
+pub struct MenuItem {
+    pub id: String,
+    pub label: String,
+    pub enabled: bool,
+    pub hotkey: Option<Key>,
+    pub submenu: Option<ContextMenu>,
+}
+
+impl MenuItem {
+    pub fn new(id: &str, label: &str) -> Self {
+        MenuItem {
+            id: String::from(id),
+            label: String::from(label),
+            enabled: true,
+            hotkey: None,
+            submenu: None,
+        }
+    }
+
+    pub fn submenu(label: &str, menu: ContextMenu) -> Self {
+        MenuItem {
+            submenu: Some(menu),
+            ..MenuItem::new(label, label)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MenuEvent {
+    // The id of the item picked.  The menu closes.
+    Selected(String),
+    // Closed without picking anything
+    Closed,
+}
+
+// What a level of the menu did with the input
+enum Outcome {
+    Ignored,
+    Handled,
+    // Leave this submenu
+    Back,
+    Event(MenuEvent),
+}
+
+pub struct ContextMenu {
+    items: Vec<MenuItem>,
+    // Where the menu was opened.  It is moved to fit when drawn.
+    position: Option<Point>,
+    selected: Option<usize>,
+    submenu_open: bool,
+    // The mouse's cell last tick, so hovering only changes the selection
+    // when the mouse moves
+    last_mouse: Option<Point>,
+}
+
+impl ContextMenu {
+    pub fn new() -> Self {
+        ContextMenu {
+            items: Vec::new(),
+            position: None,
+            selected: None,
+            submenu_open: false,
+            last_mouse: None,
+        }
+    }
+
+    pub fn with_item(&mut self, item: MenuItem) -> &mut Self {
+        self.items.push(item);
+        self
+    }
+
+    pub fn items(&self) -> &[MenuItem] {
+        &self.items
+    }
+
+    pub fn items_mut(&mut self) -> &mut [MenuItem] {
+        &mut self.items
+    }
+
+    pub fn open(&mut self, p: Point) {
+        self.position = Some(p);
+        self.selected = None;
+        self.close_submenu();
+    }
+
+    pub fn close(&mut self) {
+        self.position = None;
+        self.close_submenu();
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.position.is_some()
+    }
+
+    fn close_submenu(&mut self) {
+        if self.submenu_open {
+            if let Some(menu) = self.selected_submenu() {
+                menu.close();
+            }
+        }
+        self.submenu_open = false;
+    }
+
+    fn selected_submenu(&mut self) -> Option<&mut ContextMenu> {
+        let item = self.items.get_mut(self.selected?)?;
+        if item.enabled {
+            item.submenu.as_mut()
+        } else {
+            None
+        }
+    }
+
+    //
+    // Layout
+    //
+
+    fn size(&self) -> (u32, u32) {
+        let labels = self.items.iter().map(|item| text_width(&item.label));
+        let hotkeys = self
+            .items
+            .iter()
+            .filter_map(|item| item.hotkey)
+            .map(|key| text_width(&key.name()) + 2);
+        let arrow = if self.items.iter().any(|item| item.submenu.is_some()) {
+            text_width(SUBMENU_ARROW) + 1
+        } else {
+            0
+        };
+        let width = labels.max().unwrap_or(0) + hotkeys.max().unwrap_or(0) + arrow;
+        (width + 4, self.items.len() as u32 + 2)
+    }
+
+    // Where the menu is drawn within a screen of the given size in cells
+    fn rect(&self, bounds: (u32, u32)) -> Option<Rect> {
+        let p = self.position?;
+        let (width, height) = self.size();
+        let x = p.x.min(bounds.0 as i32 - width as i32).max(0);
+        let y = p.y.min(bounds.1 as i32 - height as i32).max(0);
+        Some(Rect::new(x, y, width, height))
+    }
+
+    // Opens the selected item's submenu beside its row, on the right unless
+    // it would go off the screen.
+    fn open_submenu(&mut self, bounds: (u32, u32)) {
+        let rect = match self.rect(bounds) {
+            Some(rect) => rect,
+            None => return,
+        };
+        let row = rect.y + 1 + self.selected.unwrap_or(0) as i32;
+        if let Some(menu) = self.selected_submenu() {
+            let width = menu.size().0 as i32;
+            let right = rect.x + rect.width as i32;
+            let x = if right + width <= bounds.0 as i32 {
+                right
+            } else {
+                rect.x - width
+            };
+            menu.open(Point::new(x, row - 1));
+            menu.selected = menu.items.iter().position(|item| item.enabled);
+            self.submenu_open = true;
+        }
+    }
+
+    //
+    // Input
+    //
+
+    pub fn handle_input(&mut self, input: &SimInput) -> Option<MenuEvent> {
+        if !self.is_open() {
+            return None;
+        }
+        let bounds = (input.width, input.height);
+
+        let mut outcome = self.handle_keys(input, bounds, true);
+        if let Outcome::Ignored = outcome {
+            let mouse = input.mouse_cell();
+            let clicked = input
+                .mouse
+                .is_some_and(|mouse| mouse.left_clicked || mouse.right_clicked);
+            if let Some(p) = mouse {
+                let moved = self.last_mouse != Some(p);
+                outcome = self.handle_mouse(p, moved, clicked, bounds);
+            }
+            self.last_mouse = mouse;
+            if clicked && matches!(outcome, Outcome::Ignored) {
+                outcome = Outcome::Event(MenuEvent::Closed);
+            }
+        }
+
+        match outcome {
+            Outcome::Event(event) => {
+                self.close();
+                Some(event)
+            }
+            _ => {
+                input.ctx.claim_escape();
+                None
+            }
+        }
+    }
+
+    // Keys go to the innermost open menu.
+    fn handle_keys(&mut self, input: &SimInput, bounds: (u32, u32), root: bool) -> Outcome {
+        if self.submenu_open {
+            if let Some(menu) = self.selected_submenu() {
+                match menu.handle_keys(input, bounds, false) {
+                    Outcome::Back => {
+                        self.close_submenu();
+                        return Outcome::Handled;
+                    }
+                    outcome => return outcome,
+                }
+            }
+        }
+
+        let key = input.key;
+        if key.key_pressed(Key::Escape) {
+            return Outcome::Event(MenuEvent::Closed);
+        }
+        if key.key_pressed(Key::Left) && !root {
+            return Outcome::Back;
+        }
+        if key.key_pressed(Key::Up) {
+            self.move_selection(-1);
+            return Outcome::Handled;
+        }
+        if key.key_pressed(Key::Down) {
+            self.move_selection(1);
+            return Outcome::Handled;
+        }
+        if key.key_pressed(Key::Return) || key.key_pressed(Key::Right) {
+            return match self.selected {
+                Some(index) => self.pick(index, bounds, key.key_pressed(Key::Return)),
+                None => Outcome::Handled,
+            };
+        }
+        let hotkey = self
+            .items
+            .iter()
+            .position(|item| item.enabled && item.hotkey.is_some_and(|k| key.key_pressed(k)));
+        match hotkey {
+            Some(index) => {
+                self.selected = Some(index);
+                self.pick(index, bounds, true)
+            }
+            None => Outcome::Ignored,
+        }
+    }
+
+    // Moves to the next enabled item, wrapping around.
+    fn move_selection(&mut self, delta: i32) {
+        let count = self.items.len() as i32;
+        if count == 0 {
+            return;
+        }
+        self.close_submenu();
+        let start = self
+            .selected
+            .map_or(if delta > 0 { -1 } else { count }, |i| i as i32);
+        for step in 1..=count {
+            let index = (start + delta * step).rem_euclid(count) as usize;
+            if self.items[index].enabled {
+                self.selected = Some(index);
+                return;
+            }
+        }
+    }
+
+    // Opens the item's submenu, or picks it if it doesn't have one and
+    // select_leaf is set.
+    fn pick(&mut self, index: usize, bounds: (u32, u32), select_leaf: bool) -> Outcome {
+        let item = &self.items[index];
+        if !item.enabled {
+            return Outcome::Handled;
+        }
+        if item.submenu.is_some() {
+            if !self.submenu_open {
+                self.open_submenu(bounds);
+            }
+            Outcome::Handled
+        } else if select_leaf {
+            Outcome::Event(MenuEvent::Selected(item.id.clone()))
+        } else {
+            Outcome::Handled
+        }
+    }
+
+    // The mouse goes to the innermost menu it is over.
+    fn handle_mouse(
+        &mut self,
+        p: Point,
+        moved: bool,
+        clicked: bool,
+        bounds: (u32, u32),
+    ) -> Outcome {
+        if self.submenu_open {
+            if let Some(menu) = self.selected_submenu() {
+                match menu.handle_mouse(p, moved, clicked, bounds) {
+                    Outcome::Ignored => {}
+                    outcome => return outcome,
+                }
+            }
+        }
+
+        let rect = match self.rect(bounds) {
+            Some(rect) if rect.contains(p) => rect,
+            _ => return Outcome::Ignored,
+        };
+        let row = p.y - rect.y - 1;
+        if row < 0 || row as usize >= self.items.len() || !(moved || clicked) {
+            return Outcome::Handled;
+        }
+        let index = row as usize;
+        if self.selected != Some(index) {
+            self.close_submenu();
+            self.selected = Some(index);
+        }
+        // Hovering over a submenu's item opens it, like clicking
+        if clicked || self.items[index].submenu.is_some() {
+            self.pick(index, bounds, clicked)
+        } else {
+            Outcome::Handled
+        }
+    }
+
+    //
+    // Drawing
+    //
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme) {
+        let rect = match self.rect((image.width, image.height)) {
+            Some(rect) => rect,
+            None => return,
+        };
+        image.draw_panel(
+            Point::new(rect.x, rect.y),
+            rect.width,
+            rect.height,
+            &NinePatch::single(theme.ink, theme.paper),
+        );
+
+        let inner_width = rect.width - 2;
+        for (i, item) in self.items.iter().enumerate() {
+            let (mut ink, paper) = if self.selected == Some(i) {
+                (theme.highlight_ink, theme.highlight_paper)
+            } else {
+                (theme.ink, theme.paper)
+            };
+            if !item.enabled {
+                ink = lerp_colour(ink, paper, 0.5);
+            }
+            let mut right = match (&item.submenu, item.hotkey) {
+                (Some(_), _) => String::from(SUBMENU_ARROW),
+                (None, Some(key)) => key.name(),
+                (None, None) => String::new(),
+            };
+            right.push(' ');
+            let gap = inner_width.saturating_sub(1 + text_width(&item.label) + text_width(&right));
+            let line = format!(" {}{}{}", item.label, " ".repeat(gap as usize), right);
+            image.draw_string(
+                Point::new(rect.x + 1, rect.y + 1 + i as i32),
+                &line,
+                ink,
+                paper,
+            );
+        }
+
+        if self.submenu_open {
+            if let Some(menu) = self.selected.and_then(|i| self.items[i].submenu.as_ref()) {
+                menu.draw(image, theme);
+            }
+        }
+    }
+}
+
+impl Default for ContextMenu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, mouse_at, press, rows, with_input};
+
+    fn menu() -> ContextMenu {
+        let mut open = MenuItem::new("open", "Open");
+        open.hotkey = Some(Key::O);
+        let mut drop = MenuItem::new("drop", "Drop");
+        drop.enabled = false;
+        let mut throw = ContextMenu::new();
+        throw
+            .with_item(MenuItem::new("me", "At me"))
+            .with_item(MenuItem::new("away", "Away"));
+
+        let mut menu = ContextMenu::new();
+        menu.with_item(open)
+            .with_item(drop)
+            .with_item(MenuItem::submenu("Throw", throw));
+        menu
+    }
+
+    #[test]
+    fn keys_move_past_disabled_items_and_into_submenus() {
+        let mut menu = menu();
+        menu.open(Point::new(0, 0));
+        let claimed = press(Key::Down, |input| {
+            menu.handle_input(input);
+            !input.ctx.escape_quits()
+        });
+        assert!(claimed);
+        press(Key::Down, |input| menu.handle_input(input));
+        assert_eq!(menu.selected, Some(2));
+
+        press(Key::Right, |input| menu.handle_input(input));
+        assert!(menu.submenu_open);
+        press(Key::Left, |input| menu.handle_input(input));
+        assert!(!menu.submenu_open);
+        assert!(menu.is_open());
+
+        press(Key::Return, |input| menu.handle_input(input));
+        press(Key::Down, |input| menu.handle_input(input));
+        assert_eq!(
+            press(Key::Return, |input| menu.handle_input(input)),
+            Some(MenuEvent::Selected(String::from("away")))
+        );
+        assert!(!menu.is_open());
+        assert_eq!(press(Key::Escape, |input| menu.handle_input(input)), None);
+
+        menu.open(Point::new(0, 0));
+        assert_eq!(
+            press(Key::O, |input| menu.handle_input(input)),
+            Some(MenuEvent::Selected(String::from("open")))
+        );
+        menu.open(Point::new(0, 0));
+        assert_eq!(
+            press(Key::Escape, |input| menu.handle_input(input)),
+            Some(MenuEvent::Closed)
+        );
+    }
+
+    #[test]
+    fn clicks_pick_items_or_close_the_menu() {
+        let mut menu = menu();
+        menu.open(Point::new(10, 5));
+        let click = |menu: &mut ContextMenu, x, y| {
+            with_input(None, "", mouse_at(x, y, true), |input| {
+                menu.handle_input(input)
+            })
+        };
+        // Disabled items do nothing
+        assert_eq!(click(&mut menu, 12, 7), None);
+        assert!(menu.is_open());
+        assert_eq!(
+            click(&mut menu, 12, 6),
+            Some(MenuEvent::Selected(String::from("open")))
+        );
+
+        // Hovering opens a submenu beside its row
+        menu.open(Point::new(10, 5));
+        with_input(None, "", mouse_at(12, 8, false), |input| {
+            menu.handle_input(input)
+        });
+        assert!(menu.submenu_open);
+        assert_eq!(
+            click(&mut menu, 25, 8),
+            Some(MenuEvent::Selected(String::from("me")))
+        );
+
+        menu.open(Point::new(10, 5));
+        assert_eq!(click(&mut menu, 0, 0), Some(MenuEvent::Closed));
+    }
+
+    #[test]
+    fn menus_fit_on_the_screen() {
+        let mut menu = menu();
+        menu.open(Point::new(10, 10));
+        let mut image = blank(16, 6);
+        menu.draw(&mut image, &Theme::default());
+        assert_eq!(
+            rows(&image),
+            [
+                "                ",
+                "  ┌────────────┐",
+                "  │ Open     O │",
+                "  │ Drop       │",
+                "  │ Throw    > │",
+                "  └────────────┘",
+            ]
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//
+// Debug overlay
+//
+// A panel in the top-left corner of the main window, over everything else,
+// showing the average time of each phase of a frame and the memory used by
+// the engine's resources.  It is toggled by a key set with
+// RogueBuilder::with_debug_overlay() or by the game.
+//
+
+use crate::{text_width, Colour, Image, Key, MemoryUsage, NinePatch, Point, Profiler, PHASES};
+
+pub struct DebugOverlay {
+    key: Option<Key>,
+    visible: bool,
+    ink: u32,
+    paper: u32,
+}
+
+impl DebugOverlay {
+    pub fn new() -> Self {
+        DebugOverlay {
+            key: None,
+            visible: false,
+            ink: Colour::White.into(),
+            paper: Colour::Black.into(),
+        }
+    }
+
+    pub fn key(&self) -> Option<Key> {
+        self.key
+    }
+
+    pub fn set_key(&mut self, key: Option<Key>) {
+        self.key = key;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn set_colours(&mut self, ink: u32, paper: u32) {
+        self.ink = ink;
+        self.paper = paper;
+    }
+
+    pub(crate) fn draw(&self, image: &mut Image, profiler: &Profiler, memory: &MemoryUsage) {
+        if !self.visible {
+            return;
+        }
+        let mut lines = PHASES
+            .iter()
+            .map(|&phase| {
+                let ms = profiler.average(phase).as_secs_f64() * 1000.0;
+                (String::from(phase.name()), format!("{:.2} ms", ms))
+            })
+            .collect::<Vec<_>>();
+        lines.push((String::new(), String::new()));
+        lines.extend(memory.lines());
+
+        let name_width = lines.iter().map(|(name, _)| text_width(name)).max();
+        let value_width = lines.iter().map(|(_, value)| text_width(value)).max();
+        let name_width = name_width.unwrap_or(0);
+        let width = name_width + value_width.unwrap_or(0) + 5;
+        let height = lines.len() as u32 + 2;
+        image.draw_panel(
+            Point::new(0, 0),
+            width,
+            height,
+            &NinePatch::single(self.ink, self.paper),
+        );
+        for (i, (name, value)) in lines.iter().enumerate() {
+            let y = i as i32 + 1;
+            image.draw_string(Point::new(1, y), name, self.ink, self.paper);
+            let x = (width - 1 - text_width(value)) as i32;
+            image.draw_string(Point::new(x, y), value, self.ink, self.paper);
+        }
+    }
+}
+
+impl Default for DebugOverlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+
+    #[test]
+    fn the_overlay_lists_timings_and_memory() {
+        let memory = MemoryUsage {
+            grids: 2048,
+            glyph_atlases: 0,
+            assets: 512,
+            game: vec![(String::from("Canvas"), 3 << 19)],
+        };
+        let mut overlay = DebugOverlay::new();
+        let mut image = blank(30, 13);
+        overlay.draw(&mut image, &Profiler::new(1), &memory);
+        assert_eq!(rows(&image), rows(&blank(30, 13)));
+
+        overlay.toggle();
+        overlay.draw(&mut image, &Profiler::new(1), &memory);
+        let lines = [
+            ("tick", "0.00 ms"),
+            ("present", "0.00 ms"),
+            ("upload", "0.00 ms"),
+            ("gpu", "0.00 ms"),
+            ("", ""),
+            ("Grids", "2.0 KB"),
+            ("Glyph atlases", "0 B"),
+            ("Assets", "512 B"),
+            ("Canvas", "1.5 MB"),
+            ("Total", "1.5 MB"),
+        ];
+        let mut expected = vec![format!("┌{}┐     ", "─".repeat(23))];
+        expected.extend(
+            lines
+                .iter()
+                .map(|(name, value)| format!("│{:<13}{:>10}│     ", name, value)),
+        );
+        expected.push(format!("└{}┘     ", "─".repeat(23)));
+        expected.push(" ".repeat(30));
+        assert_eq!(rows(&image), expected);
+    }
+}
@@ -0,0 +1,325 @@
+//
+// Dialogue box
+//
+// Shows the current node of a conversation in a panel: the speaker's name on
+// the top border, the wrapped text, and the numbered responses below it.  A
+// response is picked with its number, by selecting it with Up/Down and
+// pressing Enter, or by clicking it.  Nodes without responses move on with
+// Enter.
+//
+
+use crate::{
+    align_text, text_width, wrap_text, Align, Conversation, DialogueHooks, Image, Key, NinePatch,
+    Point, Rect, SimInput, Theme,
+};
+
+const CONTINUE_PROMPT: &str = "[Enter]";
+
+pub struct DialogueBox {
+    rect: Rect,
+    selected: usize,
+}
+
+impl DialogueBox {
+    pub fn new(rect: Rect) -> Self {
+        DialogueBox { rect, selected: 0 }
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn set_rect(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // Width available for text inside the border and a space either side
+    fn inner_width(&self) -> usize {
+        self.rect.width.saturating_sub(4) as usize
+    }
+
+    // The row each response starts on
+    fn choice_rows(&self, conversation: &Conversation) -> Vec<i32> {
+        let text_lines = conversation
+            .node()
+            .map_or(0, |node| wrap_text(&node.text, self.inner_width()).len());
+        let first = self.rect.y + 1 + text_lines as i32 + 1;
+        (0..conversation.choices().len())
+            .map(|i| first + i as i32)
+            .collect()
+    }
+
+    // Returns true if the conversation moved on this tick.
+    pub fn handle_input(
+        &mut self,
+        input: &SimInput,
+        conversation: &mut Conversation,
+        hooks: &mut dyn DialogueHooks,
+    ) -> bool {
+        let count = conversation.choices().len();
+        let key = input.key;
+
+        if count == 0 {
+            if key.key_pressed(Key::Return) {
+                self.selected = 0;
+                return conversation.advance(hooks);
+            }
+            return false;
+        }
+
+        let picked = if let Some(digit) = input.text.chars().find_map(|ch| ch.to_digit(10)) {
+            (digit as usize).checked_sub(1).filter(|&i| i < count)
+        } else if key.key_pressed(Key::Return) {
+            Some(self.selected.min(count - 1))
+        } else if key.key_pressed(Key::Up) {
+            self.selected = (self.selected + count - 1) % count;
+            None
+        } else if key.key_pressed(Key::Down) {
+            self.selected = (self.selected + 1) % count;
+            None
+        } else if input.mouse.is_some_and(|mouse| mouse.left_clicked) {
+            input
+                .mouse_cell()
+                .filter(|&p| self.rect.contains(p))
+                .and_then(|p| {
+                    self.choice_rows(conversation)
+                        .iter()
+                        .position(|&y| y == p.y)
+                })
+        } else {
+            None
+        };
+
+        match picked {
+            Some(index) => {
+                self.selected = 0;
+                conversation.choose(index, hooks)
+            }
+            None => false,
+        }
+    }
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme, conversation: &Conversation) {
+        let node = match conversation.node() {
+            Some(node) => node,
+            None => return,
+        };
+        let p = Point::new(self.rect.x, self.rect.y);
+        let width = self.inner_width();
+        image.draw_panel(
+            p,
+            self.rect.width,
+            self.rect.height,
+            &NinePatch::single(theme.ink, theme.paper),
+        );
+
+        if !node.speaker.is_empty() {
+            let speaker = format!(" {} ", node.speaker);
+            let speaker = align_text(
+                &speaker,
+                (text_width(&speaker) as usize).min(width),
+                Align::Left,
+            );
+            image.draw_string(
+                Point::new(p.x + 2, p.y),
+                &speaker,
+                theme.title_ink,
+                theme.title_paper,
+            );
+        }
+
+        for (i, line) in wrap_text(&node.text, width).iter().enumerate() {
+            image.draw_string(
+                Point::new(p.x + 2, p.y + 1 + i as i32),
+                line,
+                theme.ink,
+                theme.paper,
+            );
+        }
+
+        let choices = conversation.choices();
+        for (i, (choice, y)) in choices
+            .iter()
+            .zip(self.choice_rows(conversation))
+            .enumerate()
+        {
+            let (ink, paper) = if i == self.selected {
+                (theme.highlight_ink, theme.highlight_paper)
+            } else {
+                (theme.ink, theme.paper)
+            };
+            let text = format!("{}. {}", i + 1, choice.text);
+            image.draw_string(
+                Point::new(p.x + 2, y),
+                &align_text(&text, width, Align::Left),
+                ink,
+                paper,
+            );
+        }
+
+        if choices.is_empty() {
+            let x = p.x + self.rect.width as i32 - 2 - CONTINUE_PROMPT.len() as i32;
+            let y = p.y + self.rect.height as i32 - 1;
+            image.draw_string(
+                Point::new(x, y),
+                CONTINUE_PROMPT,
+                theme.title_ink,
+                theme.title_paper,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, mouse_at, press, rows, with_input};
+    use crate::{Dialogue, DialogueChoice, DialogueNode};
+
+    struct Hooks;
+
+    impl DialogueHooks for Hooks {}
+
+    fn node(text: &str, choices: &[&str], next: Option<&str>) -> DialogueNode {
+        DialogueNode {
+            speaker: String::from("Innkeeper"),
+            text: String::from(text),
+            effects: Vec::new(),
+            choices: choices
+                .iter()
+                .map(|&text| DialogueChoice {
+                    text: String::from(text),
+                    condition: None,
+                    effects: Vec::new(),
+                    next: Some(String::from("room")),
+                })
+                .collect(),
+            next: next.map(String::from),
+        }
+    }
+
+    fn conversation() -> Conversation {
+        let mut dialogue = Dialogue::new("hello");
+        dialogue
+            .with_node(
+                "hello",
+                node("What'll it be?", &["A room.", "A meal.", "Nothing."], None),
+            )
+            .with_node("room", node("Upstairs.", &[], Some("bye")))
+            .with_node("bye", node("Sleep well.", &[], None));
+        Conversation::new(&dialogue, &mut Hooks)
+    }
+
+    fn handle(
+        dialogue_box: &mut DialogueBox,
+        conversation: &mut Conversation,
+        key: Option<Key>,
+        text: &str,
+        mouse: Option<crate::MouseState>,
+    ) -> bool {
+        with_input(key, text, mouse, |input| {
+            dialogue_box.handle_input(input, conversation, &mut Hooks)
+        })
+    }
+
+    #[test]
+    fn nodes_are_drawn_with_their_choices() {
+        let dialogue_box = DialogueBox::new(Rect::new(0, 0, 20, 7));
+        let mut image = blank(20, 7);
+        dialogue_box.draw(&mut image, &Theme::default(), &conversation());
+        assert_eq!(
+            rows(&image),
+            [
+                "┌─ Innkeeper ──────┐",
+                "│ What'll it be?   │",
+                "│                  │",
+                "│ 1. A room.       │",
+                "│ 2. A meal.       │",
+                "│ 3. Nothing.      │",
+                "└──────────────────┘",
+            ]
+        );
+
+        let mut conversation = conversation();
+        conversation.choose(0, &mut Hooks);
+        let mut image = blank(20, 7);
+        dialogue_box.draw(&mut image, &Theme::default(), &conversation);
+        assert_eq!(rows(&image)[6], "└──────────[Enter]─┘");
+    }
+
+    #[test]
+    fn choices_are_picked_by_number_or_selection() {
+        let mut dialogue_box = DialogueBox::new(Rect::new(0, 0, 20, 7));
+        let mut conversation = conversation();
+
+        // Numbers past the last choice are ignored
+        assert!(!handle(
+            &mut dialogue_box,
+            &mut conversation,
+            None,
+            "9",
+            None
+        ));
+        assert!(!handle(
+            &mut dialogue_box,
+            &mut conversation,
+            Some(Key::Up),
+            "",
+            None
+        ));
+        assert_eq!(dialogue_box.selected(), 2);
+        assert!(handle(
+            &mut dialogue_box,
+            &mut conversation,
+            Some(Key::Return),
+            "",
+            None
+        ));
+        assert_eq!(dialogue_box.selected(), 0);
+        assert_eq!(conversation.node_id(), Some("room"));
+
+        let mut conversation = self::conversation();
+        assert!(handle(
+            &mut dialogue_box,
+            &mut conversation,
+            None,
+            "2",
+            None
+        ));
+        assert_eq!(conversation.node_id(), Some("room"));
+    }
+
+    #[test]
+    fn choices_are_clicked_and_nodes_without_them_move_on_with_enter() {
+        let mut dialogue_box = DialogueBox::new(Rect::new(0, 0, 20, 7));
+        let mut conversation = conversation();
+        // The text's row isn't a choice
+        let text = mouse_at(5, 1, true);
+        assert!(!handle(
+            &mut dialogue_box,
+            &mut conversation,
+            None,
+            "",
+            text
+        ));
+        let meal = mouse_at(5, 4, true);
+        assert!(handle(&mut dialogue_box, &mut conversation, None, "", meal));
+        assert_eq!(conversation.node_id(), Some("room"));
+
+        assert!(!handle(
+            &mut dialogue_box,
+            &mut conversation,
+            None,
+            "1",
+            None
+        ));
+        assert!(press(Key::Return, |input| {
+            dialogue_box.handle_input(input, &mut conversation, &mut Hooks)
+        }));
+        assert_eq!(conversation.node_id(), Some("bye"));
+    }
+}
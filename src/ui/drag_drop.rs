@@ -0,0 +1,409 @@
+//
+// Drag and drop
+//
+// Moves things between widgets with the mouse, e.g. from the inventory to an
+// equipment slot.  Like HitRegions, the game registers the areas things can
+// be dragged from and dropped on each frame, either lists with one slot per
+// row or grids of fixed-size slots:
+//
+//      drag.clear();
+//      drag.add_list("inventory", inventory_rect);
+//      drag.add_grid("equipment", equipment_rect, 3, 1);
+//
+// A drag starts when the left button is held on a slot and the mouse moves
+// off it, if the game's pick function says what is in the slot.  A ghost of
+// it follows the cursor, and the slot under the cursor is highlighted in the
+// theme's colours if it would take the drop or in red if not.  Releasing the
+// button drops it; Escape or the right button cancels.
+//
+
+use crate::{Colour, Image, Key, Point, Rect, SimInput, Theme};
+
+// A slot within an area: the row of a list, or the cell of a grid counted
+// across then down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DragSlot {
+    pub area: String,
+    pub index: usize,
+}
+
+// What the game's pick function returns for a slot that can be dragged from
+pub struct DragItem<T> {
+    pub data: T,
+    // Drawn next to the cursor while dragging
+    pub ghost: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DragEvent<T> {
+    Dropped {
+        data: T,
+        from: DragSlot,
+        to: DragSlot,
+    },
+    // Dropped outside any slot, on a slot that won't take it, or cancelled
+    Cancelled {
+        data: T,
+        from: DragSlot,
+    },
+}
+
+enum AreaLayout {
+    List,
+    // Slot size in cells
+    Grid { width: u32, height: u32 },
+}
+
+struct DragArea {
+    name: String,
+    rect: Rect,
+    layout: AreaLayout,
+}
+
+// The state of the left button while nothing is being dragged
+enum Press {
+    Released,
+    // Held on a slot, but the mouse hasn't moved off it yet
+    Armed(Point, DragSlot),
+    // Held, but nothing can be dragged until it is released
+    Spent,
+}
+
+type Validator<T> = Box<dyn Fn(&T, &DragSlot, &DragSlot) -> bool>;
+
+struct Drag<T> {
+    item: DragItem<T>,
+    from: DragSlot,
+}
+
+pub struct DragDrop<T> {
+    areas: Vec<DragArea>,
+    validator: Option<Validator<T>>,
+    press: Press,
+    drag: Option<Drag<T>>,
+    cursor: Option<Point>,
+}
+
+impl<T> DragDrop<T> {
+    pub fn new() -> Self {
+        DragDrop {
+            areas: Vec::new(),
+            validator: None,
+            press: Press::Released,
+            drag: None,
+            cursor: None,
+        }
+    }
+
+    //
+    // Areas
+    // Areas added later are on top of earlier ones.
+    //
+
+    pub fn clear(&mut self) {
+        self.areas.clear();
+    }
+
+    pub fn add_list(&mut self, name: &str, rect: Rect) {
+        self.add_area(name, rect, AreaLayout::List);
+    }
+
+    pub fn add_grid(&mut self, name: &str, rect: Rect, slot_width: u32, slot_height: u32) {
+        let layout = AreaLayout::Grid {
+            width: slot_width.max(1),
+            height: slot_height.max(1),
+        };
+        self.add_area(name, rect, layout);
+    }
+
+    fn add_area(&mut self, name: &str, rect: Rect, layout: AreaLayout) {
+        self.areas.push(DragArea {
+            name: String::from(name),
+            rect,
+            layout,
+        });
+    }
+
+    // Decides whether a slot takes what is being dragged from another.  By
+    // default every slot does, except the one it came from.
+    pub fn set_validator<F>(&mut self, validator: F)
+    where
+        F: Fn(&T, &DragSlot, &DragSlot) -> bool + 'static,
+    {
+        self.validator = Some(Box::new(validator));
+    }
+
+    pub fn slot_at(&self, p: Point) -> Option<DragSlot> {
+        let area = self.areas.iter().rev().find(|area| area.rect.contains(p))?;
+        let (x, y) = ((p.x - area.rect.x) as u32, (p.y - area.rect.y) as u32);
+        let index = match area.layout {
+            AreaLayout::List => y,
+            AreaLayout::Grid { width, height } => {
+                let columns = (area.rect.width / width).max(1);
+                if x / width >= columns {
+                    return None;
+                }
+                (y / height) * columns + x / width
+            }
+        };
+        Some(DragSlot {
+            area: area.name.clone(),
+            index: index as usize,
+        })
+    }
+
+    fn slot_rect(&self, slot: &DragSlot) -> Option<Rect> {
+        let area = self
+            .areas
+            .iter()
+            .rev()
+            .find(|area| area.name == slot.area)?;
+        let index = slot.index as u32;
+        Some(match area.layout {
+            AreaLayout::List => {
+                Rect::new(area.rect.x, area.rect.y + index as i32, area.rect.width, 1)
+            }
+            AreaLayout::Grid { width, height } => {
+                let columns = (area.rect.width / width).max(1);
+                Rect::new(
+                    area.rect.x + ((index % columns) * width) as i32,
+                    area.rect.y + ((index / columns) * height) as i32,
+                    width,
+                    height,
+                )
+            }
+        })
+    }
+
+    fn accepts(&self, drag: &Drag<T>, to: &DragSlot) -> bool {
+        match &self.validator {
+            Some(validator) => validator(&drag.item.data, &drag.from, to),
+            None => *to != drag.from,
+        }
+    }
+
+    //
+    // Dragging
+    //
+
+    // Widgets under an area should ignore the mouse while this returns true,
+    // so the drop isn't also taken as a click.
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // What is being dragged and where from
+    pub fn dragging(&self) -> Option<(&T, &DragSlot)> {
+        self.drag.as_ref().map(|drag| (&drag.item.data, &drag.from))
+    }
+
+    // Call once per tick.  pick says what is in a slot the player starts
+    // dragging from, or None if it is empty.
+    pub fn handle_input<F>(&mut self, input: &SimInput, pick: F) -> Option<DragEvent<T>>
+    where
+        F: FnOnce(&DragSlot) -> Option<DragItem<T>>,
+    {
+        let mouse = input.mouse.filter(|mouse| mouse.on_screen);
+        self.cursor = input.mouse_cell();
+        let held = mouse.is_some_and(|mouse| mouse.left_pressed);
+
+        if let Some(drag) = self.drag.take() {
+            let cancelled = input.key.key_pressed(Key::Escape)
+                || mouse.is_some_and(|mouse| mouse.right_clicked);
+            if held && !cancelled {
+                self.drag = Some(drag);
+                return None;
+            }
+            if !held {
+                self.press = Press::Released;
+            }
+            let target = self
+                .cursor
+                .filter(|_| !cancelled)
+                .and_then(|p| self.slot_at(p))
+                .filter(|to| self.accepts(&drag, to));
+            return Some(match target {
+                Some(to) => DragEvent::Dropped {
+                    data: drag.item.data,
+                    from: drag.from,
+                    to,
+                },
+                None => DragEvent::Cancelled {
+                    data: drag.item.data,
+                    from: drag.from,
+                },
+            });
+        }
+
+        let p = match self.cursor {
+            Some(p) if held => p,
+            _ => {
+                self.press = Press::Released;
+                return None;
+            }
+        };
+        self.press = match std::mem::replace(&mut self.press, Press::Spent) {
+            Press::Released => match self.slot_at(p) {
+                Some(slot) => Press::Armed(p, slot),
+                None => Press::Spent,
+            },
+            Press::Armed(start, from) if start == p => Press::Armed(start, from),
+            Press::Armed(_, from) => {
+                if let Some(item) = pick(&from) {
+                    self.drag = Some(Drag { item, from });
+                }
+                Press::Spent
+            }
+            Press::Spent => Press::Spent,
+        };
+        None
+    }
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme) {
+        let (drag, p) = match (&self.drag, self.cursor) {
+            (Some(drag), Some(p)) => (drag, p),
+            _ => return,
+        };
+        if let Some(to) = self.slot_at(p) {
+            let paper = if self.accepts(drag, &to) {
+                theme.highlight_paper
+            } else {
+                Colour::Red.into()
+            };
+            if let Some(rect) = self.slot_rect(&to) {
+                set_paper(image, rect, paper);
+            }
+        }
+        image.draw_string(
+            p.offset(1, 0),
+            &drag.item.ghost,
+            theme.highlight_ink,
+            theme.highlight_paper,
+        );
+    }
+}
+
+fn set_paper(image: &mut Image, rect: Rect, paper: u32) {
+    let (x, y, width, height) = image.clip(Point::new(rect.x, rect.y), rect.width, rect.height);
+    for row in y..y + height {
+        if let Some(i) = image.coords_to_index(x, row) {
+            image.back_image[i..i + width as usize].fill(paper);
+        }
+    }
+}
+
+impl<T> Default for DragDrop<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, mouse_at, with_input};
+
+    fn areas() -> DragDrop<&'static str> {
+        let mut drag = DragDrop::new();
+        drag.add_list("inventory", Rect::new(0, 0, 10, 3));
+        drag.add_grid("equipment", Rect::new(20, 0, 6, 2), 3, 1);
+        drag
+    }
+
+    fn sword(_: &DragSlot) -> Option<DragItem<&'static str>> {
+        Some(DragItem {
+            data: "sword",
+            ghost: String::from("/"),
+        })
+    }
+
+    // The left button held, or not, over a cell
+    fn mouse(
+        drag: &mut DragDrop<&'static str>,
+        x: i32,
+        y: i32,
+        held: bool,
+    ) -> Option<DragEvent<&'static str>> {
+        with_input(None, "", mouse_at(x, y, held), |input| {
+            drag.handle_input(input, sword)
+        })
+    }
+
+    fn slot(area: &str, index: usize) -> DragSlot {
+        DragSlot {
+            area: String::from(area),
+            index,
+        }
+    }
+
+    #[test]
+    fn slots_are_rows_or_grid_cells() {
+        let drag = areas();
+        assert_eq!(drag.slot_at(Point::new(1, 2)), Some(slot("inventory", 2)));
+        assert_eq!(drag.slot_at(Point::new(23, 1)), Some(slot("equipment", 3)));
+        assert_eq!(drag.slot_at(Point::new(15, 0)), None);
+        assert_eq!(
+            drag.slot_rect(&slot("equipment", 3)),
+            Some(Rect::new(23, 1, 3, 1))
+        );
+    }
+
+    #[test]
+    fn dragging_starts_when_the_mouse_leaves_the_slot() {
+        let mut drag = areas();
+        assert_eq!(mouse(&mut drag, 1, 0, true), None);
+        assert_eq!(mouse(&mut drag, 1, 0, true), None);
+        assert!(!drag.is_dragging());
+        mouse(&mut drag, 2, 0, true);
+        assert_eq!(drag.dragging(), Some((&"sword", &slot("inventory", 0))));
+
+        mouse(&mut drag, 23, 0, true);
+        let mut image = blank(30, 3);
+        drag.draw(&mut image, &Theme::default());
+        let highlight = Theme::default().highlight_paper;
+        assert_eq!(
+            image.back_image[22..27],
+            [0, highlight, highlight, highlight, 0]
+        );
+        assert_eq!(image.glyph_char(image.text_image[24]), '/');
+
+        assert_eq!(
+            mouse(&mut drag, 23, 0, false),
+            Some(DragEvent::Dropped {
+                data: "sword",
+                from: slot("inventory", 0),
+                to: slot("equipment", 1),
+            })
+        );
+        assert!(!drag.is_dragging());
+    }
+
+    #[test]
+    fn refused_drops_and_escape_cancel() {
+        let mut drag = areas();
+        drag.set_validator(|_, _, to| to.area == "equipment");
+        let cancelled = Some(DragEvent::Cancelled {
+            data: "sword",
+            from: slot("inventory", 0),
+        });
+
+        mouse(&mut drag, 1, 0, true);
+        mouse(&mut drag, 1, 1, true);
+        assert_eq!(mouse(&mut drag, 1, 1, false), cancelled);
+
+        mouse(&mut drag, 1, 0, true);
+        mouse(&mut drag, 23, 0, true);
+        let escape = with_input(Some(Key::Escape), "", mouse_at(23, 0, true), |input| {
+            drag.handle_input(input, sword)
+        });
+        assert_eq!(escape, cancelled);
+
+        // Nothing is dragged from empty slots, even once the mouse moves on
+        with_input(None, "", mouse_at(1, 0, true), |input| {
+            drag.handle_input(input, |_| None)
+        });
+        mouse(&mut drag, 1, 1, true);
+        mouse(&mut drag, 1, 2, true);
+        assert!(!drag.is_dragging());
+    }
+}
@@ -0,0 +1,460 @@
+//
+// Forms
+//
+// Screens such as character creation are built from a column of labelled
+// fields: steppers for numbers, cyclers for choosing between options, text
+// entry and buttons.  Steppers can share a pool of points so that raising one
+// stat means lowering another.
+//
+// The focused field is changed with Up/Down or Tab, values with Left/Right, and
+// buttons are pressed with Enter.  Clicking on a field focuses it, and clicking
+// on the arrows either side of a value changes it.
+//
+
+use crate::{align_text, text_width, Align, Image, Key, Point, SimInput, Theme};
+
+const LEFT_ARROW: &str = "<";
+const RIGHT_ARROW: &str = ">";
+
+pub enum FieldKind {
+    Stepper { value: i32, min: i32, max: i32 },
+    Cycler { options: Vec<String>, index: usize },
+    Text { value: String, max_len: usize },
+    Button,
+}
+
+pub struct Field {
+    pub label: String,
+    pub kind: FieldKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormEvent {
+    // A field's value was changed by the player
+    Changed(String),
+    // A button was pressed
+    Pressed(String),
+}
+
+pub struct Form {
+    position: Point,
+    fields: Vec<Field>,
+    focused: usize,
+    pool: Option<i32>,
+}
+
+impl Form {
+    pub fn new(position: Point) -> Self {
+        Form {
+            position,
+            fields: Vec::new(),
+            focused: 0,
+            pool: None,
+        }
+    }
+
+    fn with_field(&mut self, label: &str, kind: FieldKind) -> &mut Self {
+        self.fields.push(Field {
+            label: String::from(label),
+            kind,
+        });
+        self
+    }
+
+    pub fn with_stepper(&mut self, label: &str, value: i32, min: i32, max: i32) -> &mut Self {
+        self.with_field(label, FieldKind::Stepper { value, min, max })
+    }
+
+    pub fn with_cycler(&mut self, label: &str, options: &[&str]) -> &mut Self {
+        let options = options.iter().map(|&option| String::from(option)).collect();
+        self.with_field(label, FieldKind::Cycler { options, index: 0 })
+    }
+
+    pub fn with_text(&mut self, label: &str, value: &str, max_len: usize) -> &mut Self {
+        let value = String::from(value);
+        self.with_field(label, FieldKind::Text { value, max_len })
+    }
+
+    pub fn with_button(&mut self, label: &str) -> &mut Self {
+        self.with_field(label, FieldKind::Button)
+    }
+
+    // Steppers draw from a shared pool of points: raising a stepper takes a
+    // point from the pool and lowering it gives the point back.
+    pub fn with_pool(&mut self, points: i32) -> &mut Self {
+        self.pool = Some(points);
+        self
+    }
+
+    //
+    // Values
+    //
+
+    fn field(&self, label: &str) -> Option<&FieldKind> {
+        self.fields
+            .iter()
+            .find(|field| field.label == label)
+            .map(|field| &field.kind)
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn pool(&self) -> Option<i32> {
+        self.pool
+    }
+
+    pub fn value(&self, label: &str) -> Option<i32> {
+        match self.field(label) {
+            Some(FieldKind::Stepper { value, .. }) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn option(&self, label: &str) -> Option<&str> {
+        match self.field(label) {
+            Some(FieldKind::Cycler { options, index }) => options.get(*index).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, label: &str) -> Option<&str> {
+        match self.field(label) {
+            Some(FieldKind::Text { value, .. }) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn focused(&self) -> Option<&str> {
+        self.fields
+            .get(self.focused)
+            .map(|field| field.label.as_str())
+    }
+
+    // Where the caret goes in the focused text field, if there is one, to
+    // pass to Context::caret() each tick.
+    pub fn caret_position(&self) -> Option<Point> {
+        let field = self.fields.get(self.focused)?;
+        match &field.kind {
+            FieldKind::Text { value, .. } => Some(Point::new(
+                self.position.x + (self.label_width() + 2 + text_width(value) as usize) as i32,
+                self.position.y + self.focused as i32,
+            )),
+            _ => None,
+        }
+    }
+
+    //
+    // Input
+    //
+
+    // Steps the field's value up or down.  Returns true if it changed.
+    fn step(&mut self, index: usize, delta: i32) -> bool {
+        let pool = &mut self.pool;
+        match &mut self.fields[index].kind {
+            FieldKind::Stepper { value, min, max } => {
+                let mut new_value = (*value + delta).clamp(*min, *max);
+                if let Some(points) = pool {
+                    new_value = new_value.min(*value + *points);
+                    *points -= new_value - *value;
+                }
+                let changed = new_value != *value;
+                *value = new_value;
+                changed
+            }
+            FieldKind::Cycler { options, index } if !options.is_empty() => {
+                let len = options.len() as i32;
+                *index = (*index as i32 + delta).rem_euclid(len) as usize;
+                len > 1
+            }
+            _ => false,
+        }
+    }
+
+    fn changed(&self, index: usize) -> FormEvent {
+        FormEvent::Changed(self.fields[index].label.clone())
+    }
+
+    pub fn handle_input(&mut self, input: &SimInput) -> Option<FormEvent> {
+        if self.fields.is_empty() {
+            return None;
+        }
+        let key = input.key;
+        let index = self.focused;
+
+        if let Some(p) = input
+            .mouse
+            .filter(|mouse| mouse.left_clicked)
+            .and(input.mouse_cell())
+        {
+            return self.click(p);
+        }
+
+        if key.key_pressed(Key::Up) {
+            self.focused = (index + self.fields.len() - 1) % self.fields.len();
+        } else if key.key_pressed(Key::Down) || key.key_pressed(Key::Tab) {
+            self.focused = (index + 1) % self.fields.len();
+        } else if key.key_pressed(Key::Left) {
+            if self.step(index, -1) {
+                return Some(self.changed(index));
+            }
+        } else if key.key_pressed(Key::Right) {
+            if self.step(index, 1) {
+                return Some(self.changed(index));
+            }
+        } else if let FieldKind::Button = self.fields[index].kind {
+            if key.key_pressed(Key::Return) {
+                return Some(FormEvent::Pressed(self.fields[index].label.clone()));
+            }
+        } else if let FieldKind::Text { value, max_len } = &mut self.fields[index].kind {
+            let old = value.clone();
+            if key.key_pressed(Key::Back) {
+                value.pop();
+            }
+            input
+                .text
+                .chars()
+                .filter(|ch| ch.is_ascii() && !ch.is_ascii_control())
+                .for_each(|ch| {
+                    if value.len() < *max_len {
+                        value.push(ch);
+                    }
+                });
+            if *value != old {
+                return Some(self.changed(index));
+            }
+        }
+
+        None
+    }
+
+    fn click(&mut self, p: Point) -> Option<FormEvent> {
+        let row = p.y - self.position.y;
+        if row < 0 || row as usize >= self.fields.len() || p.x < self.position.x {
+            return None;
+        }
+        let index = row as usize;
+        self.focused = index;
+
+        let x = (p.x - self.position.x) as usize;
+        let value_x = self.label_width() + 1;
+        match self.fields[index].kind {
+            FieldKind::Button => Some(FormEvent::Pressed(self.fields[index].label.clone())),
+            FieldKind::Stepper { .. } | FieldKind::Cycler { .. } => {
+                let delta = if x == value_x {
+                    -1
+                } else if x == value_x + self.value_width() + 1 {
+                    1
+                } else {
+                    return None;
+                };
+                if self.step(index, delta) {
+                    Some(self.changed(index))
+                } else {
+                    None
+                }
+            }
+            FieldKind::Text { .. } => None,
+        }
+    }
+
+    //
+    // Drawing
+    //
+
+    fn label_width(&self) -> usize {
+        self.fields
+            .iter()
+            .filter(|field| !matches!(field.kind, FieldKind::Button))
+            .map(|field| text_width(&field.label) as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn value_width(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|field| match &field.kind {
+                FieldKind::Stepper { min, max, .. } => {
+                    min.to_string().len().max(max.to_string().len())
+                }
+                FieldKind::Cycler { options, .. } => {
+                    options.iter().map(String::len).max().unwrap_or(0)
+                }
+                FieldKind::Text { max_len, .. } => *max_len + 1,
+                FieldKind::Button => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn format_field(&self, field: &Field, focused: bool) -> String {
+        let label = align_text(&field.label, self.label_width(), Align::Left);
+        let width = self.value_width();
+        match &field.kind {
+            FieldKind::Stepper { value, .. } => format!(
+                "{} {}{}{}",
+                label,
+                LEFT_ARROW,
+                align_text(&value.to_string(), width, Align::Right),
+                RIGHT_ARROW
+            ),
+            FieldKind::Cycler { options, index } => format!(
+                "{} {}{}{}",
+                label,
+                LEFT_ARROW,
+                align_text(
+                    options.get(*index).map_or("", String::as_str),
+                    width,
+                    Align::Centre
+                ),
+                RIGHT_ARROW
+            ),
+            FieldKind::Text { value, .. } => {
+                let cursor = if focused { "_" } else { " " };
+                format!(
+                    "{} [{}]",
+                    label,
+                    align_text(&format!("{}{}", value, cursor), width, Align::Left)
+                )
+            }
+            FieldKind::Button => format!("[{}]", field.label),
+        }
+    }
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme) {
+        for (i, field) in self.fields.iter().enumerate() {
+            let focused = i == self.focused;
+            let (ink, paper) = if focused {
+                (theme.highlight_ink, theme.highlight_paper)
+            } else {
+                (theme.ink, theme.paper)
+            };
+            image.draw_string(
+                Point::new(self.position.x, self.position.y + i as i32),
+                &self.format_field(field, focused),
+                ink,
+                paper,
+            );
+        }
+
+        if let Some(points) = self.pool {
+            image.draw_string(
+                Point::new(
+                    self.position.x,
+                    self.position.y + self.fields.len() as i32 + 1,
+                ),
+                &format!("Points left: {}", points),
+                theme.title_ink,
+                theme.title_paper,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, mouse_at, press, rows, with_input};
+
+    // Str, Class, Name and OK, one a row from (1, 1)
+    fn form() -> Form {
+        let mut form = Form::new(Point::new(1, 1));
+        form.with_stepper("Str", 10, 8, 12)
+            .with_cycler("Class", &["Mage", "Thief"])
+            .with_text("Name", "Al", 4)
+            .with_button("OK");
+        form
+    }
+
+    fn changed(label: &str) -> Option<FormEvent> {
+        Some(FormEvent::Changed(String::from(label)))
+    }
+
+    #[test]
+    fn steppers_spend_points_from_the_pool() {
+        let mut form = form();
+        form.with_pool(1);
+        assert_eq!(
+            press(Key::Right, |input| form.handle_input(input)),
+            changed("Str")
+        );
+        // Nothing left to spend
+        assert_eq!(press(Key::Right, |input| form.handle_input(input)), None);
+        assert_eq!((form.value("Str"), form.pool()), (Some(11), Some(0)));
+        for _ in 0..5 {
+            press(Key::Left, |input| form.handle_input(input));
+        }
+        assert_eq!((form.value("Str"), form.pool()), (Some(8), Some(3)));
+    }
+
+    #[test]
+    fn fields_are_changed_from_the_keyboard() {
+        let mut form = form();
+        press(Key::Up, |input| form.handle_input(input));
+        assert_eq!(form.focused(), Some("OK"));
+        assert_eq!(
+            press(Key::Return, |input| form.handle_input(input)),
+            Some(FormEvent::Pressed(String::from("OK")))
+        );
+        press(Key::Tab, |input| form.handle_input(input));
+        press(Key::Down, |input| form.handle_input(input));
+        assert_eq!(
+            press(Key::Left, |input| form.handle_input(input)),
+            changed("Class")
+        );
+        assert_eq!(form.option("Class"), Some("Thief"));
+
+        press(Key::Down, |input| form.handle_input(input));
+        let typed = with_input(None, "ice\u{8}", None, |input| form.handle_input(input));
+        assert_eq!((typed, form.text("Name")), (changed("Name"), Some("Alic")));
+        press(Key::Back, |input| form.handle_input(input));
+        assert_eq!(form.text("Name"), Some("Ali"));
+        assert_eq!(form.caret_position(), Some(Point::new(11, 3)));
+        assert_eq!(form.value("Name"), None);
+    }
+
+    #[test]
+    fn clicks_focus_fields_and_press_arrows() {
+        let mut form = form();
+        let click = |form: &mut Form, x, y| {
+            with_input(None, "", mouse_at(x, y, true), |input| {
+                form.handle_input(input)
+            })
+        };
+        // "Str   <10>": the arrows are after the label and a space
+        assert_eq!(click(&mut form, 7, 1), changed("Str"));
+        assert_eq!(form.value("Str"), Some(9));
+        assert_eq!(click(&mut form, 13, 1), changed("Str"));
+        assert_eq!(form.value("Str"), Some(10));
+        assert_eq!(click(&mut form, 3, 3), None);
+        assert_eq!(form.focused(), Some("Name"));
+        assert_eq!(
+            click(&mut form, 1, 4),
+            Some(FormEvent::Pressed(String::from("OK")))
+        );
+        assert_eq!(click(&mut form, 1, 9), None);
+        assert_eq!(form.focused(), Some("OK"));
+    }
+
+    #[test]
+    fn forms_line_up_their_values() {
+        let mut form = form();
+        form.with_pool(2);
+        let mut image = blank(16, 7);
+        form.draw(&mut image, &Theme::default());
+        assert_eq!(
+            rows(&image),
+            [
+                "                ",
+                " Str   <   10>  ",
+                " Class <Mage >  ",
+                " Name  [Al   ]  ",
+                " [OK]           ",
+                "                ",
+                " Points left: 2 ",
+            ]
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//
+// Graphs
+//
+// Small charts for stat screens and debugging, drawn on a pixel canvas so
+// they have a finer resolution than the cells:
+//
+//      Sparkline   a bar for each recent value, newest on the right
+//      LineGraph   one or more series as lines across the rect
+//      Histogram   how many values fall into each of a number of ranges
+//
+// Values are usually kept in a Series, which only holds the most recent ones,
+// e.g. the last 100 frame times.
+//
+
+use crate::{Image, PixelCanvas, PixelMode, Point, Rect};
+use std::collections::VecDeque;
+
+pub struct Series {
+    values: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl Series {
+    pub fn new(capacity: usize) -> Self {
+        Series {
+            values: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    // Adds a value, dropping the oldest one if the series is full.
+    pub fn push(&mut self, value: f32) {
+        if self.values.len() == self.capacity {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Oldest first
+    pub fn values(&self) -> impl Iterator<Item = f32> + Clone + '_ {
+        self.values.iter().copied()
+    }
+
+    pub fn latest(&self) -> Option<f32> {
+        self.values.back().copied()
+    }
+
+    // The smallest and largest values, or None if the series is empty
+    pub fn range(&self) -> Option<(f32, f32)> {
+        self.values().fold(None, |range, v| match range {
+            None => Some((v, v)),
+            Some((low, high)) => Some((low.min(v), high.max(v))),
+        })
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.values().sum::<f32>() / self.len() as f32)
+        }
+    }
+}
+
+// The range to draw: the fixed one if given, otherwise from zero (or the
+// lowest value if negative) to the highest.
+fn graph_range(fixed: Option<(f32, f32)>, values: impl Iterator<Item = f32>) -> (f32, f32) {
+    fixed.unwrap_or_else(|| {
+        values.fold((0.0, 0.0), |(low, high): (f32, f32), v| {
+            (low.min(v), high.max(v))
+        })
+    })
+}
+
+// The pixel row of a value, 0 being the top
+fn graph_y(value: f32, (low, high): (f32, f32), height: u32) -> i32 {
+    let t = if high > low {
+        ((value - low) / (high - low)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    height as i32 - 1 - (t * (height - 1) as f32).round() as i32
+}
+
+//
+// Sparkline
+//
+
+pub struct Sparkline {
+    colour: u32,
+    paper: u32,
+    range: Option<(f32, f32)>,
+}
+
+impl Sparkline {
+    pub fn new(colour: u32) -> Self {
+        Sparkline {
+            colour,
+            paper: 0,
+            range: None,
+        }
+    }
+
+    pub fn with_paper(&mut self, paper: u32) -> &mut Self {
+        self.paper = paper;
+        self
+    }
+
+    pub fn with_range(&mut self, low: f32, high: f32) -> &mut Self {
+        self.range = Some((low, high));
+        self
+    }
+
+    // Draws the newest values that fit, one column each.
+    pub fn draw(&self, image: &mut Image, rect: Rect, series: &Series) {
+        let mut canvas = PixelCanvas::new(PixelMode::HalfBlock, rect.width, rect.height);
+        canvas.with_paper(self.paper);
+        let skip = series.len().saturating_sub(canvas.width() as usize);
+        let range = graph_range(self.range, series.values().skip(skip));
+        let left = canvas.width() as i32 - (series.len() - skip) as i32;
+        for (i, value) in series.values().skip(skip).enumerate() {
+            let x = left + i as i32;
+            let top = graph_y(value, range, canvas.height());
+            let bottom = canvas.height() as i32 - 1;
+            canvas.line(Point::new(x, top), Point::new(x, bottom), self.colour);
+        }
+        canvas.draw(image, Point::new(rect.x, rect.y));
+    }
+}
+
+//
+// Line graph
+//
+
+pub struct LineGraph {
+    mode: PixelMode,
+    paper: u32,
+    range: Option<(f32, f32)>,
+}
+
+impl LineGraph {
+    pub fn new() -> Self {
+        LineGraph {
+            mode: PixelMode::HalfBlock,
+            paper: 0,
+            range: None,
+        }
+    }
+
+    // Braille gives a smoother line but needs a braille font.
+    pub fn with_mode(&mut self, mode: PixelMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_paper(&mut self, paper: u32) -> &mut Self {
+        self.paper = paper;
+        self
+    }
+
+    pub fn with_range(&mut self, low: f32, high: f32) -> &mut Self {
+        self.range = Some((low, high));
+        self
+    }
+
+    // Each series is stretched across the whole width, using the space it
+    // will have when full.  All series share the same vertical range.
+    pub fn draw(&self, image: &mut Image, rect: Rect, series: &[(&Series, u32)]) {
+        let mut canvas = PixelCanvas::new(self.mode, rect.width, rect.height);
+        canvas.with_paper(self.paper);
+        let range = graph_range(
+            self.range,
+            series.iter().flat_map(|(series, _)| series.values()),
+        );
+        let width = canvas.width().max(1) as f32 - 1.0;
+        for &(series, colour) in series {
+            let step = width / (series.capacity().max(2) - 1) as f32;
+            let points = series
+                .values()
+                .enumerate()
+                .map(|(i, value)| {
+                    let x = (i as f32 * step).round() as i32;
+                    Point::new(x, graph_y(value, range, canvas.height()))
+                })
+                .collect::<Vec<_>>();
+            match points.len() {
+                0 => {}
+                1 => canvas.set_pixel(points[0], colour),
+                _ => points
+                    .windows(2)
+                    .for_each(|pair| canvas.line(pair[0], pair[1], colour)),
+            }
+        }
+        canvas.draw(image, Point::new(rect.x, rect.y));
+    }
+}
+
+impl Default for LineGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//
+// Histogram
+//
+
+pub struct Histogram {
+    colour: u32,
+    paper: u32,
+    range: Option<(f32, f32)>,
+}
+
+impl Histogram {
+    pub fn new(colour: u32) -> Self {
+        Histogram {
+            colour,
+            paper: 0,
+            range: None,
+        }
+    }
+
+    pub fn with_paper(&mut self, paper: u32) -> &mut Self {
+        self.paper = paper;
+        self
+    }
+
+    // The range of values split into bins.  Values outside go in the first or
+    // last bin.  Without it, the lowest and highest values are used.
+    pub fn with_range(&mut self, low: f32, high: f32) -> &mut Self {
+        self.range = Some((low, high));
+        self
+    }
+
+    // Counts the values in each bin, one bin per column.
+    pub fn bins(&self, values: impl Iterator<Item = f32> + Clone, count: usize) -> Vec<u32> {
+        let mut bins = vec![0; count];
+        if count == 0 {
+            return bins;
+        }
+        let (low, high) = self.range.unwrap_or_else(|| {
+            values
+                .clone()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), v| {
+                    (low.min(v), high.max(v))
+                })
+        });
+        for value in values {
+            let t = if high > low {
+                (value - low) / (high - low)
+            } else {
+                0.0
+            };
+            let bin = (t * count as f32).floor().clamp(0.0, (count - 1) as f32) as usize;
+            bins[bin] += 1;
+        }
+        bins
+    }
+
+    pub fn draw(&self, image: &mut Image, rect: Rect, values: impl Iterator<Item = f32> + Clone) {
+        let mut canvas = PixelCanvas::new(PixelMode::HalfBlock, rect.width, rect.height);
+        canvas.with_paper(self.paper);
+        let bins = self.bins(values, canvas.width() as usize);
+        let most = bins.iter().copied().max().unwrap_or(0);
+        for (x, &count) in bins.iter().enumerate() {
+            if count > 0 {
+                let top = graph_y(count as f32, (0.0, most as f32), canvas.height());
+                let bottom = canvas.height() as i32 - 1;
+                canvas.line(
+                    Point::new(x as i32, top),
+                    Point::new(x as i32, bottom),
+                    self.colour,
+                );
+            }
+        }
+        canvas.draw(image, Point::new(rect.x, rect.y));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_keep_the_most_recent_values() {
+        let mut series = Series::new(3);
+        assert_eq!(series.range(), None);
+        assert_eq!(series.mean(), None);
+        for value in [5.0, 1.0, 4.0, 2.0] {
+            series.push(value);
+        }
+        assert_eq!(series.values().collect::<Vec<_>>(), [1.0, 4.0, 2.0]);
+        assert_eq!(series.latest(), Some(2.0));
+        assert_eq!(series.range(), Some((1.0, 4.0)));
+        assert_eq!(series.mean(), Some(7.0 / 3.0));
+        assert_eq!(Series::new(0).capacity(), 1);
+    }
+
+    #[test]
+    fn histograms_bin_values_by_range() {
+        let histogram = Histogram::new(1);
+        let values = [0.0, 1.0, 2.5, 9.0, 10.0];
+        assert_eq!(histogram.bins(values.iter().copied(), 4), [2, 1, 0, 2]);
+        assert_eq!(histogram.bins(values.iter().copied(), 0), Vec::<u32>::new());
+
+        let mut fixed = Histogram::new(1);
+        fixed.with_range(0.0, 2.0);
+        assert_eq!(fixed.bins(values.iter().copied(), 2), [1, 4]);
+    }
+
+    #[test]
+    fn sparklines_draw_the_newest_values_on_the_right() {
+        let mut series = Series::new(10);
+        series.push(2.0);
+        series.push(0.0);
+        let mut image = Image::new(3, 1);
+        Sparkline::new(7).draw(&mut image, Rect::new(0, 0, 3, 1), &series);
+        // Half blocks: empty, full height, and the bottom pixel of zero
+        assert_eq!(image.text_image, [b' ' as u32, 223, 220]);
+        assert_eq!(image.fore_image[1..], [7, 7]);
+        assert_eq!(image.back_image[1], 7);
+    }
+
+    #[test]
+    fn line_graphs_stretch_series_across_the_width() {
+        let mut series = Series::new(3);
+        series.push(0.0);
+        series.push(1.0);
+        let mut graph = LineGraph::new();
+        graph.with_range(0.0, 1.0);
+        let mut image = Image::new(5, 1);
+        graph.draw(&mut image, Rect::new(0, 0, 5, 1), &[(&series, 7)]);
+        // Two of three points reach halfway across, rising from the bottom
+        assert_eq!(image.text_image, [220, 223, 223, b' ' as u32, b' ' as u32]);
+    }
+}
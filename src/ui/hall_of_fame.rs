@@ -0,0 +1,75 @@
+//
+// Hall of fame
+//
+// A ready-made table of the best runs from the run history.
+//
+
+use crate::{Align, ColumnWidth, Rect, RunHistory, Table};
+
+impl Table {
+    // Lists the top runs, as many as fit in the rectangle, with the given
+    // run's row selected if it made it onto the table (e.g. the run that has
+    // just ended).
+    pub fn hall_of_fame(rect: Rect, history: &RunHistory, highlight: Option<usize>) -> Self {
+        let mut table = Table::new(rect);
+        table
+            .with_column("#", ColumnWidth::Content, Align::Right)
+            .with_column("Name", ColumnWidth::Content, Align::Left)
+            .with_column("Score", ColumnWidth::Content, Align::Right)
+            .with_column("Date", ColumnWidth::Content, Align::Left)
+            .with_column("Fate", ColumnWidth::Fill, Align::Left);
+
+        let top = history.top(table.page_size());
+        let highlight = highlight.and_then(|i| history.records().get(i));
+        for (rank, record) in top.iter().enumerate() {
+            table.add_row(&[
+                &(rank + 1).to_string(),
+                &record.name,
+                &record.score.to_string(),
+                &record.date(),
+                &record.cause,
+            ]);
+            if highlight == Some(*record) {
+                table.select(Some(rank));
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+    use crate::{RunRecord, Theme};
+    use std::fs;
+
+    #[test]
+    fn the_best_runs_are_listed_with_the_latest_selected() {
+        let dir = std::env::temp_dir().join(format!("mage-hall-of-fame-{}", std::process::id()));
+        let mut history = RunHistory::open_path(&dir.join("history.txt")).unwrap();
+        for (name, score) in [("Ann", 5), ("Bob", 30), ("Cat", 20), ("Dan", 10)] {
+            let mut record = RunRecord::new(name, score, 0, "Eaten");
+            record.timestamp = 0;
+            history.add(record).unwrap();
+        }
+        let _ = fs::remove_dir_all(&dir);
+
+        // Three rows fit under the header, so Ann doesn't make it
+        let table = Table::hall_of_fame(Rect::new(0, 0, 32, 4), &history, Some(2));
+        assert_eq!(table.selected(), Some(1));
+        let mut image = blank(32, 4);
+        table.draw(&mut image, &Theme::default());
+        assert_eq!(
+            rows(&image)[1..],
+            [
+                "1 Bob     30 1970-01-01 Eaten   ",
+                "2 Cat     20 1970-01-01 Eaten   ",
+                "3 Dan     10 1970-01-01 Eaten   ",
+            ]
+        );
+
+        let table = Table::hall_of_fame(Rect::new(0, 0, 32, 4), &history, Some(0));
+        assert_eq!(table.selected(), None);
+    }
+}
@@ -0,0 +1,126 @@
+//
+// Inventory screen
+//
+// Lists an inventory with its letters, counts and weights.  Entries are chosen
+// by typing their letter or by selecting them and pressing Enter.
+//
+
+use crate::{
+    inventory_index, inventory_letter, Align, ColumnWidth, Image, Inventory, Item, Key, Rect,
+    SimInput, Table, Theme,
+};
+
+pub struct InventoryScreen {
+    table: Table,
+}
+
+impl InventoryScreen {
+    pub fn new(rect: Rect) -> Self {
+        let mut table = Table::new(rect);
+        table
+            .with_column("", ColumnWidth::Fixed(1), Align::Left)
+            .with_column("Item", ColumnWidth::Fill, Align::Left)
+            .with_column("Qty", ColumnWidth::Content, Align::Right)
+            .with_column("Wt", ColumnWidth::Content, Align::Right);
+        InventoryScreen { table }
+    }
+
+    // Updates the list to show the inventory's current contents.
+    pub fn refresh<I>(&mut self, inventory: &Inventory<I>)
+    where
+        I: Item + 'static,
+    {
+        let selected = self.table.selected();
+        self.table.clear_rows();
+        for (i, (item, count)) in inventory.entries().iter().enumerate() {
+            let letter = inventory_letter(i).map_or(String::new(), String::from);
+            self.table.add_row(&[
+                &letter,
+                &item.name(),
+                &count.to_string(),
+                &(item.weight() * count).to_string(),
+            ]);
+        }
+        self.table.select(selected.or(Some(0)));
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.table.selected()
+    }
+
+    // Returns the index of the entry the player chose this tick, if any.
+    pub fn handle_input(&mut self, input: &SimInput) -> Option<usize> {
+        if let Some(index) = input
+            .text
+            .chars()
+            .filter_map(inventory_index)
+            .find(|&index| index < self.table.row_count())
+        {
+            self.table.select(Some(index));
+            return Some(index);
+        }
+        if input.key.key_pressed(Key::Return) {
+            return self.table.selected();
+        }
+        self.table.handle_input(input);
+        None
+    }
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme) {
+        self.table.draw(image, theme);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, press, rows, with_input};
+    use crate::EventBus;
+
+    #[derive(Clone)]
+    struct Potion;
+
+    impl Item for Potion {
+        fn name(&self) -> String {
+            String::from("Potion")
+        }
+
+        fn weight(&self) -> u32 {
+            2
+        }
+    }
+
+    fn screen() -> InventoryScreen {
+        let mut events = EventBus::new();
+        let mut inventory = Inventory::new();
+        inventory.add(Potion, 3, &mut events).unwrap();
+        inventory.add(Potion, 1, &mut events).unwrap();
+        let mut screen = InventoryScreen::new(Rect::new(0, 0, 16, 3));
+        screen.refresh(&inventory);
+        screen
+    }
+
+    #[test]
+    fn entries_are_listed_with_letters_and_weights() {
+        let mut image = blank(16, 3);
+        screen().draw(&mut image, &Theme::default());
+        assert_eq!(rows(&image)[1..], ["a Potion    3  6", "b Potion    1  2"]);
+    }
+
+    #[test]
+    fn entries_are_chosen_by_letter_or_enter() {
+        let mut screen = screen();
+        assert_eq!(screen.selected(), Some(0));
+        // Letters past the end are ignored
+        assert_eq!(
+            with_input(None, "cb", None, |input| screen.handle_input(input)),
+            Some(1)
+        );
+        assert_eq!(screen.selected(), Some(1));
+        assert_eq!(press(Key::Up, |input| screen.handle_input(input)), None);
+        assert_eq!(
+            press(Key::Return, |input| screen.handle_input(input)),
+            Some(0)
+        );
+    }
+}
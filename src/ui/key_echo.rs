@@ -0,0 +1,171 @@
+//
+// Key echo
+//
+// Shows the last few keys pressed, e.g. "Ctrl+S  Down x3", in the bottom-left
+// corner of the main window over everything else.  Turned on with the
+// key_echo input option, for streams and tutorial recordings.  Keys pressed
+// again straight away are counted instead of repeated, and each key fades out
+// once it has been shown for the display time.
+//
+
+use crate::{lerp_colour, text_width, Colour, Image, NinePatch, Point};
+use std::{collections::VecDeque, time::Duration};
+
+const DISPLAY_TIME: Duration = Duration::from_millis(1500);
+// The last part of the display time is spent fading out
+const FADE_TIME: Duration = Duration::from_millis(500);
+const MAX_KEYS: usize = 5;
+
+struct EchoedKey {
+    label: String,
+    count: u32,
+    shown_for: Duration,
+}
+
+impl EchoedKey {
+    fn text(&self) -> String {
+        if self.count > 1 {
+            format!("{} x{}", self.label, self.count)
+        } else {
+            self.label.clone()
+        }
+    }
+}
+
+pub struct KeyEcho {
+    // Oldest first
+    keys: VecDeque<EchoedKey>,
+    ink: u32,
+    paper: u32,
+}
+
+impl KeyEcho {
+    pub fn new() -> Self {
+        KeyEcho {
+            keys: VecDeque::new(),
+            ink: Colour::White.into(),
+            paper: Colour::Black.into(),
+        }
+    }
+
+    pub fn set_colours(&mut self, ink: u32, paper: u32) {
+        self.ink = ink;
+        self.paper = paper;
+    }
+
+    pub fn push(&mut self, label: &str) {
+        match self.keys.back_mut() {
+            Some(key) if key.label == label => {
+                key.count += 1;
+                key.shown_for = Duration::ZERO;
+            }
+            _ => {
+                self.keys.push_back(EchoedKey {
+                    label: String::from(label),
+                    count: 1,
+                    shown_for: Duration::ZERO,
+                });
+                if self.keys.len() > MAX_KEYS {
+                    self.keys.pop_front();
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    pub(crate) fn update(&mut self, dt: Duration) {
+        for key in self.keys.iter_mut() {
+            key.shown_for += dt;
+        }
+        self.keys.retain(|key| key.shown_for < DISPLAY_TIME);
+    }
+
+    pub(crate) fn draw(&self, image: &mut Image) {
+        if self.keys.is_empty() || image.height < 3 {
+            return;
+        }
+        let texts = self.keys.iter().map(EchoedKey::text).collect::<Vec<_>>();
+        let width = texts.iter().map(|text| text_width(text) + 2).sum::<u32>() + 2;
+        let y = image.height as i32 - 4;
+        image.draw_panel(
+            Point::new(1, y),
+            width,
+            3,
+            &NinePatch::single(self.ink, self.paper),
+        );
+
+        let mut x = 2;
+        for (key, text) in self.keys.iter().zip(&texts) {
+            let fade = key.shown_for.saturating_sub(DISPLAY_TIME - FADE_TIME);
+            let ink = lerp_colour(
+                self.ink,
+                self.paper,
+                fade.as_secs_f32() / FADE_TIME.as_secs_f32(),
+            );
+            image.draw_string(Point::new(x + 1, y + 1), text, ink, self.paper);
+            x += text_width(text) as i32 + 2;
+        }
+    }
+}
+
+impl Default for KeyEcho {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+
+    fn texts(echo: &KeyEcho) -> Vec<String> {
+        echo.keys.iter().map(EchoedKey::text).collect()
+    }
+
+    #[test]
+    fn repeated_keys_are_counted() {
+        let mut echo = KeyEcho::new();
+        for label in ["A", "B", "B", "C", "D", "E", "F", "F", "F"] {
+            echo.push(label);
+        }
+        assert_eq!(texts(&echo), ["B x2", "C", "D", "E", "F x3"]);
+
+        // Pressing a key again restarts its time
+        echo.update(Duration::from_millis(1000));
+        echo.push("F");
+        echo.update(Duration::from_millis(1000));
+        assert_eq!(texts(&echo), ["F x4"]);
+        echo.update(DISPLAY_TIME);
+        assert!(texts(&echo).is_empty());
+    }
+
+    #[test]
+    fn keys_are_boxed_in_the_corner_and_fade() {
+        let mut echo = KeyEcho::default();
+        echo.push("Ctrl+S");
+        echo.update(Duration::from_millis(1250));
+        echo.push("Down");
+        echo.push("Down");
+        echo.push("Down");
+
+        let mut image = blank(20, 4);
+        echo.draw(&mut image);
+        assert_eq!(
+            rows(&image),
+            [
+                " ┌─────────────────┐",
+                " │ Ctrl+S  Down x3 │",
+                " └─────────────────┘",
+                "                    ",
+            ]
+        );
+        let white = Colour::White.into();
+        let half = lerp_colour(white, Colour::Black.into(), 0.5);
+        assert_eq!(image.fore_image[23], half);
+        assert_eq!(image.fore_image[31], white);
+    }
+}
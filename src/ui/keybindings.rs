@@ -0,0 +1,367 @@
+//
+// Key binding screen
+//
+// Lists the game's actions with the keys bound to them and lets the player
+// rebind them.  Enter waits for the next key press and binds it to the
+// selected action, Delete removes the action's bindings and Backspace cancels.
+// If the key is already bound to another action, the player is asked to
+// confirm taking it over.  Changes are saved straight away when the screen has
+// been given a config file.  While waiting for a key, the screen claims
+// Escape, so it can be bound like any other key.
+//
+
+use crate::{
+    align_text, Align, Binding, ColumnWidth, Image, InputMap, Key, Point, Rect, RogueResult,
+    SimInput, Table, Theme,
+};
+use std::path::{Path, PathBuf};
+
+enum RebindState<A> {
+    Browsing,
+    Capturing,
+    // The new binding clashes with an existing binding for another action
+    Conflict {
+        binding: Binding,
+        existing: Binding,
+        other: A,
+    },
+}
+
+pub struct KeyBindingScreen<A> {
+    rect: Rect,
+    actions: Vec<(A, String)>,
+    table: Table,
+    state: RebindState<A>,
+    path: Option<PathBuf>,
+}
+
+impl<A> KeyBindingScreen<A>
+where
+    A: Copy + PartialEq,
+{
+    // The last row of the rectangle is used for prompts.
+    pub fn new(rect: Rect, actions: &[(A, &str)], map: &InputMap<A>) -> Self {
+        let mut table = Table::new(Rect::new(
+            rect.x,
+            rect.y,
+            rect.width,
+            rect.height.saturating_sub(1),
+        ));
+        table
+            .with_column("Action", ColumnWidth::Content, Align::Left)
+            .with_column("Keys", ColumnWidth::Fill, Align::Left);
+
+        let mut screen = KeyBindingScreen {
+            rect,
+            actions: actions
+                .iter()
+                .map(|&(action, name)| (action, String::from(name)))
+                .collect(),
+            table,
+            state: RebindState::Browsing,
+            path: None,
+        };
+        screen.refresh(map);
+        screen.table.select(Some(0));
+        screen
+    }
+
+    // The file the bindings are saved to whenever they change.
+    pub fn with_config(&mut self, path: &Path) -> &mut Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+
+    pub fn action_name(&self, action: A) -> Option<&str> {
+        self.actions
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        !matches!(self.state, RebindState::Browsing)
+    }
+
+    fn selected_action(&self) -> Option<A> {
+        self.table
+            .selected()
+            .and_then(|row| self.actions.get(row))
+            .map(|(action, _)| *action)
+    }
+
+    // Returns true if the bindings changed.
+    pub fn handle_input(&mut self, input: &SimInput, map: &mut InputMap<A>) -> RogueResult<bool> {
+        let key = input.key;
+        let action = match self.selected_action() {
+            Some(action) => action,
+            None => {
+                self.table.handle_input(input);
+                return Ok(false);
+            }
+        };
+
+        let changed = match self.state {
+            RebindState::Browsing => {
+                if key.key_pressed(Key::Return) {
+                    self.state = RebindState::Capturing;
+                    false
+                } else if key.key_pressed(Key::Delete) {
+                    map.unbind_action(action);
+                    true
+                } else {
+                    self.table.handle_input(input);
+                    false
+                }
+            }
+            RebindState::Capturing => match Binding::pressed(key) {
+                Some(Binding::Key(Key::Back)) => {
+                    self.state = RebindState::Browsing;
+                    false
+                }
+                Some(binding) => {
+                    let conflict = map
+                        .bindings()
+                        .find(|(b, a)| b.matches(key) && *a != action)
+                        .copied();
+                    match conflict {
+                        Some((existing, other)) => {
+                            self.state = RebindState::Conflict {
+                                binding,
+                                existing,
+                                other,
+                            };
+                            false
+                        }
+                        None => {
+                            self.state = RebindState::Browsing;
+                            map.bind(binding, action);
+                            true
+                        }
+                    }
+                }
+                None => false,
+            },
+            RebindState::Conflict {
+                binding, existing, ..
+            } => {
+                if key.key_pressed(Key::Return) {
+                    self.state = RebindState::Browsing;
+                    map.unbind(existing).bind(binding, action);
+                    true
+                } else if key.key_pressed(Key::Back) {
+                    self.state = RebindState::Browsing;
+                    false
+                } else {
+                    false
+                }
+            }
+        };
+
+        if self.is_capturing() {
+            input.ctx.claim_escape();
+        }
+        if changed {
+            self.refresh(map);
+            if let Some(path) = &self.path {
+                let actions = &self.actions;
+                map.save(path, |action| {
+                    actions
+                        .iter()
+                        .find(|(a, _)| *a == action)
+                        .map_or_else(String::new, |(_, name)| name.clone())
+                })?;
+            }
+        }
+        Ok(changed)
+    }
+
+    // Loads the bindings from the config file, if there is one.
+    pub fn load(&mut self, map: &mut InputMap<A>) -> RogueResult<()> {
+        if let Some(path) = &self.path {
+            let actions = &self.actions;
+            map.load(path, |name| {
+                actions
+                    .iter()
+                    .find(|(_, n)| n == name)
+                    .map(|(action, _)| *action)
+            })?;
+            self.refresh(map);
+        }
+        Ok(())
+    }
+
+    fn prompt(&self) -> String {
+        let name = self
+            .selected_action()
+            .and_then(|action| self.action_name(action))
+            .unwrap_or("");
+        match &self.state {
+            RebindState::Browsing => String::from("Enter: rebind  Delete: clear  Up/Down: select"),
+            RebindState::Capturing => format!("Press a key for {} (Backspace to cancel)", name),
+            RebindState::Conflict { binding, other, .. } => format!(
+                "{} is used by {}. Enter to replace, Backspace to cancel",
+                binding.name(),
+                self.action_name(*other).unwrap_or("another action")
+            ),
+        }
+    }
+
+    // Updates the list to show the current bindings.  This only needs to be
+    // called if the map is changed outside of the screen.
+    pub fn refresh(&mut self, map: &InputMap<A>) {
+        let selected = self.table.selected();
+        let scroll = self.table.scroll();
+        self.table.clear_rows();
+        for (action, name) in &self.actions {
+            let keys = map
+                .bindings_for(*action)
+                .map(|binding| binding.name())
+                .collect::<Vec<_>>()
+                .join(", ");
+            self.table.add_row(&[name, &keys]);
+        }
+        self.table.scroll_to(scroll);
+        self.table.select(selected);
+    }
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme) {
+        self.table.draw(image, theme);
+        image.draw_string(
+            Point::new(self.rect.x, self.rect.y + self.rect.height as i32 - 1),
+            &align_text(&self.prompt(), self.rect.width as usize, Align::Left),
+            theme.title_ink,
+            theme.title_paper,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, press, rows};
+    use std::fs;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Action {
+        Fire,
+        Jump,
+    }
+
+    fn screen(map: &InputMap<Action>) -> KeyBindingScreen<Action> {
+        KeyBindingScreen::new(
+            Rect::new(0, 0, 40, 4),
+            &[(Action::Fire, "Fire"), (Action::Jump, "Jump")],
+            map,
+        )
+    }
+
+    fn keys(map: &InputMap<Action>, action: Action) -> Vec<Binding> {
+        map.bindings_for(action).collect()
+    }
+
+    fn type_key(
+        screen: &mut KeyBindingScreen<Action>,
+        map: &mut InputMap<Action>,
+        key: Key,
+    ) -> bool {
+        press(key, |input| screen.handle_input(input, map).unwrap())
+    }
+
+    #[test]
+    fn keys_are_bound_to_the_selected_action() {
+        let mut map = InputMap::new();
+        map.bind(Binding::Key(Key::F), Action::Fire);
+        let mut screen = screen(&map);
+
+        // Down selects Jump, Enter starts capturing and the next key is bound
+        assert!(!type_key(&mut screen, &mut map, Key::Down));
+        assert!(!type_key(&mut screen, &mut map, Key::Return));
+        assert!(screen.is_capturing());
+        assert!(type_key(&mut screen, &mut map, Key::Space));
+        assert!(!screen.is_capturing());
+        assert_eq!(keys(&map, Action::Jump), [Binding::Key(Key::Space)]);
+
+        // Backspace cancels capturing and Delete clears the bindings
+        type_key(&mut screen, &mut map, Key::Return);
+        assert!(!type_key(&mut screen, &mut map, Key::Back));
+        assert!(!screen.is_capturing());
+        assert!(type_key(&mut screen, &mut map, Key::Delete));
+        assert!(keys(&map, Action::Jump).is_empty());
+        assert_eq!(keys(&map, Action::Fire), [Binding::Key(Key::F)]);
+    }
+
+    #[test]
+    fn taking_a_key_from_another_action_is_confirmed() {
+        let mut map = InputMap::new();
+        map.bind(Binding::Key(Key::F), Action::Fire);
+        let mut screen = screen(&map);
+        type_key(&mut screen, &mut map, Key::Down);
+
+        // Cancelled
+        type_key(&mut screen, &mut map, Key::Return);
+        assert!(!type_key(&mut screen, &mut map, Key::F));
+        assert!(screen.is_capturing());
+        assert_eq!(
+            screen.prompt(),
+            "F is used by Fire. Enter to replace, Backspace to cancel"
+        );
+        assert!(!type_key(&mut screen, &mut map, Key::Back));
+        assert_eq!(keys(&map, Action::Fire), [Binding::Key(Key::F)]);
+
+        // Confirmed
+        type_key(&mut screen, &mut map, Key::Return);
+        type_key(&mut screen, &mut map, Key::F);
+        assert!(type_key(&mut screen, &mut map, Key::Return));
+        assert!(keys(&map, Action::Fire).is_empty());
+        assert_eq!(keys(&map, Action::Jump), [Binding::Key(Key::F)]);
+    }
+
+    #[test]
+    fn escape_is_claimed_while_capturing() {
+        let mut map = InputMap::new();
+        let mut screen = screen(&map);
+        let claimed = press(Key::Return, |input| {
+            screen.handle_input(input, &mut map).unwrap();
+            !input.ctx.escape_quits()
+        });
+        assert!(claimed);
+        assert!(type_key(&mut screen, &mut map, Key::Escape));
+        assert_eq!(keys(&map, Action::Fire), [Binding::Key(Key::Escape)]);
+    }
+
+    #[test]
+    fn bindings_are_listed_with_a_prompt() {
+        let mut map = InputMap::new();
+        map.bind(Binding::Key(Key::F), Action::Fire)
+            .bind(Binding::Scan(57), Action::Fire);
+        let screen = screen(&map);
+        let mut image = blank(40, 4);
+        screen.draw(&mut image, &Theme::default());
+        let rows = rows(&image);
+        assert!(rows[1].starts_with("Fire   F, #57"));
+        assert!(rows[2].starts_with("Jump   "));
+        assert!(rows[3].starts_with("Enter: rebind"));
+    }
+
+    #[test]
+    fn changes_are_saved_and_loaded() {
+        let dir = std::env::temp_dir().join(format!("mage-keybindings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("keys.cfg");
+
+        let mut map = InputMap::new();
+        let mut screen = screen(&map);
+        screen.with_config(&path);
+        type_key(&mut screen, &mut map, Key::Return);
+        type_key(&mut screen, &mut map, Key::G);
+
+        let mut loaded = InputMap::new();
+        let mut other = self::screen(&loaded);
+        other.with_config(&path);
+        let result = other.load(&mut loaded);
+        let _ = fs::remove_dir_all(&dir);
+        result.unwrap();
+        assert_eq!(keys(&loaded, Action::Fire), [Binding::Key(Key::G)]);
+    }
+}
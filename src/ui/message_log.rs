@@ -0,0 +1,127 @@
+//
+// Message log
+//
+// The scrolling list of messages shown under the map ("You hit the orc.").
+// The newest message is at the bottom and long messages wrap onto several
+// lines.  Older messages are forgotten once the log is full.
+//
+
+use crate::{take_log_messages, wrap_text, Char, Colour, Image, Point, Rect, Theme};
+use log::Level;
+use std::collections::VecDeque;
+
+pub struct MessageLog {
+    messages: VecDeque<(String, Option<u32>)>,
+    capacity: usize,
+}
+
+impl MessageLog {
+    pub fn new(capacity: usize) -> Self {
+        MessageLog {
+            messages: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    // Adds a message in the theme's ink colour.
+    pub fn add(&mut self, text: &str) {
+        self.push(text, None);
+    }
+
+    pub fn add_coloured(&mut self, text: &str, ink: u32) {
+        self.push(text, Some(ink));
+    }
+
+    fn push(&mut self, text: &str, ink: Option<u32>) {
+        if self.messages.len() == self.capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((String::from(text), ink));
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    // Oldest first
+    pub fn messages(&self) -> impl Iterator<Item = &str> {
+        self.messages.iter().map(|(text, _)| text.as_str())
+    }
+
+    // Adds the warnings and errors logged since the last call, when the
+    // engine's logger is installed (see init_logging()).
+    pub fn mirror_log(&mut self) {
+        for (level, text) in take_log_messages() {
+            let ink = match level {
+                Level::Error => Colour::Red,
+                _ => Colour::Yellow,
+            };
+            self.add_coloured(&text, ink.into());
+        }
+    }
+
+    // Draws as many of the newest messages as fit, filling the rect from the
+    // bottom up.
+    pub fn draw(&self, image: &mut Image, rect: Rect, theme: &Theme) {
+        image.draw_rect_filled(
+            Point::new(rect.x, rect.y),
+            rect.width,
+            rect.height,
+            Char::new(b' ', theme.ink, theme.paper),
+        );
+        let mut y = rect.y + rect.height as i32;
+        for (text, ink) in self.messages.iter().rev() {
+            let lines = wrap_text(text, rect.width as usize);
+            for line in lines.iter().rev() {
+                y -= 1;
+                if y < rect.y {
+                    return;
+                }
+                let ink = ink.unwrap_or(theme.ink);
+                image.draw_string(Point::new(rect.x, y), line, ink, theme.paper);
+            }
+        }
+    }
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+
+    #[test]
+    fn old_messages_are_forgotten() {
+        let mut log = MessageLog::new(2);
+        log.add("one");
+        log.add("two");
+        log.add_coloured("three", 5);
+        assert_eq!(log.messages().collect::<Vec<_>>(), ["two", "three"]);
+        log.clear();
+        assert_eq!(log.messages().count(), 0);
+    }
+
+    #[test]
+    fn the_newest_message_is_at_the_bottom() {
+        let mut log = MessageLog::default();
+        log.add("You hit the orc.");
+        log.add_coloured("The orc dies.", 5);
+        log.add("ok");
+
+        let theme = Theme::default();
+        let mut image = blank(10, 4);
+        log.draw(&mut image, Rect::new(0, 0, 10, 4), &theme);
+        // Long messages wrap and the oldest line is cut off at the top
+        assert_eq!(
+            rows(&image),
+            ["the orc.  ", "The orc   ", "dies.     ", "ok        "]
+        );
+        assert_eq!(image.fore_image[10], 5);
+        assert_eq!(image.fore_image[30], theme.ink);
+    }
+}
@@ -0,0 +1,170 @@
+//
+// Minimap
+//
+// Shrinks a large map image down to a small overview, colouring each cell with
+// the most common colour in the part of the map it covers.  The player's
+// position and the area currently on screen can be marked on top.
+//
+
+use crate::{Char, Image, Point, Rect};
+use std::collections::HashMap;
+
+pub struct Minimap {
+    width: u32,
+    height: u32,
+    marker: Option<(Point, Char)>,
+    viewport: Option<(Rect, Char)>,
+}
+
+impl Minimap {
+    pub fn new(width: u32, height: u32) -> Self {
+        Minimap {
+            width,
+            height,
+            marker: None,
+            viewport: None,
+        }
+    }
+
+    // Marks a position on the map, given in map cells.
+    pub fn with_marker(&mut self, p: Point, ch: Char) -> &mut Self {
+        self.marker = Some((p, ch));
+        self
+    }
+
+    // Outlines an area of the map, given in map cells.
+    pub fn with_viewport(&mut self, rect: Rect, ch: Char) -> &mut Self {
+        self.viewport = Some((rect, ch));
+        self
+    }
+
+    // Converts a map position into a minimap position.
+    pub fn to_minimap(&self, map: &Image, p: Point) -> Point {
+        let scale = |v: i32, from: u32, to: u32| {
+            if from == 0 {
+                0
+            } else {
+                (v as i64 * to as i64).div_euclid(from as i64) as i32
+            }
+        };
+        Point::new(
+            scale(p.x, map.width, self.width),
+            scale(p.y, map.height, self.height),
+        )
+    }
+
+    // A map cell's colour is its paper colour if it is blank, otherwise its ink.
+    fn cell_colour(map: &Image, i: usize) -> u32 {
+        match map.text_image[i] & 0xffff {
+            0 | 32 => map.back_image[i],
+            _ => map.fore_image[i],
+        }
+    }
+
+    pub fn render(&self, map: &Image) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        let mut counts = HashMap::new();
+
+        for y in 0..self.height {
+            let y0 = y * map.height / self.height;
+            let y1 = ((y + 1) * map.height / self.height).max(y0 + 1);
+            for x in 0..self.width {
+                let x0 = x * map.width / self.width;
+                let x1 = ((x + 1) * map.width / self.width).max(x0 + 1);
+
+                counts.clear();
+                for my in y0..y1 {
+                    for mx in x0..x1 {
+                        if let Some(i) = map.coords_to_index(mx, my) {
+                            *counts.entry(Self::cell_colour(map, i)).or_insert(0) += 1;
+                        }
+                    }
+                }
+                if let Some((&colour, _)) = counts.iter().max_by_key(|(&c, &n)| (n, c)) {
+                    image.draw_char(
+                        Point::new(x as i32, y as i32),
+                        Char::new(b' ', colour, colour),
+                    );
+                }
+            }
+        }
+
+        if let Some((rect, ch)) = self.viewport {
+            let top_left = self.to_minimap(map, Point::new(rect.x, rect.y));
+            let bottom_right = self.to_minimap(
+                map,
+                Point::new(
+                    rect.x + rect.width as i32 - 1,
+                    rect.y + rect.height as i32 - 1,
+                ),
+            );
+            image.draw_rect(
+                top_left,
+                (bottom_right.x - top_left.x + 1).max(1) as u32,
+                (bottom_right.y - top_left.y + 1).max(1) as u32,
+                ch,
+            );
+        }
+        if let Some((p, ch)) = self.marker {
+            image.draw_char(self.to_minimap(map, p), ch);
+        }
+
+        image
+    }
+
+    pub fn draw(&self, image: &mut Image, p: Point, map: &Image) {
+        let minimap = self.render(map);
+        image.blit(p, minimap.width, minimap.height, &minimap);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::rows;
+
+    // A 4x2 map: walls in ink 1 on the left half, floor in paper 2 on the
+    // right, and one wall among the floor
+    fn map() -> Image {
+        let mut map = Image::new(4, 2);
+        map.clear(0, 2);
+        map.draw_rect_filled(Point::new(0, 0), 2, 2, Char::new(b'#', 1, 0));
+        map.draw_char(Point::new(3, 1), Char::new(b'#', 1, 0));
+        map
+    }
+
+    #[test]
+    fn cells_take_the_most_common_colour() {
+        let minimap = Minimap::new(2, 1).render(&map());
+        assert_eq!(minimap.back_image, [1, 2]);
+
+        // Growing the map repeats its cells
+        let minimap = Minimap::new(8, 4).render(&map());
+        assert_eq!(minimap.back_image[..8], [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(minimap.back_image[31], 1);
+    }
+
+    #[test]
+    fn markers_are_scaled_onto_the_minimap() {
+        let mut minimap = Minimap::new(2, 1);
+        assert_eq!(
+            minimap.to_minimap(&map(), Point::new(3, 1)),
+            Point::new(1, 0)
+        );
+        assert_eq!(
+            minimap.to_minimap(&map(), Point::new(-1, 0)),
+            Point::new(-1, 0)
+        );
+
+        minimap.with_marker(Point::new(2, 0), Char::new(b'@', 3, 3));
+        let image = minimap.render(&map());
+        assert_eq!(rows(&image), [" @"]);
+
+        let mut minimap = Minimap::new(4, 4);
+        minimap.with_viewport(Rect::new(0, 0, 2, 2), Char::new(b'+', 3, 3));
+        let mut image = Image::new(5, 4);
+        image.clear(0, 0);
+        minimap.draw(&mut image, Point::new(1, 0), &map());
+        assert_eq!(rows(&image), [" ++  ", " ++  ", " ++  ", "     "]);
+    }
+}
@@ -0,0 +1,260 @@
+//
+// User interface
+//
+// Widgets for drawing menus, panels and other UI elements onto an Image.
+//
+
+mod caret;
+mod context_menu;
+mod debug_overlay;
+mod dialogue_box;
+mod drag_drop;
+mod form;
+mod graph;
+mod hall_of_fame;
+mod inventory_screen;
+mod key_echo;
+mod keybindings;
+mod message_log;
+mod minimap;
+mod notifications;
+mod panel;
+mod quest_log;
+mod scroll;
+mod table;
+mod text_layout;
+mod tooltip;
+
+pub use caret::*;
+pub use context_menu::*;
+pub use debug_overlay::*;
+pub use dialogue_box::*;
+pub use drag_drop::*;
+pub use form::*;
+pub use graph::*;
+pub use inventory_screen::*;
+pub use key_echo::*;
+pub use keybindings::*;
+pub use message_log::*;
+pub use minimap::*;
+pub use notifications::*;
+pub use panel::*;
+pub use quest_log::*;
+pub use scroll::*;
+pub use table::*;
+pub use text_layout::*;
+pub use tooltip::*;
+
+use crate::{char_width, new_colour, text_width, Colour, Image, Point};
+
+// Number of lines moved by each notch of the mouse wheel
+const WHEEL_LINES: f32 = 3.0;
+
+//
+// Theme
+// The colours shared by the widgets.  Highlighted colours are used for the
+// selected or focused item, and title colours for headers.
+//
+
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub ink: u32,
+    pub paper: u32,
+    pub highlight_ink: u32,
+    pub highlight_paper: u32,
+    pub title_ink: u32,
+    pub title_paper: u32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            ink: Colour::White.into(),
+            paper: Colour::Black.into(),
+            highlight_ink: Colour::Black.into(),
+            highlight_paper: Colour::White.into(),
+            title_ink: Colour::Yellow.into(),
+            title_paper: new_colour(32, 32, 32),
+        }
+    }
+}
+
+//
+// Alignment
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Centre,
+    Right,
+}
+
+// Pads or truncates the text to exactly the given width in cells.  A wide
+// character that would only half fit is left out, and its cell padded.
+pub fn align_text(text: &str, width: usize, align: Align) -> String {
+    let mut used = 0;
+    let text = text
+        .chars()
+        .take_while(|&ch| {
+            used += char_width(ch) as usize;
+            used <= width
+        })
+        .collect::<String>();
+    let gap = width - text_width(&text) as usize;
+    let left = match align {
+        Align::Left => 0,
+        Align::Centre => gap / 2,
+        Align::Right => gap,
+    };
+    format!("{}{}{}", " ".repeat(left), text, " ".repeat(gap - left))
+}
+
+//
+// Wrapping
+//
+
+fn chars_width(chars: &[char]) -> usize {
+    chars.iter().map(|&ch| char_width(ch) as usize).sum()
+}
+
+// Breaks text into lines no wider than the width, at spaces where possible.
+// Newlines in the text always start a new line.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    if width == 0 {
+        return lines;
+    }
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split(' ').filter(|word| !word.is_empty()) {
+            let mut word = word.chars().collect::<Vec<_>>();
+            let line_len = text_width(&line) as usize;
+            if line_len > 0 && line_len + 1 + chars_width(&word) > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            // Words longer than the line are split, keeping wide characters
+            // whole
+            while chars_width(&word) > width {
+                let fits = word
+                    .iter()
+                    .scan(0, |total, &ch| {
+                        *total += char_width(ch) as usize;
+                        Some(*total)
+                    })
+                    .take_while(|&total| total <= width)
+                    .count()
+                    .max(1);
+                lines.push(word.drain(..fits).collect());
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+impl Image {
+    // Draws wrapped text and returns the number of lines used.
+    pub fn draw_wrapped(&mut self, p: Point, width: u32, text: &str, ink: u32, paper: u32) -> u32 {
+        let lines = wrap_text(text, width as usize);
+        for (i, line) in lines.iter().enumerate() {
+            self.draw_string(p.offset(0, i as i64), line, ink, paper);
+        }
+        lines.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Context, Key, KeyState, MouseState, SimInput};
+    use std::time::Duration;
+
+    // The characters in the image, a string a row, for checking what widgets
+    // draw
+    pub(super) fn rows(image: &Image) -> Vec<String> {
+        image
+            .text_image
+            .chunks(image.width as usize)
+            .map(|row| row.iter().map(|&code| image.glyph_char(code)).collect())
+            .collect()
+    }
+
+    // A blank image to draw a widget in
+    pub(super) fn blank(width: u32, height: u32) -> Image {
+        let mut image = Image::new(width, height);
+        image.clear(0, 0);
+        image
+    }
+
+    // Runs f with the input of a tick in which the key, if any, was pressed
+    // and the text typed
+    pub(super) fn with_input<F, R>(
+        key: Option<Key>,
+        text: &str,
+        mouse: Option<MouseState>,
+        f: F,
+    ) -> R
+    where
+        F: FnOnce(&mut SimInput) -> R,
+    {
+        let key = KeyState {
+            pressed: key.is_some(),
+            shift: false,
+            ctrl: false,
+            alt: false,
+            vkey: key,
+            scancode: None,
+        };
+        let mut ctx = Context::new();
+        f(&mut SimInput {
+            dt: Duration::from_millis(16),
+            width: 80,
+            height: 25,
+            cell_width: 8,
+            cell_height: 8,
+            pixel_width: 640,
+            pixel_height: 200,
+            scale_factor: 1.0,
+            key: &key,
+            mouse,
+            gestures: &[],
+            text,
+            ctx: &mut ctx,
+        })
+    }
+
+    pub(super) fn press<F, R>(key: Key, f: F) -> R
+    where
+        F: FnOnce(&mut SimInput) -> R,
+    {
+        with_input(Some(key), "", None, f)
+    }
+
+    // The mouse over a cell, clicked or not
+    pub(super) fn mouse_at(x: i32, y: i32, clicked: bool) -> Option<MouseState> {
+        Some(MouseState {
+            on_screen: true,
+            left_pressed: clicked,
+            left_clicked: clicked,
+            grid_x: x,
+            grid_y: y,
+            ..MouseState::default()
+        })
+    }
+
+    #[test]
+    fn aligning_counts_wide_characters_as_two_cells() {
+        assert_eq!(align_text("日本", 6, Align::Left), "日本  ");
+        assert_eq!(align_text("日本", 6, Align::Right), "  日本");
+        assert_eq!(align_text("日本", 5, Align::Centre), "日本 ");
+        // The second character would only half fit
+        assert_eq!(align_text("日本", 3, Align::Left), "日 ");
+        assert_eq!(align_text("abc", 2, Align::Left), "ab");
+        assert_eq!(text_width(&align_text("a日b", 4, Align::Centre)), 4);
+    }
+}
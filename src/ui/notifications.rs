@@ -0,0 +1,228 @@
+//
+// Notifications
+//
+// Short messages posted with Context::notify(), e.g. "Level up!", shown as
+// boxes stacked in a corner of the main window over everything else.  Each
+// disappears after the display time, and the rest close up.  Messages beyond
+// the ones that fit wait their turn, with their time starting once they show.
+//
+// Achievements announce their unlocks here too.
+//
+
+use crate::{measure_text, Colour, Image, NinePatch, Point};
+use std::{collections::VecDeque, time::Duration};
+
+const DEFAULT_DISPLAY_TIME: Duration = Duration::from_secs(4);
+const DEFAULT_MAX_VISIBLE: usize = 5;
+// Boxes are no wider than this, in cells, not counting the border
+const MAX_TEXT_WIDTH: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyStyle {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl NotifyStyle {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NotifyCorner {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+struct Notification {
+    text: String,
+    style: NotifyStyle,
+    shown_for: Duration,
+}
+
+pub struct Notifications {
+    // Oldest first; the first max_visible are showing
+    queue: VecDeque<Notification>,
+    display_time: Duration,
+    max_visible: usize,
+    corner: NotifyCorner,
+    // (ink, paper) for each style
+    colours: [(u32, u32); 4],
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        let paper = Colour::Black.into();
+        Notifications {
+            queue: VecDeque::new(),
+            display_time: DEFAULT_DISPLAY_TIME,
+            max_visible: DEFAULT_MAX_VISIBLE,
+            corner: NotifyCorner::default(),
+            colours: [
+                (Colour::White.into(), paper),
+                (Colour::Green.into(), paper),
+                (Colour::Yellow.into(), paper),
+                (Colour::Red.into(), paper),
+            ],
+        }
+    }
+
+    pub fn set_display_time(&mut self, time: Duration) {
+        self.display_time = time;
+    }
+
+    pub fn set_max_visible(&mut self, count: usize) {
+        self.max_visible = count.max(1);
+    }
+
+    pub fn set_corner(&mut self, corner: NotifyCorner) {
+        self.corner = corner;
+    }
+
+    pub fn set_colours(&mut self, style: NotifyStyle, ink: u32, paper: u32) {
+        self.colours[style.index()] = (ink, paper);
+    }
+
+    // The text can have several lines, and long lines are wrapped.
+    pub fn push(&mut self, text: &str, style: NotifyStyle) {
+        self.queue.push_back(Notification {
+            text: String::from(text),
+            style,
+            shown_for: Duration::ZERO,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub(crate) fn update(&mut self, dt: Duration) {
+        let display_time = self.display_time;
+        for notification in self.queue.iter_mut().take(self.max_visible) {
+            notification.shown_for += dt;
+        }
+        self.queue
+            .retain(|notification| notification.shown_for < display_time);
+    }
+
+    // The oldest is drawn in the corner and the newer ones stack away from
+    // it, as many as fit.
+    pub(crate) fn draw(&self, image: &mut Image) {
+        let max_width = MAX_TEXT_WIDTH.min(image.width.saturating_sub(4)).max(1);
+        let top = matches!(self.corner, NotifyCorner::TopLeft | NotifyCorner::TopRight);
+        let left = matches!(
+            self.corner,
+            NotifyCorner::TopLeft | NotifyCorner::BottomLeft
+        );
+        let mut used = 1;
+
+        for notification in self.queue.iter().take(self.max_visible) {
+            let layout = measure_text(&notification.text, max_width);
+            let width = layout.width() + 2;
+            let height = layout.height() + 2;
+            if used + height > image.height {
+                break;
+            }
+            let x = if left {
+                1
+            } else {
+                image.width as i32 - width as i32 - 1
+            };
+            let y = if top {
+                used as i32
+            } else {
+                (image.height - used - height) as i32
+            };
+            used += height;
+
+            let (ink, paper) = self.colours[notification.style.index()];
+            let p = Point::new(x, y);
+            image.draw_panel(p, width, height, &NinePatch::single(ink, paper));
+            image.draw_layout(p.offset(1, 1), &layout, ink, paper);
+        }
+    }
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+
+    #[test]
+    fn waiting_notifications_start_their_time_when_shown() {
+        let mut notifications = Notifications::new();
+        notifications.set_display_time(Duration::from_secs(1));
+        notifications.set_max_visible(1);
+        notifications.push("one", NotifyStyle::Info);
+        notifications.push("two", NotifyStyle::Info);
+
+        notifications.update(Duration::from_millis(600));
+        notifications.update(Duration::from_millis(600));
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications.queue[0].text, "two");
+        notifications.update(Duration::from_millis(600));
+        assert_eq!(notifications.len(), 1);
+        notifications.update(Duration::from_millis(600));
+        assert!(notifications.is_empty());
+    }
+
+    #[test]
+    fn boxes_stack_away_from_the_corner() {
+        let mut notifications = Notifications::new();
+        notifications.set_colours(NotifyStyle::Success, 5, 6);
+        notifications.push("Hi", NotifyStyle::Success);
+        notifications.push("Level up!", NotifyStyle::Info);
+        notifications.push("No room", NotifyStyle::Info);
+
+        let mut image = blank(12, 8);
+        notifications.draw(&mut image);
+        assert_eq!(
+            rows(&image),
+            [
+                "            ",
+                "       ┌──┐ ",
+                "       │Hi│ ",
+                "       └──┘ ",
+                "    ┌─────┐ ",
+                "    │Level│ ",
+                "    │up!  │ ",
+                "    └─────┘ ",
+            ]
+        );
+        assert_eq!((image.fore_image[20], image.back_image[20]), (5, 6));
+
+        notifications.set_corner(NotifyCorner::BottomLeft);
+        notifications.set_max_visible(1);
+        let mut image = blank(12, 8);
+        notifications.draw(&mut image);
+        assert_eq!(
+            rows(&image)[4..],
+            [
+                " ┌──┐       ",
+                " │Hi│       ",
+                " └──┘       ",
+                "            "
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//
+// Nine-patch panels
+//
+// A panel is described by 9 characters: 4 corners, 4 edges and the centre.
+// The edges and centre are repeated to fill a panel of any size.
+//
+
+use crate::{Char, Image, Point};
+
+#[derive(Debug, Clone, Copy)]
+pub struct NinePatch {
+    // Laid out in rows: top-left, top, top-right, left, centre, right,
+    // bottom-left, bottom, bottom-right.
+    pub chars: [Char; 9],
+}
+
+impl NinePatch {
+    pub fn new(chars: [Char; 9]) -> Self {
+        NinePatch { chars }
+    }
+
+    // Build a nine-patch from 9 character codes that share the same colours.
+    pub fn from_codes(codes: [u8; 9], ink: u32, paper: u32) -> Self {
+        let mut chars = [Char::new(b' ', ink, paper); 9];
+        chars
+            .iter_mut()
+            .zip(codes.iter())
+            .for_each(|(ch, &code)| ch.ch = code);
+        NinePatch { chars }
+    }
+
+    // Single line box using the code page 437 line drawing characters.
+    pub fn single(ink: u32, paper: u32) -> Self {
+        Self::from_codes(
+            [0xda, 0xc4, 0xbf, 0xb3, b' ', 0xb3, 0xc0, 0xc4, 0xd9],
+            ink,
+            paper,
+        )
+    }
+
+    // Double line box using the code page 437 line drawing characters.
+    pub fn double(ink: u32, paper: u32) -> Self {
+        Self::from_codes(
+            [0xc9, 0xcd, 0xbb, 0xba, b' ', 0xba, 0xc8, 0xcd, 0xbc],
+            ink,
+            paper,
+        )
+    }
+
+    pub fn top_left(&self) -> Char {
+        self.chars[0]
+    }
+    pub fn top(&self) -> Char {
+        self.chars[1]
+    }
+    pub fn top_right(&self) -> Char {
+        self.chars[2]
+    }
+    pub fn left(&self) -> Char {
+        self.chars[3]
+    }
+    pub fn centre(&self) -> Char {
+        self.chars[4]
+    }
+    pub fn right(&self) -> Char {
+        self.chars[5]
+    }
+    pub fn bottom_left(&self) -> Char {
+        self.chars[6]
+    }
+    pub fn bottom(&self) -> Char {
+        self.chars[7]
+    }
+    pub fn bottom_right(&self) -> Char {
+        self.chars[8]
+    }
+}
+
+impl Image {
+    // Panels smaller than 2x2 have no room for corners and are filled with the
+    // centre character.
+    pub fn draw_panel(&mut self, p: Point, width: u32, height: u32, patch: &NinePatch) {
+        if width < 2 || height < 2 {
+            self.draw_rect_filled(p, width, height, patch.centre());
+            return;
+        }
+
+        let right = p.offset(width as i64 - 1, 0).x;
+        let bottom = p.offset(0, height as i64 - 1).y;
+
+        // Centre
+        self.draw_rect_filled(p.offset(1, 1), width - 2, height - 2, patch.centre());
+        // Edges
+        self.draw_rect_filled(p.offset(1, 0), width - 2, 1, patch.top());
+        self.draw_rect_filled(
+            Point::new(p.x, bottom).offset(1, 0),
+            width - 2,
+            1,
+            patch.bottom(),
+        );
+        self.draw_rect_filled(p.offset(0, 1), 1, height - 2, patch.left());
+        self.draw_rect_filled(
+            Point::new(right, p.y).offset(0, 1),
+            1,
+            height - 2,
+            patch.right(),
+        );
+        // Corners
+        self.draw_char(p, patch.top_left());
+        self.draw_char(Point::new(right, p.y), patch.top_right());
+        self.draw_char(Point::new(p.x, bottom), patch.bottom_left());
+        self.draw_char(Point::new(right, bottom), patch.bottom_right());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+
+    #[test]
+    fn panels_stretch_their_edges_and_centre() {
+        let patch = NinePatch::from_codes(*b"123456789", 1, 2);
+        let mut image = blank(6, 5);
+        image.draw_panel(Point::new(1, 1), 4, 3, &patch);
+        assert_eq!(
+            rows(&image),
+            ["      ", " 1223 ", " 4556 ", " 7889 ", "      "]
+        );
+        assert_eq!(image.fore_image[7], 1);
+        assert_eq!(image.back_image[7], 2);
+    }
+
+    #[test]
+    fn panels_are_clipped_and_small_ones_filled() {
+        let mut image = blank(3, 3);
+        image.draw_panel(Point::new(-1, 1), 3, 3, &NinePatch::single(0, 0));
+        assert_eq!(rows(&image), ["   ", "─┐ ", " │ "]);
+
+        let mut image = blank(3, 2);
+        image.draw_panel(Point::new(0, 0), 1, 2, &NinePatch::double(0, 0));
+        image.draw_panel(Point::new(2, 0), 2, 2, &NinePatch::double(0, 0));
+        assert_eq!(rows(&image), ["  ╔", "  ╚"]);
+    }
+}
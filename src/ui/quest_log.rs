@@ -0,0 +1,187 @@
+//
+// Quest log screen
+//
+// Lists active quests, then completed and failed ones, with the selected
+// quest's description and objectives shown underneath the list.
+//
+
+use crate::{
+    wrap_text, Align, ColumnWidth, Image, Point, QuestLog, QuestState, Rect, SimInput, Table, Theme,
+};
+
+// The list takes 1/LIST_FRACTION of the rows and the selected quest the rest
+const LIST_FRACTION: u32 = 2;
+
+const DONE_MARK: &str = "[x]";
+const OPEN_MARK: &str = "[ ]";
+
+pub struct QuestLogScreen {
+    rect: Rect,
+    table: Table,
+    ids: Vec<String>,
+    // Description and objective lines for each listed quest
+    details: Vec<Vec<String>>,
+}
+
+impl QuestLogScreen {
+    pub fn new(rect: Rect) -> Self {
+        let mut table = Table::new(Self::list_rect(rect));
+        table
+            .with_column("Quest", ColumnWidth::Fill, Align::Left)
+            .with_column("Status", ColumnWidth::Content, Align::Right);
+        QuestLogScreen {
+            rect,
+            table,
+            ids: Vec::new(),
+            details: Vec::new(),
+        }
+    }
+
+    fn list_rect(rect: Rect) -> Rect {
+        Rect::new(rect.x, rect.y, rect.width, rect.height / LIST_FRACTION)
+    }
+
+    // Updates the list to show the log's current state.
+    pub fn refresh(&mut self, log: &QuestLog) {
+        let selected = self.selected().map(String::from);
+        self.table.clear_rows();
+        self.ids.clear();
+        self.details.clear();
+
+        let states = [
+            (QuestState::Active, "Active"),
+            (QuestState::Completed, "Done"),
+            (QuestState::Failed, "Failed"),
+        ];
+        for (state, label) in states.iter() {
+            for id in log.with_state(*state) {
+                let quest = match log.definition(id) {
+                    Some(quest) => quest,
+                    None => continue,
+                };
+                self.table.add_row(&[&quest.title, label]);
+                self.ids.push(String::from(id));
+
+                let width = self.rect.width as usize;
+                let mut lines = wrap_text(&quest.description, width);
+                lines.push(String::new());
+                for (objective, (progress, target)) in
+                    quest.objectives.iter().zip(log.objectives(id))
+                {
+                    let mark = if progress >= target {
+                        DONE_MARK
+                    } else {
+                        OPEN_MARK
+                    };
+                    let count = if target > 1 {
+                        format!(" ({}/{})", progress, target)
+                    } else {
+                        String::new()
+                    };
+                    let optional = if objective.optional {
+                        " (optional)"
+                    } else {
+                        ""
+                    };
+                    let text = format!("{} {}{}{}", mark, objective.description, count, optional);
+                    lines.extend(wrap_text(&text, width));
+                }
+                self.details.push(lines);
+            }
+        }
+
+        let row = selected
+            .and_then(|id| self.ids.iter().position(|i| *i == id))
+            .or(if self.ids.is_empty() { None } else { Some(0) });
+        self.table.select(row);
+    }
+
+    // The id of the selected quest
+    pub fn selected(&self) -> Option<&str> {
+        self.table
+            .selected()
+            .and_then(|row| self.ids.get(row))
+            .map(|id| id.as_str())
+    }
+
+    pub fn handle_input(&mut self, input: &SimInput) -> bool {
+        self.table.handle_input(input)
+    }
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme) {
+        self.table.draw(image, theme);
+
+        let lines = match self.table.selected().and_then(|row| self.details.get(row)) {
+            Some(lines) => lines,
+            None => return,
+        };
+        let top = self.rect.y + (self.rect.height / LIST_FRACTION) as i32 + 1;
+        let bottom = self.rect.y + self.rect.height as i32;
+        for (line, y) in lines.iter().zip(top..bottom) {
+            image.draw_string(Point::new(self.rect.x, y), line, theme.ink, theme.paper);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, press, rows};
+    use crate::{EventBus, Key, QuestDef};
+
+    fn log() -> QuestLog {
+        let mut events = EventBus::new();
+        let mut rats = QuestDef::new("Rats", "Clear the cellar.");
+        rats.with_objective("Kill rats", 3, false)
+            .with_objective("Find cheese", 1, true);
+        let mut wolves = QuestDef::new("Wolves", "");
+        wolves.with_objective("Run", 1, false);
+        let mut log = QuestLog::new();
+        log.add("wolves", wolves)
+            .add("rats", rats)
+            .add("bats", QuestDef::new("Bats", ""));
+        log.start("wolves", &mut events).unwrap();
+        log.complete_objective("wolves", 0, &mut events).unwrap();
+        log.start("rats", &mut events).unwrap();
+        log.progress("rats", 0, 1, &mut events).unwrap();
+        log.complete_objective("rats", 1, &mut events).unwrap();
+        log
+    }
+
+    #[test]
+    fn active_quests_are_listed_first_with_details() {
+        let mut screen = QuestLogScreen::new(Rect::new(0, 0, 30, 10));
+        screen.refresh(&log());
+        assert_eq!(screen.selected(), Some("rats"));
+        let mut image = blank(30, 10);
+        screen.draw(&mut image, &Theme::default());
+        assert_eq!(
+            rows(&image),
+            [
+                "Quest                   Status",
+                "Rats                    Active",
+                "Wolves                    Done",
+                "                              ",
+                "                              ",
+                "                              ",
+                "Clear the cellar.             ",
+                "                              ",
+                "[ ] Kill rats (1/3)           ",
+                "[x] Find cheese (optional)    ",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_selection_follows_its_quest() {
+        let mut log = log();
+        let mut screen = QuestLogScreen::new(Rect::new(0, 0, 30, 10));
+        screen.refresh(&log);
+        press(Key::Down, |input| screen.handle_input(input));
+        assert_eq!(screen.selected(), Some("wolves"));
+
+        log.fail("rats", &mut EventBus::new()).unwrap();
+        screen.refresh(&log);
+        assert_eq!(screen.selected(), Some("wolves"));
+    }
+}
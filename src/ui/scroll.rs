@@ -0,0 +1,324 @@
+//
+// Scrolling
+//
+// Scrollbars, and a ScrollView that shows part of a larger area of content
+// drawn by the game.
+//
+
+use super::WHEEL_LINES;
+use crate::{Char, Image, Key, Point, Rect, SimInput, Theme};
+
+// Code page 437 shading for the track, and a solid block for the thumb
+const TRACK_CHAR: u8 = 0xb0;
+const THUMB_CHAR: u8 = 0xdb;
+
+//
+// Scrollbar
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
+}
+
+pub struct Scrollbar {
+    pub position: Point,
+    pub length: u32,
+    pub orientation: Orientation,
+}
+
+impl Scrollbar {
+    pub fn new(position: Point, length: u32, orientation: Orientation) -> Self {
+        Scrollbar {
+            position,
+            length,
+            orientation,
+        }
+    }
+
+    // Returns the start and length of the thumb within the bar for content of
+    // the given size, of which `view` cells are visible starting at `offset`.
+    pub fn thumb(&self, content: u32, view: u32, offset: u32) -> (u32, u32) {
+        if content <= view || self.length == 0 {
+            return (0, self.length);
+        }
+        let length = ((self.length as u64 * view as u64) / content as u64).max(1) as u32;
+        let travel = self.length - length;
+        let max_offset = content - view;
+        let start = (travel as u64 * offset.min(max_offset) as u64 / max_offset as u64) as u32;
+        (start, length)
+    }
+
+    pub fn draw(&self, image: &mut Image, content: u32, view: u32, offset: u32, theme: &Theme) {
+        let (start, length) = self.thumb(content, view, offset);
+        let track = Char::new(TRACK_CHAR, theme.ink, theme.paper);
+        let thumb = Char::new(THUMB_CHAR, theme.ink, theme.paper);
+        let p = self.position;
+        match self.orientation {
+            Orientation::Vertical => {
+                image.draw_rect_filled(p, 1, self.length, track);
+                image.draw_rect_filled(Point::new(p.x, p.y + start as i32), 1, length, thumb);
+            }
+            Orientation::Horizontal => {
+                image.draw_rect_filled(p, self.length, 1, track);
+                image.draw_rect_filled(Point::new(p.x + start as i32, p.y), length, 1, thumb);
+            }
+        }
+    }
+}
+
+//
+// ScrollView
+// Shows a window onto content of a known size.  Scrollbars are drawn along the
+// right and bottom edges when the content doesn't fit, and the offset is always
+// kept within the content.
+//
+
+pub struct ScrollView {
+    rect: Rect,
+    content_width: u32,
+    content_height: u32,
+    offset: Point,
+}
+
+impl ScrollView {
+    pub fn new(rect: Rect, content_width: u32, content_height: u32) -> Self {
+        ScrollView {
+            rect,
+            content_width,
+            content_height,
+            offset: Point::new(0, 0),
+        }
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn set_rect(&mut self, rect: Rect) {
+        self.rect = rect;
+        self.scroll_to(self.offset);
+    }
+
+    pub fn set_content_size(&mut self, width: u32, height: u32) {
+        self.content_width = width;
+        self.content_height = height;
+        self.scroll_to(self.offset);
+    }
+
+    fn has_vertical_bar(&self) -> bool {
+        self.content_height > self.rect.height
+            || (self.content_width > self.rect.width
+                && self.content_height > self.rect.height.saturating_sub(1))
+    }
+
+    fn has_horizontal_bar(&self) -> bool {
+        self.content_width > self.rect.width
+            || (self.content_height > self.rect.height
+                && self.content_width > self.rect.width.saturating_sub(1))
+    }
+
+    // The size of the area available for content once the scrollbars are
+    // taken into account.
+    pub fn view_size(&self) -> (u32, u32) {
+        let bar = |shown: bool| if shown { 1 } else { 0 };
+        (
+            self.rect.width.saturating_sub(bar(self.has_vertical_bar())),
+            self.rect
+                .height
+                .saturating_sub(bar(self.has_horizontal_bar())),
+        )
+    }
+
+    pub fn offset(&self) -> Point {
+        self.offset
+    }
+
+    pub fn scroll_to(&mut self, offset: Point) {
+        let (view_width, view_height) = self.view_size();
+        let max_x = self.content_width.saturating_sub(view_width) as i32;
+        let max_y = self.content_height.saturating_sub(view_height) as i32;
+        self.offset = Point::new(offset.x.clamp(0, max_x), offset.y.clamp(0, max_y));
+    }
+
+    pub fn scroll_by(&mut self, dx: i32, dy: i32) {
+        self.scroll_to(Point::new(self.offset.x + dx, self.offset.y + dy));
+    }
+
+    // Scrolls just enough to bring the given content cell into view.
+    pub fn scroll_into_view(&mut self, p: Point) {
+        let (view_width, view_height) = self.view_size();
+        let (view_width, view_height) = (view_width as i32, view_height as i32);
+        let mut offset = self.offset;
+        if p.x < offset.x {
+            offset.x = p.x;
+        } else if p.x >= offset.x + view_width {
+            offset.x = p.x - view_width + 1;
+        }
+        if p.y < offset.y {
+            offset.y = p.y;
+        } else if p.y >= offset.y + view_height {
+            offset.y = p.y - view_height + 1;
+        }
+        self.scroll_to(offset);
+    }
+
+    // Scrolls with the mouse wheel while the mouse is over the view, and with
+    // PageUp and PageDown.  Returns true if the view scrolled.
+    pub fn handle_input(&mut self, input: &SimInput) -> bool {
+        let old = self.offset;
+        let (_, view_height) = self.view_size();
+        let page = view_height.max(1) as i32;
+
+        if input.key.key_pressed(Key::PageUp) {
+            self.scroll_by(0, -page);
+        } else if input.key.key_pressed(Key::PageDown) {
+            self.scroll_by(0, page);
+        }
+
+        if let (Some(mouse), Some(p)) = (input.mouse, input.mouse_cell()) {
+            if self.rect.contains(p) {
+                let dx = -(mouse.wheel_x * WHEEL_LINES).round() as i32;
+                let dy = -(mouse.wheel_y * WHEEL_LINES).round() as i32;
+                self.scroll_by(dx, dy);
+            }
+        }
+
+        self.offset != old
+    }
+
+    // The closure draws the content onto an image the size of the view, with
+    // the content's top-left corner at the given point (the negated offset).
+    pub fn draw<F>(&self, image: &mut Image, theme: &Theme, draw_content: F)
+    where
+        F: FnOnce(&mut Image, Point),
+    {
+        let (view_width, view_height) = self.view_size();
+        let mut view = Image::new(view_width, view_height);
+        view.set_glyphs(image.glyphs());
+        view.clear(theme.ink, theme.paper);
+        draw_content(&mut view, Point::new(-self.offset.x, -self.offset.y));
+        image.blit(
+            Point::new(self.rect.x, self.rect.y),
+            view_width,
+            view_height,
+            &view,
+        );
+
+        if self.has_vertical_bar() {
+            Scrollbar::new(
+                Point::new(self.rect.x + view_width as i32, self.rect.y),
+                view_height,
+                Orientation::Vertical,
+            )
+            .draw(
+                image,
+                self.content_height,
+                view_height,
+                self.offset.y as u32,
+                theme,
+            );
+        }
+        if self.has_horizontal_bar() {
+            Scrollbar::new(
+                Point::new(self.rect.x, self.rect.y + view_height as i32),
+                view_width,
+                Orientation::Horizontal,
+            )
+            .draw(
+                image,
+                self.content_width,
+                view_width,
+                self.offset.x as u32,
+                theme,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, mouse_at, press, rows, with_input};
+
+    #[test]
+    fn thumbs_show_the_part_in_view() {
+        let bar = Scrollbar::new(Point::new(0, 0), 10, Orientation::Vertical);
+        assert_eq!(bar.thumb(5, 10, 0), (0, 10));
+        assert_eq!(bar.thumb(40, 10, 0), (0, 2));
+        assert_eq!(bar.thumb(40, 10, 15), (4, 2));
+        assert_eq!(bar.thumb(40, 10, 30), (8, 2));
+        assert_eq!(bar.thumb(40, 10, 100), (8, 2));
+        // Huge content still has a thumb
+        assert_eq!(bar.thumb(u32::MAX, 1, u32::MAX), (9, 1));
+
+        let mut image = blank(4, 1);
+        Scrollbar::new(Point::new(0, 0), 4, Orientation::Horizontal).draw(
+            &mut image,
+            8,
+            4,
+            4,
+            &Theme::default(),
+        );
+        assert_eq!(rows(&image), ["░░██"]);
+    }
+
+    #[test]
+    fn bars_are_shown_when_the_content_doesnt_fit() {
+        let mut view = ScrollView::new(Rect::new(0, 0, 10, 5), 10, 5);
+        assert_eq!(view.view_size(), (10, 5));
+        // Too tall, and the vertical bar makes it too wide as well
+        view.set_content_size(10, 6);
+        assert_eq!(view.view_size(), (9, 4));
+        view.set_content_size(10, 4);
+        assert_eq!(view.view_size(), (10, 5));
+        view.set_content_size(11, 4);
+        assert_eq!(view.view_size(), (10, 4));
+    }
+
+    #[test]
+    fn the_offset_stays_within_the_content() {
+        let mut view = ScrollView::new(Rect::new(0, 0, 10, 5), 30, 20);
+        view.scroll_by(-5, 100);
+        assert_eq!(view.offset(), Point::new(0, 16));
+        view.scroll_into_view(Point::new(12, 2));
+        assert_eq!(view.offset(), Point::new(4, 2));
+        view.scroll_into_view(Point::new(5, 3));
+        assert_eq!(view.offset(), Point::new(4, 2));
+        view.set_content_size(5, 5);
+        assert_eq!(view.offset(), Point::new(0, 0));
+    }
+
+    #[test]
+    fn keys_and_the_wheel_scroll() {
+        let mut view = ScrollView::new(Rect::new(2, 2, 10, 5), 9, 30);
+        assert!(press(Key::PageDown, |input| view.handle_input(input)));
+        assert_eq!(view.offset(), Point::new(0, 5));
+
+        let mut wheel = mouse_at(3, 3, false);
+        wheel.as_mut().unwrap().wheel_y = 1.0;
+        assert!(with_input(None, "", wheel, |input| view.handle_input(input)));
+        assert_eq!(view.offset(), Point::new(0, 2));
+        // Not over the view
+        let mut wheel = mouse_at(0, 0, false);
+        wheel.as_mut().unwrap().wheel_y = -1.0;
+        assert!(!with_input(None, "", wheel, |input| view.handle_input(input)));
+    }
+
+    #[test]
+    fn views_draw_the_content_from_the_offset() {
+        let mut view = ScrollView::new(Rect::new(0, 0, 4, 3), 3, 6);
+        view.scroll_to(Point::new(0, 2));
+        let mut image = blank(4, 3);
+        view.draw(&mut image, &Theme::default(), |image, p| {
+            for (y, line) in ["aaaa", "bbbb", "cccc", "dddd", "eeee", "ffff"]
+                .iter()
+                .enumerate()
+            {
+                image.draw_string(p.offset(0, y as i64), line, 0, 0);
+            }
+        });
+        assert_eq!(rows(&image), ["ccc░", "ddd█", "eee░"]);
+    }
+}
@@ -0,0 +1,389 @@
+//
+// Table
+//
+// Rows of text laid out in columns with an optional header, a selected row and
+// scrolling.  Suitable for inventories, high-score tables and stat screens.
+//
+
+use super::WHEEL_LINES;
+use crate::{align_text, text_width, Align, Image, Key, Point, Rect, SimInput, Theme};
+
+//
+// Columns
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnWidth {
+    // Always this many cells wide
+    Fixed(u32),
+    // As wide as the widest cell or title
+    Content,
+    // Shares the space left over by the other columns
+    Fill,
+}
+
+pub struct Column {
+    pub title: String,
+    pub width: ColumnWidth,
+    pub align: Align,
+}
+
+//
+// Table
+// Columns are separated by a single space.  The selection and scroll position
+// are kept within the rows, and scrolling follows the selection.
+//
+
+pub struct Table {
+    rect: Rect,
+    columns: Vec<Column>,
+    rows: Vec<Vec<String>>,
+    show_header: bool,
+    selected: Option<usize>,
+    scroll: usize,
+}
+
+impl Table {
+    pub fn new(rect: Rect) -> Self {
+        Table {
+            rect,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            show_header: true,
+            selected: None,
+            scroll: 0,
+        }
+    }
+
+    pub fn with_column(&mut self, title: &str, width: ColumnWidth, align: Align) -> &mut Self {
+        self.columns.push(Column {
+            title: String::from(title),
+            width,
+            align,
+        });
+        self
+    }
+
+    pub fn with_header(&mut self, show_header: bool) -> &mut Self {
+        self.show_header = show_header;
+        self
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn set_rect(&mut self, rect: Rect) {
+        self.rect = rect;
+        self.select(self.selected);
+    }
+
+    //
+    // Rows
+    //
+
+    pub fn add_row(&mut self, cells: &[&str]) {
+        self.rows
+            .push(cells.iter().map(|&cell| String::from(cell)).collect());
+    }
+
+    pub fn clear_rows(&mut self) {
+        self.rows.clear();
+        self.selected = None;
+        self.scroll = 0;
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    // Number of rows that fit below the header
+    pub fn page_size(&self) -> usize {
+        let header = if self.show_header { 1 } else { 0 };
+        (self.rect.height as usize).saturating_sub(header)
+    }
+
+    //
+    // Selection and scrolling
+    //
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn select(&mut self, row: Option<usize>) {
+        self.selected = row
+            .filter(|_| !self.rows.is_empty())
+            .map(|row| row.min(self.rows.len() - 1));
+        if let Some(row) = self.selected {
+            let page = self.page_size().max(1);
+            if row < self.scroll {
+                self.scroll = row;
+            } else if row >= self.scroll + page {
+                self.scroll = row + 1 - page;
+            }
+        }
+        self.scroll_to(self.scroll);
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    pub fn scroll_to(&mut self, row: usize) {
+        self.scroll = row.min(self.rows.len().saturating_sub(self.page_size()));
+    }
+
+    // Moves the selection with the cursor keys, PageUp/PageDown and Home/End,
+    // selects rows clicked with the mouse and scrolls with the mouse wheel.
+    // Returns true if the selection changed.
+    pub fn handle_input(&mut self, input: &SimInput) -> bool {
+        let old = self.selected;
+        let last = self.rows.len().saturating_sub(1);
+        let page = self.page_size().max(1);
+        let current = self.selected;
+        let key = input.key;
+
+        if key.key_pressed(Key::Up) {
+            self.select(Some(current.map_or(last, |row| row.saturating_sub(1))));
+        } else if key.key_pressed(Key::Down) {
+            self.select(Some(current.map_or(0, |row| row + 1)));
+        } else if key.key_pressed(Key::PageUp) {
+            self.select(Some(current.unwrap_or(0).saturating_sub(page)));
+        } else if key.key_pressed(Key::PageDown) {
+            self.select(Some(current.unwrap_or(0) + page));
+        } else if key.key_pressed(Key::Home) {
+            self.select(Some(0));
+        } else if key.key_pressed(Key::End) {
+            self.select(Some(last));
+        } else if input.mouse.is_some_and(|mouse| mouse.left_clicked) {
+            if let Some(row) = input.mouse_cell().and_then(|p| self.row_at(p)) {
+                self.select(Some(row));
+            }
+        }
+
+        // The wheel scrolls without changing the selection
+        if let (Some(mouse), Some(p)) = (input.mouse, input.mouse_cell()) {
+            if self.rect.contains(p) {
+                let lines = (mouse.wheel_y * WHEEL_LINES).round() as i64;
+                self.scroll_to((self.scroll as i64 - lines).max(0) as usize);
+            }
+        }
+
+        self.selected != old
+    }
+
+    // Returns the row drawn at the given cell, if any.
+    pub fn row_at(&self, p: Point) -> Option<usize> {
+        let header = if self.show_header { 1 } else { 0 };
+        if !self.rect.contains(p) || p.y < self.rect.y + header {
+            return None;
+        }
+        let row = self.scroll + (p.y - self.rect.y - header) as usize;
+        if row < self.rows.len() {
+            Some(row)
+        } else {
+            None
+        }
+    }
+
+    //
+    // Layout and drawing
+    //
+
+    // Works out the width of each column to fit the table's rectangle.
+    pub fn column_widths(&self) -> Vec<u32> {
+        let content = |i: usize| {
+            let title = text_width(&self.columns[i].title);
+            self.rows
+                .iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| text_width(cell))
+                .fold(title, u32::max)
+        };
+
+        let mut widths = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| match column.width {
+                ColumnWidth::Fixed(width) => width,
+                ColumnWidth::Content => content(i),
+                ColumnWidth::Fill => 0,
+            })
+            .collect::<Vec<_>>();
+
+        let separators = self.columns.len().saturating_sub(1) as u32;
+        let used = widths.iter().sum::<u32>() + separators;
+        let fills = self
+            .columns
+            .iter()
+            .filter(|column| column.width == ColumnWidth::Fill)
+            .count() as u32;
+        if fills > 0 {
+            let spare = self.rect.width.saturating_sub(used);
+            let mut extra = spare % fills;
+            for (width, column) in widths.iter_mut().zip(&self.columns) {
+                if column.width == ColumnWidth::Fill {
+                    *width = spare / fills;
+                    if extra > 0 {
+                        *width += 1;
+                        extra -= 1;
+                    }
+                }
+            }
+        }
+
+        widths
+    }
+
+    fn format_row<'a>(&self, widths: &[u32], cells: impl Iterator<Item = &'a str>) -> String {
+        let line = self
+            .columns
+            .iter()
+            .zip(widths)
+            .zip(cells.chain(std::iter::repeat("")))
+            .map(|((column, &width), cell)| align_text(cell, width as usize, column.align))
+            .collect::<Vec<_>>()
+            .join(" ");
+        align_text(&line, self.rect.width as usize, Align::Left)
+    }
+
+    pub fn draw(&self, image: &mut Image, theme: &Theme) {
+        let widths = self.column_widths();
+        let mut y = self.rect.y;
+
+        if self.show_header {
+            let titles = self.columns.iter().map(|column| column.title.as_str());
+            let line = self.format_row(&widths, titles);
+            image.draw_string(
+                Point::new(self.rect.x, y),
+                &line,
+                theme.title_ink,
+                theme.title_paper,
+            );
+            y += 1;
+        }
+
+        for i in self.scroll..self.scroll + self.page_size() {
+            let line = match self.rows.get(i) {
+                Some(row) => self.format_row(&widths, row.iter().map(String::as_str)),
+                None => " ".repeat(self.rect.width as usize),
+            };
+            let (ink, paper) = if self.selected == Some(i) {
+                (theme.highlight_ink, theme.highlight_paper)
+            } else {
+                (theme.ink, theme.paper)
+            };
+            image.draw_string(Point::new(self.rect.x, y), &line, ink, paper);
+            y += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, mouse_at, press, rows, with_input};
+
+    // Name, a filler and a right-aligned count, with rows "a" to "j"
+    fn table(height: u32) -> Table {
+        let mut table = Table::new(Rect::new(0, 0, 16, height));
+        table
+            .with_column("Name", ColumnWidth::Content, Align::Left)
+            .with_column("", ColumnWidth::Fill, Align::Left)
+            .with_column("N", ColumnWidth::Fixed(3), Align::Right);
+        for (i, name) in ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"]
+            .iter()
+            .enumerate()
+        {
+            table.add_row(&[name, "", &i.to_string()]);
+        }
+        table
+    }
+
+    #[test]
+    fn columns_share_out_the_width() {
+        let mut table = table(4);
+        table.add_row(&["longer name"]);
+        // 11 + 1 + fill + 1 + 3 = 16
+        assert_eq!(table.column_widths(), [11, 0, 3]);
+        table.set_rect(Rect::new(0, 0, 20, 4));
+        assert_eq!(table.column_widths(), [11, 4, 3]);
+
+        // Spare cells go to the first fills
+        let mut fills = Table::new(Rect::new(0, 0, 10, 2));
+        for _ in 0..3 {
+            fills.with_column("", ColumnWidth::Fill, Align::Left);
+        }
+        assert_eq!(fills.column_widths(), [3, 3, 2]);
+    }
+
+    #[test]
+    fn tables_draw_a_page_of_rows() {
+        let mut table = table(4);
+        table.select(Some(1));
+        let mut image = blank(16, 4);
+        let theme = Theme::default();
+        table.draw(&mut image, &theme);
+        assert_eq!(
+            rows(&image),
+            [
+                "Name           N",
+                "a              0",
+                "b              1",
+                "c              2",
+            ]
+        );
+        assert_eq!(image.back_image[32], theme.highlight_paper);
+        assert_eq!(image.back_image[16], theme.paper);
+
+        table.with_header(false).scroll_to(8);
+        assert_eq!(table.scroll(), 6);
+        let mut image = blank(16, 4);
+        table.draw(&mut image, &theme);
+        assert!(rows(&image)[0].starts_with('g'));
+    }
+
+    #[test]
+    fn scrolling_follows_the_selection() {
+        let mut table = table(4);
+        assert_eq!(table.page_size(), 3);
+        assert!(press(Key::Down, |input| table.handle_input(input)));
+        assert_eq!(table.selected(), Some(0));
+        press(Key::End, |input| table.handle_input(input));
+        assert_eq!((table.selected(), table.scroll()), (Some(9), 7));
+        press(Key::PageUp, |input| table.handle_input(input));
+        assert_eq!((table.selected(), table.scroll()), (Some(6), 6));
+        assert!(!press(Key::Down, |input| {
+            table.handle_input(input);
+            table.handle_input(input);
+            table.handle_input(input);
+            table.handle_input(input)
+        }));
+        assert_eq!(table.selected(), Some(9));
+
+        table.select(Some(100));
+        assert_eq!(table.selected(), Some(9));
+        table.clear_rows();
+        table.select(Some(0));
+        assert_eq!((table.selected(), table.scroll()), (None, 0));
+    }
+
+    #[test]
+    fn the_mouse_selects_and_scrolls() {
+        let mut table = table(4);
+        // The header isn't a row
+        assert_eq!(table.row_at(Point::new(3, 0)), None);
+        assert!(with_input(None, "", mouse_at(3, 2, true), |input| table
+            .handle_input(input)));
+        assert_eq!(table.selected(), Some(1));
+
+        let mut wheel = mouse_at(3, 2, false);
+        wheel.as_mut().unwrap().wheel_y = -1.0;
+        with_input(None, "", wheel, |input| table.handle_input(input));
+        assert_eq!((table.selected(), table.scroll()), (Some(1), 3));
+        assert_eq!(table.row_at(Point::new(0, 1)), Some(3));
+        assert_eq!(table.row_at(Point::new(16, 1)), None);
+    }
+}
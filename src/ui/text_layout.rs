@@ -0,0 +1,227 @@
+//
+// Text layout
+//
+// Works out where wrapped text goes without drawing it, so a box can be
+// sized to fit its text, and maps between character indices and cells for
+// the caret of an editable text field.
+//
+// Lines break at spaces where possible, like wrap_text(), but keep the
+// spaces inside a line as typed so the caret lines up with the text.
+//
+
+use crate::{char_width, Image, Point};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextLine {
+    // Character indices into the text, end exclusive.  The spaces at a break
+    // and the newline are not part of any line.
+    pub start: usize,
+    pub end: usize,
+    // In cells
+    pub width: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+    chars: Vec<char>,
+    lines: Vec<TextLine>,
+    width: u32,
+}
+
+// Lays out text in lines no wider than max_width cells, or without wrapping
+// if it is 0.  Newlines always start a new line.
+pub fn measure_text(text: &str, max_width: u32) -> TextLayout {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut lines = Vec::new();
+    let width_of = |range: &[char]| range.iter().map(|&ch| char_width(ch)).sum::<u32>();
+    let mut line = |start: usize, mut end: usize| {
+        while end > start && chars[end - 1] == ' ' {
+            end -= 1;
+        }
+        lines.push(TextLine {
+            start,
+            end,
+            width: width_of(&chars[start..end]),
+        });
+    };
+
+    let mut paragraph_start = 0;
+    loop {
+        let paragraph_end = chars[paragraph_start..]
+            .iter()
+            .position(|&ch| ch == '\n')
+            .map_or(chars.len(), |i| paragraph_start + i);
+
+        let mut i = paragraph_start;
+        loop {
+            while i < paragraph_end && chars[i] == ' ' && i > paragraph_start {
+                i += 1;
+            }
+            let start = i;
+            let mut width = 0;
+            let mut last_space = None;
+            while i < paragraph_end {
+                let ch_width = char_width(chars[i]);
+                if chars[i] == ' ' {
+                    last_space = Some(i);
+                }
+                if max_width > 0 && width + ch_width > max_width {
+                    break;
+                }
+                width += ch_width;
+                i += 1;
+            }
+            if i == paragraph_end {
+                line(start, paragraph_end);
+                break;
+            }
+            // Break at the character that didn't fit if it is a space, else
+            // at the last space, else in the middle of the word
+            let end = match last_space {
+                _ if chars[i] == ' ' => i,
+                Some(space) if space > start => space,
+                _ => i.max(start + 1),
+            };
+            line(start, end);
+            i = end;
+        }
+
+        if paragraph_end == chars.len() {
+            break;
+        }
+        paragraph_start = paragraph_end + 1;
+    }
+
+    let width = lines.iter().map(|line| line.width).max().unwrap_or(0);
+    TextLayout {
+        chars,
+        lines,
+        width,
+    }
+}
+
+impl TextLayout {
+    pub fn lines(&self) -> &[TextLine] {
+        &self.lines
+    }
+
+    pub fn line_text(&self, line: usize) -> String {
+        self.lines
+            .get(line)
+            .map(|line| self.chars[line.start..line.end].iter().collect())
+            .unwrap_or_default()
+    }
+
+    // The width of the widest line, in cells
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.lines.len() as u32
+    }
+
+    // The cell, relative to the top-left of the text, where the caret goes
+    // when it is before the character at index.  An index past the end puts
+    // the caret after the last character.
+    pub fn caret_position(&self, index: usize) -> Point {
+        let index = index.min(self.chars.len());
+        let y = self
+            .lines
+            .iter()
+            .rposition(|line| line.start <= index)
+            .unwrap_or(0);
+        let line = self.lines[y];
+        let x = self.chars[line.start..index.max(line.start)]
+            .iter()
+            .filter(|&&ch| ch != '\n')
+            .map(|&ch| char_width(ch))
+            .sum::<u32>();
+        Point::new(x as i32, y as i32)
+    }
+
+    // The character index for a caret placed at a cell, e.g. where the text
+    // was clicked.  Clicking the right half of a wide character puts the
+    // caret after it.
+    pub fn caret_index(&self, p: Point) -> usize {
+        let y = p.y.clamp(0, self.lines.len() as i32 - 1) as usize;
+        let line = self.lines[y];
+        let mut x = 0;
+        for i in line.start..line.end {
+            let ch_width = char_width(self.chars[i]) as i32;
+            if p.x < x + ch_width {
+                return if p.x > x { i + 1 } else { i };
+            }
+            x += ch_width;
+        }
+        line.end
+    }
+}
+
+impl Image {
+    // Draws text laid out by measure_text().
+    pub fn draw_layout(&mut self, p: Point, layout: &TextLayout, ink: u32, paper: u32) {
+        for y in 0..layout.lines.len() {
+            let text = layout.line_text(y);
+            self.draw_string(p.offset(0, y as i64), &text, ink, paper);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+
+    fn texts(layout: &TextLayout) -> Vec<String> {
+        (0..layout.lines().len())
+            .map(|i| layout.line_text(i))
+            .collect()
+    }
+
+    #[test]
+    fn lines_break_at_spaces_and_newlines() {
+        let layout = measure_text("the quick  brown fox\nhi", 10);
+        assert_eq!(texts(&layout), ["the quick", "brown fox", "hi"]);
+        assert_eq!(
+            layout.lines()[1],
+            TextLine {
+                start: 11,
+                end: 20,
+                width: 9
+            }
+        );
+        assert_eq!((layout.width(), layout.height()), (9, 3));
+
+        assert_eq!(texts(&measure_text("abcdefgh", 3)), ["abc", "def", "gh"]);
+        assert_eq!(texts(&measure_text("no  wrap", 0)), ["no  wrap"]);
+        assert_eq!(texts(&measure_text("", 5)), [""]);
+    }
+
+    #[test]
+    fn carets_map_between_indices_and_cells() {
+        let layout = measure_text("the quick  brown fox\nhi", 10);
+        assert_eq!(layout.caret_position(4), Point::new(4, 0));
+        // The spaces at a break stay at the end of the line above
+        assert_eq!(layout.caret_position(10), Point::new(10, 0));
+        assert_eq!(layout.caret_position(11), Point::new(0, 1));
+        assert_eq!(layout.caret_position(99), Point::new(2, 2));
+        assert_eq!(layout.caret_index(Point::new(0, 2)), 21);
+        assert_eq!(layout.caret_index(Point::new(50, -3)), 9);
+
+        let wide = measure_text("ab \u{6f22}x", 0);
+        assert_eq!(wide.width(), 6);
+        assert_eq!(wide.caret_position(4), Point::new(5, 0));
+        assert_eq!(wide.caret_index(Point::new(3, 0)), 3);
+        assert_eq!(wide.caret_index(Point::new(4, 0)), 4);
+        assert_eq!(wide.caret_index(Point::new(9, 0)), 5);
+    }
+
+    #[test]
+    fn layouts_draw_a_line_per_row() {
+        let mut image = blank(6, 3);
+        let layout = measure_text("one two three", 5);
+        image.draw_layout(Point::new(1, 0), &layout, 1, 0);
+        assert_eq!(rows(&image), [" one  ", " two  ", " three"]);
+    }
+}
@@ -0,0 +1,229 @@
+//
+// Tooltips
+//
+// The game describes what is under each cell, either by registering text for
+// individual cells or with a lookup function.  Once the mouse has rested on a
+// described cell for the hover delay, the engine draws the description in a
+// box next to the cursor.
+//
+
+use crate::{text_width, Image, NinePatch, Point, Theme};
+use std::{collections::HashMap, time::Duration};
+
+const DEFAULT_DELAY: Duration = Duration::from_millis(500);
+
+pub struct Tooltips {
+    descriptions: HashMap<Point, String>,
+    lookup: Option<Box<dyn Fn(Point) -> Option<String>>>,
+    delay: Duration,
+    theme: Theme,
+    hovered: Option<Point>,
+    hover_time: Duration,
+}
+
+impl Tooltips {
+    pub fn new() -> Self {
+        Tooltips {
+            descriptions: HashMap::new(),
+            lookup: None,
+            delay: DEFAULT_DELAY,
+            theme: Theme::default(),
+            hovered: None,
+            hover_time: Duration::ZERO,
+        }
+    }
+
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    //
+    // Descriptions
+    // Registered descriptions take priority over the lookup function.
+    //
+
+    pub fn set(&mut self, p: Point, text: &str) {
+        self.descriptions.insert(p, String::from(text));
+    }
+
+    pub fn remove(&mut self, p: Point) {
+        self.descriptions.remove(&p);
+    }
+
+    pub fn clear(&mut self) {
+        self.descriptions.clear();
+    }
+
+    pub fn set_lookup<F>(&mut self, lookup: F)
+    where
+        F: Fn(Point) -> Option<String> + 'static,
+    {
+        self.lookup = Some(Box::new(lookup));
+    }
+
+    pub fn clear_lookup(&mut self) {
+        self.lookup = None;
+    }
+
+    pub fn description(&self, p: Point) -> Option<String> {
+        self.descriptions
+            .get(&p)
+            .cloned()
+            .or_else(|| self.lookup.as_ref().and_then(|lookup| lookup(p)))
+    }
+
+    //
+    // Hovering
+    //
+
+    // The cell the mouse is resting on, whether or not the tooltip is showing
+    // yet.
+    pub fn hovered(&self) -> Option<Point> {
+        self.hovered
+    }
+
+    // Returns the cell and description of the tooltip being shown, if any.
+    pub fn visible(&self) -> Option<(Point, String)> {
+        let p = self.hovered.filter(|_| self.hover_time >= self.delay)?;
+        self.description(p).map(|text| (p, text))
+    }
+
+    // Called by the engine after each tick with the cell under the mouse.
+    pub(crate) fn update(&mut self, mouse_cell: Option<Point>, dt: Duration) {
+        if mouse_cell == self.hovered {
+            self.hover_time += dt;
+        } else {
+            self.hovered = mouse_cell;
+            self.hover_time = Duration::ZERO;
+        }
+    }
+
+    // The box is placed below and to the right of the cursor, moving to the
+    // other side when it would go off the edge of the image.
+    pub(crate) fn draw(&self, image: &mut Image) {
+        let (p, text) = match self.visible() {
+            Some(tooltip) => tooltip,
+            None => return,
+        };
+
+        let lines = text.lines().collect::<Vec<_>>();
+        let width = lines.iter().map(|line| text_width(line)).max().unwrap_or(0) as i32 + 2;
+        let height = lines.len() as i32 + 2;
+
+        let x = if p.x + 1 + width <= image.width as i32 {
+            p.x + 1
+        } else {
+            (p.x - width).max(0)
+        };
+        let y = if p.y + 1 + height <= image.height as i32 {
+            p.y + 1
+        } else {
+            (p.y - height).max(0)
+        };
+
+        let theme = &self.theme;
+        image.draw_panel(
+            Point::new(x, y),
+            width as u32,
+            height as u32,
+            &NinePatch::single(theme.ink, theme.paper),
+        );
+        for (i, line) in lines.iter().enumerate() {
+            image.draw_string(
+                Point::new(x + 1, y + 1 + i as i32),
+                line,
+                theme.ink,
+                theme.paper,
+            );
+        }
+    }
+}
+
+impl Default for Tooltips {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::tests::{blank, rows};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn registered_descriptions_come_before_the_lookup() {
+        let mut tooltips = Tooltips::new();
+        tooltips.set_lookup(|p| (p.x == 1).then(|| format!("row {}", p.y)));
+        tooltips.set(Point::new(1, 2), "a door");
+        assert_eq!(
+            tooltips.description(Point::new(1, 2)).as_deref(),
+            Some("a door")
+        );
+        assert_eq!(
+            tooltips.description(Point::new(1, 3)).as_deref(),
+            Some("row 3")
+        );
+        assert_eq!(tooltips.description(Point::new(0, 3)), None);
+        tooltips.remove(Point::new(1, 2));
+        assert_eq!(
+            tooltips.description(Point::new(1, 2)).as_deref(),
+            Some("row 2")
+        );
+        tooltips.clear_lookup();
+        assert_eq!(tooltips.description(Point::new(1, 2)), None);
+    }
+
+    #[test]
+    fn tooltips_show_after_the_mouse_rests() {
+        let mut tooltips = Tooltips::new();
+        tooltips.set_delay(ms(100));
+        tooltips.set(Point::new(0, 0), "wall");
+        tooltips.update(Some(Point::new(0, 0)), ms(16));
+        tooltips.update(Some(Point::new(0, 0)), ms(90));
+        assert_eq!(tooltips.visible(), None);
+        tooltips.update(Some(Point::new(0, 0)), ms(10));
+        assert_eq!(
+            tooltips.visible(),
+            Some((Point::new(0, 0), String::from("wall")))
+        );
+
+        // Moving starts the wait again
+        tooltips.update(Some(Point::new(1, 0)), ms(200));
+        assert_eq!(tooltips.hovered(), Some(Point::new(1, 0)));
+        tooltips.update(Some(Point::new(0, 0)), ms(16));
+        assert_eq!(tooltips.visible(), None);
+        tooltips.update(None, ms(200));
+        assert_eq!(tooltips.visible(), None);
+    }
+
+    #[test]
+    fn boxes_move_to_stay_on_screen() {
+        let mut tooltips = Tooltips::new();
+        tooltips.set_delay(Duration::ZERO);
+        tooltips.set(Point::new(0, 0), "ab");
+        tooltips.set(Point::new(5, 4), "é");
+        tooltips.update(Some(Point::new(0, 0)), ms(1));
+        let mut image = blank(6, 5);
+        tooltips.draw(&mut image);
+        assert_eq!(
+            rows(&image),
+            ["      ", " ┌──┐ ", " │ab│ ", " └──┘ ", "      "]
+        );
+
+        tooltips.update(Some(Point::new(5, 4)), ms(1));
+        let mut image = blank(6, 5);
+        tooltips.draw(&mut image);
+        assert_eq!(
+            rows(&image),
+            ["      ", "  ┌─┐ ", "  │é│ ", "  └─┘ ", "      "]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//
+// Weather
+//
+// Rain, snow and fog drawn over an area of the screen, usually the map view of
+// an overworld.  Rain and snow are particles that fall through the area and
+// are blown sideways by the wind; fog is a drifting haze blended over the ink
+// and paper of each cell.
+//
+// The engine keeps a Weather in the Context that it updates and draws over
+// the main window every frame, so a game only has to set it up:
+//
+//      context.weather().set_kind(WeatherKind::Rain).with_area(map_rect);
+//
+// Weather is also an Effect, so more can be created and driven by the game
+// or an Animator, e.g. for a second window.
+//
+
+use crate::{lerp_colour, new_colour, Effect, GameRng, Image, Rect};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeatherKind {
+    Clear,
+    Rain,
+    Snow,
+    Fog,
+}
+
+struct Drop {
+    x: f32,
+    y: f32,
+    // Cells per second
+    speed: f32,
+}
+
+pub struct Weather {
+    kind: WeatherKind,
+    area: Rect,
+    intensity: f32,
+    // Cells per second, positive to the right
+    wind: f32,
+    colour: Option<u32>,
+    drops: Vec<Drop>,
+    elapsed: f32,
+    rng: GameRng,
+}
+
+impl Weather {
+    pub fn new(kind: WeatherKind, area: Rect) -> Self {
+        Weather {
+            kind,
+            area,
+            intensity: 0.5,
+            wind: 0.0,
+            colour: None,
+            drops: Vec::new(),
+            elapsed: 0.0,
+            rng: GameRng::from_time(),
+        }
+    }
+
+    pub fn kind(&self) -> WeatherKind {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: WeatherKind) -> &mut Self {
+        if kind != self.kind {
+            self.kind = kind;
+            self.drops.clear();
+        }
+        self
+    }
+
+    pub fn with_area(&mut self, area: Rect) -> &mut Self {
+        if area != self.area {
+            self.area = area;
+            self.drops.clear();
+        }
+        self
+    }
+
+    // From 0 (nothing) to 1 (a downpour, blizzard or pea-souper)
+    pub fn with_intensity(&mut self, intensity: f32) -> &mut Self {
+        self.intensity = intensity.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_wind(&mut self, wind: f32) -> &mut Self {
+        self.wind = wind;
+        self
+    }
+
+    // Overrides the colour of the rain, snow or fog.
+    pub fn with_colour(&mut self, colour: u32) -> &mut Self {
+        self.colour = Some(colour);
+        self
+    }
+
+    fn colour(&self) -> u32 {
+        self.colour.unwrap_or(match self.kind {
+            WeatherKind::Rain => new_colour(96, 128, 208),
+            WeatherKind::Snow => new_colour(240, 240, 255),
+            _ => new_colour(160, 160, 168),
+        })
+    }
+
+    // How many particles cover the area at full intensity, per cell
+    fn density(&self) -> f32 {
+        match self.kind {
+            WeatherKind::Rain => 0.06,
+            WeatherKind::Snow => 0.08,
+            _ => 0.0,
+        }
+    }
+
+    fn new_drop(&mut self, y: f32) -> Drop {
+        let x = self.rng.unit() as f32 * self.area.width as f32;
+        let speed = match self.kind {
+            WeatherKind::Rain => 20.0 + self.rng.unit() as f32 * 10.0,
+            _ => 2.0 + self.rng.unit() as f32 * 2.0,
+        };
+        Drop { x, y, speed }
+    }
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::new(WeatherKind::Clear, Rect::new(0, 0, 0, 0))
+    }
+}
+
+impl Effect for Weather {
+    fn update(&mut self, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        self.elapsed += dt;
+        let (width, height) = (self.area.width as f32, self.area.height as f32);
+        if width == 0.0 || height == 0.0 {
+            return;
+        }
+
+        // Move the drops, wrapping around the sides and starting again at the
+        // top once they fall out of the bottom.
+        let drift = match self.kind {
+            WeatherKind::Snow => self.wind * 0.5,
+            _ => self.wind,
+        };
+        for i in 0..self.drops.len() {
+            let drop = &mut self.drops[i];
+            drop.y += drop.speed * dt;
+            drop.x = (drop.x + drift * dt).rem_euclid(width);
+            if drop.y >= height {
+                self.drops[i] = self.new_drop(0.0);
+            }
+        }
+
+        // New drops appear anywhere so the area fills as soon as it starts
+        let target = (width * height * self.density() * self.intensity) as usize;
+        self.drops.truncate(target);
+        while self.drops.len() < target {
+            let y = self.rng.unit() as f32 * height;
+            let drop = self.new_drop(y);
+            self.drops.push(drop);
+        }
+    }
+
+    fn draw(&self, image: &mut Image) {
+        let colour = self.colour();
+        match self.kind {
+            WeatherKind::Clear => {}
+            WeatherKind::Rain | WeatherKind::Snow => {
+                for (i, drop) in self.drops.iter().enumerate() {
+                    let ch = if self.kind == WeatherKind::Rain {
+                        if self.wind.abs() < drop.speed * 0.3 {
+                            b'|'
+                        } else if self.wind > 0.0 {
+                            b'\\'
+                        } else {
+                            b'/'
+                        }
+                    } else if i % 3 == 0 {
+                        b'*'
+                    } else {
+                        b'.'
+                    };
+                    let x = self.area.x + drop.x as i32;
+                    let y = self.area.y + drop.y as i32;
+                    if x < 0 || y < 0 {
+                        continue;
+                    }
+                    if let Some(i) = image.coords_to_index(x as u32, y as u32) {
+                        image.fore_image[i] = colour;
+                        image.text_image[i] = ch as u32;
+                    }
+                }
+            }
+            WeatherKind::Fog => {
+                let offset = self.elapsed * self.wind;
+                for y in 0..self.area.height {
+                    for x in 0..self.area.width {
+                        let (sx, sy) = (self.area.x + x as i32, self.area.y + y as i32);
+                        if sx < 0 || sy < 0 {
+                            continue;
+                        }
+                        if let Some(i) = image.coords_to_index(sx as u32, sy as u32) {
+                            let haze = fog_noise(x as f32 - offset, y as f32, self.elapsed);
+                            let t = self.intensity * (0.4 + 0.6 * haze);
+                            image.fore_image[i] = lerp_colour(image.fore_image[i], colour, t);
+                            image.back_image[i] = lerp_colour(image.back_image[i], colour, t);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+//
+// Fog noise
+// Smooth value noise from 0 to 1 made from a hash of the lattice points,
+// slowly changing over time so the fog swirls as well as drifts.
+//
+
+fn fog_noise(x: f32, y: f32, time: f32) -> f32 {
+    // Fog banks are several cells across and wider than they are tall
+    let (x, y) = (x / 8.0, y / 4.0 + time * 0.05);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (smooth(x - x0), smooth(y - y0));
+    let (x0, y0) = (x0 as i32, y0 as i32);
+    let top = lerp(lattice(x0, y0), lattice(x0 + 1, y0), fx);
+    let bottom = lerp(lattice(x0, y0 + 1), lattice(x0 + 1, y0 + 1), fx);
+    lerp(top, bottom, fy)
+}
+
+fn lattice(x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x9e37_79b1) ^ (y as u32).wrapping_mul(0x85eb_ca77);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    (h & 0xffff) as f32 / 65535.0
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drawn(weather: &Weather, width: u32, height: u32) -> Image {
+        let mut image = Image::new(width, height);
+        image.clear(0, 0);
+        weather.draw(&mut image);
+        image
+    }
+
+    #[test]
+    fn the_area_fills_with_drops_for_the_intensity() {
+        let mut weather = Weather::new(WeatherKind::Rain, Rect::new(2, 1, 20, 10));
+        weather.with_intensity(1.0);
+        weather.update(Duration::from_millis(16));
+        assert_eq!(weather.drops.len(), 12);
+        weather.with_intensity(0.5).update(Duration::ZERO);
+        assert_eq!(weather.drops.len(), 6);
+
+        // Drops keep falling inside the area
+        for _ in 0..100 {
+            weather.update(Duration::from_millis(50));
+            assert!(weather
+                .drops
+                .iter()
+                .all(|d| (0.0..20.0).contains(&d.x) && (0.0..10.0).contains(&d.y)));
+        }
+        let image = drawn(&weather, 30, 15);
+        for (i, &ch) in image.text_image.iter().enumerate() {
+            let (x, y) = (i as u32 % 30, i as u32 / 30);
+            let inside = (2..22).contains(&x) && (1..11).contains(&y);
+            assert!(
+                ch == b' ' as u32 || (inside && ch == b'|' as u32),
+                "{} {}",
+                x,
+                y
+            );
+        }
+
+        weather.set_kind(WeatherKind::Snow);
+        assert!(weather.drops.is_empty());
+    }
+
+    #[test]
+    fn rain_slants_with_the_wind() {
+        let mut weather = Weather::new(WeatherKind::Rain, Rect::new(0, 0, 40, 20));
+        weather.with_intensity(1.0).with_wind(-50.0);
+        weather.update(Duration::ZERO);
+        let image = drawn(&weather, 40, 20);
+        assert!(image.text_image.contains(&(b'/' as u32)));
+        assert!(!image.text_image.contains(&(b'|' as u32)));
+    }
+
+    #[test]
+    fn fog_blends_every_cell_towards_its_colour() {
+        let mut weather = Weather::new(WeatherKind::Fog, Rect::new(0, 0, 4, 2));
+        weather
+            .with_intensity(1.0)
+            .with_colour(new_colour(200, 200, 200));
+        weather.update(Duration::from_secs(1));
+        assert!(weather.drops.is_empty());
+        let image = drawn(&weather, 5, 2);
+        for (i, &paper) in image.back_image.iter().enumerate() {
+            let grey = paper & 0xff;
+            if i % 5 == 4 {
+                assert_eq!(paper, 0);
+            } else {
+                assert!((80..=200).contains(&grey), "{}", grey);
+            }
+        }
+    }
+
+    #[test]
+    fn fog_noise_is_smooth() {
+        for i in 0..100 {
+            let x = i as f32 * 0.37;
+            let a = fog_noise(x, 1.0, 0.0);
+            assert!((0.0..=1.0).contains(&a));
+            assert!((a - fog_noise(x + 0.01, 1.0, 0.0)).abs() < 0.01);
+        }
+        assert_eq!(fog_noise(8.0, 4.0, 0.0), lattice(1, 1));
+    }
+}
@@ -0,0 +1,385 @@
+//
+// Window management
+//
+// Monitor selection, window placement and fullscreen toggling for the game
+// window, and the registry of secondary windows opened by the game.
+//
+
+use crate::{
+    present_input,
+    render::{GraphicsOptions, Renderer},
+    Context, Game, MouseState, RogueFontData,
+};
+use futures::executor::block_on;
+#[cfg(feature = "window-persistence")]
+use std::{fs, io, path::PathBuf};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::WindowEvent,
+    event_loop::EventLoopWindowTarget,
+    monitor::{MonitorHandle, VideoMode},
+    window::{Fullscreen, Window, WindowBuilder, WindowId},
+};
+
+//
+// FullscreenMode
+// Exclusive fullscreen changes the video mode of the monitor, whereas
+// borderless fullscreen covers the monitor with a window at its current mode.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Borderless,
+    Exclusive,
+}
+
+impl Default for FullscreenMode {
+    fn default() -> Self {
+        if cfg!(any(target_os = "macos", unix)) {
+            FullscreenMode::Borderless
+        } else {
+            FullscreenMode::Exclusive
+        }
+    }
+}
+
+//
+// WindowPosition
+// Where the window initially appears.  Positions are relative to the top-left
+// of the target monitor, or the primary monitor if none was chosen.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WindowPosition {
+    #[default]
+    Default,
+    At(i32, i32),
+    Centred,
+}
+
+impl WindowPosition {
+    pub(crate) fn resolve(
+        self,
+        monitor: Option<&MonitorHandle>,
+        primary: Option<&MonitorHandle>,
+        size: PhysicalSize<u32>,
+    ) -> Option<PhysicalPosition<i32>> {
+        match self {
+            WindowPosition::Default => monitor.map(|monitor| monitor.position()),
+            WindowPosition::At(x, y) => {
+                let origin = monitor
+                    .or(primary)
+                    .map_or(PhysicalPosition::new(0, 0), |m| m.position());
+                Some(PhysicalPosition::new(origin.x + x, origin.y + y))
+            }
+            WindowPosition::Centred => monitor.or(primary).map(|monitor| {
+                let origin = monitor.position();
+                let monitor_size = monitor.size();
+                PhysicalPosition::new(
+                    origin.x + (monitor_size.width as i32 - size.width as i32) / 2,
+                    origin.y + (monitor_size.height as i32 - size.height as i32) / 2,
+                )
+            }),
+        }
+    }
+}
+
+//
+// Window geometry persistence
+// The windowed position and size are stored as a single line of text in the
+// user's config directory so the window reopens where the player left it.
+//
+
+#[cfg(feature = "window-persistence")]
+pub(crate) struct WindowGeometry {
+    pub position: PhysicalPosition<i32>,
+    pub size: PhysicalSize<u32>,
+}
+
+#[cfg(feature = "window-persistence")]
+impl WindowGeometry {
+    fn path(name: &str) -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(name).join("window.cfg"))
+    }
+
+    pub fn load(name: &str) -> Option<Self> {
+        let text = fs::read_to_string(Self::path(name)?).ok()?;
+        let values = text
+            .split_whitespace()
+            .map(|value| value.parse::<i32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        match values.as_slice() {
+            &[x, y, width, height] if width > 0 && height > 0 => Some(WindowGeometry {
+                position: PhysicalPosition::new(x, y),
+                size: PhysicalSize::new(width as u32, height as u32),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn save(&self, name: &str) -> io::Result<()> {
+        let path = Self::path(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            path,
+            format!(
+                "{} {} {} {}\n",
+                self.position.x, self.position.y, self.size.width, self.size.height
+            ),
+        )
+    }
+}
+
+//
+// Fullscreen state
+// Tracks the fullscreen preferences and the windowed geometry to restore when
+// leaving fullscreen.
+//
+
+pub(crate) struct FullscreenState {
+    mode: FullscreenMode,
+    monitor: Option<MonitorHandle>,
+    video_mode: Option<(u32, u32)>,
+    windowed: Option<(Option<PhysicalPosition<i32>>, PhysicalSize<u32>)>,
+}
+
+impl FullscreenState {
+    pub fn new(
+        mode: FullscreenMode,
+        monitor: Option<MonitorHandle>,
+        video_mode: Option<(u32, u32)>,
+    ) -> Self {
+        FullscreenState {
+            mode,
+            monitor,
+            video_mode,
+            windowed: None,
+        }
+    }
+
+    pub fn toggle(&mut self, window: &Window) {
+        if window.fullscreen().is_some() {
+            window.set_fullscreen(None);
+            if let Some((position, size)) = self.windowed.take() {
+                window.set_inner_size(size);
+                if let Some(position) = position {
+                    window.set_outer_position(position);
+                }
+            }
+        } else if let Some(monitor) = self.monitor.clone().or_else(|| window.current_monitor()) {
+            let fullscreen = match self.mode {
+                FullscreenMode::Borderless => Some(Fullscreen::Borderless(Some(monitor))),
+                FullscreenMode::Exclusive => {
+                    pick_video_mode(&monitor, self.video_mode).map(Fullscreen::Exclusive)
+                }
+            };
+            if fullscreen.is_some() {
+                // Remember the windowed geometry so it can be restored later
+                self.windowed = Some((window.outer_position().ok(), window.inner_size()));
+                window.set_fullscreen(fullscreen);
+            }
+        }
+    }
+
+    // The position and size the window has, or will have, when not fullscreen.
+    #[cfg(feature = "window-persistence")]
+    pub fn windowed_geometry(&self, window: &Window) -> Option<WindowGeometry> {
+        let (position, size) = match self.windowed {
+            Some(windowed) if window.fullscreen().is_some() => windowed,
+            _ => (window.outer_position().ok(), window.inner_size()),
+        };
+        position.map(|position| WindowGeometry { position, size })
+    }
+}
+
+// Choose the video mode that best matches the requested size, preferring the
+// highest refresh rate and bit depth.  Without a matching size, the largest
+// mode is used.
+fn pick_video_mode(monitor: &MonitorHandle, size: Option<(u32, u32)>) -> Option<VideoMode> {
+    let quality = |mode: &VideoMode| (mode.refresh_rate(), mode.bit_depth());
+    let area = |mode: &VideoMode| mode.size().width * mode.size().height;
+
+    size.and_then(|(width, height)| {
+        monitor
+            .video_modes()
+            .filter(|mode| mode.size() == PhysicalSize::new(width, height))
+            .max_by_key(quality)
+    })
+    .or_else(|| {
+        monitor
+            .video_modes()
+            .max_by_key(|mode| (area(mode), quality(mode)))
+    })
+}
+
+//
+// Secondary windows
+// Each secondary window has its own renderer and character grid.  Keyboard
+// input from any window goes to SimInput's KeyState, but each window tracks
+// its own mouse, which the game can query via the Context.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WindowHandle(pub(crate) u32);
+
+pub(crate) enum WindowRequest {
+    Open {
+        handle: WindowHandle,
+        title: String,
+        width: u32,
+        height: u32,
+    },
+    Close(WindowHandle),
+}
+
+struct SecondaryWindow {
+    handle: WindowHandle,
+    window: Window,
+    render: Renderer,
+    mouse: MouseState,
+}
+
+pub(crate) struct WindowRegistry {
+    windows: Vec<SecondaryWindow>,
+}
+
+impl WindowRegistry {
+    pub fn new() -> Self {
+        WindowRegistry {
+            windows: Vec::new(),
+        }
+    }
+
+    // Carry out the open and close requests the game made during its tick.
+    pub fn process_requests(
+        &mut self,
+        context: &mut Context,
+        target: &EventLoopWindowTarget<()>,
+        font: &RogueFontData,
+        graphics: &GraphicsOptions,
+    ) {
+        for request in context.window_requests.drain(..) {
+            match request {
+                WindowRequest::Open {
+                    handle,
+                    title,
+                    width,
+                    height,
+                } => {
+                    let window = WindowBuilder::new()
+                        .with_title(title)
+                        .with_inner_size(PhysicalSize::new(
+                            width * font.width,
+                            height * font.height,
+                        ))
+                        .with_min_inner_size(PhysicalSize::new(font.width, font.height))
+                        .build(target);
+                    let window = match window {
+                        Ok(window) => window,
+                        Err(e) => {
+                            log::error!("Unable to open window: {}", e);
+                            continue;
+                        }
+                    };
+                    // Only the main window's grid is fixed or limited, or has
+                    // effects
+                    let graphics = GraphicsOptions {
+                        grid_size: None,
+                        min_grid_size: None,
+                        max_grid_size: None,
+                        effects_shader: None,
+                        ..graphics.clone()
+                    };
+                    match block_on(Renderer::new(&window, font, &graphics)) {
+                        Ok(render) => self.windows.push(SecondaryWindow {
+                            handle,
+                            window,
+                            render,
+                            mouse: MouseState::default(),
+                        }),
+                        Err(e) => log::error!("Unable to render to window: {}", e),
+                    }
+                }
+                WindowRequest::Close(handle) => self.windows.retain(|w| w.handle != handle),
+            }
+        }
+    }
+
+    // Publish the open windows and their mouse states to the context before
+    // the game's tick.
+    pub fn sync(&self, context: &mut Context) {
+        context.windows = self.windows.iter().map(|w| (w.handle, w.mouse)).collect();
+    }
+
+    // Bytes used by the secondary windows' grids and font textures
+    pub fn memory(&self) -> (usize, usize) {
+        self.windows.iter().fold((0, 0), |(grids, atlases), w| {
+            (
+                grids + w.render.grid_memory(),
+                atlases + w.render.atlas_memory(),
+            )
+        })
+    }
+
+    pub fn end_tick(&mut self) {
+        self.windows.iter_mut().for_each(|w| w.mouse.end_tick());
+    }
+
+    // Returns true if the event was consumed by a secondary window.  Keyboard
+    // events are not consumed so they can be passed on to the game.
+    pub fn handle_event(&mut self, window_id: WindowId, event: &WindowEvent) -> bool {
+        let index = match self.windows.iter().position(|w| w.window.id() == window_id) {
+            Some(index) => index,
+            None => return false,
+        };
+        let secondary = &mut self.windows[index];
+
+        match event {
+            WindowEvent::CloseRequested => {
+                self.windows.remove(index);
+            }
+            WindowEvent::Resized(new_size) => secondary.render.resize(*new_size),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                secondary.render.resize(**new_inner_size)
+            }
+            WindowEvent::CursorEntered { .. }
+            | WindowEvent::CursorLeft { .. }
+            | WindowEvent::CursorMoved { .. }
+            | WindowEvent::MouseInput { .. }
+            | WindowEvent::MouseWheel { .. } => {
+                secondary.mouse.handle_event(event);
+                if let WindowEvent::CursorMoved { position, .. } = *event {
+                    secondary.render.place_mouse(&mut secondary.mouse, position);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn request_redraws(&self) {
+        self.windows.iter().for_each(|w| w.window.request_redraw());
+    }
+
+    // Returns true if the window was a secondary window.
+    pub fn redraw(&mut self, window_id: WindowId, game: &dyn Game) -> bool {
+        let secondary = match self.windows.iter_mut().find(|w| w.window.id() == window_id) {
+            Some(secondary) => secondary,
+            None => return false,
+        };
+
+        game.present_window(
+            secondary.handle,
+            present_input(&secondary.window, &mut secondary.render),
+        );
+        if let Err(e) = secondary.render.render() {
+            if let Err(e) = block_on(secondary.render.recover(&secondary.window, e)) {
+                log::error!("Unable to render to window: {}", e);
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,224 @@
+//
+// Window handles
+//
+// winit hands out its window's handle in the version 0.3 format, but wgpu
+// takes version 0.4 and softbuffer version 0.6.  A WindowHandle keeps a
+// copy of winit's handle and converts it to whichever is asked for.  It must
+// not outlive the window it came from.
+//
+
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use raw_window_handle_04 as rwh_04;
+#[cfg(feature = "fallback")]
+use raw_window_handle_06 as rwh_06;
+use winit::window::Window;
+
+#[derive(Clone, Copy)]
+pub(crate) struct WindowHandle(RawWindowHandle);
+
+impl WindowHandle {
+    pub(crate) fn new(window: &Window) -> Self {
+        WindowHandle(window.raw_window_handle())
+    }
+}
+
+unsafe impl rwh_04::HasRawWindowHandle for WindowHandle {
+    fn raw_window_handle(&self) -> rwh_04::RawWindowHandle {
+        match self.0 {
+            #[cfg(target_os = "ios")]
+            RawWindowHandle::IOS(handle) => {
+                let mut ios = rwh_04::UiKitHandle::empty();
+                ios.ui_window = handle.ui_window;
+                ios.ui_view = handle.ui_view;
+                ios.ui_view_controller = handle.ui_view_controller;
+                rwh_04::RawWindowHandle::UiKit(ios)
+            }
+            #[cfg(target_os = "macos")]
+            RawWindowHandle::MacOS(handle) => {
+                let mut macos = rwh_04::AppKitHandle::empty();
+                macos.ns_window = handle.ns_window;
+                macos.ns_view = handle.ns_view;
+                rwh_04::RawWindowHandle::AppKit(macos)
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Xlib(handle) => {
+                let mut xlib = rwh_04::XlibHandle::empty();
+                xlib.window = handle.window;
+                xlib.display = handle.display;
+                rwh_04::RawWindowHandle::Xlib(xlib)
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Xcb(handle) => {
+                let mut xcb = rwh_04::XcbHandle::empty();
+                xcb.window = handle.window;
+                xcb.connection = handle.connection;
+                rwh_04::RawWindowHandle::Xcb(xcb)
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Wayland(handle) => {
+                let mut wayland = rwh_04::WaylandHandle::empty();
+                wayland.surface = handle.surface;
+                wayland.display = handle.display;
+                rwh_04::RawWindowHandle::Wayland(wayland)
+            }
+            #[cfg(target_os = "windows")]
+            RawWindowHandle::Windows(handle) => {
+                let mut win32 = rwh_04::Win32Handle::empty();
+                win32.hwnd = handle.hwnd;
+                win32.hinstance = handle.hinstance;
+                rwh_04::RawWindowHandle::Win32(win32)
+            }
+            #[cfg(target_arch = "wasm32")]
+            RawWindowHandle::Web(handle) => {
+                let mut web = rwh_04::WebHandle::empty();
+                web.id = handle.id;
+                rwh_04::RawWindowHandle::Web(web)
+            }
+            #[cfg(target_os = "android")]
+            RawWindowHandle::Android(handle) => {
+                let mut android = rwh_04::AndroidNdkHandle::empty();
+                android.a_native_window = handle.a_native_window;
+                rwh_04::RawWindowHandle::AndroidNdk(android)
+            }
+            _ => unreachable!("winit doesn't make any other kind of window"),
+        }
+    }
+}
+
+// softbuffer checks the handles each time it uses them, so a handle winit
+// left empty is reported as unavailable rather than trusted.
+#[cfg(feature = "fallback")]
+impl rwh_06::HasWindowHandle for WindowHandle {
+    fn window_handle(&self) -> Result<rwh_06::WindowHandle<'_>, rwh_06::HandleError> {
+        use std::ptr::NonNull;
+        let pointer = |p| NonNull::new(p).ok_or(rwh_06::HandleError::Unavailable);
+        let raw = match self.0 {
+            #[cfg(target_os = "ios")]
+            RawWindowHandle::IOS(handle) => {
+                rwh_06::UiKitWindowHandle::new(pointer(handle.ui_view)?).into()
+            }
+            #[cfg(target_os = "macos")]
+            RawWindowHandle::MacOS(handle) => {
+                rwh_06::AppKitWindowHandle::new(pointer(handle.ns_view)?).into()
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Xlib(handle) => rwh_06::XlibWindowHandle::new(handle.window).into(),
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Xcb(handle) => {
+                let window = std::num::NonZeroU32::new(handle.window)
+                    .ok_or(rwh_06::HandleError::Unavailable)?;
+                rwh_06::XcbWindowHandle::new(window).into()
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Wayland(handle) => {
+                rwh_06::WaylandWindowHandle::new(pointer(handle.surface)?).into()
+            }
+            #[cfg(target_os = "windows")]
+            RawWindowHandle::Windows(handle) => {
+                let hwnd = std::num::NonZeroIsize::new(handle.hwnd as isize)
+                    .ok_or(rwh_06::HandleError::Unavailable)?;
+                let mut win32 = rwh_06::Win32WindowHandle::new(hwnd);
+                win32.hinstance = std::num::NonZeroIsize::new(handle.hinstance as isize);
+                win32.into()
+            }
+            #[cfg(target_arch = "wasm32")]
+            RawWindowHandle::Web(handle) => rwh_06::WebWindowHandle::new(handle.id).into(),
+            #[cfg(target_os = "android")]
+            RawWindowHandle::Android(handle) => {
+                rwh_06::AndroidNdkWindowHandle::new(pointer(handle.a_native_window)?).into()
+            }
+            _ => return Err(rwh_06::HandleError::NotSupported),
+        };
+        // The window outlives the handle, as above
+        Ok(unsafe { rwh_06::WindowHandle::borrow_raw(raw) })
+    }
+}
+
+#[cfg(feature = "fallback")]
+impl rwh_06::HasDisplayHandle for WindowHandle {
+    fn display_handle(&self) -> Result<rwh_06::DisplayHandle<'_>, rwh_06::HandleError> {
+        use std::ptr::NonNull;
+        let raw = match self.0 {
+            #[cfg(target_os = "ios")]
+            RawWindowHandle::IOS(_) => rwh_06::UiKitDisplayHandle::new().into(),
+            #[cfg(target_os = "macos")]
+            RawWindowHandle::MacOS(_) => rwh_06::AppKitDisplayHandle::new().into(),
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Xlib(handle) => {
+                rwh_06::XlibDisplayHandle::new(NonNull::new(handle.display), 0).into()
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Xcb(handle) => {
+                rwh_06::XcbDisplayHandle::new(NonNull::new(handle.connection), 0).into()
+            }
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd"
+            ))]
+            RawWindowHandle::Wayland(handle) => {
+                let display =
+                    NonNull::new(handle.display).ok_or(rwh_06::HandleError::Unavailable)?;
+                rwh_06::WaylandDisplayHandle::new(display).into()
+            }
+            #[cfg(target_os = "windows")]
+            RawWindowHandle::Windows(_) => rwh_06::WindowsDisplayHandle::new().into(),
+            #[cfg(target_arch = "wasm32")]
+            RawWindowHandle::Web(_) => rwh_06::WebDisplayHandle::new().into(),
+            #[cfg(target_os = "android")]
+            RawWindowHandle::Android(_) => rwh_06::AndroidDisplayHandle::new().into(),
+            _ => return Err(rwh_06::HandleError::NotSupported),
+        };
+        Ok(unsafe { rwh_06::DisplayHandle::borrow_raw(raw) })
+    }
+}
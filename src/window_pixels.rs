@@ -0,0 +1,80 @@
+//
+// Window pixels
+//
+// Puts a buffer of 0x00RRGGBB pixels into a window with softbuffer, for the
+// SoftwareRenderer to present with when there is no graphics adapter.  It
+// works wherever softbuffer does: X11, Wayland, Windows, macOS and the web.
+//
+// softbuffer is only built with the fallback feature.  Without it there is
+// nothing to present with, so creating the WindowPixels fails with
+// RenderError::AdapterNotFound and a game without an adapter can't start.
+//
+
+use crate::render::{RenderError, RenderResult};
+#[cfg(feature = "fallback")]
+use crate::window_handle::WindowHandle;
+#[cfg(feature = "fallback")]
+use softbuffer::{Context, SoftBufferError, Surface};
+#[cfg(feature = "fallback")]
+use std::num::NonZeroU32;
+use winit::window::Window;
+
+#[cfg(feature = "fallback")]
+pub(crate) struct WindowPixels {
+    surface: Surface<WindowHandle, WindowHandle>,
+    size: (u32, u32),
+}
+
+// Never made without the fallback feature
+#[cfg(not(feature = "fallback"))]
+pub(crate) enum WindowPixels {}
+
+impl WindowPixels {
+    #[cfg(feature = "fallback")]
+    pub(crate) fn new(window: &Window) -> RenderResult<Self> {
+        let handle = WindowHandle::new(window);
+        let context = Context::new(handle).map_err(software_error)?;
+        let surface = Surface::new(&context, handle).map_err(software_error)?;
+        Ok(WindowPixels {
+            surface,
+            size: (0, 0),
+        })
+    }
+
+    #[cfg(not(feature = "fallback"))]
+    pub(crate) fn new(_window: &Window) -> RenderResult<Self> {
+        Err(RenderError::AdapterNotFound)
+    }
+
+    // The pixels are size.0 x size.1, top row first.
+    #[cfg(feature = "fallback")]
+    pub(crate) fn present(&mut self, pixels: &[u32], size: (u32, u32)) -> RenderResult<()> {
+        let (width, height) = match (NonZeroU32::new(size.0), NonZeroU32::new(size.1)) {
+            (Some(width), Some(height)) if pixels.len() == size.0 as usize * size.1 as usize => {
+                (width, height)
+            }
+            _ => {
+                return Err(RenderError::Software(String::from(
+                    "the pixels don't match their size",
+                )))
+            }
+        };
+        if size != self.size {
+            self.surface.resize(width, height).map_err(software_error)?;
+            self.size = size;
+        }
+        let mut buffer = self.surface.buffer_mut().map_err(software_error)?;
+        buffer.copy_from_slice(pixels);
+        buffer.present().map_err(software_error)
+    }
+
+    #[cfg(not(feature = "fallback"))]
+    pub(crate) fn present(&mut self, _pixels: &[u32], _size: (u32, u32)) -> RenderResult<()> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "fallback")]
+fn software_error(e: SoftBufferError) -> RenderError {
+    RenderError::Software(e.to_string())
+}